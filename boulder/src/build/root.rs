@@ -43,11 +43,11 @@ pub fn populate(
     timing.finish(initialize_timer);
 
     // Install packages
-    let install_timing = moss_client.install(&packages, true)?;
-
-    timing.record(timing::Populate::Resolve, install_timing.resolve);
-    timing.record(timing::Populate::Fetch, install_timing.fetch);
-    timing.record(timing::Populate::Blit, install_timing.blit);
+    if let Some(install_timing) = moss_client.install(&packages, true)? {
+        timing.record(timing::Populate::Resolve, install_timing.resolve);
+        timing.record(timing::Populate::Fetch, install_timing.fetch);
+        timing.record(timing::Populate::Blit, install_timing.blit);
+    }
 
     Ok(())
 }
@@ -149,6 +149,29 @@ pub fn cmake(bucket: &mut BucketMut<'_>, info: &mut PathInfo) -> Result<Response
     Ok(Decision::NextHandler.into())
 }
 
+/// Hardware supported by firmware & driver packages, listed as one modalias pattern per line
+/// in `/usr/share/modalias.d/*.modalias`
+pub fn modalias(bucket: &mut BucketMut<'_>, info: &mut PathInfo) -> Result<Response, BoxError> {
+    if !info.target_path.starts_with("/usr/share/modalias.d") || !info.file_name().ends_with(".modalias") {
+        return Ok(Decision::NextHandler.into());
+    }
+
+    let patterns = fs::read_to_string(&info.path)?;
+
+    for pattern in patterns
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        bucket.providers.insert(Provider {
+            kind: dependency::Kind::Modalias,
+            name: pattern.to_owned(),
+        });
+    }
+
+    Ok(Decision::NextHandler.into())
+}
+
 /// Ensure that man and info files are zst compressed for on-disk space savings.
 pub fn compressman(bucket: &mut BucketMut<'_>, info: &mut PathInfo) -> Result<Response, BoxError> {
     /* if the compressman option is turned off, exit early */
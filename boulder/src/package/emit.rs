@@ -9,7 +9,10 @@ use std::{
 
 use fs_err::{self as fs, File};
 use itertools::Itertools;
-use moss::{Dependency, Provider, package::Meta};
+use moss::{
+    Dependency, Provider,
+    package::{Meta, meta},
+};
 use regex::Regex;
 use thiserror::Error;
 use tui::{ProgressBar, ProgressStyle, Styled};
@@ -70,6 +73,8 @@ impl<'a> Package<'a> {
             description: self.definition.description.clone().unwrap_or_default(),
             source_id: self.source.name.clone(),
             homepage: self.source.homepage.clone(),
+            bug_tracker: self.source.bug_tracker.clone(),
+            maintainer: self.source.maintainer.clone(),
             licenses: self.source.license.clone().into_iter().sorted().collect(),
             dependencies: self
                 .analysis
@@ -99,9 +104,31 @@ impl<'a> Package<'a> {
                 .iter()
                 .filter_map(|name| Provider::from_name(name).ok())
                 .collect(),
+            replaces: self
+                .definition
+                .replaces
+                .iter()
+                .filter_map(|name| Provider::from_name(name).ok())
+                .collect(),
+            exports: self
+                .definition
+                .exports
+                .iter()
+                .filter_map(|export| export.parse().ok())
+                .collect(),
+            config_files: self
+                .definition
+                .config_files
+                .iter()
+                .filter(|path| meta::is_config_file(path))
+                .cloned()
+                .collect(),
             uri: None,
             hash: None,
             download_size: None,
+            installed_size: None,
+            classification: self.source.update.as_deref().and_then(|update| update.parse().ok()),
+            security_release: None,
         }
     }
 }
@@ -7,6 +7,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use fs_common::exchange;
 use fs_err as fs;
 use serde_core::{Serialize, de::DeserializeOwned};
 use snafu::{ResultExt, Snafu};
@@ -77,14 +78,113 @@ impl Manager {
         fs::create_dir_all(&dir).context(CreateDirSnafu { path: &dir })?;
 
         let path = dir.join(format!("{name}.{EXTENSION}"));
+        let temp_path = dir.join(format!("{name}.{EXTENSION}.tmp"));
 
         let serialized = serde_yaml::to_string(config).context(YamlSnafu)?;
 
-        fs::write(&path, serialized).context(WriteSnafu { path })?;
+        // Write to a temporary file & rename over the original so readers
+        // never observe a partially written config
+        fs::write(&temp_path, serialized).context(WriteSnafu { path: &temp_path })?;
+        fs::rename(&temp_path, &path).context(WriteSnafu { path })?;
 
         Ok(())
     }
 
+    /// Replace all saved configs for the domain of `T` with `configs`
+    ///
+    /// The configs are written to a separate directory which is then swapped in for
+    /// the save directory, so a failed write leaves the existing configs untouched.
+    pub fn replace<T: Config + Serialize>(
+        &self,
+        configs: impl IntoIterator<Item = (impl fmt::Display, T)>,
+    ) -> Result<(), SaveError> {
+        let domain = T::domain();
+
+        let dir = self.scope.save_dir(&domain);
+        let staging = dir.with_extension("d.new");
+        let swap = dir.with_extension("d.swap");
+
+        if staging.exists() {
+            fs::remove_dir_all(&staging).context(WriteSnafu { path: &staging })?;
+        }
+        fs::create_dir_all(&staging).context(CreateDirSnafu { path: &staging })?;
+
+        for (name, config) in configs {
+            let path = staging.join(format!("{name}.{EXTENSION}"));
+            let serialized = serde_yaml::to_string(&config).context(YamlSnafu)?;
+            fs::write(&path, serialized).context(WriteSnafu { path })?;
+        }
+
+        if dir.exists() {
+            fs::rename(&dir, &swap).context(WriteSnafu { path: &dir })?;
+        }
+        fs::rename(&staging, &dir).context(WriteSnafu { path: &dir })?;
+        if swap.exists() {
+            fs::remove_dir_all(&swap).context(WriteSnafu { path: swap })?;
+        }
+
+        Ok(())
+    }
+
+    /// Snapshot all saved configs for the domain of `T`, replacing any previous snapshot
+    ///
+    /// Only a single backup generation is kept, which can be restored via [`Manager::restore`]
+    pub fn backup<T: Config>(&self) -> io::Result<()> {
+        let domain = T::domain();
+
+        let dir = self.scope.save_dir(&domain);
+        let backup = backup_dir(&dir);
+        let staging = dir.with_extension("d.bak.tmp");
+
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)?.flatten() {
+                let path = entry.path();
+
+                if path.is_file() {
+                    fs::copy(&path, staging.join(entry.file_name()))?;
+                }
+            }
+        }
+
+        // Swapped in, so a failure never leaves the domain without a backup
+        if backup.exists() {
+            exchange(&staging, &backup)?;
+            fs::remove_dir_all(&staging)?;
+        } else {
+            fs::rename(&staging, &backup)?;
+        }
+
+        Ok(())
+    }
+
+    /// Restore the snapshot taken by [`Manager::backup`] for the domain of `T`
+    ///
+    /// The backup is swapped in atomically & the current configs become the new backup,
+    /// so restoring twice reverts the restore. Returns `false` if no backup exists.
+    pub fn restore<T: Config>(&self) -> io::Result<bool> {
+        let domain = T::domain();
+
+        let dir = self.scope.save_dir(&domain);
+        let backup = backup_dir(&dir);
+
+        if !backup.exists() {
+            return Ok(false);
+        }
+
+        if dir.exists() {
+            exchange(&backup, &dir)?;
+        } else {
+            fs::rename(&backup, &dir)?;
+        }
+
+        Ok(true)
+    }
+
     pub fn delete<T: Config>(&self, name: impl fmt::Display) -> io::Result<()> {
         let domain = T::domain();
 
@@ -140,6 +240,11 @@ fn enumerate_paths(entry: Entry, resolve: Resolve<'_>, domain: &str) -> Vec<Path
     }
 }
 
+/// Backup location for the `{domain}.d` save directory
+fn backup_dir(dir: &Path) -> PathBuf {
+    dir.with_extension("d.bak")
+}
+
 fn read_config<T: Config>(path: PathBuf) -> Option<T> {
     let bytes = fs::read(path).ok()?;
     serde_yaml::from_slice(&bytes).ok()
@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Common filesystem operations for moss and related tools
+
+use std::io;
+
+use nix::{
+    NixPath,
+    errno::Errno,
+    libc::{AT_FDCWD, RENAME_EXCHANGE, SYS_renameat2, syscall},
+};
+
+/// Atomically swap the existing paths `a` & `b`
+///
+/// `renameat2` is called through its syscall as musl libc doesn't expose it,
+/// largely modelled on the existing renameat2 API in the nix crate.
+pub fn exchange<A: ?Sized + NixPath, B: ?Sized + NixPath>(a: &A, b: &B) -> io::Result<()> {
+    let result = a.with_nix_path(|a| {
+        b.with_nix_path(|b| unsafe {
+            syscall(
+                SYS_renameat2,
+                AT_FDCWD,
+                a.as_ptr(),
+                AT_FDCWD,
+                b.as_ptr(),
+                RENAME_EXCHANGE,
+            )
+        })
+    })?? as i32;
+
+    Errno::result(result).map(drop).map_err(io::Error::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_exchange() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::create_dir(&a).unwrap();
+        fs::write(a.join("file"), "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        exchange(&a, &b).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(fs::read_to_string(b.join("file")).unwrap(), "a");
+
+        assert!(exchange(&a, &dir.path().join("missing")).is_err());
+    }
+}
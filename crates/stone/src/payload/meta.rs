@@ -50,6 +50,9 @@ pub enum Dependency {
 
     /// An emul32-compatible pkgconfig .pc dependency (lib32/*.pc)
     PkgConfig32,
+
+    /// A kernel modalias pattern of hardware supported by the package
+    Modalias,
 }
 
 #[repr(u8)]
@@ -132,6 +135,24 @@ pub enum Tag {
     SourcePath = 19,
     // Ref/commit of the upstream source
     SourceRef = 20,
+    // Supersedes some capability or name (i.e. a renamed package)
+    Replaces = 21,
+    // Where to report bugs for the package
+    BugTracker = 22,
+    // Who maintains the package
+    Maintainer = 23,
+    // Environment variable exported to user sessions
+    Export = 24,
+    // Configuration file installed to /etc from its vendor default
+    ConfigFile = 25,
+    // Repository index specific (size of the unpacked content)
+    InstalledSize = 26,
+    // What the update to this release brings, i.e. `security`
+    Classification = 27,
+    // Repository delta specific (unix time the index is valid until)
+    ValidUntil = 28,
+    // Repository index specific (latest release up to this one classified as a security fix)
+    SecurityRelease = 29,
 }
 
 /// Helper to decode a dependency's encoded kind
@@ -146,37 +167,49 @@ fn decode_dependency(i: u8) -> Result<Dependency, DecodeError> {
         6 => Dependency::Binary,
         7 => Dependency::SystemBinary,
         8 => Dependency::PkgConfig32,
+        9 => Dependency::Modalias,
         _ => return Err(DecodeError::UnknownDependency(i)),
     };
     Ok(result)
 }
 
 impl Record for Meta {
+    /// Decodes the whole record, even when its tag or dependency kind is unknown
+    /// to this version, so the reader is left at the start of the next record
     fn decode<R: Read>(mut reader: R) -> Result<Self, DecodeError> {
         let length = reader.read_u32()?;
 
         let tag = match reader.read_u16()? {
-            1 => Tag::Name,
-            2 => Tag::Architecture,
-            3 => Tag::Version,
-            4 => Tag::Summary,
-            5 => Tag::Description,
-            6 => Tag::Homepage,
-            7 => Tag::SourceID,
-            8 => Tag::Depends,
-            9 => Tag::Provides,
-            10 => Tag::Conflicts,
-            11 => Tag::Release,
-            12 => Tag::License,
-            13 => Tag::BuildRelease,
-            14 => Tag::PackageURI,
-            15 => Tag::PackageHash,
-            16 => Tag::PackageSize,
-            17 => Tag::BuildDepends,
-            18 => Tag::SourceURI,
-            19 => Tag::SourcePath,
-            20 => Tag::SourceRef,
-            t => return Err(DecodeError::UnknownMetaTag(t)),
+            1 => Ok(Tag::Name),
+            2 => Ok(Tag::Architecture),
+            3 => Ok(Tag::Version),
+            4 => Ok(Tag::Summary),
+            5 => Ok(Tag::Description),
+            6 => Ok(Tag::Homepage),
+            7 => Ok(Tag::SourceID),
+            8 => Ok(Tag::Depends),
+            9 => Ok(Tag::Provides),
+            10 => Ok(Tag::Conflicts),
+            11 => Ok(Tag::Release),
+            12 => Ok(Tag::License),
+            13 => Ok(Tag::BuildRelease),
+            14 => Ok(Tag::PackageURI),
+            15 => Ok(Tag::PackageHash),
+            16 => Ok(Tag::PackageSize),
+            17 => Ok(Tag::BuildDepends),
+            18 => Ok(Tag::SourceURI),
+            19 => Ok(Tag::SourcePath),
+            20 => Ok(Tag::SourceRef),
+            21 => Ok(Tag::Replaces),
+            22 => Ok(Tag::BugTracker),
+            23 => Ok(Tag::Maintainer),
+            24 => Ok(Tag::Export),
+            25 => Ok(Tag::ConfigFile),
+            26 => Ok(Tag::InstalledSize),
+            27 => Ok(Tag::Classification),
+            28 => Ok(Tag::ValidUntil),
+            29 => Ok(Tag::SecurityRelease),
+            t => Err(DecodeError::UnknownMetaTag(t)),
         };
 
         let kind = reader.read_u8()?;
@@ -195,20 +228,22 @@ impl Record for Meta {
             7 => Kind::Int64(reader.read_u64()? as i64),
             8 => Kind::Uint64(reader.read_u64()?),
             9 => Kind::String(sanitize(reader.read_string(length as u64)?)),
-            10 => Kind::Dependency(
+            10 => {
+                let dependency = decode_dependency(reader.read_u8()?);
                 // DependencyKind u8 subtracted from length
-                decode_dependency(reader.read_u8()?)?,
-                sanitize(reader.read_string(length as u64 - 1)?),
-            ),
-            11 => Kind::Provider(
+                let name = sanitize(reader.read_string(length as u64 - 1)?);
+                Kind::Dependency(dependency?, name)
+            }
+            11 => {
+                let dependency = decode_dependency(reader.read_u8()?);
                 // DependencyKind u8 subtracted from length
-                decode_dependency(reader.read_u8()?)?,
-                sanitize(reader.read_string(length as u64 - 1)?),
-            ),
+                let name = sanitize(reader.read_string(length as u64 - 1)?);
+                Kind::Provider(dependency?, name)
+            }
             k => return Err(DecodeError::UnknownMetaKind(k)),
         };
 
-        Ok(Self { tag, kind })
+        Ok(Self { tag: tag?, kind })
     }
 
     fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
@@ -259,3 +294,104 @@ impl Record for Meta {
         4 + 2 + 1 + 1 + self.kind.size()
     }
 }
+
+impl Meta {
+    /// Meta payload version which introduced the tag & dependency kind of this record
+    ///
+    /// Readers predating a version fail on its records, so they're only marked as such
+    /// when actually used.
+    pub fn version(&self) -> u16 {
+        let dependency = match &self.kind {
+            Kind::Dependency(dependency, _) | Kind::Provider(dependency, _) => Some(*dependency),
+            _ => None,
+        };
+
+        if self.tag as u16 > Tag::SourceRef as u16
+            || dependency.is_some_and(|d| d as u8 > Dependency::PkgConfig32 as u8)
+        {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+/// Lowest meta payload version holding all of `records`, so payloads sticking to the
+/// original records are still written as version 1, readable by any reader
+pub fn version(records: &[Meta]) -> u16 {
+    records.iter().map(Meta::version).max().unwrap_or(1)
+}
+
+/// Decode `num_records` meta records, skipping those with a tag or dependency kind
+/// unknown to this version so packages & indexes from newer writers remain readable
+pub fn decode_records<R: Read>(mut reader: R, num_records: usize) -> Result<Vec<Meta>, DecodeError> {
+    let mut records = Vec::with_capacity(num_records);
+
+    for _ in 0..num_records {
+        match Meta::decode(&mut reader) {
+            Ok(meta) => records.push(meta),
+            Err(DecodeError::UnknownMetaTag(_) | DecodeError::UnknownDependency(_)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::payload::encode_records;
+
+    #[test]
+    fn test_skip_unknown() {
+        let name = Meta {
+            tag: Tag::Name,
+            kind: Kind::String("nano".to_owned()),
+        };
+        let provides = Meta {
+            tag: Tag::Provides,
+            kind: Kind::Provider(Dependency::Binary, "nano".to_owned()),
+        };
+
+        let mut bytes = vec![];
+        encode_records(&mut bytes, &[name.clone(), provides.clone()]).unwrap();
+        // A tag & a dependency kind from a future version, each followed by a known record
+        let mut future = bytes.clone();
+        future[4..6].copy_from_slice(&999u16.to_be_bytes());
+        future[name.size() + 8] = 200;
+        future.extend_from_slice(&bytes);
+
+        let records = decode_records(future.as_slice(), 4).unwrap();
+        assert_eq!(records, vec![name, provides]);
+        assert!(matches!(
+            Meta::decode(future.as_slice()),
+            Err(DecodeError::UnknownMetaTag(999))
+        ));
+    }
+
+    #[test]
+    fn test_version() {
+        let name = Meta {
+            tag: Tag::Name,
+            kind: Kind::String("nano".to_owned()),
+        };
+        let provides = Meta {
+            tag: Tag::Provides,
+            kind: Kind::Provider(Dependency::Binary, "nano".to_owned()),
+        };
+        let modalias = Meta {
+            tag: Tag::Provides,
+            kind: Kind::Provider(Dependency::Modalias, "pci:v00008086d*".to_owned()),
+        };
+        let maintainer = Meta {
+            tag: Tag::Maintainer,
+            kind: Kind::String("Serpent OS".to_owned()),
+        };
+
+        assert_eq!(version(&[]), 1);
+        assert_eq!(version(&[name.clone(), provides.clone()]), 1);
+        assert_eq!(version(&[name.clone(), modalias]), 2);
+        assert_eq!(version(&[name, provides, maintainer]), 2);
+    }
+}
@@ -113,7 +113,7 @@ impl PayloadKind {
                 let payload = match header.kind {
                     payload::Kind::Meta => PayloadKind::Meta(Payload {
                         header,
-                        body: payload::decode_records(
+                        body: payload::meta::decode_records(
                             PayloadReader::new(&mut framed, header.compression)?,
                             header.num_records,
                         )?,
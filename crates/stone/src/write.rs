@@ -231,6 +231,13 @@ impl InnerPayload<'_> {
         Ok(())
     }
 
+    fn version(&self) -> u16 {
+        match self {
+            InnerPayload::Meta(records) => payload::meta::version(records),
+            InnerPayload::Attributes(_) | InnerPayload::Layout(_) | InnerPayload::Index(_) => 1,
+        }
+    }
+
     fn kind(&self) -> payload::Kind {
         match self {
             InnerPayload::Meta(_) => payload::Kind::Meta,
@@ -288,7 +295,7 @@ fn encode_payload(
         plain_size,
         checksum: hasher.digest().to_be_bytes(),
         num_records: payload.num_records(),
-        version: 1,
+        version: payload.version(),
         kind: payload.kind(),
         compression: payload::Compression::Zstd,
     };
@@ -65,6 +65,13 @@ pub struct Source {
     pub version: String,
     pub release: u64,
     pub homepage: String,
+    #[serde(default, rename = "bugtracker")]
+    pub bug_tracker: Option<String>,
+    #[serde(default)]
+    pub maintainer: Option<String>,
+    /// What the update to this release brings: `security`, `bugfix` or `feature`
+    #[serde(default)]
+    pub update: Option<String>,
     #[serde(deserialize_with = "single_as_sequence")]
     pub license: Vec<String>,
 }
@@ -115,6 +122,12 @@ pub struct Package {
     pub paths: Vec<Path>,
     #[serde(default)]
     pub conflicts: Vec<String>,
+    #[serde(default)]
+    pub replaces: Vec<String>,
+    #[serde(default)]
+    pub exports: Vec<String>,
+    #[serde(default, rename = "config-files")]
+    pub config_files: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
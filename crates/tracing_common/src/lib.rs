@@ -5,3 +5,6 @@
 //! Common tracing utilities for moss and related tools
 
 pub mod logging;
+pub mod progress;
+pub mod timeline;
+pub mod watchdog;
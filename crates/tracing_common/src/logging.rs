@@ -6,7 +6,9 @@
 
 use std::{fs::OpenOptions, io, str::FromStr};
 use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _};
+use tracing_subscriber::{Layer as _, fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _};
+
+use crate::timeline;
 
 #[derive(Debug, Clone, Copy)]
 pub enum OutputFormat {
@@ -22,6 +24,16 @@ pub enum OutputDestination {
 
 /// Initialize tracing subscriber with the specified format, log level, and destination
 pub fn init_log(format: OutputFormat, level: LevelFilter, destination: OutputDestination) {
+    init_log_with_targets(format, level, &[], destination);
+}
+
+/// Initialize tracing subscriber like [`init_log`], overriding the level of specific targets
+pub fn init_log_with_targets(
+    format: OutputFormat,
+    level: LevelFilter,
+    targets: &[(String, LevelFilter)],
+    destination: OutputDestination,
+) {
     let filter = tracing_subscriber::filter::Targets::new()
         .with_default(level)
         // these log a lot of stuff when downloading.
@@ -29,49 +41,49 @@ pub fn init_log(format: OutputFormat, level: LevelFilter, destination: OutputDes
         // helpful to set up tcpdump or wireshark anyways.
         .with_target("h2", LevelFilter::INFO)
         .with_target("hyper", LevelFilter::INFO)
-        .with_target("hyper_util", LevelFilter::INFO);
-
-    match (format, destination) {
-        (OutputFormat::Text, OutputDestination::Stderr) => {
-            tracing_subscriber::registry()
-                .with(filter)
-                .with(fmt::layer().with_writer(io::stderr))
-                .init();
-        }
-        (OutputFormat::Json, OutputDestination::Stderr) => {
-            tracing_subscriber::registry()
-                .with(filter)
-                .with(fmt::layer().json().with_writer(io::stderr))
-                .init();
-        }
+        .with_target("hyper_util", LevelFilter::INFO)
+        .with_targets(targets.iter().cloned());
+
+    let open = |path: &str| {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Failed to open log file {path}: {e}"))
+    };
+
+    let layer = match (format, destination) {
+        (OutputFormat::Text, OutputDestination::Stderr) => fmt::layer().with_writer(io::stderr).boxed(),
+        (OutputFormat::Json, OutputDestination::Stderr) => fmt::layer().json().with_writer(io::stderr).boxed(),
         (OutputFormat::Text, OutputDestination::File(path)) => {
-            let file = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&path)
-                .unwrap_or_else(|e| panic!("Failed to open log file {path}: {e}"));
-            tracing_subscriber::registry()
-                .with(filter)
-                .with(fmt::layer().with_writer(file).with_ansi(false))
-                .init();
-        }
-        (OutputFormat::Json, OutputDestination::File(path)) => {
-            let file = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&path)
-                .unwrap_or_else(|e| panic!("Failed to open log file {path}: {e}"));
-            tracing_subscriber::registry()
-                .with(filter)
-                .with(fmt::layer().json().with_writer(file))
-                .init();
+            fmt::layer().with_writer(open(&path)).with_ansi(false).boxed()
         }
+        (OutputFormat::Json, OutputDestination::File(path)) => fmt::layer().json().with_writer(open(&path)).boxed(),
+    };
+
+    // The filter only applies to the log, the timeline records phases regardless of the level
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .with(timeline::layer())
+        .init();
+}
+
+/// Map a repeated `-v` flag to a log level, starting at `-vv`
+///
+/// A single `-v` only enables verbose output, not logging
+pub fn verbosity_level(count: u8) -> Option<LevelFilter> {
+    match count {
+        0 | 1 => None,
+        2 => Some(LevelFilter::DEBUG),
+        _ => Some(LevelFilter::TRACE),
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct LogConfig {
     pub level: LevelFilter,
+    /// Per-target level overrides, i.e. `moss::registry=trace`
+    pub targets: Vec<(String, LevelFilter)>,
     pub format: OutputFormat,
     pub destination: OutputDestination,
 }
@@ -80,55 +92,137 @@ impl FromStr for LogConfig {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split(':').collect();
+        let mut parts = split_segments(s);
 
-        if parts.is_empty() || parts.len() > 3 {
-            return Err("Invalid log format. Expected: <level>[:<format>][:<destination>]".to_owned());
+        if parts.is_empty() {
+            return Err(USAGE.to_owned());
         }
 
-        let level = match parts[0].to_lowercase().as_str() {
-            "trace" => LevelFilter::TRACE,
-            "debug" => LevelFilter::DEBUG,
-            "info" => LevelFilter::INFO,
-            "warn" => LevelFilter::WARN,
-            "error" => LevelFilter::ERROR,
-            _ => {
-                return Err(format!(
-                    "Invalid log level: {}. Valid levels: trace, debug, info, warn, error",
-                    parts[0]
-                ));
+        // The level may carry comma separated filters itself, i.e. `debug,moss::registry=trace`
+        let mut filters = parts.remove(0).split(',');
+        let level = parse_level(filters.next().unwrap_or_default())?;
+        let mut targets = filters.map(parse_target).collect::<Result<Vec<_>, _>>()?;
+
+        // Followed by any number of `<target>=<level>` segments
+        while let Some(part) = parts.first().filter(|part| is_target_filter(part)) {
+            for filter in part.split(',') {
+                targets.push(parse_target(filter)?);
             }
-        };
+            parts.remove(0);
+        }
+
+        if parts.len() > 2 {
+            return Err(USAGE.to_owned());
+        }
 
-        let format = if parts.len() >= 2 {
-            match parts[1].to_lowercase().as_str() {
+        let format = if let Some(format) = parts.first() {
+            match format.to_lowercase().as_str() {
                 "text" => OutputFormat::Text,
                 "json" => OutputFormat::Json,
-                _ => return Err(format!("Invalid log format: {}. Valid formats: text, json", parts[1])),
+                _ => return Err(format!("Invalid log format: {format}. Valid formats: text, json")),
             }
         } else {
             OutputFormat::Text
         };
 
-        let destination = if parts.len() == 3 {
-            if parts[2] == "stderr" {
-                OutputDestination::Stderr
-            } else {
-                OutputDestination::File(parts[2].to_owned())
-            }
-        } else {
-            OutputDestination::Stderr
+        let destination = match parts.get(1) {
+            Some(&"stderr") | None => OutputDestination::Stderr,
+            Some(path) => OutputDestination::File((*path).to_owned()),
         };
 
         Ok(LogConfig {
             level,
+            targets,
             format,
             destination,
         })
     }
 }
 
+const USAGE: &str = "Invalid log format. Expected: <level>[:<target>=<level>...][:<format>][:<destination>]";
+
+/// Split on `:`, leaving the `::` of module paths intact
+fn split_segments(s: &str) -> Vec<&str> {
+    let bytes = s.as_bytes();
+    let mut parts = vec![];
+    let mut start = 0;
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        let is_path = (i > 0 && bytes[i - 1] == b':') || bytes.get(i + 1) == Some(&b':');
+        if byte == b':' && !is_path {
+            parts.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+fn is_target_filter(part: &str) -> bool {
+    part.split(',').all(|filter| parse_target(filter).is_ok())
+}
+
+fn parse_target(filter: &str) -> Result<(String, LevelFilter), String> {
+    let (target, level) = filter
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid log filter: {filter}. Expected: <target>=<level>"))?;
+
+    if target.is_empty()
+        || !target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+    {
+        return Err(format!("Invalid log target: {target}"));
+    }
+
+    Ok((target.to_owned(), parse_level(level)?))
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    match level.to_lowercase().as_str() {
+        "trace" => Ok(LevelFilter::TRACE),
+        "debug" => Ok(LevelFilter::DEBUG),
+        "info" => Ok(LevelFilter::INFO),
+        "warn" => Ok(LevelFilter::WARN),
+        "error" => Ok(LevelFilter::ERROR),
+        "off" => Ok(LevelFilter::OFF),
+        _ => Err(format!(
+            "Invalid log level: {level}. Valid levels: trace, debug, info, warn, error, off"
+        )),
+    }
+}
+
 /// Initialize tracing with a parsed log configuration
 pub fn init_log_with_config(config: LogConfig) {
-    init_log(config.format, config.level, config.destination);
+    init_log_with_targets(config.format, config.level, &config.targets, config.destination);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_config() {
+        let config = "debug:moss::registry=trace,moss::client=off:json:/tmp/moss.log"
+            .parse::<LogConfig>()
+            .unwrap();
+        assert_eq!(config.level, LevelFilter::DEBUG);
+        assert_eq!(
+            config.targets,
+            vec![
+                ("moss::registry".to_owned(), LevelFilter::TRACE),
+                ("moss::client".to_owned(), LevelFilter::OFF)
+            ]
+        );
+        assert!(matches!(config.format, OutputFormat::Json));
+        assert!(matches!(config.destination, OutputDestination::File(path) if path == "/tmp/moss.log"));
+
+        let config = "info,stone=debug".parse::<LogConfig>().unwrap();
+        assert_eq!(config.targets, vec![("stone".to_owned(), LevelFilter::DEBUG)]);
+        assert!(matches!(config.destination, OutputDestination::Stderr));
+
+        assert!("debug:moss::registry".parse::<LogConfig>().is_err());
+        assert!("debug:text:stderr:extra".parse::<LogConfig>().is_err());
+    }
 }
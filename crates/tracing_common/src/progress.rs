@@ -0,0 +1,275 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Structured progress events
+//!
+//! Every long running phase of an operation (resolution, downloads, blitting,
+//! triggers, boot synchronisation, repository refreshes, ...) is reported
+//! through a [`Phase`] so that machine readable logs (`--log info:json`) form
+//! a consistent schema which tooling can rely on for timing analysis.
+//!
+//! # Schema
+//!
+//! Each phase is a span named `progress` carrying the fields:
+//!
+//! | field        | type   | description                         |
+//! |--------------|--------|-------------------------------------|
+//! | `phase`      | string | name of the phase, one of [`PHASES`] |
+//! | `event_type` | string | always `progress`                   |
+//!
+//! Within that span the following events are emitted, distinguished by `event_type`:
+//!
+//! | `event_type`         | fields                                                  |
+//! |----------------------|---------------------------------------------------------|
+//! | `progress_start`     | `phase`, `total_items`, `progress` (`0.0`)               |
+//! | `progress_update`    | `progress` (`0.0..=1.0`), `current`, `total`, `message` |
+//! | `progress_completed` | `phase`, `duration_ms`, `items_processed`, `progress` (`1.0`) |
+//!
+//! Every phase emits exactly one `progress_start` followed by zero or more
+//! `progress_update` events, and finishes with exactly one `progress_completed`
+//! unless the operation fails. Phases may nest, in which case the innermost
+//! span identifies the phase an update belongs to.
+//!
+//! The phases reported by moss are:
+//!
+//! | `phase`                                         | reported while                                             |
+//! |-------------------------------------------------|------------------------------------------------------------|
+//! | `resolve`                                       | resolving the packages of a transaction                    |
+//! | `cache_packages`                                | fetching and unpacking missing packages                    |
+//! | `install`, `remove`, `sync`, `create`, `export` | applying a new state, named after its lowercased operation |
+//! | `snapshot`                                      | snapshotting the filesystem before applying a state        |
+//! | `blit`                                          | blitting the filesystem of a state                         |
+//! | `transaction-scope-triggers`                    | running triggers within the staged state                   |
+//! | `system-scope-triggers`                         | running triggers on the activated state                    |
+//! | `fixups`                                        | applying ownership & permission fixups                     |
+//! | `boot-sync`                                     | synchronising boot entries                                 |
+//! | `activate-state`                                | activating a previous state                                |
+//! | `repo-update`                                   | refreshing repositories                                    |
+//!
+//! This schema is a contract: fields may be added, but existing ones must not
+//! be renamed, removed or change type.
+
+use std::{fmt, future::Future, time::Instant};
+
+use tracing::{Instrument, Span, info, info_span, instrument::Instrumented, span::EnteredSpan};
+
+/// Names of all phases reported by moss, as documented in the [module](self) docs
+pub const PHASES: &[&str] = &[
+    "resolve",
+    "cache_packages",
+    "install",
+    "remove",
+    "sync",
+    "create",
+    "export",
+    "snapshot",
+    "blit",
+    "transaction-scope-triggers",
+    "system-scope-triggers",
+    "fixups",
+    "boot-sync",
+    "activate-state",
+    "repo-update",
+];
+
+/// A phase of work reporting structured progress
+///
+/// The phase span is entered for as long as the [`Phase`] is alive, so
+/// any events emitted in the meantime are attributed to it.
+pub struct Phase {
+    _span: EnteredSpan,
+    name: String,
+    started: Instant,
+}
+
+impl Phase {
+    /// Start a new phase named `name`, expected to process `total_items`
+    pub fn start(name: impl Into<String>, total_items: usize) -> Self {
+        let name = name.into();
+        let span = span(&name).entered();
+
+        start_event(&name, total_items);
+
+        Self {
+            _span: span,
+            name,
+            started: Instant::now(),
+        }
+    }
+
+    /// Mark the phase as completed having processed `items_processed`
+    pub fn complete(self, items_processed: usize) {
+        complete_event(&self.name, self.started, items_processed);
+    }
+}
+
+/// A [`Phase`] of asynchronous work
+///
+/// An entered span mustn't be held across `.await`, so rather than being entered
+/// the phase span is attached to the futures doing its work via [`Self::instrument`].
+pub struct AsyncPhase {
+    span: Span,
+    name: String,
+    started: Instant,
+}
+
+impl AsyncPhase {
+    /// Start a new phase named `name`, expected to process `total_items`
+    pub fn start(name: impl Into<String>, total_items: usize) -> Self {
+        let name = name.into();
+        let span = span(&name);
+
+        span.in_scope(|| start_event(&name, total_items));
+
+        Self {
+            span,
+            name,
+            started: Instant::now(),
+        }
+    }
+
+    /// Attribute the events of `future` to this phase
+    pub fn instrument<F: Future>(&self, future: F) -> Instrumented<F> {
+        future.instrument(self.span.clone())
+    }
+
+    /// Mark the phase as completed having processed `items_processed`
+    pub fn complete(self, items_processed: usize) {
+        self.span
+            .in_scope(|| complete_event(&self.name, self.started, items_processed));
+    }
+}
+
+fn span(name: &str) -> Span {
+    info_span!("progress", phase = %name, event_type = "progress")
+}
+
+fn start_event(name: &str, total_items: usize) {
+    info!(
+        phase = %name,
+        total_items,
+        progress = 0.0,
+        event_type = "progress_start",
+    );
+}
+
+fn complete_event(name: &str, started: Instant, items_processed: usize) {
+    info!(
+        phase = %name,
+        duration_ms = started.elapsed().as_millis() as u64,
+        items_processed,
+        progress = 1.0,
+        event_type = "progress_completed",
+    );
+}
+
+/// Report progress of the current phase, `current` out of `total`
+pub fn update(current: usize, total: usize, message: impl fmt::Display) {
+    let progress = if total == 0 { 1.0 } else { current as f32 / total as f32 };
+
+    info!(progress, current, total, event_type = "progress_update", "{message}");
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::Value;
+    use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt as _};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn schema() {
+        let buffer = Buffer::default();
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_subscriber::fmt::layer().json().with_writer(buffer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let phase = Phase::start("cache_packages", 2);
+            update(1, 2, "Cached a");
+            update(2, 2, "Cached b");
+            phase.complete(2);
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let events = output
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        let event_types = events
+            .iter()
+            .map(|event| event["fields"]["event_type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            event_types,
+            [
+                "progress_start",
+                "progress_update",
+                "progress_update",
+                "progress_completed"
+            ]
+        );
+
+        for event in &events {
+            assert_eq!(event["span"]["name"], "progress");
+            assert_eq!(event["span"]["phase"], "cache_packages");
+            assert_eq!(event["span"]["event_type"], "progress");
+        }
+
+        let start = &events[0]["fields"];
+        assert_eq!(start["phase"], "cache_packages");
+        assert_eq!(start["total_items"], 2);
+        assert_eq!(start["progress"], 0.0);
+
+        let update = &events[1]["fields"];
+        assert_eq!(update["progress"], 0.5);
+        assert_eq!(update["current"], 1);
+        assert_eq!(update["total"], 2);
+        assert_eq!(update["message"], "Cached a");
+
+        let completed = &events[3]["fields"];
+        assert_eq!(completed["phase"], "cache_packages");
+        assert_eq!(completed["items_processed"], 2);
+        assert_eq!(completed["progress"], 1.0);
+        assert!(completed["duration_ms"].is_u64());
+    }
+
+    #[test]
+    fn documented_phases() {
+        let docs = include_str!("progress.rs")
+            .lines()
+            .filter_map(|line| line.strip_prefix("//!"))
+            .collect::<String>();
+
+        for phase in PHASES {
+            assert!(docs.contains(&format!("`{phase}`")), "{phase} is undocumented");
+        }
+    }
+}
@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Timeline of progress phases
+//!
+//! Once [`enable`]d, the [`layer`] records when each [`Phase`](crate::progress::Phase)
+//! started & how long it took, independently of the log level. The recorded spans
+//! can be taken with [`take`] to show a breakdown of where time went, or written
+//! out as a [Chrome trace](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU)
+//! for `chrome://tracing` or [Perfetto](https://ui.perfetto.dev).
+
+use std::{
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use serde_json::{Value, json};
+use tracing::{
+    Subscriber,
+    field::{Field, Visit},
+    span,
+};
+use tracing_subscriber::{
+    Layer as _,
+    filter::filter_fn,
+    layer::{Context, SubscriberExt as _},
+    registry::LookupSpan,
+    util::SubscriberInitExt as _,
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SPANS: Mutex<Vec<Span>> = Mutex::new(vec![]);
+
+/// Name of the spans opened by [`Phase`](crate::progress::Phase)
+const PROGRESS_SPAN: &str = "progress";
+
+/// A completed phase
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub phase: String,
+    /// Number of enclosing phases
+    pub depth: usize,
+    pub start: Instant,
+    pub duration: Duration,
+    pub thread: ThreadId,
+}
+
+/// Start recording phases
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Take the phases recorded so far, ordered by their start
+pub fn take() -> Vec<Span> {
+    let mut spans = std::mem::take(&mut *spans());
+    spans.sort_by_key(|span| (span.start, span.depth));
+    spans
+}
+
+/// Encode `spans` in the Chrome trace event format
+pub fn chrome_trace(spans: &[Span]) -> Value {
+    let Some(origin) = spans.iter().map(|span| span.start).min() else {
+        return json!({ "traceEvents": [] });
+    };
+
+    let mut threads = vec![];
+    let events = spans
+        .iter()
+        .map(|span| {
+            let tid = match threads.iter().position(|thread| *thread == span.thread) {
+                Some(index) => index,
+                None => {
+                    threads.push(span.thread);
+                    threads.len() - 1
+                }
+            };
+
+            json!({
+                "name": span.phase,
+                "cat": "phase",
+                "ph": "X",
+                "ts": span.start.duration_since(origin).as_micros() as u64,
+                "dur": span.duration.as_micros() as u64,
+                "pid": 1,
+                "tid": tid,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
+
+/// Layer recording the phases, only interested in progress spans while [`enable`]d
+pub fn layer<S>() -> impl tracing_subscriber::Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Recorder.with_filter(filter_fn(|metadata| {
+        is_enabled() && metadata.is_span() && metadata.name() == PROGRESS_SPAN
+    }))
+}
+
+/// Record phases without logging, for when no log is initialized
+pub fn init() {
+    let _ = tracing_subscriber::registry().with(layer()).try_init();
+}
+
+struct Recorder;
+
+/// Stored in the extensions of an open progress span
+struct Open {
+    phase: String,
+    depth: usize,
+    start: Instant,
+}
+
+impl<S> tracing_subscriber::Layer<S> for Recorder
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut visitor = PhaseVisitor::default();
+        attrs.record(&mut visitor);
+
+        let depth = span
+            .scope()
+            .skip(1)
+            .filter(|parent| parent.name() == PROGRESS_SPAN)
+            .count();
+
+        span.extensions_mut().insert(Open {
+            phase: visitor.0.unwrap_or_default(),
+            depth,
+            start: Instant::now(),
+        });
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<Open>() else {
+            return;
+        };
+
+        spans().push(Span {
+            phase: open.phase,
+            depth: open.depth,
+            start: open.start,
+            duration: open.start.elapsed(),
+            thread: thread::current().id(),
+        });
+    }
+}
+
+#[derive(Default)]
+struct PhaseVisitor(Option<String>);
+
+impl Visit for PhaseVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "phase" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "phase" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+}
+
+fn spans() -> std::sync::MutexGuard<'static, Vec<Span>> {
+    // Spans are pushed whole, so a poisoned lock is still usable
+    SPANS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::progress::Phase;
+
+    #[test]
+    fn test_timeline() {
+        enable();
+
+        let subscriber = tracing_subscriber::registry().with(layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = Phase::start("sync", 1);
+            Phase::start("resolve", 1).complete(1);
+            outer.complete(1);
+        });
+
+        let spans = take();
+        let phases = spans
+            .iter()
+            .map(|span| (span.phase.as_str(), span.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(phases, [("sync", 0), ("resolve", 1)]);
+
+        let trace = chrome_trace(&spans);
+        assert_eq!(trace["traceEvents"][1]["name"], "resolve");
+        assert_eq!(trace["traceEvents"][1]["ph"], "X");
+    }
+}
@@ -0,0 +1,173 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Stall detection for long running work
+//!
+//! Work which may hang, i.e. a download from an unresponsive mirror or a trigger
+//! waiting on something that never happens, registers an [`Activity`] for as long
+//! as it runs and ticks it whenever it makes progress. Once [`spawn`]ed, the
+//! watchdog reports every activity which hasn't ticked within the timeout, once
+//! per stall, so silent hangs tell the user what's being waited on.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+static ACTIVITIES: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// How often the watchdog checks for stalls
+const INTERVAL: Duration = Duration::from_secs(1);
+
+struct Entry {
+    phase: &'static str,
+    waiting_on: String,
+    last_progress: Instant,
+    reported: bool,
+}
+
+/// Running work watched for stalls, unregistered once dropped
+#[must_use = "the activity is unregistered once dropped"]
+pub struct Activity {
+    id: u64,
+}
+
+impl Activity {
+    /// Start watching work in `phase` (i.e. `fetch`) waiting on `waiting_on` (i.e. a URL)
+    pub fn start(phase: &'static str, waiting_on: impl Into<String>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        activities().insert(
+            id,
+            Entry {
+                phase,
+                waiting_on: waiting_on.into(),
+                last_progress: Instant::now(),
+                reported: false,
+            },
+        );
+
+        Self { id }
+    }
+
+    /// Record progress
+    pub fn tick(&self) {
+        if let Some(entry) = activities().get_mut(&self.id) {
+            entry.last_progress = Instant::now();
+            entry.reported = false;
+        }
+    }
+
+    /// Record progress, now waiting on `waiting_on`
+    pub fn waiting_on(&self, waiting_on: impl Into<String>) {
+        if let Some(entry) = activities().get_mut(&self.id) {
+            entry.waiting_on = waiting_on.into();
+            entry.last_progress = Instant::now();
+            entry.reported = false;
+        }
+    }
+}
+
+impl Drop for Activity {
+    fn drop(&mut self) {
+        activities().remove(&self.id);
+    }
+}
+
+/// An [`Activity`] which made no progress within the timeout
+#[derive(Debug, Clone)]
+pub struct Stall {
+    pub phase: &'static str,
+    pub waiting_on: String,
+    /// Time since the last progress
+    pub idle: Duration,
+}
+
+/// Watch all activities from a background thread, calling `on_stall` for each one
+/// making no progress within `timeout`
+pub fn spawn(timeout: Duration, on_stall: impl Fn(&Stall) + Send + 'static) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(INTERVAL);
+
+            for stall in stalled(timeout) {
+                warn!(
+                    phase = stall.phase,
+                    waiting_on = stall.waiting_on,
+                    idle_secs = stall.idle.as_secs(),
+                    "Stalled"
+                );
+                on_stall(&stall);
+            }
+        }
+    });
+}
+
+/// Activities newly stalled for longer than `timeout`, marking them as reported
+fn stalled(timeout: Duration) -> Vec<Stall> {
+    activities()
+        .values_mut()
+        .filter(|entry| !entry.reported && entry.last_progress.elapsed() >= timeout)
+        .map(|entry| {
+            entry.reported = true;
+            Stall {
+                phase: entry.phase,
+                waiting_on: entry.waiting_on.clone(),
+                idle: entry.last_progress.elapsed(),
+            }
+        })
+        .collect()
+}
+
+fn activities() -> std::sync::MutexGuard<'static, BTreeMap<u64, Entry>> {
+    // An entry is never left half-updated, so a poisoned lock is still usable
+    ACTIVITIES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stalled() {
+        let activity = Activity::start("fetch", "https://example.com/a.stone");
+
+        assert!(stalled(Duration::from_secs(60)).is_empty());
+
+        let stalls = stalled(Duration::ZERO);
+        assert!(
+            stalls
+                .iter()
+                .any(|stall| stall.phase == "fetch" && stall.waiting_on == "https://example.com/a.stone")
+        );
+        // Reported once per stall
+        assert!(
+            stalled(Duration::ZERO)
+                .iter()
+                .all(|stall| stall.waiting_on != "https://example.com/a.stone")
+        );
+
+        activity.waiting_on("https://example.com/b.stone");
+        assert!(
+            stalled(Duration::ZERO)
+                .iter()
+                .any(|stall| stall.waiting_on == "https://example.com/b.stone")
+        );
+
+        drop(activity);
+        assert!(
+            stalled(Duration::ZERO)
+                .iter()
+                .all(|stall| !stall.waiting_on.starts_with("https://example.com"))
+        );
+    }
+}
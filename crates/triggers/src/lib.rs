@@ -78,6 +78,11 @@ impl<'a> Collection<'a> {
         }
     }
 
+    /// Names of the triggers matched by any processed path
+    pub fn matched(&self) -> impl Iterator<Item = &str> {
+        self.hits.keys().map(String::as_str)
+    }
+
     /// Bake the trigger collection into a sane dependency order
     pub fn bake(&mut self) -> Result<Vec<format::CompiledHandler>, Error> {
         let mut graph = dag::Dag::new();
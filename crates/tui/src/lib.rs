@@ -2,13 +2,36 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-pub use self::styled::Styled;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub use self::styled::{Styled, no_color, set_color};
 pub use dialoguer;
 pub use indicatif::*;
 
 pub mod pretty;
 mod styled;
 
+/// Whether progress & informational output is suppressed, see [`set_quiet`]
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress progress bars & informational output, leaving summaries & errors
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Where progress bars are drawn, hidden when [`is_quiet`]
+pub fn progress_target() -> ProgressDrawTarget {
+    if is_quiet() {
+        ProgressDrawTarget::hidden()
+    } else {
+        ProgressDrawTarget::stderr()
+    }
+}
+
 /// The size of a terminal emulator window.
 pub struct TermSize {
     /// Width (or number of columns) of the terminal, in characters.
@@ -1,11 +1,32 @@
-use std::io::stdout;
+use std::{env, io::stdout, sync::OnceLock};
 
 use crossterm::{style::Stylize, tty::IsTty};
 
+/// Whether to style output, see [`set_color`]
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+/// Force styling on or off, instead of only styling when stdout is a TTY & `NO_COLOR` isn't set
+///
+/// Only the first call applies.
+pub fn set_color(enabled: bool) {
+    if COLOR.set(enabled).is_ok() {
+        crossterm::style::force_color_output(enabled);
+    }
+}
+
+/// Whether `NO_COLOR` asks for output without color, see <https://no-color.org>
+pub fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+fn is_colored() -> bool {
+    *COLOR.get_or_init(|| stdout().is_tty() && !no_color())
+}
+
 macro_rules! impl_method {
     ($method:ident) => {
         fn $method(self) -> <Self as Stylize>::Styled {
-            if stdout().is_tty() {
+            if is_colored() {
                 <Self as Stylize>::$method(self)
             } else {
                 self.stylize()
@@ -14,7 +35,7 @@ macro_rules! impl_method {
     };
 }
 
-/// Wrapper around `Stylized` which does nothing if not a TTY
+/// Wrapper around `Stylized` which does nothing if not a TTY, unless forced by [`set_color`]
 pub trait Styled: Stylize {
     impl_method!(reset);
     impl_method!(bold);
@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Command aliases
+//!
+//! Aliases are expanded before the arguments are parsed. The defaults can be
+//! overridden, and new ones added, in `alias.yaml` of each settings [`Layer`]
+//! of the host, i.e. `/etc/moss/alias.yaml` or `$XDG_CONFIG_HOME/moss/alias.yaml`:
+//!
+//! ```yaml
+//! lsu: list sync --security-only
+//! # `$1`..`$9` & `$@` are replaced with the arguments following the alias
+//! files: info $@ --files
+//! # An empty expansion disables an alias
+//! ls: ""
+//! ```
+//!
+//! Arguments following an alias without placeholders are appended to the expansion.
+
+use std::{collections::BTreeMap, path::Path};
+
+use clap::Command;
+use moss::settings::Layer;
+use serde::Deserialize;
+
+const DEFAULTS: &[(&str, &str)] = &[
+    ("li", "list installed"),
+    ("la", "list available"),
+    ("ls", "list sync"),
+    ("lu", "list sync"),
+    ("ar", "repo add"),
+    ("lr", "repo list"),
+    ("rr", "repo remove"),
+    ("ur", "repo update"),
+    ("er", "repo enable"),
+    ("dr", "repo disable"),
+    ("ix", "index"),
+    ("it", "install"),
+    ("rm", "remove"),
+    ("up", "sync"),
+];
+
+/// Aliases by name, each expanding to a template of arguments
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Aliases(BTreeMap<String, String>);
+
+impl config::Config for Aliases {
+    fn domain() -> String {
+        "alias".into()
+    }
+}
+
+impl Aliases {
+    /// The default aliases, overridden by those configured for the system & user
+    pub fn load() -> Self {
+        let mut aliases = Self(
+            DEFAULTS
+                .iter()
+                .map(|&(name, template)| (name.to_owned(), template.to_owned()))
+                .collect(),
+        );
+
+        let configured = Layer::ALL
+            .iter()
+            .filter_map(|layer| layer.dir(Path::new("/")))
+            .flat_map(|dir| ::config::Manager::custom(dir).load::<Self>());
+        for configured in configured {
+            aliases.0.extend(configured.0);
+        }
+        aliases.0.retain(|_, template| !template.trim().is_empty());
+
+        aliases
+    }
+
+    /// Expand the alias given in place of the subcommand of `cmd`, if any
+    pub fn replace(&self, cmd: &Command, mut args: Vec<String>) -> Vec<String> {
+        let Some(pos) = subcommand_position(cmd, &args) else {
+            return args;
+        };
+        // Subcommands can't be shadowed
+        if cmd.find_subcommand(&args[pos]).is_some() {
+            return args;
+        }
+        let Some(template) = self.0.get(&args[pos]) else {
+            return args;
+        };
+
+        let trailing = args.split_off(pos + 1);
+        args.pop();
+        args.extend(expand(template, trailing));
+
+        args
+    }
+}
+
+/// Position of the subcommand in `args`, skipping global options & their values
+fn subcommand_position(cmd: &Command, args: &[String]) -> Option<usize> {
+    let takes_value = |arg: &str| {
+        cmd.get_arguments().any(|option| {
+            let matches = match arg.strip_prefix("--") {
+                Some(long) => option.get_long() == Some(long),
+                None => arg.len() == 2 && option.get_short() == arg.chars().nth(1),
+            };
+            matches && option.get_action().takes_values()
+        })
+    };
+
+    let mut pos = 1;
+    while let Some(arg) = args.get(pos) {
+        if arg == "--" || !arg.starts_with('-') {
+            return (arg != "--").then_some(pos);
+        }
+        pos += if takes_value(arg) { 2 } else { 1 };
+    }
+
+    None
+}
+
+/// Expand `template` with the arguments following the alias
+fn expand(template: &str, args: Vec<String>) -> Vec<String> {
+    let words = template.split_whitespace().collect::<Vec<_>>();
+    let placeholder = |word: &str| word == "$@" || positional(word).is_some();
+
+    if !words.iter().any(|word| placeholder(word)) {
+        return words.into_iter().map(str::to_owned).chain(args).collect();
+    }
+
+    words
+        .into_iter()
+        .flat_map(|word| match (word, positional(word)) {
+            ("$@", _) => args.clone(),
+            (_, Some(index)) => args.get(index).cloned().into_iter().collect(),
+            (word, None) => vec![word.to_owned()],
+        })
+        .collect()
+}
+
+/// Index of the argument referenced by a `$1`..`$9` placeholder
+fn positional(word: &str) -> Option<usize> {
+    let digit = word.strip_prefix('$')?;
+    match digit.parse::<usize>() {
+        Ok(n @ 1..=9) if digit.len() == 1 => Some(n - 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{Arg, ArgAction};
+
+    use super::*;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn test_replace() {
+        let cmd = Command::new("moss")
+            .arg(Arg::new("root").short('D').long("directory").action(ArgAction::Set))
+            .arg(Arg::new("yes").short('y').action(ArgAction::SetTrue))
+            .subcommand(Command::new("list"))
+            .subcommand(Command::new("why"));
+        let aliases = Aliases(
+            [
+                ("lsu", "list sync --security-only"),
+                ("whyr", "why $1 --reverse $@"),
+                ("why", "list"),
+            ]
+            .into_iter()
+            .map(|(name, template)| (name.to_owned(), template.to_owned()))
+            .collect(),
+        );
+
+        assert_eq!(
+            aliases.replace(&cmd, args("moss -D lsu -y lsu --json")),
+            args("moss -D lsu -y list sync --security-only --json")
+        );
+        assert_eq!(
+            aliases.replace(&cmd, args("moss whyr bash zsh")),
+            args("moss why bash --reverse bash zsh")
+        );
+        assert_eq!(aliases.replace(&cmd, args("moss why bash")), args("moss why bash"));
+        assert_eq!(aliases.replace(&cmd, args("moss -- lsu")), args("moss -- lsu"));
+    }
+}
@@ -0,0 +1,240 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path;
+
+use clap::{ArgAction, ArgMatches, Command, arg};
+use itertools::Itertools;
+use moss::{
+    Client, Installation, Package, client, environment,
+    package::Flags,
+    repository::{self, advisory},
+    runtime,
+};
+use serde::Serialize;
+use thiserror::Error;
+use tui::Styled;
+use url::Url;
+
+pub fn command() -> Command {
+    Command::new("audit")
+        .about("Audit installed packages for known vulnerabilities")
+        .long_about(
+            "Audit installed packages for known vulnerabilities
+
+Advisories in the OSV format are read from the `advisories.json` published by each active repository, \
+or from the feeds passed with --feed instead. Exits with status 6 if any installed package is affected, \
+so images can be gated on it in CI.",
+        )
+        .arg(
+            arg!(--feed <LOCATION> "Read advisories from this path or URL instead of the repositories")
+                .action(ArgAction::Append),
+        )
+        .arg(arg!(--json "Print the affected packages as JSON").action(ArgAction::SetTrue))
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let json = args.get_flag("json");
+    let feeds = args
+        .get_many::<String>("feed")
+        .into_iter()
+        .flatten()
+        .map(|feed| feed_url(feed))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let client = Client::new(environment::NAME, installation).map_err(Error::SetupClient)?;
+
+    let advisories = if feeds.is_empty() {
+        published(&client)?
+    } else {
+        feeds
+            .into_iter()
+            .map(|url| runtime::block_on(advisory::fetch(url.clone())).map_err(|error| Error::Feed(url, error)))
+            .flatten_ok()
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let installed = client
+        .registry
+        .list_installed()
+        .sorted_by(|a, b| a.meta.name.cmp(&b.meta.name))
+        .collect::<Vec<_>>();
+
+    let reports = installed
+        .iter()
+        .filter_map(|package| Report::new(&client, package, &advisories))
+        .collect::<Vec<_>>();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        print_reports(&reports);
+    }
+
+    if reports.is_empty() {
+        if !json {
+            println!(
+                "No known vulnerabilities in {} installed packages, {} advisories checked",
+                installed.len(),
+                advisories.len()
+            );
+        }
+        Ok(())
+    } else {
+        if !json {
+            println!(
+                "{} of {} installed packages affected by known vulnerabilities",
+                reports.len(),
+                installed.len()
+            );
+        }
+        Err(Error::Vulnerable(reports.len()))
+    }
+}
+
+/// Advisories published by the active repositories, skipping unreachable ones
+fn published(client: &Client) -> Result<Vec<advisory::Advisory>, Error> {
+    let mut advisories = vec![];
+    let mut found = false;
+
+    for (id, repo) in client.active_repositories() {
+        let published = match runtime::block_on(advisory::fetch_published(&repo.uri)) {
+            Ok(published) => published,
+            // Audit against the repositories which are reachable, rather than none
+            Err(advisory::Error::Fetch(error)) => {
+                eprintln!(
+                    "{}: skipping the advisories of repository {id}, it's unreachable: {error}",
+                    "Warning".yellow()
+                );
+                continue;
+            }
+            Err(error) => return Err(Error::Published(id.clone(), error)),
+        };
+
+        if let Some(published) = published {
+            found = true;
+            advisories.extend(published);
+        }
+    }
+
+    if !found {
+        return Err(Error::NoAdvisories);
+    }
+
+    Ok(advisories)
+}
+
+/// A feed is either an URL or a local path
+fn feed_url(feed: &str) -> Result<Url, Error> {
+    match Url::parse(feed) {
+        Ok(url) if matches!(url.scheme(), "http" | "https" | "file") => Ok(url),
+        _ => path::absolute(feed)
+            .ok()
+            .and_then(|path| Url::from_file_path(path).ok())
+            .ok_or_else(|| Error::InvalidFeed(feed.to_owned())),
+    }
+}
+
+fn print_reports(reports: &[Report]) {
+    for report in reports {
+        println!("{} {}-{}", report.name.as_str().bold(), report.version, report.release);
+
+        for entry in &report.advisories {
+            let aliases = if entry.finding.aliases.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", format!("({})", entry.finding.aliases.join(", ")).dim())
+            };
+            println!(
+                "  {}{aliases} {}",
+                entry.finding.id.as_str().red(),
+                entry.finding.summary
+            );
+
+            let hint = match (&entry.update, &entry.finding.fixed) {
+                (Some(update), _) => format!("fixed by updating to {update}").green().to_string(),
+                (None, Some(fixed)) => format!("fixed in {fixed}, not available yet").yellow().to_string(),
+                (None, None) => "no fix known".dim().to_string(),
+            };
+            println!("    {hint}");
+        }
+        println!();
+    }
+}
+
+/// An installed package affected by known vulnerabilities
+#[derive(Debug, Serialize)]
+struct Report {
+    name: String,
+    version: String,
+    release: u64,
+    advisories: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    #[serde(flatten)]
+    finding: advisory::Finding,
+    /// Revision of the available candidate that's no longer affected, if any
+    update: Option<String>,
+}
+
+impl Report {
+    fn new(client: &Client, package: &Package, advisories: &[advisory::Advisory]) -> Option<Self> {
+        let candidate = client
+            .registry
+            .by_name(&package.meta.name, Flags::new().with_available())
+            .next()
+            .filter(|candidate| candidate.meta.source_release > package.meta.source_release);
+
+        let entries = advisories
+            .iter()
+            .filter_map(|advisory| {
+                let finding = advisory.affects(&package.meta)?;
+                let update = candidate
+                    .as_ref()
+                    .filter(|candidate| advisory.affects(&candidate.meta).is_none())
+                    .map(|candidate| {
+                        format!(
+                            "{}-{}",
+                            candidate.meta.version_identifier, candidate.meta.source_release
+                        )
+                    });
+
+                Some(Entry { finding, update })
+            })
+            .collect::<Vec<_>>();
+
+        (!entries.is_empty()).then(|| Self {
+            name: package.meta.name.to_string(),
+            version: package.meta.version_identifier.clone(),
+            release: package.meta.source_release,
+            advisories: entries,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to setup moss client")]
+    SetupClient(#[source] client::Error),
+
+    #[error("invalid feed {0:?}, expected a path or URL")]
+    InvalidFeed(String),
+
+    #[error("failed to read advisories from {0}")]
+    Feed(Url, #[source] advisory::Error),
+
+    #[error("failed to read advisories published by {0}")]
+    Published(repository::Id, #[source] advisory::Error),
+
+    #[error("no active repository publishes advisories, pass a feed with --feed")]
+    NoAdvisories,
+
+    #[error("encode json")]
+    Json(#[from] serde_json::Error),
+
+    #[error("{0} installed package(s) affected by known vulnerabilities")]
+    Vulnerable(usize),
+}
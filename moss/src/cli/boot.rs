@@ -2,13 +2,19 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::BTreeSet;
 use std::path::Path;
 
 use blsforme::bootloader::systemd_boot::{self};
-use clap::{ArgMatches, Command};
+use clap::{ArgAction, ArgMatches, Command, arg};
 use thiserror::Error;
 
-use moss::Installation;
+use moss::{
+    Installation, State,
+    client::{self, Client, boot::BootEntry},
+    environment, state,
+};
+use tui::Styled;
 
 pub fn command() -> Command {
     Command::new("boot")
@@ -16,10 +22,98 @@ pub fn command() -> Command {
         .long_about("Manage boot configuration")
         .subcommand_required(true)
         .subcommand(Command::new("status").about("Status of boot configuration"))
+        .subcommand(Command::new("list").about("List kernels and boot entries").long_about(
+            "List the kernels & initrds shipped by each state along with the boot loader \
+             entries pointing at them, reporting stale entries whose state no longer exists.\n\n\
+             Boot partitions aren't mounted for this, entries of one which isn't mounted are left out",
+        ))
+        .subcommand(
+            Command::new("generate-uki")
+                .about("Generate unified kernel images")
+                .long_about(
+                    "Assemble the kernel, initrds, command line and os-release of a state into a \
+                     unified kernel image per kernel, installed to the ESP along with a boot loader \
+                     entry so each state can be booted independently",
+                )
+                .arg(
+                    arg!([ID] ... "States (id or tag) to generate images for, defaults to the active state")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(--cmdline <CMDLINE> "Append to the kernel command line").action(ArgAction::Set)),
+        )
+        .subcommand(
+            Command::new("cmdline")
+                .about("Manage the kernel command line of states")
+                .long_about(
+                    "Each state has its own kernel command line fragment, appended to its boot loader entries \
+                     & unified kernel images. New states inherit the fragment of the active state, so tweaks \
+                     such as `quiet splash` survive transitions, while a rollback boots with the command line \
+                     the state was used with",
+                )
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Show the kernel command line fragment of a state")
+                        .arg(
+                            arg!([ID] "State (id or tag), defaults to the active state")
+                                .value_parser(clap::value_parser!(state::Reference)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Set the kernel command line fragment of a state")
+                        .long_about(
+                            "Set the kernel command line fragment of a state, replacing any previous one, \
+                             and regenerate the boot loader entries",
+                        )
+                        .arg(
+                            arg!(--state <ID> "State (id or tag), defaults to the active state")
+                                .value_parser(clap::value_parser!(state::Reference)),
+                        )
+                        .arg(
+                            arg!([ARGS] ... "Kernel command line arguments")
+                                .action(ArgAction::Append)
+                                .required_unless_present("clear"),
+                        )
+                        .arg(
+                            arg!(--clear "Remove the fragment")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("ARGS"),
+                        ),
+                ),
+        )
 }
 
-/// Handle status for now
-pub fn handle(_args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand() {
+        Some(("status" | "list", _)) => false,
+        Some(("generate-uki", _)) => true,
+        Some(("cmdline", args)) => match args.subcommand_name() {
+            Some("get") => false,
+            Some("set") => true,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("status", _)) => status(installation),
+        Some(("list", _)) => list(installation),
+        Some(("generate-uki", args)) => generate_uki(args, installation),
+        Some(("cmdline", args)) => match args.subcommand() {
+            Some(("get", args)) => cmdline_get(args, installation),
+            Some(("set", args)) => cmdline_set(args, installation),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn status(installation: Installation) -> Result<(), Error> {
     fn display_optional_path(path: Option<&Path>) -> std::path::Display<'_> {
         path.unwrap_or_else(|| "none".as_ref()).display()
     }
@@ -55,9 +149,214 @@ pub fn handle(_args: &ArgMatches, installation: Installation) -> Result<(), Erro
 
     println!("Global cmdline : {:?}", manager.cmdline());
 
+    let client = Client::new(environment::NAME, installation)?;
+    let entries = client.boot_entries()?;
+    let states = state_ids(&client)?;
+    let stale = entries
+        .iter()
+        .filter(|entry| matches!(classify(entry, &states, &client.installation), Status::Stale(_)))
+        .count();
+
+    println!("Entries        : {} ({stale} stale)", entries.len());
+
+    Ok(())
+}
+
+fn list(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let entries = client.boot_entries()?;
+    let states = state_ids(&client)?;
+
+    for id in states.iter().rev() {
+        let state = client.state_db.get(*id)?;
+        let assets = client.boot_assets(&state)?;
+
+        let mut header = format!("State #{}", id.to_string().bold());
+        if client.installation.active_state == Some(*id) {
+            header.push_str(&format!(" {}", "(active)".green()));
+        }
+        if let Some(summary) = &state.summary {
+            header.push_str(&format!(" - {summary}"));
+        }
+        println!("{header}");
+
+        if !assets.is_bootable() {
+            println!("  {}", "No kernels".dim());
+        }
+        for kernel in &assets.kernels {
+            println!("  {} {}", "Kernel:".bold(), kernel.version);
+            if let Some(image) = &kernel.image {
+                println!("    {} {}", "Image:".dim(), image.display());
+            }
+            for initrd in &kernel.initrds {
+                println!("    {} {}", "Initrd:".dim(), initrd.display());
+            }
+        }
+        if !assets.missing.is_empty() {
+            println!(
+                "  {}: {} boot asset(s) have been pruned",
+                "Warning".yellow(),
+                assets.missing.len()
+            );
+        }
+
+        let state_entries = entries.iter().filter(|e| e.state == Some(*id)).collect::<Vec<_>>();
+        if state_entries.is_empty() {
+            println!("  {} {}", "Entry:".bold(), "none".dim());
+        }
+        for entry in state_entries {
+            println!("  {} {}", "Entry:".bold(), entry.path.display());
+        }
+
+        println!();
+    }
+
+    let (stale, unmanaged): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .filter_map(|entry| match classify(entry, &states, &client.installation) {
+            Status::Current => None,
+            status => Some((entry, status)),
+        })
+        .partition(|(_, status)| matches!(status, Status::Stale(_)));
+
+    if !stale.is_empty() {
+        println!("{}", "Stale entries:".yellow());
+        for (entry, status) in stale {
+            if let Status::Stale(reason) = status {
+                println!("  {} {}", entry.path.display(), format!("({reason})").dim());
+            }
+        }
+        println!();
+    }
+
+    if !unmanaged.is_empty() {
+        println!("{}", "Entries not managed by moss:".bold());
+        for (entry, _) in unmanaged {
+            let title = entry.title.as_deref().unwrap_or("untitled");
+            println!("  {} {}", entry.path.display(), format!("({title})").dim());
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn generate_uki(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let cmdline = args.get_one::<String>("cmdline").map(String::as_str);
+    let active_state = installation.active_state;
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let ids = match args.get_many::<state::Reference>("ID") {
+        Some(references) => references
+            .map(|reference| client.state_db.resolve(reference))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![active_state.ok_or(Error::NoActiveState)?],
+    };
+
+    for id in ids {
+        let state = client.state_db.get(id)?;
+
+        for uki in client.generate_uki(&state, cmdline)? {
+            println!(
+                "State #{} kernel {}: {}",
+                id.to_string().bold(),
+                uki.version,
+                uki.image.display()
+            );
+            println!("  {} {}", "Entry:".dim(), uki.entry.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn cmdline_get(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let state = resolve_state(&client, args.get_one::<state::Reference>("ID"))?;
+
+    match &state.cmdline {
+        Some(cmdline) => println!("State #{}: {cmdline}", state.id.to_string().bold()),
+        None => println!(
+            "State #{}: {}",
+            state.id.to_string().bold(),
+            "no kernel command line fragment".dim()
+        ),
+    }
+
+    Ok(())
+}
+
+fn cmdline_set(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+    let state = resolve_state(&client, args.get_one::<state::Reference>("state"))?;
+
+    let cmdline = args
+        .get_many::<String>("ARGS")
+        .map(|args| args.map(String::as_str).collect::<Vec<_>>().join(" "));
+
+    client.set_boot_cmdline(&state, cmdline.as_deref())?;
+
+    match &cmdline {
+        Some(cmdline) => println!("State #{} boots with {cmdline}", state.id.to_string().bold()),
+        None => println!("State #{} kernel command line cleared", state.id.to_string().bold()),
+    }
+
+    // Unified kernel images embed their command line, so aren't regenerated along with entries
+    let has_uki = client
+        .boot_entries()
+        .unwrap_or_default()
+        .iter()
+        .any(|entry| entry.state == Some(state.id) && entry.efi.is_some());
+    if has_uki {
+        println!(
+            "{}: run `moss boot generate-uki {}` to update its unified kernel images",
+            "Note".yellow(),
+            state.id
+        );
+    }
+
     Ok(())
 }
 
+/// The referenced state, or the active state if none is given
+fn resolve_state(client: &Client, reference: Option<&state::Reference>) -> Result<State, Error> {
+    let id = match reference {
+        Some(reference) => client.state_db.resolve(reference)?,
+        None => client.installation.active_state.ok_or(Error::NoActiveState)?,
+    };
+
+    Ok(client.state_db.get(id)?)
+}
+
+/// Status of a boot entry relative to the recorded states
+enum Status {
+    /// Boots an existing state
+    Current,
+    /// Boots a state which can no longer be booted
+    Stale(String),
+    /// Not created for any state
+    Unmanaged,
+}
+
+fn classify(entry: &BootEntry, states: &BTreeSet<state::Id>, installation: &Installation) -> Status {
+    let Some(id) = entry.state else {
+        return Status::Unmanaged;
+    };
+
+    if !states.contains(&id) {
+        Status::Stale(format!("state #{id} was removed"))
+    } else if installation.active_state != Some(id) && !installation.root_path(id.to_string()).exists() {
+        Status::Stale(format!("state #{id} is not archived"))
+    } else {
+        Status::Current
+    }
+}
+
+fn state_ids(client: &Client) -> Result<BTreeSet<state::Id>, Error> {
+    Ok(client.state_db.list_ids()?.into_iter().map(|(id, _)| id).collect())
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("blsforme")]
@@ -71,4 +370,13 @@ pub enum Error {
 
     #[error("os-release")]
     OsRelease(#[from] blsforme::os_release::Error),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
+    #[error("no active state")]
+    NoActiveState,
 }
@@ -1,6 +1,13 @@
-use clap::{ArgMatches, Command};
-use moss::{Client, Installation, client, environment};
+use std::{io, process};
+
+use clap::{Arg, ArgMatches, Command};
+use moss::{
+    Client, Installation,
+    client::{self, cache},
+    environment, repository,
+};
 use thiserror::Error;
+use tui::{ProgressBar, ProgressStyle, Styled};
 
 pub fn command() -> Command {
     Command::new("cache")
@@ -11,11 +18,36 @@ pub fn command() -> Command {
 
 This will remove all downloaded stones & unpacked asset data for packages not in any state or active repository.",
         ))
+        .subcommand(
+            Command::new("purge")
+                .about("Purge cached downloads of a repository")
+                .long_about(
+                    "Purge cached downloads of a repository
+
+This will remove all stones downloaded from the repository, i.e. when they can't be trusted anymore. Unpacked asset data is shared between repositories and not affected, use `cache prune` afterwards to remove assets no longer in use.",
+                )
+                .arg(Arg::new("NAME").required(true).help("repository name")),
+        )
+        .subcommand(Command::new("verify").about("Verify cached downloads").long_about(
+            "Verify cached downloads
+
+Every downloaded stone is checked against the hash it was indexed with. Corrupt stones are moved to the `quarantine` directory of the cache & downloaded again when next needed.",
+        ))
+}
+
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand_name() {
+        Some("prune" | "purge" | "verify") => true,
+        _ => unreachable!(),
+    }
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     match args.subcommand() {
         Some(("prune", args)) => handle_prune(args, installation),
+        Some(("purge", args)) => handle_purge(args, installation),
+        Some(("verify", args)) => handle_verify(args, installation),
         _ => unreachable!(),
     }
 }
@@ -36,10 +68,73 @@ fn handle_prune(_args: &ArgMatches, installation: Installation) -> Result<(), Er
     Ok(())
 }
 
+fn handle_purge(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let id = repository::Id::new(args.get_one::<String>("NAME").unwrap());
+
+    let client = Client::new(environment::NAME, installation).map_err(Error::SetupClient)?;
+
+    if !client.purge_downloads(&id).map_err(Error::PurgeDownloads)? {
+        println!("{id} not found");
+        process::exit(1);
+    }
+
+    println!("Cached downloads of {id} purged");
+
+    Ok(())
+}
+
+fn handle_verify(_args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let downloads = cache::downloads(&installation).map_err(Error::Verify)?;
+
+    let progress = ProgressBar::with_draw_target(Some(downloads.len() as u64), tui::progress_target()).with_style(
+        ProgressStyle::with_template("\n|{bar:20.cyan/blue}| {pos}/{len} {wide_msg}")
+            .unwrap()
+            .progress_chars("■≡=- "),
+    );
+
+    let mut corrupt = 0;
+
+    for (path, hash) in &downloads {
+        progress.set_message(format!("{} {}", "Verifying".blue(), hash.as_str().dim()));
+
+        if !cache::verify(path, hash).map_err(Error::Verify)? {
+            let quarantined = cache::quarantine(&installation, path).map_err(Error::Verify)?;
+            progress.suspend(|| {
+                println!(
+                    "{} {} moved to {}",
+                    "Corrupt".red(),
+                    path.display(),
+                    quarantined.display()
+                );
+            });
+            corrupt += 1;
+        }
+
+        progress.inc(1);
+    }
+
+    progress.finish_and_clear();
+
+    if corrupt > 0 {
+        println!(
+            "{} cached download(s) verified, {corrupt} corrupt & quarantined",
+            downloads.len()
+        );
+    } else {
+        println!("{} cached download(s) verified, none corrupt", downloads.len());
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to setup moss client")]
     SetupClient(#[source] client::Error),
     #[error("failed to prune cache")]
     PruneCache(#[source] client::Error),
+    #[error("failed to purge cached downloads")]
+    PurgeDownloads(#[source] client::Error),
+    #[error("failed to verify cached downloads")]
+    Verify(#[source] io::Error),
 }
@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::PathBuf;
+
+use clap::{ArgAction, ArgGroup, ArgMatches, Command, arg};
+use moss::{
+    Client, Installation,
+    client::{self, prune, space},
+    environment,
+};
+use thiserror::Error;
+use tui::{HumanBytes, Styled};
+
+pub fn command() -> Command {
+    Command::new("clean")
+        .about("Free up disk space")
+        .long_about(
+            "Free up disk space
+
+Cached downloads, unpacked assets & archived states are cleaned up as chosen, reporting the space reclaimed from each. \
+Without --keep, states are pruned by the policy configured in /etc/moss/prune.yaml & /etc/moss/prune.d.",
+        )
+        .arg(
+            arg!(--downloads "Remove all cached downloads, they're downloaded again when needed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--assets "Remove unpacked assets & downloads not used by any state or active repository")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(arg!(--states "Prune archived states").action(ArgAction::SetTrue))
+        .arg(
+            arg!(-k --keep <N> "Keep this many states when pruning them")
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--force "Also prune states referenced by boot entries, removing those entries")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(arg!(--all "Clean up downloads, assets & states").action(ArgAction::SetTrue))
+        .group(
+            ArgGroup::new("what")
+                .args(["downloads", "assets", "states", "all"])
+                .multiple(true)
+                .required(true),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let all = args.get_flag("all");
+    let yes = args.get_flag("yes");
+
+    let client = Client::new(environment::NAME, installation).map_err(Error::SetupClient)?;
+
+    let paths = [
+        client.installation.cache_path(""),
+        client.installation.assets_path(""),
+        client.installation.root_path(""),
+    ];
+    let usage = || space::disk_usage(paths.iter().map(PathBuf::as_path)).map_err(Error::DiskUsage);
+
+    let mut reclaimed = vec![];
+    let mut before = usage()?;
+    let mut measure = |category: &'static str| -> Result<(), Error> {
+        let after = usage()?;
+        reclaimed.push((category, before.saturating_sub(after)));
+        before = after;
+        Ok(())
+    };
+
+    // States first, as pruning them leaves more assets & downloads unused
+    if all || args.get_flag("states") {
+        let strategy = match args.get_one::<u64>("keep") {
+            Some(keep) => prune::Strategy::KeepRecent {
+                keep: *keep,
+                include_newer: false,
+            },
+            None => prune::Strategy::Policy(client.prune_policy()),
+        };
+        client
+            .prune_states(strategy, args.get_flag("force"), yes)
+            .map_err(Error::PruneStates)?;
+        measure("states")?;
+    }
+
+    if all || args.get_flag("assets") {
+        client.prune_cache().map_err(Error::PruneCache)?;
+        measure("assets")?;
+    }
+
+    if all || args.get_flag("downloads") {
+        client.clean_downloads().map_err(Error::CleanDownloads)?;
+        measure("downloads")?;
+    }
+
+    println!("{}", "Reclaimed".bold());
+    for (category, bytes) in &reclaimed {
+        println!("  {:<12} {}", format!("{category}:"), HumanBytes(*bytes));
+    }
+    println!(
+        "  {:<12} {}",
+        "total:",
+        HumanBytes(reclaimed.iter().map(|(_, bytes)| bytes).sum())
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to setup moss client")]
+    SetupClient(#[source] client::Error),
+    #[error("failed to prune states")]
+    PruneStates(#[source] client::Error),
+    #[error("failed to prune cache")]
+    PruneCache(#[source] client::Error),
+    #[error("failed to remove cached downloads")]
+    CleanDownloads(#[source] client::Error),
+    #[error("failed to measure disk usage")]
+    DiskUsage(#[source] std::io::Error),
+}
@@ -0,0 +1,117 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{Arg, ArgMatches, Command, ValueEnum, value_parser};
+use moss::{
+    Installation,
+    client::{self, Client},
+    environment,
+    package::Flags,
+};
+use thiserror::Error;
+use tui::Styled;
+
+use super::Outcome;
+
+/// Shells with a command-not-found handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+pub fn command() -> Command {
+    Command::new("command-not-found")
+        .about("Suggest packages providing a missing command")
+        .long_about(
+            "Suggest the packages providing a missing command, without installing anything.
+
+Meant to be called by the command-not-found handler of a shell, as printed by --shell. With --exit-code, \
+exits with 8 if no package provides the command, leaving the shell to report it as usual.",
+        )
+        .arg(
+            Arg::new("COMMAND")
+                .required_unless_present("shell")
+                .help("Command to look up")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("shell")
+                .long("shell")
+                .help("Print the command-not-found handler for the shell, i.e. to source it from its rc file")
+                .value_parser(value_parser!(Shell))
+                .conflicts_with("COMMAND"),
+        )
+}
+
+/// Handle execution of `moss command-not-found`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<Outcome, Error> {
+    if let Some(shell) = args.get_one::<Shell>("shell") {
+        print!("{}", handler(*shell));
+        return Ok(Outcome::Done);
+    }
+
+    let command = args.get_one::<String>("COMMAND").unwrap();
+    let client = Client::new(environment::NAME, installation)?;
+
+    // Installed but not on `$PATH`, i.e. a binary of `/usr/sbin` for a regular user
+    if let Some(installed) = client.command_providers(command, Flags::new().with_installed()).first() {
+        eprintln!(
+            "{command}: command not found, but it's provided by the installed package {}, check your $PATH",
+            installed.meta.name.to_string().bold()
+        );
+        return Ok(Outcome::Done);
+    }
+
+    let available = client.command_providers(command, Flags::new().with_available());
+    if available.is_empty() {
+        return Ok(Outcome::NothingToDo);
+    }
+
+    eprintln!("{command}: command not found, it's provided by:");
+    for package in &available {
+        let repository = client
+            .origin(package)
+            .map(|id| format!(" ({id})").dim().to_string())
+            .unwrap_or_default();
+        eprintln!("  {}{repository}", package.meta.name.to_string().bold());
+    }
+    eprintln!();
+    eprintln!("Install it with: moss install --command {command}");
+
+    Ok(Outcome::Done)
+}
+
+/// The command-not-found handler for `shell`, falling back to its own report if nothing provides the command
+fn handler(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => {
+            "command_not_found_handle() {
+    /usr/bin/moss command-not-found --exit-code -- \"$1\" || printf '%s: command not found\\n' \"$1\" >&2
+    return 127
+}
+"
+        }
+        Shell::Zsh => {
+            "command_not_found_handler() {
+    /usr/bin/moss command-not-found --exit-code -- \"$1\" || printf 'zsh: command not found: %s\\n' \"$1\" >&2
+    return 127
+}
+"
+        }
+        Shell::Fish => {
+            "function fish_command_not_found
+    /usr/bin/moss command-not-found --exit-code -- $argv[1]; or __fish_default_command_not_found_handler $argv
+end
+"
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+}
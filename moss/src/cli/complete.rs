@@ -0,0 +1,225 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Dynamic shell completion
+//!
+//! The completions generated by `--generate-completions` are static, so values only known
+//! at runtime, such as state ids & package names, are completed by calling back into the
+//! hidden `__complete` command, which prints one `value\tdescription` line per candidate.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use chrono::Local;
+use clap::{ArgMatches, Command, ValueEnum, arg, value_parser};
+use clap_complete::Shell;
+use moss::{
+    Client, Installation, client, db, environment,
+    package::{self, Flags},
+};
+use thiserror::Error;
+
+/// `state` subcommands taking a state id
+const STATE_ID_COMMANDS: [&str; 4] = ["activate", "query", "remove", "tag"];
+
+/// Commands taking package names, with the kind of package they complete
+const PACKAGE_COMMANDS: [(&str, Kind); 2] = [
+    ("install", Kind::AvailablePackages),
+    ("remove", Kind::InstalledPackages),
+];
+
+/// Kinds of values completed dynamically
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Kind {
+    /// Ids of all states newest first, followed by their tags
+    StateIds,
+    /// Names of the packages available from the active repositories
+    AvailablePackages,
+    /// Names of the installed packages
+    InstalledPackages,
+}
+
+pub fn command() -> Command {
+    Command::new("__complete")
+        .about("Print completion candidates for the shell completions")
+        .hide(true)
+        .arg(arg!(<KIND> "kind of value to complete").value_parser(value_parser!(Kind)))
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.get_one::<Kind>("KIND").unwrap() {
+        Kind::StateIds => state_ids(&installation),
+        Kind::AvailablePackages => packages(installation, Flags::new().with_available()),
+        Kind::InstalledPackages => packages(installation, Flags::new().with_installed()),
+    }
+}
+
+/// Print the name of every package matching `flags`, described by its summary
+fn packages(installation: Installation, flags: Flags) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    // Names are listed once, with the summary of the highest priority candidate
+    let mut packages = BTreeMap::<package::Name, String>::new();
+    for package in client.registry.list(flags) {
+        packages.entry(package.meta.name).or_insert(package.meta.summary);
+    }
+
+    let mut stdout = io::stdout().lock();
+    for (name, summary) in packages {
+        writeln!(stdout, "{name}\t{summary}")?;
+    }
+
+    Ok(())
+}
+
+/// Print every state id, described by its creation time & summary, then every tag
+fn state_ids(installation: &Installation) -> Result<(), Error> {
+    let state_db = db::state::Database::new(installation.db_path("state").to_str().unwrap_or_default())?;
+
+    let mut states = state_db.all()?;
+    states.sort_by_key(|state| std::cmp::Reverse((state.created, state.id)));
+
+    let mut stdout = io::stdout().lock();
+
+    for state in &states {
+        let created = state.created.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+        let active = if installation.active_state == Some(state.id) {
+            " (active)"
+        } else {
+            ""
+        };
+
+        match &state.summary {
+            Some(summary) => writeln!(stdout, "{}\t{created} {summary}{active}", state.id)?,
+            None => writeln!(stdout, "{}\t{created}{active}", state.id)?,
+        }
+    }
+
+    for state in &states {
+        for tag in &state.tags {
+            writeln!(stdout, "{tag}\ttag of state #{}", state.id)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Extend the generated completion `script` for `shell` with the dynamic completions
+pub fn extend(shell: Shell, cmd: &Command, script: &mut String) {
+    match shell {
+        Shell::Bash => script.push_str(BASH),
+        Shell::Fish => script.push_str(FISH),
+        Shell::Zsh => {
+            // Swap the completer of each state id argument
+            let state = cmd.find_subcommand("state").expect("state command");
+            for name in STATE_ID_COMMANDS {
+                let help = state
+                    .find_subcommand(name)
+                    .and_then(|subcommand| subcommand.get_arguments().find(|arg| arg.get_id() == "ID"))
+                    .and_then(|arg| arg.get_help())
+                    .expect("state id argument");
+                *script = script.replace(
+                    &format!("':ID -- {help}:_default'"),
+                    &format!("':ID -- {help}:_moss_complete state-ids'"),
+                );
+            }
+
+            for (name, kind) in PACKAGE_COMMANDS {
+                let help = cmd
+                    .find_subcommand(name)
+                    .and_then(|subcommand| subcommand.get_arguments().find(|arg| arg.get_id() == "NAME"))
+                    .and_then(|arg| arg.get_help())
+                    .expect("package name argument");
+                let kind = kind.to_possible_value().expect("kind value");
+                *script = script.replace(
+                    &format!("'*::NAME -- {help}:_default'"),
+                    &format!("'*::NAME -- {help}:_moss_complete {}'", kind.get_name()),
+                );
+            }
+
+            // Functions must be defined before the trailing `compdef`
+            let end = script.rfind("if [ \"$funcstack[1]\"").unwrap_or(script.len());
+            script.insert_str(end, ZSH);
+        }
+        _ => {}
+    }
+}
+
+const BASH: &str = r#"
+# Complete state ids & package names from the root being completed
+_moss_dynamic() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD - 1]}" root=() words=() kind= i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -D | --directory) root=(-D "${COMP_WORDS[i + 1]}"); ((i++)) ;;
+            --cache | --chrome-trace | --color | --log | --stall-timeout) ((i++)) ;;
+            -*) ;;
+            *) words+=("${COMP_WORDS[i]}") ;;
+        esac
+    done
+
+    if [[ $cur != -* && $prev != --to ]]; then
+        if [[ ${#words[@]} -eq 2 && ${words[0]} == state && ${words[1]} =~ ^(activate|query|remove|tag)$ ]]; then
+            kind=state-ids
+        elif [[ ${#words[@]} -ge 1 && ${words[0]} =~ ^(install|it)$ ]]; then
+            kind=available-packages
+        elif [[ ${#words[@]} -ge 1 && ${words[0]} =~ ^(remove|rm)$ ]]; then
+            kind=installed-packages
+        fi
+    fi
+
+    if [[ -n $kind ]]; then
+        COMPREPLY=($(compgen -W "$(moss "${root[@]}" __complete $kind 2>/dev/null | cut -f1)" -- "$cur"))
+        return 0
+    fi
+
+    _moss "$@"
+}
+
+complete -F _moss_dynamic -o bashdefault -o default moss
+"#;
+
+const FISH: &str = r#"
+# Complete state ids & package names from the root being completed
+function __fish_moss_complete
+    set -l cmd (commandline -opc)
+    set -e cmd[1]
+    argparse -s (__fish_moss_global_optspecs) -- $cmd 2>/dev/null
+    set -l root
+    if set -q _flag_directory
+        set root -D $_flag_directory
+    end
+    moss $root __complete $argv[1] 2>/dev/null
+end
+
+complete -c moss -n "__fish_moss_using_subcommand state; and __fish_seen_subcommand_from activate query remove tag" -f -a "(__fish_moss_complete state-ids)"
+complete -c moss -n "__fish_moss_using_subcommand install it" -f -a "(__fish_moss_complete available-packages)"
+complete -c moss -n "__fish_moss_using_subcommand remove rm" -f -a "(__fish_moss_complete installed-packages)"
+"#;
+
+const ZSH: &str = r#"# Complete state ids & package names from the root being completed
+(( $+functions[_moss_complete] )) ||
+_moss_complete() {
+    local -a line root values
+    local i
+    line=(${(z)BUFFER})
+    i=${line[(I)-D|--directory]}
+    (( i )) && root=(-D "${(Q)line[i + 1]}")
+    values=(${${(f)"$(moss $root __complete $1 2>/dev/null)"}//$'\t'/:})
+    _describe -t $1 ${1//-/ } values
+}
+
+"#;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("db")]
+    DB(#[from] db::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
@@ -0,0 +1,226 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::Path;
+
+use clap::{ArgAction, ArgMatches, Command, arg};
+use fs_err as fs;
+use moss::{
+    Client, Installation,
+    client::{self, etc},
+    environment,
+    settings::{self, Layer, Settings},
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("config")
+        .about("Inspect configuration files & manage settings")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("status")
+                .about("List locally modified configuration files")
+                .long_about(
+                    "List locally modified configuration files
+
+Files in /etc are compared with the packaged vendor defaults under /usr/share/defaults/etc, using the hashes recorded for the active state. Files awaiting a merge by hand have their new default written alongside them as `<file>.new`.",
+                )
+                .arg(arg!(--diff "Show the changes to the packaged version").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List settings")
+                .long_about(
+                    "List settings
+
+Settings are read from config.yaml in /usr/share/moss (vendor), /etc/moss (system) & $XDG_CONFIG_HOME/moss (user), \
+along with any config.d/*.yaml drop-ins, later layers overriding earlier ones. Each setting is shown with the layer \
+setting it.",
+                ),
+        )
+        .subcommand(
+            Command::new("get")
+                .about("Print the value of a setting")
+                .arg(arg!(<KEY> "Setting to print")),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("Change a setting")
+                .arg(arg!(<KEY> "Setting to change"))
+                .arg(arg!(<VALUE> "New value of the setting"))
+                .arg(arg!(--user "Change the setting for the invoking user only").action(ArgAction::SetTrue)),
+        )
+        .subcommand(
+            Command::new("unset")
+                .about("Reset a setting to the value of a lower layer or its default")
+                .arg(arg!(<KEY> "Setting to reset"))
+                .arg(arg!(--user "Reset the setting for the invoking user only").action(ArgAction::SetTrue)),
+        )
+}
+
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand() {
+        Some(("status" | "list" | "get", _)) => false,
+        // The settings of the invoking user live outside the installation
+        Some(("set" | "unset", args)) => !args.get_flag("user"),
+        _ => unreachable!(),
+    }
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("status", args)) => handle_status(args, installation),
+        Some(("list", _)) => handle_list(installation),
+        Some(("get", args)) => handle_get(args, installation),
+        Some(("set", args)) => handle_set(args, installation),
+        Some(("unset", args)) => handle_unset(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+fn handle_status(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let show_diff = args.get_flag("diff");
+
+    let client = Client::new(environment::NAME, installation).map_err(Error::SetupClient)?;
+    let statuses = client.config_status().map_err(Error::Status)?;
+
+    if statuses.is_empty() {
+        println!("No locally modified configuration files");
+        return Ok(());
+    }
+
+    for status in &statuses {
+        let path = format!("/etc/{}", status.path);
+        let (marker, description) = match status.change {
+            etc::Change::Modified => ("M".yellow(), "modified"),
+            etc::Change::Removed => ("D".red(), "removed"),
+            etc::Change::Conflict => ("C".red(), "conflict"),
+        };
+        println!(
+            "{marker} {} {}",
+            path.as_str().bold(),
+            format!("({description}, from {})", status.package).dim()
+        );
+
+        if show_diff {
+            let vendor = client.installation.root.join(etc::VENDOR_DIR).join(&status.path);
+            print_diff(&vendor, &client.installation.root.join("etc").join(&status.path))?;
+        }
+    }
+
+    if !show_diff {
+        println!();
+        println!(
+            "{} configuration file(s) differ from their packaged version",
+            statuses.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn handle_list(installation: Installation) -> Result<(), Error> {
+    let resolved = Settings::resolve(&installation.root)?;
+
+    let width = settings::KEYS
+        .iter()
+        .map(|(key, _)| key.len())
+        .max()
+        .unwrap_or_default();
+    for (key, description) in settings::KEYS {
+        match resolved.get(key) {
+            Some((value, layer)) => println!("{key:width$}  {value} {}", format!("({layer})").dim()),
+            None => println!("{key:width$}  {}", "(default)".dim()),
+        }
+        println!("{:width$}  {}", "", description.dim());
+    }
+
+    Ok(())
+}
+
+fn handle_get(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let key = args.get_one::<String>("KEY").unwrap();
+
+    match Settings::load(&installation.root).get(key)? {
+        Some(value) => println!("{value}"),
+        None => return Err(Error::Unset(key.clone())),
+    }
+
+    Ok(())
+}
+
+fn handle_set(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let value = args.get_one::<String>("VALUE").unwrap();
+    update(args, installation, |settings, key| settings.set(key, value))
+}
+
+fn handle_unset(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    update(args, installation, Settings::unset)
+}
+
+/// Change a setting in the config file of a single layer
+fn update(
+    args: &ArgMatches,
+    installation: Installation,
+    change: impl FnOnce(&mut Settings, &str) -> Result<(), settings::Error>,
+) -> Result<(), Error> {
+    let key = args.get_one::<String>("KEY").unwrap();
+    let layer = if args.get_flag("user") {
+        Layer::User
+    } else {
+        Layer::System
+    };
+
+    let mut settings = settings::read(layer, &installation.root)?;
+    change(&mut settings, key)?;
+    let path = settings::write(layer, &installation.root, &settings)?;
+
+    println!("{} {key} in {}", "Updated".green(), path.display());
+
+    Ok(())
+}
+
+/// Print the changes from the vendor default at `old` to the file at `new`
+fn print_diff(old: &Path, new: &Path) -> Result<(), Error> {
+    let read = |path: &Path| match fs::read(path) {
+        Ok(content) => Ok(content),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(error) => Err(Error::Read(error)),
+    };
+
+    let Some(diff) = etc::unified_diff(&read(old)?, &read(new)?) else {
+        println!("{}", "  too large to compare".dim());
+        return Ok(());
+    };
+
+    println!("{}", format!("--- {}", old.display()).bold());
+    println!("{}", format!("+++ {}", new.display()).bold());
+    for line in diff.lines() {
+        match line.chars().next() {
+            Some('@') => println!("{}", line.cyan()),
+            Some('-') => println!("{}", line.red()),
+            Some('+') => println!("{}", line.green()),
+            _ => println!("{line}"),
+        }
+    }
+    println!();
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to setup moss client")]
+    SetupClient(#[source] client::Error),
+    #[error("failed to compare configuration files")]
+    Status(#[source] client::Error),
+    #[error("failed to read configuration file")]
+    Read(#[source] std::io::Error),
+    #[error("settings")]
+    Settings(#[from] settings::Error),
+    #[error("{0} isn't set")]
+    Unset(String),
+}
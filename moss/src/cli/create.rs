@@ -0,0 +1,206 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use fs_err as fs;
+use moss::{
+    Installation,
+    client::{self, Client},
+    environment, firmware, installation,
+    registry::transaction,
+    runtime,
+    state::Selection,
+    system_model::{self, SystemModel, template},
+};
+use thiserror::Error;
+use tui::Styled;
+
+use super::sync;
+
+pub fn command() -> clap::Command {
+    Command::command()
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "create",
+    about = "Create a new root from a system-model",
+    long_about = "Create a complete root in a new directory from the repositories & packages of a system-model.kdl
+
+The system-model is installed to the root so it can be synced later on, i.e. for containers, \
+image builders or debug chroots"
+)]
+pub struct Command {
+    /// Directory to create the root in
+    #[arg(value_name = "dir")]
+    dir: PathBuf,
+
+    /// The system-model.kdl describing the root
+    #[arg(value_name = "file", long)]
+    model: PathBuf,
+
+    /// Don't keep the moss state & database in the root
+    ///
+    /// The root can't be managed by moss afterwards, other than recreating it
+    #[arg(long)]
+    stateless: bool,
+
+    /// Do not run triggers when creating the root
+    #[arg(long)]
+    skip_triggers: bool,
+
+    /// Only include the firmware & driver packages supporting this hardware
+    ///
+    /// Either `detect` to probe the running machine, or a file listing one PCI id
+    /// (`8086:a7a0`), modalias or device-tree compatible string per line
+    #[arg(value_name = "profile", long)]
+    firmware: Option<String>,
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let command = Command::from_arg_matches(args).expect("validated by clap");
+
+    let model = system_model::load(&command.model, &installation.variables)?
+        .ok_or_else(|| Error::ModelDoesntExist(command.model.clone()))?;
+
+    if command.dir.join("usr").exists() {
+        return Err(Error::AlreadyExists(command.dir));
+    }
+
+    let profile = match command.firmware.as_deref() {
+        Some("detect") => Some(firmware::Profile::detect(Path::new("/sys"))),
+        Some(path) => Some(firmware::Profile::load(Path::new(path)).map_err(|e| Error::LoadProfile(path.into(), e))?),
+        None => None,
+    };
+
+    populate(
+        &command.dir,
+        &command.model,
+        &model,
+        &installation.variables,
+        installation.cache_dirs(),
+        command.skip_triggers,
+        profile.as_ref(),
+    )?;
+
+    if command.stateless {
+        fs::remove_dir_all(command.dir.join(".moss"))?;
+        fs::remove_file(command.dir.join("usr").join(".stateID"))?;
+        let fixups = command.dir.join("usr").join(".fixups");
+        if fixups.exists() {
+            fs::remove_file(fixups)?;
+        }
+    }
+
+    println!("Created {}", command.dir.display());
+
+    Ok(())
+}
+
+/// Install the packages of `model` (loaded from `model_path`) into a new root at `dir`
+///
+/// A templated `model` is installed as is, rendered with `variables` in the new root
+///
+/// With a firmware `profile`, the hardware specific packages of `model` are trimmed
+/// down to those supporting it & the installed system-model is updated to match
+pub(super) fn populate(
+    dir: &Path,
+    model_path: &Path,
+    model: &SystemModel,
+    variables: &template::Variables,
+    cache_dirs: Vec<PathBuf>,
+    skip_triggers: bool,
+    profile: Option<&firmware::Profile>,
+) -> Result<(), Error> {
+    // Installed first so the new installation picks up its repositories
+    let model_dir = dir.join("etc").join("moss");
+    fs::create_dir_all(&model_dir)?;
+    fs::copy(model_path, model_dir.join("system-model.kdl"))?;
+
+    let target = Installation::open_with_variables(dir, cache_dirs, variables)?;
+    let mut client = Client::new(environment::NAME, target)?.skip_triggers(skip_triggers);
+
+    runtime::block_on(client.refresh_repositories())?;
+
+    let selection;
+    let model = match profile {
+        Some(profile) => {
+            selection = firmware::select(&client.registry, profile, model.clone())?;
+
+            println!(
+                "Selected firmware for {} devices, {} added, {} removed",
+                profile.len(),
+                selection.added.len(),
+                selection.removed.len()
+            );
+            for name in &selection.added {
+                println!("  {} {name}", "+".green());
+            }
+            for name in &selection.removed {
+                println!("  {} {name}", "-".red());
+            }
+
+            fs::write(model_dir.join("system-model.kdl"), selection.model.source())?;
+            &selection.model
+        }
+        None => model,
+    };
+
+    let packages = sync::resolve_with_system_model(&client, model)?;
+
+    println!(
+        "Creating {} with {} packages",
+        dir.display().to_string().bold(),
+        packages.len()
+    );
+
+    runtime::block_on(client.cache_packages(&packages))?;
+
+    let required_by = transaction::required_by(&packages.iter().collect::<Vec<_>>());
+    let selections = packages
+        .iter()
+        .map(|package| {
+            Selection::system_model(
+                package.id.clone(),
+                model.packages.intersection(&package.meta.providers).next().is_some(),
+                required_by.get(&package.id).map(|dependent| &dependent.meta.name),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    client.new_state(&selections, "Create")?;
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("installation")]
+    Installation(#[from] installation::Error),
+
+    #[error("resolve")]
+    Resolve(#[from] sync::Error),
+
+    #[error("io")]
+    Io(#[from] std::io::Error),
+
+    #[error("load system model")]
+    LoadSystemModel(#[from] system_model::LoadError),
+
+    #[error("system model doesn't exist at {0:?}")]
+    ModelDoesntExist(PathBuf),
+
+    #[error("select firmware")]
+    SelectFirmware(#[from] system_model::UpdateError),
+
+    #[error("load firmware profile {0:?}")]
+    LoadProfile(PathBuf, #[source] std::io::Error),
+
+    #[error("{0:?} already contains a root")]
+    AlreadyExists(PathBuf),
+}
@@ -0,0 +1,334 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::BTreeMap;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, Command, arg};
+use fs_err::{self as fs, File};
+use moss::{Installation, db, installation, state};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use stone::payload::layout;
+use stone::write::digest;
+use thiserror::Error;
+use tui::Styled;
+
+use super::Outcome;
+
+pub fn command() -> Command {
+    Command::new("diff-root")
+        .about("Compare two roots file by file")
+        .long_about(
+            "Compare the `/usr` trees of two roots, or a root and a state, listing added, removed & changed files.\n\n\
+             Use `state:<ID>` to refer to a state of the installation by id or tag. Moss managed roots are compared using the \
+             hashes recorded in their layout database, anything else is hashed on disk.\n\n\
+             Exits with status 6 if the trees differ.",
+        )
+        .arg(arg!(<OLD> "root directory or `state:<ID|TAG>` to compare from"))
+        .arg(arg!(<NEW> "root directory or `state:<ID|TAG>` to compare against"))
+        .arg(
+            arg!(--hash "Always hash files on disk instead of trusting layout databases")
+                .action(clap::ArgAction::SetTrue),
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<Outcome, Error> {
+    let rehash = args.get_flag("hash");
+
+    let old = args.get_one::<String>("OLD").unwrap().parse::<Side>()?;
+    let new = args.get_one::<String>("NEW").unwrap().parse::<Side>()?;
+
+    let old_tree = old.load(&installation, rehash)?;
+    let new_tree = new.load(&installation, rehash)?;
+
+    let changes = diff_trees(old_tree, new_tree);
+
+    if changes.is_empty() {
+        println!("No differences between {old} and {new}");
+        return Ok(Outcome::Done);
+    }
+
+    let mut counts = [0; 3];
+
+    for change in &changes {
+        match change {
+            Change::Added(path, _) => {
+                counts[0] += 1;
+                println!("{} /usr/{path}", "+".green());
+            }
+            Change::Removed(path, _) => {
+                counts[1] += 1;
+                println!("{} /usr/{path}", "-".red());
+            }
+            Change::Changed { path, old, new } => {
+                counts[2] += 1;
+                println!("{} /usr/{path} {}", "~".yellow(), describe_change(old, new).dim());
+            }
+        }
+    }
+
+    let [added, removed, changed] = counts;
+    println!();
+    println!("{added} added, {removed} removed, {changed} changed");
+
+    Ok(Outcome::Differs)
+}
+
+/// One side of the comparison
+enum Side {
+    Root(PathBuf),
+    State(state::Reference),
+}
+
+impl std::str::FromStr for Side {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("state:") {
+            Some(reference) => reference
+                .parse::<state::Reference>()
+                .map(Side::State)
+                .map_err(|_| Error::InvalidState(reference.to_owned())),
+            None => Ok(Side::Root(PathBuf::from(value))),
+        }
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Side::Root(path) => write!(f, "{}", path.display()),
+            Side::State(state::Reference::Id(id)) => write!(f, "state #{id}"),
+            Side::State(state::Reference::Tag(tag)) => write!(f, "state {tag}"),
+        }
+    }
+}
+
+impl Side {
+    /// Load the file entries of this side, keyed by their path relative to `/usr`
+    fn load(&self, installation: &Installation, rehash: bool) -> Result<Tree, Error> {
+        match self {
+            Side::State(reference) => {
+                let state_db = open_db(&installation.db_path("state"), db::state::Database::new)?;
+                let layout_db = open_db(&installation.db_path("layout"), db::layout::Database::new)?;
+                let id = state_db.resolve(reference)?;
+                state_tree(&state_db, &layout_db, id)
+            }
+            Side::Root(root) => {
+                if !root.join("usr").is_dir() {
+                    return Err(Error::NotARoot(root.clone()));
+                }
+
+                let state_path = root.join(".moss").join("db").join("state");
+                let layout_path = root.join(".moss").join("db").join("layout");
+
+                match installation::read_state_id(root) {
+                    Some(id) if !rehash && state_path.exists() && layout_path.exists() => {
+                        let state_db = open_db(&state_path, db::state::Database::new)?;
+                        let layout_db = open_db(&layout_path, db::layout::Database::new)?;
+                        state_tree(&state_db, &layout_db, id)
+                    }
+                    _ => disk_tree(&root.join("usr")),
+                }
+            }
+        }
+    }
+}
+
+/// What a path is, and enough to tell whether its contents changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Regular(u128),
+    Symlink(String),
+    Directory,
+    Special,
+}
+
+type Tree = BTreeMap<String, Entry>;
+
+fn open_db<T>(path: &Path, open: impl FnOnce(&str) -> Result<T, db::Error>) -> Result<T, Error> {
+    Ok(open(path.to_str().unwrap_or_default())?)
+}
+
+/// Build the tree of `state` from the recorded layouts of its selections
+fn state_tree(state_db: &db::state::Database, layout_db: &db::layout::Database, id: state::Id) -> Result<Tree, Error> {
+    let state = state_db.get(id)?;
+    let layouts = layout_db.query(state.selections.iter().map(|selection| &selection.package))?;
+
+    Ok(layouts
+        .into_iter()
+        .map(|(_, layout)| {
+            let entry = match &layout.entry {
+                layout::Entry::Regular(hash, _) => Entry::Regular(*hash),
+                layout::Entry::Symlink(source, _) => Entry::Symlink(source.clone()),
+                layout::Entry::Directory(_) => Entry::Directory,
+                layout::Entry::CharacterDevice(_)
+                | layout::Entry::BlockDevice(_)
+                | layout::Entry::Fifo(_)
+                | layout::Entry::Socket(_) => Entry::Special,
+            };
+            (layout.entry.target().trim_start_matches('/').to_owned(), entry)
+        })
+        .collect())
+}
+
+/// Build the tree of `usr` by walking & hashing it on disk
+fn disk_tree(usr: &Path) -> Result<Tree, Error> {
+    let mut paths = vec![];
+    walk(usr, usr, &mut paths)?;
+
+    // Skip state bookkeeping which never comes from a package
+    paths.retain(|(target, _)| !matches!(target.as_str(), ".stateID" | ".fixups"));
+
+    Ok(paths
+        .into_par_iter()
+        .map(|(target, path)| {
+            let file_type = fs::symlink_metadata(&path)?.file_type();
+
+            let entry = if file_type.is_symlink() {
+                Entry::Symlink(fs::read_link(&path)?.to_string_lossy().into_owned())
+            } else if file_type.is_dir() {
+                Entry::Directory
+            } else if file_type.is_char_device()
+                || file_type.is_block_device()
+                || file_type.is_fifo()
+                || file_type.is_socket()
+            {
+                Entry::Special
+            } else {
+                let mut hasher = digest::Hasher::new();
+                let mut writer = digest::Writer::new(io::sink(), &mut hasher);
+                io::copy(&mut File::open(&path)?, &mut writer)?;
+                Entry::Regular(hasher.digest128())
+            };
+
+            Ok((target, entry))
+        })
+        .collect::<io::Result<_>>()?)
+}
+
+/// Collect every path below `dir` without following symlinks
+fn walk(root: &Path, dir: &Path, paths: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let target = path
+            .strip_prefix(root)
+            .expect("entry within root")
+            .to_string_lossy()
+            .into_owned();
+
+        if entry.file_type()?.is_dir() {
+            walk(root, &path, paths)?;
+        }
+
+        paths.push((target, path));
+    }
+
+    Ok(())
+}
+
+/// A difference between the two trees
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    Added(String, Entry),
+    Removed(String, Entry),
+    Changed { path: String, old: Entry, new: Entry },
+}
+
+/// Compare `old` & `new` by path, sorted by path
+fn diff_trees(mut old: Tree, new: Tree) -> Vec<Change> {
+    let mut changes = vec![];
+
+    for (path, new) in new {
+        match old.remove(&path) {
+            None => changes.push(Change::Added(path, new)),
+            Some(old) if old != new => changes.push(Change::Changed { path, old, new }),
+            Some(_) => {}
+        }
+    }
+    changes.extend(old.into_iter().map(|(path, entry)| Change::Removed(path, entry)));
+
+    changes.sort_by(|a, b| change_path(a).cmp(change_path(b)));
+    changes
+}
+
+fn change_path(change: &Change) -> &str {
+    match change {
+        Change::Added(path, _) | Change::Removed(path, _) | Change::Changed { path, .. } => path,
+    }
+}
+
+fn describe_change(old: &Entry, new: &Entry) -> String {
+    match (old, new) {
+        (Entry::Regular(_), Entry::Regular(_)) => "(content)".to_owned(),
+        (Entry::Symlink(old), Entry::Symlink(new)) => format!("({old} -> {new})"),
+        _ => format!("({} -> {})", kind(old), kind(new)),
+    }
+}
+
+fn kind(entry: &Entry) -> &'static str {
+    match entry {
+        Entry::Regular(_) => "file",
+        Entry::Symlink(_) => "symlink",
+        Entry::Directory => "directory",
+        Entry::Special => "special",
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+
+    #[error("db")]
+    Db(#[from] db::Error),
+
+    #[error("invalid state id or tag: {0}")]
+    InvalidState(String),
+
+    #[error("{0:?} has no /usr tree")]
+    NotARoot(PathBuf),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff_trees() {
+        let old = Tree::from([
+            ("bin".to_owned(), Entry::Directory),
+            ("bin/bash".to_owned(), Entry::Regular(1)),
+            ("bin/sh".to_owned(), Entry::Symlink("bash".to_owned())),
+            ("lib/libfoo.so".to_owned(), Entry::Regular(2)),
+        ]);
+        let new = Tree::from([
+            ("bin".to_owned(), Entry::Directory),
+            ("bin/bash".to_owned(), Entry::Regular(3)),
+            ("bin/sh".to_owned(), Entry::Symlink("dash".to_owned())),
+            ("bin/zsh".to_owned(), Entry::Regular(4)),
+        ]);
+
+        assert_eq!(
+            diff_trees(old, new),
+            vec![
+                Change::Changed {
+                    path: "bin/bash".to_owned(),
+                    old: Entry::Regular(1),
+                    new: Entry::Regular(3)
+                },
+                Change::Changed {
+                    path: "bin/sh".to_owned(),
+                    old: Entry::Symlink("bash".to_owned()),
+                    new: Entry::Symlink("dash".to_owned())
+                },
+                Change::Added("bin/zsh".to_owned(), Entry::Regular(4)),
+                Change::Removed("lib/libfoo.so".to_owned(), Entry::Regular(2)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, Command, arg, value_parser};
+use fs_err as fs;
+use moss::{
+    Installation,
+    client::{self, Client},
+    environment, state, system_model,
+};
+use thiserror::Error;
+
+use super::create;
+
+mod oci;
+mod sbom;
+mod sysext;
+pub mod tar;
+
+pub fn command() -> Command {
+    Command::new("export")
+        .about("Export a root as an image")
+        .long_about(
+            "Build a root from the active state, another state, a system-model or a set of packages and export \
+             it as an image.\n\n\
+             The root is assembled in a scratch directory below `.moss/root` and removed once exported.",
+        )
+        .subcommand_required(true)
+        .subcommand(source_args(oci::command()))
+        .subcommand(sbom::command())
+        .subcommand(sysext::command())
+}
+
+/// Arguments selecting what to export, shared by all formats
+fn source_args(command: Command) -> Command {
+    command
+        .arg(
+            arg!(--state <ID> "Export this state (id or tag) instead of the active state")
+                .value_parser(value_parser!(state::Reference))
+                .conflicts_with("model"),
+        )
+        .arg(
+            arg!(--model <FILE> "Export a fresh root built from this system-model.kdl")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(arg!(--"skip-triggers" "Do not run triggers when building the root"))
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("oci", args)) => {
+            let root = build_root(args, installation)?;
+            oci::export(args, &root)
+        }
+        Some(("sbom", args)) => sbom::export(args, installation),
+        Some(("sysext", args)) => sysext::export(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+/// A root assembled for export, removed once dropped
+pub struct Root {
+    pub path: PathBuf,
+    /// Where the root came from, i.e. `state #12`
+    pub origin: String,
+    /// Architecture of the packages in the root
+    pub architecture: String,
+    /// Cache directory of the installation, to stage intermediate output in
+    pub cache: PathBuf,
+}
+
+impl Root {
+    /// Create an empty scratch root within `installation`
+    fn create(installation: &Installation) -> Result<Self, Error> {
+        let path = installation.root_path("export");
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+
+        Ok(Self {
+            path,
+            origin: String::new(),
+            architecture: installation.architecture().to_owned(),
+            cache: installation.cache_path(""),
+        })
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Assemble the root selected by the [`source_args`] of `args`
+fn build_root(args: &ArgMatches, installation: Installation) -> Result<Root, Error> {
+    let skip_triggers = args.get_flag("skip-triggers");
+
+    let mut root = Root::create(&installation)?;
+
+    if let Some(model_path) = args.get_one::<PathBuf>("model") {
+        let model = system_model::load(model_path, &installation.variables)?
+            .ok_or_else(|| Error::ModelDoesntExist(model_path.clone()))?;
+
+        // Release our locks, the new root shares the cache dir
+        let cache_dirs = installation.cache_dirs();
+        let variables = installation.variables.clone();
+        drop(installation);
+
+        create::populate(
+            &root.path,
+            model_path,
+            &model,
+            &variables,
+            cache_dirs,
+            skip_triggers,
+            None,
+        )?;
+        strip_state(&root.path)?;
+
+        root.origin = format!("system-model {}", model_path.display());
+        return Ok(root);
+    }
+
+    let active_state = installation.active_state;
+
+    let client = Client::new(environment::NAME, installation)?;
+    let id = match args.get_one::<state::Reference>("state") {
+        Some(reference) => client.state_db.resolve(reference)?,
+        None => active_state.ok_or(Error::NoActiveState)?,
+    };
+
+    let client = client.ephemeral(&root.path)?.skip_triggers(skip_triggers);
+    let state = client.state_db.get(id)?;
+
+    client.new_state(&state.selections, "Export")?;
+
+    root.origin = format!("state #{id}");
+    Ok(root)
+}
+
+/// Remove moss bookkeeping which doesn't belong in an exported image
+fn strip_state(root: &Path) -> Result<(), Error> {
+    fs::remove_dir_all(root.join(".moss"))?;
+    for bookkeeping in [".stateID", ".fixups"] {
+        let path = root.join("usr").join(bookkeeping);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    Db(#[from] moss::db::Error),
+
+    #[error("create root")]
+    Create(#[from] create::Error),
+
+    #[error("load system model")]
+    LoadSystemModel(#[from] system_model::LoadError),
+
+    #[error("system model doesn't exist at {0:?}")]
+    ModelDoesntExist(PathBuf),
+
+    #[error("transaction")]
+    Transaction(#[from] moss::registry::transaction::Error),
+
+    #[error("no active state to export")]
+    NoActiveState,
+
+    #[error("no package found for {0}")]
+    NoPackage(String),
+
+    #[error("all packages are already part of the active state")]
+    NothingToExport,
+
+    #[error("run {0}")]
+    Mkfs(String, #[source] std::io::Error),
+
+    #[error("{0} exited with status {1:?}")]
+    MkfsStatus(String, Option<i32>),
+
+    #[error("{0:?} already exists")]
+    OutputExists(PathBuf),
+
+    #[error("encode image metadata")]
+    Json(#[from] serde_json::Error),
+
+    #[error("io")]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,285 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Export a root as an [OCI image layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md)
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{SecondsFormat, Utc};
+use clap::{ArgMatches, Command, arg, value_parser};
+use flate2::{Compression, write::GzEncoder};
+use fs_err::{self as fs, File};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tui::{HumanBytes, Styled};
+
+use super::{Error, Root, tar};
+
+const MANIFEST_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+const LAYER_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+pub fn command() -> Command {
+    Command::new("oci")
+        .about("Export as an OCI image")
+        .long_about(
+            "Export the root as a single layer OCI image.\n\n\
+             If OUTPUT ends in `.tar` a `docker save` compatible tarball is written, which can be loaded \
+             with `docker load` or `podman load`. Otherwise an OCI image layout directory is created, \
+             which can be pushed to a registry with i.e. `skopeo copy oci:OUTPUT docker://...`.",
+        )
+        .arg(arg!(<OUTPUT> "image layout directory or `.tar` archive to create").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(-t --tag <REF> "Image reference to tag the image with").default_value("moss:latest"))
+        .arg(
+            arg!(--cmd <ARG> ... "Default command of the image, defaults to /usr/bin/sh if present")
+                .num_args(1..)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Export `root` in the format selected by `args`
+pub fn export(args: &ArgMatches, root: &Root) -> Result<(), Error> {
+    let output = args.get_one::<PathBuf>("OUTPUT").unwrap();
+    let tag = args.get_one::<String>("tag").unwrap();
+    let cmd = match args.get_many::<String>("cmd") {
+        Some(cmd) => cmd.cloned().collect(),
+        None if root.path.join("usr/bin/sh").exists() => vec!["/usr/bin/sh".to_owned()],
+        None => vec![],
+    };
+
+    if output.exists() {
+        return Err(Error::OutputExists(output.clone()));
+    }
+
+    let archive = output.extension().is_some_and(|ext| ext == "tar");
+
+    println!("Exporting {} to {}", root.origin.as_str().bold(), output.display());
+
+    let manifest = if archive {
+        // The layout is only an intermediate of the tarball, stage it where it can't clobber anything
+        fs::create_dir_all(&root.cache)?;
+        let staging = tempfile::Builder::new().prefix("oci-").tempdir_in(&root.cache)?;
+
+        let manifest = write_layout(root, staging.path(), tag, &cmd)?;
+        write_archive(staging.path(), output, tag, &manifest).inspect_err(|_| {
+            let _ = fs::remove_file(output);
+        })?;
+        manifest
+    } else {
+        write_layout(root, output, tag, &cmd).inspect_err(|_| {
+            let _ = fs::remove_dir_all(output);
+        })?
+    };
+
+    println!(
+        "Exported {} ({}, {})",
+        tag.as_str().bold(),
+        manifest.layer.digest,
+        HumanBytes(manifest.layer.size)
+    );
+
+    Ok(())
+}
+
+/// Content addressed reference to a blob
+struct Descriptor {
+    media_type: &'static str,
+    digest: String,
+    size: u64,
+}
+
+impl Descriptor {
+    fn to_json(&self) -> Value {
+        json!({
+            "mediaType": self.media_type,
+            "digest": self.digest,
+            "size": self.size,
+        })
+    }
+
+    fn blob_path(&self) -> String {
+        format!("blobs/{}", self.digest.replace(':', "/"))
+    }
+}
+
+struct Manifest {
+    config: Descriptor,
+    layer: Descriptor,
+    manifest: Descriptor,
+}
+
+fn write_layout(root: &Root, layout: &Path, tag: &str, cmd: &[String]) -> Result<Manifest, Error> {
+    let blobs = layout.join("blobs").join("sha256");
+    fs::create_dir_all(&blobs)?;
+
+    let (layer, diff_id) = write_layer(&root.path, &blobs)?;
+
+    let created = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut image_config = json!({ "Env": ["PATH=/usr/local/bin:/usr/bin:/usr/sbin"] });
+    if !cmd.is_empty() {
+        image_config["Cmd"] = json!(cmd);
+    }
+
+    let config = write_blob(
+        &blobs,
+        CONFIG_TYPE,
+        &serde_json::to_vec(&json!({
+            "created": created,
+            "architecture": architecture(&root.architecture),
+            "os": "linux",
+            "config": image_config,
+            "rootfs": { "type": "layers", "diff_ids": [diff_id] },
+            "history": [{ "created": created, "created_by": "moss export oci", "comment": root.origin }],
+        }))?,
+    )?;
+
+    let manifest = write_blob(
+        &blobs,
+        MANIFEST_TYPE,
+        &serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_TYPE,
+            "config": config.to_json(),
+            "layers": [layer.to_json()],
+            "annotations": { "org.opencontainers.image.created": created },
+        }))?,
+    )?;
+
+    let mut reference = manifest.to_json();
+    reference["annotations"] = json!({
+        "io.containerd.image.name": tag,
+        "org.opencontainers.image.ref.name": tag.rsplit_once(':').map_or(tag, |(_, version)| version),
+    });
+
+    fs::write(
+        layout.join("index.json"),
+        serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [reference],
+        }))?,
+    )?;
+    fs::write(layout.join("oci-layout"), br#"{"imageLayoutVersion":"1.0.0"}"#)?;
+
+    Ok(Manifest {
+        config,
+        layer,
+        manifest,
+    })
+}
+
+/// Write the root as a gzipped tar layer, returning its descriptor and the digest of the uncompressed tar
+fn write_layer(root: &Path, blobs: &Path) -> Result<(Descriptor, String), Error> {
+    let partial = blobs.join("layer.partial");
+
+    let file = Hashing::new(File::create(&partial)?);
+    let mut builder = tar::Builder::new(Hashing::new(GzEncoder::new(file, Compression::default())));
+    builder.append_tree(root, &[".moss"])?;
+
+    let uncompressed = builder.finish()?;
+    let diff_id = uncompressed.digest();
+    let (file, digest, size) = uncompressed.inner.finish()?.finish();
+    file.sync_all()?;
+
+    fs::rename(&partial, blobs.join(&digest[7..]))?;
+
+    Ok((
+        Descriptor {
+            media_type: LAYER_TYPE,
+            digest,
+            size,
+        },
+        diff_id,
+    ))
+}
+
+fn write_blob(blobs: &Path, media_type: &'static str, data: &[u8]) -> Result<Descriptor, Error> {
+    let digest = format!("sha256:{}", hex::encode(Sha256::digest(data)));
+    fs::write(blobs.join(&digest[7..]), data)?;
+
+    Ok(Descriptor {
+        media_type,
+        digest,
+        size: data.len() as u64,
+    })
+}
+
+/// Pack the image layout into a single tarball, adding the `manifest.json` used by `docker load`
+fn write_archive(layout: &Path, output: &Path, tag: &str, manifest: &Manifest) -> Result<(), Error> {
+    let docker_manifest = serde_json::to_vec(&json!([{
+        "Config": manifest.config.blob_path(),
+        "RepoTags": [tag],
+        "Layers": [manifest.layer.blob_path()],
+    }]))?;
+
+    let mut builder = tar::Builder::new(io::BufWriter::new(File::create(output)?));
+
+    builder.append_file("oci-layout", &layout.join("oci-layout"))?;
+    builder.append_file("index.json", &layout.join("index.json"))?;
+    builder.append_data("manifest.json", &docker_manifest)?;
+    builder.append_dir("blobs")?;
+    builder.append_dir("blobs/sha256")?;
+    for descriptor in [&manifest.config, &manifest.layer, &manifest.manifest] {
+        let path = descriptor.blob_path();
+        builder.append_file(&path, &layout.join(&path))?;
+    }
+
+    builder.finish()?.flush()?;
+
+    Ok(())
+}
+
+/// The OCI (GOARCH) name of the package architecture `arch`
+fn architecture(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "powerpc64" => "ppc64le",
+        arch => arch,
+    }
+}
+
+/// Digests & counts everything written through it
+struct Hashing<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W> Hashing<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn digest(&self) -> String {
+        format!("sha256:{}", hex::encode(self.hasher.clone().finalize()))
+    }
+
+    fn finish(self) -> (W, String, u64) {
+        let digest = self.digest();
+        (self.inner, digest, self.size)
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
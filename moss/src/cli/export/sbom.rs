@@ -0,0 +1,253 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Export a state as a Software Bill of Materials, in the
+//! [SPDX](https://spdx.github.io/spdx-spec/v2.3/) or [CycloneDX](https://cyclonedx.org/docs/1.5/json/) JSON format
+
+use std::path::PathBuf;
+
+use chrono::SecondsFormat;
+use clap::{ArgMatches, Command, ValueEnum, arg, value_parser};
+use fs_err as fs;
+use moss::{Installation, Package, client::Client, environment, registry::transaction, state};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use super::Error;
+
+/// Format of the SBOM
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Spdx,
+    Cyclonedx,
+}
+
+pub fn command() -> Command {
+    Command::new("sbom")
+        .about("Export a state as a Software Bill of Materials")
+        .long_about(
+            "Export a state as a Software Bill of Materials in the SPDX 2.3 or CycloneDX 1.5 JSON format.\n\n\
+             Every package of the state is listed with its version, licenses, stone hash & providers, along with \
+             the dependencies between them.",
+        )
+        .arg(
+            arg!(--state <ID> "Export this state (id or tag) instead of the active state")
+                .value_parser(value_parser!(state::Reference)),
+        )
+        .arg(
+            arg!(--format <FORMAT> "Format of the SBOM")
+                .value_parser(value_parser!(Format))
+                .default_value("spdx"),
+        )
+        .arg(
+            arg!(-o --output <FILE> "Write the SBOM to this file instead of stdout")
+                .value_parser(value_parser!(PathBuf)),
+        )
+}
+
+pub fn export(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let format = *args.get_one::<Format>("format").unwrap();
+    let active_state = installation.active_state;
+
+    let client = Client::new(environment::NAME, installation)?;
+    let id = match args.get_one::<state::Reference>("state") {
+        Some(reference) => client.state_db.resolve(reference)?,
+        None => active_state.ok_or(Error::NoActiveState)?,
+    };
+    let state = client.state_db.get(id)?;
+
+    let mut packages = client.resolve_packages(state.selections.iter().map(|selection| &selection.package))?;
+    packages.sort_by(|a, b| a.meta.name.cmp(&b.meta.name));
+
+    let sbom = match format {
+        Format::Spdx => spdx(&state, &packages),
+        Format::Cyclonedx => cyclonedx(&state, &packages),
+    };
+    let contents = serde_json::to_string_pretty(&sbom)?;
+
+    match args.get_one::<PathBuf>("output") {
+        Some(output) => {
+            fs::write(output, format!("{contents}\n"))?;
+            println!("Exported {} packages of state #{id} to {output:?}", packages.len());
+        }
+        None => println!("{contents}"),
+    }
+
+    Ok(())
+}
+
+fn spdx(state: &state::State, packages: &[Package]) -> Value {
+    let spdx_id = |package: &Package| {
+        let name = package
+            .meta
+            .name
+            .to_string()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+            .collect::<String>();
+        format!("SPDXRef-Package-{name}")
+    };
+
+    let documented = packages.iter().map(|package| {
+        let meta = &package.meta;
+        let providers = meta.providers.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let mut document = json!({
+            "name": meta.name.to_string(),
+            "SPDXID": spdx_id(package),
+            "versionInfo": version(package),
+            "downloadLocation": meta.uri.as_deref().unwrap_or("NOASSERTION"),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": license_expression(package).unwrap_or_else(|| "NOASSERTION".to_owned()),
+            "copyrightText": "NOASSERTION",
+            "summary": meta.summary,
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl(package),
+            }],
+            "comment": format!("Provides: {}", providers.join(", ")),
+        });
+        if !meta.homepage.is_empty() {
+            document["homepage"] = json!(meta.homepage);
+        }
+        if let Some(hash) = &meta.hash {
+            document["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": hash }]);
+        }
+        document
+    });
+
+    let describes = packages.iter().map(|package| {
+        json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": spdx_id(package),
+        })
+    });
+    let depends_on = dependencies(packages).into_iter().flat_map(|(package, dependencies)| {
+        dependencies.into_iter().map(move |dependency| {
+            json!({
+                "spdxElementId": spdx_id(package),
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": spdx_id(dependency),
+            })
+        })
+    });
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("moss-state-{}", state.id),
+        "documentNamespace": format!("https://spdx.org/spdxdocs/moss-state-{}-{}", state.id, digest(packages)),
+        "creationInfo": {
+            "created": state.created.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "creators": [format!("Tool: {}-{}", environment::NAME, env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": documented.collect::<Vec<_>>(),
+        "relationships": describes.chain(depends_on).collect::<Vec<_>>(),
+    })
+}
+
+fn cyclonedx(state: &state::State, packages: &[Package]) -> Value {
+    let components = packages.iter().map(|package| {
+        let meta = &package.meta;
+        let mut component = json!({
+            "type": "library",
+            "bom-ref": purl(package),
+            "name": meta.name.to_string(),
+            "version": version(package),
+            "description": meta.summary,
+            "purl": purl(package),
+            "properties": meta
+                .providers
+                .iter()
+                .map(|provider| json!({ "name": "moss:provides", "value": provider.to_string() }))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(expression) = license_expression(package) {
+            component["licenses"] = json!([{ "expression": expression }]);
+        }
+        if let Some(hash) = &meta.hash {
+            component["hashes"] = json!([{ "alg": "SHA-256", "content": hash }]);
+        }
+        component
+    });
+
+    let dependencies = dependencies(packages);
+    let dependencies = packages.iter().map(|package| {
+        json!({
+            "ref": purl(package),
+            "dependsOn": dependencies
+                .iter()
+                .find(|(dependent, _)| dependent.id == package.id)
+                .map(|(_, dependencies)| dependencies.iter().map(|dependency| purl(dependency)).collect::<Vec<_>>())
+                .unwrap_or_default(),
+        })
+    });
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": state.created.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": environment::NAME,
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": {
+                "type": "operating-system",
+                "bom-ref": format!("moss-state-{}", state.id),
+                "name": format!("moss-state-{}", state.id),
+            },
+        },
+        "components": components.collect::<Vec<_>>(),
+        "dependencies": dependencies.collect::<Vec<_>>(),
+    })
+}
+
+/// Each package with the packages of the state it depends on
+fn dependencies(packages: &[Package]) -> Vec<(&Package, Vec<&Package>)> {
+    let depends_on = transaction::depends_on(&packages.iter().collect::<Vec<_>>());
+
+    packages
+        .iter()
+        .filter_map(|package| {
+            let ids = depends_on.get(&package.id)?;
+            Some((package, packages.iter().filter(|p| ids.contains(&p.id)).collect()))
+        })
+        .collect()
+}
+
+fn version(package: &Package) -> String {
+    format!("{}-{}", package.meta.version_identifier, package.meta.source_release)
+}
+
+/// There's no purl type for stones, so the generic type is used
+fn purl(package: &Package) -> String {
+    format!(
+        "pkg:generic/{}@{}?arch={}",
+        package.meta.name,
+        version(package),
+        package.meta.architecture
+    )
+}
+
+fn license_expression(package: &Package) -> Option<String> {
+    let licenses = &package.meta.licenses;
+    (!licenses.is_empty()).then(|| licenses.join(" AND "))
+}
+
+/// Identifies the set of packages, keeping the namespace of each document unique
+fn digest(packages: &[Package]) -> String {
+    let mut hasher = Sha256::new();
+    for package in packages {
+        hasher.update(&package.id);
+    }
+    hex::encode(&hasher.finalize()[..8])
+}
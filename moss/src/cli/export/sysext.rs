@@ -0,0 +1,241 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Export packages as a [systemd-sysext](https://www.freedesktop.org/software/systemd/man/latest/systemd-sysext.html)
+//! extension image
+
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
+
+use clap::{ArgMatches, Command, ValueEnum, arg, value_parser};
+use fs_err as fs;
+use moss::{
+    Installation, Provider, client::Client, environment, package::Flags, registry::transaction, runtime,
+    state::Selection,
+};
+use tui::Styled;
+
+use super::{Error, Root};
+
+/// Filesystem of the extension image
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Erofs,
+    Squashfs,
+}
+
+pub fn command() -> Command {
+    Command::new("sysext")
+        .about("Export packages as a systemd-sysext image")
+        .long_about(
+            "Export packages as a systemd-sysext extension image, which is merged over `/usr` of an immutable host \
+             with `systemd-sysext merge`.\n\n\
+             Dependencies already present in the active state are left out, the image only contains what the \
+             host is missing. Requires `mkfs.erofs` or `mksquashfs`.",
+        )
+        .arg(arg!(<PACKAGE> ... "packages to include in the extension"))
+        .arg(arg!(--name <NAME> "Name of the extension, defaults to the first package"))
+        .arg(arg!(-o --output <FILE> "Image to create, defaults to <NAME>.raw").value_parser(value_parser!(PathBuf)))
+        .arg(
+            arg!(--format <FORMAT> "Filesystem of the image")
+                .value_parser(value_parser!(Format))
+                .default_value("erofs"),
+        )
+}
+
+pub fn export(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("PACKAGE")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let name = args.get_one::<String>("name").unwrap_or(names[0]);
+    let output = args
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from(format!("{name}.raw")));
+    let format = *args.get_one::<Format>("format").unwrap();
+
+    if output.exists() {
+        return Err(Error::OutputExists(output));
+    }
+
+    let host_release = fs::read_to_string(installation.root.join("usr/lib/os-release")).unwrap_or_default();
+    let active_state = installation.active_state;
+    let mut root = Root::create(&installation)?;
+    root.origin = format!("extension {name}");
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let base = match active_state {
+        Some(id) => client
+            .state_db
+            .get(id)?
+            .selections
+            .into_iter()
+            .map(|selection| selection.package)
+            .collect(),
+        None => vec![],
+    };
+
+    let input = names
+        .iter()
+        .map(|name| {
+            let provider = Provider::from_name(name).map_err(|_| Error::NoPackage((*name).clone()))?;
+            client
+                .registry
+                .by_provider(&provider, Flags::new().with_available())
+                .next()
+                .map(|package| package.id)
+                .ok_or_else(|| Error::NoPackage((*name).clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut tx = client.registry.transaction(transaction::Lookup::PreferInstalled)?;
+    tx.add(input.clone())?;
+    let packages = client
+        .resolve_packages(tx.finalize())?
+        .into_iter()
+        .filter(|package| !base.contains(&package.id))
+        .collect::<Vec<_>>();
+
+    if packages.is_empty() {
+        return Err(Error::NothingToExport);
+    }
+
+    println!(
+        "Exporting {} with {} packages",
+        root.origin.as_str().bold(),
+        packages.len()
+    );
+
+    runtime::block_on(client.cache_packages(&packages))?;
+
+    let required_by = transaction::required_by(&packages.iter().collect::<Vec<_>>());
+    let selections = packages
+        .iter()
+        .map(|package| {
+            if input.contains(&package.id) {
+                Selection::requested(package.id.clone())
+            } else {
+                Selection::dependency(
+                    package.id.clone(),
+                    required_by.get(&package.id).map(|dependent| &dependent.meta.name),
+                )
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Triggers would run against the extension alone, the host runs them once merged
+    let client = client.ephemeral(&root.path)?.skip_triggers(true);
+    client.new_state(&selections, "Export")?;
+    drop(client);
+
+    prepare(&root.path, name, &host_release)?;
+    make_image(&root.path, &output, format)?;
+
+    println!("Exported {} to {}", name.as_str().bold(), output.display());
+
+    Ok(())
+}
+
+/// Reduce the root to `/usr` and add the extension-release file
+fn prepare(root: &Path, name: &str, host_release: &str) -> Result<(), Error> {
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_name() == "usr" {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    // Recorded by the blit, but an extension must not override the host's
+    let lib = root.join("usr").join("lib");
+    for file in ["os-release", "system-model.kdl"] {
+        let path = lib.join(file);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let release_dir = lib.join("extension-release.d");
+    fs::create_dir_all(&release_dir)?;
+    fs::write(
+        release_dir.join(format!("extension-release.{name}")),
+        extension_release(host_release),
+    )?;
+
+    Ok(())
+}
+
+/// Ties the extension to the host OS (& version) it was built against
+fn extension_release(host_release: &str) -> String {
+    let field = |key: &str| {
+        host_release.lines().find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            (name.trim() == key).then(|| value.trim().trim_matches(['"', '\'']))
+        })
+    };
+
+    let mut release = format!("ID={}\n", field("ID").unwrap_or("_any"));
+    if let Some(version) = field("VERSION_ID") {
+        release.push_str(&format!("VERSION_ID={version}\n"));
+    }
+    release.push_str(&format!("ARCHITECTURE={}\n", architecture()));
+
+    release
+}
+
+/// The systemd name of the host architecture
+fn architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "x86-64",
+        "aarch64" => "arm64",
+        "x86" => "x86",
+        "powerpc64" => "ppc64-le",
+        arch => arch,
+    }
+}
+
+fn make_image(root: &Path, output: &Path, format: Format) -> Result<(), Error> {
+    let mut command = match format {
+        Format::Erofs => {
+            let mut command = process::Command::new("mkfs.erofs");
+            command.arg("--all-root").arg(output).arg(root);
+            command
+        }
+        Format::Squashfs => {
+            let mut command = process::Command::new("mksquashfs");
+            command.arg(root).arg(output).args(["-all-root", "-noappend", "-quiet"]);
+            command
+        }
+    };
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command.status().map_err(|error| Error::Mkfs(program.clone(), error))?;
+    if !status.success() {
+        return Err(Error::MkfsStatus(program, status.code()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extension_release() {
+        let release = extension_release("NAME=\"AerynOS\"\nID=\"aerynos\"\nVERSION_ID=2025.10\n");
+        assert!(release.starts_with("ID=aerynos\nVERSION_ID=2025.10\nARCHITECTURE="));
+
+        assert!(extension_release("").starts_with("ID=_any\nARCHITECTURE="));
+    }
+}
@@ -0,0 +1,466 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Minimal POSIX (pax) tar writer for exported images
+//!
+//! Names are written byte for byte, those which aren't UTF-8 are flagged with
+//! the pax `hdrcharset=BINARY` record so readers don't attempt to decode them.
+
+use std::{
+    ffi::OsStr,
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Component, Path, PathBuf},
+};
+
+use fs_err::{self as fs, File};
+
+const BLOCK: usize = 512;
+/// Largest size the 11 octal digits of a ustar header can hold
+const MAX_SIZE: u64 = 0o77_777_777_777;
+
+/// Writes a tar archive to `W`
+pub struct Builder<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Builder<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Append the tree at `dir` with paths relative to it, skipping top level `exclude` names
+    ///
+    /// Entries are sorted so the archive is reproducible for identical trees.
+    pub fn append_tree(&mut self, dir: &Path, exclude: &[&str]) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        for name in entries {
+            if exclude.iter().any(|exclude| name == *exclude) {
+                continue;
+            }
+            self.append_recursive(dir, Path::new(&name))?;
+        }
+
+        Ok(())
+    }
+
+    fn append_recursive(&mut self, root: &Path, relative: &Path) -> io::Result<()> {
+        let path = root.join(relative);
+        let metadata = fs::symlink_metadata(&path)?;
+        let file_type = metadata.file_type();
+
+        let mut header = Header {
+            path: relative.as_os_str().as_bytes().to_vec(),
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mtime: metadata.mtime().max(0) as u64,
+            ..Default::default()
+        };
+
+        if file_type.is_dir() {
+            header.kind = Kind::Directory;
+            header.path.push(b'/');
+            self.append_header(&header)?;
+
+            let mut children = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+
+            for child in children {
+                self.append_recursive(root, &relative.join(child))?;
+            }
+        } else if file_type.is_symlink() {
+            header.kind = Kind::Symlink;
+            header.link = fs::read_link(&path)?.as_os_str().as_bytes().to_vec();
+            self.append_header(&header)?;
+        } else if file_type.is_char_device() || file_type.is_block_device() {
+            header.kind = if file_type.is_char_device() {
+                Kind::CharDevice
+            } else {
+                Kind::BlockDevice
+            };
+            header.device = (major(metadata.rdev()), minor(metadata.rdev()));
+            self.append_header(&header)?;
+        } else if file_type.is_fifo() {
+            header.kind = Kind::Fifo;
+            self.append_header(&header)?;
+        } else if file_type.is_file() {
+            header.size = metadata.len();
+            self.append_header(&header)?;
+            self.append_content(File::open(&path)?, metadata.len())?;
+        }
+        // Sockets can't be archived
+
+        Ok(())
+    }
+
+    /// Append a regular file with `data` as its contents
+    pub fn append_data(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        self.append_header(&Header {
+            path: path.as_bytes().to_vec(),
+            mode: 0o644,
+            size: data.len() as u64,
+            ..Default::default()
+        })?;
+        self.append_content(data, data.len() as u64)
+    }
+
+    /// Append a regular file read from `path` on disk
+    pub fn append_file(&mut self, path: &str, disk_path: &Path) -> io::Result<()> {
+        let size = fs::metadata(disk_path)?.len();
+        self.append_header(&Header {
+            path: path.as_bytes().to_vec(),
+            mode: 0o644,
+            size,
+            ..Default::default()
+        })?;
+        self.append_content(File::open(disk_path)?, size)
+    }
+
+    /// Append a directory entry
+    pub fn append_dir(&mut self, path: &str) -> io::Result<()> {
+        self.append_header(&Header {
+            path: format!("{}/", path.trim_end_matches('/')).into_bytes(),
+            mode: 0o755,
+            kind: Kind::Directory,
+            ..Default::default()
+        })
+    }
+
+    /// Write the end of archive marker, returning the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; BLOCK * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn append_header(&mut self, header: &Header) -> io::Result<()> {
+        // Names which don't fit the ustar fields are stored in a preceding pax header
+        let mut records = vec![];
+        if header.path.len() > 100 || !header.path.is_ascii() {
+            records.push(pax_record("path", &header.path));
+        }
+        if header.link.len() > 100 || !header.link.is_ascii() {
+            records.push(pax_record("linkpath", &header.link));
+        }
+        // Names are kept byte for byte, so those which aren't UTF-8 must be declared as such
+        if std::str::from_utf8(&header.path).is_err() || std::str::from_utf8(&header.link).is_err() {
+            records.insert(0, pax_record("hdrcharset", b"BINARY"));
+        }
+        if header.size > MAX_SIZE {
+            records.push(pax_record("size", header.size.to_string().as_bytes()));
+        }
+
+        if !records.is_empty() {
+            let data = records.concat();
+            let pax = Header {
+                path: b"././@PaxHeader".to_vec(),
+                mode: 0o644,
+                size: data.len() as u64,
+                kind: Kind::Pax,
+                ..Default::default()
+            };
+            self.inner.write_all(&pax.encode())?;
+            self.append_content(data.as_slice(), data.len() as u64)?;
+        }
+
+        self.inner.write_all(&header.encode())
+    }
+
+    fn append_content(&mut self, reader: impl Read, size: u64) -> io::Result<()> {
+        let copied = io::copy(&mut reader.take(size), &mut self.inner)?;
+        if copied != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file changed while archiving",
+            ));
+        }
+
+        let padding = (BLOCK - (size as usize % BLOCK)) % BLOCK;
+        self.inner.write_all(&[0; BLOCK][..padding])
+    }
+}
+
+/// Unpack the regular files & directories of the archive read from `reader` into `dir`
+///
+/// Meant for archives written by [`Builder`], so other kinds of entries are rejected,
+/// as are paths escaping `dir`.
+pub fn unpack(mut reader: impl Read, dir: &Path) -> io::Result<()> {
+    let mut pax_path = None;
+
+    loop {
+        let mut block = [0; BLOCK];
+        reader.read_exact(&mut block)?;
+
+        // End of archive marker
+        if block.iter().all(|&byte| byte == 0) {
+            return Ok(());
+        }
+
+        let size = get_octal(&block[124..136])?;
+        let padding = (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64;
+
+        if block[156] == Kind::Pax.flag() {
+            let mut records = vec![];
+            (&mut reader).take(size).read_to_end(&mut records)?;
+            pax_path = pax_value(&records, "path").map(<[u8]>::to_vec);
+            io::copy(&mut (&mut reader).take(padding), &mut io::sink())?;
+            continue;
+        }
+
+        let name = pax_path.take().unwrap_or_else(|| get_bytes(&block[0..100]).to_vec());
+        let name = OsStr::from_bytes(&name);
+        let path = dir.join(relative_path(name)?);
+
+        match block[156] {
+            b'5' => fs::create_dir_all(&path)?,
+            b'0' | 0 => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let copied = io::copy(&mut (&mut reader).take(size), &mut File::create(&path)?)?;
+                if copied != size {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated archive"));
+                }
+            }
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported entry {name:?} of kind {:?}", kind as char),
+                ));
+            }
+        }
+
+        io::copy(&mut (&mut reader).take(padding), &mut io::sink())?;
+    }
+}
+
+/// `name` as a path relative to the archive, rejecting absolute paths & `..`
+fn relative_path(name: &OsStr) -> io::Result<PathBuf> {
+    let path = Path::new(name);
+
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        Ok(path.to_owned())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("entry {name:?} escapes the archive"),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum Kind {
+    #[default]
+    Regular,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    Fifo,
+    Pax,
+}
+
+impl Kind {
+    fn flag(self) -> u8 {
+        match self {
+            Kind::Regular => b'0',
+            Kind::Symlink => b'2',
+            Kind::CharDevice => b'3',
+            Kind::BlockDevice => b'4',
+            Kind::Directory => b'5',
+            Kind::Fifo => b'6',
+            Kind::Pax => b'x',
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Header {
+    path: Vec<u8>,
+    link: Vec<u8>,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: u64,
+    kind: Kind,
+    device: (u64, u64),
+}
+
+impl Header {
+    fn encode(&self) -> [u8; BLOCK] {
+        let mut block = [0; BLOCK];
+
+        // Overlong values are truncated here, their full value is in the pax header
+        put_bytes(&mut block[0..100], &self.path);
+        put_octal(&mut block[100..108], self.mode.into());
+        put_octal(&mut block[108..116], self.uid.into());
+        put_octal(&mut block[116..124], self.gid.into());
+        put_octal(&mut block[124..136], self.size.min(MAX_SIZE));
+        put_octal(&mut block[136..148], self.mtime);
+        block[156] = self.kind.flag();
+        put_bytes(&mut block[157..257], &self.link);
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        put_octal(&mut block[329..337], self.device.0);
+        put_octal(&mut block[337..345], self.device.1);
+
+        // Checksum is computed with its own field filled with spaces
+        block[148..156].fill(b' ');
+        let checksum = block.iter().map(|&byte| u64::from(byte)).sum();
+        put_octal(&mut block[148..155], checksum);
+
+        block
+    }
+}
+
+/// NUL terminated bytes
+fn get_bytes(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+/// Octal, NUL or space terminated
+fn get_octal(field: &[u8]) -> io::Result<u64> {
+    let digits = String::from_utf8_lossy(get_bytes(field));
+    u64::from_str_radix(digits.trim_matches([' ', '\0']), 8)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid octal field {digits:?}")))
+}
+
+fn put_bytes(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Zero padded octal, NUL terminated
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    put_bytes(field, &digits.as_bytes()[digits.len() - (field.len() - 1)..]);
+}
+
+/// A `<length> <key>=<value>\n` record, where length includes itself
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let base = key.len() + value.len() + 3;
+    let mut len = base + base.to_string().len();
+    if len.to_string().len() != base.to_string().len() {
+        len += 1;
+    }
+    [format!("{len} {key}=").as_bytes(), value, b"\n"].concat()
+}
+
+/// Value of `key` in the `<length> <key>=<value>\n` records
+fn pax_value<'a>(mut records: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    // Values may hold newlines, so records are split by their length
+    while !records.is_empty() {
+        let space = records.iter().position(|&byte| byte == b' ')?;
+        let len = std::str::from_utf8(&records[..space]).ok()?.parse::<usize>().ok()?;
+        let record = records.get(space + 1..len)?.strip_suffix(b"\n")?;
+        let equals = record.iter().position(|&byte| byte == b'=')?;
+
+        if &record[..equals] == key.as_bytes() {
+            return Some(&record[equals + 1..]);
+        }
+        records = &records[len..];
+    }
+
+    None
+}
+
+fn major(dev: u64) -> u64 {
+    ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0000_0fff)
+}
+
+fn minor(dev: u64) -> u64 {
+    ((dev >> 12) & 0xffff_ff00) | (dev & 0x0000_00ff)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!(pax_record("path", b"a"), b"9 path=a\n");
+        let record = pax_record("path", "a".repeat(90).as_bytes());
+        assert_eq!(
+            record.len().to_string().as_bytes(),
+            record.split(|&byte| byte == b' ').next().unwrap()
+        );
+
+        let records = [pax_record("hdrcharset", b"BINARY"), pax_record("path", b"a\nb\xff")].concat();
+        assert_eq!(pax_value(&records, "path"), Some(&b"a\nb\xff"[..]));
+        assert_eq!(pax_value(&records, "linkpath"), None);
+    }
+
+    #[test]
+    fn test_unpack() {
+        let long = format!("stones/{}.stone", "a".repeat(120));
+
+        let mut builder = Builder::new(vec![]);
+        builder.append_data("system-model.kdl", b"packages {}").unwrap();
+        builder.append_dir("stones").unwrap();
+        builder.append_data(&long, &[1; 700]).unwrap();
+        let archive = builder.finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        unpack(archive.as_slice(), dir.path()).unwrap();
+
+        assert_eq!(fs::read(dir.path().join("system-model.kdl")).unwrap(), b"packages {}");
+        assert_eq!(fs::read(dir.path().join(&long)).unwrap(), [1; 700]);
+
+        let mut builder = Builder::new(vec![]);
+        builder.append_data("../escape", b"").unwrap();
+        assert!(unpack(builder.finish().unwrap().as_slice(), dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_non_utf8_names() {
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+
+        let source = tempfile::tempdir().unwrap();
+        fs::write(source.path().join(name), b"latin-1").unwrap();
+
+        let mut builder = Builder::new(vec![]);
+        builder.append_tree(source.path(), &[]).unwrap();
+        let archive = builder.finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        unpack(archive.as_slice(), dir.path()).unwrap();
+
+        assert_eq!(fs::read(dir.path().join(name)).unwrap(), b"latin-1");
+    }
+
+    #[test]
+    fn test_header_checksum() {
+        let block = Header {
+            path: b"usr/".to_vec(),
+            mode: 0o755,
+            kind: Kind::Directory,
+            ..Default::default()
+        }
+        .encode();
+
+        let stored = u64::from_str_radix(std::str::from_utf8(&block[148..154]).unwrap(), 8).unwrap();
+        let expected = block
+            .iter()
+            .enumerate()
+            .map(|(i, &byte)| if (148..156).contains(&i) { 32 } else { u64::from(byte) })
+            .sum::<u64>();
+        assert_eq!(stored, expected);
+        assert_eq!(&block[257..263], b"ustar\0");
+    }
+}
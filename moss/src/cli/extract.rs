@@ -3,23 +3,33 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
-    io::{Read, Seek, SeekFrom, copy},
+    io::{self, Read, Seek, SeekFrom, Write, copy},
+    ops::Range,
     os::unix::fs::symlink,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-use clap::{ArgMatches, Command, arg};
+use clap::{ArgMatches, Command, arg, value_parser};
 use fs_err::{self as fs, File};
 use moss::package::{self, MissingMetaFieldError};
 use stone::{payload::layout, read::PayloadKind};
 use thiserror::{self, Error};
 use tui::{ProgressBar, ProgressStyle};
+use xxhash_rust::xxh3::xxh3_128;
 
 pub fn command() -> Command {
     Command::new("extract")
         .about("Extract a `.stone` content to disk")
         .long_about("For all valid content-bearing archives, extract to disk")
         .arg(arg!(<PATH> ... "files to inspect").value_parser(clap::value_parser!(PathBuf)))
+        .arg(
+            arg!(--cat <FILE> "Write a single file from the stone to stdout after verifying its hash")
+                .long_help(
+                    "Write a single file from the stone to stdout after verifying its hash. \
+                     FILE is the installed path of the file, such as /usr/share/licenses/zlib/Zlib",
+                )
+                .value_parser(value_parser!(PathBuf)),
+        )
 }
 
 /// Handle the `extract` command
@@ -31,6 +41,13 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
         .cloned()
         .collect::<Vec<_>>();
 
+    if let Some(file) = args.get_one::<PathBuf>("cat") {
+        let [path] = paths.as_slice() else {
+            return Err(Error::CatMultiple);
+        };
+        return cat(path, file);
+    }
+
     // Begin unpack
     fs::create_dir_all(".stoneStore")?;
 
@@ -63,11 +80,12 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
                 .truncate(true)
                 .open(".stoneContent")?;
 
-            let progress = ProgressBar::new(content.header.plain_size).with_style(
-                ProgressStyle::with_template("|{bar:20.cyan/bue}| {percent}%")
-                    .unwrap()
-                    .progress_chars("■≡=- "),
-            );
+            let progress = ProgressBar::with_draw_target(Some(content.header.plain_size), tui::progress_target())
+                .with_style(
+                    ProgressStyle::with_template("|{bar:20.cyan/bue}| {percent}%")
+                        .unwrap()
+                        .progress_chars("■≡=- "),
+                );
             reader.unpack_content(content, &mut progress.wrap_write(&content_file))?;
 
             // Extract all indices from the `.stoneContent` into hash-indexed unique files
@@ -134,8 +152,121 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// Stream the contents of `file` within the stone at `path` to stdout
+fn cat(path: &Path, file: &Path) -> Result<(), Error> {
+    // Layout targets are relative to `/usr`
+    let target = file.strip_prefix("/").unwrap_or(file);
+    let target = target.strip_prefix("usr").unwrap_or(target);
+
+    let rdr = File::open(path).map_err(Error::IO)?;
+    let mut reader = stone::read(rdr).map_err(Error::Format)?;
+
+    let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+
+    let digest = payloads
+        .iter()
+        .filter_map(PayloadKind::layout)
+        .flat_map(|p| &p.body)
+        .find_map(|layout| match &layout.entry {
+            layout::Entry::Regular(id, name) if Path::new(name) == target => Some(Ok(*id)),
+            entry if Path::new(entry.target()) == target => Some(Err(Error::NotRegularFile(file.to_owned()))),
+            _ => None,
+        })
+        .ok_or_else(|| Error::NoSuchFile(file.to_owned()))??;
+
+    let index = payloads
+        .iter()
+        .filter_map(PayloadKind::index)
+        .flat_map(|p| &p.body)
+        .find(|idx| idx.digest == digest)
+        .ok_or_else(|| Error::NoSuchFile(file.to_owned()))?;
+    let content = payloads
+        .iter()
+        .find_map(PayloadKind::content)
+        .ok_or_else(|| Error::NoSuchFile(file.to_owned()))?;
+
+    // Only the file is kept in memory as the content is streamed, so nothing touches the disk
+    let mut range = RangeWriter::new(index.start..index.end);
+    reader.unpack_content(content, &mut range)?;
+
+    let data = range.data;
+    if data.len() as u64 != index.end.saturating_sub(index.start) {
+        return Err(Error::NoSuchFile(file.to_owned()));
+    }
+
+    let actual = xxh3_128(&data);
+    if actual != digest {
+        return Err(Error::HashMismatch {
+            path: file.to_owned(),
+            expected: digest,
+            actual,
+        });
+    }
+
+    let mut stdout = io::stdout().lock();
+    match stdout.write_all(&data).and_then(|_| stdout.flush()) {
+        // Reader went away, e.g. `| head`
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Keeps the bytes within `range` of everything written to it, discarding the rest
+struct RangeWriter {
+    range: Range<u64>,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+impl RangeWriter {
+    fn new(range: Range<u64>) -> Self {
+        Self {
+            range,
+            offset: 0,
+            data: vec![],
+        }
+    }
+}
+
+impl Write for RangeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.offset;
+        let end = start + buf.len() as u64;
+        let from = self.range.start.clamp(start, end);
+        let to = self.range.end.clamp(start, end);
+
+        if from < to {
+            self.data
+                .extend_from_slice(&buf[(from - start) as usize..(to - start) as usize]);
+        }
+        self.offset = end;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("--cat requires a single stone")]
+    CatMultiple,
+
+    #[error("no such file in stone: {0:?}")]
+    NoSuchFile(PathBuf),
+
+    #[error("not a regular file: {0:?}")]
+    NotRegularFile(PathBuf),
+
+    #[error("hash mismatch for {path:?}, expected {expected:02x} got {actual:02x}")]
+    HashMismatch {
+        path: PathBuf,
+        expected: u128,
+        actual: u128,
+    },
+
     #[error("Missing metadata")]
     MissingMeta,
 
@@ -143,8 +274,26 @@ pub enum Error {
     MalformedMeta(#[from] MissingMetaFieldError),
 
     #[error("io")]
-    IO(#[from] std::io::Error),
+    IO(#[from] io::Error),
 
     #[error("stone format")]
     Format(#[from] stone::read::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_range_writer() {
+        let mut range = RangeWriter::new(3..9);
+        for chunk in b"0123456789abcdef".chunks(4) {
+            range.write_all(chunk).unwrap();
+        }
+        assert_eq!(range.data, b"345678");
+
+        let mut inverted = RangeWriter::new(9..3);
+        inverted.write_all(b"0123456789").unwrap();
+        assert!(inverted.data.is_empty());
+    }
+}
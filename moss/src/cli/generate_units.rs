@@ -0,0 +1,322 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use clap::{Arg, ArgMatches, Command, ValueEnum, arg, value_parser};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tui::Styled;
+
+/// Name of the repository refresh units
+const REFRESH_UNIT: &str = "moss-refresh";
+/// Name of the package download units
+const DOWNLOAD_UNIT: &str = "moss-download";
+/// Name of the update units
+const UPDATE_UNIT: &str = "moss-update";
+/// Name of the units staging updates
+const STAGE_UNIT: &str = "moss-stage";
+/// Name of the boot-time service activating staged updates
+const APPLY_STAGED_UNIT: &str = "moss-apply-staged";
+
+/// How updates are handled in the background
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Policy {
+    /// Only refresh repositories, updates are applied manually
+    Manual,
+    /// Also download pending updates, ready to be applied manually
+    #[default]
+    Download,
+    /// Also apply pending updates
+    Apply,
+    /// Also stage pending updates, applied on the next boot
+    Stage,
+}
+
+impl Policy {
+    pub const ALL: [Policy; 4] = [Policy::Manual, Policy::Download, Policy::Apply, Policy::Stage];
+
+    /// Human readable description of the policy
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Policy::Manual => "Manual: refresh repositories in the background, update with moss sync -u",
+            Policy::Download => "Download: also download updates in the background, apply with moss sync -u",
+            Policy::Apply => "Apply: download & apply updates in the background",
+            Policy::Stage => "Stage: prepare updates in the background, applied on the next boot",
+        }
+    }
+}
+
+/// Update configuration, as chosen in `moss setup`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub policy: Policy,
+}
+
+impl config::Config for Config {
+    fn domain() -> String {
+        "update".into()
+    }
+}
+
+pub fn command() -> Command {
+    Command::new("generate-units")
+        .about("Generate systemd units for automatic updates")
+        .long_about(
+            "Generate the recommended systemd service & timer units which periodically refresh \
+             repositories and download pending updates in the background.
+
+Both services run non-interactively at idle priority and share moss's own locking, so they wait \
+for any interactive moss invocation to finish rather than racing it. Timers are randomly delayed \
+to spread load on repository mirrors.
+
+With the stage policy, updates are prepared in the background but only activated early during \
+the next boot, by a service enabled for sysinit.target.",
+        )
+        .arg(arg!(<DIR> "Directory to write the units to").value_parser(value_parser!(PathBuf)))
+        .arg(
+            Arg::new("policy")
+                .long("policy")
+                .default_value("download")
+                .help("How updates are handled in the background")
+                .value_parser(value_parser!(Policy)),
+        )
+        .arg(
+            Arg::new("calendar")
+                .long("calendar")
+                .default_value("daily")
+                .help("systemd calendar expression for when the timers elapse")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("jitter")
+                .long("jitter")
+                .default_value("1h")
+                .help("Maximum randomized delay added to each timer")
+                .value_parser(value_parser!(String)),
+        )
+}
+
+/// Handle execution of `moss generate-units`
+pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+    let dir = args.get_one::<PathBuf>("DIR").unwrap();
+    let policy = *args.get_one::<Policy>("policy").unwrap();
+    let calendar = args.get_one::<String>("calendar").unwrap();
+    let jitter = args.get_one::<String>("jitter").unwrap();
+
+    write(dir, policy, calendar, jitter)?;
+
+    Ok(())
+}
+
+/// Write the units for `policy` to `dir`, returning the names of those to enable
+/// along with the target wanting them
+pub fn write(dir: &Path, policy: Policy, calendar: &str, jitter: &str) -> Result<Vec<(String, &'static str)>, Error> {
+    fs::create_dir_all(dir)?;
+
+    let units = units(policy, calendar, jitter);
+
+    for (name, content) in &units {
+        let path = dir.join(name);
+        fs::write(&path, content)?;
+        println!("{} {}", "Generated".green(), path.display());
+    }
+
+    Ok(units
+        .into_iter()
+        .filter_map(|(name, _)| wanted_by(&name).map(|target| (name, target)))
+        .collect())
+}
+
+/// Target wanting the unit `name` once enabled, if it's enabled at all
+pub fn wanted_by(name: &str) -> Option<&'static str> {
+    if name.ends_with(".timer") {
+        Some("timers.target")
+    } else if name == format!("{APPLY_STAGED_UNIT}.service") {
+        Some("sysinit.target")
+    } else {
+        None
+    }
+}
+
+/// Names of the units to handle updates per `policy`
+pub fn unit_names(policy: Policy) -> Vec<String> {
+    units(policy, "", "").into_iter().map(|(name, _)| name).collect()
+}
+
+/// Units to handle updates per `policy`, by name
+fn units(policy: Policy, calendar: &str, jitter: &str) -> Vec<(String, String)> {
+    let mut units = vec![
+        (
+            format!("{REFRESH_UNIT}.service"),
+            service("Refresh moss repositories", "repo update", None),
+        ),
+        (
+            format!("{REFRESH_UNIT}.timer"),
+            timer("Periodically refresh moss repositories", calendar, jitter),
+        ),
+    ];
+
+    match policy {
+        Policy::Manual => {}
+        Policy::Download => units.extend([
+            (
+                format!("{DOWNLOAD_UNIT}.service"),
+                service(
+                    "Download pending moss updates",
+                    "sync --update --download-only",
+                    Some(REFRESH_UNIT),
+                ),
+            ),
+            (
+                format!("{DOWNLOAD_UNIT}.timer"),
+                timer("Periodically download pending moss updates", calendar, jitter),
+            ),
+        ]),
+        Policy::Apply => units.extend([
+            (
+                format!("{UPDATE_UNIT}.service"),
+                service("Apply pending moss updates", "sync --update", Some(REFRESH_UNIT)),
+            ),
+            (
+                format!("{UPDATE_UNIT}.timer"),
+                timer("Periodically apply pending moss updates", calendar, jitter),
+            ),
+        ]),
+        Policy::Stage => units.extend([
+            (
+                format!("{STAGE_UNIT}.service"),
+                service(
+                    "Stage pending moss updates",
+                    "sync --update --stage",
+                    Some(REFRESH_UNIT),
+                ),
+            ),
+            (
+                format!("{STAGE_UNIT}.timer"),
+                timer("Periodically stage pending moss updates", calendar, jitter),
+            ),
+            (format!("{APPLY_STAGED_UNIT}.service"), apply_staged_service()),
+        ]),
+    }
+
+    units
+}
+
+/// Generate a oneshot service running `moss <args>` non-interactively
+fn service(description: &str, args: &str, after: Option<&str>) -> String {
+    let ordering = after.map(|unit| format!("After={unit}.service\n")).unwrap_or_default();
+
+    format!(
+        "[Unit]
+Description={description}
+Documentation=man:moss(1)
+Wants=network-online.target
+After=network-online.target
+{ordering}ConditionACPower=true
+
+[Service]
+Type=oneshot
+ExecStart=/usr/bin/moss --non-interactive --yes-all --wait {args}
+Nice=19
+IOSchedulingClass=idle
+CPUSchedulingPolicy=idle
+# Waits on the moss lockfile while an interactive session is running
+TimeoutStartSec=2h
+"
+    )
+}
+
+/// Generate the service activating a staged update early during boot, before the
+/// services it could affect are started
+fn apply_staged_service() -> String {
+    "[Unit]
+Description=Apply staged moss update
+Documentation=man:moss(1)
+DefaultDependencies=no
+RequiresMountsFor=/usr /.moss
+After=local-fs.target
+Before=sysinit.target shutdown.target
+Conflicts=shutdown.target
+ConditionPathExists=/.moss/db/pending.json
+
+[Service]
+Type=oneshot
+ExecStart=/usr/bin/moss --non-interactive --yes-all --wait state apply-staged
+TimeoutStartSec=infinity
+
+[Install]
+WantedBy=sysinit.target
+"
+    .to_owned()
+}
+
+/// Generate a persistent timer elapsing on `calendar` with up to `jitter` random delay
+fn timer(description: &str, calendar: &str, jitter: &str) -> String {
+    format!(
+        "[Unit]
+Description={description}
+
+[Timer]
+OnCalendar={calendar}
+RandomizedDelaySec={jitter}
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+"
+    )
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        assert_eq!(
+            unit_names(Policy::Manual),
+            ["moss-refresh.service", "moss-refresh.timer"]
+        );
+        assert_eq!(
+            unit_names(Policy::Download),
+            [
+                "moss-refresh.service",
+                "moss-refresh.timer",
+                "moss-download.service",
+                "moss-download.timer"
+            ]
+        );
+
+        let stage = units(Policy::Stage, "daily", "1h");
+        assert!(
+            stage[2]
+                .1
+                .contains("ExecStart=/usr/bin/moss --non-interactive --yes-all --wait sync --update --stage\n")
+        );
+        assert!(stage[4].1.contains("WantedBy=sysinit.target\n"));
+        assert_eq!(wanted_by(&stage[4].0), Some("sysinit.target"));
+        assert_eq!(wanted_by(&stage[2].0), None);
+
+        let apply = units(Policy::Apply, "daily", "1h");
+        assert!(
+            apply[2]
+                .1
+                .contains("ExecStart=/usr/bin/moss --non-interactive --yes-all --wait sync --update\n")
+        );
+    }
+}
@@ -2,18 +2,23 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 use std::{
-    collections::{BTreeMap, btree_map},
+    collections::{BTreeMap, BTreeSet, btree_map},
     io,
     path::{Path, PathBuf, StripPrefixError},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use camino::{Utf8Path, Utf8PathBuf};
-use clap::{ArgMatches, Command, arg, value_parser};
+use clap::{
+    ArgMatches, Command, arg,
+    builder::{PossibleValuesParser, TypedValueParser},
+    value_parser,
+};
 use fs_err as fs;
 use moss::{
-    client,
+    Installation, client, foreign,
     package::{self, Meta, MissingMetaFieldError},
+    repository::{alias, delta, expiry, index},
 };
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use sha2::{Digest, Sha256};
@@ -24,25 +29,155 @@ pub fn command() -> Command {
     Command::new("index")
         .visible_alias("ix")
         .about("Index a collection of packages")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(arg!(<INDEX_DIR> "directory of index files").value_parser(value_parser!(PathBuf)))
         .arg(
             arg!(-o --"output-dir" [output_dir] "directory to write the stone.index to (defaults to INDEX_DIR)")
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--deltas <COUNT> "number of previous index revisions to publish deltas from")
+                .long_help(
+                    "Number of previous index revisions to publish deltas from, so clients refreshing from one \
+                     of them only download the changes.\n\n\
+                     Previous indices are kept in `stone.index.d` alongside their deltas. 0 disables deltas.",
+                )
+                .value_parser(value_parser!(usize))
+                .default_value("16"),
+        )
+        .arg(
+            arg!(--format <FORMAT> "format of the written stone.index")
+                .long_help(
+                    "Format of the written stone.index.\n\n\
+                     v2 is compressed & splits the metadata into sections, making it considerably smaller \
+                     & faster to load. Clients detect the format, but older ones can only read v1. \
+                     Installed sizes of the packages are only published in v2.",
+                )
+                .value_parser(
+                    PossibleValuesParser::new(["v1", "v2"])
+                        .map(|format| format.parse::<index::Format>().expect("valid format")),
+                )
+                .default_value("v1"),
+        )
+        .arg(
+            arg!(--"valid-for" <DURATION> "how long clients should trust the index, i.e. 7d")
+                .long_help(
+                    "How long clients should trust the index, i.e. 7d.\n\n\
+                     Declared within the index, so it's covered by its signature, which requires `--format v2`. \
+                     Clients warn about an index past its validity, protecting them against mirrors which are \
+                     frozen or replaying old metadata.",
+                )
+                .value_parser(expiry::parse_duration),
+        )
+        .arg(
+            arg!(--aliases <FILE> "file mapping names of other distributions to the packages they're known as")
+                .long_help(
+                    "YAML or JSON file mapping names of other distributions to the packages they're known as, \
+                     i.e. `vim: neovim`.\n\n\
+                     Published as `stone.index.aliases`. Clients resolve the aliases transparently when \
+                     installing & searching.",
+                )
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(-j --jobs <COUNT> "number of stones to read in parallel (defaults to the number of CPUs)")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--full "re-read every stone instead of reusing the scan cache").long_help(
+                "Re-read every stone instead of reusing the scan cache.\n\n\
+                     The metadata of every stone read is cached in `.stone.index.cache` of the output \
+                     directory and reused for stones whose size is unchanged & which haven't been modified \
+                     since the previous run.",
+            ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Import the metadata of a foreign repository")
+                .long_about(
+                    "Import the metadata of another distribution's repository as a read-only package source.\n\n\
+                     Imported packages can be searched, queried with `info` and used for provider lookups, \
+                     but can't be installed as there are no stones behind them. Importing under an existing \
+                     name replaces the previous import.",
+                )
+                .arg(
+                    arg!(--from <FORMAT> "metadata format of the repository")
+                        .required(true)
+                        .value_parser(
+                            PossibleValuesParser::new(["rpm-md", "apt"])
+                                .map(|format| format.parse::<foreign::Format>().expect("valid format")),
+                        ),
+                )
+                .arg(
+                    arg!(<DIR> "repository directory, containing `repodata/` or `Packages` indices")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(-n --name <NAME> "name of the imported repository (defaults to the directory name)")
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+}
+
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand_name() {
+        // Writes the foreign package database of the installation
+        Some("import") => true,
+        // Indexes a directory of stones
+        None => false,
+        _ => unreachable!(),
+    }
 }
 
-pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    if let Some(("import", args)) = args.subcommand() {
+        return import(args, installation);
+    }
+
     let index_dir = args.get_one::<PathBuf>("INDEX_DIR").unwrap().canonicalize()?;
     let output_dir = match args.get_one::<PathBuf>("output-dir") {
         Some(dir) => &dir.canonicalize()?,
         None => &index_dir,
     };
 
+    let deltas = *args.get_one::<usize>("deltas").unwrap();
+    let jobs = args.get_one::<usize>("jobs").copied().unwrap_or_default();
+    let format = *args.get_one::<index::Format>("format").unwrap();
+    let valid_until = args
+        .get_one::<Duration>("valid-for")
+        .map(|valid_for| {
+            expiry::unix_now()
+                .checked_add(valid_for.as_secs())
+                .ok_or(Error::InvalidValidity)
+        })
+        .transpose()?;
+    if valid_until.is_some() && format == index::Format::V1 {
+        return Err(Error::ValidityFormat);
+    }
+    let aliases = args
+        .get_one::<PathBuf>("aliases")
+        .map(|path| {
+            alias::parse(&fs::read_to_string(path)?).map_err(|source| Error::Aliases {
+                source,
+                path: path.clone(),
+            })
+        })
+        .transpose()?;
+
+    let scan_start = SystemTime::now();
+    let cache = if args.get_flag("full") {
+        ScanCache::default()
+    } else {
+        ScanCache::load(output_dir)
+    };
+
     let stone_files = enumerate_stone_files(&index_dir)?;
 
     println!("Indexing {} files\n", stone_files.len());
 
-    let multi_progress = MultiProgress::new();
+    let multi_progress = MultiProgress::with_draw_target(tui::progress_target());
 
     let total_progress = multi_progress.add(
         ProgressBar::new(stone_files.len() as u64).with_style(
@@ -55,13 +190,39 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
 
     let ctx = GetMetaCtx {
         output_dir,
+        cache: &cache,
         multi_progress: &multi_progress,
         total_progress: &total_progress,
     };
-    let list = stone_files
-        .par_iter()
-        .map(|path| get_meta(path, ctx))
-        .collect::<Result<Vec<_>, _>>()?;
+    let list = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?.install(|| {
+        stone_files
+            .par_iter()
+            .map(|path| get_meta(path, ctx))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    ScanCache::write(output_dir, &list, scan_start)?;
+
+    let index_path = output_dir.join("stone.index");
+    let previous = fs::read(&index_path).ok();
+
+    // Security fixes are cumulative, updating past a release classified as security brings
+    // its fix, so the latest such release is carried forward from older stones & the
+    // previous index
+    let mut security_releases = BTreeMap::<package::Name, BTreeSet<u64>>::new();
+    let previous_packages = previous
+        .as_deref()
+        .and_then(|previous| index::read(io::Cursor::new(previous)).ok())
+        .unwrap_or_default();
+    for meta in previous_packages.iter().chain(&list) {
+        let release = match meta.classification {
+            Some(package::Classification::Security) => Some(meta.source_release),
+            _ => meta.security_release,
+        };
+        if let Some(release) = release {
+            security_releases.entry(meta.name.clone()).or_default().insert(release);
+        }
+    }
 
     let mut map = BTreeMap::new();
 
@@ -89,7 +250,44 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
         }
     }
 
-    write_index(output_dir, map, &total_progress)?;
+    for meta in map.values_mut() {
+        meta.security_release = security_releases
+            .get(&meta.name)
+            .and_then(|releases| releases.range(..=meta.source_release).next_back().copied());
+    }
+
+    // Clients predating v2 fail on meta tags unknown to them, so the sizes of the
+    // unpacked content & security releases are only published in v2 indices
+    if format == index::Format::V1 {
+        map.values_mut().for_each(|meta| {
+            meta.installed_size = None;
+            meta.security_release = None;
+        });
+    }
+
+    let packages = map.values().cloned().collect::<Vec<_>>();
+
+    write_index(output_dir, map, format, valid_until, &total_progress)?;
+
+    let revision = delta::revision(&fs::read(&index_path)?);
+    fs::write(output_dir.join(delta::REVISION_FILE), &revision)?;
+
+    alias::save(output_dir, aliases.as_ref()).map_err(|source| Error::Aliases {
+        source,
+        path: output_dir.join(alias::ALIASES_FILE),
+    })?;
+
+    let delta_dir = output_dir.join(delta::DELTA_DIR);
+    if let Some(previous) = previous.filter(|_| deltas > 0) {
+        let previous_revision = delta::revision(&previous);
+        if previous_revision != revision {
+            fs::create_dir_all(&delta_dir)?;
+            fs::write(delta_dir.join(format!("{previous_revision}.index")), previous)?;
+        }
+    }
+    if delta_dir.exists() {
+        write_deltas(&delta_dir, &packages, valid_until, deltas, &total_progress)?;
+    }
 
     multi_progress.clear()?;
 
@@ -98,7 +296,40 @@ pub fn handle(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
-fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &ProgressBar) -> Result<(), Error> {
+fn import(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let format = *args.get_one::<foreign::Format>("from").unwrap();
+    let dir = args.get_one::<PathBuf>("DIR").unwrap().canonicalize()?;
+    let name = match args.get_one::<String>("name") {
+        Some(name) => name.clone(),
+        None => dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| Error::MissingImportName(dir.clone()))?,
+    };
+    foreign::validate_name(&name)?;
+
+    let packages = foreign::import(format, &dir)?;
+    let count = packages.len();
+
+    foreign::save(&installation, &name, packages)?;
+
+    println!(
+        "{} {count} packages from {} as foreign repository {}",
+        "Imported".green(),
+        dir.display(),
+        name.bold()
+    );
+
+    Ok(())
+}
+
+fn write_index(
+    dir: &Path,
+    map: BTreeMap<package::Name, Meta>,
+    format: index::Format,
+    valid_until: Option<u64>,
+    total_progress: &ProgressBar,
+) -> Result<(), Error> {
     total_progress.set_message("Writing index file");
     total_progress.set_style(
         ProgressStyle::with_template("\n {spinner} {wide_msg}")
@@ -108,25 +339,141 @@ fn write_index(dir: &Path, map: BTreeMap<package::Name, Meta>, total_progress: &
     total_progress.enable_steady_tick(Duration::from_millis(150));
 
     let path = dir.join("stone.index");
-    let mut file = fs::File::create(&path)?;
+    let file = fs::File::create(&path)?;
 
-    let write_stone_index = || {
-        let mut writer = stone::Writer::new(&mut file, stone::header::v1::FileType::Repository)?;
+    index::write(file, format, map.into_values().collect(), valid_until)
+        .map_err(|source| Error::IndexWrite { source, path })
+}
 
-        for (_, meta) in map {
-            let payload = meta.to_stone_payload();
-            writer.add_payload(payload.as_slice())?;
+/// Write the delta from each of the `keep` most recent previous indices in `dir` to the
+/// current index with `packages` valid until `valid_until`, removing older ones
+fn write_deltas(
+    dir: &Path,
+    packages: &[Meta],
+    valid_until: Option<u64>,
+    keep: usize,
+    total_progress: &ProgressBar,
+) -> Result<(), Error> {
+    total_progress.set_message("Writing index deltas");
+
+    let mut previous = fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "index"))
+        .map(|entry| Ok((entry.metadata()?.modified()?, entry.path())))
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    // Most recent first
+    previous.sort_by(|a, b| b.cmp(a));
+
+    for (_, path) in previous.iter().skip(keep) {
+        fs::remove_file(path)?;
+    }
+
+    for (_, path) in previous.iter().take(keep) {
+        let old = index::read(fs::File::open(path)?).map_err(|source| Error::IndexRead {
+            source,
+            path: path.clone(),
+        })?;
+
+        let delta_path = path.with_extension("delta");
+        let file = fs::File::create(&delta_path)?;
+        let mut delta = delta::Delta::between(&old, packages);
+        delta.valid_until = valid_until;
+        delta.write(file).map_err(|source| Error::StoneWrite {
+            source,
+            path: delta_path,
+        })?;
+    }
+
+    // Deltas of removed indices
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "delta") && !path.with_extension("index").exists() {
+            fs::remove_file(path)?;
         }
+    }
 
-        writer.finalize()
-    };
+    Ok(())
+}
 
-    write_stone_index().map_err(|source| Error::StoneWrite { source, path })
+/// Metadata of the stones read by the previous run, kept in [`SCAN_CACHE`]
+#[derive(Default)]
+struct ScanCache {
+    /// When the previous run started
+    scanned: Option<SystemTime>,
+    /// Metadata by relative path
+    metas: BTreeMap<String, Meta>,
+}
+
+const SCAN_CACHE: &str = ".stone.index.cache";
+
+impl ScanCache {
+    /// Load the cache from `dir`, an unreadable cache is treated as empty
+    fn load(dir: &Path) -> Self {
+        let path = dir.join(SCAN_CACHE);
+
+        let read = || -> Option<Self> {
+            let mut file = fs::File::open(&path).ok()?;
+            let scanned = file.metadata().ok()?.modified().ok()?;
+            let mut reader = stone::read(&mut file).ok()?;
+            let metas = reader
+                .payloads()
+                .ok()?
+                .filter_map(|payload| {
+                    let meta = Meta::from_stone_payload(&payload.ok()?.meta()?.body).ok()?;
+                    Some((meta.uri.clone()?, meta))
+                })
+                .collect();
+
+            Some(Self {
+                scanned: Some(scanned),
+                metas,
+            })
+        };
+
+        read().unwrap_or_default()
+    }
+
+    /// Cached metadata of the stone at `path` (`relative_path` from the output dir) if it's unchanged
+    fn get(&self, path: &Path, relative_path: &Utf8Path) -> Option<Meta> {
+        let scanned = self.scanned?;
+        let meta = self.metas.get(relative_path.as_str())?;
+        let metadata = fs::metadata(path).ok()?;
+
+        // Entries cached before installed sizes were indexed are read again
+        (meta.download_size == Some(metadata.len())
+            && meta.installed_size.is_some()
+            && metadata.modified().ok()? < scanned)
+            .then(|| meta.clone())
+    }
+
+    /// Write the cache of `metas` to `dir`, timestamped with the start of the scan so
+    /// stones modified while scanning are read again next time
+    fn write(dir: &Path, metas: &[Meta], scan_start: SystemTime) -> Result<(), Error> {
+        let path = dir.join(SCAN_CACHE);
+        let mut file = fs::File::create(&path)?;
+
+        let write_cache = |file: &mut fs::File| {
+            let mut writer = stone::Writer::new(file, stone::header::v1::FileType::Repository)?;
+            for meta in metas {
+                writer.add_payload(meta.clone().to_stone_payload().as_slice())?;
+            }
+            writer.finalize()
+        };
+        write_cache(&mut file).map_err(|source| Error::StoneWrite {
+            source,
+            path: path.clone(),
+        })?;
+
+        file.file().set_modified(scan_start)?;
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy)]
 struct GetMetaCtx<'a> {
     output_dir: &'a Path,
+    cache: &'a ScanCache,
     multi_progress: &'a MultiProgress,
     total_progress: &'a ProgressBar,
 }
@@ -136,6 +483,11 @@ fn get_meta(path: &Path, ctx: GetMetaCtx<'_>) -> Result<Meta, Error> {
         .try_into()
         .map_err(|_| Error::NonUtf8Path { path: path.to_owned() })?;
 
+    if let Some(meta) = ctx.cache.get(path, &relative_path) {
+        ctx.total_progress.inc(1);
+        return Ok(meta);
+    }
+
     let progress = ctx
         .multi_progress
         .insert_before(ctx.total_progress, ProgressBar::new_spinner());
@@ -168,6 +520,10 @@ fn get_meta(path: &Path, ctx: GetMetaCtx<'_>) -> Result<Meta, Error> {
     let mut meta = Meta::from_stone_payload(&payload.body)?;
     meta.hash = Some(hash);
     meta.download_size = Some(size);
+    meta.installed_size = payloads
+        .iter()
+        .find_map(|payload| payload.content())
+        .map(|content| content.header.plain_size);
     meta.uri = Some(relative_path.as_str().to_owned());
 
     progress.finish();
@@ -228,9 +584,24 @@ pub enum Error {
     #[error("writing {path}")]
     StoneWrite { source: stone::write::Error, path: PathBuf },
 
+    #[error("reading {path}")]
+    IndexRead { source: index::Error, path: PathBuf },
+
+    #[error("writing {path}")]
+    IndexWrite { source: index::Error, path: PathBuf },
+
+    #[error("--valid-for requires --format v2")]
+    ValidityFormat,
+
+    #[error("--valid-for is too far in the future")]
+    InvalidValidity,
+
     #[error("package {0} has two files with the same release {1}")]
     DuplicateRelease(package::Name, u64),
 
+    #[error("aliases {path}")]
+    Aliases { source: alias::Error, path: PathBuf },
+
     #[error("meta payload missing")]
     MissingMetaPayload,
 
@@ -245,6 +616,15 @@ pub enum Error {
 
     #[error("non-utf8 path: {path}")]
     NonUtf8Path { path: PathBuf },
+
+    #[error("foreign import")]
+    Foreign(#[from] foreign::Error),
+
+    #[error("cannot derive a repository name from {0:?}, use --name")]
+    MissingImportName(PathBuf),
+
+    #[error("thread pool")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
 }
 
 /// Make a relative path that points to `to` if the current working directory is `from_dir`.
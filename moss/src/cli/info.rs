@@ -2,17 +2,24 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::{
+    collections::BTreeMap,
+    env, io,
+    process::{self, Stdio},
+};
+
 use clap::{ArgMatches, Command, arg};
 use itertools::Itertools;
 use moss::{
     Installation, Package, Provider,
     client::{self, Client},
-    environment,
+    dependency, environment,
     package::Flags,
+    state,
 };
 use stone::payload::layout;
 use thiserror::Error;
-use tui::{Styled, TermSize};
+use tui::{HumanBytes, Styled, TermSize};
 use vfs::tree::BlitFile;
 
 const COLUMN_WIDTH: usize = 20;
@@ -23,6 +30,29 @@ pub fn command() -> Command {
         .long_about("List detailed package information from all available sources")
         .arg(arg!(<NAME> ... "Packages to query").value_parser(clap::value_parser!(String)))
         .arg(arg!(-f --files ... "Show files provided by package").action(clap::ArgAction::SetTrue))
+        .arg(
+            arg!(--provides "Only show the providers of the package, grouped by kind")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["files", "compare", "web", "report-bug"]),
+        )
+        .arg(
+            arg!(--compare "Show the installed version beside the best available candidate, i.e. to check for updates")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["files", "web", "report-bug"]),
+        )
+        .arg(
+            arg!(--state <ID> "Report packages as installed in the given state (id or tag) instead of the active one")
+                .value_parser(clap::value_parser!(state::Reference)),
+        )
+        .arg(
+            arg!(--web "Open the homepage of the package in a browser ($BROWSER, or xdg-open)")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("report-bug"),
+        )
+        .arg(
+            arg!(--"report-bug" "Open the bug tracker of the package, pre-filled with its version")
+                .action(clap::ArgAction::SetTrue),
+        )
 }
 
 /// For all arguments, try to match a package
@@ -34,8 +64,37 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .cloned()
         .collect::<Vec<_>>();
     let show_files = args.get_flag("files");
+    let show_providers = args.get_flag("provides");
+    let compare = args.get_flag("compare");
+    let web = args.get_flag("web");
+    let report_bug = args.get_flag("report-bug");
 
     let client = Client::new(environment::NAME, installation)?;
+    let selected_state = args
+        .get_one::<state::Reference>("state")
+        .map(|reference| client.state_db.resolve(reference))
+        .transpose()?;
+
+    // Why each package was installed to the state reported on
+    let reasons = match selected_state.or(client.installation.active_state) {
+        Some(id) => client
+            .state_db
+            .get(id)?
+            .selections
+            .into_iter()
+            .filter_map(|selection| Some((selection.package, selection.reason?)))
+            .collect(),
+        None => BTreeMap::new(),
+    };
+
+    let client = match selected_state {
+        Some(id) => client.as_of_state(id)?,
+        None => client,
+    };
+
+    if compare {
+        return print_comparisons(&client, &pkgs);
+    }
 
     for pkg in pkgs {
         let lookup = Provider::from_name(&pkg).unwrap();
@@ -47,8 +106,42 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         if resolved.is_empty() {
             return Err(Error::NotFound(pkg));
         }
+
+        if web || report_bug {
+            // Prefer what's installed, as that's what bugs would be reported against
+            let candidate = resolved
+                .iter()
+                .find(|candidate| candidate.flags.installed)
+                .unwrap_or(&resolved[0]);
+
+            let url = if web {
+                Some(candidate.meta.homepage.clone())
+                    .filter(|homepage| !homepage.is_empty())
+                    .ok_or_else(|| Error::NoHomepage(pkg.clone()))?
+            } else {
+                candidate
+                    .meta
+                    .bug_tracker
+                    .as_deref()
+                    .map(|tracker| bug_report_url(tracker, candidate))
+                    .ok_or_else(|| Error::NoBugTracker(pkg.clone()))?
+            };
+
+            println!("Opening {url}");
+            open_url(&url)?;
+            continue;
+        }
+
+        if show_providers {
+            for candidate in resolved {
+                print_providers(&candidate);
+                println!();
+            }
+            continue;
+        }
+
         for candidate in resolved {
-            print_package(&candidate);
+            print_package(&candidate, reasons.get(&candidate.id));
 
             if candidate.flags.installed && show_files {
                 let vfs = client.vfs([&candidate.id])?;
@@ -61,6 +154,134 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
+/// Installed version of a package beside its best available candidate
+struct Comparison {
+    name: String,
+    installed: Option<Package>,
+    candidate: Option<Package>,
+    repository: Option<String>,
+}
+
+impl Comparison {
+    fn status(&self) -> String {
+        match (&self.installed, &self.candidate) {
+            (None, _) => "not installed".dim().to_string(),
+            (Some(_), None) => "not in any repository".dim().to_string(),
+            (Some(installed), Some(candidate)) if installed.id == candidate.id => "up to date".to_owned(),
+            (Some(installed), Some(candidate)) if candidate.meta.source_release > installed.meta.source_release => {
+                "update available".green().bold().to_string()
+            }
+            (Some(_), Some(_)) => "differs from repository".yellow().to_string(),
+        }
+    }
+}
+
+/// Print the installed version of each package beside its best available candidate
+fn print_comparisons(client: &Client, pkgs: &[String]) -> Result<(), Error> {
+    let comparisons = pkgs
+        .iter()
+        .map(|pkg| {
+            let lookup = Provider::from_name(pkg).unwrap();
+            let installed = client
+                .registry
+                .by_provider(&lookup, Flags::new().with_installed())
+                .next();
+            // Thanks to priorities, the first one is the winning candidate
+            let candidate = client
+                .registry
+                .by_provider(&lookup, Flags::new().with_available())
+                .next();
+
+            if installed.is_none() && candidate.is_none() {
+                return Err(Error::NotFound(pkg.clone()));
+            }
+
+            let repository = candidate
+                .as_ref()
+                .and_then(|candidate| client.origin(candidate))
+                .map(|id| id.to_string());
+            let name = installed
+                .as_ref()
+                .or(candidate.as_ref())
+                .map(|package| package.meta.name.to_string())
+                .unwrap_or_default();
+
+            Ok(Comparison {
+                name,
+                installed,
+                candidate,
+                repository,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let version = |package: &Option<Package>| {
+        package
+            .as_ref()
+            .map(|package| format!("{}-{}", package.meta.version_identifier, package.meta.source_release))
+            .unwrap_or_else(|| "-".to_owned())
+    };
+    let rows = comparisons
+        .iter()
+        .map(|comparison| {
+            let size = comparison
+                .candidate
+                .as_ref()
+                .and_then(|candidate| candidate.meta.download_size)
+                .map(|size| HumanBytes(size).to_string())
+                .unwrap_or_default();
+
+            [
+                comparison.name.clone(),
+                version(&comparison.installed),
+                version(&comparison.candidate),
+                comparison.repository.clone().unwrap_or_else(|| "-".to_owned()),
+                size,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let headers = ["Name", "Installed", "Candidate", "Repository", "Size"];
+    let widths: [usize; 5] = std::array::from_fn(|column| {
+        rows.iter()
+            .map(|row| row[column].len())
+            .chain([headers[column].len()])
+            .max()
+            .unwrap_or_default()
+    });
+
+    println!(
+        "{}",
+        headers
+            .iter()
+            .zip(widths)
+            .map(|(header, width)| format!("{header:<width$}"))
+            .join("  ")
+            .bold()
+    );
+    for (row, comparison) in rows.iter().zip(&comparisons) {
+        let [name, installed, candidate, repository, size] = row;
+        let [
+            name_width,
+            installed_width,
+            candidate_width,
+            repository_width,
+            size_width,
+        ] = widths;
+
+        println!(
+            "{}  {installed:<installed_width$}  {}  {}  {}  {}",
+            format!("{name:<name_width$}").bold(),
+            format!("{candidate:<candidate_width$}").magenta(),
+            format!("{repository:<repository_width$}").dim(),
+            format!("{size:>size_width$}").dim(),
+            comparison.status()
+        );
+    }
+
+    Ok(())
+}
+
 /// Print the title for each metadata section
 fn print_titled(title: &'static str) {
     let display_width = COLUMN_WIDTH - title.len();
@@ -134,12 +355,18 @@ where
 }
 
 /// Pretty print a package
-fn print_package(pkg: &Package) {
+fn print_package(pkg: &Package, reason: Option<&String>) {
     print_titled("Name");
     println!("{}", pkg.meta.name);
     print_titled("Status");
     if pkg.flags.installed {
         println!("Installed");
+        if let Some(reason) = reason {
+            print_titled("Install reason");
+            println!("{reason}");
+        }
+    } else if pkg.flags.foreign {
+        println!("Foreign, can't be installed");
     } else {
         println!("Not installed");
     }
@@ -153,6 +380,14 @@ fn print_package(pkg: &Package) {
     }
     print_titled("Homepage");
     println!("{}", pkg.meta.homepage);
+    if let Some(bug_tracker) = &pkg.meta.bug_tracker {
+        print_titled("Bug tracker");
+        println!("{bug_tracker}");
+    }
+    if let Some(maintainer) = &pkg.meta.maintainer {
+        print_titled("Maintainer");
+        println!("{maintainer}");
+    }
     print_titled("Summary");
     println!("{}", pkg.meta.summary);
     print_titled("Description");
@@ -169,6 +404,98 @@ fn print_package(pkg: &Package) {
     }
 }
 
+/// Print the full provider set of a package, grouped by kind
+fn print_providers(pkg: &Package) {
+    println!(
+        "{} {}",
+        pkg.meta.name.to_string().bold(),
+        format!("{}-{}", pkg.meta.version_identifier, pkg.meta.source_release).magenta()
+    );
+
+    let by_kind = pkg.meta.providers.iter().into_group_map_by(|provider| provider.kind);
+    for kind in KINDS {
+        if let Some(providers) = by_kind.get(&kind) {
+            print_titled(kind_title(kind));
+            print_list(providers.iter().map(|provider| &provider.name).sorted());
+        }
+    }
+}
+
+/// Order in which providers are listed
+const KINDS: [dependency::Kind; 10] = [
+    dependency::Kind::PackageName,
+    dependency::Kind::SharedLibrary,
+    dependency::Kind::PkgConfig,
+    dependency::Kind::PkgConfig32,
+    dependency::Kind::CMake,
+    dependency::Kind::Binary,
+    dependency::Kind::SystemBinary,
+    dependency::Kind::Interpreter,
+    dependency::Kind::Python,
+    dependency::Kind::Modalias,
+];
+
+fn kind_title(kind: dependency::Kind) -> &'static str {
+    match kind {
+        dependency::Kind::PackageName => "Names",
+        dependency::Kind::SharedLibrary => "Shared libraries",
+        dependency::Kind::PkgConfig => "pkg-config",
+        dependency::Kind::PkgConfig32 => "pkg-config (32-bit)",
+        dependency::Kind::CMake => "CMake modules",
+        dependency::Kind::Binary => "Binaries",
+        dependency::Kind::SystemBinary => "System binaries",
+        dependency::Kind::Interpreter => "Interpreters",
+        dependency::Kind::Python => "Python modules",
+        dependency::Kind::Modalias => "Modaliases",
+    }
+}
+
+/// The URL to file a new issue against `tracker`, pre-filled with the version of `pkg`
+///
+/// Trackers that aren't known to accept pre-filled issues are opened as is.
+fn bug_report_url(tracker: &str, pkg: &Package) -> String {
+    let encode = |value: &str| url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+
+    let title = encode(&format!("{}: ", pkg.meta.name));
+    let body = encode(&format!(
+        "Package: {} {}-{}.{} ({})\nmoss: {}\n\n",
+        pkg.meta.name,
+        pkg.meta.version_identifier,
+        pkg.meta.source_release,
+        pkg.meta.build_release,
+        pkg.meta.architecture,
+        tools_buildinfo::get_simple_version(),
+    ));
+
+    let tracker = tracker.trim_end_matches('/');
+
+    if tracker.contains("gitlab") && tracker.ends_with("/issues") {
+        format!("{tracker}/new?issue[title]={title}&issue[description]={body}")
+    } else if tracker.ends_with("/issues") {
+        // GitHub, Gitea & Forgejo
+        format!("{tracker}/new?title={title}&body={body}")
+    } else {
+        tracker.to_owned()
+    }
+}
+
+/// Open `url` with `$BROWSER`, falling back to `xdg-open`
+fn open_url(url: &str) -> Result<(), Error> {
+    let browser = env::var("BROWSER")
+        .ok()
+        .and_then(|browsers| browsers.split(':').next().map(ToOwned::to_owned))
+        .filter(|browser| !browser.is_empty())
+        .unwrap_or_else(|| "xdg-open".to_owned());
+
+    process::Command::new(&browser)
+        .arg(url)
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|error| Error::Browser(browser, error))?;
+
+    Ok(())
+}
+
 fn print_files(vfs: vfs::Tree<client::PendingFile>) {
     let files = vfs
         .iter()
@@ -205,4 +532,12 @@ pub enum Error {
     NotFound(String),
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+    #[error("{0} has no homepage")]
+    NoHomepage(String),
+    #[error("{0} has no bug tracker")]
+    NoBugTracker(String),
+    #[error("open browser {0}")]
+    Browser(String, #[source] io::Error),
 }
@@ -4,12 +4,15 @@
 
 use clap::{ArgMatches, Command, arg};
 use fs_err::File;
+use moss::{Installation, client, environment, package};
+use std::collections::BTreeMap;
 use std::io::{Read, Seek, sink};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use stone::payload::layout;
 use stone::payload::meta;
 use stone::read::PayloadKind;
 use thiserror::Error;
+use tui::Styled;
 
 const COLUMN_WIDTH: usize = 20;
 
@@ -18,18 +21,35 @@ pub fn command() -> Command {
         .about("Examine raw stone files")
         .long_about("Show detailed (debug) information on a local `.stone` file")
         .arg(arg!(<PATH> ... "files to inspect").value_parser(clap::value_parser!(PathBuf)))
-        .arg(arg!(--check "Check the integrity of the stone file(s)").action(clap::ArgAction::SetTrue))
         .arg(
-            arg!(-q --quiet "Suppress output, only exit status indicates success or failure (requires --check)")
-                .action(clap::ArgAction::SetTrue)
-                .requires("check"),
+            arg!(--check "Check the integrity of the stone file(s), with --quiet only the exit status reports the result")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("layout")
+                .about("Show the layout of a stone as a tree")
+                .long_about(
+                    "Show the layout of a stone as a tree annotated with permissions and ownership, \
+                     optionally comparing it against the installed package or another stone",
+                )
+                .arg(arg!(<PATH> "stone file to inspect").value_parser(clap::value_parser!(PathBuf)))
+                .arg(
+                    arg!(--"diff-against" <TARGET> "Report permission & ownership changes against `installed` or another stone")
+                        .value_parser(clap::value_parser!(String)),
+                ),
         )
 }
 
 ///
 /// Inspect the given .stone files and print results
 ///
-pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    if let Some(("layout", args)) = args.subcommand() {
+        return handle_layout(args, installation);
+    }
+
     let paths = args
         .get_many::<PathBuf>("PATH")
         .into_iter()
@@ -94,10 +114,11 @@ fn handle_detailed(paths: Vec<PathBuf>) -> Result<(), Error> {
         for payload in payloads.flatten() {
             let mut layouts = vec![];
 
-            // Grab deps/providers/conflicts
+            // Grab deps/providers/conflicts/replaces
             let mut deps = vec![];
             let mut provs = vec![];
             let mut cnfls = vec![];
+            let mut rplcs = vec![];
 
             match payload {
                 PayloadKind::Layout(l) => layouts = l.body,
@@ -114,6 +135,9 @@ fn handle_detailed(paths: Vec<PathBuf>) -> Result<(), Error> {
                             meta::Kind::Provider(k, p) if record.tag == meta::Tag::Conflicts => {
                                 cnfls.push(format!("{k}({p})"));
                             }
+                            meta::Kind::Provider(k, p) if record.tag == meta::Tag::Replaces => {
+                                rplcs.push(format!("{k}({p})"));
+                            }
                             meta::Kind::Dependency(k, d) => {
                                 deps.push(format!("{k}({d})"));
                             }
@@ -153,6 +177,12 @@ fn handle_detailed(paths: Vec<PathBuf>) -> Result<(), Error> {
                     println!("    - {cnfl}");
                 }
             }
+            if !rplcs.is_empty() {
+                println!("\n{:COLUMN_WIDTH$} :", "Replaces");
+                for rplc in rplcs {
+                    println!("    - {rplc}");
+                }
+            }
 
             if !layouts.is_empty() {
                 println!("\n{:COLUMN_WIDTH$} :", "Layout entries");
@@ -176,6 +206,223 @@ fn handle_detailed(paths: Vec<PathBuf>) -> Result<(), Error> {
     Ok(())
 }
 
+fn handle_layout(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let path = args.get_one::<PathBuf>("PATH").unwrap();
+    let (meta, layouts) = read_layout(path)?;
+
+    let Some(against) = args.get_one::<String>("diff-against") else {
+        print_tree(&layouts);
+        return Ok(());
+    };
+
+    let (label, previous) = if against == "installed" {
+        let client = client::Client::new(environment::NAME, installation)?;
+        let installed = client
+            .registry
+            .by_name(&meta.name, package::Flags::new().with_installed())
+            .next()
+            .ok_or_else(|| Error::NotInstalled(meta.name.to_string()))?;
+        let layouts = client
+            .layout_db
+            .query([&installed.id])?
+            .into_iter()
+            .map(|(_, layout)| layout)
+            .collect();
+
+        (
+            format!(
+                "installed {} {}-{}",
+                installed.meta.name, installed.meta.version_identifier, installed.meta.source_release
+            ),
+            layouts,
+        )
+    } else {
+        (against.clone(), read_layout(Path::new(against))?.1)
+    };
+
+    let changes = diff_layouts(&previous, &layouts);
+    if changes.is_empty() {
+        println!("No permission or ownership changes against {label}");
+        return Ok(());
+    }
+
+    println!("Changes against {label}:");
+    println!();
+    for change in changes {
+        match change {
+            Change::Added(layout) => println!("{} {} {}", "+".green(), describe(&layout), layout.entry.target()),
+            Change::Removed(layout) => println!("{} {} {}", "-".red(), describe(&layout), layout.entry.target()),
+            Change::Modified { old, new } => println!(
+                "{} {} {} {}",
+                "~".yellow(),
+                describe(&old).dim(),
+                describe(&new),
+                new.entry.target()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the package metadata and layout entries of a stone
+fn read_layout(path: &Path) -> Result<(package::Meta, Vec<layout::Layout>), Error> {
+    let mut file = File::open(path)?;
+    let mut reader = stone::read(&mut file)?;
+
+    let mut meta = None;
+    let mut layouts = vec![];
+
+    for payload in reader.payloads()? {
+        match payload? {
+            PayloadKind::Meta(payload) => meta = Some(package::Meta::from_stone_payload(&payload.body)?),
+            PayloadKind::Layout(payload) => layouts = payload.body,
+            _ => {}
+        }
+    }
+
+    Ok((meta.ok_or(Error::MissingMeta)?, layouts))
+}
+
+/// A directory in the layout tree
+#[derive(Default)]
+struct Node<'a> {
+    layout: Option<&'a layout::Layout>,
+    children: BTreeMap<&'a str, Node<'a>>,
+}
+
+/// Print layout entries as a tree rooted at `/usr`, annotated with mode & ownership
+fn print_tree(layouts: &[layout::Layout]) {
+    let mut root = Node::default();
+
+    for layout in layouts {
+        let mut node = &mut root;
+        for component in layout.entry.target().split('/').filter(|c| !c.is_empty()) {
+            node = node.children.entry(component).or_default();
+        }
+        node.layout = Some(layout);
+    }
+
+    println!("{} /usr", annotation(root.layout));
+    print_children(&root, "");
+}
+
+fn print_children(node: &Node<'_>, prefix: &str) {
+    let count = node.children.len();
+
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+
+        let name = match child.layout.map(|l| &l.entry) {
+            Some(layout::Entry::Symlink(source, _)) => format!("{name} -> {source}"),
+            Some(layout::Entry::Directory(_)) => name.bold().to_string(),
+            _ => name.to_string(),
+        };
+        println!("{} {prefix}{branch}{name}", annotation(child.layout));
+
+        let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+        print_children(child, &prefix);
+    }
+}
+
+/// Mode & ownership column for a layout entry, blank for implied directories
+fn annotation(layout: Option<&layout::Layout>) -> String {
+    match layout {
+        Some(layout) => describe(layout),
+        None => format!("{:<10} {:>5} {:>5}", "d?????????", "-", "-"),
+    }
+}
+
+/// Render a layout's type, permissions & ownership like `ls -ln`
+fn describe(layout: &layout::Layout) -> String {
+    format!("{} {:>5} {:>5}", mode_string(layout), layout.uid, layout.gid)
+}
+
+fn mode_string(layout: &layout::Layout) -> String {
+    let kind = match layout.entry {
+        layout::Entry::Regular(..) => '-',
+        layout::Entry::Symlink(..) => 'l',
+        layout::Entry::Directory(_) => 'd',
+        layout::Entry::CharacterDevice(_) => 'c',
+        layout::Entry::BlockDevice(_) => 'b',
+        layout::Entry::Fifo(_) => 'p',
+        layout::Entry::Socket(_) => 's',
+    };
+
+    let mode = layout.mode;
+    let bit = |mask: u32, c: char| if mode & mask != 0 { c } else { '-' };
+    // Execute bit, replaced by the setuid/setgid/sticky marker when set
+    let exec = |mask: u32, special: u32, set: char| match (mode & mask != 0, mode & special != 0) {
+        (true, true) => set,
+        (false, true) => set.to_ascii_uppercase(),
+        (true, false) => 'x',
+        (false, false) => '-',
+    };
+
+    [
+        kind,
+        bit(0o400, 'r'),
+        bit(0o200, 'w'),
+        exec(0o100, 0o4000, 's'),
+        bit(0o040, 'r'),
+        bit(0o020, 'w'),
+        exec(0o010, 0o2000, 's'),
+        bit(0o004, 'r'),
+        bit(0o002, 'w'),
+        exec(0o001, 0o1000, 't'),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// A permission, ownership or file type difference between two layouts
+#[derive(Debug)]
+enum Change {
+    Added(layout::Layout),
+    Removed(layout::Layout),
+    Modified { old: layout::Layout, new: layout::Layout },
+}
+
+/// Compare `old` & `new` layouts by path, ignoring content changes
+fn diff_layouts(old: &[layout::Layout], new: &[layout::Layout]) -> Vec<Change> {
+    let by_path = |layouts: &[layout::Layout]| {
+        layouts
+            .iter()
+            .map(|l| (l.entry.target().to_owned(), l.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let mut old = by_path(old);
+    let new = by_path(new);
+
+    let mut changes = vec![];
+
+    for (path, new) in new {
+        match old.remove(&path) {
+            None => changes.push(Change::Added(new)),
+            Some(old) => {
+                let differs = old.mode != new.mode
+                    || old.uid != new.uid
+                    || old.gid != new.gid
+                    || mode_string(&old).chars().next() != mode_string(&new).chars().next();
+                if differs {
+                    changes.push(Change::Modified { old, new });
+                }
+            }
+        }
+    }
+    changes.extend(old.into_values().map(Change::Removed));
+
+    changes.sort_by(|a, b| change_path(a).cmp(change_path(b)));
+    changes
+}
+
+fn change_path(change: &Change) -> &str {
+    match change {
+        Change::Added(layout) | Change::Removed(layout) | Change::Modified { new: layout, .. } => layout.entry.target(),
+    }
+}
+
 /// Checks the integrity of a single .stone file by reading all payloads
 /// and validating their checksums from any readable source.
 fn check_stone_integrity(mut source: impl Read + Seek) -> Result<Vec<String>, Error> {
@@ -211,6 +458,21 @@ pub enum Error {
 
     #[error("One or more files failed the integrity check")]
     ValidationFailed,
+
+    #[error("stone has no metadata")]
+    MissingMeta,
+
+    #[error("malformed metadata")]
+    MalformedMeta(#[from] package::MissingMetaFieldError),
+
+    #[error("package is not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
 }
 
 #[cfg(test)]
@@ -255,6 +517,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_diff_layouts() {
+        let layout = |target: &str, mode, uid| layout::Layout {
+            uid,
+            gid: 0,
+            mode,
+            tag: 0,
+            entry: layout::Entry::Regular(0, target.to_owned()),
+        };
+
+        let old = [
+            layout("bin/a", 0o100_755, 0),
+            layout("bin/b", 0o100_755, 0),
+            layout("bin/c", 0o100_755, 0),
+        ];
+        let new = [
+            layout("bin/a", 0o100_755, 0),
+            layout("bin/b", 0o104_777, 0),
+            layout("bin/d", 0o100_644, 1000),
+        ];
+
+        let changes = diff_layouts(&old, &new)
+            .iter()
+            .map(|change| match change {
+                Change::Added(l) => format!("+{}", l.entry.target()),
+                Change::Removed(l) => format!("-{}", l.entry.target()),
+                Change::Modified { old, new } => {
+                    format!("~{} {} {}", new.entry.target(), mode_string(old), mode_string(new))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(changes, ["~bin/b -rwxr-xr-x -rwsrwxrwx", "-bin/c", "+bin/d"]);
+    }
+
     #[test]
     fn test_check_malformed_stone() {
         // Use garbage data that doesn't even have a valid header.
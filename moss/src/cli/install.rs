@@ -4,9 +4,12 @@
 
 use std::path::PathBuf;
 
-use clap::{ArgMatches, Command, arg, value_parser};
-use moss::{Installation, client::Client, environment};
+use clap::{ArgAction, ArgMatches, Command, arg, value_parser};
+use moss::{Installation, client::Client, environment, package::Flags};
 use tracing::instrument;
+use tui::Styled;
+
+use super::Outcome;
 
 pub use moss::client::install::Error;
 
@@ -16,6 +19,16 @@ pub fn command() -> Command {
         .about("Install packages")
         .long_about("Install the requested software to the local system")
         .arg(arg!(<NAME> ... "packages to install").value_parser(value_parser!(String)))
+        .arg(
+            arg!(--command "Treat the names as commands, installing the package providing each")
+                .long_help(
+                    "Treat the names as commands, installing the package providing each as a binary in \
+                     /usr/bin or /usr/sbin. \n\
+                     \n\
+                     When several packages provide a command, the one with the highest priority is installed",
+                )
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--to <blit_target> "Blit this install to the provided directory instead of the root")
                 .long_help(
@@ -25,28 +38,91 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(arg!(--"skip-triggers" "Do not run triggers when applying the new state").action(ArgAction::SetTrue))
+        .arg(
+            arg!(--force "Continue even if there isn't enough disk space for the transaction")
+                .action(ArgAction::SetTrue),
+        )
+        .args(super::system_model_change_args())
+        .args(super::divergence_args())
+}
+
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    // `--to` blits elsewhere, leaving the installation alone
+    !args.contains_id("to")
 }
 
 /// Handle execution of `moss install`
 #[instrument(skip_all)]
-pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let pkgs = args
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<Outcome, Error> {
+    let names = args
         .get_many::<String>("NAME")
         .into_iter()
         .flatten()
         .map(String::as_str)
         .collect::<Vec<_>>();
     let yes = *args.get_one::<bool>("yes").unwrap();
+    let blit_target = args.get_one::<PathBuf>("to").cloned();
+
+    // Ephemeral installs leave the installation, and so its system-model, alone
+    let update_model = blit_target.is_none() && super::update_model(args, &installation)?;
 
     // Grab a client for the root
-    let mut client = Client::new(environment::NAME, installation)?;
+    let mut client = Client::new(environment::NAME, installation)?
+        .skip_triggers(args.get_flag("skip-triggers"))
+        .force_space(args.get_flag("force"));
 
     // Make ephemeral if a blit target was provided
-    if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
+    if let Some(blit_target) = blit_target {
         client = client.ephemeral(blit_target)?;
     }
 
-    client.install(&pkgs, yes)?;
+    super::warn_stale_metadata(&client);
+
+    let pkgs = if args.get_flag("command") {
+        names
+            .iter()
+            .map(|command| command_package(&client, command))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        names.iter().map(|name| name.to_string()).collect()
+    };
+    let pkgs = pkgs.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let kept = super::resolve_divergence(&client, args, yes)?;
+    let mut client = client.keep_local(kept);
+
+    match client.install(&pkgs, yes)? {
+        Some(_) => {
+            if update_model {
+                client.update_system_model()?;
+            }
+            Ok(Outcome::Done)
+        }
+        None => Ok(Outcome::NothingToDo),
+    }
+}
+
+/// Name of the package providing `command`, noting the alternatives if there are several
+fn command_package(client: &Client, command: &str) -> Result<String, Error> {
+    let providers = client.command_providers(command, Flags::new().with_available());
+    let Some((first, others)) = providers.split_first() else {
+        return Err(Error::NoCommand(command.to_owned()));
+    };
+
+    if !others.is_empty() {
+        println!(
+            "{} {command} is also provided by {}, using {}",
+            "Note:".yellow(),
+            others
+                .iter()
+                .map(|package| package.meta.name.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            first.meta.name
+        );
+    }
 
-    Ok(())
+    Ok(first.meta.name.to_string())
 }
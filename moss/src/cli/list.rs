@@ -2,74 +2,163 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use clap::{ArgMatches, Command, arg};
+use std::collections::{BTreeMap, BTreeSet, btree_map};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use clap::{Arg, ArgMatches, Command, arg, value_parser};
+use fnmatch::Pattern;
 use itertools::Itertools;
 use thiserror::Error;
 
 use moss::{
     Installation,
-    client::{self, Client},
+    client::{self, Client, space},
     environment,
-    package::Flags,
+    package::{self, Classification, Flags},
+    state,
 };
-use tui::Styled;
+use tui::{HumanBytes, Styled};
 
 pub fn command() -> Command {
     Command::new("list")
         .about("List packages")
         .long_about("List packages according to a filter")
         .subcommand_required(true)
-        .subcommand(
+        .subcommand(with_reason_args(with_common_args(
             Command::new("installed")
                 .about("List all installed packages")
                 .visible_alias("li")
-                .arg(arg!(-e --"explicit" "List explicit packages only")),
-        )
+                .arg(
+                    arg!(--state <ID> "List the packages installed as of the given state (id or tag) instead")
+                        .value_parser(value_parser!(state::Reference)),
+                ),
+        )))
         .subcommand(
-            Command::new("available")
-                .about("List all available packages")
-                .visible_alias("la"),
+            with_common_args(
+                Command::new("available")
+                    .about("List all available packages")
+                    .visible_alias("la"),
+            )
+            // Only installed packages have a date
+            .mut_arg("sort", |sort| sort.value_parser(["name", "size"])),
         )
-        .subcommand(
+        .subcommand(with_reason_args(with_common_args(
             Command::new("sync")
                 .about("List packages with sync changes")
                 .visible_aliases(["ls", "lu"])
-                .arg(arg!(--"upgrade-only" "Only sync packages that have a version upgrade")),
+                .arg(arg!(--"upgrade-only" "Only sync packages that have a version upgrade"))
+                .arg(arg!(--"security-only" "Only sync packages that have an update classified as security")),
+        )))
+}
+
+/// Filtering & sorting arguments shared by all list subcommands
+fn with_common_args(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_name("GLOB")
+                .help("Only list packages with a name matching the glob")
+                .value_parser(value_parser!(String)),
         )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Sort the listed packages")
+                .default_value("name")
+                .value_parser(["name", "size", "date"]),
+        )
+}
+
+/// Explicit / automatic selection filters for installed packages
+fn with_reason_args(command: Command) -> Command {
+    command
+        .arg(arg!(-e --"explicit" "List explicitly installed packages only").conflicts_with("auto"))
+        .arg(arg!(-a --"auto" "List automatically installed dependencies only"))
 }
 
 enum Sync {
     All,
     Upgrades,
+    Security,
+}
+
+#[derive(Clone, Copy)]
+enum Sort {
+    Name,
+    Size,
+    Date,
 }
 
 /// Handle listing by filter
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let (filter_flags, sync) = match args.subcommand() {
-        Some(("available", _)) => (Flags::new().with_available(), None),
+    let (filter_flags, sync, args) = match args.subcommand() {
+        Some(("available", args)) => (Flags::new().with_available(), None, args),
         Some(("installed", args)) => {
-            let flags = if *args.get_one::<bool>("explicit").unwrap() {
+            let flags = if args.get_flag("explicit") {
                 Flags::new().with_installed().with_explicit()
             } else {
                 Flags::new().with_installed()
             };
-            (flags, None)
+            (flags, None, args)
         }
         Some(("sync", args)) => {
-            let sync = if *args.get_one::<bool>("upgrade-only").unwrap() {
+            let sync = if args.get_flag("security-only") {
+                Sync::Security
+            } else if *args.get_one::<bool>("upgrade-only").unwrap() {
                 Sync::Upgrades
             } else {
                 Sync::All
             };
 
-            (Flags::new().with_installed(), Some(sync))
+            (Flags::new().with_installed(), Some(sync), args)
         }
         _ => unreachable!(),
     };
 
+    let filter = args
+        .get_one::<String>("filter")
+        .map(|glob| Pattern::from_str(glob))
+        .transpose()?;
+    let sort = match args.get_one::<String>("sort").map(String::as_str) {
+        Some("size") => Sort::Size,
+        Some("date") => Sort::Date,
+        _ => Sort::Name,
+    };
+    let explicit_only = args
+        .try_get_one::<bool>("explicit")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or_default();
+    let auto_only = args
+        .try_get_one::<bool>("auto")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or_default();
+
+    let as_of = args.try_get_one::<state::Reference>("state").ok().flatten();
+
     // Grab a client for the target, enumerate packages
     let client = Client::new(environment::NAME, installation)?;
-    let pkgs = client.registry.list(filter_flags).collect::<Vec<_>>();
+    let as_of = as_of.map(|reference| client.state_db.resolve(reference)).transpose()?;
+    let client = match as_of {
+        Some(id) => client.as_of_state(id)?,
+        None => client,
+    };
+    let pkgs = client
+        .registry
+        .list(filter_flags)
+        .filter(|p| !explicit_only || p.flags.explicit)
+        .filter(|p| !auto_only || !p.flags.explicit)
+        .filter(|p| {
+            filter
+                .as_ref()
+                .is_none_or(|pattern| pattern.match_path(p.meta.name.as_ref()).is_some())
+        })
+        .collect::<Vec<_>>();
 
     let sync_available = if sync.is_some() {
         client.registry.list(Flags::new().with_available()).collect::<Vec<_>>()
@@ -81,29 +170,59 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         return Err(Error::NoneFound);
     }
 
+    let is_installed = filter_flags.installed;
+    // Packages predating the installed size metadata are summed from their assets
+    let sizes = if is_installed {
+        space::installed_sizes(
+            &client,
+            pkgs.iter().filter(|p| p.meta.installed_size.is_none()).map(|p| &p.id),
+        )?
+    } else {
+        BTreeMap::new()
+    };
+    let dates = if is_installed && matches!(sort, Sort::Date) {
+        install_dates(&client, as_of)?
+    } else {
+        BTreeMap::new()
+    };
+
     // map to renderable state
     let mut set = pkgs
         .into_iter()
         .map(|p| {
-            let sync = sync_available
+            let candidate = sync_available
                 .iter()
                 // Get first (priority based)
                 .find(|u| u.meta.name == p.meta.name)
                 // Ensure it's an upgrade (if `upgrades-only`)
                 // otherwise check if it's a change
-                .filter(|u| {
-                    if matches!(sync, Some(Sync::Upgrades)) {
-                        u.meta.source_release > p.meta.source_release
-                    } else {
-                        u.meta.source_release != p.meta.source_release
+                .filter(|u| match sync {
+                    Some(Sync::Upgrades) => u.meta.source_release > p.meta.source_release,
+                    Some(Sync::Security) => {
+                        u.meta.source_release != p.meta.source_release && u.meta.is_security_update(&p.meta)
                     }
-                })
-                .map(|u| Revision {
-                    version: u.meta.version_identifier.clone(),
-                    release: u.meta.source_release.to_string(),
+                    _ => u.meta.source_release != p.meta.source_release,
                 });
+            // Fixes of skipped releases count towards the update
+            let classification = candidate.and_then(|u| {
+                if u.meta.is_security_update(&p.meta) {
+                    Some(Classification::Security)
+                } else {
+                    u.meta.classification
+                }
+            });
+            let sync = candidate.map(|u| Revision {
+                version: u.meta.version_identifier.clone(),
+                release: u.meta.source_release.to_string(),
+            });
 
             Format {
+                bytes: if is_installed {
+                    p.meta.installed_size.or_else(|| sizes.get(&p.id).copied())
+                } else {
+                    p.meta.download_size
+                },
+                date: dates.get(&p.meta.name).copied(),
                 name: p.meta.name.to_string(),
                 revision: Revision {
                     version: p.meta.version_identifier,
@@ -116,6 +235,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
                     true
                 },
                 sync,
+                classification,
             }
         })
         .filter(|item| if sync.is_some() { item.sync.is_some() } else { true })
@@ -126,6 +246,12 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     set.sort_by_key(|s| s.name.clone());
     set.dedup_by_key(|s| s.name.clone());
 
+    match sort {
+        Sort::Name => {}
+        Sort::Size => set.sort_by_key(|s| std::cmp::Reverse(s.bytes)),
+        Sort::Date => set.sort_by_key(|s| std::cmp::Reverse(s.date)),
+    }
+
     // Grab maximum length
     let max_length = set.iter().map(Format::size).max().unwrap_or_default() + 2;
 
@@ -157,19 +283,78 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
             print_revision(sync, true);
         }
 
+        // Print size
+        let size = item
+            .bytes
+            .map(|bytes| HumanBytes(bytes).to_string())
+            .unwrap_or_default();
+        print!(" {}", format!("{size:>10}").dim());
+
+        // Highlight what the sync brings
+        match item.classification {
+            Some(Classification::Security) => print!(" {}", "[security]".red().bold()),
+            Some(classification) => print!(" {}", format!("[{classification}]").dim()),
+            None => {}
+        }
+
         println!(" - {}", item.summary);
     }
 
     Ok(())
 }
 
+/// Date each package was first installed, as recorded by the oldest state selecting it
+///
+/// Keyed by name, as updating a package replaces its id but doesn't make it newly
+/// installed. Only states up to `as_of` are considered, if given.
+fn install_dates(client: &Client, as_of: Option<state::Id>) -> Result<BTreeMap<package::Name, DateTime<Utc>>, Error> {
+    let names = client
+        .install_db
+        .query(None)?
+        .into_iter()
+        .map(|(id, meta)| (id, meta.name))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut dates = BTreeMap::new();
+
+    for state in client
+        .state_db
+        .all()?
+        .into_iter()
+        .filter(|state| as_of.is_none_or(|id| state.id <= id))
+        .sorted_by_key(|state| state.id)
+    {
+        let current = state
+            .selections
+            .iter()
+            .filter_map(|s| names.get(&s.package).cloned())
+            .collect::<BTreeSet<_>>();
+
+        // Forget packages that were removed, so a reinstall resets its date
+        dates.retain(|name, _| current.contains(name));
+
+        for name in current {
+            if let btree_map::Entry::Vacant(entry) = dates.entry(name) {
+                entry.insert(state.created);
+            }
+        }
+    }
+
+    Ok(dates)
+}
+
 #[derive(Debug)]
 struct Format {
+    /// Installed or download size in bytes
+    bytes: Option<u64>,
+    date: Option<DateTime<Utc>>,
     name: String,
     summary: String,
     revision: Revision,
     explicit: bool,
     sync: Option<Revision>,
+    /// What the sync brings, if classified
+    classification: Option<Classification>,
 }
 
 impl Format {
@@ -196,4 +381,8 @@ pub enum Error {
     NoneFound,
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+    #[error("invalid filter")]
+    Filter(#[from] fnmatch::Error),
 }
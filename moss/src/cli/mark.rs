@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command, arg};
+use moss::{
+    Installation, Provider,
+    client::{self, Client},
+    environment,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("mark")
+        .about("Record why packages are installed")
+        .long_about(
+            "Record why packages are installed
+
+The reason is kept with the packages in the active state & shown by `info`, `why` and `state query`. \
+An empty reason clears it.",
+        )
+        .arg(arg!(<NAME> ... "Installed packages to mark").value_parser(clap::value_parser!(String)))
+        .arg(arg!(-r --reason <REASON> "Why the packages are installed").required(true))
+}
+
+/// Handle execution of `moss mark`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let reason = args
+        .get_one::<String>("reason")
+        .map(|reason| reason.trim())
+        .filter(|reason| !reason.is_empty());
+
+    let client = Client::new(environment::NAME, installation)?;
+    let state = client.installation.active_state.ok_or(Error::NoActiveState)?;
+
+    let installed = client.registry.list_installed().collect::<Vec<_>>();
+
+    // Resolve all names first, so nothing is marked if any is wrong
+    let packages = names
+        .iter()
+        .map(|name| {
+            let lookup = Provider::from_name(name).map_err(|_| Error::NotInstalled((*name).clone()))?;
+            installed
+                .iter()
+                .find(|pkg| pkg.meta.providers.contains(&lookup) || pkg.meta.name.as_ref() == name.as_str())
+                .ok_or_else(|| Error::NotInstalled((*name).clone()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for package in packages {
+        let name = package.meta.name.to_string();
+
+        if !client.state_db.set_reason(state, &package.id, reason)? {
+            return Err(Error::NotInstalled(name));
+        }
+
+        match reason {
+            Some(reason) => println!("{} marked: {reason}", name.bold()),
+            None => println!("Reason of {} cleared", name.bold()),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
+    #[error("no active state to mark packages in")]
+    NoActiveState,
+
+    #[error("no installed package found: {0}")]
+    NotInstalled(String),
+}
@@ -2,34 +2,118 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{env, fs, io, path::Path, path::PathBuf};
+use std::{
+    env, fs, io,
+    path::Path,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use clap::{Arg, ArgAction, Command};
-use clap_complete::{
-    generate_to,
-    shells::{Bash, Fish, Zsh},
+use clap::{
+    Arg, ArgAction, ArgMatches, Command,
+    builder::{PossibleValuesParser, TypedValueParser},
 };
+use clap_complete::{Generator, Shell, generate};
 use clap_mangen::Man;
-use moss::{Installation, installation};
+use moss::{
+    Client, Installation,
+    client::{self, divergence, journal, metrics, pending},
+    installation, prompt,
+    registry::transaction,
+    release,
+    repository::{self, expiry, local},
+    request,
+    settings::{self, Settings},
+    system_model::{self, template},
+};
 use thiserror::Error;
-use tracing_common::{self, logging::LogConfig, logging::init_log_with_config};
+use tracing_common::{
+    self,
+    logging::{self, LogConfig, init_log_with_config},
+    watchdog,
+};
 use tui::Styled;
 
+mod alias;
+mod audit;
 mod boot;
 mod cache;
+mod clean;
+mod command_not_found;
+mod complete;
+mod config;
+mod create;
+mod diff_root;
+mod export;
 mod extract;
+mod generate_units;
 mod index;
 mod info;
 mod inspect;
 mod install;
 mod list;
+mod mark;
+mod model;
+mod pack;
+mod provides;
 mod remove;
 mod repo;
 mod search;
 mod search_file;
+mod self_update;
+mod setup;
 mod state;
 mod sync;
+mod timings;
+mod trigger;
 mod version;
+mod why;
+
+/// Documented exit codes, rendered into `--help` and the manpage
+const EXIT_STATUS_HELP: &str = "Exit status:
+  0  Success, including when there's nothing to do
+  1  General failure
+  2  Invalid usage
+  3  Package resolution failure
+  4  Network failure
+  5  Cancelled by the user
+  6  Verification failure
+  7  Input required but running non-interactively
+  8  Nothing to do, only with --exit-code";
+
+/// Process exit codes for scripting, see [`EXIT_STATUS_HELP`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    Failure = 1,
+    Resolution = 3,
+    Network = 4,
+    Cancelled = 5,
+    Verification = 6,
+    NonInteractive = 7,
+    NothingToDo = 8,
+}
+
+/// Successful outcome of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The command made changes or completed its query
+    Done,
+    /// The system is already in the requested state
+    NothingToDo,
+    /// The compared trees differ
+    Differs,
+}
+
+impl From<Outcome> for ExitCode {
+    fn from(outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Done => ExitCode::Success,
+            Outcome::NothingToDo => ExitCode::NothingToDo,
+            Outcome::Differs => ExitCode::Verification,
+        }
+    }
+}
 
 /// Generate the CLI command structure
 fn command() -> Command {
@@ -41,7 +125,11 @@ fn command() -> Command {
                 .long("verbose")
                 .global(true)
                 .help("Prints additional information about what moss is doing")
-                .action(ArgAction::SetTrue),
+                .long_help(
+                    "Prints additional information about what moss is doing\n\n\
+                     Repeat to enable logging to stderr: -vv for debug, -vvv for trace",
+                )
+                .action(ArgAction::Count),
         )
         .arg(
             Arg::new("version")
@@ -65,18 +153,45 @@ fn command() -> Command {
             Arg::new("cache")
                 .long("cache")
                 .global(true)
-                .help("Cache directory")
-                .action(ArgAction::Set)
+                .help("Cache directory, repeat to layer read-only shared caches below it")
+                .action(ArgAction::Append)
                 .value_parser(clap::value_parser!(PathBuf)),
         )
         .arg(
             Arg::new("log")
                 .long("log")
-                .help("Logging configuration: <level>[:<format>][:<destination>]\nLevels: trace, debug, info, warn, error\nFormats: text, json\nDestinations: stderr, <file>")
+                .help("Logging configuration: <level>[:<target>=<level>...][:<format>][:<destination>]\nLevels: trace, debug, info, warn, error, off\nTargets: i.e. moss::registry=trace,stone=debug\nFormats: text, json\nDestinations: stderr, <file>")
                 .action(ArgAction::Set)
                 .global(true)
                 .value_parser(clap::value_parser!(LogConfig)),
         )
+        .arg(
+            Arg::new("arch")
+                .long("arch")
+                .global(true)
+                .value_name("ARCH")
+                .help("Architecture of the root, i.e. to provision a root of another architecture with -D")
+                .long_help(
+                    "Architecture of the root, i.e. to provision a root of another architecture with -D\n\n\
+                     Only packages built for this architecture are candidates. The architecture is recorded \
+                     in the root, so later use of it keeps to it.",
+                )
+                .action(ArgAction::Set)
+                .value_parser(["x86_64", "x86", "aarch64", "riscv64"]),
+        )
+        .arg(
+            Arg::new("define")
+                .long("define")
+                .global(true)
+                .value_name("KEY=VALUE")
+                .help("Define a variable for a templated system-model, repeatable")
+                .long_help(
+                    "Define a variable for a templated system-model, repeatable\n\n\
+                     `hostname` & `arch` are defined for the root already, and may be overridden.",
+                )
+                .action(ArgAction::Append)
+                .value_parser(parse_define),
+        )
         .arg(
             Arg::new("yes")
                 .short('y')
@@ -85,6 +200,107 @@ fn command() -> Command {
                 .help("Assume yes for all questions")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .global(true)
+                .value_name("WHEN")
+                .help("When to color output, overriding NO_COLOR & the color setting")
+                .value_parser(
+                    PossibleValuesParser::new(["auto", "always", "never"])
+                        .map(|when| match when.as_str() {
+                            "always" => settings::Color::Always,
+                            "never" => settings::Color::Never,
+                            _ => settings::Color::Auto,
+                        }),
+                ),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .help("Only print summaries & errors, without progress or informational output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("timings")
+                .long("timings")
+                .global(true)
+                .help("Print how long each phase (resolve, fetch, blit, triggers, ...) took once done")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("chrome-trace")
+                .long("chrome-trace")
+                .global(true)
+                .value_name("FILE")
+                .help("Save the timings of each phase as a Chrome trace, i.e. for chrome://tracing or Perfetto")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("non-interactive")
+                .long("non-interactive")
+                .global(true)
+                .help("Fail instead of prompting for input (implied when stdin is not a terminal)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .global(true)
+                .help("Wait for another moss process changing the installation to finish, rather than failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("exit-code")
+                .long("exit-code")
+                .global(true)
+                .help("Exit with 8 rather than 0 when there's nothing to do, i.e. no updates to apply")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("apply-on-reboot")
+                .long("apply-on-reboot")
+                .global(true)
+                .help("Stage new states to be applied on the next reboot, rather than changing /usr live")
+                .long_help(
+                    "Stage new states to be applied on the next reboot, rather than changing /usr live. \
+                     The new state is booted into next, once the boot loader entries are regenerated.
+
+This is the default when /usr is mounted read-only or the root is an immutable OS.",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stall-timeout")
+                .long("stall-timeout")
+                .global(true)
+                .value_name("SECONDS")
+                .help("Report work making no progress for this long, 0 to disable")
+                .long_help(
+                    "Report work making no progress for this long, 0 to disable\n\n\
+                     Downloads, unpacking, blitting & triggers are watched. A stall reports what is being \
+                     waited on, i.e. the URL, directory or trigger, so a hang can be told apart from slow work.",
+                )
+                .action(ArgAction::Set)
+                .default_value("30")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("limit-rate")
+                .long("limit-rate")
+                .global(true)
+                .value_name("RATE")
+                .help("Limit downloads to this many bytes per second, i.e. 500K or 2M")
+                .long_help(
+                    "Limit downloads to this many bytes per second, i.e. 500K or 2M\n\n\
+                     The limit is shared by all concurrent downloads. Defaults to `limit_rate` of the \
+                     download config (/etc/moss/download.d/*.yaml), 0 lifts it.",
+                )
+                .action(ArgAction::Set)
+                .value_parser(clap::value_parser!(request::Rate)),
+        )
         .arg(
             Arg::new("generate-manpages")
                 .long("generate-manpages")
@@ -101,22 +317,40 @@ fn command() -> Command {
                 .value_name("DIR")
                 .hide(true),
         )
+        .after_long_help(EXIT_STATUS_HELP)
         .arg_required_else_help(true)
+        .subcommand(audit::command())
         .subcommand(boot::command())
         .subcommand(cache::command())
+        .subcommand(clean::command())
+        .subcommand(command_not_found::command())
+        .subcommand(complete::command())
+        .subcommand(config::command())
+        .subcommand(create::command())
+        .subcommand(diff_root::command())
+        .subcommand(export::command())
         .subcommand(extract::command())
+        .subcommand(generate_units::command())
         .subcommand(index::command())
         .subcommand(info::command())
         .subcommand(inspect::command())
         .subcommand(install::command())
         .subcommand(list::command())
+        .subcommand(mark::command())
+        .subcommand(model::command())
+        .subcommand(pack::command())
+        .subcommand(provides::command())
         .subcommand(remove::command())
         .subcommand(repo::command())
         .subcommand(search::command())
         .subcommand(search_file::command())
+        .subcommand(self_update::command())
+        .subcommand(setup::command())
         .subcommand(state::command())
         .subcommand(sync::command())
+        .subcommand(trigger::command())
         .subcommand(version::command())
+        .subcommand(why::command())
 }
 
 /// Generate manpages for all commands recursively
@@ -145,17 +379,40 @@ fn generate_manpages(cmd: &Command, dir: &Path, prefix: Option<&str>) -> io::Res
     Ok(())
 }
 
-/// Generate shell completions
+/// Generate shell completions, including the [`complete`] dynamic ones
 fn generate_completions(cmd: &mut Command, dir: &Path) -> io::Result<()> {
-    generate_to(Bash, cmd, "moss", dir)?;
-    generate_to(Fish, cmd, "moss", dir)?;
-    generate_to(Zsh, cmd, "moss", dir)?;
+    for shell in [Shell::Bash, Shell::Fish, Shell::Zsh] {
+        let mut buffer = vec![];
+        generate(shell, cmd, "moss", &mut buffer);
+
+        let mut script =
+            String::from_utf8(buffer).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        complete::extend(shell, cmd, &mut script);
+
+        fs::write(dir.join(shell.file_name("moss")), script)?;
+    }
     Ok(())
 }
 
+/// Report the timings of the phases of the command, if requested with `--timings` or `--chrome-trace`
+pub fn report_timings() {
+    timings::report();
+}
+
+/// Parse a `KEY=VALUE` definition of a system-model variable
+fn parse_define(value: &str) -> Result<(String, String), String> {
+    let (name, value) = value.split_once('=').ok_or("expected KEY=VALUE")?;
+
+    if !template::is_name(name) {
+        return Err(format!("invalid variable name {name:?}"));
+    }
+
+    Ok((name.to_owned(), value.to_owned()))
+}
+
 /// Process all CLI arguments
-pub fn process() -> Result<(), Error> {
-    let args = replace_aliases(env::args());
+pub fn process() -> Result<Outcome, Error> {
+    let args = alias::Aliases::load().replace(&command(), env::args().collect());
     let matches = command().get_matches_from(args);
 
     let show_version = matches.get_one::<bool>("version").is_some_and(|v| *v);
@@ -164,101 +421,445 @@ pub fn process() -> Result<(), Error> {
         println!("moss {}", tools_buildinfo::get_full_version());
     }
 
+    if matches.get_flag("non-interactive") {
+        prompt::set_non_interactive(true);
+    }
+
+    let root = matches.get_one::<PathBuf>("root").unwrap();
+    let settings = Settings::load(root);
+
+    // An explicit --color wins over NO_COLOR, which wins over the configured default
+    let color = match matches.get_one::<settings::Color>("color") {
+        Some(color) => Some(*color),
+        None if tui::no_color() => Some(settings::Color::Never),
+        None => settings.color,
+    };
+    match color {
+        Some(settings::Color::Always) => tui::set_color(true),
+        Some(settings::Color::Never) => tui::set_color(false),
+        Some(settings::Color::Auto) | None => {}
+    }
+
+    tui::set_quiet(matches.get_flag("quiet"));
+
     if let Some(log_config) = matches.get_one::<LogConfig>("log") {
         init_log_with_config(log_config.clone());
+    } else if let Some(level) =
+        logging::verbosity_level(matches.get_count("verbose")).or_else(|| settings.log_level.map(Into::into))
+    {
+        logging::init_log(logging::OutputFormat::Text, level, logging::OutputDestination::Stderr);
+    }
+
+    let chrome_trace = matches.get_one::<PathBuf>("chrome-trace").cloned();
+    if matches.get_flag("timings") || chrome_trace.is_some() {
+        timings::enable(matches.get_flag("timings"), chrome_trace);
+    }
+
+    let stall_timeout = *matches.get_one::<u64>("stall-timeout").unwrap();
+    if stall_timeout > 0 {
+        watchdog::spawn(Duration::from_secs(stall_timeout), report_stall);
     }
 
     if let Some(dir) = matches.get_one::<String>("generate-manpages") {
         let dir = Path::new(dir);
         fs::create_dir_all(dir)?;
         generate_manpages(&command(), dir, None)?;
-        return Ok(());
+        return Ok(Outcome::Done);
     }
 
     if let Some(dir) = matches.get_one::<String>("generate-completions") {
         let dir = Path::new(dir);
         fs::create_dir_all(dir)?;
         generate_completions(&mut command(), dir)?;
-        return Ok(());
+        return Ok(Outcome::Done);
     }
 
     // Print the version, but not if the user is using the version subcommand
-    if matches.get_flag("verbose")
+    if matches.get_count("verbose") > 0
         && let Some(command) = matches.subcommand_name()
         && command != "version"
     {
         version::print();
     }
 
-    let root = matches.get_one::<PathBuf>("root").unwrap();
-    let cache = matches.get_one::<PathBuf>("cache");
+    let cache = matches.get_many::<PathBuf>("cache").into_iter().flatten().cloned();
+
+    let mut variables = template::Variables::default();
+    variables.extend(
+        matches
+            .get_many::<(String, String)>("define")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+
+    if let Some(arch) = matches.get_one::<String>("arch") {
+        variables.define("arch", arch);
+    }
+
+    // Changes can't wait on another moss unnoticed, queries keep waiting for a consistent view
+    let mutation = is_mutation(&matches);
+    installation::lockfile::set_fail_if_held(mutation && !matches.get_flag("wait"));
+    // Queries use the existing index of local directory repositories
+    local::set_reindex(mutation);
 
-    let installation = Installation::open(root, cache.cloned())?;
+    // Suggestions for a missing command must never wait on, or be refused by, another moss
+    let mut installation = if matches.subcommand_name() == Some("command-not-found") {
+        Installation::open_read_only(root, cache, &variables)?
+    } else {
+        Installation::open_with_variables(root, cache, &variables)?
+    };
+    installation.apply_on_reboot |= matches.get_flag("apply-on-reboot");
 
-    if installation.system_model.is_some() {
+    if installation.system_model.is_some() && !tui::is_quiet() {
         print_system_model_warning(&installation);
     }
 
+    let mut download = ::config::Manager::system(&installation.root, "moss")
+        .load::<request::Config>()
+        .pop()
+        .unwrap_or_default();
+    if let Some(rate) = matches.get_one::<request::Rate>("limit-rate") {
+        download.limit_rate = Some(*rate);
+    }
+    request::configure(download);
+    if let Some(downloads) = settings.parallel_downloads {
+        request::set_concurrency(downloads);
+    }
+
+    if let Some(mode) = &installation.read_only_mode
+        && mutation
+    {
+        if let Some(guidance) = &mode.guidance {
+            eprintln!("{}: {guidance}", "INFO".green());
+        }
+        return Err(Error::ReadOnlyMode(mode.marker.clone()));
+    }
+
+    if installation.mounts == installation::Mounts::ReadOnly && mutation {
+        return Err(Error::ReadOnlyRoot(installation.root.clone()));
+    }
+
+    // A transaction interrupted by a crash is recovered before making other changes
+    if mutation
+        && !matches!(
+            matches.subcommand(),
+            Some(("state", args)) if matches!(args.subcommand_name(), Some("complete" | "repair"))
+        )
+        && journal::is_pending(&installation)
+    {
+        eprintln!(
+            "{}: recovering a transaction interrupted by a crash",
+            "Warning".yellow()
+        );
+        state::recover(&installation).map_err(Error::State)?;
+    }
+
+    // As is the activation of a state applied on reboot, once booted into
+    if mutation
+        && !matches!(
+            matches.subcommand(),
+            Some(("state", args)) if matches!(args.subcommand_name(), Some("complete" | "repair"))
+        )
+        && pending::is_booted(&installation)
+    {
+        state::complete_pending(&installation).map_err(Error::State)?;
+    }
+
+    // Transactions are recorded once the command is done, successful ones with the state the client applied
+    let transaction = transaction_operation(&matches);
+    let (metrics_installation, started) = (installation.clone(), Instant::now());
+
+    let result = dispatch(&matches, installation, show_version);
+
+    let applied = metrics::take_applied();
+    let outcome = match (&result, applied) {
+        (Ok(_), Some((operation, state))) => Some((operation, metrics::Outcome::Success(state))),
+        (Err(error), applied) if error.exit_code() != ExitCode::Cancelled => transaction
+            .map(ToOwned::to_owned)
+            .or(applied.map(|(operation, _)| operation))
+            .map(|operation| (operation, metrics::Outcome::Failure)),
+        _ => None,
+    };
+    if let Some((operation, outcome)) = outcome {
+        metrics::record(&metrics_installation, &operation, outcome, started.elapsed());
+    }
+
+    // Nothing to do is a success, unless scripts asked to tell it apart
+    match result {
+        Ok(Outcome::NothingToDo) if !matches.get_flag("exit-code") => Ok(Outcome::Done),
+        result => result,
+    }
+}
+
+/// Run the invoked subcommand
+fn dispatch(matches: &ArgMatches, installation: Installation, show_version: bool) -> Result<Outcome, Error> {
     match matches.subcommand() {
-        Some(("boot", args)) => boot::handle(args, installation).map_err(Error::Boot),
-        Some(("cache", args)) => cache::handle(args, installation).map_err(Error::Cache),
-        Some(("extract", args)) => extract::handle(args).map_err(Error::Extract),
-        Some(("index", args)) => index::handle(args).map_err(Error::Index),
-        Some(("info", args)) => info::handle(args, installation).map_err(Error::Info),
-        Some(("inspect", args)) => inspect::handle(args).map_err(Error::Inspect),
-        Some(("install", args)) => install::handle(args, installation).map_err(Error::Install),
-        Some(("list", args)) => list::handle(args, installation).map_err(Error::List),
-        Some(("remove", args)) => remove::handle(args, installation).map_err(Error::Remove),
-        Some(("repo", args)) => repo::handle(args, installation).map_err(Error::Repo),
-        Some(("search", args)) => search::handle(args, installation).map_err(Error::Search),
-        Some(("search-file", args)) => search_file::handle(args, installation).map_err(Error::SearchFile),
-        Some(("state", args)) => state::handle(args, installation).map_err(Error::State),
-        Some(("sync", args)) => sync::handle(args, installation).map_err(Error::Sync),
-        Some(("version", args)) => {
-            version::handle(args);
-            Ok(())
+        Some(("audit", args)) => audit::handle(args, installation).map_err(Error::Audit)?,
+        Some(("boot", args)) => boot::handle(args, installation).map_err(Error::Boot)?,
+        Some(("cache", args)) => cache::handle(args, installation).map_err(Error::Cache)?,
+        Some(("clean", args)) => clean::handle(args, installation).map_err(Error::Clean)?,
+        Some(("command-not-found", args)) => {
+            return command_not_found::handle(args, installation).map_err(Error::CommandNotFound);
         }
+        Some(("__complete", args)) => complete::handle(args, installation).map_err(Error::Complete)?,
+        Some(("config", args)) => config::handle(args, installation).map_err(Error::Config)?,
+        Some(("create", args)) => create::handle(args, installation).map_err(Error::Create)?,
+        Some(("diff-root", args)) => return diff_root::handle(args, installation).map_err(Error::DiffRoot),
+        Some(("export", args)) => export::handle(args, installation).map_err(Error::Export)?,
+        Some(("extract", args)) => extract::handle(args).map_err(Error::Extract)?,
+        Some(("generate-units", args)) => generate_units::handle(args).map_err(Error::GenerateUnits)?,
+        Some(("index", args)) => index::handle(args, installation).map_err(Error::Index)?,
+        Some(("info", args)) => info::handle(args, installation).map_err(Error::Info)?,
+        Some(("inspect", args)) => inspect::handle(args, installation).map_err(Error::Inspect)?,
+        Some(("install", args)) => return install::handle(args, installation).map_err(Error::Install),
+        Some(("list", args)) => list::handle(args, installation).map_err(Error::List)?,
+        Some(("mark", args)) => mark::handle(args, installation).map_err(Error::Mark)?,
+        Some(("model", args)) => model::handle(args, installation).map_err(Error::Model)?,
+        Some(("pack", args)) => pack::handle(args).map_err(Error::Pack)?,
+        Some(("provides", args)) => provides::handle(args, installation).map_err(Error::Provides)?,
+        Some(("remove", args)) => remove::handle(args, installation).map_err(Error::Remove)?,
+        Some(("repo", args)) => repo::handle(args, installation).map_err(Error::Repo)?,
+        Some(("search", args)) => search::handle(args, installation).map_err(Error::Search)?,
+        Some(("search-file", args)) => search_file::handle(args, installation).map_err(Error::SearchFile)?,
+        Some(("self-update", args)) => return self_update::handle(args, installation).map_err(Error::SelfUpdate),
+        Some(("setup", args)) => setup::handle(args, installation).map_err(Error::Setup)?,
+        Some(("state", args)) => state::handle(args, installation).map_err(Error::State)?,
+        Some(("sync", args)) => return sync::handle(args, installation).map_err(Error::Sync),
+        Some(("trigger", args)) => trigger::handle(args, installation).map_err(Error::Trigger)?,
+        Some(("version", args)) => version::handle(args, installation).map_err(Error::Version)?,
+        Some(("why", args)) => why::handle(args, installation).map_err(Error::Why)?,
         None => {
             if !show_version {
                 command().print_help().unwrap();
             }
-            Ok(())
         }
         _ => unreachable!(),
     }
+
+    Ok(Outcome::Done)
+}
+
+/// Arguments deciding what happens to an active system-model on a direct install or removal
+fn system_model_change_args() -> [Arg; 2] {
+    [
+        Arg::new("update-model")
+            .long("update-model")
+            .action(ArgAction::SetTrue)
+            .help("Also record the change in the active system-model")
+            .conflicts_with("ephemeral-change"),
+        Arg::new("ephemeral-change")
+            .long("ephemeral-change")
+            .action(ArgAction::SetTrue)
+            .help("Accept the change will be reverted by the next sync of the active system-model"),
+    ]
 }
 
-fn replace_aliases(args: env::Args) -> Vec<String> {
-    const ALIASES: &[(&str, &[&str])] = &[
-        ("li", &["list", "installed"]),
-        ("la", &["list", "available"]),
-        ("ls", &["list", "sync"]),
-        ("lu", &["list", "sync"]),
-        ("ar", &["repo", "add"]),
-        ("lr", &["repo", "list"]),
-        ("rr", &["repo", "remove"]),
-        ("ur", &["repo", "update"]),
-        ("er", &["repo", "enable"]),
-        ("dr", &["repo", "disable"]),
-        ("ix", &["index"]),
-        ("it", &["install"]),
-        ("rm", &["remove"]),
-        ("up", &["sync"]),
-    ];
-
-    let mut args = args.collect::<Vec<_>>();
-
-    for (alias, replacements) in ALIASES {
-        let Some(pos) = args.iter().position(|a| a == *alias) else {
+/// Arguments deciding what a transaction does with the files of `/usr` changed at runtime,
+/// see [`resolve_divergence`]
+fn divergence_args() -> [Arg; 2] {
+    [
+        Arg::new("keep-local")
+            .long("keep-local")
+            .value_name("PATH")
+            .num_args(0..=1)
+            .require_equals(true)
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Carry files of /usr changed at runtime over to the new state, or only PATH")
+            .long_help(
+                "Carry files of /usr changed at runtime over to the new state, rather than resetting them \
+                 to the files of the new state. Given a PATH, only that file is kept, repeat to keep several.",
+            ),
+        Arg::new("reset")
+            .long("reset")
+            .value_name("PATH")
+            .num_args(0..=1)
+            .require_equals(true)
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Reset files of /usr changed at runtime to those of the new state, or only PATH"),
+    ]
+}
+
+/// Report the files of `/usr` changed since the active state was applied, deciding per
+/// [`divergence_args`] which the transaction carries over to the new state
+///
+/// Files neither kept nor reset explicitly are reset once confirmed.
+fn resolve_divergence(
+    client: &Client,
+    args: &ArgMatches,
+    yes: bool,
+) -> Result<Vec<divergence::Divergence>, client::Error> {
+    let diverged = client.divergence()?;
+
+    let selected = |id: &str| {
+        args.contains_id(id).then(|| {
+            args.get_many::<PathBuf>(id)
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+    };
+    let keep = selected("keep-local");
+    let reset = selected("reset");
+
+    for path in keep.iter().chain(&reset).flatten() {
+        if !diverged.iter().any(|divergence| divergence.matches(path)) {
+            println!("{}: {} wasn't changed at runtime", "Warning".yellow(), path.display());
+        }
+    }
+
+    if diverged.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // A path given explicitly wins over keeping or resetting all files
+    let named = |paths: &Option<Vec<PathBuf>>, divergence: &divergence::Divergence| {
+        paths.iter().flatten().any(|path| divergence.matches(path))
+    };
+    let all = |paths: &Option<Vec<PathBuf>>| paths.as_ref().is_some_and(Vec::is_empty);
+    let (kept, rest): (Vec<_>, Vec<_>) = diverged
+        .into_iter()
+        .partition(|divergence| named(&keep, divergence) || (all(&keep) && !named(&reset, divergence)));
+    let undecided = rest
+        .iter()
+        .filter(|divergence| !named(&reset, divergence) && !all(&reset))
+        .collect::<Vec<_>>();
+
+    if !kept.is_empty() {
+        println!("The following files changed at runtime will be kept: ");
+        println!();
+        for divergence in &kept {
+            println!("  {} {divergence}", "~".green());
+        }
+        println!();
+    }
+
+    if !undecided.is_empty() {
+        println!(
+            "{}: the following files were changed at runtime & will be reset to those of the new state: ",
+            "Warning".yellow()
+        );
+        println!();
+        for divergence in &undecided {
+            println!("  {} {divergence}", "~".yellow());
+        }
+        println!();
+        println!(
+            "Pass {} to carry them over to the new state, or {} to reset them",
+            "--keep-local[=PATH]".bold(),
+            "--reset[=PATH]".bold()
+        );
+
+        if !prompt::confirm("reset files changed at runtime", " Reset them? ", yes)? {
+            return Err(client::Error::Cancelled);
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Whether a direct change should also be recorded in the active system-model, see
+/// [`system_model_change_args`]
+///
+/// Fails if the user chose neither.
+fn update_model(args: &ArgMatches, installation: &Installation) -> Result<bool, system_model::UnrecordedChange> {
+    if installation.system_model.is_none() || args.get_flag("ephemeral-change") {
+        Ok(false)
+    } else if args.get_flag("update-model") {
+        Ok(true)
+    } else {
+        Err(system_model::UnrecordedChange(installation.system_model_path()))
+    }
+}
+
+/// Tell the user what a stalled operation is waiting on & whether it can be cancelled
+fn report_stall(stall: &watchdog::Stall) {
+    let (doing, cancel) = match stall.phase {
+        "fetch" => ("downloading", true),
+        "unpack" => ("unpacking", true),
+        "blit" => ("blitting", false),
+        "trigger" => ("running trigger", false),
+        phase => (phase, false),
+    };
+    let hint = if cancel {
+        "press Ctrl-C to cancel"
+    } else {
+        "this step can't be safely interrupted"
+    };
+
+    eprintln!(
+        "{}: no progress for {}s {doing} {}, {hint}",
+        "Warning".yellow(),
+        stall.idle.as_secs(),
+        stall.waiting_on.as_str().bold(),
+    );
+}
+
+/// Warn about every repository whose metadata is stale or expired
+fn warn_stale_metadata(client: &Client) {
+    for (id, record) in client.repository_freshness() {
+        let Some(record) = record.filter(expiry::Record::is_stale) else {
             continue;
         };
 
-        args.splice(pos..pos + 1, replacements.iter().map(|&arg| arg.to_owned()));
+        if record.is_expired() {
+            eprintln!(
+                "{}: metadata of repository {} has expired, the mirror may be frozen or replaying old metadata, \
+                 run {} to refresh it",
+                "Warning".yellow(),
+                id.to_string().bold(),
+                "moss sync -u".bold(),
+            );
+        } else {
+            eprintln!(
+                "{}: metadata of repository {} is {} old, run {} to refresh it",
+                "Warning".yellow(),
+                id.to_string().bold(),
+                expiry::Human(record.age()),
+                "moss sync -u".bold(),
+            );
+        }
+    }
+}
 
-        break;
+/// Operation of the invoked subcommand, if it's a transaction creating a new state
+fn transaction_operation(matches: &ArgMatches) -> Option<&'static str> {
+    match matches.subcommand() {
+        Some(("install", args)) if !args.contains_id("to") => Some("install"),
+        Some(("remove", _)) => Some("remove"),
+        Some(("sync", _)) => Some("sync"),
+        Some(("state", args)) if matches!(args.subcommand_name(), Some("activate" | "apply-staged")) => {
+            Some("activate")
+        }
+        _ => None,
     }
+}
 
-    args
+/// Whether the invoked subcommand changes the installation, as declared by each subcommand
+fn is_mutation(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("boot", args)) => boot::is_mutation(args),
+        Some(("cache", args)) => cache::is_mutation(args),
+        Some(("config", args)) => config::is_mutation(args),
+        Some(("index", args)) => index::is_mutation(args),
+        Some(("install", args)) => install::is_mutation(args),
+        Some(("model", args)) => model::is_mutation(args),
+        Some(("repo", args)) => repo::is_mutation(args),
+        Some(("state", args)) => state::is_mutation(args),
+        Some(("trigger", args)) => trigger::is_mutation(args),
+        Some(("clean" | "mark" | "remove" | "self-update" | "setup" | "sync", _)) => true,
+        Some((
+            "audit" | "command-not-found" | "__complete" | "create" | "diff-root" | "export" | "extract"
+            | "generate-units" | "info" | "inspect" | "list" | "pack" | "provides" | "search" | "search-file"
+            | "version" | "why",
+            _,
+        )) => false,
+        None => false,
+        _ => unreachable!(),
+    }
 }
 
 fn print_system_model_warning(installation: &Installation) {
@@ -266,7 +867,8 @@ fn print_system_model_warning(installation: &Installation) {
         "{}: `{path:?}` is present & therefore active. This means that:
  
 - The system-model is the source of truth and defines all repositories & installed packages.
-- Any changes made via `moss` commands will be temporary until the system-model is updated.
+- Any changes made via `moss` commands will be temporary until the system-model is updated,
+  `moss install` & `moss remove` can do so with `--update-model`.
 - The system state can be reverted to match the system-model state with a `moss sync`.
 - To disable the system-model, either remove or rename `{path:?}`.",
         "INFO".green(),
@@ -274,14 +876,147 @@ fn print_system_model_warning(installation: &Installation) {
     );
 }
 
+impl Error {
+    /// Classify this error into an [`ExitCode`] by inspecting the source chain
+    pub fn exit_code(&self) -> ExitCode {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(self);
+
+        while let Some(error) = source {
+            if let Some(code) = classify(error) {
+                return code;
+            }
+            source = error.source();
+        }
+
+        ExitCode::Failure
+    }
+}
+
+/// Map a single error in the chain to its [`ExitCode`], if it denotes a failure class
+fn classify(error: &(dyn std::error::Error + 'static)) -> Option<ExitCode> {
+    if let Some(error) = error.downcast_ref::<prompt::Error>() {
+        return matches!(error, prompt::Error::NonInteractive(_)).then_some(ExitCode::NonInteractive);
+    }
+    if let Some(error) = error.downcast_ref::<install::Error>() {
+        return match error {
+            install::Error::Cancelled => Some(ExitCode::Cancelled),
+            install::Error::NoPackage(_) | install::Error::NoCommand(_) => Some(ExitCode::Resolution),
+            _ => None,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<remove::Error>() {
+        return match error {
+            remove::Error::Cancelled => Some(ExitCode::Cancelled),
+            remove::Error::NoSuchPackage
+            | remove::Error::Dependents(_)
+            | remove::Error::BootCritical(_)
+            | remove::Error::Essential(_) => Some(ExitCode::Resolution),
+            _ => None,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<sync::Error>() {
+        return match error {
+            sync::Error::Cancelled => Some(ExitCode::Cancelled),
+            sync::Error::MissingSystemModelPackage(_) | sync::Error::BootCritical(_) => Some(ExitCode::Resolution),
+            _ => None,
+        };
+    }
+    if let Some(self_update::Error::Cancelled) = error.downcast_ref() {
+        return Some(ExitCode::Cancelled);
+    }
+    if let Some(error) = error.downcast_ref::<client::Error>() {
+        return match error {
+            client::Error::Cancelled => Some(ExitCode::Cancelled),
+            client::Error::MissingMetadata(_) => Some(ExitCode::Resolution),
+            _ => None,
+        };
+    }
+    if let Some(client::prune::Error::Cancelled) = error.downcast_ref() {
+        return Some(ExitCode::Cancelled);
+    }
+    if let Some(
+        transaction::Error::NoCandidate(_)
+        | transaction::Error::Unsatisfiable { .. }
+        | transaction::Error::Conflict { .. },
+    ) = error.downcast_ref()
+    {
+        return Some(ExitCode::Resolution);
+    }
+    if let Some(error) = error.downcast_ref::<repository::manager::Error>() {
+        return match error {
+            // Boxed, so their source skips past the wrapped error itself
+            repository::manager::Error::Refresh(_, error) | repository::manager::Error::Validate(_, error) => {
+                classify(error.as_ref())
+            }
+            repository::manager::Error::FetchIndex(repository::FetchError::Request(request::Error::Fetch(_))) => {
+                Some(ExitCode::Network)
+            }
+            _ => None,
+        };
+    }
+    if let Some(client::cache::Error::Request(request::Error::Fetch(_))) = error.downcast_ref() {
+        return Some(ExitCode::Network);
+    }
+    if let Some(repository::FetchError::Request(request::Error::Fetch(_))) = error.downcast_ref() {
+        return Some(ExitCode::Network);
+    }
+    if let Some(request::Error::Fetch(_)) = error.downcast_ref() {
+        return Some(ExitCode::Network);
+    }
+    if error.is::<reqwest::Error>() {
+        return Some(ExitCode::Network);
+    }
+    if let Some(inspect::Error::ValidationFailed) = error.downcast_ref() {
+        return Some(ExitCode::Verification);
+    }
+    if let Some(release::Error::Checksum { .. } | release::Error::Signature(_)) = error.downcast_ref() {
+        return Some(ExitCode::Verification);
+    }
+    if let Some(state::Error::Unpublished(_)) = error.downcast_ref() {
+        return Some(ExitCode::Verification);
+    }
+    if let Some(audit::Error::Vulnerable(_)) = error.downcast_ref() {
+        return Some(ExitCode::Verification);
+    }
+    if let Some(stone::read::Error::PayloadChecksum { .. }) = error.downcast_ref() {
+        return Some(ExitCode::Verification);
+    }
+
+    None
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("audit")]
+    Audit(#[from] audit::Error),
+
     #[error("boot")]
     Boot(#[from] boot::Error),
 
     #[error("cache")]
     Cache(#[from] cache::Error),
 
+    #[error("clean")]
+    Clean(#[from] clean::Error),
+
+    #[error("command-not-found")]
+    CommandNotFound(#[from] command_not_found::Error),
+
+    #[error("complete")]
+    Complete(#[from] complete::Error),
+
+    #[error("config")]
+    Config(#[from] config::Error),
+
+    #[error("create")]
+    Create(#[from] create::Error),
+
+    #[error("diff-root")]
+    DiffRoot(#[from] diff_root::Error),
+
+    #[error("export")]
+    Export(#[from] export::Error),
+
     #[error("index")]
     Index(#[from] index::Error),
 
@@ -294,12 +1029,27 @@ pub enum Error {
     #[error("list")]
     List(#[from] list::Error),
 
+    #[error("mark")]
+    Mark(#[from] mark::Error),
+
     #[error("inspect")]
     Inspect(#[from] inspect::Error),
 
     #[error("extract")]
     Extract(#[from] extract::Error),
 
+    #[error("generate-units")]
+    GenerateUnits(#[from] generate_units::Error),
+
+    #[error("model")]
+    Model(#[from] model::Error),
+
+    #[error("pack")]
+    Pack(#[from] pack::Error),
+
+    #[error("provides")]
+    Provides(#[from] provides::Error),
+
     #[error("remove")]
     Remove(#[from] remove::Error),
 
@@ -312,15 +1062,36 @@ pub enum Error {
     #[error("search-file")]
     SearchFile(#[from] search_file::Error),
 
+    #[error("self-update")]
+    SelfUpdate(#[from] self_update::Error),
+
+    #[error("setup")]
+    Setup(#[from] setup::Error),
+
     #[error("state")]
     State(#[from] state::Error),
 
     #[error("sync")]
     Sync(#[from] sync::Error),
 
+    #[error("trigger")]
+    Trigger(#[from] trigger::Error),
+
+    #[error("version")]
+    Version(#[from] version::Error),
+
+    #[error("why")]
+    Why(#[from] why::Error),
+
     #[error("installation")]
     Installation(#[from] installation::Error),
 
+    #[error("installation is in read-only mode, as marked by {0:?}")]
+    ReadOnlyMode(PathBuf),
+
+    #[error("{0:?} is mounted read-only, remount it read-write to make changes")]
+    ReadOnlyRoot(PathBuf),
+
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
 }
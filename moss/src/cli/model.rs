@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, Command, arg, value_parser};
+use fs_err as fs;
+use moss::{
+    Installation,
+    client::{self, Client},
+    environment, firmware,
+    package::Flags,
+    system_model::{self, detect},
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("model")
+        .about("Work with the system-model")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("detect")
+                .about("Recommend driver & firmware packages for this host")
+                .long_about(
+                    "Probe the devices of the running host and emit a system-model fragment with the driver & \
+                     firmware packages supporting them.\n\n\
+                     Packages are matched by the modaliases they provide, from the configured repositories.",
+                )
+                .arg(
+                    arg!(-o --output <FILE> "Write the fragment to a file instead of stdout")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(arg!(--apply "Add the recommended packages to the system-model").conflicts_with("output")),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Render & validate a system-model")
+                .long_about(
+                    "Render a templated system-model with the variables of this host & any given with --define, \
+                     then check it decodes & every package is available from its repositories.\n\n\
+                     Defaults to the system-model of the installation.",
+                )
+                .arg(arg!([FILE] "System-model to check").value_parser(value_parser!(PathBuf)))
+                .arg(arg!(--render "Print the rendered system-model")),
+        )
+}
+
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand() {
+        Some(("detect", args)) => args.get_flag("apply"),
+        Some(("check", _)) => false,
+        _ => unreachable!(),
+    }
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("detect", args)) => detect(args, installation),
+        Some(("check", args)) => check(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+/// Handle execution of `moss model detect`
+fn detect(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let apply = args.get_flag("apply");
+    let model_path = installation.system_model_path();
+
+    if apply && installation.system_model.is_none() {
+        return Err(Error::NoSystemModel(model_path));
+    }
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let profile = firmware::Profile::detect(Path::new("/sys"));
+    let recommendations = detect::detect(&client.registry, &profile);
+
+    if recommendations.is_empty() {
+        eprintln!("No packages to recommend for this host");
+        return Ok(());
+    }
+
+    if apply {
+        let model = client
+            .installation
+            .system_model
+            .clone()
+            .ok_or_else(|| Error::NoSystemModel(model_path.clone()))?
+            .add_packages(
+                recommendations
+                    .iter()
+                    .map(|recommendation| recommendation.package.as_str()),
+            )?;
+        fs::write(&model_path, model.source())?;
+
+        println!(
+            "Updated {}, run {} to apply it",
+            model_path.display(),
+            "moss sync".bold()
+        );
+    } else if let Some(output) = args.get_one::<PathBuf>("output") {
+        fs::write(output, detect::fragment(&recommendations))?;
+    } else {
+        print!("{}", detect::fragment(&recommendations));
+    }
+
+    Ok(())
+}
+
+/// Handle execution of `moss model check`
+fn check(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let path = args
+        .get_one::<PathBuf>("FILE")
+        .cloned()
+        .unwrap_or_else(|| installation.system_model_path());
+
+    let variables = installation.variables.clone();
+    let model = system_model::load(&path, &variables)?.ok_or_else(|| Error::ModelDoesntExist(path.clone()))?;
+
+    if args.get_flag("render") {
+        print!("{}", model.encoded());
+    }
+
+    let client = Client::with_explicit_repositories(environment::NAME, installation, model.repositories.clone())?;
+
+    let unavailable = model
+        .packages
+        .iter()
+        .filter(|provider| {
+            client
+                .registry
+                .by_provider(provider, Flags::new().with_available())
+                .next()
+                .is_none()
+        })
+        .collect::<Vec<_>>();
+
+    for provider in &unavailable {
+        eprintln!(
+            "{}: {} isn't available from any repository of the system-model",
+            "Error".red(),
+            provider.to_string().as_str().bold()
+        );
+    }
+
+    if !unavailable.is_empty() {
+        return Err(Error::UnavailablePackages(unavailable.len()));
+    }
+
+    if model.is_templated() {
+        let variables = variables
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        eprintln!("Rendered with {variables}");
+    }
+    eprintln!(
+        "{} is valid with {} repositories & {} packages",
+        path.display(),
+        model.repositories.iter().count(),
+        model.packages.len()
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("update system model")]
+    UpdateSystemModel(#[from] system_model::UpdateError),
+
+    #[error("no system-model at {0:?} to apply to")]
+    NoSystemModel(PathBuf),
+
+    #[error("load system model")]
+    LoadSystemModel(#[from] system_model::LoadError),
+
+    #[error("system-model {0:?} doesn't exist")]
+    ModelDoesntExist(PathBuf),
+
+    #[error("{0} packages of the system-model aren't available")]
+    UnavailablePackages(usize),
+
+    #[error("io")]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,384 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, Command, arg, value_parser};
+use fs_err::{self as fs, File};
+use itertools::Itertools;
+use kdl::{KdlDocument, KdlValue};
+use moss::{
+    Dependency, Provider, dependency,
+    package::{self, Meta},
+    signing,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use sha2::{Digest, Sha256};
+use stone::{
+    payload::layout::{self, Layout},
+    write::digest,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("pack")
+        .about("Create a stone from a directory")
+        .long_about(
+            "Create a stone from a directory tree and a KDL metadata manifest, without a full build.\n\n\
+             The directory is installed as `/usr`. All files are owned by root in the resulting stone.\n\n\
+             The manifest supports the following nodes:\n\n  \
+             name \"example\"\n  \
+             version \"1.0.0\"\n  \
+             release 1\n  \
+             summary \"An example\"\n  \
+             description \"A longer description\"\n  \
+             homepage \"https://example.com\"\n  \
+             bugtracker \"https://example.com/issues\"\n  \
+             maintainer \"Jane Doe <jane@example.com>\"\n  \
+             update \"security\"\n  \
+             license \"MIT\" \"Apache-2.0\"\n  \
+             architecture \"x86_64\"\n  \
+             depends \"binary(sh)\" \"soname(libc.so.6(x86_64))\"\n  \
+             provides \"binary(example)\"\n  \
+             conflicts \"name(other)\"\n  \
+             replaces \"name(old-example)\"\n  \
+             exports \"EXAMPLE_HOME=/usr/lib/example\" \"PATH+=/usr/lib/example/bin\"\n  \
+             config-files \"example.conf\"\n\n\
+             Each of the config-files, relative to /etc, is installed from its vendor\n\
+             default under share/defaults/etc of DIR.",
+        )
+        .arg(arg!(<MANIFEST> "metadata manifest (.kdl)").value_parser(value_parser!(PathBuf)))
+        .arg(arg!(<DIR> "directory tree to pack, installed as /usr").value_parser(value_parser!(PathBuf)))
+        .arg(
+            arg!(-o --output <DIR> "Output directory for the stone")
+                .value_parser(value_parser!(PathBuf))
+                .default_value("."),
+        )
+        .arg(
+            arg!(--"sign-key" <KEY> "Sign the stone with an ed25519 PKCS#8 (DER) private key")
+                .value_parser(value_parser!(PathBuf)),
+        )
+}
+
+pub fn handle(args: &ArgMatches) -> Result<(), Error> {
+    let manifest = args.get_one::<PathBuf>("MANIFEST").unwrap();
+    let dir = args.get_one::<PathBuf>("DIR").unwrap();
+    let output = args.get_one::<PathBuf>("output").unwrap();
+    let sign_key = args.get_one::<PathBuf>("sign-key");
+
+    // Load the key first so we don't pack anything with a bad key
+    let key_pair = sign_key
+        .map(|path| -> Result<_, Error> {
+            let der = fs::read(path)?;
+            Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(|_| Error::InvalidKey(path.clone()))
+        })
+        .transpose()?;
+
+    let mut meta = parse_manifest(&fs::read_to_string(manifest)?)?;
+
+    if !dir.is_dir() {
+        return Err(Error::NotADirectory(dir.clone()));
+    }
+
+    let mut hasher = digest::Hasher::new();
+    let mut entries = vec![];
+    collect(dir, dir, &mut hasher, &mut entries)?;
+
+    meta.providers.insert(Provider {
+        kind: dependency::Kind::PackageName,
+        name: meta.name.to_string(),
+    });
+
+    let filename = format!(
+        "{}-{}-{}-{}-{}.stone",
+        meta.name, meta.version_identifier, meta.source_release, meta.build_release, meta.architecture
+    );
+    let out_path = output.join(&filename);
+
+    write_stone(&out_path, meta, &entries)?;
+
+    println!("{} {}", "Packed".green(), out_path.display());
+
+    if let Some(key_pair) = key_pair {
+        let signature_path = sign(&out_path, &key_pair)?;
+        println!(
+            "{} {} {}",
+            "Signed".green(),
+            signature_path.display(),
+            format!("(public key {})", hex::encode(key_pair.public_key().as_ref())).dim()
+        );
+    }
+
+    Ok(())
+}
+
+/// A file system entry to be packed
+struct Entry {
+    path: PathBuf,
+    layout: Layout,
+    size: u64,
+}
+
+/// Parse the metadata manifest into a [`Meta`]
+fn parse_manifest(content: &str) -> Result<Meta, Error> {
+    let document: KdlDocument = content.parse().map_err(Error::ParseManifest)?;
+
+    let values = |name: &str| -> Vec<&KdlValue> {
+        document
+            .get(name)
+            .map(|node| node.entries().iter().map(|entry| entry.value()).collect())
+            .unwrap_or_default()
+    };
+    let string = |name: &'static str| -> Result<Option<String>, Error> {
+        values(name)
+            .first()
+            .map(|value| {
+                value
+                    .as_string()
+                    .map(ToOwned::to_owned)
+                    .ok_or(Error::InvalidValue(name, "string"))
+            })
+            .transpose()
+    };
+    let strings = |name: &'static str| -> Result<Vec<String>, Error> {
+        values(name)
+            .into_iter()
+            .map(|value| {
+                value
+                    .as_string()
+                    .map(ToOwned::to_owned)
+                    .ok_or(Error::InvalidValue(name, "string"))
+            })
+            .collect()
+    };
+    let providers = |name: &'static str| -> Result<BTreeSet<Provider>, Error> {
+        strings(name)?
+            .iter()
+            .map(|p| Provider::from_name(p).map_err(|e| Error::ParseDependency(name, e)))
+            .collect()
+    };
+
+    let name = string("name")?.ok_or(Error::MissingValue("name"))?;
+    let release = values("release")
+        .first()
+        .map(|value| {
+            value
+                .as_integer()
+                .and_then(|i| u64::try_from(i).ok())
+                .ok_or(Error::InvalidValue("release", "positive integer"))
+        })
+        .transpose()?
+        .unwrap_or(1);
+
+    Ok(Meta {
+        name: name.clone().into(),
+        version_identifier: string("version")?.ok_or(Error::MissingValue("version"))?,
+        source_release: release,
+        build_release: 1,
+        architecture: string("architecture")?.unwrap_or_else(|| std::env::consts::ARCH.to_owned()),
+        summary: string("summary")?.unwrap_or_default(),
+        description: string("description")?.unwrap_or_default(),
+        source_id: name,
+        homepage: string("homepage")?.unwrap_or_default(),
+        bug_tracker: string("bugtracker")?,
+        maintainer: string("maintainer")?,
+        licenses: strings("license")?.into_iter().sorted().collect(),
+        dependencies: strings("depends")?
+            .iter()
+            .map(|d| Dependency::from_name(d).map_err(|e| Error::ParseDependency("depends", e)))
+            .collect::<Result<_, _>>()?,
+        providers: providers("provides")?,
+        conflicts: providers("conflicts")?,
+        replaces: providers("replaces")?,
+        exports: strings("exports")?
+            .iter()
+            .map(|e| e.parse().map_err(Error::ParseExport))
+            .collect::<Result<_, _>>()?,
+        config_files: strings("config-files")?
+            .into_iter()
+            .map(|path| {
+                if package::meta::is_config_file(&path) {
+                    Ok(path)
+                } else {
+                    Err(Error::InvalidConfigFile(path))
+                }
+            })
+            .collect::<Result<_, _>>()?,
+        uri: None,
+        hash: None,
+        download_size: None,
+        installed_size: None,
+        classification: string("update")?
+            .map(|update| {
+                update
+                    .parse()
+                    .map_err(|_| Error::InvalidValue("update", "security, bugfix or feature"))
+            })
+            .transpose()?,
+        security_release: None,
+    })
+}
+
+/// Recursively collect the entries of `dir`, relative to `root`
+fn collect(root: &Path, dir: &Path, hasher: &mut digest::Hasher, entries: &mut Vec<Entry>) -> Result<(), Error> {
+    for entry in fs::read_dir(dir)?.sorted_by_key(|e| e.as_ref().map(|e| e.file_name()).ok()) {
+        let path = entry?.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        let file_type = metadata.file_type();
+
+        let target = path
+            .strip_prefix(root)
+            .expect("entry within root")
+            .to_string_lossy()
+            .to_string();
+
+        let entry = if file_type.is_symlink() {
+            layout::Entry::Symlink(fs::read_link(&path)?.to_string_lossy().to_string(), target)
+        } else if file_type.is_dir() {
+            layout::Entry::Directory(target)
+        } else if file_type.is_char_device() {
+            layout::Entry::CharacterDevice(target)
+        } else if file_type.is_block_device() {
+            layout::Entry::BlockDevice(target)
+        } else if file_type.is_fifo() {
+            layout::Entry::Fifo(target)
+        } else if file_type.is_socket() {
+            layout::Entry::Socket(target)
+        } else {
+            hasher.reset();
+            let mut writer = digest::Writer::new(io::sink(), hasher);
+            io::copy(&mut File::open(&path)?, &mut writer)?;
+            layout::Entry::Regular(hasher.digest128(), target)
+        };
+
+        let is_dir = matches!(entry, layout::Entry::Directory(_));
+
+        entries.push(Entry {
+            path: path.clone(),
+            layout: Layout {
+                uid: 0,
+                gid: 0,
+                mode: metadata.mode(),
+                tag: 0,
+                entry,
+            },
+            size: metadata.len(),
+        });
+
+        if is_dir {
+            collect(root, &path, hasher, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the stone with meta, layout & compressed content payloads
+fn write_stone(out_path: &Path, meta: Meta, entries: &[Entry]) -> Result<(), Error> {
+    // Dedupe by hash -> sort largest to smallest
+    let files = entries
+        .iter()
+        .filter_map(|e| match e.layout.entry {
+            layout::Entry::Regular(hash, _) => Some((hash, e)),
+            _ => None,
+        })
+        .unique_by(|(hash, _)| *hash)
+        .sorted_by(|(_, a), (_, b)| a.size.cmp(&b.size).reverse())
+        .map(|(_, e)| e)
+        .collect::<Vec<_>>();
+    let total_size = files.iter().map(|e| e.size).sum();
+
+    if out_path.exists() {
+        fs::remove_file(out_path)?;
+    }
+    let mut out_file = File::create(out_path)?;
+
+    let mut writer = stone::Writer::new(&mut out_file, stone::header::v1::FileType::Binary)?;
+    writer.add_payload(meta.to_stone_payload().as_slice())?;
+
+    let layouts = entries.iter().map(|e| e.layout.clone()).collect::<Vec<_>>();
+    if !layouts.is_empty() {
+        writer.add_payload(layouts.as_slice())?;
+    }
+
+    if files.is_empty() {
+        writer.finalize()?;
+    } else {
+        let temp_path = out_path.with_extension("stone.tmp");
+        let mut temp_content = File::options()
+            .read(true)
+            .append(true)
+            .create(true)
+            .truncate(false)
+            .open(&temp_path)?;
+
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        let mut writer = writer.with_content(&mut temp_content, Some(total_size), workers)?;
+
+        for entry in files {
+            writer.add_content(&mut File::open(&entry.path)?)?;
+        }
+
+        writer.finalize()?;
+        fs::remove_file(temp_path)?;
+    }
+
+    out_file.flush()?;
+
+    Ok(())
+}
+
+/// Write a detached signature of the SHA-256 digest of `path` to `<path>.sig`
+fn sign(path: &Path, key_pair: &Ed25519KeyPair) -> Result<PathBuf, Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let signature = signing::sign_digest(key_pair, &hasher.finalize());
+
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(signing::SUFFIX);
+    let signature_path = PathBuf::from(signature_path);
+
+    fs::write(&signature_path, signature)?;
+
+    Ok(signature_path)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+
+    #[error("stone writer")]
+    Writer(#[from] stone::write::Error),
+
+    #[error("parse manifest")]
+    ParseManifest(#[source] kdl::KdlError),
+
+    #[error("manifest is missing {0}")]
+    MissingValue(&'static str),
+
+    #[error("invalid {0} in manifest, expected {1}")]
+    InvalidValue(&'static str, &'static str),
+
+    #[error("invalid {0} in manifest")]
+    ParseDependency(&'static str, #[source] dependency::ParseError),
+
+    #[error("invalid exports in manifest")]
+    ParseExport(#[source] package::export::ParseError),
+
+    #[error("invalid config file {0:?} in manifest, must be relative to /etc")]
+    InvalidConfigFile(String),
+
+    #[error("not a directory: {0:?}")]
+    NotADirectory(PathBuf),
+
+    #[error("invalid ed25519 PKCS#8 key: {0:?}")]
+    InvalidKey(PathBuf),
+}
@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command, arg};
+use itertools::Itertools;
+use moss::{
+    Installation, Provider,
+    client::{self, Client},
+    dependency, environment,
+    package::Flags,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("provides")
+        .about("Find packages providing a capability")
+        .long_about(
+            "Find the packages advertising the given provider, across the installed packages and \
+             all repositories.
+
+Providers are given as kind(name), i.e. soname(libz.so.1(x86_64)), pkgconfig(zlib), cmake(ZLIB), \
+binary(bash), sysbinary(fdisk) or interpreter(/usr/lib/ld-linux-x86-64.so.2). A plain name looks up \
+packages known by that name. Use `moss info --provides` to list the providers of a package.",
+        )
+        .arg(arg!(<PROVIDER> "Provider to look up").value_parser(clap::value_parser!(String)))
+}
+
+/// Handle execution of `moss provides`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let name = args.get_one::<String>("PROVIDER").unwrap();
+    let provider = Provider::from_name(name).map_err(|error| Error::InvalidProvider(name.clone(), error))?;
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    // Each package is yielded once per source, the installed copy first
+    let packages = client
+        .registry
+        .by_provider(&provider, Flags::default())
+        .into_group_map_by(|package| package.id.clone())
+        .into_values()
+        .map(|copies| {
+            let installed = copies.iter().any(|package| package.flags.installed);
+            let repository = copies.iter().find_map(|package| client.origin(package));
+            (copies.into_iter().next().unwrap(), installed, repository)
+        })
+        .sorted_by(|(a, ..), (b, ..)| {
+            a.meta
+                .name
+                .cmp(&b.meta.name)
+                .then_with(|| b.meta.source_release.cmp(&a.meta.source_release))
+        })
+        .collect::<Vec<_>>();
+
+    if packages.is_empty() {
+        return Err(Error::NotFound(name.clone()));
+    }
+
+    let rows = packages
+        .iter()
+        .map(|(package, _, repository)| {
+            [
+                package.meta.name.to_string(),
+                format!("{}-{}", package.meta.version_identifier, package.meta.source_release),
+                repository
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| "-".to_owned()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let [name_width, version_width, repository_width] =
+        std::array::from_fn(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or_default());
+
+    for ([name, version, repository], (package, installed, _)) in rows.iter().zip(&packages) {
+        let status = if *installed {
+            "installed".green().to_string()
+        } else if package.flags.foreign {
+            "foreign".dim().to_string()
+        } else {
+            String::new()
+        };
+
+        println!(
+            "{}  {}  {}  {status}",
+            format!("{name:<name_width$}").bold(),
+            format!("{version:<version_width$}").magenta(),
+            format!("{repository:<repository_width$}").dim(),
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid provider {0}, expected kind(name)")]
+    InvalidProvider(String, #[source] dependency::ParseError),
+    #[error("no package provides {0}")]
+    NotFound(String),
+    #[error("client")]
+    Client(#[from] client::Error),
+}
@@ -9,25 +9,50 @@ use std::time::{Duration, Instant};
 use thiserror::Error;
 
 use moss::{
-    Installation, Provider,
-    client::{self, Client},
+    Installation, Package, Provider,
+    client::{self, Client, boot, essential},
     environment,
+    package::{
+        self,
+        render::{self, Change},
+    },
+    prompt,
     registry::transaction,
     state::Selection,
+    system_model,
 };
 use tracing::{debug, info, instrument, warn};
-use tui::{
-    Styled,
-    dialoguer::{Confirm, theme::ColorfulTheme},
-    pretty::autoprint_columns,
-};
+use tracing_common::progress;
+use tui::Styled;
 
 pub fn command() -> Command {
     Command::new("remove")
         .visible_alias("rm")
         .about("Remove packages")
-        .long_about("Remove packages by name")
+        .long_about(
+            "Remove packages by name, along with their dependencies no longer needed. \
+             Installed packages depending on those removed are only removed too with --recursive",
+        )
         .arg(arg!(<NAME> ... "packages to remove").value_parser(clap::value_parser!(String)))
+        .arg(
+            arg!(--"force-boot-critical" "Allow removing the running kernel or the bootloader")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(-r --recursive "Also remove the installed packages depending on those removed")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"force-essential" "Allow removing essential packages, i.e. the C library or moss")
+                .long_help(
+                    "Allow removing essential packages, i.e. the C library or moss itself. Essential \
+                     packages are configured in /etc/moss/essential.yaml",
+                )
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(arg!(--"skip-triggers" "Do not run triggers when applying the new state").action(clap::ArgAction::SetTrue))
+        .args(super::system_model_change_args())
+        .args(super::divergence_args())
 }
 
 /// Handle execution of `moss remove`
@@ -43,13 +68,19 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .map(|name| Provider::from_name(name).unwrap())
         .collect::<Vec<_>>();
     let yes = *args.get_one::<bool>("yes").unwrap();
+    let force_boot_critical = args.get_flag("force-boot-critical");
+    let force_essential = args.get_flag("force-essential");
+    let recursive = args.get_flag("recursive");
+    let update_model = super::update_model(args, &installation)?;
 
     // Grab a client for the target, enumerate packages
-    let client = Client::new(environment::NAME, installation)?;
+    let client = Client::new(environment::NAME, installation)?.skip_triggers(args.get_flag("skip-triggers"));
 
     let installed = client.registry.list_installed().collect::<Vec<_>>();
     let installed_ids = installed.iter().map(|p| p.id.clone()).collect::<BTreeSet<_>>();
 
+    let phase = progress::Phase::start("resolve", pkgs.len());
+
     // Separate packages between installed / not installed (or invalid)
     let (for_removal, not_installed): (Vec<_>, Vec<_>) = pkgs.iter().partition_map(|provider| {
         installed
@@ -76,12 +107,24 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         transaction.add(installed_ids.clone().into_iter().collect())?;
 
         // Remove all pkgs for removal
-        transaction.remove(for_removal);
+        transaction.remove(for_removal.clone());
 
         // Finalized tx has all reverse deps removed
         transaction.finalize().cloned().collect::<BTreeSet<_>>()
     };
 
+    // Installed packages depending on those requested, which can't be kept without them
+    let dependents = installed
+        .iter()
+        .filter(|p| !tx_with_removed.contains(&p.id) && !for_removal.contains(&p.id))
+        .collect::<Vec<_>>();
+    if !dependents.is_empty() {
+        print_dependents(&installed, &tx_with_removed, &dependents);
+        if !recursive {
+            return Err(Error::Dependents(dependents.len()));
+        }
+    }
+
     // Build a new transaction w/ the leftover "explicit" packages. This will cause all orphaned
     // transitive dependencies to get dropped. These are packages that were depended on by removed
     // packages that are no longer depended on.
@@ -103,6 +146,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let removed = client.resolve_packages(installed_ids.difference(&finalized))?;
 
     timing.resolve = instant.elapsed();
+    phase.complete(removed.len());
     info!(
         total_packages = removed.len(),
         packages_to_remove = removed.len(),
@@ -120,19 +164,25 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         );
     }
 
+    let critical = client.boot_critical(&removed)?;
+    if !critical.is_empty() && !force_boot_critical {
+        return Err(Error::BootCritical(critical));
+    }
+
+    let essential = client.essential(&removed);
+    if !essential.is_empty() && !force_essential {
+        return Err(Error::Essential(essential));
+    }
+
+    let kept = super::resolve_divergence(&client, args, yes)?;
+    let client = client.keep_local(kept);
+
     println!("The following package(s) will be removed:");
     println!();
-    autoprint_columns(&removed);
+    render::print_changes(&removed.iter().map(Change::Remove).collect::<Vec<_>>());
     println!();
 
-    let result = if yes {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm("remove packages", " Do you wish to continue? ", yes)?;
     if !result {
         return Err(Error::Cancelled);
     }
@@ -140,8 +190,10 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     instant = Instant::now();
 
     // Print each package to stdout
-    for package in &removed {
-        println!("{} {}", "Removed".red(), package.meta.name.to_string().bold());
+    if !tui::is_quiet() {
+        for package in &removed {
+            println!("{} {}", "Removed".red(), package.meta.name.to_string().bold());
+        }
     }
 
     // Map finalized state to a [`Selection`] by referencing
@@ -167,11 +219,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
                             "Unreachable: previous selection not found during removal, marking as not explicit"
                         );
 
-                        Selection {
-                            package: id,
-                            explicit: false,
-                            reason: None,
-                        }
+                        Selection::transitive(id)
                     })
             })
             .collect::<Vec<_>>()
@@ -180,10 +228,18 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     // Apply state
     client.new_state(&new_state_pkgs, "Remove")?;
 
+    if update_model {
+        client.update_system_model()?;
+    }
+
     timing.blit = instant.elapsed();
+    timing.blit_phases = client.blit_timing();
 
     info!(
         blit_time_ms = timing.blit.as_millis(),
+        blit_plan_time_ms = timing.blit_phases.plan.as_millis(),
+        blit_directories_time_ms = timing.blit_phases.directories.as_millis(),
+        blit_files_time_ms = timing.blit_phases.files.as_millis(),
         total_time_ms = (timing.resolve + timing.blit).as_millis(),
         "Removal completed successfully"
     );
@@ -191,6 +247,31 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
+/// Report which of the removed packages each of the `dependents` depends on
+fn print_dependents(installed: &[Package], kept: &BTreeSet<package::Id>, dependents: &[&Package]) {
+    let removed = installed.iter().filter(|p| !kept.contains(&p.id)).collect::<Vec<_>>();
+
+    println!("The following installed package(s) depend on the packages being removed:");
+    println!();
+    for dependent in dependents {
+        let needs = removed
+            .iter()
+            .filter(|p| p.id != dependent.id)
+            .filter(|p| {
+                dependent.meta.dependencies.iter().any(|dependency| {
+                    p.meta.providers.contains(&Provider {
+                        kind: dependency.kind,
+                        name: dependency.name.clone(),
+                    })
+                })
+            })
+            .map(|p| p.meta.name.to_string())
+            .join(", ");
+        println!("  {} {} {needs}", dependent.meta.name.to_string().bold(), "needs".dim());
+    }
+    println!();
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("cancelled")]
@@ -199,6 +280,18 @@ pub enum Error {
     #[error("no such package")]
     NoSuchPackage,
 
+    #[error("{0} installed package(s) depend on the packages being removed, pass --recursive to remove them too")]
+    Dependents(usize),
+
+    #[error(transparent)]
+    SystemModelChange(#[from] system_model::UnrecordedChange),
+
+    #[error("refusing to remove boot critical packages without --force-boot-critical: {}", .0.iter().join(", "))]
+    BootCritical(Vec<boot::Critical>),
+
+    #[error("refusing to remove essential packages without --force-essential: {}", .0.iter().join("; "))]
+    Essential(Vec<essential::Essential>),
+
     #[error("client")]
     Client(#[from] client::Error),
 
@@ -211,8 +304,8 @@ pub enum Error {
     #[error("io")]
     Io(#[from] std::io::Error),
 
-    #[error("string processing")]
-    Dialog(#[from] tui::dialoguer::Error),
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
 }
 
 /// Simple timing information for Remove
@@ -220,4 +313,6 @@ pub enum Error {
 pub struct Timing {
     pub resolve: Duration,
     pub blit: Duration,
+    /// Breakdown of the blit itself, part of [`Self::blit`]
+    pub blit_phases: client::BlitTiming,
 }
@@ -2,14 +2,17 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{path::PathBuf, process};
+use std::{
+    path::{Path, PathBuf},
+    process,
+};
 
-use clap::{Arg, ArgAction, ArgMatches, Command, arg};
+use clap::{Arg, ArgAction, ArgMatches, Command, arg, parser::ValueSource};
 use itertools::Itertools;
 use moss::{
     Installation, Repository, environment,
-    repository::{self, Priority},
-    runtime, system_model,
+    repository::{self, Priority, health::Status},
+    runtime, signing, system_model,
 };
 use thiserror::Error;
 use tui::Styled;
@@ -21,12 +24,20 @@ enum Action {
     List,
     // Root, Id, Url, Comment
     Add(String, Url, String, Priority),
-    // Root, Id
-    Remove(String),
-    // Root, Id
-    Update(Option<String>),
-    Enable(String),
-    Disable(String),
+    // Root, Ids, Include disabled
+    Remove(Vec<String>, bool),
+    // Root, Ids, Atomic
+    Update(Vec<String>, bool),
+    Enable(Vec<String>),
+    Disable(Vec<String>),
+    // Root, Ids, Key, Sample
+    Verify(Vec<String>, Option<signing::PublicKey>, usize),
+    Undo,
+    ProfileList,
+    // Name, Description
+    ProfileSave(String, String),
+    ProfileUse(String),
+    ProfileRemove(String),
 }
 
 /// Return a command for handling `repo` subcommands
@@ -38,8 +49,26 @@ pub fn command() -> Command {
         .subcommand(
             Command::new("add")
                 .visible_alias("ar")
-                .arg(arg!(<NAME> "repo name").value_parser(clap::value_parser!(String)))
-                .arg(arg!(<URI> "repo uri").value_parser(clap::value_parser!(Url)))
+                .about("Add a repository to the system")
+                .long_about(
+                    "Add a repository to the system.\n\n\
+                     URI is either the URL of a stone.index or a local directory of stones, which is \
+                     indexed on the fly & reindexed whenever its stones change.\n\n\
+                     With --official, NAME is one of the official repositories of the signed manifest shipped \
+                     with moss and no URI is needed. Run `moss repo add --official` without a NAME to list them.",
+                )
+                .arg(
+                    arg!([NAME] "repo name")
+                        .value_parser(clap::value_parser!(String))
+                        .required_unless_present("official"),
+                )
+                .arg(
+                    arg!([URI] "repo uri or local directory of stones")
+                        .value_parser(parse_uri)
+                        .required_unless_present("official")
+                        .conflicts_with("official"),
+                )
+                .arg(arg!(--official "Add an official repository by name"))
                 .arg(
                     Arg::new("comment")
                         .short('c')
@@ -66,35 +95,120 @@ pub fn command() -> Command {
         .subcommand(
             Command::new("remove")
                 .visible_alias("rr")
-                .about("Remove a repository for the system")
-                .arg(arg!(<NAME> "repo name").value_parser(clap::value_parser!(String))),
+                .about("Remove repositories from the system")
+                .arg(
+                    arg!([NAME]... "repo names")
+                        .value_parser(clap::value_parser!(String))
+                        .required_unless_present("all-disabled"),
+                )
+                .arg(arg!(--"all-disabled" "Also remove every disabled repository")),
         )
         .subcommand(
             Command::new("update")
                 .visible_alias("ur")
                 .about("Update the system repositories")
-                .long_about("If no repository is named, update them all")
-                .arg(arg!([NAME] "repo name").value_parser(clap::value_parser!(String))),
+                .long_about(
+                    "If no repository is named, update them all.\n\n\
+                     Repositories which fail to update are reported once all others are updated. With \
+                     --atomic, every index is fetched before any is loaded, so either all repositories are \
+                     updated or none are.",
+                )
+                .arg(arg!([NAME]... "repo names").value_parser(clap::value_parser!(String)))
+                .arg(arg!(--atomic "Update all of the repositories or none of them")),
         )
         .subcommand(
             Command::new("enable")
                 .visible_alias("er")
                 .about("Enable the system repositories")
-                .arg(arg!([NAME] "repo name").value_parser(clap::value_parser!(String))),
+                .arg(arg!(<NAME>... "repo names").value_parser(clap::value_parser!(String))),
         )
         .subcommand(
             Command::new("disable")
                 .visible_alias("dr")
                 .about("Disable the system repositories")
-                .arg(arg!([NAME] "repo name").value_parser(clap::value_parser!(String))),
+                .arg(arg!(<NAME>... "repo names").value_parser(clap::value_parser!(String))),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check the health of the system repositories")
+                .long_about(
+                    "Check the health of the system repositories, or only those named.\n\n\
+                     Each repository is checked for whether its index can be fetched & parsed, the signature \
+                     of the index, the validity it publishes, the skew between the local clock & the server, \
+                     and whether a sample of its packages can be downloaded.",
+                )
+                .arg(arg!([NAME]... "repo names").value_parser(clap::value_parser!(String)))
+                .arg(
+                    arg!(--key <KEY> "Hex encoded ed25519 public key to verify index signatures with")
+                        .value_parser(signing::PublicKey::from_hex),
+                )
+                .arg(
+                    arg!(--sample <COUNT> "Number of packages to check the availability of per repository")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            Command::new("profile")
+                .about("Manage repository profiles")
+                .long_about(
+                    "Manage named sets of repositories, such as \"stable\" or \"local-dev\".\n\n\
+                     Profiles are stored in /etc/moss/repo-profile.d. Switching to a profile replaces the \
+                     configured repositories with those of the profile, keeping any others disabled, and \
+                     states created while it's in use record the profile in their summary.",
+                )
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("List the repository profiles"))
+                .subcommand(
+                    Command::new("save")
+                        .about("Save the current repositories as a profile")
+                        .arg(arg!(<NAME> "profile name").value_parser(clap::value_parser!(String)))
+                        .arg(
+                            Arg::new("comment")
+                                .short('c')
+                                .default_value("...")
+                                .action(ArgAction::Set)
+                                .help("Set the comment for the profile")
+                                .value_parser(clap::value_parser!(String)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("use")
+                        .about("Switch to the repositories of a profile")
+                        .arg(arg!(<NAME> "profile name").value_parser(clap::value_parser!(String))),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a profile")
+                        .arg(arg!(<NAME> "profile name").value_parser(clap::value_parser!(String))),
+                ),
+        )
+        .subcommand(
+            Command::new("undo")
+                .about("Undo the last repository change")
+                .long_about("Restore the repository configuration from before the last add, remove, enable or disable"),
         )
 }
 
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand() {
+        Some(("list" | "verify", _)) => false,
+        Some(("add" | "remove" | "update" | "enable" | "disable" | "undo", _)) => true,
+        Some(("profile", args)) => match args.subcommand_name() {
+            Some("list") => false,
+            Some("save" | "use" | "remove") => true,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
 /// Handle subcommands to `repo`
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let config = config::Manager::system(&installation.root, "moss");
 
-    let system_model = system_model::load(&installation.system_model_path())?;
+    let system_model = system_model::load(&installation.system_model_path(), &installation.variables)?;
 
     let manager = if let Some(system_model) = &system_model {
         repository::Manager::explicit(
@@ -108,22 +222,64 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
 
     let handler = match args.subcommand() {
         Some(("list", _)) => Action::List,
+        Some(("profile", cmd_args)) if cmd_args.subcommand_name() == Some("list") => Action::ProfileList,
+        Some(("verify", cmd_args)) => Action::Verify(
+            names(cmd_args),
+            cmd_args.get_one::<signing::PublicKey>("key").cloned(),
+            *cmd_args.get_one::<usize>("sample").unwrap(),
+        ),
         Some((command, _)) if system_model.is_some() => {
             return Err(Error::SystemModelDisallowed {
                 command: command.to_owned(),
                 path: installation.system_model_path(),
             });
         }
+        Some(("add", cmd_args)) if cmd_args.get_flag("official") => {
+            let Some(name) = cmd_args.get_one::<String>("NAME").cloned() else {
+                return list_official(&installation);
+            };
+            let repo = repository::official::get(&installation.root, &name)?
+                .ok_or_else(|| Error::UnknownOfficial(name.clone()))?;
+
+            // Curated values, unless explicitly overridden
+            let explicit = |id| cmd_args.value_source(id) == Some(ValueSource::CommandLine);
+            Action::Add(
+                name,
+                repo.uri,
+                if explicit("comment") {
+                    cmd_args.get_one::<String>("comment").cloned().unwrap()
+                } else {
+                    repo.description
+                },
+                if explicit("priority") {
+                    Priority::new(*cmd_args.get_one::<u64>("priority").unwrap())
+                } else {
+                    repo.priority
+                },
+            )
+        }
         Some(("add", cmd_args)) => Action::Add(
             cmd_args.get_one::<String>("NAME").cloned().unwrap(),
             cmd_args.get_one::<Url>("URI").cloned().unwrap(),
             cmd_args.get_one::<String>("comment").cloned().unwrap(),
             Priority::new(*cmd_args.get_one::<u64>("priority").unwrap()),
         ),
-        Some(("remove", cmd_args)) => Action::Remove(cmd_args.get_one::<String>("NAME").cloned().unwrap()),
-        Some(("update", cmd_args)) => Action::Update(cmd_args.get_one::<String>("NAME").cloned()),
-        Some(("enable", cmd_args)) => Action::Enable(cmd_args.get_one::<String>("NAME").cloned().unwrap()),
-        Some(("disable", cmd_args)) => Action::Disable(cmd_args.get_one::<String>("NAME").cloned().unwrap()),
+        Some(("remove", cmd_args)) => Action::Remove(names(cmd_args), cmd_args.get_flag("all-disabled")),
+        Some(("update", cmd_args)) => Action::Update(names(cmd_args), cmd_args.get_flag("atomic")),
+        Some(("enable", cmd_args)) => Action::Enable(names(cmd_args)),
+        Some(("disable", cmd_args)) => Action::Disable(names(cmd_args)),
+        Some(("undo", _)) => Action::Undo,
+        Some(("profile", cmd_args)) => {
+            let (command, profile_args) = cmd_args.subcommand().unwrap();
+            let name = profile_args.get_one::<String>("NAME").cloned().unwrap();
+
+            match command {
+                "save" => Action::ProfileSave(name, profile_args.get_one::<String>("comment").cloned().unwrap()),
+                "use" => Action::ProfileUse(name),
+                "remove" => Action::ProfileRemove(name),
+                _ => unreachable!(),
+            }
+        }
         _ => unreachable!(),
     };
 
@@ -131,11 +287,40 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     match handler {
         Action::List => list(manager),
         Action::Add(name, uri, comment, priority) => add(manager, name, uri, comment, priority),
-        Action::Remove(name) => remove(manager, name),
-        Action::Update(name) => update(manager, name),
-        Action::Enable(name) => enable(manager, name),
-        Action::Disable(name) => disable(manager, name),
+        Action::Remove(names, all_disabled) => remove(manager, names, all_disabled),
+        Action::Update(names, atomic) => update(manager, names, atomic),
+        Action::Enable(names) => enable(manager, names),
+        Action::Disable(names) => disable(manager, names),
+        Action::Verify(names, key, sample) => verify(manager, names, key, sample),
+        Action::Undo => undo(manager),
+        Action::ProfileList => list_profiles(manager),
+        Action::ProfileSave(name, comment) => save_profile(manager, name, comment),
+        Action::ProfileUse(name) => use_profile(manager, name),
+        Action::ProfileRemove(name) => remove_profile(manager, name),
+    }
+}
+
+/// Parse a repository URI, accepting a path to a local directory of stones
+pub(super) fn parse_uri(uri: &str) -> Result<Url, String> {
+    if let Ok(url) = Url::parse(uri) {
+        return Ok(url);
+    }
+
+    let path = Path::new(uri)
+        .canonicalize()
+        .map_err(|error| format!("neither a URL nor an existing path: {error}"))?;
+
+    if path.is_dir() {
+        Url::from_directory_path(&path)
+    } else {
+        Url::from_file_path(&path)
     }
+    .map_err(|()| format!("can't be used as a file URL: {path:?}"))
+}
+
+/// Repository names given to a subcommand
+fn names(args: &ArgMatches) -> Vec<String> {
+    args.get_many::<String>("NAME").into_iter().flatten().cloned().collect()
 }
 
 // Actual implementation of moss repo add
@@ -148,17 +333,16 @@ fn add(
 ) -> Result<(), Error> {
     let id = repository::Id::new(&name);
 
-    manager.add_repository(
+    runtime::block_on(manager.add_repository(
         id.clone(),
         Repository {
             description: comment,
             uri,
             priority,
             active: true,
+            aliases: Default::default(),
         },
-    )?;
-
-    runtime::block_on(manager.refresh(&id))?;
+    ))?;
 
     println!("{id} added");
 
@@ -173,6 +357,10 @@ fn list(manager: repository::Manager) -> Result<(), Error> {
         return Ok(());
     }
 
+    if let Some(profile) = manager.active_profile() {
+        println!("Profile: {}", profile.bold());
+    }
+
     for (id, repo) in configured_repos.sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse()) {
         let disabled = if !repo.active {
             " (disabled)".dim().to_string()
@@ -186,57 +374,252 @@ fn list(manager: repository::Manager) -> Result<(), Error> {
     Ok(())
 }
 
+/// List the official repositories available to `repo add --official`
+fn list_official(installation: &Installation) -> Result<(), Error> {
+    for (id, repo) in repository::official::list(&installation.root)? {
+        println!(" - {} = {}", id.to_string().bold(), repo.uri);
+        println!("   {}", repo.description.dim());
+    }
+
+    Ok(())
+}
+
 /// Update specific repos or all
-fn update(mut manager: repository::Manager, which: Option<String>) -> Result<(), Error> {
-    runtime::block_on(async {
-        match which {
-            Some(repo) => manager.refresh(&repository::Id::new(&repo)).await,
-            None => manager.refresh_all().await,
+fn update(manager: repository::Manager, which: Vec<String>, atomic: bool) -> Result<(), Error> {
+    let ids = if which.is_empty() {
+        manager
+            .list()
+            .filter(|(_, repo)| repo.active)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>()
+    } else {
+        which.iter().map(|name| repository::Id::new(name)).collect()
+    };
+
+    if atomic {
+        runtime::block_on(manager.refresh_atomic(&ids))?;
+        return Ok(());
+    }
+
+    let failed = runtime::block_on(manager.refresh_each(&ids))
+        .into_iter()
+        .filter_map(|(id, result)| result.err().map(|error| (id, error)))
+        .collect::<Vec<_>>();
+
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    println!("\nFailed to update:");
+    for (id, error) in &failed {
+        println!(" - {}: {}", id.to_string().bold(), error_chain(error));
+    }
+
+    Err(Error::UpdateFailed(failed.len(), ids.len()))
+}
+
+/// Join the chain of sources of `error`, as printed for a failed command
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut sources = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(error) = source {
+        sources.push(error.to_string());
+        source = error.source();
+    }
+    sources.join(": ")
+}
+
+/// Remove repos
+fn remove(mut manager: repository::Manager, repos: Vec<String>, all_disabled: bool) -> Result<(), Error> {
+    let mut ids = repos.iter().map(|repo| repository::Id::new(repo)).collect::<Vec<_>>();
+
+    if all_disabled {
+        ids.extend(
+            manager
+                .list()
+                .filter(|(id, repo)| !repo.active && !ids.contains(id))
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>(),
+        );
+
+        if ids.is_empty() {
+            println!("No disabled repositories");
+            return Ok(());
         }
-    })?;
+    }
+
+    let mut failed = false;
+
+    for (id, removal) in manager.remove(ids)? {
+        match removal {
+            repository::manager::Removal::NotFound => {
+                println!("{id} not found");
+                failed = true;
+            }
+            repository::manager::Removal::ConfigDeleted(false) => {
+                println!(
+                    "{id} configuration must be manually deleted since it doesn't exist in it's own configuration file"
+                );
+                failed = true;
+            }
+            repository::manager::Removal::ConfigDeleted(true) => {
+                println!("{id} removed");
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
 
     Ok(())
 }
 
-/// Remove repo
-fn remove(mut manager: repository::Manager, repo: String) -> Result<(), Error> {
-    let id = repository::Id::new(&repo);
+fn enable(mut manager: repository::Manager, repos: Vec<String>) -> Result<(), Error> {
+    let ids = repos.iter().map(|repo| repository::Id::new(repo)).collect::<Vec<_>>();
 
-    match manager.remove(id.clone())? {
-        repository::manager::Removal::NotFound => {
-            println!("{id} not found");
-            process::exit(1);
-        }
-        repository::manager::Removal::ConfigDeleted(false) => {
-            println!(
-                "{id} configuration must be manually deleted since it doesn't exist in it's own configuration file"
-            );
-            process::exit(1);
+    runtime::block_on(manager.enable(&ids))?;
+
+    for id in ids {
+        println!("{id} enabled");
+    }
+
+    Ok(())
+}
+
+fn disable(mut manager: repository::Manager, repos: Vec<String>) -> Result<(), Error> {
+    let ids = repos.iter().map(|repo| repository::Id::new(repo)).collect::<Vec<_>>();
+
+    runtime::block_on(manager.disable(&ids))?;
+
+    for id in ids {
+        println!("{id} disabled");
+    }
+
+    Ok(())
+}
+
+/// Check the health of specific repos or all
+fn verify(
+    manager: repository::Manager,
+    which: Vec<String>,
+    key: Option<signing::PublicKey>,
+    sample: usize,
+) -> Result<(), Error> {
+    let ids = which.iter().map(|name| repository::Id::new(name)).collect::<Vec<_>>();
+
+    if let Some(id) = ids.iter().find(|id| manager.list().all(|(known, _)| known != *id)) {
+        return Err(Error::UnknownRepo(id.clone()));
+    }
+
+    let repos = manager
+        .list()
+        .filter(|(id, _)| ids.is_empty() || ids.contains(id))
+        .collect::<Vec<_>>();
+
+    let mut unhealthy = vec![];
+
+    for (id, repo) in repos {
+        let checks = runtime::block_on(repository::health::check(repo, key.as_ref(), sample));
+
+        let disabled = if !repo.active {
+            " (disabled)".dim().to_string()
+        } else {
+            String::new()
+        };
+        println!("{} {}{disabled}", id.to_string().bold(), repo.uri.as_str().dim());
+
+        for check in &checks {
+            let status = format!("{:<8}", check.status.to_string());
+            let status = match check.status {
+                Status::Ok => status.green(),
+                Status::Warning => status.yellow(),
+                Status::Failed => status.red(),
+                Status::Skipped => status.dim(),
+            };
+            println!("  {status} {:<10} {}", check.name, check.detail);
         }
-        repository::manager::Removal::ConfigDeleted(true) => {
-            println!("{id} removed");
+        println!();
+
+        if checks.iter().any(|check| check.status == Status::Failed) {
+            unhealthy.push(id.to_string());
         }
     }
 
+    if !unhealthy.is_empty() {
+        return Err(Error::Unhealthy(unhealthy.join(", ")));
+    }
+
     Ok(())
 }
 
-fn enable(mut manager: repository::Manager, repo: String) -> Result<(), Error> {
-    let id = repository::Id::new(&repo);
+/// Restore the repository configuration prior to the last change
+fn undo(mut manager: repository::Manager) -> Result<(), Error> {
+    if runtime::block_on(manager.undo())? {
+        println!("Repository configuration restored");
+    } else {
+        println!("Nothing to undo");
+    }
+
+    Ok(())
+}
+
+/// List the repository profiles and the repositories they activate
+fn list_profiles(manager: repository::Manager) -> Result<(), Error> {
+    let profiles = manager.profiles()?;
+    if profiles.iter().len() == 0 {
+        println!("No repository profiles have been configured yet");
+        return Ok(());
+    }
+
+    let active = manager.active_profile();
 
-    runtime::block_on(manager.enable(&id))?;
+    for (name, profile) in profiles.iter() {
+        let marker = if active.as_ref() == Some(name) {
+            " (active)".green().to_string()
+        } else {
+            String::new()
+        };
+        let repos = profile
+            .repositories
+            .iter()
+            .filter(|(_, repo)| repo.active)
+            .map(|(id, _)| id.to_string())
+            .join(", ");
+
+        println!(" - {}{marker} = {repos}", name.as_str().bold());
+        println!("   {}", profile.description.as_str().dim());
+    }
 
-    println!("{id} enabled");
+    Ok(())
+}
+
+/// Save the current repositories as a profile
+fn save_profile(manager: repository::Manager, name: String, comment: String) -> Result<(), Error> {
+    manager.save_profile(&name, comment)?;
+
+    println!("Profile {name} saved");
 
     Ok(())
 }
 
-fn disable(mut manager: repository::Manager, repo: String) -> Result<(), Error> {
-    let id = repository::Id::new(&repo);
+/// Switch to the repositories of a profile
+fn use_profile(mut manager: repository::Manager, name: String) -> Result<(), Error> {
+    runtime::block_on(manager.use_profile(&name))?;
 
-    runtime::block_on(manager.disable(&id))?;
+    println!("Switched to profile {name}");
 
-    println!("{id} disabled");
+    Ok(())
+}
+
+/// Remove a profile
+fn remove_profile(manager: repository::Manager, name: String) -> Result<(), Error> {
+    if manager.delete_profile(&name)? {
+        println!("Profile {name} removed");
+    } else {
+        println!("Profile {name} must be manually deleted since it doesn't exist in it's own configuration file");
+        process::exit(1);
+    }
 
     Ok(())
 }
@@ -251,4 +634,14 @@ pub enum Error {
         "`moss repo {command}` is not allowed with system-model enabled. Repos must be manually edited from {path:?}"
     )]
     SystemModelDisallowed { command: String, path: PathBuf },
+    #[error("no official repository named {0}, run `moss repo add --official` to list them")]
+    UnknownOfficial(String),
+    #[error("official repositories")]
+    Official(#[from] repository::official::Error),
+    #[error("failed to update {0} of {1} repositories")]
+    UpdateFailed(usize, usize),
+    #[error("unhealthy repositories: {0}")]
+    Unhealthy(String),
+    #[error("unknown repository {0}")]
+    UnknownRepo(repository::Id),
 }
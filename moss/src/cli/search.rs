@@ -2,24 +2,67 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::ops::Range;
+
 use clap::builder::NonEmptyStringValueParser;
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use itertools::Itertools;
+use regex::{Regex, RegexBuilder};
 
 use moss::client;
-use moss::package::{self, Name};
+use moss::dependency;
+use moss::package::{self, Package};
 use moss::{Client, Installation, environment};
 use tui::Styled;
 use tui::pretty::{ColumnDisplay, print_columns};
 
 const ARG_KEYWORD: &str = "KEYWORD";
 const FLAG_INSTALLED: &str = "installed";
+const FLAG_REGEX: &str = "regex";
+const FLAG_FUZZY: &str = "fuzzy";
+
+/// Flags restricting the search to providers of these kinds, i.e. `--binary gcc`
+const PROVIDER_FLAGS: [(&str, &str, &[dependency::Kind]); 5] = [
+    (
+        "binary",
+        "Search for packages providing a binary in /usr/bin or /usr/sbin",
+        &[dependency::Kind::Binary, dependency::Kind::SystemBinary],
+    ),
+    (
+        "soname",
+        "Search for packages providing a shared library",
+        &[dependency::Kind::SharedLibrary],
+    ),
+    (
+        "pkgconfig",
+        "Search for packages providing a pkg-config module",
+        &[dependency::Kind::PkgConfig, dependency::Kind::PkgConfig32],
+    ),
+    (
+        "cmake",
+        "Search for packages providing a CMake module",
+        &[dependency::Kind::CMake],
+    ),
+    (
+        "python",
+        "Search for packages providing a Python module",
+        &[dependency::Kind::Python],
+    ),
+];
 
 /// Returns the Clap struct for this command.
 pub fn command() -> Command {
     Command::new("search")
         .visible_alias("sr")
         .about("Search packages")
-        .long_about("Search packages by looking into package names and summaries.")
+        .long_about(
+            "Search packages by looking into package names, summaries, descriptions and providers.
+
+Results are ranked by relevance, with name matches ranked above provider, summary and description matches.
+
+With a provider flag, i.e. `moss search --binary gcc` or `moss search --soname libz.so.1`, only providers of \
+that kind are searched, to find which package delivers them.",
+        )
         .arg(
             Arg::new(ARG_KEYWORD)
                 .required(true)
@@ -33,12 +76,53 @@ pub fn command() -> Command {
                 .num_args(0)
                 .help("Search among installed packages only"),
         )
+        .arg(
+            Arg::new(FLAG_REGEX)
+                .short('r')
+                .long("regex")
+                .action(ArgAction::SetTrue)
+                .conflicts_with(FLAG_FUZZY)
+                .help("Treat the keyword as a (case insensitive) regular expression"),
+        )
+        .arg(
+            Arg::new(FLAG_FUZZY)
+                .short('f')
+                .long("fuzzy")
+                .action(ArgAction::SetTrue)
+                .help("Match the keyword as a fuzzy subsequence, tolerating gaps"),
+        )
+        .args(PROVIDER_FLAGS.map(|(name, help, _)| {
+            Arg::new(name)
+                .long(name)
+                .action(ArgAction::SetTrue)
+                .help(help)
+                .help_heading("Provider kinds")
+                .conflicts_with_all(
+                    PROVIDER_FLAGS
+                        .map(|(other, ..)| other)
+                        .into_iter()
+                        .filter(|other| *other != name),
+                )
+        }))
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let keyword = args.get_one::<String>(ARG_KEYWORD).unwrap();
     let only_installed = args.get_flag(FLAG_INSTALLED);
 
+    let matcher = if args.get_flag(FLAG_REGEX) {
+        Matcher::Regex(RegexBuilder::new(keyword).case_insensitive(true).build()?)
+    } else if args.get_flag(FLAG_FUZZY) {
+        Matcher::Fuzzy(keyword.to_lowercase())
+    } else {
+        Matcher::Substring(keyword.to_lowercase())
+    };
+
+    let kinds = PROVIDER_FLAGS
+        .into_iter()
+        .find(|(name, ..)| args.get_flag(name))
+        .map(|(.., kinds)| kinds);
+
     let client = Client::new(environment::NAME, installation)?;
     let flags = if only_installed {
         package::Flags::new().with_installed()
@@ -46,13 +130,41 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         package::Flags::new().with_available()
     };
 
-    let output: Vec<Output> = client
-        .registry
-        .by_keyword(keyword, flags)
-        .map(|pkg| Output {
-            name: pkg.meta.name,
-            summary: pkg.meta.summary,
+    // Plain keywords are looked up via the search index built on repository
+    // refresh, other modes & provider searches can't be indexed and need to scan every package
+    let lookup = |flags| match &matcher {
+        Matcher::Substring(_) if kinds.is_none() => client.registry.by_keyword(keyword, flags).collect::<Vec<_>>(),
+        _ => client.registry.list(flags).collect(),
+    };
+    let mut candidates = lookup(flags);
+
+    // Packages imported from foreign repositories rank behind our own of the same name
+    if !only_installed {
+        candidates.extend(lookup(package::Flags::new().with_foreign()));
+    }
+
+    // Names from other distributions resolve to the package they're known as here
+    let alias = kinds
+        .is_none()
+        .then(|| client.package_alias(keyword))
+        .flatten()
+        .map(|target| Alias {
+            name: keyword.clone(),
+            target: package::Name::from(target),
+        });
+    if let Some(alias) = &alias {
+        candidates.extend(client.registry.by_name(&alias.target, flags));
+    }
+
+    // Registry yields packages in priority order, so keep the first of each name
+    let output: Vec<Output> = candidates
+        .into_iter()
+        .unique_by(|pkg| pkg.meta.name.to_string())
+        .filter_map(|pkg| match kinds {
+            Some(kinds) => score_providers(&matcher, kinds, pkg),
+            None => score(&matcher, alias.as_ref(), pkg),
         })
+        .sorted_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)))
         .collect();
 
     if output.is_empty() {
@@ -64,29 +176,231 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     Ok(())
 }
 
+/// How the keyword is matched against package fields
+enum Matcher {
+    /// The whole keyword, as looked up by [`moss::Registry::by_keyword`]
+    Substring(String),
+    Regex(Regex),
+    Fuzzy(String),
+}
+
+impl Matcher {
+    /// Returns the matched byte ranges within `haystack` and the match quality
+    fn find(&self, haystack: &str) -> Option<(Vec<Range<usize>>, u32)> {
+        match self {
+            Matcher::Substring(keyword) => {
+                let lowercase = haystack.to_lowercase();
+                // Lowercasing may change byte lengths for some scripts, skip highlighting then
+                let ranges = if lowercase.len() == haystack.len() {
+                    lowercase
+                        .match_indices(keyword.as_str())
+                        .map(|(start, m)| start..start + m.len())
+                        .collect()
+                } else {
+                    vec![]
+                };
+                lowercase.contains(keyword.as_str()).then(|| {
+                    let quality = if lowercase == *keyword {
+                        3
+                    } else if lowercase.starts_with(keyword.as_str()) {
+                        2
+                    } else {
+                        1
+                    };
+                    (ranges, quality)
+                })
+            }
+            Matcher::Regex(regex) => {
+                let ranges = regex.find_iter(haystack).map(|m| m.range()).collect::<Vec<_>>();
+                let quality = match ranges.first() {
+                    Some(range) if *range == (0..haystack.len()) => 3,
+                    Some(range) if range.start == 0 => 2,
+                    Some(_) => 1,
+                    None => return None,
+                };
+                Some((ranges, quality))
+            }
+            Matcher::Fuzzy(needle) => fuzzy_match(needle, haystack),
+        }
+    }
+}
+
+/// Match `needle` as an in-order subsequence of `haystack`, ignoring case
+///
+/// Quality rewards consecutive characters and matches starting early on.
+fn fuzzy_match(needle: &str, haystack: &str) -> Option<(Vec<Range<usize>>, u32)> {
+    let mut needle_chars = needle.chars().peekable();
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut consecutive = 0;
+    let mut gaps = 0;
+
+    for (idx, ch) in haystack.char_indices() {
+        let Some(next) = needle_chars.peek() else {
+            break;
+        };
+
+        if ch.to_lowercase().eq(next.to_lowercase()) {
+            needle_chars.next();
+            match ranges.last_mut() {
+                Some(last) if last.end == idx => {
+                    last.end = idx + ch.len_utf8();
+                    consecutive += 1;
+                }
+                _ => ranges.push(idx..idx + ch.len_utf8()),
+            }
+        } else if !ranges.is_empty() {
+            gaps += 1;
+        }
+    }
+
+    if needle_chars.peek().is_some() {
+        return None;
+    }
+
+    let starts_early = ranges.first().is_some_and(|range| range.start == 0);
+    let quality = 1 + u32::from(starts_early) + consecutive / 2;
+
+    Some((ranges, quality.saturating_sub(gaps / 4).max(1)))
+}
+
+/// An alternative package name that was searched for
+struct Alias {
+    name: String,
+    target: package::Name,
+}
+
+/// Score a package against the matcher, returning `None` if nothing matches
+fn score(matcher: &Matcher, alias: Option<&Alias>, pkg: Package) -> Option<Output> {
+    let name = pkg.meta.name.to_string();
+    let alias = alias.filter(|alias| alias.target == pkg.meta.name);
+
+    let name_match = matcher.find(&name);
+    let summary_match = matcher.find(&pkg.meta.summary);
+    let description_match = matcher.find(&pkg.meta.description);
+    let provider_match = pkg
+        .meta
+        .providers
+        .iter()
+        .filter_map(|provider| {
+            let provider = provider.to_name();
+            matcher.find(&provider).map(|(_, quality)| (provider, quality))
+        })
+        .max_by_key(|(_, quality)| *quality);
+
+    // Fields are weighted by how strongly they identify a package
+    let score = alias.map_or(0, |_| 300)
+        + name_match.as_ref().map_or(0, |(_, q)| 100 * q)
+        + provider_match.as_ref().map_or(0, |(_, q)| 20 * q)
+        + summary_match.as_ref().map_or(0, |(_, q)| 10 * q)
+        + description_match.as_ref().map_or(0, |(_, q)| 3 * q);
+
+    if score == 0 {
+        return None;
+    }
+
+    // Explain matches which aren't visible in the name or summary
+    let note = if pkg.flags.foreign {
+        Some("foreign, not installable".to_owned())
+    } else if let Some(alias) = alias {
+        Some(format!("known as {} elsewhere", alias.name))
+    } else if name_match.is_some() || summary_match.is_some() {
+        None
+    } else if let Some((provider, _)) = provider_match {
+        Some(format!("provides {provider}"))
+    } else {
+        Some("matched description".to_owned())
+    };
+
+    Some(Output {
+        name_highlighted: highlight(&name, name_match.map(|(r, _)| r).unwrap_or_default(), true),
+        summary: highlight(
+            &pkg.meta.summary,
+            summary_match.map(|(r, _)| r).unwrap_or_default(),
+            false,
+        ),
+        name,
+        note,
+        score,
+    })
+}
+
+/// Score a package by its providers of the given `kinds` only, returning `None` if none match
+fn score_providers(matcher: &Matcher, kinds: &[dependency::Kind], pkg: Package) -> Option<Output> {
+    let (provider, quality) = pkg
+        .meta
+        .providers
+        .iter()
+        .filter(|provider| kinds.contains(&provider.kind))
+        .filter_map(|provider| matcher.find(&provider.name).map(|(_, quality)| (provider, quality)))
+        .max_by_key(|(_, quality)| *quality)?;
+
+    let name = pkg.meta.name.to_string();
+    let note = if pkg.flags.foreign {
+        format!("provides {provider}, foreign, not installable")
+    } else {
+        format!("provides {provider}")
+    };
+
+    Some(Output {
+        name_highlighted: name.clone().bold().to_string(),
+        summary: pkg.meta.summary.clone(),
+        name,
+        note: Some(note),
+        score: quality,
+    })
+}
+
+/// Render `text` with the given byte ranges highlighted
+fn highlight(text: &str, ranges: Vec<Range<usize>>, bold: bool) -> String {
+    let plain = |s: &str| if bold { s.bold().to_string() } else { s.to_owned() };
+
+    let mut output = String::new();
+    let mut cursor = 0;
+
+    for range in ranges {
+        if range.start < cursor || !text.is_char_boundary(range.start) || !text.is_char_boundary(range.end) {
+            continue;
+        }
+        output.push_str(&plain(&text[cursor..range.start]));
+        output.push_str(&text[range.clone()].yellow().bold().to_string());
+        cursor = range.end;
+    }
+    output.push_str(&plain(&text[cursor..]));
+
+    output
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("invalid regex")]
+    Regex(#[from] regex::Error),
 }
 
 struct Output {
-    name: Name,
+    name: String,
+    name_highlighted: String,
     summary: String,
+    note: Option<String>,
+    score: u32,
 }
 
 impl ColumnDisplay for Output {
     fn get_display_width(&self) -> usize {
-        self.name.as_ref().chars().count()
+        self.name.chars().count()
     }
 
     fn display_column(&self, writer: &mut impl std::io::prelude::Write, _col: tui::pretty::Column, width: usize) {
         let _ = write!(
             writer,
             "{}{:width$}  {}",
-            self.name.to_string().bold(),
+            self.name_highlighted,
             " ".repeat(width),
             self.summary
         );
+        if let Some(note) = &self.note {
+            let _ = write!(writer, " {}", format!("({note})").dim());
+        }
     }
 }
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{env, io};
+
+use clap::{ArgMatches, Command};
+use moss::{Client, Installation, client, environment, prompt, release, runtime};
+use thiserror::Error;
+use tui::Styled;
+
+use super::{Outcome, version};
+
+pub fn command() -> Command {
+    Command::new("self-update")
+        .about("Update moss from its release endpoint")
+        .long_about(
+            "Update moss from its release endpoint
+
+Only for moss installed outside of a repository, moss installed from a repository is updated with `moss sync`. \\
+The endpoint is configured in /etc/moss/release.yaml, along with the key the releases are signed with.",
+        )
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<Outcome, Error> {
+    let yes = args.get_flag("yes");
+
+    let config = version::release_config(&installation);
+    let endpoint = config.url.ok_or(Error::NoEndpoint)?;
+    let key = config.key.ok_or(Error::NoKey)?;
+
+    let client = Client::new(environment::NAME, installation).map_err(Error::SetupClient)?;
+    if let Some(package) = version::installed(&client) {
+        return Err(Error::RepositoryManaged(package.meta.version_identifier));
+    }
+
+    let latest = runtime::block_on(release::latest(endpoint, &key))?;
+    if !latest.is_newer() {
+        println!("moss {} is up to date", release::VERSION);
+        return Ok(Outcome::NothingToDo);
+    }
+
+    let path = env::current_exe().map_err(Error::CurrentExe)?;
+    println!(
+        "moss {} at {} will be updated to {}",
+        release::VERSION,
+        path.display(),
+        latest.version.as_str().bold()
+    );
+    println!();
+
+    if !prompt::confirm("update moss", " Do you wish to continue? ", yes)? {
+        return Err(Error::Cancelled);
+    }
+
+    runtime::block_on(release::install(&latest, &key, &path))?;
+    println!("{} moss to {}", "Updated".green(), latest.version);
+
+    Ok(Outcome::Done)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to setup moss client")]
+    SetupClient(#[source] client::Error),
+
+    #[error("no release endpoint configured in /etc/moss/release.yaml")]
+    NoEndpoint,
+
+    #[error("no key to verify releases with configured in /etc/moss/release.yaml")]
+    NoKey,
+
+    #[error("moss {0} is installed from a repository, update it with `moss sync` instead")]
+    RepositoryManaged(String),
+
+    #[error("locate the running moss")]
+    CurrentExe(#[source] io::Error),
+
+    #[error("release")]
+    Release(#[from] release::Error),
+
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
+
+    #[error("cancelled")]
+    Cancelled,
+}
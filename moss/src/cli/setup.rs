@@ -0,0 +1,286 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{io, os::unix::fs::symlink, path::PathBuf};
+
+use clap::{ArgAction, ArgMatches, Command, arg, value_parser};
+use fs_err as fs;
+use moss::{
+    Installation, Repository,
+    client::{self, Client},
+    environment, prompt,
+    repository::{self, Priority, official},
+    runtime,
+};
+use thiserror::Error;
+use tui::Styled;
+use url::Url;
+
+use super::{
+    generate_units::{self, Policy},
+    repo,
+};
+
+/// Location of system units, relative to the root
+const UNIT_DIR: &str = "etc/systemd/system";
+
+/// Official repository enabled by default on a fresh installation
+const DEFAULT_REPOSITORY: &str = "volatile";
+
+pub fn command() -> Command {
+    Command::new("setup")
+        .about("Guided setup of a fresh installation")
+        .long_about(
+            "Walk through the setup of a fresh installation: enabling official repositories, choosing how \
+             updates are handled in the background, enabling the system-model and installing the systemd \
+             units for the chosen update policy.
+
+Questions answered by an option aren't asked, and the defaults are taken for the remaining ones with --yes-all. \
+Already configured repositories are left alone.",
+        )
+        .arg(
+            arg!(--repo <"NAME[=URI]"> "Add the repository NAME from URI, or the official one without, may be repeated")
+                .action(ArgAction::Append)
+                .value_parser(parse_repo),
+        )
+        .arg(
+            arg!(--"update-policy" <POLICY> "How updates are handled in the background")
+                .value_parser(value_parser!(Policy)),
+        )
+}
+
+/// Handle execution of `moss setup`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let yes = args.get_flag("yes");
+    let config = config::Manager::system(&installation.root, "moss");
+
+    if installation.system_model.is_some() {
+        println!(
+            "Repositories are managed by {}, skipping repository setup",
+            installation.system_model_path().display()
+        );
+    } else {
+        setup_repositories(args, &config, &installation, yes)?;
+    }
+
+    let policy = setup_update_policy(args, &config, yes)?;
+
+    if installation.system_model.is_none() {
+        setup_system_model(&installation, yes)?;
+    }
+
+    setup_units(&installation, policy, yes)?;
+
+    println!();
+    println!("{}", "Setup complete".green());
+
+    Ok(())
+}
+
+/// Add the repositories given by `--repo`, or choose among the official ones
+fn setup_repositories(
+    args: &ArgMatches,
+    config: &config::Manager,
+    installation: &Installation,
+    yes: bool,
+) -> Result<(), Error> {
+    let mut manager = repository::Manager::system(config.clone(), installation.clone())?;
+    let configured = manager.list().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+
+    let selected = match args.get_many::<(String, Option<Url>)>("repo") {
+        Some(repos) => repos
+            .map(|(name, uri)| {
+                let repo = match uri {
+                    Some(uri) => Repository {
+                        description: "...".to_owned(),
+                        uri: uri.clone(),
+                        priority: Priority::new(0),
+                        active: true,
+                        aliases: Default::default(),
+                    },
+                    None => {
+                        official::get(&installation.root, name)?.ok_or_else(|| Error::UnknownOfficial(name.clone()))?
+                    }
+                };
+                Ok((repository::Id::new(name), repo))
+            })
+            .collect::<Result<Vec<_>, Error>>()?,
+        None => select_official(installation, &configured, yes)?,
+    };
+
+    if configured.is_empty() && selected.is_empty() {
+        println!(
+            "No repositories configured, add one with {} or {}",
+            "moss setup --repo NAME[=URI]".bold(),
+            "moss repo add".bold()
+        );
+    }
+
+    for (id, repo) in selected {
+        if configured.contains(&id) {
+            continue;
+        }
+
+        runtime::block_on(manager.add_repository(id.clone(), repo))?;
+        println!("{} {id}", "Added".green());
+    }
+
+    Ok(())
+}
+
+/// Choose among the official repositories, defaulting to those already configured or
+/// to [`DEFAULT_REPOSITORY`] on a fresh installation
+fn select_official(
+    installation: &Installation,
+    configured: &[repository::Id],
+    yes: bool,
+) -> Result<Vec<(repository::Id, Repository)>, Error> {
+    let official = match official::list(&installation.root) {
+        Ok(official) => official.into_iter().collect::<Vec<_>>(),
+        // Still usable with repositories of its own
+        Err(error) => {
+            eprintln!(
+                "{}: official repositories aren't available: {error}",
+                "Warning".yellow()
+            );
+            return Ok(vec![]);
+        }
+    };
+
+    let items = official
+        .iter()
+        .map(|(id, repo)| format!("{id} - {}", repo.description))
+        .collect::<Vec<_>>();
+    let defaults = official
+        .iter()
+        .map(|(id, _)| configured.contains(id) || (configured.is_empty() && id.to_string() == DEFAULT_REPOSITORY))
+        .collect::<Vec<_>>();
+
+    let selected = prompt::multi_select("choose repositories", "Repositories to enable", &items, &defaults, yes)?;
+
+    Ok(selected.into_iter().map(|idx| official[idx].clone()).collect())
+}
+
+/// Parse a `NAME=URI` repository, or the `NAME` of an official one
+fn parse_repo(value: &str) -> Result<(String, Option<Url>), String> {
+    let (name, uri) = match value.split_once('=') {
+        Some((name, uri)) => (name, Some(repo::parse_uri(uri)?)),
+        None => (value, None),
+    };
+    if name.is_empty() {
+        return Err("expected NAME[=URI]".to_owned());
+    }
+
+    Ok((name.to_owned(), uri))
+}
+
+/// Choose & save the update policy
+fn setup_update_policy(args: &ArgMatches, config: &config::Manager, yes: bool) -> Result<Policy, Error> {
+    let policy = match args.get_one::<Policy>("update-policy") {
+        Some(policy) => *policy,
+        None => {
+            let current = config.load::<generate_units::Config>().pop().unwrap_or_default().policy;
+            let items = Policy::ALL.map(|policy| policy.describe());
+            let default = Policy::ALL
+                .iter()
+                .position(|policy| *policy == current)
+                .unwrap_or_default();
+
+            Policy::ALL[prompt::select("choose an update policy", "Updates", &items, default, yes)?]
+        }
+    };
+
+    config.save("setup", &generate_units::Config { policy })?;
+    println!("{} update policy {}", "Saved".green(), policy.to_string().bold());
+
+    Ok(policy)
+}
+
+/// Offer to manage the installation with a system-model
+fn setup_system_model(installation: &Installation, yes: bool) -> Result<(), Error> {
+    let path = installation.system_model_path();
+
+    if !prompt::confirm(
+        "enable the system-model",
+        " Manage this installation with a system-model? ",
+        yes,
+    )? {
+        return Ok(());
+    }
+
+    let client = Client::new(environment::NAME, installation.clone())?;
+    let system_model = client.create_system_model()?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, system_model.encoded())?;
+    println!("{} {}", "Generated".green(), path.display());
+
+    Ok(())
+}
+
+/// Offer to install & enable the systemd units for `policy`, replacing those of other policies
+fn setup_units(installation: &Installation, policy: Policy, yes: bool) -> Result<(), Error> {
+    if !prompt::confirm(
+        "install systemd units",
+        " Install systemd units for the update policy? ",
+        yes,
+    )? {
+        return Ok(());
+    }
+
+    let dir = installation.root.join(UNIT_DIR);
+    let wants = |target: &str| dir.join(format!("{target}.wants"));
+
+    let wanted = generate_units::unit_names(policy);
+    for name in Policy::ALL.into_iter().flat_map(generate_units::unit_names) {
+        if wanted.contains(&name) {
+            continue;
+        }
+
+        let enabled = generate_units::wanted_by(&name).map(|target| wants(target).join(&name));
+        for path in enabled.into_iter().chain([dir.join(&name)]) {
+            if path.symlink_metadata().is_ok() {
+                fs::remove_file(&path)?;
+                println!("{} {}", "Removed".red(), path.display());
+            }
+        }
+    }
+
+    let enable = generate_units::write(&dir, policy, "daily", "1h")?;
+
+    for (unit, target) in enable {
+        let wants = wants(target);
+        fs::create_dir_all(&wants)?;
+
+        let link = wants.join(&unit);
+        if link.symlink_metadata().is_err() {
+            symlink(PathBuf::from("..").join(&unit), &link)?;
+            println!("{} {unit}", "Enabled".green());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("repo manager")]
+    RepositoryManager(#[from] repository::manager::Error),
+    #[error("no official repository named {0}, run `moss repo add --official` to list them")]
+    UnknownOfficial(String),
+    #[error("official repositories")]
+    Official(#[from] official::Error),
+    #[error("save config")]
+    SaveConfig(#[from] config::SaveError),
+    #[error("generate units")]
+    GenerateUnits(#[from] generate_units::Error),
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
@@ -8,17 +8,25 @@ use std::{
 };
 
 use chrono::Local;
-use clap::{ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches, Parser, arg};
+use clap::{
+    ArgAction, ArgGroup, ArgMatches, Command, CommandFactory, FromArgMatches, Parser, ValueEnum, arg,
+    parser::ValueSource,
+};
 use fs_err as fs;
 use moss::{
-    Installation, State,
-    client::{self, Client, prune},
-    environment, state,
+    Installation, Package, State, SystemModel,
+    client::{self, Client, boot, pending, prune},
+    environment,
+    package::Flags,
+    prompt, repository, runtime, state,
 };
 use nix::unistd::gethostname;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tui::Styled;
 
+pub mod archive;
+
 pub fn command() -> Command {
     Command::new("state")
         .about("Manage state")
@@ -30,22 +38,53 @@ pub fn command() -> Command {
             Command::new("activate")
                 .about("Activate a state")
                 .arg(
-                    arg!(<ID> "State id to be activated")
+                    arg!(<ID> "State id or tag to be activated")
                         .action(ArgAction::Set)
-                        .value_parser(clap::value_parser!(u64)),
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(--"skip-triggers" "Do not run triggers on activation").action(ArgAction::SetTrue))
+                .arg(
+                    arg!(--strict "Warn & ask for confirmation if the repositories differ from those of the state")
+                        .long_help(
+                            "Compare the repositories & index hashes recorded when the state was created \
+                             against the active repositories, and ask for confirmation before activating \
+                             if any were added, removed or changed since",
+                        )
+                        .action(ArgAction::SetTrue),
                 )
-                .arg(arg!(--"skip-triggers" "Do not run triggers on activation").action(ArgAction::SetTrue)),
+                .arg(
+                    arg!(--snapshot "Restore the filesystem snapshot recorded for the state, on next boot")
+                        .long_help(
+                            "Restore the btrfs or ZFS snapshot taken while the state was active, including \
+                             /etc & the moss databases. The running root can't be replaced, so the restored \
+                             snapshot becomes the default root on the next boot.
+
+Snapshots are taken before applying new states when enabled in /etc/moss/snapshot.yaml",
+                        )
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("skip-triggers"),
+                ),
         )
         .subcommand(
             Command::new("query").about("Query information for a state").arg(
-                arg!(<ID> "State id to query")
+                arg!(<ID> "State id or tag to query")
                     .action(ArgAction::Set)
-                    .value_parser(clap::value_parser!(u64)),
+                    .value_parser(clap::value_parser!(state::Reference)),
             ),
         )
         .subcommand(
             Command::new("prune")
                 .about("Prune archived states")
+                .long_about(
+                    "Prune archived states, keeping the most recent ones along with the active state. States \
+                     referenced by boot entries are kept unless --force is given, removing those entries. \
+                     Nothing is pruned without --force when the boot entries can't be enumerated.
+
+With --policy, states are pruned by the policy configured in /etc/moss/prune.yaml & /etc/moss/prune.d, \
+overridden by the options given. The resulting policy is persisted with --save, and applied after each \
+successful transaction once --auto is enabled. The policy always keeps states referenced by boot entries, \
+and is skipped after a transaction when they can't be enumerated.",
+                )
                 .arg(
                     arg!(-k --keep "Keep this many states")
                         .action(ArgAction::Set)
@@ -54,34 +93,180 @@ pub fn command() -> Command {
                 )
                 .arg(
                     arg!(--"include-newer" "Include states newer than the active state when pruning")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("policy"),
+                )
+                .arg(arg!(--policy "Prune by the configured policy").action(ArgAction::SetTrue))
+                .arg(
+                    arg!(--"keep-daily" <N> "Keep the newest state of each of the last N days")
+                        .value_parser(clap::value_parser!(u64))
+                        .requires("policy"),
+                )
+                .arg(
+                    arg!(--"keep-weekly" <N> "Keep the newest state of each of the last N weeks")
+                        .value_parser(clap::value_parser!(u64))
+                        .requires("policy"),
+                )
+                .arg(
+                    arg!(--"keep-tagged" <BOOL> "Keep all tagged states")
+                        .value_parser(clap::value_parser!(bool))
+                        .requires("policy"),
+                )
+                .arg(
+                    arg!(--auto <BOOL> "Prune by the policy after each successful transaction")
+                        .value_parser(clap::value_parser!(bool))
+                        .requires("policy"),
+                )
+                .arg(
+                    arg!(--save "Persist the policy to /etc/moss/prune.d/policy.yaml")
+                        .action(ArgAction::SetTrue)
+                        .requires("policy"),
+                )
+                .arg(
+                    arg!(--force "Also prune states referenced by boot entries, removing those entries")
                         .action(ArgAction::SetTrue),
                 ),
         )
         .subcommand(
-            Command::new("remove").about("Remove an archived state").arg(
-                arg!(<ID> "State id to be removed")
-                    .action(ArgAction::Set)
-                    .value_parser(clap::value_parser!(u64)),
+            Command::new("remove")
+                .about("Remove an archived state")
+                .arg(
+                    arg!(<ID> "State id or tag to be removed")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(
+                    arg!(--force "Remove the state even if boot entries reference it, removing those entries")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("edit")
+                .about("Edit the summary or description of a state")
+                .long_about(
+                    "Annotate a state after the fact, i.e. to record why it exists. \
+                     An empty value clears the field.",
+                )
+                .arg(
+                    arg!(<ID> "State id or tag to be edited")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(-s --summary <SUMMARY> "Replace the summary").action(ArgAction::Set))
+                .arg(arg!(-d --description <DESCRIPTION> "Replace the description").action(ArgAction::Set))
+                .group(
+                    ArgGroup::new("fields")
+                        .args(["summary", "description"])
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("tag")
+                .about("Name a state with a tag")
+                .long_about(
+                    "Name a state with a tag, which is accepted anywhere a state id is. \
+                     A tag names a single state, tagging another state with it moves the tag.",
+                )
+                .arg(
+                    arg!(<ID> "State id or tag to be tagged")
+                        .action(ArgAction::Set)
+                        .value_parser(clap::value_parser!(state::Reference)),
+                )
+                .arg(arg!(<TAG> "Tag to name the state with").action(ArgAction::Set)),
+        )
+        .subcommand(
+            Command::new("untag")
+                .about("Remove a tag from its state")
+                .arg(arg!(<TAG> "Tag to be removed").action(ArgAction::Set)),
+        )
+        .subcommand(
+            Command::new("repair")
+                .about("Recover a transaction interrupted by a crash or power loss")
+                .long_about(
+                    "Recover a transaction interrupted by a crash or power loss, as recorded in its journal. \
+                     The transaction is rolled back if /usr wasn't replaced yet, otherwise rolled forward \
+                     to complete the activation of the new state. Likewise, the activation of a state \
+                     applied on reboot is completed once booted into.
+
+This is done automatically before any change to the installation.",
+                ),
+        )
+        .subcommand(
+            Command::new("apply-staged")
+                .about("Activate the state staged by `moss sync --stage`")
+                .long_about(
+                    "Activate the state staged by `moss sync --stage`, as done early during boot by \
+                     moss-apply-staged.service. A staged state is discarded if another state was \
+                     activated since it was staged.",
+                ),
+        )
+        .subcommand(
+            Command::new("verify").about("Verify TODO").arg(
+                arg!(--"against-repo" "Check every package of the active state is still published, instead of the files")
+                    .long_help(
+                        "Check every package of the active state is still published in an active repository \
+                         with the same hash, rather than verifying the installed files. Packages yanked \
+                         from their repository or installed from a local stone are listed, i.e. before \
+                         exporting the state for another machine.",
+                    )
+                    .action(ArgAction::SetTrue),
             ),
         )
         .subcommand(
-            Command::new("verify")
-                .about("Verify TODO")
-                .arg(arg!(--verbose "Vebose output").action(ArgAction::SetTrue)),
+            Command::new("complete")
+                .about("Complete the activation of the active state, as handed over by a replaced moss")
+                .hide(true)
+                .arg(arg!(--"skip-triggers" "Do not run system triggers").action(ArgAction::SetTrue))
+                .arg(arg!(--"sync-boot" "Synchronize boot").action(ArgAction::SetTrue)),
         )
         .subcommand(Export::command())
 }
 
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand() {
+        Some(("active" | "list" | "query" | "export", _)) => false,
+        // Only repairs when checking the files of the state
+        Some(("verify", args)) => !args.get_flag("against-repo"),
+        Some((
+            "activate" | "prune" | "remove" | "edit" | "tag" | "untag" | "repair" | "apply-staged" | "complete",
+            _,
+        )) => true,
+        _ => unreachable!(),
+    }
+}
+
 #[derive(Debug, Parser)]
 #[command(name = "export", about = "Export a state as a system-model.kdl file")]
 struct Export {
-    /// State id to export or current state if omitted
-    id: Option<i32>,
+    /// State id or tag to export or current state if omitted
+    id: Option<state::Reference>,
     /// Export to the provided path or stdout if not supplied
     ///
-    /// If supplied without a path or path is a directory, outputs to "system-model-{hostname}-fstxn-{id}.kdl"
+    /// If supplied without a path or path is a directory, outputs to "system-model-{hostname}-fstxn-{id}.{format}"
     #[arg(short, long)]
     output: Option<Option<PathBuf>>,
+    /// Format to export as
+    ///
+    /// `json` & `toml` pin every package of the state by version & hash, rather than
+    /// listing only the explicitly selected packages as `kdl` does
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Kdl)]
+    format: ExportFormat,
+    /// Export an archive bundling the system-model with the stone of every package
+    ///
+    /// The archive is applied with `moss sync --import-archive`, without network access.
+    /// Written to "moss-state-{hostname}-fstxn-{id}.tar" unless --output is a file.
+    #[arg(long, conflicts_with = "format")]
+    archive: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+enum ExportFormat {
+    Kdl,
+    Json,
+    Toml,
 }
 
 pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
@@ -92,8 +277,14 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         Some(("query", args)) => query(args, installation),
         Some(("prune", args)) => prune(args, installation),
         Some(("remove", args)) => remove(args, installation),
+        Some(("edit", args)) => edit(args, installation),
+        Some(("tag", args)) => tag(args, installation),
+        Some(("untag", args)) => untag(args, installation),
+        Some(("repair", _)) => repair(installation),
+        Some(("apply-staged", _)) => apply_staged(installation),
         Some(("verify", args)) => verify(args, installation),
         Some(("export", args)) => export(args, installation),
+        Some(("complete", args)) => complete(args, installation),
         _ => unreachable!(),
     }
 }
@@ -128,11 +319,32 @@ pub fn list(installation: Installation) -> Result<(), Error> {
 }
 
 pub fn activate(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let new_id = *args.get_one::<u64>("ID").unwrap() as i32;
+    let reference = args.get_one::<state::Reference>("ID").unwrap();
     let skip_triggers = args.get_flag("skip-triggers");
 
     let client = Client::new(environment::NAME, installation)?;
-    let old_id = client.activate_state(new_id.into(), skip_triggers)?;
+    let new_id = client.state_db.resolve(reference)?;
+
+    if args.get_flag("strict") {
+        let state = client.state_db.get(new_id)?;
+        confirm_repositories(&client, &state, args.get_flag("yes"))?;
+    }
+
+    if args.get_flag("snapshot") {
+        client.restore_snapshot(new_id)?;
+        println!(
+            "Snapshot of state {} restored, reboot to boot into it",
+            new_id.to_string().bold()
+        );
+        return Ok(());
+    }
+
+    let old_id = client.activate_state(new_id, skip_triggers)?;
+
+    if client.installation.apply_on_reboot {
+        println!("State {} staged, reboot to apply it", new_id.to_string().bold());
+        return Ok(());
+    }
 
     println!(
         "State {} activated {}",
@@ -143,14 +355,80 @@ pub fn activate(args: &ArgMatches, installation: Installation) -> Result<(), Err
     Ok(())
 }
 
+/// Warn about repositories which changed since `state` was created, asking
+/// for confirmation to proceed if any did
+fn confirm_repositories(client: &Client, state: &State, yes: bool) -> Result<(), Error> {
+    if state.repositories.is_empty() {
+        println!(
+            "{}: state {} has no recorded repositories to compare against",
+            "Warning".yellow(),
+            state.id
+        );
+        return Ok(());
+    }
+
+    let changes = client.repository_changes(state);
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    println!(
+        "{}: repositories changed since state {} was created",
+        "Warning".yellow(),
+        state.id
+    );
+    for change in &changes {
+        match change {
+            state::RepositoryChange::Removed(repo) => {
+                println!("  {} {} {}", "-".red(), repo.id, repo.uri.as_str().dim());
+            }
+            state::RepositoryChange::Added(repo) => {
+                println!("  {} {} {}", "+".green(), repo.id, repo.uri.as_str().dim());
+            }
+            state::RepositoryChange::Changed { recorded, current } => {
+                println!("  {} {}", "~".yellow(), recorded.id);
+                if recorded.uri != current.uri {
+                    println!("      uri {} → {}", recorded.uri.as_str().dim(), current.uri);
+                }
+                if recorded.priority != current.priority {
+                    println!("      priority {} → {}", recorded.priority, current.priority);
+                }
+                if recorded.index_hash != current.index_hash {
+                    println!(
+                        "      index {} → {}",
+                        short_hash(recorded.index_hash.as_deref()).dim(),
+                        short_hash(current.index_hash.as_deref())
+                    );
+                }
+            }
+        }
+    }
+    println!();
+
+    if !prompt::confirm("activate state", " Do you wish to continue? ", yes)? {
+        return Err(Error::Cancelled);
+    }
+
+    Ok(())
+}
+
+fn short_hash(hash: Option<&str>) -> &str {
+    hash.map_or("none", |hash| &hash[..hash.len().min(12)])
+}
+
 pub fn query(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let id = *args.get_one::<u64>("ID").unwrap() as i32;
+    let reference = args.get_one::<state::Reference>("ID").unwrap();
 
     let client = Client::new(environment::NAME, installation)?;
 
-    let state = client.state_db.get(id.into())?;
+    let id = client.state_db.resolve(reference)?;
+    let state = client.state_db.get(id)?;
+
+    let boot = client.boot_assets(&state)?;
 
     print_state(state.clone());
+    print_boot_assets(&boot);
+    print_state_repositories(&state);
     print_state_selections(state, &client);
 
     Ok(())
@@ -159,52 +437,288 @@ pub fn query(args: &ArgMatches, installation: Installation) -> Result<(), Error>
 pub fn prune(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let keep = *args.get_one::<u64>("keep").unwrap();
     let include_newer = args.get_flag("include-newer");
+    let force = args.get_flag("force");
     let yes = args.get_flag("yes");
 
     let client = Client::new(environment::NAME, installation)?;
-    client.prune_states(prune::Strategy::KeepRecent { keep, include_newer }, yes)?;
+
+    let strategy = if args.get_flag("policy") {
+        let mut policy = client.prune_policy();
+
+        // Only an explicit --keep overrides the policy
+        if args.value_source("keep") == Some(ValueSource::CommandLine) {
+            policy.keep = keep;
+        }
+        if let Some(keep_daily) = args.get_one::<u64>("keep-daily") {
+            policy.keep_daily = *keep_daily;
+        }
+        if let Some(keep_weekly) = args.get_one::<u64>("keep-weekly") {
+            policy.keep_weekly = *keep_weekly;
+        }
+        if let Some(keep_tagged) = args.get_one::<bool>("keep-tagged") {
+            policy.keep_tagged = *keep_tagged;
+        }
+        if let Some(auto) = args.get_one::<bool>("auto") {
+            policy.auto = *auto;
+        }
+
+        if args.get_flag("save") {
+            client.save_prune_policy(&policy)?;
+            println!("{} prune policy", "Saved".green());
+        }
+
+        prune::Strategy::Policy(policy)
+    } else {
+        prune::Strategy::KeepRecent { keep, include_newer }
+    };
+
+    client.prune_states(strategy, force, yes)?;
 
     Ok(())
 }
 
 pub fn remove(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let id = *args.get_one::<u64>("ID").unwrap() as i32;
+    let reference = args.get_one::<state::Reference>("ID").unwrap();
+    let force = args.get_flag("force");
     let yes = args.get_flag("yes");
 
     let client = Client::new(environment::NAME, installation)?;
-    client.prune_states(prune::Strategy::Remove(id.into()), yes)?;
+    let id = client.state_db.resolve(reference)?;
+    client.prune_states(prune::Strategy::Remove(id), force, yes)?;
+
+    Ok(())
+}
+
+/// Replace the summary and / or description of a state
+pub fn edit(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let reference = args.get_one::<state::Reference>("ID").unwrap();
+    // An empty value clears the field
+    let field = |name| {
+        args.get_one::<String>(name)
+            .map(|value| Some(value.as_str()).filter(|value| !value.is_empty()))
+    };
+
+    let client = Client::new(environment::NAME, installation)?;
+    let id = client.state_db.resolve(reference)?;
+    let state = client.state_db.edit(id, field("summary"), field("description"))?;
+
+    print_state(state);
+
+    Ok(())
+}
+
+/// Name a state with a tag
+pub fn tag(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let reference = args.get_one::<state::Reference>("ID").unwrap();
+    let tag = args.get_one::<String>("TAG").unwrap();
+
+    state::validate_tag(tag)?;
+
+    let client = Client::new(environment::NAME, installation)?;
+    let id = client.state_db.resolve(reference)?;
+    // Ensure the state exists
+    client.state_db.get(id)?;
+    client.state_db.tag(id, tag)?;
+
+    println!("State {} tagged {}", id.to_string().bold(), tag.as_str().bold());
+
+    Ok(())
+}
+
+/// Remove a tag from its state
+pub fn untag(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let tag = args.get_one::<String>("TAG").unwrap();
+
+    let client = Client::new(environment::NAME, installation)?;
+    if !client.state_db.untag(tag)? {
+        return Err(Error::DB(moss::db::Error::UnknownTag(tag.clone())));
+    }
+
+    println!("Tag {} removed", tag.as_str().bold());
+
+    Ok(())
+}
+
+/// Recover an interrupted transaction
+fn repair(installation: Installation) -> Result<(), Error> {
+    let recovered = recover(&installation)?;
+    let completed = pending::is_booted(&installation) && complete_pending(&installation)?;
+
+    if !recovered && !completed {
+        println!("No interrupted transaction to repair");
+    }
+
+    Ok(())
+}
+
+/// Recover the transaction interrupted by a crash or power loss, if any
+///
+/// Returns `true` if there was one.
+pub fn recover(installation: &Installation) -> Result<bool, Error> {
+    let client = Client::new(environment::NAME, installation.clone())?;
+
+    match client.recover()? {
+        Some(recovery) => {
+            println!("{recovery}");
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Activate the state staged by `moss sync --stage`, if any
+fn apply_staged(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    match client.apply_staged()? {
+        Some(pending) => println!(
+            "State {} activated {}",
+            pending.state.to_string().bold(),
+            pending
+                .old_state
+                .map(|old| format!("({old} archived)").dim().to_string())
+                .unwrap_or_default()
+        ),
+        None => println!("No staged state to apply"),
+    }
 
     Ok(())
 }
 
+/// Complete the activation of the state applied on reboot, if it was booted into
+///
+/// Returns `true` if there was one.
+pub fn complete_pending(installation: &Installation) -> Result<bool, Error> {
+    let client = Client::new(environment::NAME, installation.clone())?;
+
+    match client.complete_pending()? {
+        Some(pending) => {
+            println!(
+                "State {} applied on reboot, activation completed",
+                pending.state.to_string().bold()
+            );
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
 pub fn verify(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
-    let verbose = args.get_flag("verbose");
+    // Uses the global `-v` flag
+    let verbose = args.get_count("verbose") > 0;
     let yes = args.get_flag("yes");
 
     let client = Client::new(environment::NAME, installation)?;
+
+    if args.get_flag("against-repo") {
+        return verify_against_repo(&client);
+    }
+
     client.verify(yes, verbose)?;
 
     Ok(())
 }
 
+/// Check the packages of the active state are published in an active repository
+fn verify_against_repo(client: &Client) -> Result<(), Error> {
+    let id = client.installation.active_state.ok_or(Error::NoActiveState)?;
+    let state = client.state_db.get(id)?;
+    let mut packages = client.resolve_packages(state.selections.iter().map(|selection| &selection.package))?;
+    packages.sort_by(|a, b| a.meta.name.cmp(&b.meta.name));
+
+    let version = |package: &Package| format!("{}-{}", package.meta.version_identifier, package.meta.source_release);
+    let mut unpublished = 0;
+
+    for package in &packages {
+        // The id is the hash of the stone, so the same id is the same package
+        let published = client
+            .registry
+            .by_id(&package.id)
+            .find(|candidate| candidate.flags.available)
+            .and_then(|candidate| client.origin(&candidate));
+        if published.is_some() {
+            continue;
+        }
+
+        unpublished += 1;
+        let upstream = client
+            .registry
+            .by_name(&package.meta.name, Flags::new().with_available())
+            .next();
+        let reason = match upstream {
+            Some(upstream) => format!("not published, repositories have {}", version(&upstream)),
+            None => "not in any repository, installed locally or removed upstream".to_owned(),
+        };
+        println!(
+            " {} {} {} {reason}",
+            "×".yellow(),
+            package.meta.name.to_string().bold(),
+            version(package).dim(),
+        );
+    }
+
+    if unpublished > 0 {
+        println!();
+        return Err(Error::Unpublished(unpublished));
+    }
+
+    println!(
+        "All {} packages of state #{id} are published in an active repository",
+        packages.len()
+    );
+
+    Ok(())
+}
+
+/// Complete an activation handed over by the moss of the previous state
+fn complete(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let skip_triggers = args.get_flag("skip-triggers");
+    let sync_boot = args.get_flag("sync-boot");
+
+    let client = Client::new(environment::NAME, installation)?.skip_triggers(skip_triggers);
+    client.complete_activation(sync_boot)?;
+
+    Ok(())
+}
+
 fn export(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let export = Export::from_arg_matches(args).expect("validate by clap");
-
-    let id = match export.id {
-        Some(id) => state::Id::from(id),
-        None => installation.active_state.ok_or(Error::NoActiveState)?,
-    };
+    let active_state = installation.active_state;
 
     let client = Client::new(environment::NAME, installation)?;
+
+    let id = match &export.id {
+        Some(reference) => client.state_db.resolve(reference)?,
+        None => active_state.ok_or(Error::NoActiveState)?,
+    };
     let system_model = client.export_state(id)?;
 
+    if export.archive {
+        return export_archive(&client, id, &system_model, export.output.flatten());
+    }
+
+    let contents = match export.format {
+        ExportFormat::Kdl => system_model.encoded().to_owned(),
+        ExportFormat::Json | ExportFormat::Toml => {
+            let state = client.state_db.get(id)?;
+            let pinned = PinnedState::new(&client, state, system_model.repositories)?;
+
+            if matches!(export.format, ExportFormat::Json) {
+                serde_json::to_string_pretty(&pinned)?
+            } else {
+                toml::to_string(&pinned)?
+            }
+        }
+    };
+
     match export.output {
         Some(maybe_path) => {
+            let format = export.format;
             let format_filename = || {
                 if let Some(hostname) = gethostname().ok().and_then(|s| s.into_string().ok()) {
-                    format!("system-model-{hostname}-fstxn-{id}.kdl")
+                    format!("system-model-{hostname}-fstxn-{id}.{format}")
                 } else {
-                    format!("system-model-fstxn-{id}.kdl")
+                    format!("system-model-fstxn-{id}.{format}")
                 }
             };
 
@@ -219,18 +733,110 @@ fn export(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
                 None => Path::new(".").join(format_filename()),
             };
 
-            fs::write(&path, system_model.encoded())?;
+            fs::write(&path, contents)?;
 
             println!("Exported to {path:?}");
         }
         None => {
-            println!("{}", system_model.encoded());
+            println!("{}", contents.trim_end());
         }
     }
 
     Ok(())
 }
 
+/// Export state `id` as an [`archive`] to `output`
+fn export_archive(
+    client: &Client,
+    id: state::Id,
+    system_model: &SystemModel,
+    output: Option<PathBuf>,
+) -> Result<(), Error> {
+    let filename = || match gethostname().ok().and_then(|s| s.into_string().ok()) {
+        Some(hostname) => format!("moss-state-{hostname}-fstxn-{id}.tar"),
+        None => format!("moss-state-fstxn-{id}.tar"),
+    };
+    let path = match output {
+        Some(path) if path.is_dir() => path.join(filename()),
+        Some(path) => path,
+        None => Path::new(".").join(filename()),
+    };
+
+    let state = client.state_db.get(id)?;
+    let packages = client.resolve_packages(state.selections.iter().map(|selection| &selection.package))?;
+    let stones = runtime::block_on(client.fetch_stones(&packages))?;
+
+    let pinned = PinnedState::new(client, state, system_model.repositories.clone())?;
+    let manifest = serde_json::to_string_pretty(&pinned)?;
+
+    archive::write(&path, system_model.encoded(), &manifest, &stones)?;
+
+    println!("Exported {} packages to {path:?}", stones.len());
+
+    Ok(())
+}
+
+/// A state with every package pinned, for tools that can't consume a system-model
+#[derive(Debug, Serialize, Deserialize)]
+struct PinnedState {
+    state: i32,
+    created: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    repositories: repository::Map,
+    packages: Vec<PinnedPackage>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct PinnedPackage {
+    name: String,
+    version: String,
+    source_release: u64,
+    build_release: u64,
+    architecture: String,
+    /// Hash of the stone, which also identifies the package
+    hash: String,
+    explicit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl PinnedState {
+    fn new(client: &Client, state: State, repositories: repository::Map) -> Result<Self, Error> {
+        let packages = client.resolve_packages(state.selections.iter().map(|selection| &selection.package))?;
+
+        let mut packages = packages
+            .into_iter()
+            .map(|package| {
+                let selection = state
+                    .selections
+                    .iter()
+                    .find(|selection| selection.package == package.id);
+
+                PinnedPackage {
+                    name: package.meta.name.to_string(),
+                    version: package.meta.version_identifier,
+                    source_release: package.meta.source_release,
+                    build_release: package.meta.build_release,
+                    architecture: package.meta.architecture,
+                    hash: package.meta.hash.unwrap_or_else(|| package.id.to_string()),
+                    explicit: selection.is_some_and(|selection| selection.explicit),
+                    reason: selection.and_then(|selection| selection.reason.clone()),
+                }
+            })
+            .collect::<Vec<_>>();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Self {
+            state: state.id.into(),
+            created: state.created.to_rfc3339(),
+            summary: state.summary,
+            repositories,
+            packages,
+        })
+    }
+}
+
 /// Emit a state description for the TUI
 fn print_state(state: State) {
     let local_time = state.created.with_timezone(&Local);
@@ -245,10 +851,76 @@ fn print_state(state: State) {
     if let Some(desc) = &state.description {
         println!("{} {desc}", "Description:".bold());
     }
+    if !state.tags.is_empty() {
+        println!("{} {}", "Tags:".bold(), state.tags.join(", "));
+    }
     println!("{} {}", "Packages:".bold(), state.selections.len());
     println!();
 }
 
+/// Emit the repositories recorded when a state was created
+fn print_state_repositories(state: &State) {
+    if state.repositories.is_empty() {
+        return;
+    }
+
+    println!("{}", "Repositories:".bold());
+    for repo in &state.repositories {
+        println!(
+            "  {} {} {}",
+            repo.id,
+            repo.uri.as_str().dim(),
+            format!("(index {})", short_hash(repo.index_hash.as_deref())).dim()
+        );
+    }
+    println!();
+}
+
+/// Emit the kernels, initrds & boot loader entries of a state
+fn print_boot_assets(boot: &boot::StateAssets) {
+    if !boot.is_bootable() {
+        return;
+    }
+
+    for kernel in &boot.kernels {
+        println!("{} {}", "Kernel:".bold(), kernel.version);
+        if let Some(image) = &kernel.image {
+            println!("  {} {}", "Image:".dim(), image.display());
+        }
+        for initrd in &kernel.initrds {
+            println!("  {} {}", "Initrd:".dim(), initrd.display());
+        }
+    }
+
+    if boot.entries.is_empty() {
+        println!("{} {}", "Boot entry:".bold(), "none".dim());
+    }
+    for entry in &boot.entries {
+        match &entry.title {
+            Some(title) => println!(
+                "{} {} {}",
+                "Boot entry:".bold(),
+                title,
+                entry.path.display().to_string().dim()
+            ),
+            None => println!("{} {}", "Boot entry:".bold(), entry.path.display()),
+        }
+    }
+
+    if !boot.missing.is_empty() {
+        println!(
+            "{}: {} boot asset(s) of this state have been pruned and it can no longer be booted",
+            "Warning".yellow(),
+            boot.missing.len()
+        );
+        for path in &boot.missing {
+            println!("  {} {}", "×".yellow(), path.display());
+        }
+    }
+
+    println!();
+}
+
 fn print_state_selections(state: State, client: &Client) {
     let set: Vec<_> = state
         .selections
@@ -261,6 +933,7 @@ fn print_state_selections(state: State, client: &Client) {
                     release: pkg.meta.source_release,
                 },
                 explicit: s.explicit,
+                reason: s.reason,
             })
         })
         .collect();
@@ -275,11 +948,15 @@ fn print_state_selections(state: State, client: &Client) {
             item.name.clone().dim()
         };
         print!("{name} {:width$} ", " ");
-        println!(
+        print!(
             "{}-{}",
             item.revision.version.magenta(),
             item.revision.release.to_string().dim(),
         );
+        match &item.reason {
+            Some(reason) => println!(" {}", format!("({reason})").dim()),
+            None => println!(),
+        }
     }
     println!();
 }
@@ -289,6 +966,7 @@ struct Format {
     name: String,
     revision: Revision,
     explicit: bool,
+    reason: Option<String>,
 }
 
 impl Format {
@@ -319,4 +997,95 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("no active state")]
     NoActiveState,
+    #[error("encode json")]
+    Json(#[from] serde_json::Error),
+    #[error("encode toml")]
+    Toml(#[from] toml::ser::Error),
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
+    #[error("cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    InvalidTag(#[from] state::InvalidTag),
+    #[error("archive")]
+    Archive(#[from] archive::Error),
+    #[error("{0} packages not published in any active repository")]
+    Unpublished(usize),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pinned() -> PinnedState {
+        let volatile = repository::Repository {
+            description: "Volatile".to_owned(),
+            uri: "https://example.com/volatile/x86_64/stone.index".parse().unwrap(),
+            priority: repository::Priority::new(10),
+            active: true,
+            aliases: [("firefox-esr".to_owned(), "firefox".to_owned())].into(),
+        };
+        let package = |name: &str, hash: &str, reason: Option<&str>| PinnedPackage {
+            name: name.to_owned(),
+            version: "1.0".to_owned(),
+            source_release: 2,
+            build_release: 1,
+            architecture: "x86_64".to_owned(),
+            hash: hash.to_owned(),
+            explicit: reason.is_none(),
+            reason: reason.map(str::to_owned),
+        };
+
+        PinnedState {
+            state: 12,
+            created: "2025-01-01T00:00:00+00:00".to_owned(),
+            summary: None,
+            repositories: repository::Map::with([(repository::Id::new("volatile"), volatile)]),
+            packages: vec![
+                package("firefox", "1b2c3d", None),
+                package("nss", "4e5f6a", Some("dependency of firefox")),
+            ],
+        }
+    }
+
+    fn assert_pinned(decoded: &PinnedState, pinned: &PinnedState) {
+        assert_eq!(decoded.state, pinned.state);
+        assert_eq!(decoded.created, pinned.created);
+        assert_eq!(decoded.summary, None);
+        assert_eq!(decoded.packages, pinned.packages);
+
+        let (id, repository) = decoded.repositories.iter().next().unwrap();
+        assert_eq!(id, &repository::Id::new("volatile"));
+        assert_eq!(
+            repository.uri.as_str(),
+            "https://example.com/volatile/x86_64/stone.index"
+        );
+        assert_eq!(repository.priority, repository::Priority::new(10));
+        assert_eq!(
+            repository.aliases.get("firefox-esr").map(String::as_str),
+            Some("firefox")
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let pinned = pinned();
+
+        let json = serde_json::to_string_pretty(&pinned).unwrap();
+        let decoded = serde_json::from_str::<PinnedState>(&json).unwrap();
+
+        assert_pinned(&decoded, &pinned);
+        assert_eq!(serde_json::to_string_pretty(&decoded).unwrap(), json);
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let pinned = pinned();
+
+        let toml = toml::to_string(&pinned).unwrap();
+        let decoded = toml::from_str::<PinnedState>(&toml).unwrap();
+
+        assert_pinned(&decoded, &pinned);
+        assert_eq!(toml::to_string(&decoded).unwrap(), toml);
+    }
 }
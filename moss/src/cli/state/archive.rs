@@ -0,0 +1,223 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Archives bundling a state with the stones of its packages
+//!
+//! An archive is a tar of:
+//!
+//! - `system-model.kdl`, the system-model of the state
+//! - `state.json`, the state with every package pinned by hash, along with the
+//!   repositories they came from
+//! - `stones/`, the stone of every package of the state
+//!
+//! `moss sync --import-archive` verifies the bundled stones against their pins and
+//! syncs to exactly the pinned packages, reproducing the state on machines without
+//! network access.
+
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err::{self as fs, File};
+use moss::{Installation, SystemModel, package, repository, system_model};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::cli::export::tar;
+
+const SYSTEM_MODEL: &str = "system-model.kdl";
+const MANIFEST: &str = "state.json";
+const STONES: &str = "stones";
+
+/// Repository the bundled stones are imported from
+const REPOSITORY: &str = "archive";
+
+/// Write an archive of the `system_model` & `manifest` of a state along with its `stones` to `output`
+pub fn write(output: &Path, system_model: &str, manifest: &str, stones: &[PathBuf]) -> Result<(), Error> {
+    let mut builder = tar::Builder::new(io::BufWriter::new(File::create(output)?));
+
+    builder.append_data(SYSTEM_MODEL, system_model.as_bytes())?;
+    builder.append_data(MANIFEST, manifest.as_bytes())?;
+    builder.append_dir(STONES)?;
+    for stone in stones {
+        // Cached stones are named by their hash
+        let name = stone.file_name().unwrap_or_default().to_string_lossy();
+        builder.append_file(&format!("{STONES}/{name}.stone"), stone)?;
+    }
+
+    builder.finish()?;
+
+    Ok(())
+}
+
+/// An archive unpacked into the cache of an installation
+pub struct Archive {
+    dir: PathBuf,
+    packages: Vec<package::Id>,
+}
+
+/// The part of `state.json` needed for the import
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    packages: Vec<Pin>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pin {
+    name: String,
+    hash: String,
+}
+
+impl Archive {
+    /// Unpack the archive at `path` into the cache of `installation`
+    ///
+    /// Archives are kept in the cache, as the repository of states created from
+    /// them refers to the unpacked stones. They're keyed by the hash of the archive,
+    /// so importing the same archive again reuses them and different archives never
+    /// replace the stones of another.
+    pub fn unpack(path: &Path, installation: &Installation) -> Result<Self, Error> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+
+        let archives = installation.cache_path("archives");
+        let dir = archives.join(hex::encode(hasher.finalize()));
+
+        if !dir.exists() {
+            fs::create_dir_all(&archives)?;
+            // Unpacked aside, so an interrupted unpack never passes for the archive
+            let staging = tempfile::Builder::new().prefix(".unpack-").tempdir_in(&archives)?;
+            tar::unpack(io::BufReader::new(File::open(path)?), staging.path())
+                .map_err(|err| Error::Unpack(path.to_owned(), err))?;
+            fs::rename(staging.path(), &dir)?;
+        }
+
+        if !dir.join(SYSTEM_MODEL).exists() || !dir.join(STONES).is_dir() || !dir.join(MANIFEST).exists() {
+            return Err(Error::InvalidArchive(path.to_owned()));
+        }
+
+        let manifest = serde_json::from_str::<Manifest>(&fs::read_to_string(dir.join(MANIFEST))?)?;
+        verify_stones(&dir.join(STONES), &manifest)?;
+
+        Ok(Self {
+            dir,
+            packages: manifest
+                .packages
+                .into_iter()
+                .map(|pin| package::Id::from(pin.hash))
+                .collect(),
+        })
+    }
+
+    /// The packages pinned by the archived state, each bundled & verified
+    pub fn packages(&self) -> &[package::Id] {
+        &self.packages
+    }
+
+    /// The system-model of the archived state
+    pub fn system_model(&self) -> Result<SystemModel, Error> {
+        let path = self.dir.join(SYSTEM_MODEL);
+        // The recorded system model is already rendered
+        system_model::load(&path, &Default::default())?.ok_or(Error::InvalidArchive(path))
+    }
+
+    /// A single repository of the bundled stones
+    pub fn repositories(&self) -> Result<repository::Map, Error> {
+        let stones = self.dir.join(STONES);
+        let uri = Url::from_directory_path(&stones).map_err(|()| Error::InvalidArchive(stones))?;
+
+        Ok(repository::Map::with([(
+            repository::Id::new(REPOSITORY),
+            repository::Repository {
+                description: "Stones bundled with an imported state archive".to_owned(),
+                uri,
+                priority: repository::Priority::new(0),
+                active: true,
+                aliases: Default::default(),
+            },
+        )]))
+    }
+}
+
+/// Verify the stones in `dir` are exactly those pinned by the `manifest`
+fn verify_stones(dir: &Path, manifest: &Manifest) -> Result<(), Error> {
+    let mut bundled = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<BTreeSet<_>, io::Error>>()?;
+
+    for pin in &manifest.packages {
+        let name = format!("{}.stone", pin.hash);
+        if !bundled.remove(&name) {
+            return Err(Error::MissingStone(pin.name.clone()));
+        }
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(dir.join(&name))?, &mut hasher)?;
+        if hex::encode(hasher.finalize()) != pin.hash {
+            return Err(Error::Checksum(pin.name.clone()));
+        }
+    }
+
+    match bundled.into_iter().next() {
+        Some(name) => Err(Error::UnpinnedStone(name)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0:?} isn't a state archive")]
+    InvalidArchive(PathBuf),
+    #[error("unpack {0:?}")]
+    Unpack(PathBuf, #[source] io::Error),
+    #[error("read state manifest")]
+    Manifest(#[from] serde_json::Error),
+    #[error("stone of pinned package {0} isn't bundled")]
+    MissingStone(String),
+    #[error("bundled stone of {0} doesn't match its pinned hash")]
+    Checksum(String),
+    #[error("bundled stone {0} isn't pinned by the state")]
+    UnpinnedStone(String),
+    #[error("load system model")]
+    LoadSystemModel(#[from] system_model::LoadError),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify_stones() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let hash = hex::encode(Sha256::digest(b"stone"));
+        fs::write(dir.join(format!("{hash}.stone")), b"stone").unwrap();
+
+        let manifest = |hash: &str| Manifest {
+            packages: vec![Pin {
+                name: "a".to_owned(),
+                hash: hash.to_owned(),
+            }],
+        };
+
+        assert!(verify_stones(dir, &manifest(&hash)).is_ok());
+        assert!(matches!(
+            verify_stones(dir, &Manifest { packages: vec![] }),
+            Err(Error::UnpinnedStone(_))
+        ));
+        assert!(matches!(
+            verify_stones(dir, &manifest(&"0".repeat(64))),
+            Err(Error::MissingStone(_))
+        ));
+
+        fs::write(dir.join(format!("{hash}.stone")), b"tampered").unwrap();
+        assert!(matches!(verify_stones(dir, &manifest(&hash)), Err(Error::Checksum(_))));
+    }
+}
@@ -3,28 +3,39 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::io::{self, Read};
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 
 use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use fs_err as fs;
+use futures_util::StreamExt;
 use itertools::Itertools;
 use moss::registry::transaction;
 use moss::state::Selection;
-use moss::{Installation, Provider, SystemModel, environment, runtime, system_model};
+use moss::{Installation, Provider, SystemModel, environment, prompt, request, runtime, signing, system_model};
 use moss::{
     Package,
-    client::{self, Client},
-    package::{self},
+    client::{self, Client, boot, essential},
+    package::{
+        self,
+        render::{self, Change},
+    },
 };
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use url::Url;
 
-use tracing::{Instrument, debug, info, info_span, instrument};
-use tui::dialoguer::Confirm;
-use tui::dialoguer::theme::ColorfulTheme;
-use tui::pretty::autoprint_columns;
+use tracing::{Instrument, debug, info, instrument};
+use tracing_common::progress;
+use tui::Styled;
+
+use super::{Outcome, state::archive};
 
 pub fn command() -> clap::Command {
-    Command::command()
+    Command::command().args(super::divergence_args())
 }
 
 #[derive(Debug, Parser)]
@@ -38,6 +49,9 @@ pub struct Command {
     /// Update repositories before syncing
     #[arg(short, long)]
     update: bool,
+    /// Update repositories whose metadata is older than this, i.e. 12h or 7d
+    #[arg(long, value_name = "duration", value_parser = moss::repository::expiry::parse_duration)]
+    max_metadata_age: Option<Duration>,
     /// Blit this sync to the provided directory instead of the root
     ///
     /// This operation won't be captured as a new state
@@ -46,14 +60,80 @@ pub struct Command {
 
     /// Sync against the provided system-model.kdl
     ///
-    /// Only the repositories and packages from the provided file
-    /// will be used to create the new state
-    #[arg(value_name = "file", long)]
-    import: Option<PathBuf>,
+    /// Read from a path, an `https://` URL or `-` for stdin. Only the repositories
+    /// and packages from the provided file will be used to create the new state.
+    /// Any other URL must be verified by `--import-sha256` or `--import-key` and
+    /// reading from stdin requires `--yes`, as the confirmation can't be prompted
+    #[arg(value_name = "file|url|-", long)]
+    import: Option<Location>,
+
+    /// Verify the imported system-model has this SHA-256 checksum
+    #[arg(value_name = "hex", long, requires = "import")]
+    import_sha256: Option<String>,
+
+    /// Verify the imported system-model is signed by this hex encoded ed25519 public key
+    ///
+    /// The detached signature, as written by `moss pack --sign-key`, is read from
+    /// `--import-signature` or else from the import location with a `.sig` suffix
+    #[arg(value_name = "hex", long, requires = "import")]
+    import_key: Option<String>,
+
+    /// Read the signature verified by `--import-key` from this path or URL
+    #[arg(value_name = "file|url", long, requires = "import_key")]
+    import_signature: Option<Location>,
+
+    /// Sync against a state archive exported by `moss state export --archive`
+    ///
+    /// Only the stones bundled with the archive are used, so no network access
+    /// is needed to reproduce the state
+    #[arg(value_name = "file", long, conflicts_with_all = ["import", "update", "max_metadata_age"])]
+    import_archive: Option<PathBuf>,
+
+    /// Only apply updates classified as security fixes
+    ///
+    /// Every other installed package is kept at its current version
+    #[arg(long, conflicts_with_all = ["import", "import_archive"])]
+    security_only: bool,
+
+    /// Only download the packages required for the sync without applying it
+    #[arg(long)]
+    download_only: bool,
+
+    /// Prepare the new state without activating it, which is deferred to the next boot
+    ///
+    /// The state is activated early during boot by `moss state apply-staged`, as run by
+    /// the moss-apply-staged.service unit of `moss generate-units --policy stage`
+    #[arg(long, conflicts_with_all = ["download_only", "blit_target"])]
+    stage: bool,
+
+    /// Allow removing the running kernel or the bootloader
+    #[arg(long)]
+    force_boot_critical: bool,
+
+    /// Allow removing essential packages, i.e. the C library or moss itself
+    ///
+    /// Essential packages are configured in /etc/moss/essential.yaml
+    #[arg(long)]
+    force_essential: bool,
+
+    /// Do not run triggers when applying the new state
+    #[arg(long)]
+    skip_triggers: bool,
+
+    /// Continue even if there isn't enough disk space for the transaction
+    #[arg(long)]
+    force: bool,
+
+    /// Keep the configured repositories as they are
+    ///
+    /// Otherwise, when the system-model declares repositories, those missing from
+    /// the configuration are added & any others are disabled to match it
+    #[arg(long)]
+    no_repo_reconcile: bool,
 }
 
 #[instrument(skip_all)]
-pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<Outcome, Error> {
     let command = Command::from_arg_matches(args).expect("validated by clap");
 
     let mut timing = Timing::default();
@@ -62,20 +142,48 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     let yes_all = *args.get_one::<bool>("yes").unwrap();
     let update = command.update;
 
-    let mut client = Client::new(environment::NAME, installation)?;
+    let archive = command
+        .import_archive
+        .as_deref()
+        .map(|path| archive::Archive::unpack(path, &installation))
+        .transpose()?;
+
+    let mut client = match &archive {
+        Some(archive) => Client::with_explicit_repositories(environment::NAME, installation, archive.repositories()?)?,
+        None => Client::new(environment::NAME, installation)?,
+    }
+    .skip_triggers(command.skip_triggers)
+    .force_space(command.force)
+    .stage(command.stage);
 
     // Make ephemeral if a blit target was provided
-    if let Some(blit_target) = command.blit_target {
+    if let Some(blit_target) = &command.blit_target {
         client = client.ephemeral(blit_target)?;
     }
 
-    // Update repos if requested
-    if update {
+    // Update repos if requested or their metadata is too old
+    let outdated = command.max_metadata_age.is_some_and(|max_age| {
+        client
+            .repository_freshness()
+            .into_iter()
+            .any(|(_, record)| record.is_none_or(|record| record.age() > max_age))
+    });
+    // The stones of an archive are indexed afresh as they're unpacked on each import
+    if update || outdated || archive.is_some() {
         runtime::block_on(client.refresh_repositories())?;
     }
-
-    let system_model = if let Some(path) = command.import {
-        Some(system_model::load(&path)?.ok_or(Error::ImportSystemModelDoesntExist(path))?)
+    super::warn_stale_metadata(&client);
+
+    let system_model = if let Some(archive) = &archive {
+        Some(archive.system_model()?)
+    } else if let Some(location) = &command.import {
+        check_import(location, &command, yes_all)?;
+        let content = location.read()?;
+        verify_import(&content, location, &command)?;
+        Some(system_model::parse(
+            &String::from_utf8(content)?,
+            &client.installation.variables,
+        )?)
     } else {
         client.installation.system_model.clone()
     };
@@ -83,9 +191,20 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     // Grab all the existing installed packages
     let installed = client.registry.list_installed().collect::<Vec<_>>();
 
+    let phase = progress::Phase::start("resolve", installed.len());
+
     // Resolve the final state of packages after considering sync updates
     let finalized = if let Some(system_model) = &system_model {
-        resolve_with_system_model(&client, system_model)?
+        if command.security_only {
+            return Err(Error::SecurityOnlyWithSystemModel);
+        }
+        match &archive {
+            // Exactly the packages pinned by the archived state
+            Some(archive) => client.resolve_packages(archive.packages())?,
+            None => resolve_with_system_model(&client, system_model)?,
+        }
+    } else if command.security_only {
+        resolve_security_only(&client, &installed)?
     } else {
         resolve_with_installed(&client, &installed)?
     };
@@ -101,6 +220,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     }
 
     timing.resolve = instant.elapsed();
+    phase.complete(finalized.len());
     info!(
         total_resolved = finalized.len(),
         resolve_time_ms = timing.resolve.as_millis(),
@@ -116,7 +236,10 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         .filter(|p| client.is_ephemeral() || !installed.iter().any(|i| i.id == p.id))
         .collect::<Vec<_>>();
     let (added, updated): (Vec<_>, Vec<_>) = synced.iter().partition_map(|p| {
-        if let Some(i) = installed.iter().find(|i| i.meta.name == p.meta.name)
+        if let Some(i) = installed
+            .iter()
+            .find(|i| i.meta.name == p.meta.name)
+            .or_else(|| installed.iter().find(|i| supersedes(p, i)))
             && !client.is_ephemeral()
         {
             itertools::Either::Right(package::Update { old: i, new: *p })
@@ -126,7 +249,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     });
     let removed = installed
         .iter()
-        .filter(|p| !client.is_ephemeral() && !finalized.iter().any(|f| f.meta.name == p.meta.name))
+        .filter(|p| !client.is_ephemeral() && !finalized.iter().any(|f| f.meta.name == p.meta.name || supersedes(f, p)))
         .cloned()
         .collect::<Vec<_>>();
 
@@ -137,80 +260,88 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         "Sync analysis completed"
     );
 
+    // Repositories of an archive only exist for the import
+    let reconcile = system_model
+        .as_ref()
+        .filter(|_| !command.no_repo_reconcile && archive.is_none() && !client.is_ephemeral());
+
     if synced.is_empty() && removed.is_empty() {
+        if let Some(system_model) = reconcile {
+            reconcile_repositories(&client, system_model)?;
+        }
         println!("No packages to sync");
-        return Ok(());
+        return Ok(Outcome::NothingToDo);
+    }
+
+    let critical = client.boot_critical(&removed)?;
+    if !critical.is_empty() && !command.force_boot_critical {
+        return Err(Error::BootCritical(critical));
+    }
+
+    let essential = client.essential(&removed);
+    if !essential.is_empty() && !command.force_essential {
+        return Err(Error::Essential(essential));
     }
 
     if !added.is_empty() {
         println!("The following packages will be added: ");
         println!();
-        autoprint_columns(added.as_slice());
+        render::print_changes(&added.iter().map(|p| Change::Add(p)).collect::<Vec<_>>());
         println!();
     }
     if !updated.is_empty() {
         println!("The following packages will be updated: ");
         println!();
-        autoprint_columns(updated.as_slice());
+        render::print_changes(&updated.iter().copied().map(Change::Update).collect::<Vec<_>>());
         println!();
     }
     if !removed.is_empty() {
         println!("The following orphaned packages will be removed: ");
         println!();
-        autoprint_columns(removed.as_slice());
+        render::print_changes(&removed.iter().map(Change::Remove).collect::<Vec<_>>());
         println!();
     }
 
+    let kept = super::resolve_divergence(&client, args, yes_all)?;
+    let client = client.keep_local(kept);
+
+    let outgoing = updated.iter().map(|u| u.old).chain(&removed).collect::<Vec<_>>();
+    client.check_space(&synced, &outgoing)?;
+
     // Must we prompt?
-    let result = if yes_all {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm("synchronize packages", " Do you wish to continue? ", yes_all)?;
     if !result {
         return Err(Error::Cancelled);
     }
 
     instant = Instant::now();
 
-    let cache_packages_span = info_span!("progress", phase = "cache_packages", event_type = "progress");
-    let _cache_packages_guard = cache_packages_span.enter();
-    info!(
-        total_items = synced.len(),
-        progress = 0.0,
-        event_type = "progress_start"
-    );
+    let phase = progress::Phase::start("cache_packages", synced.len());
 
     runtime::block_on(client.cache_packages(&synced).in_current_span())?;
 
     timing.fetch = instant.elapsed();
-    info!(
-        duration_ms = timing.fetch.as_millis(),
-        items_processed = synced.len(),
-        progress = 1.0,
-        event_type = "progress_completed",
-    );
-    drop(_cache_packages_guard);
+    phase.complete(synced.len());
+
+    if command.download_only {
+        println!("Packages downloaded, rerun without --download-only to apply the sync");
+        return Ok(Outcome::Done);
+    }
+
     instant = Instant::now();
 
+    let required_by = transaction::required_by(&finalized.iter().collect::<Vec<_>>());
+    let dependent_of = |p: &Package| required_by.get(&p.id).map(|dependent| &dependent.meta.name);
+
     let new_selections = if let Some(system_model) = &system_model {
         // For system model, "explicit" is what was defined in the system model file
 
         finalized
-            .into_iter()
+            .iter()
             .map(|p| {
                 let is_explicit = system_model.packages.intersection(&p.meta.providers).next().is_some();
 
-                Selection {
-                    package: p.id,
-                    explicit: is_explicit,
-                    // TODO: We can map the "why" of system-model packages to this? Or
-                    // can we remove "reason" entirely, we haven't used it to-date
-                    reason: None,
-                }
+                Selection::system_model(p.id.clone(), is_explicit, dependent_of(p))
             })
             .collect()
     } else {
@@ -221,13 +352,14 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
         };
 
         finalized
-            .into_iter()
+            .iter()
             .map(|p| {
-                // Use old version id to lookup previous selection
+                // Use old version id to lookup previous selection, following renames
                 let lookup_id = installed
                     .iter()
-                    .find_map(|i| (i.meta.name == p.meta.name).then_some(&i.id))
-                    .unwrap_or(&p.id);
+                    .find(|i| i.meta.name == p.meta.name)
+                    .or_else(|| installed.iter().find(|i| supersedes(p, i)))
+                    .map_or(&p.id, |i| &i.id);
 
                 previous_selections
                     .iter()
@@ -239,11 +371,7 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
                         ..s
                     })
                     // Must be transitive
-                    .unwrap_or(Selection {
-                        package: p.id,
-                        explicit: false,
-                        reason: None,
-                    })
+                    .unwrap_or_else(|| Selection::dependency(p.id.clone(), dependent_of(p)))
             })
             .collect::<Vec<_>>()
     };
@@ -251,17 +379,178 @@ pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error
     // Perfect, apply state.
     client.new_state(&new_selections, "Sync")?;
 
+    if let Some(system_model) = reconcile {
+        reconcile_repositories(&client, system_model)?;
+    }
+
     timing.blit = instant.elapsed();
+    timing.blit_phases = client.blit_timing();
 
     info!(
         blit_time_ms = timing.blit.as_millis(),
+        blit_plan_time_ms = timing.blit_phases.plan.as_millis(),
+        blit_directories_time_ms = timing.blit_phases.directories.as_millis(),
+        blit_files_time_ms = timing.blit_phases.files.as_millis(),
         total_time_ms = (timing.resolve + timing.fetch + timing.blit).as_millis(),
         "Sync completed successfully"
     );
 
+    Ok(Outcome::Done)
+}
+
+/// Reconcile the configured repositories with those declared by `system_model`, if any
+fn reconcile_repositories(client: &Client, system_model: &SystemModel) -> Result<(), Error> {
+    if system_model.repositories.iter().next().is_none() {
+        return Ok(());
+    }
+
+    let reconciliation = client.reconcile_repositories(&system_model.repositories)?;
+
+    for (ids, action) in [
+        (&reconciliation.added, "Added"),
+        (&reconciliation.updated, "Updated"),
+        (&reconciliation.disabled, "Disabled"),
+    ] {
+        for id in ids {
+            println!(
+                "{action} repository {} to match the system-model",
+                id.to_string().as_str().bold()
+            );
+        }
+    }
+
     Ok(())
 }
 
+/// Where an imported system-model, or its signature, is read from
+#[derive(Debug, Clone)]
+enum Location {
+    Stdin,
+    Url(Url),
+    Path(PathBuf),
+}
+
+impl FromStr for Location {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            return Ok(Location::Stdin);
+        }
+
+        match Url::parse(s) {
+            Ok(url) if matches!(url.scheme(), "http" | "https" | "file") => Ok(Location::Url(url)),
+            _ => Ok(Location::Path(PathBuf::from(s))),
+        }
+    }
+}
+
+impl Location {
+    fn read(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            Location::Stdin => {
+                let mut content = vec![];
+                io::stdin().read_to_end(&mut content)?;
+                Ok(content)
+            }
+            Location::Url(url) => Ok(runtime::block_on(fetch(url.clone()))?),
+            Location::Path(path) => {
+                if !path.exists() {
+                    return Err(Error::ImportSystemModelDoesntExist(path.clone()));
+                }
+                Ok(fs::read(path)?)
+            }
+        }
+    }
+
+    /// Whether the content read is trusted as is, unlike a plain `http://` or `file://` URL
+    fn is_trusted(&self) -> bool {
+        match self {
+            Location::Stdin | Location::Path(_) => true,
+            Location::Url(url) => url.scheme() == "https",
+        }
+    }
+
+    /// Location of the detached signature published alongside, if any
+    fn signature(&self) -> Option<Location> {
+        match self {
+            Location::Stdin => None,
+            Location::Url(url) => Url::parse(&format!("{url}.sig")).ok().map(Location::Url),
+            Location::Path(path) => {
+                let mut signature = path.clone().into_os_string();
+                signature.push(".sig");
+                Some(Location::Path(signature.into()))
+            }
+        }
+    }
+}
+
+async fn fetch(url: Url) -> Result<Vec<u8>, request::Error> {
+    let mut stream = request::get(url).await?;
+    let mut content = vec![];
+
+    while let Some(chunk) = stream.next().await {
+        content.extend_from_slice(&chunk?);
+    }
+
+    Ok(content)
+}
+
+/// Ensure the import from `location` can be verified & confirmed before it's read
+fn check_import(location: &Location, command: &Command, yes_all: bool) -> Result<(), Error> {
+    if !location.is_trusted() && command.import_sha256.is_none() && command.import_key.is_none() {
+        return Err(Error::UnverifiedImport);
+    }
+
+    // Stdin is consumed by the import, leaving nothing to answer the confirmation
+    let stdin = [Some(location), command.import_signature.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|location| matches!(location, Location::Stdin));
+    if stdin && !yes_all {
+        return Err(Error::ImportStdinConfirmation);
+    }
+
+    Ok(())
+}
+
+/// Verify the imported `content` read from `location` against the checksum and key
+/// given by `command`, if any
+fn verify_import(content: &[u8], location: &Location, command: &Command) -> Result<(), Error> {
+    if let Some(expected) = &command.import_sha256 {
+        let actual = hex::encode(Sha256::digest(content));
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(Error::ImportChecksum {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    if let Some(key) = &command.import_key {
+        let key = signing::PublicKey::from_hex(key).map_err(|_| Error::InvalidImportKey(key.clone()))?;
+        let signature = command
+            .import_signature
+            .clone()
+            .or_else(|| location.signature())
+            .ok_or(Error::MissingImportSignature)?
+            .read()?;
+
+        key.verify(content, &signature).map_err(|_| Error::ImportSignature)?;
+    }
+
+    Ok(())
+}
+
+/// Returns true if `package` replaces the `installed` package, i.e. it was renamed
+fn supersedes(package: &Package, installed: &Package) -> bool {
+    installed
+        .meta
+        .providers
+        .iter()
+        .any(|p| package.meta.replaces.contains(p))
+}
+
 /// Returns the resolved package set w/ sync'd changes swapped in using
 /// the provided installed `packages`
 ///
@@ -284,9 +573,25 @@ fn resolve_with_installed(client: &Client, packages: &[Package]) -> Result<Vec<P
                 .registry
                 .by_name(&p.meta.name, package::Flags::new().with_available())
                 .next()
-                && !all_ids.contains(&lookup.id)
             {
-                return Some(lookup.id);
+                return Some(if all_ids.contains(&lookup.id) {
+                    p.id.clone()
+                } else {
+                    lookup.id
+                });
+            }
+
+            // Renamed packages are swapped for the package superseding them, once
+            // they've left the repositories
+            if let Some(replacement) = client
+                .registry
+                .by_replaces(
+                    &Provider::package_name(p.meta.name.as_ref()),
+                    package::Flags::new().with_available(),
+                )
+                .next()
+            {
+                return Some(replacement.id);
             }
 
             Some(p.id.clone())
@@ -302,12 +607,38 @@ fn resolve_with_installed(client: &Client, packages: &[Package]) -> Result<Vec<P
     Ok(client.resolve_packages(tx.finalize())?)
 }
 
+/// Returns the installed package set with only the updates bringing security fixes applied
+///
+/// All installed packages are considered, as security fixes are just as likely in dependencies,
+/// and anything newly required by an update prefers what's already installed. An update brings
+/// the fixes of every release since the installed one, see [`package::Meta::is_security_update`].
+#[tracing::instrument(skip_all)]
+fn resolve_security_only(client: &Client, packages: &[Package]) -> Result<Vec<Package>, Error> {
+    let with_security = packages
+        .iter()
+        .map(|p| {
+            // Get first available = use highest priority
+            client
+                .registry
+                .by_name(&p.meta.name, package::Flags::new().with_available())
+                .next()
+                .filter(|lookup| lookup.id != p.id && lookup.meta.is_security_update(&p.meta))
+                .map_or_else(|| p.id.clone(), |lookup| lookup.id)
+        })
+        .collect::<Vec<_>>();
+
+    let mut tx = client.registry.transaction(transaction::Lookup::PreferInstalled)?;
+    tx.add(with_security)?;
+
+    Ok(client.resolve_packages(tx.finalize())?)
+}
+
 /// Returns the resolved package set based on the packages defined in the system model
 ///
 /// System model is the source of truth here vs "implicit" mode which relies on the active
 /// state + configured repos as the source of truth
 #[tracing::instrument(skip_all)]
-fn resolve_with_system_model(client: &Client, system_model: &SystemModel) -> Result<Vec<Package>, Error> {
+pub(super) fn resolve_with_system_model(client: &Client, system_model: &SystemModel) -> Result<Vec<Package>, Error> {
     // Lookup the available package for each
     let packages = system_model
         .packages
@@ -335,6 +666,8 @@ pub struct Timing {
     pub resolve: Duration,
     pub fetch: Duration,
     pub blit: Duration,
+    /// Breakdown of the blit itself, part of [`Self::blit`]
+    pub blit_phases: client::BlitTiming,
 }
 
 #[derive(Debug, Error)]
@@ -345,24 +678,93 @@ pub enum Error {
     #[error("cancelled")]
     Cancelled,
 
+    #[error("--security-only can't be used with a system model, update it by hand instead")]
+    SecurityOnlyWithSystemModel,
+
+    #[error("refusing to remove boot critical packages without --force-boot-critical: {}", .0.iter().join(", "))]
+    BootCritical(Vec<boot::Critical>),
+
+    #[error("refusing to remove essential packages without --force-essential: {}", .0.iter().join("; "))]
+    Essential(Vec<essential::Essential>),
+
     #[error("client")]
     Client(#[from] client::Error),
 
     #[error("db")]
     DB(#[from] moss::db::Error),
 
-    #[error("string processing")]
-    Dialog(#[from] tui::dialoguer::Error),
+    #[error("disk space")]
+    Space(#[from] client::space::Error),
+
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
 
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
 
     #[error("io")]
-    Io(#[from] std::io::Error),
+    Io(#[from] io::Error),
 
     #[error("load system model")]
     LoadSystemModel(#[from] system_model::LoadError),
 
     #[error("system model doesn't exist at {0:?}")]
     ImportSystemModelDoesntExist(PathBuf),
+
+    #[error("fetch system model")]
+    FetchSystemModel(#[from] request::Error),
+
+    #[error("imported system model isn't valid UTF-8")]
+    ImportEncoding(#[from] std::string::FromUtf8Error),
+
+    #[error("imported system model has checksum {actual}, expected {expected}")]
+    ImportChecksum { expected: String, actual: String },
+
+    #[error("invalid import key {0:?}, expected a hex encoded ed25519 public key")]
+    InvalidImportKey(String),
+
+    #[error("no signature to verify the import from stdin with, pass --import-signature")]
+    MissingImportSignature,
+
+    #[error("importing from a URL other than https:// requires --import-sha256 or --import-key")]
+    UnverifiedImport,
+
+    #[error("importing from stdin requires --yes, as the confirmation can't be prompted")]
+    ImportStdinConfirmation,
+
+    #[error("imported system model doesn't match its signature & key")]
+    ImportSignature,
+
+    #[error("import archive")]
+    Archive(#[from] archive::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        let location = |s: &str| s.parse::<Location>().unwrap();
+
+        assert!(matches!(location("-"), Location::Stdin));
+        assert!(matches!(location("https://example.com/model.kdl"), Location::Url(_)));
+        assert!(matches!(location("model.kdl"), Location::Path(_)));
+        assert!(matches!(location("/etc/model.kdl"), Location::Path(_)));
+
+        let Some(Location::Url(url)) = location("https://example.com/model.kdl").signature() else {
+            panic!("expected a signature url");
+        };
+        assert_eq!(url.as_str(), "https://example.com/model.kdl.sig");
+        let Some(Location::Path(path)) = location("model.kdl").signature() else {
+            panic!("expected a signature path");
+        };
+        assert_eq!(path, PathBuf::from("model.kdl.sig"));
+        assert!(location("-").signature().is_none());
+
+        assert!(location("https://example.com/model.kdl").is_trusted());
+        assert!(location("model.kdl").is_trusted());
+        assert!(!location("http://example.com/model.kdl").is_trusted());
+        assert!(!location("file:///etc/model.kdl").is_trusted());
+    }
 }
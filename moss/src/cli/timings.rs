@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Report how long each phase took, see [`timeline`]
+
+use std::{path::PathBuf, sync::OnceLock, time::Duration};
+
+use fs_err as fs;
+use tracing_common::timeline;
+use tui::Styled;
+
+/// Where to report the phases, see [`enable`]
+static REPORT: OnceLock<Report> = OnceLock::new();
+
+struct Report {
+    /// Print a breakdown to stderr
+    print: bool,
+    /// Save a Chrome trace to this file
+    trace: Option<PathBuf>,
+}
+
+/// Record phases, to report them once the command is done
+pub fn enable(print: bool, trace: Option<PathBuf>) {
+    if REPORT.set(Report { print, trace }).is_ok() {
+        timeline::enable();
+        // Only takes effect if no log was initialized, which records phases itself
+        timeline::init();
+    }
+}
+
+/// Report the phases recorded by the command, if enabled
+pub fn report() {
+    let Some(report) = REPORT.get() else {
+        return;
+    };
+
+    let spans = timeline::take();
+
+    if report.print && !spans.is_empty() {
+        print_breakdown(&spans);
+    }
+
+    if let Some(path) = &report.trace {
+        let trace = timeline::chrome_trace(&spans);
+        match fs::write(path, trace.to_string()) {
+            Ok(()) => eprintln!("{} Chrome trace to {}", "Saved".green(), path.display()),
+            Err(error) => eprintln!("{}: failed to save Chrome trace: {error}", "Warning".yellow()),
+        }
+    }
+}
+
+fn print_breakdown(spans: &[timeline::Span]) {
+    let total = spans
+        .iter()
+        .filter(|span| span.depth == 0)
+        .map(|span| span.duration)
+        .sum::<Duration>();
+    let width = spans
+        .iter()
+        .map(|span| span.phase.len() + span.depth * 2)
+        .max()
+        .unwrap_or_default()
+        .max("Total".len());
+
+    eprintln!();
+    eprintln!("{}", "Timings".bold());
+    for span in spans {
+        let name = format!("{}{}", "  ".repeat(span.depth), span.phase);
+        let share = if total.is_zero() {
+            0.0
+        } else {
+            span.duration.as_secs_f64() / total.as_secs_f64() * 100.0
+        };
+        eprintln!(
+            "  {name:width$}  {:>8}  {}",
+            format_duration(span.duration),
+            format!("{share:>3.0}%").dim()
+        );
+    }
+    eprintln!(
+        "  {}  {:>8}",
+        format!("{:width$}", "Total").bold(),
+        format_duration(total)
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
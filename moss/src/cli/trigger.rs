@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use clap::{ArgMatches, Command, arg};
+use thiserror::Error;
+use triggers::format::Handler;
+use tui::Styled;
+
+use moss::{
+    Installation, Provider,
+    client::{self, Client, fixup},
+    environment,
+    package::Flags,
+    registry::transaction,
+};
+
+pub fn command() -> Command {
+    Command::new("trigger")
+        .visible_alias("triggers")
+        .about("Manage triggers")
+        .long_about("Inspect triggers and re-run them against the active state")
+        .subcommand_required(true)
+        .subcommand(Command::new("list").about("List installed triggers and the paths they watch"))
+        .subcommand(
+            Command::new("plan")
+                .about("Show which triggers would fire, without running them")
+                .long_about(
+                    "Show which triggers would fire for the active state, or for the state resulting \
+                     from installing the given packages.\n\n\
+                     Only cached packages can be planned for, as their files aren't known until downloaded. \
+                     Nothing is downloaded or installed, packages which aren't cached are listed & left out.",
+                )
+                .arg(arg!([PACKAGE] ... "packages to install on top of the active state")),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run a trigger")
+                .arg(arg!(<NAME> "Trigger to run").long_help(
+                    "Trigger to run, as listed by `moss trigger list`
+
+tmpfiles: apply the ownership & mode fixups declared in tmpfiles.d",
+                )),
+        )
+}
+
+/// Whether the invoked subcommand changes the installation
+pub fn is_mutation(args: &ArgMatches) -> bool {
+    match args.subcommand_name() {
+        Some("list" | "plan") => false,
+        Some("run") => true,
+        _ => unreachable!(),
+    }
+}
+
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    match args.subcommand() {
+        Some(("list", _)) => list(installation),
+        Some(("plan", args)) => plan(args, installation),
+        Some(("run", args)) => run(args, installation),
+        _ => unreachable!(),
+    }
+}
+
+fn list(installation: Installation) -> Result<(), Error> {
+    let client = Client::new(environment::NAME, installation)?;
+
+    for (kind, trigger) in client.registered_triggers() {
+        println!("{} ({kind}) - {}", trigger.name.bold(), trigger.description);
+        for (pattern, definition) in &trigger.paths {
+            println!("  {pattern} {}", format!("→ {}", definition.handlers.join(", ")).dim());
+        }
+    }
+    println!(
+        "{} (system) - Apply tmpfiles.d ownership & mode fixups",
+        fixup::TRIGGER.bold()
+    );
+
+    Ok(())
+}
+
+fn plan(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let names = args
+        .get_many::<String>("PACKAGE")
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let active_state = installation.active_state;
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let mut selected = match active_state {
+        Some(id) => client
+            .state_db
+            .get(id)?
+            .selections
+            .into_iter()
+            .map(|selection| selection.package)
+            .collect(),
+        None => vec![],
+    };
+
+    if !names.is_empty() {
+        let input = names
+            .iter()
+            .map(|name| {
+                let provider = Provider::from_name(name).map_err(|_| Error::NoPackage((*name).clone()))?;
+                client
+                    .registry
+                    .by_provider(&provider, Flags::new().with_available())
+                    .next()
+                    .map(|package| package.id)
+                    .ok_or_else(|| Error::NoPackage((*name).clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut tx = client.registry.transaction(transaction::Lookup::PreferInstalled)?;
+        tx.add(input)?;
+        let resolved = client.resolve_packages(tx.finalize())?;
+
+        // Layouts are only known once a package is cached
+        let cached = client.layout_db.package_ids()?;
+        let (resolved, missing): (Vec<_>, Vec<_>) =
+            resolved.into_iter().partition(|package| cached.contains(&package.id));
+
+        for package in &missing {
+            eprintln!(
+                "{}: {} isn't cached, its triggers & files are left out",
+                "Warning".yellow(),
+                package.meta.name.to_string().bold()
+            );
+        }
+
+        selected.extend(resolved.into_iter().map(|package| package.id));
+        selected.sort();
+        selected.dedup();
+    }
+
+    for plan in client.plan_triggers(&selected)? {
+        if plan.triggers.is_empty() {
+            println!("No {} triggers would fire", plan.kind);
+            continue;
+        }
+
+        println!("Triggers ({}): {}", plan.kind, plan.triggers.join(", ").bold());
+        for handler in &plan.handlers {
+            match handler {
+                Handler::Run { run, args } => println!("  {run} {}", args.join(" ")),
+                Handler::Delete { delete } => println!("  delete {}", delete.join(" ")),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let name = args.get_one::<String>("NAME").unwrap();
+    let client = Client::new(environment::NAME, installation)?;
+
+    if name == fixup::TRIGGER {
+        let adjusted = client.run_fixups()?;
+        println!("{adjusted} path(s) adjusted");
+        return Ok(());
+    }
+
+    match client.run_trigger(name)? {
+        Some(0) => println!("{name} doesn't match any paths of the active state"),
+        Some(handlers) => println!("{name} ran {handlers} handler(s)"),
+        None => return Err(Error::NoTrigger(name.clone())),
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+    #[error("no package found for {0}")]
+    NoPackage(String),
+    #[error("no trigger named {0}")]
+    NoTrigger(String),
+}
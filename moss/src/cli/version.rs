@@ -3,21 +3,38 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use clap::{ArgMatches, Command, arg};
+use moss::{
+    Client, Installation, Package, client, environment,
+    package::{self, Flags},
+    release, runtime,
+};
+use thiserror::Error;
+use tui::Styled;
 
 /// Construct the Version command
 pub fn command() -> Command {
     Command::new("version")
         .about("Display version and exit")
         .arg(arg!(-f --"full" "Print the full build and version info").action(clap::ArgAction::SetTrue))
+        .arg(
+            arg!(--"check-update" "Check whether a newer moss is available from the repositories or release endpoint")
+                .action(clap::ArgAction::SetTrue),
+        )
 }
 
-pub fn handle(args: &ArgMatches) {
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
     let show_full = args.get_flag("full");
     if show_full {
         print_full();
     } else {
         print();
     }
+
+    if args.get_flag("check-update") {
+        check_update(installation)?;
+    }
+
+    Ok(())
 }
 
 /// Print program version
@@ -29,3 +46,86 @@ pub fn print() {
 pub fn print_full() {
     println!("moss {}", tools_buildinfo::get_full_version());
 }
+
+/// Check the repositories & the release endpoint for a newer moss
+fn check_update(installation: Installation) -> Result<(), Error> {
+    let config = release_config(&installation);
+    let client = Client::new(environment::NAME, installation).map_err(Error::SetupClient)?;
+
+    let installed = installed(&client);
+    let candidate = client.registry.by_name(&name(), Flags::new().with_available()).next();
+
+    println!();
+
+    if let Some(candidate) = &candidate {
+        let repo = client
+            .origin(candidate)
+            .map_or_else(|| "the repositories".to_owned(), |id| id.to_string());
+
+        if release::compare(&candidate.meta.version_identifier, release::VERSION).is_gt() {
+            let action = if installed.is_some() {
+                "moss sync"
+            } else {
+                "moss install moss"
+            };
+            println!(
+                "{} moss {} is available from {repo}, update with `{action}`",
+                "Update".green(),
+                candidate.meta.version_identifier
+            );
+        } else {
+            println!("moss is up to date with {repo}");
+        }
+    }
+
+    if let Some(endpoint) = config.url {
+        let key = config.key.ok_or(Error::NoKey)?;
+        let latest = runtime::block_on(release::latest(endpoint, &key)).map_err(Error::Release)?;
+
+        if !latest.is_newer() {
+            println!("moss is up to date with the latest release");
+        } else if installed.is_some() {
+            println!(
+                "moss {} is released, it's updated from the repositories once available there",
+                latest.version
+            );
+        } else {
+            println!(
+                "{} moss {} is released, update with `moss self-update`",
+                "Update".green(),
+                latest.version
+            );
+        }
+    } else if candidate.is_none() {
+        println!("No repository provides moss & no release endpoint is configured in /etc/moss/release.yaml");
+    }
+
+    Ok(())
+}
+
+fn name() -> package::Name {
+    package::Name::from(environment::NAME.to_owned())
+}
+
+/// The moss package installed from a repository, if moss is managed by one
+pub fn installed(client: &Client) -> Option<Package> {
+    client.registry.by_name(&name(), Flags::new().with_installed()).next()
+}
+
+/// The configured release endpoint & key
+pub fn release_config(installation: &Installation) -> release::Config {
+    config::Manager::system(&installation.root, "moss")
+        .load::<release::Config>()
+        .pop()
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to setup moss client")]
+    SetupClient(#[source] client::Error),
+    #[error("no key to verify releases with configured in /etc/moss/release.yaml")]
+    NoKey,
+    #[error("failed to check the latest release")]
+    Release(#[source] release::Error),
+}
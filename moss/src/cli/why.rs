@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use clap::{ArgMatches, Command, arg};
+use itertools::Itertools;
+use moss::{
+    Installation, Package, Provider,
+    client::{self, Client},
+    environment, package,
+};
+use thiserror::Error;
+use tui::Styled;
+
+pub fn command() -> Command {
+    Command::new("why")
+        .about("Explain why a package is installed")
+        .long_about(
+            "Explain why a package is installed by showing the dependency chains leading to it \
+             from explicitly installed (or system-model mandated) packages",
+        )
+        .arg(arg!(<NAME> "Installed package to explain").value_parser(clap::value_parser!(String)))
+}
+
+/// Handle execution of `moss why`
+pub fn handle(args: &ArgMatches, installation: Installation) -> Result<(), Error> {
+    let name = args.get_one::<String>("NAME").unwrap();
+
+    let client = Client::new(environment::NAME, installation)?;
+
+    let installed = client
+        .registry
+        .list_installed()
+        .map(|pkg| (pkg.id.clone(), pkg))
+        .collect::<BTreeMap<_, _>>();
+
+    let lookup = Provider::from_name(name).map_err(|_| Error::NotInstalled(name.clone()))?;
+    let target = installed
+        .values()
+        .find(|pkg| pkg.meta.providers.contains(&lookup) || pkg.meta.name.as_ref() == name.as_str())
+        .ok_or_else(|| Error::NotInstalled(name.clone()))?;
+
+    let model = client.installation.system_model.as_ref().map(|model| &model.packages);
+    let is_mandated = |pkg: &Package| model.is_some_and(|model| pkg.meta.providers.iter().any(|p| model.contains(p)));
+    let is_root = |pkg: &Package| pkg.flags.explicit || is_mandated(pkg);
+
+    let reasons = match client.installation.active_state {
+        Some(id) => client
+            .state_db
+            .get(id)?
+            .selections
+            .into_iter()
+            .filter_map(|selection| Some((selection.package, selection.reason?)))
+            .collect(),
+        None => BTreeMap::new(),
+    };
+
+    println!(
+        "{} {}",
+        target.meta.name.to_string().bold(),
+        describe(target, &is_mandated, &reasons)
+    );
+
+    if is_root(target) {
+        return Ok(());
+    }
+
+    let dependents = reverse_dependencies(&installed);
+    let chains = chains_from_roots(&target.id, &dependents, |id| installed.get(id).is_some_and(is_root));
+
+    if chains.is_empty() {
+        println!();
+        println!("No installed package depends on it, it may be removed");
+        return Ok(());
+    }
+
+    println!();
+    println!("Required by:");
+    for chain in chains {
+        let root = &installed[&chain[0]];
+        let path = chain
+            .iter()
+            .map(|id| installed[id].meta.name.to_string())
+            .join(&format!(" {} ", "→".dim()));
+        println!(
+            "  {path} {}",
+            format!("({})", describe(root, &is_mandated, &reasons)).dim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Describe why `pkg` itself is installed
+fn describe(pkg: &Package, is_mandated: &impl Fn(&Package) -> bool, reasons: &BTreeMap<package::Id, String>) -> String {
+    let mut description = if is_mandated(pkg) {
+        "is required by the system-model".to_owned()
+    } else if pkg.flags.explicit {
+        "is explicitly installed".to_owned()
+    } else {
+        "is installed as a dependency".to_owned()
+    };
+
+    if let Some(reason) = reasons.get(&pkg.id) {
+        description.push_str(&format!(": {reason}"));
+    }
+
+    description
+}
+
+/// Map each installed package to the installed packages depending on it
+fn reverse_dependencies(installed: &BTreeMap<package::Id, Package>) -> BTreeMap<package::Id, BTreeSet<package::Id>> {
+    let mut providers = BTreeMap::<&Provider, Vec<&package::Id>>::new();
+    for pkg in installed.values() {
+        for provider in &pkg.meta.providers {
+            providers.entry(provider).or_default().push(&pkg.id);
+        }
+    }
+
+    let mut dependents = BTreeMap::<_, BTreeSet<_>>::new();
+
+    for pkg in installed.values() {
+        for dependency in &pkg.meta.dependencies {
+            let provider = Provider {
+                kind: dependency.kind,
+                name: dependency.name.clone(),
+            };
+
+            for provided_by in providers.get(&provider).into_iter().flatten() {
+                if **provided_by != pkg.id {
+                    dependents
+                        .entry((*provided_by).clone())
+                        .or_default()
+                        .insert(pkg.id.clone());
+                }
+            }
+        }
+    }
+
+    dependents
+}
+
+/// Find the shortest dependency chain from each root leading to `target`
+///
+/// Chains are ordered root first, ending with `target`.
+fn chains_from_roots(
+    target: &package::Id,
+    dependents: &BTreeMap<package::Id, BTreeSet<package::Id>>,
+    is_root: impl Fn(&package::Id) -> bool,
+) -> Vec<Vec<package::Id>> {
+    // Breadth first walk up the reverse graph, remembering how each node was reached
+    let mut reached_from = BTreeMap::<&package::Id, &package::Id>::new();
+    let mut queue = VecDeque::from([target]);
+    let mut chains = vec![];
+
+    while let Some(id) = queue.pop_front() {
+        for dependent in dependents.get(id).into_iter().flatten() {
+            if dependent == target || reached_from.contains_key(dependent) {
+                continue;
+            }
+            reached_from.insert(dependent, id);
+
+            if is_root(dependent) {
+                let mut chain = vec![dependent.clone()];
+                let mut next = id;
+                while next != target {
+                    chain.push(next.clone());
+                    next = reached_from[next];
+                }
+                chain.push(target.clone());
+                chains.push(chain);
+            } else {
+                queue.push_back(dependent);
+            }
+        }
+    }
+
+    chains
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("db")]
+    DB(#[from] moss::db::Error),
+
+    #[error("no installed package found: {0}")]
+    NotInstalled(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use moss::package;
+
+    use super::chains_from_roots;
+
+    fn id(name: &str) -> package::Id {
+        package::Id::from(name.to_owned())
+    }
+
+    #[test]
+    fn test_shortest_chain_per_root() {
+        // firefox -> libpng -> zlib, firefox -> zlib, curl -> openssl -> zlib
+        let dependents = BTreeMap::from([
+            (id("zlib"), BTreeSet::from([id("libpng"), id("firefox"), id("openssl")])),
+            (id("libpng"), BTreeSet::from([id("firefox")])),
+            (id("openssl"), BTreeSet::from([id("curl")])),
+        ]);
+        let roots = [id("firefox"), id("curl")];
+
+        let chains = chains_from_roots(&id("zlib"), &dependents, |id| roots.contains(id));
+
+        assert_eq!(
+            chains,
+            vec![
+                vec![id("firefox"), id("zlib")],
+                vec![id("curl"), id("openssl"), id("zlib")]
+            ]
+        );
+    }
+}
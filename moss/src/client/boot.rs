@@ -6,7 +6,9 @@
 //! Boot management integration in moss
 
 use std::{
-    io,
+    collections::BTreeSet,
+    fmt,
+    io::{self, Write as _},
     path::{Path, PathBuf},
     str::FromStr,
     vec,
@@ -22,9 +24,11 @@ use itertools::Itertools;
 use stone::payload::layout::{self, Layout};
 use thiserror::{self, Error};
 
-use crate::{Installation, State, db, package::Id};
+use crate::{Installation, Package, State, db, package::Id, state};
 
-use super::Client;
+use super::{Client, cache};
+
+mod uki;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -49,6 +53,24 @@ pub enum Error {
 
     #[error("incomplete kernel tree: {0}")]
     IncompleteKernel(String),
+
+    #[error("uki: {0}")]
+    Uki(#[from] uki::Error),
+
+    #[error("unified kernel images require an EFI system partition")]
+    NoEsp,
+
+    #[error("missing EFI stub {0}, is systemd-boot installed?")]
+    MissingStub(PathBuf),
+
+    #[error("state #{0} has no kernels")]
+    NoKernels(state::Id),
+
+    #[error("state #{0} is not archived")]
+    NotArchived(state::Id),
+
+    #[error("invalid kernel command line {0:?}: {1}")]
+    InvalidCmdline(String, &'static str),
 }
 
 /// Simple mapping type for kernel discovery paths, retaining the layout reference
@@ -95,18 +117,14 @@ fn kernel_files_from_state<'a>(layouts: &'a [(Id, Layout)], pattern: &'a Pattern
 }
 
 /// Find bootloader assets in the new state
-fn boot_files_from_new_state<'a>(
-    install: &Installation,
-    layouts: &'a [(Id, Layout)],
-    pattern: &'a Pattern,
-) -> Vec<PathBuf> {
+fn boot_files_from_new_state<'a>(root: &Path, layouts: &'a [(Id, Layout)], pattern: &'a Pattern) -> Vec<PathBuf> {
     let mut rets = vec![];
 
     for (_, path) in layouts.iter() {
         if let layout::Entry::Regular(_, target) = &path.entry
             && pattern.match_path(target).is_some()
         {
-            rets.push(install.root.join("usr").join(target));
+            rets.push(root.join("usr").join(target));
         }
     }
 
@@ -159,24 +177,658 @@ fn os_schema_for_root(root: &Path) -> Result<Schema, Error> {
     }
 }
 
-pub fn synchronize(client: &Client, state: &State) -> Result<(), Error> {
-    let root = client.installation.root.clone();
-    let is_native = root.to_string_lossy() == "/";
-    // Create an appropriate configuration
-    let config = blsforme::Configuration {
-        root: if is_native {
-            blsforme::Root::Native(root.clone())
+/// Boot assets shipped by a single state
+#[derive(Debug)]
+pub struct StateAssets {
+    /// Kernels provided by the state, keyed by version
+    pub kernels: Vec<KernelAssets>,
+    /// Bootloader assets (i.e. systemd-boot EFI binaries)
+    pub bootloader: Vec<PathBuf>,
+    /// Kernel command line snippet selecting this state at boot, followed by its own fragment
+    pub cmdline: String,
+    /// Loader entries booting this state, as found on the mounted boot partitions
+    pub entries: Vec<BootEntry>,
+    /// Boot assets that no longer exist on disk, i.e. pruned from the cache
+    pub missing: Vec<PathBuf>,
+}
+
+impl StateAssets {
+    /// Returns true if the state ships at least one kernel
+    pub fn is_bootable(&self) -> bool {
+        !self.kernels.is_empty()
+    }
+}
+
+/// The kernel image and initrds of a kernel version
+#[derive(Debug)]
+pub struct KernelAssets {
+    pub version: String,
+    pub image: Option<PathBuf>,
+    pub initrds: Vec<PathBuf>,
+}
+
+/// Identify the kernel(s), initrd(s) and bootloader assets associated with `state`
+pub fn state_assets(client: &Client, state: &State) -> Result<StateAssets, Error> {
+    let layouts = layouts_for_state(client, state)?;
+    let kernel_pattern = Pattern::from_str("lib/kernel/(version:*)/(file:*)")?;
+    let systemd = Pattern::from_str("lib*/systemd/boot/efi/*.efi")?;
+
+    let is_active = client.installation.active_state == Some(state.id);
+    let sysroot = if is_active {
+        client.installation.root.clone()
+    } else {
+        client.installation.root_path(state.id.to_string())
+    };
+
+    let mut kernels = Vec::<KernelAssets>::new();
+    let mut shared_initrds = vec![];
+    let mut bootloader = vec![];
+    let mut missing = vec![];
+
+    for (_, layout) in &layouts {
+        let (hash, target) = match &layout.entry {
+            layout::Entry::Regular(hash, target) => (Some(*hash), target),
+            layout::Entry::Symlink(_, target) => (None, target),
+            _ => continue,
+        };
+        let path = PathBuf::from("/usr").join(target);
+
+        if let Some(m) = kernel_pattern.match_path(target) {
+            let version = &m.variables["version"];
+            let file = &m.variables["file"];
+
+            if version == "initrd.d" {
+                shared_initrds.push(path.clone());
+            } else {
+                let kernel = match kernels.iter_mut().find(|k| &k.version == version) {
+                    Some(kernel) => kernel,
+                    None => {
+                        kernels.push(KernelAssets {
+                            version: version.clone(),
+                            image: None,
+                            initrds: vec![],
+                        });
+                        kernels.last_mut().expect("just pushed")
+                    }
+                };
+
+                if file == "vmlinuz" {
+                    kernel.image = Some(path.clone());
+                } else if file.contains("initrd") {
+                    kernel.initrds.push(path.clone());
+                } else {
+                    continue;
+                }
+            }
+        } else if systemd.match_path(target).is_some() {
+            bootloader.push(path.clone());
+        } else {
+            continue;
+        }
+
+        // Archived states are reblitted from the asset store, so both must survive pruning
+        let asset_missing =
+            hash.is_some_and(|hash| !cache::asset_path(&client.installation, &format!("{hash:02x}")).exists());
+        if asset_missing || !sysroot.join(path.strip_prefix("/").unwrap_or(&path)).exists() {
+            missing.push(path);
+        }
+    }
+
+    for kernel in &mut kernels {
+        kernel.initrds.extend(shared_initrds.iter().cloned());
+    }
+    kernels.sort_by(|a, b| a.version.cmp(&b.version));
+
+    let entries = mounted_entries(&client.installation)?
+        .into_iter()
+        .filter(|entry| entry.state == Some(state.id))
+        .collect();
+
+    Ok(StateAssets {
+        kernels,
+        bootloader,
+        cmdline: [Some(format!("moss.fstx={}", state.id)), state.cmdline.clone()]
+            .into_iter()
+            .flatten()
+            .join(" "),
+        entries,
+        missing,
+    })
+}
+
+/// A boot critical asset that would be removed by a transaction
+#[derive(Debug, Clone)]
+pub enum Critical {
+    /// The package ships the currently running kernel
+    RunningKernel { package: String, version: String },
+    /// The package ships the bootloader
+    Bootloader { package: String },
+}
+
+impl fmt::Display for Critical {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Critical::RunningKernel { package, version } => write!(f, "{package} (running kernel {version})"),
+            Critical::Bootloader { package } => write!(f, "{package} (bootloader)"),
+        }
+    }
+}
+
+/// Version of the kernel currently running on the host, if `install` is the live root
+pub fn running_kernel(install: &Installation) -> Option<String> {
+    if !is_native(install) {
+        return None;
+    }
+
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").ok()?;
+    let release = release.trim();
+
+    (!release.is_empty()).then(|| release.to_owned())
+}
+
+/// Identify which of the `removed` packages ship the running kernel or the bootloader
+///
+/// Packages which are updated in place (replaced by a newer version of the same name)
+/// must not be passed here, as their boot assets remain available.
+pub fn critical_removals(client: &Client, removed: &[Package]) -> Result<Vec<Critical>, Error> {
+    if client.is_ephemeral() || removed.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let running = running_kernel(&client.installation);
+    let systemd = Pattern::from_str("lib*/systemd/boot/efi/*.efi")?;
+    let layouts = client.layout_db.query(removed.iter().map(|p| &p.id))?;
+
+    let mut critical = vec![];
+
+    for package in removed {
+        let targets = layouts
+            .iter()
+            .filter(|(id, _)| *id == package.id)
+            .map(|(_, layout)| layout.entry.target())
+            .collect::<Vec<_>>();
+        let name = package.meta.name.to_string();
+
+        if let Some(version) = &running
+            && targets.iter().any(|target| ships_kernel(target, version))
+        {
+            critical.push(Critical::RunningKernel {
+                package: name.clone(),
+                version: version.clone(),
+            });
+        }
+
+        if targets.iter().any(|target| systemd.match_path(target).is_some()) {
+            critical.push(Critical::Bootloader { package: name });
+        }
+    }
+
+    Ok(critical)
+}
+
+/// Layouts of the running kernel's modules to carry over into a new state lacking them
+///
+/// When the running kernel is replaced or removed, its modules are retained in the new
+/// `/usr` so they remain loadable until the next boot. They're not recorded as part
+/// of the new state and are dropped again by the first transaction after rebooting.
+pub fn retained_assets(client: &Client, new_layouts: &[(Id, Layout)]) -> Result<Vec<(Id, Layout)>, Error> {
+    let Some(version) = running_kernel(&client.installation) else {
+        return Ok(vec![]);
+    };
+    let Some(active) = client.installation.active_state else {
+        return Ok(vec![]);
+    };
+    if client.is_ephemeral() {
+        return Ok(vec![]);
+    }
+
+    let modules = format!("lib/modules/{version}");
+    let is_module = |layout: &Layout| {
+        let target = layout.entry.target();
+        target == modules || target.strip_prefix(&modules).is_some_and(|rest| rest.starts_with('/'))
+    };
+
+    if new_layouts.iter().any(|(_, layout)| is_module(layout)) {
+        return Ok(vec![]);
+    }
+
+    let state = client.state_db.get(active)?;
+
+    Ok(layouts_for_state(client, &state)?
+        .into_iter()
+        .filter(|(_, layout)| is_module(layout))
+        .collect())
+}
+
+/// Returns true if `target` belongs to the kernel `version`
+fn ships_kernel(target: &str, version: &str) -> bool {
+    ["lib/kernel", "lib/modules"].iter().any(|dir| {
+        target
+            .strip_prefix(dir)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .and_then(|rest| rest.strip_prefix(version))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// A boot loader entry found on one of the boot partitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootEntry {
+    /// Location of the entry file
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub version: Option<String>,
+    /// Kernel image, relative to the partition root
+    pub linux: Option<String>,
+    /// Initrds, relative to the partition root
+    pub initrd: Vec<String>,
+    /// Unified kernel image, relative to the partition root
+    pub efi: Option<String>,
+    /// State booted by this entry, from its `moss.fstx=` cmdline option
+    pub state: Option<state::Id>,
+}
+
+impl BootEntry {
+    /// Parse a Boot Loader Specification (type #1) entry
+    pub fn parse(path: PathBuf, contents: &str) -> Self {
+        let mut entry = BootEntry {
+            path,
+            title: None,
+            version: None,
+            linux: None,
+            initrd: vec![],
+            efi: None,
+            state: None,
+        };
+
+        for line in contents.lines().map(str::trim) {
+            let Some((key, value)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key {
+                "title" => entry.title = Some(value.to_owned()),
+                "version" => entry.version = Some(value.to_owned()),
+                "linux" => entry.linux = Some(value.to_owned()),
+                "initrd" => entry.initrd.push(value.to_owned()),
+                "efi" => entry.efi = Some(value.to_owned()),
+                "options" => {
+                    if let Some(id) = value
+                        .split_whitespace()
+                        .find_map(|option| option.strip_prefix("moss.fstx="))
+                        .and_then(|id| id.parse::<i32>().ok())
+                    {
+                        entry.state = Some(id.into());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        entry
+    }
+
+    /// Partition holding this entry, i.e. the parent of `loader/entries`
+    fn partition(&self) -> Option<&Path> {
+        self.path.ancestors().nth(3)
+    }
+
+    /// Kernel images, initrds & UKIs referenced by this entry, resolved against its partition
+    fn files(&self) -> Vec<PathBuf> {
+        let Some(partition) = self.partition() else {
+            return vec![];
+        };
+
+        self.linux
+            .iter()
+            .chain(&self.initrd)
+            .chain(&self.efi)
+            .map(|file| partition.join(file.trim_start_matches('/')))
+            .collect()
+    }
+}
+
+/// Enumerate the boot loader entries on the ESP, XBOOTLDR or `/boot` partitions
+pub fn installed_entries(client: &Client) -> Result<Vec<BootEntry>, Error> {
+    let config = configuration(&client.installation);
+
+    // If we can't get a manager there are no boot partitions to look at
+    let Ok(manager) = blsforme::Manager::new(&config) else {
+        return Ok(vec![]);
+    };
+    let _mounts = if is_native(&client.installation) {
+        Some(manager.mount_partitions()?)
+    } else {
+        None
+    };
+
+    scan_entries(&manager)
+}
+
+/// Enumerate the boot loader entries on the boot partitions as they are, without mounting them
+///
+/// For read-only queries, so entries of a partition which isn't mounted are left out.
+pub fn mounted_entries(install: &Installation) -> Result<Vec<BootEntry>, Error> {
+    let config = configuration(install);
+
+    let Ok(manager) = blsforme::Manager::new(&config) else {
+        return Ok(vec![]);
+    };
+
+    scan_entries(&manager)
+}
+
+/// Remove the provided boot loader entries along with the kernel images, initrds
+/// & UKIs which are no longer referenced by any of the remaining entries
+pub fn remove_entries(install: &Installation, entries: &[BootEntry]) -> Result<(), Error> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let config = configuration(install);
+    let manager = blsforme::Manager::new(&config)?;
+    let _mounts = if is_native(install) {
+        Some(manager.mount_partitions()?)
+    } else {
+        None
+    };
+
+    for entry in entries {
+        if entry.path.exists() {
+            fs::remove_file(&entry.path)?;
+        }
+    }
+
+    let referenced = scan_entries(&manager)?
+        .iter()
+        .flat_map(BootEntry::files)
+        .collect::<BTreeSet<_>>();
+
+    for file in entries.iter().flat_map(BootEntry::files).unique() {
+        if referenced.contains(&file) || !file.exists() {
+            continue;
+        }
+
+        fs::remove_file(&file)?;
+
+        // Kernels are installed into a per version directory, drop it once empty
+        if let Some(parent) = file.parent()
+            && fs::read_dir(parent)?.next().is_none()
+        {
+            fs::remove_dir(parent)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read all entries from the `loader/entries` directory of each boot partition
+fn scan_entries(manager: &blsforme::Manager) -> Result<Vec<BootEntry>, Error> {
+    let environment = manager.boot_environment();
+    let partitions = [environment.xbootldr(), environment.esp(), environment.boot_partition()]
+        .into_iter()
+        .flatten()
+        .unique()
+        .collect::<Vec<_>>();
+
+    let mut entries = vec![];
+
+    for partition in partitions {
+        let dir = partition.join("loader").join("entries");
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for path in read_dir.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.extension().is_some_and(|ext| ext == "conf") {
+                let contents = fs::read_to_string(&path)?;
+                entries.push(BootEntry::parse(path, &contents));
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(entries)
+}
+
+/// A unified kernel image installed to the ESP
+#[derive(Debug)]
+pub struct InstalledUki {
+    pub version: String,
+    /// The UKI itself
+    pub image: PathBuf,
+    /// Boot loader entry booting the UKI
+    pub entry: PathBuf,
+}
+
+/// Assemble a unified kernel image for each kernel of `state` and install it to the
+/// ESP, along with a boot loader entry so every state can be booted on its own
+///
+/// `extra_cmdline` is appended to the global kernel command line.
+pub fn generate_uki(client: &Client, state: &State, extra_cmdline: Option<&str>) -> Result<Vec<InstalledUki>, Error> {
+    let sysroot = if client.installation.active_state == Some(state.id) {
+        client.installation.root.clone()
+    } else {
+        client.installation.root_path(state.id.to_string())
+    };
+    if !sysroot.exists() {
+        return Err(Error::NotArchived(state.id));
+    }
+
+    let assets = state_assets(client, state)?;
+    if !assets.is_bootable() {
+        return Err(Error::NoKernels(state.id));
+    }
+
+    let in_sysroot = |path: &Path| sysroot.join(path.strip_prefix("/").unwrap_or(path));
+
+    let stub_path = in_sysroot(&Path::new("/usr/lib/systemd/boot/efi").join(format!("linux{}.efi.stub", efi_arch())));
+    if !stub_path.exists() {
+        return Err(Error::MissingStub(stub_path));
+    }
+    let stub = fs::read(&stub_path)?;
+
+    let os_release = fs::read_to_string(in_sysroot(Path::new("/usr/lib/os-release")))?;
+    let os_id = os_id(&os_release).to_owned();
+    let os_name = os_release_field(&os_release, "PRETTY_NAME")
+        .or_else(|| os_release_field(&os_release, "NAME"))
+        .unwrap_or(&os_id)
+        .to_owned();
+
+    let config = configuration(&client.installation);
+    let manager = blsforme::Manager::new(&config)?;
+    let _mounts = if is_native(&client.installation) {
+        Some(manager.mount_partitions()?)
+    } else {
+        None
+    };
+    let esp = manager.boot_environment().esp().ok_or(Error::NoEsp)?.to_owned();
+
+    let cmdline = manager
+        .cmdline()
+        .map(|arg| arg.to_owned())
+        .chain(Some(assets.cmdline.clone()))
+        .chain(extra_cmdline.map(ToOwned::to_owned))
+        .join(" ");
+
+    let mut installed = vec![];
+
+    for kernel in &assets.kernels {
+        let image = kernel
+            .image
+            .as_deref()
+            .ok_or_else(|| Error::IncompleteKernel(kernel.version.clone()))?;
+        let linux = fs::read(in_sysroot(image))?;
+
+        // Concatenated cpio archives form a valid initrd
+        let mut initrd = vec![];
+        for path in &kernel.initrds {
+            initrd.extend(fs::read(in_sysroot(path))?);
+        }
+
+        let mut sections = vec![
+            (".osrel", os_release.as_bytes()),
+            (".cmdline", cmdline.as_bytes()),
+            (".uname", kernel.version.as_bytes()),
+        ];
+        if !initrd.is_empty() {
+            sections.push((".initrd", &initrd));
+        }
+        sections.push((".linux", &linux));
+
+        // Kept out of `EFI/Linux` so systemd-boot doesn't list it twice
+        let relative = PathBuf::from("EFI")
+            .join(&os_id)
+            .join(format!("uki-{}-{}.efi", kernel.version, state.id));
+        let image_path = esp.join(&relative);
+        if let Some(parent) = image_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_replace(&image_path, &uki::assemble(&stub, &sections)?)?;
+
+        let entry_path = esp
+            .join("loader")
+            .join("entries")
+            .join(format!("{os_id}-uki-{}-{}.conf", kernel.version, state.id));
+        if let Some(parent) = entry_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_replace(
+            &entry_path,
+            format!(
+                "title {os_name} ({}, state #{})\nversion {}\nefi /{}\noptions {cmdline}\nsort-key {os_id}\n",
+                kernel.version,
+                state.id,
+                kernel.version,
+                relative.display(),
+            )
+            .as_bytes(),
+        )?;
+
+        installed.push(InstalledUki {
+            version: kernel.version.clone(),
+            image: image_path,
+            entry: entry_path,
+        });
+    }
+
+    Ok(installed)
+}
+
+/// EFI architecture suffix of the systemd stub for the running machine
+fn efi_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "x64",
+        "x86" => "ia32",
+        "aarch64" => "aa64",
+        "riscv64" => "riscv64",
+        arch => arch,
+    }
+}
+
+/// Write `content` to a temporary file alongside `path` before renaming it into place,
+/// so an interrupted write never leaves a truncated image or entry behind
+fn write_replace(path: &Path, content: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut temp = tempfile::Builder::new().prefix(".moss-").tempfile_in(dir)?;
+    temp.write_all(content)?;
+    temp.as_file().sync_all()?;
+    temp.persist(path).map_err(|error| error.error)?;
+
+    Ok(())
+}
+
+/// The os-release `ID` if it's safe to use in ESP paths, which only allows
+/// `[a-z0-9._-]`, else `linux`
+fn os_id(os_release: &str) -> &str {
+    os_release_field(os_release, "ID")
+        .filter(|id| {
+            !matches!(*id, "" | "." | "..")
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+        })
+        .unwrap_or("linux")
+}
+
+/// Lookup an (unquoted) field of an os-release file
+fn os_release_field<'a>(os_release: &'a str, key: &str) -> Option<&'a str> {
+    os_release.lines().find_map(|line| {
+        let (name, value) = line.split_once('=')?;
+        (name.trim() == key).then(|| value.trim().trim_matches(['"', '\'']))
+    })
+}
+
+fn is_native(install: &Installation) -> bool {
+    install.root.to_string_lossy() == "/"
+}
+
+/// Create an appropriate blsforme configuration for the installation
+fn configuration(install: &Installation) -> blsforme::Configuration {
+    let root = install.root.clone();
+
+    blsforme::Configuration {
+        root: if is_native(install) {
+            blsforme::Root::Native(root)
         } else {
-            blsforme::Root::Image(root.clone())
+            blsforme::Root::Image(root)
         },
         vfs: "/".into(),
-    };
+    }
+}
+
+/// Replace the kernel command line fragment of `state`, `None` clears it, then regenerate
+/// the boot loader entries
+pub fn set_cmdline(client: &Client, state: &State, cmdline: Option<&str>) -> Result<(), Error> {
+    if let Some(cmdline) = cmdline {
+        let invalid = |reason| Err(Error::InvalidCmdline(cmdline.to_owned(), reason));
+
+        if cmdline.trim().is_empty() {
+            return invalid("empty");
+        }
+        if cmdline.chars().any(char::is_control) {
+            return invalid("contains control characters");
+        }
+        if cmdline.split_whitespace().any(|arg| arg.starts_with("moss.fstx=")) {
+            return invalid("moss.fstx is set by moss");
+        }
+    }
+
+    client.state_db.set_cmdline(state.id, cmdline)?;
+
+    // Entries of every state are regenerated along with the active one
+    if let Some(id) = client.installation.active_state {
+        synchronize(client, &client.state_db.get(id)?)?;
+    }
+
+    Ok(())
+}
+
+pub fn synchronize(client: &Client, state: &State) -> Result<(), Error> {
+    synchronize_with_head(client, state, client.installation.root.clone())
+}
+
+/// Regenerate the boot loader entries so the archived `state` is booted into next,
+/// while keeping those of the active state
+pub fn synchronize_pending(client: &Client, state: &State) -> Result<(), Error> {
+    synchronize_with_head(client, state, client.installation.root_path(state.id.to_string()))
+}
+
+/// Synchronize boot with `state` as the head, whose tree is found in `head_root`
+fn synchronize_with_head(client: &Client, state: &State, head_root: PathBuf) -> Result<(), Error> {
+    let root = client.installation.root.clone();
+    let is_native = is_native(&client.installation);
+    // Create an appropriate configuration
+    let config = configuration(&client.installation);
 
     // For the new/active state
     let head_layouts = layouts_for_state(client, state)?;
     let kernel_pattern = Pattern::from_str("lib/kernel/(version:*)/*")?;
     let systemd = Pattern::from_str("lib*/systemd/boot/efi/*.efi")?;
-    let booty_bits = boot_files_from_new_state(&client.installation, &head_layouts, &systemd);
+    let booty_bits = boot_files_from_new_state(&head_root, &head_layouts, &systemd);
 
     let mut all_states = states_except_new(client, state)?;
 
@@ -185,7 +837,7 @@ pub fn synchronize(client: &Client, state: &State) -> Result<(), Error> {
         return Ok(());
     }
 
-    let global_schema = os_schema_for_root(&root)?;
+    let global_schema = os_schema_for_root(&head_root)?;
 
     // Grab the entries for the new state
     let mut all_kernels = vec![];
@@ -194,17 +846,19 @@ pub fn synchronize(client: &Client, state: &State) -> Result<(), Error> {
         let layouts = layouts_for_state(client, state)?;
         let local_kernels = kernel_files_from_state(&layouts, &kernel_pattern);
         let mapped = global_schema.discover_system_kernels(local_kernels.into_iter())?;
-        all_kernels.push((mapped, state.id));
+        all_kernels.push((mapped, state.id, state.cmdline.as_deref()));
     }
 
     // pipe all of our entries into blsforme
     let mut entries = all_kernels
         .iter()
-        .flat_map(|(kernels, state_id)| {
+        .flat_map(|(kernels, state_id, cmdline)| {
             kernels
                 .iter()
                 .filter_map(|k| {
                     let sysroot = if state.id == *state_id {
+                        head_root.clone()
+                    } else if client.installation.active_state == Some(*state_id) {
                         root.clone()
                     } else {
                         client.installation.root_path(state_id.to_string()).to_owned()
@@ -215,13 +869,18 @@ pub fn synchronize(client: &Client, state: &State) -> Result<(), Error> {
                     }
 
                     let local_schema = os_schema_for_root(&sysroot).ok();
-                    let entry = Entry::new(k)
-                        .with_cmdline(CmdlineEntry {
-                            name: "---fstx---".to_owned(),
-                            snippet: format!("moss.fstx={state_id}"),
-                        })
-                        .with_state_id(i32::from(*state_id))
-                        .with_sysroot(sysroot);
+                    let mut entry = Entry::new(k).with_cmdline(CmdlineEntry {
+                        name: "---fstx---".to_owned(),
+                        snippet: format!("moss.fstx={state_id}"),
+                    });
+                    // Managed with `moss boot cmdline`, so it follows the state across rollbacks
+                    if let Some(cmdline) = cmdline {
+                        entry = entry.with_cmdline(CmdlineEntry {
+                            name: "---state---".to_owned(),
+                            snippet: (*cmdline).to_owned(),
+                        });
+                    }
+                    let entry = entry.with_state_id(i32::from(*state_id)).with_sysroot(sysroot);
 
                     match local_schema {
                         Some(schema) => Some(entry.with_schema(schema)),
@@ -258,3 +917,67 @@ pub fn synchronize(client: &Client, state: &State) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{BootEntry, ships_kernel};
+
+    #[test]
+    fn test_parse_entry() {
+        let entry = BootEntry::parse(
+            PathBuf::from("/boot/loader/entries/aerynos-6.12.4-1.desktop-12.conf"),
+            "title AerynOS (6.12.4-1.desktop)\n\
+             version 6.12.4-1.desktop\n\
+             linux /EFI/aerynos/kernel-6.12.4-1.desktop\n\
+             initrd /EFI/aerynos/initrd-6.12.4-1.desktop\n\
+             options root=UUID=abcd rw moss.fstx=12 quiet\n",
+        );
+
+        assert_eq!(entry.title.as_deref(), Some("AerynOS (6.12.4-1.desktop)"));
+        assert_eq!(entry.version.as_deref(), Some("6.12.4-1.desktop"));
+        assert_eq!(entry.linux.as_deref(), Some("/EFI/aerynos/kernel-6.12.4-1.desktop"));
+        assert_eq!(entry.initrd, vec!["/EFI/aerynos/initrd-6.12.4-1.desktop".to_owned()]);
+        assert_eq!(entry.state, Some(12.into()));
+        assert_eq!(
+            entry.files(),
+            vec![
+                PathBuf::from("/boot/EFI/aerynos/kernel-6.12.4-1.desktop"),
+                PathBuf::from("/boot/EFI/aerynos/initrd-6.12.4-1.desktop"),
+            ]
+        );
+
+        let unmanaged = BootEntry::parse(PathBuf::from("other.conf"), "title Other\noptions quiet\n");
+        assert_eq!(unmanaged.state, None);
+    }
+
+    #[test]
+    fn test_ships_kernel() {
+        assert!(ships_kernel("lib/kernel/6.12.4-1.desktop/vmlinuz", "6.12.4-1.desktop"));
+        assert!(ships_kernel("lib/modules/6.12.4-1.desktop", "6.12.4-1.desktop"));
+        assert!(ships_kernel(
+            "lib/modules/6.12.4-1.desktop/kernel/fs/ext4.ko",
+            "6.12.4-1.desktop"
+        ));
+        assert!(!ships_kernel(
+            "lib/modules/6.12.4-1.desktop-rc/modules.dep",
+            "6.12.4-1.desktop"
+        ));
+        assert!(!ships_kernel("lib/kernel/initrd.d/00-base.cpio", "6.12.4-1.desktop"));
+        assert!(!ships_kernel(
+            "share/doc/lib/modules/6.12.4-1.desktop",
+            "6.12.4-1.desktop"
+        ));
+    }
+
+    #[test]
+    fn test_os_id() {
+        assert_eq!(os_id("NAME=\"Serpent OS\"\nID=serpentos\n"), "serpentos");
+        assert_eq!(os_id("ID=\"opensuse-tumbleweed\"\n"), "opensuse-tumbleweed");
+        assert_eq!(os_id("ID=../../etc\n"), "linux");
+        assert_eq!(os_id("ID=..\n"), "linux");
+        assert_eq!(os_id("ID=Serpent OS\n"), "linux");
+        assert_eq!(os_id("NAME=Linux\n"), "linux");
+    }
+}
@@ -0,0 +1,265 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Assembly of Unified Kernel Images
+//!
+//! A UKI is the systemd EFI stub with the kernel, initrd, cmdline etc. embedded
+//! as additional PE sections, which the stub picks up when booted.
+
+use thiserror::Error;
+
+/// Size of a PE section header
+const SECTION_HEADER_SIZE: usize = 40;
+/// `IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ`
+const SECTION_CHARACTERISTICS: u32 = 0x4000_0040;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("stub is not a PE image")]
+    NotPe,
+    #[error("malformed stub: {0}")]
+    Malformed(&'static str),
+    #[error("stub has no room for {0} additional section headers")]
+    NoRoom(usize),
+    #[error("invalid section name {0}")]
+    SectionName(String),
+    #[error("image exceeds the size limits of PE images")]
+    TooLarge,
+}
+
+/// Append the named `sections` to the EFI `stub`, returning the combined image
+///
+/// Any signature of the stub is dropped as it no longer covers the image.
+pub fn assemble(stub: &[u8], sections: &[(&str, &[u8])]) -> Result<Vec<u8>, Error> {
+    if stub.get(..2) != Some(b"MZ") {
+        return Err(Error::NotPe);
+    }
+
+    // Offsets are u32 read from the stub, so these can't overflow a 64-bit usize
+    let pe = read_u32(stub, 0x3c)? as usize;
+    if stub.get(pe..pe + 4) != Some(b"PE\0\0") {
+        return Err(Error::NotPe);
+    }
+
+    let coff = pe + 4;
+    let num_sections = read_u16(stub, coff + 2)? as usize;
+    let optional_size = read_u16(stub, coff + 16)? as usize;
+
+    let optional = coff + 20;
+    let security_directory = match read_u16(stub, optional)? {
+        0x10b => optional + 96 + 4 * 8,
+        0x20b => optional + 112 + 4 * 8,
+        _ => return Err(Error::Malformed("unknown optional header magic")),
+    };
+    let section_alignment = read_u32(stub, optional + 32)?;
+    let file_alignment = read_u32(stub, optional + 36)?;
+    let size_of_headers = read_u32(stub, optional + 60)? as usize;
+
+    if section_alignment == 0 || file_alignment == 0 {
+        return Err(Error::Malformed("zero alignment"));
+    }
+
+    let table = optional + optional_size;
+    let table_end = table + num_sections * SECTION_HEADER_SIZE;
+
+    // Every field written below must be within the headers, which are always kept
+    if security_directory + 8 > table || optional + 68 > table {
+        return Err(Error::Malformed("optional header too small"));
+    }
+    if table_end > size_of_headers || size_of_headers > stub.len() {
+        return Err(Error::Malformed("headers out of range"));
+    }
+
+    let mut image_end = 0;
+    let mut virtual_end = 0;
+    let mut first_data = size_of_headers;
+
+    for index in 0..num_sections {
+        let header = table + index * SECTION_HEADER_SIZE;
+        let virtual_size = read_u32(stub, header + 8)?;
+        let virtual_address = read_u32(stub, header + 12)?;
+        let raw_size = read_u32(stub, header + 16)?;
+        let raw_pointer = read_u32(stub, header + 20)?;
+
+        let section_end = virtual_address
+            .checked_add(virtual_size.max(raw_size))
+            .ok_or(Error::Malformed("section exceeds the address space"))?;
+        virtual_end = virtual_end.max(section_end);
+        if raw_size > 0 {
+            let data_end = raw_pointer
+                .checked_add(raw_size)
+                .ok_or(Error::Malformed("section data out of range"))?;
+            image_end = image_end.max(data_end as usize);
+            first_data = first_data.min(raw_pointer as usize);
+        }
+    }
+
+    // New headers have to fit between the section table and the first section's data
+    let new_table_end = sections
+        .len()
+        .checked_mul(SECTION_HEADER_SIZE)
+        .and_then(|size| table_end.checked_add(size))
+        .ok_or(Error::NoRoom(sections.len()))?;
+    if new_table_end > first_data.min(size_of_headers) {
+        return Err(Error::NoRoom(sections.len()));
+    }
+
+    // Drop trailing data such as the certificate table
+    let mut image = stub
+        .get(..image_end.max(size_of_headers))
+        .ok_or(Error::Malformed("section data out of range"))?
+        .to_vec();
+    write_u32(&mut image, security_directory, 0);
+    write_u32(&mut image, security_directory + 4, 0);
+
+    let mut virtual_address = align(virtual_end, section_alignment)?;
+
+    for (index, (name, data)) in sections.iter().enumerate() {
+        if name.len() > 8 || !name.is_ascii() {
+            return Err(Error::SectionName((*name).to_owned()));
+        }
+
+        let data_size = u32::try_from(data.len()).map_err(|_| Error::TooLarge)?;
+        let raw_pointer = align(file_size(&image)?, file_alignment)?;
+        let raw_size = align(data_size, file_alignment)?;
+        let raw_end = raw_pointer.checked_add(raw_size).ok_or(Error::TooLarge)?;
+
+        image.resize(raw_pointer as usize, 0);
+        image.extend_from_slice(data);
+        image.resize(raw_end as usize, 0);
+
+        let header = table_end + index * SECTION_HEADER_SIZE;
+        let mut name_bytes = [0; 8];
+        name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+        image[header..header + SECTION_HEADER_SIZE].fill(0);
+        image[header..header + 8].copy_from_slice(&name_bytes);
+        write_u32(&mut image, header + 8, data_size);
+        write_u32(&mut image, header + 12, virtual_address);
+        write_u32(&mut image, header + 16, raw_size);
+        write_u32(&mut image, header + 20, raw_pointer);
+        write_u32(&mut image, header + 36, SECTION_CHARACTERISTICS);
+
+        virtual_address = virtual_address
+            .checked_add(data_size)
+            .ok_or(Error::TooLarge)
+            .and_then(|end| align(end, section_alignment))?;
+    }
+
+    let num_sections = u16::try_from(num_sections + sections.len()).map_err(|_| Error::NoRoom(sections.len()))?;
+    image[coff + 2..coff + 4].copy_from_slice(&num_sections.to_le_bytes());
+    // SizeOfImage
+    write_u32(&mut image, optional + 56, virtual_address);
+    // The checksum is only verified for drivers, clear it rather than recompute it
+    write_u32(&mut image, optional + 64, 0);
+
+    Ok(image)
+}
+
+fn align(value: u32, alignment: u32) -> Result<u32, Error> {
+    value.div_ceil(alignment).checked_mul(alignment).ok_or(Error::TooLarge)
+}
+
+/// Size of `image`, which must be addressable by the u32 pointers of PE section headers
+fn file_size(image: &[u8]) -> Result<u32, Error> {
+    u32::try_from(image.len()).map_err(|_| Error::TooLarge)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(Error::Malformed("truncated header"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(Error::Malformed("truncated header"))
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A PE32+ image with a single `.text` section
+    fn stub() -> Vec<u8> {
+        let mut image = vec![0; 0x400];
+        image[..2].copy_from_slice(b"MZ");
+        write_u32(&mut image, 0x3c, 0x40);
+        image[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // NumberOfSections, SizeOfOptionalHeader
+        image[0x46..0x48].copy_from_slice(&1u16.to_le_bytes());
+        image[0x54..0x56].copy_from_slice(&240u16.to_le_bytes());
+        // Optional header
+        image[0x58..0x5a].copy_from_slice(&0x20bu16.to_le_bytes());
+        write_u32(&mut image, 0x58 + 32, 0x1000);
+        write_u32(&mut image, 0x58 + 36, 0x200);
+        write_u32(&mut image, 0x58 + 56, 0x2000);
+        write_u32(&mut image, 0x58 + 60, 0x200);
+        // .text section header
+        let header = 0x58 + 240;
+        image[header..header + 5].copy_from_slice(b".text");
+        write_u32(&mut image, header + 8, 0x10);
+        write_u32(&mut image, header + 12, 0x1000);
+        write_u32(&mut image, header + 16, 0x200);
+        write_u32(&mut image, header + 20, 0x200);
+        image
+    }
+
+    #[test]
+    fn test_assemble() {
+        let image = assemble(&stub(), &[(".cmdline", b"quiet"), (".linux", &[0xaa; 0x300])]).unwrap();
+
+        assert_eq!(read_u16(&image, 0x46).unwrap(), 3);
+        // Both sections are page aligned following .text
+        assert_eq!(read_u32(&image, 0x58 + 56).unwrap(), 0x4000);
+
+        let cmdline = 0x58 + 240 + SECTION_HEADER_SIZE;
+        assert_eq!(&image[cmdline..cmdline + 8], b".cmdline");
+        assert_eq!(read_u32(&image, cmdline + 8).unwrap(), 5);
+        assert_eq!(read_u32(&image, cmdline + 12).unwrap(), 0x2000);
+        assert_eq!(read_u32(&image, cmdline + 20).unwrap(), 0x400);
+        assert_eq!(&image[0x400..0x405], b"quiet");
+
+        let linux = cmdline + SECTION_HEADER_SIZE;
+        assert_eq!(read_u32(&image, linux + 12).unwrap(), 0x3000);
+        assert_eq!(read_u32(&image, linux + 16).unwrap(), 0x400);
+        assert_eq!(read_u32(&image, linux + 20).unwrap(), 0x600);
+        assert_eq!(image.len(), 0xa00);
+
+        assert!(matches!(assemble(b"MZ", &[]), Err(Error::Malformed(_))));
+        assert!(matches!(assemble(b"ELF", &[]), Err(Error::NotPe)));
+    }
+
+    #[test]
+    fn test_assemble_overflow() {
+        let header = 0x58 + 240;
+
+        let mut image = stub();
+        write_u32(&mut image, header + 12, u32::MAX - 0x10);
+        assert!(matches!(assemble(&image, &[]), Err(Error::Malformed(_))));
+
+        let mut image = stub();
+        write_u32(&mut image, header + 20, u32::MAX - 0x10);
+        assert!(matches!(assemble(&image, &[]), Err(Error::Malformed(_))));
+
+        // Headers extending past the end of the stub
+        let mut image = stub();
+        write_u32(&mut image, 0x58 + 60, 0x10000);
+        assert!(matches!(assemble(&image, &[]), Err(Error::Malformed(_))));
+
+        // Optional header too small to hold the fields rewritten
+        let mut image = stub();
+        image[0x54..0x56].copy_from_slice(&16u16.to_le_bytes());
+        assert!(matches!(assemble(&image, &[]), Err(Error::Malformed(_))));
+
+        // Section alignment leaving no room for the appended sections
+        let mut image = stub();
+        write_u32(&mut image, 0x58 + 32, 0x8000_0000);
+        assert!(matches!(assemble(&image, &[(".linux", b"x")]), Err(Error::TooLarge)));
+    }
+}
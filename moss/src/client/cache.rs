@@ -6,19 +6,22 @@
 
 use std::collections::HashSet;
 use std::{
-    io,
-    path::PathBuf,
+    io, iter,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
+use chrono::Utc;
 use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use tokio::io::AsyncWriteExt;
+use tracing::warn;
 use url::Url;
 
 use stone::{payload, read::PayloadKind};
 
-use crate::{Installation, package, request};
+use crate::{Installation, package, request, runtime};
 
 /// Synchronized set of assets that are currently being
 /// unpacked. Used to prevent unpacking the same asset
@@ -80,8 +83,16 @@ impl Progress {
 }
 
 /// Fetch a package with the provided [`package::Meta`] and [`Installation`] and return a [`Download`] on success.
+///
+/// The download is cached in the given `partition`, see [`download_dir`]. Downloads are
+/// verified against the hash of `meta`, both when cached & when fetched. Corrupt cached
+/// downloads are moved out of the way with [`quarantine`] & fetched again.
+///
+/// Downloads missing from the cache are used from the shared caches of the installation
+/// if found there, see [`Installation::shared_caches`].
 pub async fn fetch(
     meta: &package::Meta,
+    partition: Option<&str>,
     installation: &Installation,
     on_progress: impl Fn(Progress),
 ) -> Result<Download, Error> {
@@ -90,24 +101,80 @@ pub async fn fetch(
     let url = meta.uri.as_ref().ok_or(Error::MissingUri)?.parse::<Url>()?;
     let hash = meta.hash.as_ref().ok_or(Error::MissingHash)?;
 
-    let destination_path = download_path(installation, hash)?;
+    let destination_path = download_path(installation, partition, hash)?;
     let partial_path = destination_path.with_extension("part");
 
     if let Some(parent) = destination_path.parent() {
         fs::create_dir_all(parent).await?;
     }
 
+    // Adopt downloads from the unpartitioned cache layout
+    let legacy_path = legacy_download_path(installation, hash)?;
+    if tokio::fs::try_exists(&legacy_path).await? {
+        fs::rename(&legacy_path, &destination_path).await?;
+    }
+
+    let mut quarantined = None;
+
     if tokio::fs::try_exists(&destination_path).await? {
-        return Ok(Download {
-            id: meta.id().into(),
-            path: destination_path,
-            installation: installation.clone(),
-            was_cached: true,
-        });
+        let is_valid = runtime::unblock({
+            let path = destination_path.clone();
+            let hash = hash.clone();
+            move || verify(&path, &hash)
+        })
+        .await?;
+
+        if is_valid {
+            return Ok(Download {
+                id: meta.id().into(),
+                path: destination_path,
+                installation: installation.clone(),
+                was_cached: true,
+                quarantined: None,
+            });
+        }
+
+        let path = quarantine(installation, &destination_path)?;
+        warn!(
+            package = %meta.name,
+            quarantined = %path.display(),
+            "Cached download is corrupt, downloading again"
+        );
+        quarantined = Some(path);
+    }
+
+    for shared in &installation.shared_caches {
+        let Some(path) = find_download_in(shared, hash) else {
+            continue;
+        };
+
+        let is_valid = runtime::unblock({
+            let path = path.clone();
+            let hash = hash.clone();
+            move || verify(&path, &hash)
+        })
+        .await?;
+
+        if is_valid {
+            return Ok(Download {
+                id: meta.id().into(),
+                path,
+                installation: installation.clone(),
+                was_cached: true,
+                quarantined,
+            });
+        }
+
+        warn!(
+            package = %meta.name,
+            path = %path.display(),
+            "Download in shared cache is corrupt, ignoring it"
+        );
     }
 
     let mut bytes = request::get(url).await?;
     let mut out = File::create(&partial_path).await?;
+    let mut hasher = Sha256::new();
 
     let mut total = 0;
 
@@ -116,6 +183,7 @@ pub async fn fetch(
         let delta = bytes.len() as u64;
         total += delta;
         out.write_all(&bytes).await?;
+        hasher.update(&bytes);
 
         (on_progress)(Progress {
             delta,
@@ -126,6 +194,17 @@ pub async fn fetch(
 
     out.flush().await?;
 
+    let actual = hex::encode(hasher.finalize());
+    if actual != *hash {
+        let path = quarantine(installation, &partial_path)?;
+        return Err(Error::HashMismatch {
+            package: meta.name.to_string(),
+            expected: hash.clone(),
+            actual,
+            quarantined: path,
+        });
+    }
+
     fs::rename(partial_path, &destination_path).await?;
 
     Ok(Download {
@@ -133,15 +212,72 @@ pub async fn fetch(
         path: destination_path,
         installation: installation.clone(),
         was_cached: false,
+        quarantined,
     })
 }
 
+/// Returns true if the sha256 hash of the file at `path` is `hash`
+pub fn verify(path: &Path, hash: &str) -> io::Result<bool> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs_err::File::open(path)?, &mut hasher)?;
+
+    Ok(hex::encode(hasher.finalize()) == hash)
+}
+
+/// Move the corrupt download at `path` out of the cache, returning where it's kept for inspection
+pub fn quarantine(installation: &Installation, path: &Path) -> io::Result<PathBuf> {
+    let dir = installation.cache_path("quarantine");
+    fs_err::create_dir_all(&dir)?;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let destination = dir.join(format!("{name}.{}", Utc::now().timestamp()));
+    fs_err::rename(path, &destination)?;
+
+    Ok(destination)
+}
+
+/// All downloads in the cache, as pairs of their path & hash
+pub fn downloads(installation: &Installation) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut downloads = vec![];
+
+    // `v2/{partition}/{hash prefix}/{hash suffix}/{hash}`
+    let mut dirs = vec![(installation.cache_path("downloads").join("v2"), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let entries = match fs_err::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+
+            if depth < 3 {
+                if entry.file_type()?.is_dir() {
+                    dirs.push((path, depth + 1));
+                }
+            } else if let Some(hash) = path.file_name().and_then(|name| name.to_str())
+                && path.extension().is_none()
+            {
+                downloads.push((path.clone(), hash.to_owned()));
+            }
+        }
+    }
+
+    downloads.sort();
+
+    Ok(downloads)
+}
+
 /// A package that has been downloaded to the installation
 pub struct Download {
     id: package::Id,
     path: PathBuf,
     installation: Installation,
     pub was_cached: bool,
+    /// Where the corrupt cached download was moved to, if it had to be fetched again
+    pub quarantined: Option<PathBuf>,
 }
 
 /// Upon fetch completion we have this unpacked asset bound with
@@ -151,6 +287,11 @@ pub struct UnpackedAsset {
 }
 
 impl Download {
+    /// Path of the downloaded stone
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// Unpack the downloaded package
     // TODO: Return an "Unpacked" struct which has a "blit" method on it?
     pub fn unpack(
@@ -284,8 +425,62 @@ fn check_assets_exist(indices: &[&payload::Index], installation: &Installation)
     })
 }
 
+/// Returns the directory downloads of the given partition are cached in
+///
+/// Each repository has its own partition so its downloads can be purged
+/// independently, see [`repository::Manager::partition`]. Packages that
+/// don't originate from a repository share the `local` partition.
+///
+/// [`repository::Manager::partition`]: crate::repository::Manager::partition
+pub fn download_dir(installation: &Installation, partition: Option<&str>) -> PathBuf {
+    installation
+        .cache_path("downloads")
+        .join("v2")
+        .join(partition.unwrap_or("local"))
+}
+
 /// Returns a fully qualified filesystem path to download the given hash ID into
-pub fn download_path(installation: &Installation, hash: &str) -> Result<PathBuf, Error> {
+pub fn download_path(installation: &Installation, partition: Option<&str>, hash: &str) -> Result<PathBuf, Error> {
+    if hash.len() < 5 {
+        return Err(Error::MalformedHash(hash.to_owned()));
+    }
+
+    let directory = download_dir(installation, partition)
+        .join(&hash[..5])
+        .join(&hash[hash.len() - 5..]);
+
+    Ok(directory.join(hash))
+}
+
+/// Returns the path the given hash ID was downloaded into by any partition, if any
+///
+/// The shared caches of the installation are searched after its own.
+pub fn find_download(installation: &Installation, hash: &str) -> Option<PathBuf> {
+    iter::once(installation.cache_path(""))
+        .chain(installation.shared_caches.iter().cloned())
+        .find_map(|cache| find_download_in(&cache, hash))
+}
+
+/// Returns the path the given hash ID was downloaded into by any partition of the `cache` dir
+fn find_download_in(cache: &Path, hash: &str) -> Option<PathBuf> {
+    if hash.len() < 5 {
+        return None;
+    }
+
+    let partitions = fs_err::read_dir(cache.join("downloads").join("v2")).ok()?;
+
+    partitions.flatten().find_map(|partition| {
+        let path = partition
+            .path()
+            .join(&hash[..5])
+            .join(&hash[hash.len() - 5..])
+            .join(hash);
+        path.exists().then_some(path)
+    })
+}
+
+/// Returns the path the given hash ID was downloaded into before the cache was partitioned
+fn legacy_download_path(installation: &Installation, hash: &str) -> Result<PathBuf, Error> {
     if hash.len() < 5 {
         return Err(Error::MalformedHash(hash.to_owned()));
     }
@@ -324,6 +519,13 @@ pub enum Error {
     MissingContent,
     #[error("Malformed download hash: {0}")]
     MalformedHash(String),
+    #[error("Download of {package} is corrupt, expected hash {expected} but got {actual}. Moved to {}", .quarantined.display())]
+    HashMismatch {
+        package: String,
+        expected: String,
+        actual: String,
+        quarantined: PathBuf,
+    },
     #[error("stone format")]
     Format(#[from] stone::read::Error),
     #[error("invalid url")]
@@ -0,0 +1,285 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Changelog of transactions
+//!
+//! A summary of every transaction is appended as a line of JSON to
+//! `.moss/log/transactions.jsonl`, so the history of a system can be
+//! reconstructed without the state db. Transactions on the running system
+//! are also sent to the systemd journal, with the summary in `MOSS_*` fields,
+//! or to syslog when there's no journal.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, Write},
+    os::unix::net::UnixDatagram,
+    process,
+    time::Duration,
+};
+
+use chrono::{SecondsFormat, Utc};
+use fs_err::{self as fs, OpenOptions};
+use nix::unistd::{Uid, User};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{Installation, Package};
+
+/// File in [`Installation::log_path`] holding an [`Entry`] per line
+pub const LOG_FILE: &str = "transactions.jsonl";
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Summary of a single transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: String,
+    /// What the transaction did, i.e. `install` or `activate`
+    pub operation: String,
+    pub old_state: Option<i32>,
+    pub new_state: i32,
+    /// Who ran the transaction, the invoking user for `sudo`
+    pub initiator: String,
+    pub duration_ms: u64,
+    pub added: Vec<Change>,
+    pub removed: Vec<Change>,
+    pub updated: Vec<Update>,
+}
+
+/// A package added or removed by a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub name: String,
+    pub version: String,
+}
+
+/// A package whose version changed in a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Update {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl Entry {
+    /// Summarize the transaction from `old_state` with the `old` packages to `new_state` with the `new` ones
+    pub fn new(
+        operation: impl ToString,
+        old_state: Option<i32>,
+        new_state: i32,
+        old: &[Package],
+        new: &[Package],
+        duration: Duration,
+    ) -> Self {
+        let version =
+            |package: &Package| format!("{}-{}", package.meta.version_identifier, package.meta.source_release);
+        fn by_name(packages: &[Package]) -> BTreeMap<String, &Package> {
+            packages
+                .iter()
+                .map(|package| (package.meta.name.to_string(), package))
+                .collect()
+        }
+        let old = by_name(old);
+        let new = by_name(new);
+
+        let added = new
+            .iter()
+            .filter(|(name, _)| !old.contains_key(*name))
+            .map(|(name, package)| Change {
+                name: name.clone(),
+                version: version(package),
+            })
+            .collect();
+        let removed = old
+            .iter()
+            .filter(|(name, _)| !new.contains_key(*name))
+            .map(|(name, package)| Change {
+                name: name.clone(),
+                version: version(package),
+            })
+            .collect();
+        let updated = new
+            .iter()
+            .filter_map(|(name, package)| {
+                let previous = old.get(name).filter(|previous| previous.id != package.id)?;
+                Some(Update {
+                    name: name.clone(),
+                    old: version(previous),
+                    new: version(package),
+                })
+            })
+            .collect();
+
+        Self {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            operation: operation.to_string().to_lowercase(),
+            old_state,
+            new_state,
+            initiator: initiator(),
+            duration_ms: duration.as_millis() as u64,
+            added,
+            removed,
+            updated,
+        }
+    }
+
+    /// One line summary for the system log
+    pub fn message(&self) -> String {
+        let old = self
+            .old_state
+            .map_or_else(|| "none".to_owned(), |state| format!("#{state}"));
+
+        format!(
+            "{} by {}: state {old} -> #{}, {} added, {} removed, {} updated in {}ms",
+            self.operation,
+            self.initiator,
+            self.new_state,
+            self.added.len(),
+            self.removed.len(),
+            self.updated.len(),
+            self.duration_ms
+        )
+    }
+
+    /// Fields of the journal entry, in the native protocol
+    fn journal_fields(&self) -> String {
+        let changes = |changes: &[Change]| {
+            changes
+                .iter()
+                .map(|change| format!("{}={}", change.name, change.version))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let updates = self
+            .updated
+            .iter()
+            .map(|update| format!("{}={}->{}", update.name, update.old, update.new))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        [
+            ("MESSAGE", self.message()),
+            ("PRIORITY", "6".to_owned()),
+            ("SYSLOG_IDENTIFIER", "moss".to_owned()),
+            ("MOSS_OPERATION", self.operation.clone()),
+            (
+                "MOSS_OLD_STATE",
+                self.old_state.map(|state| state.to_string()).unwrap_or_default(),
+            ),
+            ("MOSS_NEW_STATE", self.new_state.to_string()),
+            ("MOSS_INITIATOR", self.initiator.clone()),
+            ("MOSS_DURATION_MS", self.duration_ms.to_string()),
+            ("MOSS_ADDED", changes(&self.added)),
+            ("MOSS_REMOVED", changes(&self.removed)),
+            ("MOSS_UPDATED", updates),
+        ]
+        .into_iter()
+        .map(|(key, value)| format!("{key}={}\n", value.replace('\n', " ")))
+        .collect()
+    }
+}
+
+/// Record `entry` to the changelog of `installation` & the system log
+///
+/// Failing to do so doesn't fail the transaction, it has already been applied.
+pub fn record(installation: &Installation, entry: &Entry) {
+    if let Err(error) = append(installation, entry) {
+        warn!("Failed to write the transaction log: {error}");
+    }
+
+    // Transactions on other roots aren't part of the history of this system
+    if installation.root.to_string_lossy() == "/" {
+        send(entry);
+    }
+}
+
+fn append(installation: &Installation, entry: &Entry) -> io::Result<()> {
+    let path = installation.log_path(LOG_FILE);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    // A single write keeps lines whole with concurrent appends
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Send `entry` to the journal, falling back to syslog
+fn send(entry: &Entry) {
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+
+    if socket
+        .send_to(entry.journal_fields().as_bytes(), JOURNAL_SOCKET)
+        .is_ok()
+    {
+        return;
+    }
+
+    // `user.info`
+    let message = format!("<14>moss[{}]: {}", process::id(), entry.message());
+    let _ = socket.send_to(message.as_bytes(), SYSLOG_SOCKET);
+}
+
+/// The user running moss, or the one invoking it through `sudo`
+fn initiator() -> String {
+    if let Ok(user) = env::var("SUDO_USER")
+        && !user.is_empty()
+    {
+        return user;
+    }
+
+    let uid = Uid::current();
+    User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map_or_else(|| uid.to_string(), |user| user.name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_journal_fields() {
+        let entry = Entry {
+            timestamp: "2025-01-01T00:00:00Z".to_owned(),
+            operation: "sync".to_owned(),
+            old_state: None,
+            new_state: 1,
+            initiator: "root".to_owned(),
+            duration_ms: 42,
+            added: vec![Change {
+                name: "nano".to_owned(),
+                version: "8.0-1".to_owned(),
+            }],
+            removed: vec![],
+            updated: vec![Update {
+                name: "bash".to_owned(),
+                old: "5.2-1".to_owned(),
+                new: "5.3-2".to_owned(),
+            }],
+        };
+
+        assert_eq!(
+            entry.message(),
+            "sync by root: state none -> #1, 1 added, 0 removed, 1 updated in 42ms"
+        );
+
+        let fields = entry.journal_fields();
+        assert!(fields.lines().all(|line| line.contains('=')));
+        assert!(fields.contains("MOSS_OLD_STATE=\n"));
+        assert!(fields.contains("MOSS_ADDED=nano=8.0-1\n"));
+        assert!(fields.contains("MOSS_UPDATED=bash=5.2-1->5.3-2\n"));
+    }
+}
@@ -0,0 +1,239 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Divergence of the live `/usr` from the active state
+//!
+//! Once a state's `/usr` is prepared, the inode, size & modification time of each
+//! of its files is recorded as its baseline in `.moss/db/baseline/<id>`. Files
+//! changed by hand afterwards, i.e. a patched script in `/usr/bin`, no longer match
+//! the baseline and are reported before the next transaction replaces them, which
+//! can either carry them over to the new state or reset them to its files.
+//!
+//! `/etc` isn't covered, as its changes are merged by [`super::etc`].
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use fs_err::{self as fs, File};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use thiserror::Error;
+use vfs::tree::{BlitFile, Kind};
+
+use crate::{Installation, client::PendingFile, state};
+
+/// Directory in [`Installation::db_path`] holding the baseline of each state
+const BASELINE_DIR: &str = "baseline";
+
+/// How a file diverged from its state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Change {
+    Modified,
+    Removed,
+}
+
+/// A file of the active state changed at runtime
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Path relative to `/usr`
+    pub path: String,
+    pub change: Change,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/usr/{} ({})", self.path, self.change)
+    }
+}
+
+impl Divergence {
+    /// Whether `path`, either absolute or relative to `/usr`, refers to this file
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.strip_prefix("/usr").unwrap_or(path);
+        path == Path::new(&self.path)
+    }
+}
+
+/// Recorded metadata of a file, changed by any write to or replacement of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    ino: u64,
+    size: u64,
+    mtime: i128,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
+
+        Ok(Self {
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: i128::from(metadata.mtime()) * 1_000_000_000 + i128::from(metadata.mtime_nsec()),
+        })
+    }
+}
+
+fn baseline_path(installation: &Installation, state: state::Id) -> PathBuf {
+    installation.db_path(BASELINE_DIR).join(state.to_string())
+}
+
+/// Record the baseline of `state` from the files of `fstree` found in `usr`
+pub(crate) fn record(
+    installation: &Installation,
+    state: state::Id,
+    usr: &Path,
+    fstree: &vfs::Tree<PendingFile>,
+) -> Result<(), Error> {
+    let paths = fstree
+        .iter()
+        .filter(|file| matches!(file.kind(), Kind::Regular))
+        .filter_map(|file| file.path().strip_prefix("/usr/").map(str::to_owned))
+        // Lines of the baseline can't represent these
+        .filter(|path| !path.contains('\n'))
+        .collect::<Vec<_>>();
+
+    let stamps = paths
+        .into_par_iter()
+        .filter_map(|path| Stamp::of(&usr.join(&path)).ok().map(|stamp| (path, stamp)))
+        .collect::<BTreeMap<_, _>>();
+
+    let path = baseline_path(installation, state);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for (path, stamp) in &stamps {
+        writeln!(writer, "{} {} {} {path}", stamp.ino, stamp.size, stamp.mtime)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Remove the baseline of `state`, once it's pruned
+pub(crate) fn remove(installation: &Installation, state: state::Id) -> io::Result<()> {
+    match fs::remove_file(baseline_path(installation, state)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Files of the active state whose live copy in `/usr` diverged from its baseline
+///
+/// Empty if there's no baseline for the active state, i.e. one created by an older moss.
+pub fn detect(installation: &Installation) -> Result<Vec<Divergence>, Error> {
+    let Some(state) = installation.active_state else {
+        return Ok(vec![]);
+    };
+
+    let file = match File::open(baseline_path(installation, state)) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error.into()),
+    };
+
+    let baseline = BufReader::new(file)
+        .lines()
+        .map(|line| parse_line(&line?).ok_or(Error::InvalidBaseline(state)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let usr = installation.root.join("usr");
+    let mut divergences = baseline
+        .into_par_iter()
+        .filter_map(|(path, recorded)| {
+            let change = match Stamp::of(&usr.join(&path)) {
+                Ok(stamp) if stamp == recorded => return None,
+                Ok(_) => Change::Modified,
+                Err(_) => Change::Removed,
+            };
+            Some(Divergence { path, change })
+        })
+        .collect::<Vec<_>>();
+    divergences.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(divergences)
+}
+
+fn parse_line(line: &str) -> Option<(String, Stamp)> {
+    let mut fields = line.splitn(4, ' ');
+    let stamp = Stamp {
+        ino: fields.next()?.parse().ok()?,
+        size: fields.next()?.parse().ok()?,
+        mtime: fields.next()?.parse().ok()?,
+    };
+
+    Some((fields.next()?.to_owned(), stamp))
+}
+
+/// Carry the live state of the `kept` files over to the new `/usr` in `target`
+pub(crate) fn carry_over(installation: &Installation, target: &Path, kept: &[Divergence]) -> io::Result<()> {
+    let usr = installation.root.join("usr");
+
+    for divergence in kept {
+        let destination = target.join(&divergence.path);
+
+        match divergence.change {
+            Change::Modified => {
+                if let Some(parent) = destination.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // Never write through a hardlink shared with the asset store
+                if destination.symlink_metadata().is_ok() {
+                    fs::remove_file(&destination)?;
+                }
+                fs::copy(usr.join(&divergence.path), &destination)?;
+            }
+            Change::Removed => {
+                if destination.symlink_metadata().is_ok() {
+                    fs::remove_file(&destination)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid baseline of state {0}")]
+    InvalidBaseline(state::Id),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let (path, stamp) = parse_line("42 1024 1700000000123456789 share/doc/read me.txt").unwrap();
+        assert_eq!(path, "share/doc/read me.txt");
+        assert_eq!(
+            stamp,
+            Stamp {
+                ino: 42,
+                size: 1024,
+                mtime: 1_700_000_000_123_456_789,
+            }
+        );
+        assert!(parse_line("42 1024 share/doc").is_none());
+
+        let divergence = Divergence {
+            path: "bin/script".to_owned(),
+            change: Change::Modified,
+        };
+        assert!(divergence.matches(Path::new("/usr/bin/script")));
+        assert!(divergence.matches(Path::new("bin/script")));
+        assert_eq!(divergence.to_string(), "/usr/bin/script (modified)");
+    }
+}
@@ -0,0 +1,130 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Running the binaries of a root built for another architecture
+//!
+//! Triggers execute binaries of the root, which for a root of another architecture
+//! is only possible with a `binfmt_misc` handler such as `qemu-user-static`.
+
+use std::{
+    env::consts::ARCH,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+/// Where `binfmt_misc` handlers are registered
+const BINFMT_MISC: &str = "/proc/sys/fs/binfmt_misc";
+
+/// How binaries of a root can be run on this host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Support {
+    /// The root is of an architecture the host runs natively
+    Native,
+    /// Binaries are run through the registered `interpreter`
+    Emulated { interpreter: PathBuf },
+    /// Binaries of the root can't be run
+    Unsupported,
+}
+
+impl Support {
+    /// Returns true if binaries of the root can be run
+    pub fn can_execute(&self) -> bool {
+        !matches!(self, Support::Unsupported)
+    }
+}
+
+/// Detect how binaries of `root`, built for `architecture`, can be run on this host
+pub fn detect(architecture: &str, root: &Path) -> Support {
+    detect_with(architecture, root, Path::new(BINFMT_MISC))
+}
+
+fn detect_with(architecture: &str, root: &Path, binfmt_misc: &Path) -> Support {
+    // 64-bit x86 hosts run 32-bit x86 binaries natively
+    if architecture == ARCH || (architecture == "x86" && ARCH == "x86_64") {
+        return Support::Native;
+    }
+
+    // Handlers are registered under the name qemu uses for the architecture
+    let name = match architecture {
+        "x86" => "i386",
+        architecture => architecture,
+    };
+
+    let Ok(entry) = fs::read_to_string(binfmt_misc.join(format!("qemu-{name}"))) else {
+        return Support::Unsupported;
+    };
+
+    let mut enabled = false;
+    let mut interpreter = None;
+    let mut fix_binary = false;
+
+    for line in entry.lines() {
+        if line == "enabled" {
+            enabled = true;
+        } else if let Some(path) = line.strip_prefix("interpreter ") {
+            interpreter = Some(PathBuf::from(path.trim()));
+        } else if let Some(flags) = line.strip_prefix("flags:") {
+            fix_binary = flags.contains('F');
+        }
+    }
+
+    let Some(interpreter) = interpreter.filter(|_| enabled) else {
+        return Support::Unsupported;
+    };
+
+    // Triggers run isolated within the root, where the interpreter must either be
+    // opened by the kernel up front or exist within the root itself
+    if fix_binary
+        || root
+            .join(interpreter.strip_prefix("/").unwrap_or(&interpreter))
+            .exists()
+    {
+        Support::Emulated { interpreter }
+    } else {
+        Support::Unsupported
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let dir = tempfile::tempdir().unwrap();
+        let binfmt_misc = dir.path().join("binfmt_misc");
+        let root = dir.path().join("root");
+        fs::create_dir_all(&binfmt_misc).unwrap();
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+
+        // Pick a foreign architecture for the host running the test
+        let foreign = if ARCH == "aarch64" { "riscv64" } else { "aarch64" };
+        let entry = |enabled, flags| {
+            fs::write(
+                binfmt_misc.join(format!("qemu-{foreign}")),
+                format!("{enabled}\ninterpreter /usr/bin/qemu-{foreign}-static\nflags: {flags}\noffset 0\n"),
+            )
+            .unwrap();
+        };
+
+        assert_eq!(detect_with(ARCH, &root, &binfmt_misc), Support::Native);
+        assert_eq!(detect_with(foreign, &root, &binfmt_misc), Support::Unsupported);
+
+        entry("enabled", "OCF");
+        assert!(matches!(
+            detect_with(foreign, &root, &binfmt_misc),
+            Support::Emulated { .. }
+        ));
+
+        entry("disabled", "OCF");
+        assert_eq!(detect_with(foreign, &root, &binfmt_misc), Support::Unsupported);
+
+        // Without the fix binary flag, the interpreter must exist within the root
+        entry("enabled", "");
+        assert_eq!(detect_with(foreign, &root, &binfmt_misc), Support::Unsupported);
+        fs::write(root.join(format!("usr/bin/qemu-{foreign}-static")), "").unwrap();
+        assert!(detect_with(foreign, &root, &binfmt_misc).can_execute());
+    }
+}
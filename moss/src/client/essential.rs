@@ -0,0 +1,151 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Essential packages
+//!
+//! Packages a system can't work without, i.e. the C library or moss itself, are
+//! refused removal unless forced. The defaults can be extended, or exempted, in
+//! `essential.yaml` of `/usr/share/moss` & `/etc/moss` of the root:
+//!
+//! ```yaml
+//! packages:
+//!   - systemd
+//!   - soname(libc.so.6(x86_64))
+//! exempt:
+//!   - moss
+//! ```
+
+use std::{collections::BTreeSet, fmt};
+
+use serde::Deserialize;
+
+use crate::{Client, Package, Provider};
+
+/// Dependents listed before summarizing the rest
+const MAX_DEPENDENTS: usize = 8;
+
+/// Providers essential to every system
+const DEFAULTS: &[&str] = &["glibc", "moss"];
+
+/// Essential package configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Providers to treat as essential, in addition to the defaults
+    pub packages: Vec<String>,
+    /// Providers no longer essential, including defaults
+    pub exempt: Vec<String>,
+}
+
+impl config::Config for Config {
+    fn domain() -> String {
+        "essential".into()
+    }
+}
+
+/// An essential package that would be removed by a transaction
+#[derive(Debug, Clone)]
+pub struct Essential {
+    pub package: String,
+    /// Names of the removed packages depending on it
+    pub dependents: Vec<String>,
+}
+
+impl fmt::Display for Essential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.package)?;
+
+        match self.dependents.len() {
+            0 => {}
+            n if n <= MAX_DEPENDENTS => write!(f, " (needed by {})", self.dependents.join(", "))?,
+            n => write!(
+                f,
+                " (needed by {} & {} more)",
+                self.dependents[..MAX_DEPENDENTS].join(", "),
+                n - MAX_DEPENDENTS
+            )?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Providers configured as essential for the installation of `client`
+pub fn providers(client: &Client) -> BTreeSet<Provider> {
+    let configs = config::Manager::system(&client.installation.root, "moss").load::<Config>();
+    let parse = |name: &String| Provider::from_name(name).ok();
+
+    let exempt = configs
+        .iter()
+        .flat_map(|config| config.exempt.iter().filter_map(parse))
+        .collect::<BTreeSet<_>>();
+
+    DEFAULTS
+        .iter()
+        .map(|name| (*name).to_owned())
+        .chain(configs.iter().flat_map(|config| config.packages.clone()))
+        .filter_map(|name| parse(&name))
+        .filter(|provider| !exempt.contains(provider))
+        .collect()
+}
+
+/// Identify which of the `removed` packages are essential
+pub fn essential_removals(client: &Client, removed: &[Package]) -> Vec<Essential> {
+    if client.is_ephemeral() || removed.is_empty() {
+        return vec![];
+    }
+
+    let essential = providers(client);
+
+    removed
+        .iter()
+        .filter(|package| {
+            package
+                .meta
+                .providers
+                .iter()
+                .any(|provider| essential.contains(provider))
+        })
+        .map(|package| {
+            let dependents = removed
+                .iter()
+                .filter(|dependent| dependent.id != package.id)
+                .filter(|dependent| {
+                    dependent.meta.dependencies.iter().any(|dependency| {
+                        package.meta.providers.contains(&Provider {
+                            kind: dependency.kind,
+                            name: dependency.name.clone(),
+                        })
+                    })
+                })
+                .map(|dependent| dependent.meta.name.to_string())
+                .collect();
+
+            Essential {
+                package: package.meta.name.to_string(),
+                dependents,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let essential = |dependents: usize| Essential {
+            package: "glibc".to_owned(),
+            dependents: (0..dependents).map(|i| format!("pkg{i}")).collect(),
+        };
+
+        assert_eq!(essential(0).to_string(), "glibc");
+        assert_eq!(essential(2).to_string(), "glibc (needed by pkg0, pkg1)");
+        assert_eq!(
+            essential(10).to_string(),
+            "glibc (needed by pkg0, pkg1, pkg2, pkg3, pkg4, pkg5, pkg6, pkg7 & 2 more)"
+        );
+    }
+}
@@ -0,0 +1,611 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Configuration files in `/etc` kept in step with their vendor defaults
+//!
+//! Packages declare configuration files, which are installed to `/etc` from their
+//! vendor default under `/usr/share/defaults/etc`. When a new state changes a vendor
+//! default, the changes the admin made to `/etc` are kept by a three-way merge against
+//! the vendor default of the previous state. If both changed the same lines, the file
+//! in `/etc` is left alone and the new default is written alongside it as `<file>.new`
+//! to be merged by hand.
+//!
+//! Files in `/etc` overriding a vendor default are reported by [`status`], comparing
+//! them with the hashes recorded in the layouts of their packages.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::{self, Write as _},
+    os::unix::fs::{MetadataExt, fchown},
+    path::Path,
+};
+
+use fs_err::{self as fs, File};
+use stone::write::digest;
+use tracing::warn;
+
+use crate::package;
+
+/// Vendor defaults of configuration files, relative to the root
+pub const VENDOR_DIR: &str = "usr/share/defaults/etc";
+
+/// Appended to a configuration file to name its conflicting new default
+pub const NEW_SUFFIX: &str = ".new";
+
+/// Line pairs compared at most when merging a file, beyond which it's a conflict
+const MAX_COMPARISONS: usize = 16 * 1024 * 1024;
+
+/// The `/usr` & configuration files of the state being replaced
+#[derive(Debug, Clone, Copy)]
+pub struct Previous<'a> {
+    pub usr: &'a Path,
+    pub config_files: &'a BTreeSet<String>,
+}
+
+/// What happened to a configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Installed from the vendor default, as it didn't exist yet
+    Installed,
+    /// Replaced by the new vendor default, as the admin hadn't changed it
+    Updated,
+    /// Changes of the admin were merged with the new vendor default
+    Merged,
+    /// Changes of the admin conflict with the new vendor default, written to `<file>.new`
+    Conflict,
+}
+
+/// Configuration files changed by [`apply`], relative to `/etc`
+#[derive(Debug, Default)]
+pub struct Report {
+    pub changes: Vec<(String, Outcome)>,
+}
+
+impl Report {
+    /// Configuration files whose new vendor default needs merging by hand
+    pub fn conflicts(&self) -> impl Iterator<Item = &str> {
+        self.changes
+            .iter()
+            .filter(|(_, outcome)| *outcome == Outcome::Conflict)
+            .map(|(path, _)| path.as_str())
+    }
+}
+
+/// Bring the `config_files` in `/etc` of `root` in step with their vendor defaults
+///
+/// Without a `previous` state there's nothing to merge against, so only missing
+/// files are installed. Files the admin removed stay removed.
+pub fn apply(root: &Path, config_files: &BTreeSet<String>, previous: Option<Previous<'_>>) -> io::Result<Report> {
+    let mut report = Report::default();
+
+    for path in config_files {
+        if let Some(outcome) = apply_file(root, path, previous)? {
+            report.changes.push((path.clone(), outcome));
+        }
+    }
+
+    Ok(report)
+}
+
+fn apply_file(root: &Path, path: &str, previous: Option<Previous<'_>>) -> io::Result<Option<Outcome>> {
+    // Declared by the package, so it mustn't escape `/etc` or its vendor defaults
+    if !package::meta::is_config_file(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid configuration file path {path:?}"),
+        ));
+    }
+
+    let vendor = root.join(VENDOR_DIR).join(path);
+    let target = root.join("etc").join(path);
+
+    // The admin pointed it elsewhere, writing through it could clobber any file
+    if fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.is_symlink()) {
+        warn!("Skipped configuration file /etc/{path}, it's a symlink");
+        return Ok(None);
+    }
+
+    // Nothing to install if the package doesn't ship the default
+    let Some(theirs) = read(&vendor)? else {
+        return Ok(None);
+    };
+    let declared = previous.filter(|previous| previous.config_files.contains(path));
+
+    let Some(ours) = read(&target)? else {
+        if declared.is_some() {
+            return Ok(None);
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        replace(&target, &theirs, &vendor)?;
+        return Ok(Some(Outcome::Installed));
+    };
+
+    if ours == theirs || previous.is_none() {
+        return Ok(None);
+    }
+
+    let base = match declared {
+        Some(previous) => read(&previous.usr.join("share/defaults/etc").join(path))?,
+        None => None,
+    };
+
+    match merge(base.as_deref(), &ours, &theirs) {
+        Merge::Ours => Ok(None),
+        Merge::Theirs => {
+            // Retains the ownership & mode of the admin's file
+            replace(&target, &theirs, &target)?;
+            Ok(Some(Outcome::Updated))
+        }
+        Merge::Clean(merged) => {
+            replace(&target, &merged, &target)?;
+            Ok(Some(Outcome::Merged))
+        }
+        Merge::Conflict => {
+            replace(&root.join("etc").join(format!("{path}{NEW_SUFFIX}")), &theirs, &vendor)?;
+            Ok(Some(Outcome::Conflict))
+        }
+    }
+}
+
+/// Atomically replace `target` with `content`, owned by & with the mode of `like`
+///
+/// Written to a temporary file alongside, so `target` is never seen half written
+/// and a symlink in its place is replaced rather than written through.
+fn replace(target: &Path, content: &[u8], like: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(like)?;
+    let dir = target.parent().unwrap_or(Path::new("."));
+
+    let mut temp = tempfile::Builder::new().prefix(".moss-etc-").tempfile_in(dir)?;
+    temp.write_all(content)?;
+    fchown(temp.as_file(), Some(metadata.uid()), Some(metadata.gid()))?;
+    temp.as_file().set_permissions(metadata.permissions())?;
+    temp.as_file().sync_all()?;
+    temp.persist(target).map_err(|error| error.error)?;
+
+    Ok(())
+}
+
+/// A file in `/etc` that differs from its packaged vendor default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// Path relative to `/etc`
+    pub path: String,
+    /// Package shipping the vendor default
+    pub package: package::Id,
+    pub change: Change,
+}
+
+/// How a file in `/etc` differs from its vendor default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// Changed locally
+    Modified,
+    /// Removed locally, though installed as a configuration file
+    Removed,
+    /// Changed locally, with a conflicting new default awaiting a merge by hand
+    Conflict,
+}
+
+/// Compare the files in `/etc` of `root` with the vendor `defaults` of their packages
+///
+/// Each default is its path relative to `/etc`, the package shipping it and the hash
+/// of its content. Only declared `config_files` are reported as removed, as other
+/// defaults are used directly without a copy in `/etc`.
+pub fn status(
+    root: &Path,
+    defaults: impl IntoIterator<Item = (String, package::Id, u128)>,
+    config_files: &BTreeSet<String>,
+) -> io::Result<Vec<Status>> {
+    let etc = root.join("etc");
+    let mut statuses = vec![];
+
+    for (path, package, hash) in defaults {
+        let target = etc.join(&path);
+
+        let change = match self::hash(&target)? {
+            None if config_files.contains(&path) => Change::Removed,
+            None => continue,
+            Some(local) if local == hash => continue,
+            Some(_) if etc.join(format!("{path}{NEW_SUFFIX}")).exists() => Change::Conflict,
+            Some(_) => Change::Modified,
+        };
+
+        statuses.push(Status { path, package, change });
+    }
+
+    statuses.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(statuses)
+}
+
+/// Content hash of the regular file at `path`, as recorded in layouts, or `None`
+/// if it doesn't exist
+fn hash(path: &Path) -> io::Result<Option<u128>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let mut hasher = digest::Hasher::new();
+    let mut writer = digest::Writer::new(io::sink(), &mut hasher);
+    io::copy(&mut file, &mut writer)?;
+
+    Ok(Some(hasher.digest128()))
+}
+
+/// Contents of `path`, or `None` if it doesn't exist
+fn read(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Result of a three-way merge
+#[derive(Debug, PartialEq, Eq)]
+pub enum Merge {
+    /// Our content stands, as theirs made no other changes
+    Ours,
+    /// Their content stands, as we made no changes
+    Theirs,
+    /// Both made changes which were combined
+    Clean(Vec<u8>),
+    /// Both changed the same lines
+    Conflict,
+}
+
+/// Merge the changes `ours` & `theirs` made to `base` line by line
+///
+/// Without a `base`, differing content always conflicts.
+pub fn merge(base: Option<&[u8]>, ours: &[u8], theirs: &[u8]) -> Merge {
+    let Some(base) = base else {
+        return if ours == theirs { Merge::Ours } else { Merge::Conflict };
+    };
+
+    if ours == base {
+        Merge::Theirs
+    } else if theirs == base || ours == theirs {
+        Merge::Ours
+    } else {
+        diff3(base, ours, theirs).map_or(Merge::Conflict, Merge::Clean)
+    }
+}
+
+/// Combine the changes of `ours` & `theirs` to `base`, or `None` if they overlap
+fn diff3(base: &[u8], ours: &[u8], theirs: &[u8]) -> Option<Vec<u8>> {
+    let (base, ours, theirs) = (lines(base), lines(ours), lines(theirs));
+    let ours_matches = matches(&base, &ours)?;
+    let theirs_matches = matches(&base, &theirs)?;
+
+    let mut merged = vec![];
+    let (mut b, mut o, mut t) = (0, 0, 0);
+
+    loop {
+        // Lines unchanged by both sides are kept as is
+        if b < base.len() && ours_matches[b] == Some(o) && theirs_matches[b] == Some(t) {
+            merged.extend_from_slice(base[b]);
+            (b, o, t) = (b + 1, o + 1, t + 1);
+            continue;
+        }
+
+        // Otherwise the changed chunk runs up to the next line unchanged by both
+        let stable = (b..base.len()).find_map(|i| Some((i, ours_matches[i]?, theirs_matches[i]?)));
+        let (end_b, end_o, end_t) = stable.unwrap_or((base.len(), ours.len(), theirs.len()));
+        let (base_chunk, ours_chunk, theirs_chunk) = (&base[b..end_b], &ours[o..end_o], &theirs[t..end_t]);
+
+        let chunk = if ours_chunk == base_chunk {
+            theirs_chunk
+        } else if theirs_chunk == base_chunk || ours_chunk == theirs_chunk {
+            ours_chunk
+        } else {
+            return None;
+        };
+        merged.extend(chunk.concat());
+
+        if stable.is_none() {
+            return Some(merged);
+        }
+        (b, o, t) = (end_b, end_o, end_t);
+    }
+}
+
+/// Lines of context around each change of a [`unified_diff`]
+const DIFF_CONTEXT: usize = 3;
+
+/// Unified diff of the lines changed from `old` to `new`, without file headers
+///
+/// Returns `None` if the comparison is too costly.
+pub fn unified_diff(old: &[u8], new: &[u8]) -> Option<String> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Op {
+        Equal(usize),
+        Delete(usize),
+        Insert(usize),
+    }
+
+    let (old, new) = (lines(old), lines(new));
+    let matches = matches(&old, &new)?;
+
+    let mut ops = vec![];
+    let mut j = 0;
+    for (i, matched) in matches.iter().enumerate() {
+        match *matched {
+            Some(matched) => {
+                ops.extend((j..matched).map(Op::Insert));
+                ops.push(Op::Equal(i));
+                j = matched + 1;
+            }
+            None => ops.push(Op::Delete(i)),
+        }
+    }
+    ops.extend((j..new.len()).map(Op::Insert));
+
+    // Line numbers preceding each op, for the hunk headers
+    let (mut old_line, mut new_line) = (0, 0);
+    let positions = ops
+        .iter()
+        .map(|op| {
+            let position = (old_line, new_line);
+            match op {
+                Op::Equal(_) => (old_line, new_line) = (old_line + 1, new_line + 1),
+                Op::Delete(_) => old_line += 1,
+                Op::Insert(_) => new_line += 1,
+            }
+            position
+        })
+        .collect::<Vec<_>>();
+
+    let changes = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(_)))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+
+    // Changes close enough to share their context form a single hunk
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for idx in changes {
+        match hunks.last_mut() {
+            Some((_, last)) if idx - *last <= DIFF_CONTEXT * 2 => *last = idx,
+            _ => hunks.push((idx, idx)),
+        }
+    }
+
+    let mut diff = String::new();
+    for (first, last) in hunks {
+        let start = first.saturating_sub(DIFF_CONTEXT);
+        let end = (last + 1 + DIFF_CONTEXT).min(ops.len());
+        let hunk = &ops[start..end];
+
+        let old_count = hunk.iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+        let new_count = hunk.iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+        let (old_start, new_start) = positions[start];
+        // Empty ranges are numbered by the line preceding them
+        let old_start = if old_count > 0 { old_start + 1 } else { old_start };
+        let new_start = if new_count > 0 { new_start + 1 } else { new_start };
+        let _ = writeln!(diff, "@@ -{old_start},{old_count} +{new_start},{new_count} @@");
+
+        for op in hunk {
+            let (prefix, line) = match *op {
+                Op::Equal(i) => (' ', old[i]),
+                Op::Delete(i) => ('-', old[i]),
+                Op::Insert(j) => ('+', new[j]),
+            };
+            diff.push(prefix);
+            diff.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                diff.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    Some(diff)
+}
+
+/// Lines of `content`, including their terminator
+fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&byte| byte == b'\n').collect()
+}
+
+/// For each line of `a`, the line of `b` it matches in their longest common subsequence
+///
+/// Returns `None` if the comparison is too costly.
+fn matches(a: &[&[u8]], b: &[&[u8]]) -> Option<Vec<Option<usize>>> {
+    if a.len().saturating_mul(b.len()) > MAX_COMPARISONS {
+        return None;
+    }
+
+    // Length of the longest common subsequence of `a[i..]` & `b[j..]`
+    let width = b.len() + 1;
+    let mut lengths = vec![0_u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i * width + j] = if a[i] == b[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut matches = vec![None; a.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            matches[i] = Some(j);
+            (i, j) = (i + 1, j + 1);
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    Some(matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let base = b"a\nb\nc\nd\ne\n";
+
+        assert_eq!(merge(Some(base), base, b"a\nB\nc\nd\ne\n"), Merge::Theirs);
+        assert_eq!(merge(Some(base), b"a\nB\nc\nd\ne\n", base), Merge::Ours);
+        assert_eq!(
+            merge(Some(base), b"a\nB\nc\nd\ne\n", b"a\nb\nc\nd\nE\nf\n"),
+            Merge::Clean(b"a\nB\nc\nd\nE\nf\n".to_vec())
+        );
+        assert_eq!(
+            merge(Some(base), b"x\na\nb\nc\nd\ne\n", b"a\nb\nd\ne\n"),
+            Merge::Clean(b"x\na\nb\nd\ne\n".to_vec())
+        );
+        assert_eq!(
+            merge(Some(base), b"a\nB\nc\nd\ne\n", b"a\nb2\nc\nd\ne\n"),
+            Merge::Conflict
+        );
+        assert_eq!(merge(None, b"a\n", b"a\n"), Merge::Ours);
+        assert_eq!(merge(None, b"a\n", b"b\n"), Merge::Conflict);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = b"1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13";
+
+        assert_eq!(
+            unified_diff(old, new).unwrap(),
+            "@@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+             @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n\\ No newline at end of file\n"
+        );
+        assert_eq!(unified_diff(b"", b"a\n").unwrap(), "@@ -0,0 +1,1 @@\n+a\n");
+        assert_eq!(unified_diff(old, old).unwrap(), "");
+    }
+
+    #[test]
+    fn test_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let etc = dir.path().join("etc");
+        fs::create_dir_all(&etc).unwrap();
+
+        let digest = |content: &[u8]| {
+            let mut hasher = digest::Hasher::new();
+            hasher.update(content);
+            hasher.digest128()
+        };
+        let package = package::Id::from("test".to_owned());
+
+        fs::write(etc.join("same.conf"), "a\n").unwrap();
+        fs::write(etc.join("modified.conf"), "b\n").unwrap();
+        fs::write(etc.join("conflict.conf"), "b\n").unwrap();
+        fs::write(etc.join("conflict.conf.new"), "c\n").unwrap();
+
+        let defaults = [
+            "same.conf",
+            "modified.conf",
+            "conflict.conf",
+            "removed.conf",
+            "unused.conf",
+        ]
+        .map(|path| (path.to_owned(), package.clone(), digest(b"a\n")));
+        let config_files = BTreeSet::from(["removed.conf".to_owned()]);
+
+        let changes = status(dir.path(), defaults, &config_files)
+            .unwrap()
+            .into_iter()
+            .map(|status| (status.path, status.change))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            changes,
+            [
+                ("conflict.conf".to_owned(), Change::Conflict),
+                ("modified.conf".to_owned(), Change::Modified),
+                ("removed.conf".to_owned(), Change::Removed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let usr = dir.path().join("previous/usr");
+        let vendor = root.join(VENDOR_DIR);
+        let old_vendor = usr.join("share/defaults/etc");
+        let etc = root.join("etc");
+        for dir in [&vendor, &old_vendor, &etc] {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        let config_files = [
+            "fresh.conf",
+            "untouched.conf",
+            "merged.conf",
+            "conflict.conf",
+            "removed.conf",
+        ]
+        .map(ToOwned::to_owned)
+        .into_iter()
+        .collect::<BTreeSet<_>>();
+        let previous_files = config_files.iter().filter(|f| *f != "fresh.conf").cloned().collect();
+
+        for (path, base, ours, theirs) in [
+            ("untouched.conf", "a\n", Some("a\n"), "b\n"),
+            ("merged.conf", "a\nb\nc\n", Some("A\nb\nc\n"), "a\nb\nC\n"),
+            ("conflict.conf", "a\n", Some("b\n"), "c\n"),
+            ("removed.conf", "a\n", None, "b\n"),
+        ] {
+            fs::write(old_vendor.join(path), base).unwrap();
+            if let Some(ours) = ours {
+                fs::write(etc.join(path), ours).unwrap();
+            }
+            fs::write(vendor.join(path), theirs).unwrap();
+        }
+        fs::write(vendor.join("fresh.conf"), "new\n").unwrap();
+
+        let previous = Previous {
+            usr: &usr,
+            config_files: &previous_files,
+        };
+        let report = apply(&root, &config_files, Some(previous)).unwrap();
+
+        assert_eq!(
+            report.changes,
+            [
+                ("conflict.conf".to_owned(), Outcome::Conflict),
+                ("fresh.conf".to_owned(), Outcome::Installed),
+                ("merged.conf".to_owned(), Outcome::Merged),
+                ("untouched.conf".to_owned(), Outcome::Updated),
+            ]
+        );
+        assert_eq!(report.conflicts().collect::<Vec<_>>(), ["conflict.conf"]);
+
+        let read = |path: &str| fs::read_to_string(etc.join(path)).ok();
+        assert_eq!(read("fresh.conf").as_deref(), Some("new\n"));
+        assert_eq!(read("untouched.conf").as_deref(), Some("b\n"));
+        assert_eq!(read("merged.conf").as_deref(), Some("A\nb\nC\n"));
+        assert_eq!(read("conflict.conf").as_deref(), Some("b\n"));
+        assert_eq!(read("conflict.conf.new").as_deref(), Some("c\n"));
+        assert_eq!(read("removed.conf"), None);
+
+        // Symlinks the admin put in place are left alone, as is what they point to
+        fs::write(dir.path().join("elsewhere"), "ours\n").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("elsewhere"), etc.join("linked.conf")).unwrap();
+        fs::write(vendor.join("linked.conf"), "theirs\n").unwrap();
+        let linked = BTreeSet::from(["linked.conf".to_owned()]);
+        assert!(apply(&root, &linked, Some(previous)).unwrap().changes.is_empty());
+        assert_eq!(fs::read_to_string(dir.path().join("elsewhere")).unwrap(), "ours\n");
+
+        let escaping = BTreeSet::from(["../shadow".to_owned()]);
+        assert!(apply(&root, &escaping, Some(previous)).is_err());
+    }
+}
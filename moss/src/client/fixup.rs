@@ -0,0 +1,491 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Ownership & mode fixups applied after activation
+//!
+//! Packages can't ship correct ownership for paths outside of `/usr` when it
+//! depends on the target system, i.e. users allocated dynamically by `sysusers.d`.
+//! Instead they declare it in `tmpfiles.d` and we apply the ownership & mode
+//! related lines (`d`, `D`, `z` & `Z`) once the state is activated.
+//!
+//! The fixups of each state are recorded to `/usr/.fixups` and can be re-applied
+//! with `moss trigger run tmpfiles`.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ffi::OsString,
+    fmt, io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::fs::{PermissionsExt, lchown},
+    },
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use fs_err as fs;
+use nix::{
+    errno::Errno,
+    fcntl::{self, AtFlags, OFlag},
+    sys::stat::{Mode, SFlag, fstatat, mkdirat},
+};
+use thiserror::Error;
+use tracing::warn;
+
+/// Name of the trigger applying fixups
+pub const TRIGGER: &str = "tmpfiles";
+
+/// What a fixup line does, named after its `tmpfiles.d` type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Create the directory if missing, then adjust it (`d` & `D`)
+    Directory,
+    /// Adjust an existing path (`z`)
+    Adjust,
+    /// Adjust an existing path and everything below it (`Z`)
+    AdjustRecursive,
+}
+
+/// A single ownership/mode declaration for a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixup {
+    pub kind: Kind,
+    /// Absolute path on the target system
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+impl FromStr for Fixup {
+    type Err = ();
+
+    /// Parse a `tmpfiles.d` line, failing for any lines which aren't fixups
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next().ok_or(())?;
+        let path = fields.next().ok_or(())?;
+
+        // Boot only lines can't safely be applied to a running system
+        if kind.contains('!') {
+            return Err(());
+        }
+        let kind = match kind.trim_end_matches(['-', '=', '+', '^']) {
+            "d" | "D" => Kind::Directory,
+            "z" => Kind::Adjust,
+            "Z" => Kind::AdjustRecursive,
+            _ => return Err(()),
+        };
+
+        // `/usr` is owned by the state, and specifiers need a systemd runtime to expand
+        if !path.starts_with('/') || path == "/usr" || path.starts_with("/usr/") || path.contains('%') {
+            return Err(());
+        }
+        // Nor may the path escape the root it's applied to
+        if !Path::new(path)
+            .components()
+            .skip(1)
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(());
+        }
+
+        // `-` leaves a field unset, `:` restricts it to newly created paths which we don't distinguish
+        fn field(value: Option<&str>) -> Option<&str> {
+            value
+                .filter(|value| *value != "-")
+                .map(|value| value.trim_start_matches(':'))
+        }
+
+        Ok(Fixup {
+            kind,
+            path: PathBuf::from(path),
+            // Masked modes (`~`) depend on the existing mode, leave those alone
+            mode: field(fields.next())
+                .filter(|mode| !mode.starts_with('~'))
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok()),
+            user: field(fields.next()).map(ToOwned::to_owned),
+            group: field(fields.next()).map(ToOwned::to_owned),
+        })
+    }
+}
+
+impl fmt::Display for Fixup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            Kind::Directory => "d",
+            Kind::Adjust => "z",
+            Kind::AdjustRecursive => "Z",
+        };
+        let mode = self.mode.map_or_else(|| "-".to_owned(), |mode| format!("{mode:04o}"));
+
+        write!(
+            f,
+            "{kind} {} {mode} {} {}",
+            self.path.display(),
+            self.user.as_deref().unwrap_or("-"),
+            self.group.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// Load the fixups declared by `tmpfiles.d` snippets in `root`
+///
+/// Snippets in `/etc/tmpfiles.d` override those of the same name in `/usr/lib/tmpfiles.d`,
+/// and the first declaration of a path wins.
+pub fn load(root: &Path) -> Result<Vec<Fixup>, Error> {
+    let mut snippets = BTreeMap::new();
+
+    for dir in ["usr/lib/tmpfiles.d", "etc/tmpfiles.d"] {
+        let Ok(entries) = fs::read_dir(root.join(dir)) else {
+            continue;
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "conf")
+                && let Some(name) = path.file_name()
+            {
+                snippets.insert(name.to_owned(), path);
+            }
+        }
+    }
+
+    let mut fixups = Vec::<Fixup>::new();
+
+    for path in snippets.values() {
+        let contents = fs::read_to_string(path)?;
+
+        for fixup in contents.lines().filter_map(|line| line.trim().parse::<Fixup>().ok()) {
+            if !fixups.iter().any(|existing| existing.path == fixup.path) {
+                fixups.push(fixup);
+            }
+        }
+    }
+
+    Ok(fixups)
+}
+
+/// Record the fixups of the state whose `/usr` lives in `root`
+pub fn record(root: &Path, fixups: &[Fixup]) -> Result<(), Error> {
+    let contents = fixups.iter().map(|fixup| format!("{fixup}\n")).collect::<String>();
+
+    fs::write(root.join("usr").join(".fixups"), contents)?;
+
+    Ok(())
+}
+
+/// Apply the fixups to `root`, returning the number of paths that were adjusted
+///
+/// Unknown users or groups, missing paths and paths that can't be adjusted, i.e.
+/// by an unprivileged build, are skipped with a warning rather than failing activation.
+pub fn apply(root: &Path, fixups: &[Fixup]) -> usize {
+    let accounts = Accounts::load(root);
+
+    fixups
+        .iter()
+        .map(|fixup| {
+            apply_fixup(root, fixup, &accounts).unwrap_or_else(|error| {
+                warn!("Failed to apply fixup {fixup}: {error}");
+                0
+            })
+        })
+        .sum()
+}
+
+/// Apply a single fixup to `root`, returning the number of paths that were adjusted
+fn apply_fixup(root: &Path, fixup: &Fixup, accounts: &Accounts) -> io::Result<usize> {
+    let create = fixup.kind == Kind::Directory;
+    let Some(path) = resolve(root, &fixup.path, create)? else {
+        return Ok(0);
+    };
+
+    if create && path.symlink_metadata().is_err() {
+        fs::create_dir(&path)?;
+    }
+
+    if path.symlink_metadata().is_err() {
+        return Ok(0);
+    }
+
+    let uid = fixup.user.as_deref().and_then(|user| {
+        let uid = accounts.uid(user);
+        if uid.is_none() {
+            warn!("Unknown user {user} for {}", fixup.path.display());
+        }
+        uid
+    });
+    let gid = fixup.group.as_deref().and_then(|group| {
+        let gid = accounts.gid(group);
+        if gid.is_none() {
+            warn!("Unknown group {group} for {}", fixup.path.display());
+        }
+        gid
+    });
+
+    let paths = if fixup.kind == Kind::AdjustRecursive {
+        walk(&path)?
+    } else {
+        vec![path]
+    };
+
+    let mut adjusted = 0;
+
+    for path in paths {
+        match adjust(&path, uid, gid, fixup.mode) {
+            Ok(true) => adjusted += 1,
+            Ok(false) => {}
+            Err(error) => warn!("Failed to adjust {}: {error}", path.display()),
+        }
+    }
+
+    Ok(adjusted)
+}
+
+/// Set the ownership & mode of `path`, returning `false` for symlinks which are left alone
+fn adjust(path: &Path, uid: Option<u32>, gid: Option<u32>, mode: Option<u32>) -> io::Result<bool> {
+    // Never follow symlinks out of the fixed up tree
+    if path.symlink_metadata()?.is_symlink() {
+        return Ok(false);
+    }
+
+    if uid.is_some() || gid.is_some() {
+        lchown(path, uid, gid)?;
+    }
+    if let Some(mode) = mode {
+        fs::set_permissions(path, PermissionsExt::from_mode(mode))?;
+    }
+
+    Ok(true)
+}
+
+/// Most symlinks followed when resolving a path, as for the kernel
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolve the absolute `path` within `root`, as though `root` were `/`
+///
+/// Each directory along it is opened with `O_NOFOLLOW` relative to the last, and
+/// symlinks are followed by hand so absolute ones (i.e. `/var/run -> /run`) stay
+/// within `root` rather than leading to the host. Missing directories are created
+/// if `create` is set, otherwise `None` is returned. The final component is kept
+/// as is, so a symlink there is never followed.
+fn resolve(root: &Path, path: &Path, create: bool) -> io::Result<Option<PathBuf>> {
+    let open = |parent: i32, name: &OsString| -> io::Result<OwnedFd> {
+        let fd = fcntl::openat(
+            parent,
+            name.as_os_str(),
+            OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        // SAFETY: the descriptor was just opened and is owned by nothing else
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    };
+
+    let root_fd = std::fs::File::open(root)?;
+    let mut dirs = Vec::<(OsString, OwnedFd)>::new();
+    let mut pending = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_owned()),
+            _ => None,
+        })
+        .collect::<VecDeque<_>>();
+    let mut symlinks = 0;
+
+    while let Some(name) = pending.pop_front() {
+        if name == ".." {
+            dirs.pop();
+            continue;
+        }
+        if pending.is_empty() {
+            return Ok(Some(joined(root, &dirs).join(name)));
+        }
+
+        let parent = dirs.last().map_or(root_fd.as_raw_fd(), |(_, fd)| fd.as_raw_fd());
+
+        match fstatat(parent, name.as_os_str(), AtFlags::AT_SYMLINK_NOFOLLOW) {
+            Ok(stat) if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFLNK => {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(Errno::ELOOP.into());
+                }
+
+                let target = PathBuf::from(fcntl::readlinkat(parent, name.as_os_str())?);
+                if target.is_absolute() {
+                    dirs.clear();
+                }
+                for component in target.components().rev() {
+                    match component {
+                        Component::Normal(name) => pending.push_front(name.to_owned()),
+                        Component::ParentDir => pending.push_front("..".into()),
+                        Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+                    }
+                }
+                continue;
+            }
+            Ok(_) => {}
+            Err(Errno::ENOENT) if create => mkdirat(parent, name.as_os_str(), Mode::from_bits_truncate(0o755))?,
+            Err(Errno::ENOENT) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        }
+
+        let fd = open(parent, &name)?;
+        dirs.push((name, fd));
+    }
+
+    Ok(Some(joined(root, &dirs)))
+}
+
+/// The path of the last of the `dirs` opened below `root`
+fn joined(root: &Path, dirs: &[(OsString, OwnedFd)]) -> PathBuf {
+    dirs.iter().fold(root.to_owned(), |path, (name, _)| path.join(name))
+}
+
+/// Returns `path` and everything below it, without following symlinks
+fn walk(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![path.to_owned()];
+
+    if path.symlink_metadata()?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            paths.extend(walk(&entry?.path())?);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Users & groups of the target system
+struct Accounts {
+    users: BTreeMap<String, u32>,
+    groups: BTreeMap<String, u32>,
+}
+
+impl Accounts {
+    fn load(root: &Path) -> Self {
+        let parse = |name: &str| {
+            // Entries in `/etc` take precedence
+            ["usr/lib", "etc"]
+                .iter()
+                .filter_map(|dir| fs::read_to_string(root.join(dir).join(name)).ok())
+                .flat_map(|contents| {
+                    contents
+                        .lines()
+                        .filter_map(|line| {
+                            let mut fields = line.split(':');
+                            let name = fields.next()?;
+                            let id = fields.nth(1)?.parse().ok()?;
+                            Some((name.to_owned(), id))
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+
+        Self {
+            users: parse("passwd"),
+            groups: parse("group"),
+        }
+    }
+
+    fn uid(&self, user: &str) -> Option<u32> {
+        user.parse().ok().or_else(|| self.users.get(user).copied())
+    }
+
+    fn gid(&self, group: &str) -> Option<u32> {
+        group.parse().ok().or_else(|| self.groups.get(group).copied())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fixup() {
+        let fixup = "d /var/lib/colord 0755 colord colord -".parse::<Fixup>().unwrap();
+        assert_eq!(fixup.kind, Kind::Directory);
+        assert_eq!(fixup.path, PathBuf::from("/var/lib/colord"));
+        assert_eq!(fixup.mode, Some(0o755));
+        assert_eq!(fixup.user.as_deref(), Some("colord"));
+        assert_eq!(fixup.group.as_deref(), Some("colord"));
+        assert_eq!(fixup.to_string(), "d /var/lib/colord 0755 colord colord");
+
+        let fixup = "Z /var/log/journal ~2755 - systemd-journal".parse::<Fixup>().unwrap();
+        assert_eq!(fixup.kind, Kind::AdjustRecursive);
+        assert_eq!(fixup.mode, None);
+        assert_eq!(fixup.user, None);
+        assert_eq!(fixup.group.as_deref(), Some("systemd-journal"));
+
+        for line in [
+            "# comment",
+            "L /etc/mtab - - - - ../proc/self/mounts",
+            "d! /tmp/.X11-unix 1777 root root",
+            "d /usr/share/foo 0755 root root",
+            "d %h/.cache 0700 - -",
+            "d /var/../usr/lib 0755 root root",
+        ] {
+            assert!(line.parse::<Fixup>().is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("run/lock")).unwrap();
+        fs::create_dir_all(root.join("var")).unwrap();
+        std::os::unix::fs::symlink("/run", root.join("var/run")).unwrap();
+        std::os::unix::fs::symlink("../run/lock", root.join("var/lock")).unwrap();
+
+        // Absolute symlinks resolve within the root rather than on the host
+        assert_eq!(
+            resolve(root, Path::new("/var/run/colord"), false).unwrap(),
+            Some(root.join("run/colord"))
+        );
+        assert_eq!(
+            resolve(root, Path::new("/var/lock/subsys"), false).unwrap(),
+            Some(root.join("run/lock/subsys"))
+        );
+        // The final component is never followed
+        assert_eq!(
+            resolve(root, Path::new("/var/run"), false).unwrap(),
+            Some(root.join("var/run"))
+        );
+
+        assert_eq!(resolve(root, Path::new("/srv/www/html"), false).unwrap(), None);
+        assert_eq!(
+            resolve(root, Path::new("/srv/www/html"), true).unwrap(),
+            Some(root.join("srv/www/html"))
+        );
+        assert!(root.join("srv/www").is_dir());
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("var")).unwrap();
+        fs::write(root.join("var/file"), "").unwrap();
+
+        let fixups = [
+            // Can't be created below a file, which is skipped
+            "d /var/file/child 0750 - -",
+            "d /var/lib/colord 0750 - -",
+            "z /var/missing 0750 - -",
+        ]
+        .map(|line| line.parse::<Fixup>().unwrap());
+
+        assert_eq!(apply(root, &fixups), 1);
+        assert_eq!(
+            root.join("var/lib/colord").metadata().unwrap().permissions().mode() & 0o7777,
+            0o750
+        );
+    }
+}
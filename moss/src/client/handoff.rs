@@ -0,0 +1,161 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Handing activation over to the moss of a new state
+//!
+//! moss links against libraries it manages itself. Once a state replacing any of them
+//! is promoted, the running process is left with a stale image: anything it loads from
+//! then on (NSS modules, `libgcc_s` for unwinding, ...) comes from the new `/usr` and
+//! may not match the libraries it already mapped. When that happens, the new moss is
+//! verified to run & to support completing an activation, then the remainder of the
+//! activation (system triggers, fixups & boot synchronization) is completed by it,
+//! against a consistent `/usr`. An older moss, i.e. when activating a previous state,
+//! can't complete it so the running moss does.
+
+use std::{
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use fs_err as fs;
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{Installation, installation::lockfile};
+
+/// Files of the root's `/usr` mapped into the running moss, by device & inode
+#[derive(Debug, Default)]
+pub struct Image {
+    files: Vec<(PathBuf, u64, u64)>,
+}
+
+impl Image {
+    /// Capture the files of `root`'s `/usr` mapped into this process
+    ///
+    /// Must be captured before promoting a state, as mappings follow the files
+    /// they map when the old `/usr` is archived.
+    pub fn capture(root: &Path) -> Self {
+        let Ok(usr) = root.join("usr").canonicalize() else {
+            return Self::default();
+        };
+        let Ok(maps) = fs::read_to_string("/proc/self/maps") else {
+            return Self::default();
+        };
+
+        let files = maps
+            .lines()
+            .filter_map(mapped_path)
+            .filter(|path| path.starts_with(&usr))
+            .unique()
+            .filter_map(|path| {
+                let metadata = fs::metadata(&path).ok()?;
+                Some((path, metadata.dev(), metadata.ino()))
+            })
+            .collect();
+
+        Self { files }
+    }
+
+    /// Whether any captured file was replaced or removed since capture
+    pub fn is_stale(&self) -> bool {
+        self.files.iter().any(|(path, dev, ino)| {
+            fs::metadata(path).map_or(true, |metadata| (metadata.dev(), metadata.ino()) != (*dev, *ino))
+        })
+    }
+}
+
+/// Path of the file backing a `/proc/self/maps` entry, if any
+fn mapped_path(line: &str) -> Option<PathBuf> {
+    // address perms offset dev inode [path]
+    let path = line.split_whitespace().nth(5)?;
+
+    // Anonymous & pseudo mappings such as `[heap]`, or files deleted since mapping
+    if !path.starts_with('/') || line.ends_with(" (deleted)") {
+        return None;
+    }
+
+    Some(PathBuf::from(path))
+}
+
+/// Verify the moss of the active state runs & supports `state complete`, then complete
+/// its activation with it
+///
+/// Locks on the installation are shared with the new moss, which blocks until
+/// it's done.
+pub fn complete(installation: &Installation, skip_triggers: bool, sync_boot: bool) -> Result<(), Error> {
+    let moss = installation.root.join("usr/bin/moss");
+
+    // Fails for a moss predating the subcommand, before anything was handed over
+    let verified = Command::new(&moss)
+        .args(["state", "complete", "--help"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(Error::Spawn)?;
+    if !verified.success() {
+        return Err(Error::Verify(verified));
+    }
+
+    let locks = installation.share_locks()?;
+
+    let mut command = Command::new(&moss);
+    command
+        .arg("-D")
+        .arg(&installation.root)
+        .args(["state", "complete"])
+        .env(lockfile::SHARED_ENV, locks.iter().join(","));
+    for cache_dir in installation.cache_dirs() {
+        command.arg("--cache").arg(cache_dir);
+    }
+    if skip_triggers {
+        command.arg("--skip-triggers");
+    }
+    if sync_boot {
+        command.arg("--sync-boot");
+    }
+
+    let status = command.status().map_err(Error::Spawn)?;
+    if !status.success() {
+        return Err(Error::Complete(status));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("run new moss")]
+    Spawn(#[source] io::Error),
+    #[error("new moss failed to run or can't complete activation: {0}")]
+    Verify(ExitStatus),
+    #[error("share installation locks")]
+    ShareLocks(#[from] lockfile::Error),
+    #[error("new moss failed to complete activation: {0}")]
+    Complete(ExitStatus),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mapped_path() {
+        assert_eq!(
+            mapped_path("7f1c2a000000-7f1c2a028000 r--p 00000000 fd:01 1835 /usr/lib/libc.so.6"),
+            Some(PathBuf::from("/usr/lib/libc.so.6"))
+        );
+        assert_eq!(
+            mapped_path("7f1c2a000000-7f1c2a028000 r--p 00000000 fd:01 1835 /usr/lib/libz.so.1 (deleted)"),
+            None
+        );
+        assert_eq!(
+            mapped_path("55d0c1a4e000-55d0c1a6f000 rw-p 00000000 00:00 0 [heap]"),
+            None
+        );
+        assert_eq!(mapped_path("7ffd3b1c6000-7ffd3b1c8000 rw-p 00000000 00:00 0"), None);
+    }
+}
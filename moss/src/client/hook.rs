@@ -0,0 +1,368 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Administrator defined hooks, run before & after every transaction
+//!
+//! Hooks are either executables dropped into `/etc/moss/hooks.d`, which run in
+//! both phases, or declarative `*.yaml` files in `/usr/share/moss/hooks.d` or
+//! `/etc/moss/hooks.d`:
+//!
+//! ```yaml
+//! run: /usr/bin/fc-cache
+//! args: ["-s"]
+//! when: post
+//! packages: ["font-*"]
+//! ```
+//!
+//! The changeset is passed as JSON on stdin, and summarized via `MOSS_HOOK_*`
+//! environment variables.
+
+use std::{
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use container::Container;
+use fnmatch::Pattern;
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::Installation;
+
+/// When a hook runs relative to the transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Phase {
+    /// Before the new state is applied
+    Pre,
+    /// After the new state has been activated
+    #[default]
+    Post,
+}
+
+/// A declarative hook
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hook {
+    /// Executable to run
+    pub run: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub when: Phase,
+    /// Only run when any package with a matching name changes
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Abort the transaction if a `pre` hook fails
+    #[serde(default)]
+    pub abort_on_fail: bool,
+}
+
+impl config::Config for Hook {
+    fn domain() -> String {
+        "hooks".into()
+    }
+}
+
+/// A package added or removed by a transaction
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub name: String,
+    pub version: String,
+}
+
+/// The packages changed by a transaction
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Changeset {
+    /// The new state, only known once applied
+    pub state: Option<i32>,
+    pub added: Vec<Change>,
+    pub removed: Vec<Change>,
+}
+
+impl Changeset {
+    fn names(changes: &[Change]) -> String {
+        changes
+            .iter()
+            .map(|change| change.name.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Run all hooks of `phase` against `installation`
+pub fn run(installation: &Installation, phase: Phase, changeset: &Changeset) -> Result<(), Error> {
+    let invocations = invocations(&installation.root, phase, changeset);
+
+    if invocations.is_empty() {
+        return Ok(());
+    }
+
+    let input = serde_json::to_vec(&PhaseChangeset { phase, changeset })?;
+
+    for invocation in &invocations {
+        let execute = || invocation.execute(phase, changeset, &input);
+
+        let result = if installation.root.to_string_lossy() == "/" {
+            execute().map_err(Error::from)
+        } else {
+            let root = &installation.root;
+            let mut container = Container::new(installation.isolation_dir())
+                .bind_rw(root.join("etc"), "/etc")
+                .bind_rw(root.join("usr"), "/usr")
+                .work_dir("/");
+            if root.join("var").exists() {
+                container = container.bind_rw(root.join("var"), "/var");
+            }
+            container.run(execute).map_err(Error::from)
+        };
+
+        if let Err(error) = result {
+            if invocation.abort_on_fail {
+                return Err(error);
+            }
+            warn!("{phase} hook {} failed: {error}", invocation.run.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Declarative hooks of `phase` in `root` concerned with `changeset`, followed by the hook scripts in name order
+fn invocations(root: &Path, phase: Phase, changeset: &Changeset) -> Vec<Invocation> {
+    let dir = root.join("etc").join("moss").join("hooks.d");
+
+    let declarative = config::Manager::system(root, "moss")
+        .load::<Hook>()
+        .into_iter()
+        .filter(|hook| hook.when == phase && hook.matches(changeset));
+
+    let mut invocations = declarative
+        .map(|hook| Invocation {
+            run: hook.run,
+            args: hook.args,
+            abort_on_fail: phase == Phase::Pre && hook.abort_on_fail,
+        })
+        .collect::<Vec<_>>();
+
+    // Scripts never abort the transaction, nor does failing to list them
+    let scripts = scripts(&dir).unwrap_or_else(|error| {
+        warn!("failed to read {phase} hooks from {}: {error}", dir.display());
+        vec![]
+    });
+
+    invocations.extend(scripts.into_iter().map(|path| Invocation {
+        // Run from within the root so the path is valid in the container too
+        run: Path::new("/").join(path.strip_prefix(root).unwrap_or(&path)),
+        args: vec![],
+        abort_on_fail: false,
+    }));
+
+    invocations
+}
+
+impl Hook {
+    fn matches(&self, changeset: &Changeset) -> bool {
+        if self.packages.is_empty() {
+            return true;
+        }
+
+        let patterns = self
+            .packages
+            .iter()
+            .filter_map(|pattern| pattern.parse::<Pattern>().ok())
+            .collect::<Vec<_>>();
+
+        changeset.added.iter().chain(&changeset.removed).any(|change| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.match_path(&change.name).is_some())
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct PhaseChangeset<'a> {
+    phase: Phase,
+    #[serde(flatten)]
+    changeset: &'a Changeset,
+}
+
+struct Invocation {
+    run: PathBuf,
+    args: Vec<String>,
+    abort_on_fail: bool,
+}
+
+impl Invocation {
+    fn execute(&self, phase: Phase, changeset: &Changeset, input: &[u8]) -> Result<(), ExecuteError> {
+        let mut command = Command::new(&self.run);
+        command
+            .args(&self.args)
+            .current_dir("/")
+            .env("MOSS_HOOK_PHASE", phase.to_string())
+            .env("MOSS_HOOK_ADDED", Changeset::names(&changeset.added))
+            .env("MOSS_HOOK_REMOVED", Changeset::names(&changeset.removed))
+            .stdin(Stdio::piped());
+        if let Some(state) = changeset.state {
+            command.env("MOSS_HOOK_STATE", state.to_string());
+        }
+
+        let mut child = command.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // Hooks aren't required to read the changeset
+            let _ = stdin.write_all(input);
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(ExecuteError::Status(status.code()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Executable files in the hooks directory
+fn scripts(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(vec![]);
+    };
+
+    let mut scripts = vec![];
+
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        let is_declarative = entry.path().extension().is_some_and(|ext| ext == "yaml");
+
+        if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 && !is_declarative {
+            scripts.push(entry.path());
+        }
+    }
+
+    scripts.sort();
+
+    Ok(scripts)
+}
+
+#[derive(Debug, Error)]
+pub enum ExecuteError {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("exited with status {0:?}")]
+    Status(Option<i32>),
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("encode changeset")]
+    Json(#[from] serde_json::Error),
+    #[error("execute")]
+    Execute(#[from] ExecuteError),
+    #[error("container")]
+    Container(#[from] container::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn changeset(added: &[&str], removed: &[&str]) -> Changeset {
+        let changes = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| Change {
+                    name: (*name).to_owned(),
+                    version: "1.0".to_owned(),
+                })
+                .collect()
+        };
+
+        Changeset {
+            state: None,
+            added: changes(added),
+            removed: changes(removed),
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let hook = |packages: &[&str]| Hook {
+            run: "/usr/bin/true".into(),
+            args: vec![],
+            when: Phase::Post,
+            packages: packages.iter().map(|package| (*package).to_owned()).collect(),
+            abort_on_fail: false,
+        };
+
+        // Without packages, any transaction concerns the hook
+        assert!(hook(&[]).matches(&changeset(&[], &[])));
+        assert!(hook(&["font-*"]).matches(&changeset(&["font-dejavu"], &[])));
+        assert!(hook(&["font-*"]).matches(&changeset(&[], &["font-noto"])));
+        assert!(hook(&["nano", "font-*"]).matches(&changeset(&["nano"], &[])));
+        assert!(!hook(&["font-*"]).matches(&changeset(&["fontconfig"], &["nano"])));
+        assert!(!hook(&["nano"]).matches(&changeset(&["nano-syntax"], &[])));
+    }
+
+    #[test]
+    fn test_invocations() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("etc/moss/hooks.d");
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(
+            dir.join("fonts.yaml"),
+            "run: /usr/bin/fc-cache\nargs: [\"-s\"]\npackages: [\"font-*\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("backup.yaml"),
+            "run: /usr/bin/backup\nwhen: pre\nabort-on-fail: true\n",
+        )
+        .unwrap();
+        for (name, mode) in [("20-notify", 0o755), ("10-log", 0o755), ("30-disabled", 0o644)] {
+            fs::write(dir.join(name), "#!/bin/sh\n").unwrap();
+            fs::set_permissions(dir.join(name), std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let summary = |invocations: Vec<Invocation>| {
+            invocations
+                .into_iter()
+                .map(|invocation| (invocation.run, invocation.args, invocation.abort_on_fail))
+                .collect::<Vec<_>>()
+        };
+        let script = |name: &str| (PathBuf::from("/etc/moss/hooks.d").join(name), vec![], false);
+
+        // Declarative hooks come first, then the executable scripts by name
+        assert_eq!(
+            summary(invocations(root.path(), Phase::Post, &changeset(&["font-dejavu"], &[]))),
+            [
+                (PathBuf::from("/usr/bin/fc-cache"), vec!["-s".to_owned()], false),
+                script("10-log"),
+                script("20-notify"),
+            ]
+        );
+        assert_eq!(
+            summary(invocations(root.path(), Phase::Post, &changeset(&["nano"], &[]))),
+            [script("10-log"), script("20-notify")]
+        );
+        assert_eq!(
+            summary(invocations(root.path(), Phase::Pre, &changeset(&["nano"], &[]))),
+            [
+                (PathBuf::from("/usr/bin/backup"), vec![], true),
+                script("10-log"),
+                script("20-notify"),
+            ]
+        );
+    }
+}
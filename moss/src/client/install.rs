@@ -7,30 +7,37 @@
 use std::time::{Duration, Instant};
 
 use thiserror::Error;
-use tracing::{Instrument, debug, info, info_span, instrument};
-use tui::{
-    dialoguer::{Confirm, theme::ColorfulTheme},
-    pretty::autoprint_columns,
-};
+use tracing::{Instrument, debug, info, instrument};
+use tracing_common::progress;
+use tui::{Styled, pretty::autoprint_columns};
 
 use crate::{
     Package, Provider,
     client::{self, Client},
-    package::{self, Flags},
+    package::{
+        self, Flags,
+        render::{self, Change},
+    },
+    prompt,
     registry::transaction,
     runtime,
     state::Selection,
+    system_model,
 };
 
 /// Install a set of packages.
 ///
 /// If this call is successful a new State is recorded into the [`super::db::state::Database`].
 /// Upon completion the `/usr` tree is "hot swapped" with the staging tree through `renameat2` call.
+///
+/// Returns `None` if all requested packages are already installed.
 #[instrument(skip(client), fields(ephemeral = client.is_ephemeral()))]
-pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing, Error> {
+pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Option<Timing>, Error> {
     let mut timing = Timing::default();
     let mut instant = Instant::now();
 
+    let phase = progress::Phase::start("resolve", pkgs.len());
+
     // Resolve input packages
     let input = resolve_input(pkgs, client)?;
     debug!(resolved_packages = input.len(), "Resolved input packages");
@@ -56,7 +63,31 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
         .filter(|p| client.is_ephemeral() || !is_installed(p))
         .collect::<Vec<_>>();
 
+    // Packages superseded by an incoming package are dropped from the new state
+    let replaced = installed
+        .iter()
+        .filter(|i| {
+            missing
+                .iter()
+                .any(|m| i.meta.providers.iter().any(|p| m.meta.replaces.contains(p)))
+        })
+        .collect::<Vec<_>>();
+
+    // Ensure incoming packages can coexist with what remains installed
+    let remaining = installed
+        .iter()
+        .filter(|i| !replaced.iter().any(|r| r.id == i.id) && !missing.iter().any(|m| m.meta.name == i.meta.name))
+        .chain(missing.iter().copied())
+        .collect::<Vec<_>>();
+    if let Some((package, other)) = transaction::find_conflict(&remaining) {
+        return Err(Error::Transaction(transaction::Error::Conflict {
+            package: package.meta.name.to_string(),
+            conflicts_with: other.meta.name.to_string(),
+        }));
+    }
+
     timing.resolve = instant.elapsed();
+    phase.complete(resolved.len());
     info!(
         total_resolved = resolved.len(),
         missing_packages = missing.len(),
@@ -79,7 +110,7 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
             autoprint_columns(&installed);
         }
 
-        return Ok(timing);
+        return Ok(None);
     }
 
     // Testing panic for hyperfine benchmarking purposes (build flag tuning)
@@ -87,43 +118,33 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
 
     println!("The following package(s) will be installed:");
     println!();
-    autoprint_columns(&missing);
+    render::print_changes(&missing.iter().map(|p| Change::Add(p)).collect::<Vec<_>>());
     println!();
 
+    if !replaced.is_empty() {
+        println!("The following package(s) are replaced and will be removed:");
+        println!();
+        render::print_changes(&replaced.iter().map(|p| Change::Remove(p)).collect::<Vec<_>>());
+        println!();
+    }
+
+    client.check_space(&missing, &replaced)?;
+
     // Must we prompt?
-    let result = if yes {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm("install packages", " Do you wish to continue? ", yes)?;
     if !result {
         return Err(Error::Cancelled);
     }
 
     instant = Instant::now();
 
-    let cache_packages_span = info_span!("progress", phase = "cache_packages", event_type = "progress");
-    let _cache_packages_guard = cache_packages_span.enter();
-    info!(
-        total_items = missing.len(),
-        progress = 0.0,
-        event_type = "progress_start"
-    );
+    let phase = progress::Phase::start("cache_packages", missing.len());
 
     // Cache packages
     runtime::block_on(client.cache_packages(&missing).in_current_span())?;
 
     timing.fetch = instant.elapsed();
-    info!(
-        duration_ms = timing.fetch.as_millis(),
-        items_processed = missing.len(),
-        progress = 1.0,
-        event_type = "progress_completed",
-    );
-    drop(_cache_packages_guard);
+    phase.complete(missing.len());
     instant = Instant::now();
 
     // Calculate the new state of packages (old_state + missing)
@@ -133,29 +154,45 @@ pub fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<Timing,
             Some(id) if !client.is_ephemeral() => client.state_db.get(id)?.selections,
             _ => vec![],
         };
-        let missing_selections = missing.iter().map(|p| Selection {
-            package: p.id.clone(),
+        let required_by = transaction::required_by(&missing);
+        let missing_selections = missing.iter().map(|p| {
             // Package is explicit if it was one of the input
             // packages provided by the user
-            explicit: input.contains(&p.id),
-            reason: None,
+            if input.contains(&p.id) {
+                Selection::requested(p.id.clone())
+            } else {
+                Selection::dependency(
+                    p.id.clone(),
+                    required_by.get(&p.id).map(|dependent| &dependent.meta.name),
+                )
+            }
         });
 
-        missing_selections.chain(previous_selections).collect::<Vec<_>>()
+        missing_selections
+            .chain(
+                previous_selections
+                    .into_iter()
+                    .filter(|s| !replaced.iter().any(|r| r.id == s.package)),
+            )
+            .collect::<Vec<_>>()
     };
 
     // Perfect, apply state.
     client.new_state(&new_state_pkgs, "Install")?;
 
     timing.blit = instant.elapsed();
+    timing.blit_phases = client.blit_timing();
 
     info!(
         blit_time_ms = timing.blit.as_millis(),
+        blit_plan_time_ms = timing.blit_phases.plan.as_millis(),
+        blit_directories_time_ms = timing.blit_phases.directories.as_millis(),
+        blit_files_time_ms = timing.blit_phases.files.as_millis(),
         total_time_ms = (timing.resolve + timing.fetch + timing.blit).as_millis(),
         "Installation completed successfully"
     );
 
-    Ok(timing)
+    Ok(Some(timing))
 }
 
 /// Resolves the package arguments as valid input packages. Returns an error
@@ -179,14 +216,26 @@ fn resolve_input(pkgs: &[&str], client: &Client) -> Result<Vec<package::Id>, Err
 }
 
 /// Resolve a package name to the first package
+///
+/// Names that aren't provided by any package are looked up in the
+/// repository aliases, notifying the user of the substitution.
 fn find_packages(id: &str, client: &Client) -> (String, Option<Package>) {
-    let provider = Provider::from_name(id).unwrap();
-    let result = client
-        .registry
-        .by_provider(&provider, Flags::new().with_available())
-        .next();
+    let lookup = |name: &str| {
+        let provider = Provider::from_name(name).unwrap();
+        // First only, pre-sorted
+        client
+            .registry
+            .by_provider(&provider, Flags::new().with_available())
+            .next()
+    };
+
+    let result = lookup(id).or_else(|| {
+        let alias = client.package_alias(id)?;
+        let package = lookup(&alias)?;
+        println!("{} {id} is known as {alias}, using it instead", "Note:".yellow());
+        Some(package)
+    });
 
-    // First only, pre-sorted
     (id.into(), result)
 }
 
@@ -196,6 +245,8 @@ pub struct Timing {
     pub resolve: Duration,
     pub fetch: Duration,
     pub blit: Duration,
+    /// Breakdown of the blit itself, part of [`Self::blit`]
+    pub blit_phases: client::BlitTiming,
 }
 
 /// Error's specific to installation operations
@@ -213,6 +264,14 @@ pub enum Error {
     #[error("no package found: {0}")]
     NoPackage(String),
 
+    /// No package provides the given command
+    #[error("no package provides the command {0}")]
+    NoCommand(String),
+
+    /// A system-model is active, but the user didn't say what should happen to it
+    #[error(transparent)]
+    SystemModelChange(#[from] system_model::UnrecordedChange),
+
     /// A transaction specific error occurred
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
@@ -221,9 +280,13 @@ pub enum Error {
     #[error("db")]
     DB(#[from] crate::db::Error),
 
-    /// Had issues processing user-provided string input
-    #[error("string processing")]
-    Dialog(#[from] tui::dialoguer::Error),
+    /// The transaction doesn't fit on disk, or its size couldn't be computed
+    #[error("disk space")]
+    Space(#[from] client::space::Error),
+
+    /// Failed to obtain confirmation from the user
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
 
     /// We forgot how disks work
     #[error("io")]
@@ -0,0 +1,367 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Journal of the transaction being applied
+//!
+//! Each step changing the root while a state is applied is recorded ahead of
+//! time in `.moss/db/journal.json`, so a transaction interrupted by a crash or
+//! power loss is recovered by the next invocation: rolled back if `/usr` wasn't
+//! replaced yet, otherwise rolled forward to complete the activation.
+//!
+//! A transaction failing with an error rather than a crash clears its journal while
+//! `/usr` wasn't replaced yet, once an archived state taken to staging is moved back.
+//! Otherwise the journal is kept so the next invocation recovers, as the root is left
+//! half applied.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    path::Path,
+};
+
+use chrono::{SecondsFormat, Utc};
+use fs_err::{self as fs, File};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::{Client, Installation, client, installation, state};
+
+/// File in [`Installation::db_path`] holding the [`Entry`] of the transaction in progress
+const JOURNAL_FILE: &str = "journal.json";
+
+/// A transaction in progress
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub operation: Operation,
+    pub old_state: Option<i32>,
+    /// Unset until the new state is recorded, which happens after its blit starts
+    pub new_state: Option<i32>,
+    pub phase: Phase,
+    pub started: String,
+}
+
+/// How the new state is brought in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Operation {
+    /// A new state blitted to staging
+    NewState,
+    /// An archived state moved to staging
+    Activate,
+}
+
+/// The last step reached by a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Phase {
+    /// The new `/usr` is being put in staging
+    Staged,
+    /// `/usr` was swapped with staging, the old state & `/etc` are yet to be updated
+    Promoted,
+    /// Only the system triggers & boot synchronization remain
+    Activating,
+}
+
+/// What was done to recover an interrupted transaction
+#[derive(Debug, Clone)]
+pub enum Recovery {
+    /// The old state is active again
+    RolledBack(Entry),
+    /// The new state was activated
+    RolledForward(Entry),
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (verb, entry) = match self {
+            Recovery::RolledBack(entry) => ("Rolled back", entry),
+            Recovery::RolledForward(entry) => ("Rolled forward", entry),
+        };
+
+        match entry.new_state {
+            Some(state) => write!(
+                f,
+                "{verb} the {} of state #{state} interrupted in the {} phase",
+                entry.operation, entry.phase
+            ),
+            None => write!(
+                f,
+                "{verb} the {} interrupted in the {} phase",
+                entry.operation, entry.phase
+            ),
+        }
+    }
+}
+
+/// Journal of a transaction, cleared once it's [`finish`](Journal::finish)ed or dropped
+/// by an error return before `/usr` is promoted
+pub(crate) struct Journal<'a> {
+    installation: &'a Installation,
+    entry: Entry,
+    finished: bool,
+}
+
+impl<'a> Journal<'a> {
+    /// Record the start of `operation` from `old_state` to `new_state`
+    pub fn begin(
+        installation: &'a Installation,
+        operation: Operation,
+        old_state: Option<state::Id>,
+        new_state: Option<state::Id>,
+    ) -> Result<Self, Error> {
+        let journal = Self {
+            installation,
+            entry: Entry {
+                operation,
+                old_state: old_state.map(i32::from),
+                new_state: new_state.map(i32::from),
+                phase: Phase::Staged,
+                started: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            },
+            finished: false,
+        };
+        write(installation, &journal.entry)?;
+
+        Ok(journal)
+    }
+
+    /// Record the new state once it's added to the state db
+    pub fn record_state(&mut self, new_state: state::Id) -> Result<(), Error> {
+        self.entry.new_state = Some(new_state.into());
+        write(self.installation, &self.entry)
+    }
+
+    /// Record reaching `phase`
+    pub fn advance(&mut self, phase: Phase) -> Result<(), Error> {
+        self.entry.phase = phase;
+        write(self.installation, &self.entry)
+    }
+
+    /// Record the transaction completed
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.finished = true;
+        clear(self.installation)
+    }
+}
+
+impl Drop for Journal<'_> {
+    fn drop(&mut self) {
+        if self.finished || self.entry.phase != Phase::Staged {
+            return;
+        }
+
+        // Clearing the journal before the archived tree is back would lose it to the next blit
+        if self.entry.operation == Operation::Activate
+            && let Err(error) = restore_archived(self.installation, self.entry.new_state.map(state::Id::from))
+        {
+            warn!("Failed to move the archived state back from staging, it's left for recovery: {error}");
+            return;
+        }
+
+        if let Err(error) = clear(self.installation) {
+            warn!("Failed to clear the journal of the failed transaction: {error}");
+        }
+    }
+}
+
+/// The interrupted transaction of `installation`, if any
+pub fn read(installation: &Installation) -> Result<Option<Entry>, Error> {
+    match fs::read(installation.db_path(JOURNAL_FILE)) {
+        Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Whether `installation` has an interrupted transaction to recover
+pub fn is_pending(installation: &Installation) -> bool {
+    installation.db_path(JOURNAL_FILE).exists()
+}
+
+/// Write `entry` so it survives a power loss, replacing the previous one whole
+fn write(installation: &Installation, entry: &Entry) -> Result<(), Error> {
+    let path = installation.db_path(JOURNAL_FILE);
+    let staged = path.with_extension("json.tmp");
+
+    let mut file = File::create(&staged)?;
+    file.write_all(&serde_json::to_vec(entry)?)?;
+    file.sync_all()?;
+    fs::rename(&staged, &path)?;
+    sync_parent(&path)?;
+
+    Ok(())
+}
+
+pub(crate) fn clear(installation: &Installation) -> Result<(), Error> {
+    let path = installation.db_path(JOURNAL_FILE);
+
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(sync_parent(&path)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Persist the rename or removal of the entry at `path`
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+/// Recover the interrupted transaction recorded for the installation of `client`, if any
+pub fn recover(client: &Client) -> Result<Option<Recovery>, client::Error> {
+    let Some(entry) = read(&client.installation)? else {
+        return Ok(None);
+    };
+    let installation = &client.installation;
+    let new = entry.new_state.map(state::Id::from);
+    let old = entry.old_state.map(state::Id::from);
+
+    // `/usr` records the state it belongs to, so the swap is detected even if
+    // it happened after the journal was last written
+    let promoted =
+        new.filter(|new| entry.phase != Phase::Staged || installation::read_state_id(&installation.root) == Some(*new));
+
+    let Some(new) = promoted else {
+        info!(operation = %entry.operation, state = ?entry.new_state, "Rolling back interrupted transaction");
+
+        match entry.operation {
+            // The state never became active, so it's dropped along with its partial tree
+            Operation::NewState => {
+                if let Some(new) = new
+                    && client.state_db.get(new).is_ok()
+                    && !installation.root_path(new.to_string()).exists()
+                {
+                    client.state_db.remove(&new)?;
+                }
+                let staging = installation.staging_dir();
+                if staging.exists() {
+                    fs::remove_dir_all(&staging)?;
+                }
+                fs::create_dir_all(&staging)?;
+            }
+            // The archived tree goes back where it came from
+            Operation::Activate => restore_archived(installation, new)?,
+        }
+
+        clear(installation)?;
+        return Ok(Some(Recovery::RolledBack(entry)));
+    };
+
+    info!(operation = %entry.operation, state = %new, phase = %entry.phase, "Rolling forward interrupted transaction");
+
+    if entry.phase != Phase::Activating {
+        // After the swap, staging holds the old `/usr` until it's archived
+        if let Some(old) = old
+            && old != new
+            && installation.staging_path("usr").exists()
+            && !installation.root_path(old.to_string()).join("usr").exists()
+        {
+            client.archive_state(old)?;
+        }
+
+        let state = client.state_db.get(new)?;
+        client.merge_config_files(&installation.root, &state.selections, old.filter(|old| *old != new))?;
+    }
+
+    client.complete_activation(true)?;
+
+    Ok(Some(Recovery::RolledForward(entry)))
+}
+
+/// Move the archived tree of `new`, taken to staging by an activation, back where it came from
+fn restore_archived(installation: &Installation, new: Option<state::Id>) -> io::Result<()> {
+    if let Some(new) = new
+        && installation.staging_path("usr").exists()
+        && !installation.root_path(new.to_string()).exists()
+    {
+        fs::rename(installation.staging_dir(), installation.root_path(new.to_string()))?;
+        fs::create_dir_all(installation.staging_dir())?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid journal")]
+    Json(#[from] serde_json::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let entry = Entry {
+            operation: Operation::NewState,
+            old_state: Some(4),
+            new_state: Some(5),
+            phase: Phase::Promoted,
+            started: "2025-01-01T00:00:00Z".to_owned(),
+        };
+
+        let encoded = serde_json::to_string(&entry).unwrap();
+        assert!(encoded.contains(r#""operation":"new-state""#));
+        assert!(encoded.contains(r#""phase":"promoted""#));
+        assert_eq!(serde_json::from_str::<Entry>(&encoded).unwrap(), entry);
+        assert_eq!(
+            Recovery::RolledForward(entry.clone()).to_string(),
+            "Rolled forward the new-state of state #5 interrupted in the promoted phase"
+        );
+
+        let blitting = Entry {
+            new_state: None,
+            phase: Phase::Staged,
+            ..entry
+        };
+        assert_eq!(
+            Recovery::RolledBack(blitting).to_string(),
+            "Rolled back the new-state interrupted in the staged phase"
+        );
+    }
+
+    #[test]
+    fn test_failed_activation() {
+        let root = tempfile::tempdir().unwrap();
+        let installation = Installation::open(root.path(), None).unwrap();
+        let archived = installation.root_path("2");
+        fs::create_dir_all(archived.join("usr")).unwrap();
+
+        {
+            let _journal = Journal::begin(&installation, Operation::Activate, Some(1.into()), Some(2.into())).unwrap();
+            assert!(is_pending(&installation));
+
+            fs::rename(&archived, installation.staging_dir()).unwrap();
+            // Promoting staging fails, dropping the journal
+        }
+
+        assert!(archived.join("usr").exists());
+        assert!(installation.staging_dir().exists());
+        assert!(!installation.staging_path("usr").exists());
+        assert!(!is_pending(&installation));
+    }
+
+    #[test]
+    fn test_failed_activation_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let installation = Installation::open(root.path(), None).unwrap();
+        fs::create_dir_all(installation.staging_path("usr")).unwrap();
+        // The archived tree can't be moved back onto a dangling symlink
+        std::os::unix::fs::symlink("missing", installation.root_path("2")).unwrap();
+
+        drop(Journal::begin(&installation, Operation::Activate, Some(1.into()), Some(2.into())).unwrap());
+
+        assert!(installation.staging_path("usr").exists());
+        assert!(is_pending(&installation));
+    }
+}
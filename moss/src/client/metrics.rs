@@ -0,0 +1,259 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Prometheus metrics of transactions
+//!
+//! Once a path is configured in `/etc/moss/metrics.yaml`, counters of the
+//! transactions, failures, durations & downloads are kept across runs in
+//! `.moss/db/metrics.json` and written to the path in the text format after
+//! each transaction, i.e. for the node exporter's textfile collector:
+//!
+//! ```yaml
+//! path: /var/lib/node_exporter/textfile_collector/moss.prom
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::Utc;
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{Installation, request};
+
+/// File in [`Installation::db_path`] keeping the counters between runs
+const COUNTERS_FILE: &str = "metrics.json";
+
+/// Operation & state of the last transaction applied by this process, see [`take_applied`]
+static APPLIED: Mutex<Option<(String, i32)>> = Mutex::new(None);
+
+/// Metrics configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where to write the metrics, nothing is recorded if unset
+    pub path: Option<PathBuf>,
+}
+
+impl config::Config for Config {
+    fn domain() -> String {
+        "metrics".into()
+    }
+}
+
+/// How a transaction ended
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    /// The transaction activated this state
+    Success(i32),
+    Failure,
+}
+
+/// Counters of all recorded transactions
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Counters {
+    operations: BTreeMap<String, Operation>,
+    download_bytes: u64,
+    last_success: Option<i64>,
+    last_failure: Option<i64>,
+    active_state: Option<i32>,
+}
+
+/// Counters of a single kind of transaction, i.e. `install`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Operation {
+    transactions: u64,
+    failures: u64,
+    duration_seconds: f64,
+}
+
+/// Note a transaction of `operation` applied `state`
+///
+/// It's recorded once the command is done, as a later failure fails the transaction.
+pub fn applied(operation: &str, state: i32) {
+    *APPLIED.lock().unwrap() = Some((operation.to_owned(), state));
+}
+
+/// The transaction noted by [`applied`] since the last call
+pub fn take_applied() -> Option<(String, i32)> {
+    APPLIED.lock().unwrap().take()
+}
+
+/// Record a transaction of `operation` taking `duration`, if metrics are configured
+///
+/// Failing to do so doesn't fail the transaction.
+pub fn record(installation: &Installation, operation: &str, outcome: Outcome, duration: Duration) {
+    let config = config::Manager::system(&installation.root, "moss")
+        .load::<Config>()
+        .pop()
+        .unwrap_or_default();
+    let Some(path) = config.path else {
+        return;
+    };
+
+    if let Err(error) = update(installation, &path, operation, outcome, duration) {
+        warn!("Failed to write metrics to {}: {error}", path.display());
+    }
+}
+
+fn update(
+    installation: &Installation,
+    path: &Path,
+    operation: &str,
+    outcome: Outcome,
+    duration: Duration,
+) -> Result<(), Error> {
+    let counters_path = installation.db_path(COUNTERS_FILE);
+    let mut counters = match fs::read(&counters_path) {
+        Ok(contents) => serde_json::from_slice(&contents)?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Counters::default(),
+        Err(error) => return Err(error.into()),
+    };
+
+    counters.add(operation, outcome, duration, request::take_downloaded());
+
+    write_atomic(&counters_path, serde_json::to_string(&counters)?)?;
+    write_atomic(path, counters.render())?;
+
+    Ok(())
+}
+
+impl Counters {
+    fn add(&mut self, operation: &str, outcome: Outcome, duration: Duration, downloaded: u64) {
+        let now = Utc::now().timestamp();
+        let counters = self.operations.entry(operation.to_lowercase()).or_default();
+
+        counters.transactions += 1;
+        counters.duration_seconds += duration.as_secs_f64();
+        match outcome {
+            Outcome::Success(state) => {
+                self.last_success = Some(now);
+                self.active_state = Some(state);
+            }
+            Outcome::Failure => {
+                counters.failures += 1;
+                self.last_failure = Some(now);
+            }
+        }
+        self.download_bytes += downloaded;
+    }
+
+    /// Render in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            if samples.is_empty() {
+                return;
+            }
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{name}{labels} {value}");
+            }
+        };
+        let by_operation = |value: &dyn Fn(&Operation) -> String| {
+            self.operations
+                .iter()
+                .map(|(operation, counters)| (format!("{{operation=\"{operation}\"}}"), value(counters)))
+                .collect::<Vec<_>>()
+        };
+        let single = |value: Option<String>| value.map(|value| (String::new(), value)).into_iter().collect();
+
+        metric(
+            "moss_transactions_total",
+            "counter",
+            "Transactions attempted, by operation",
+            by_operation(&|counters| counters.transactions.to_string()),
+        );
+        metric(
+            "moss_transaction_failures_total",
+            "counter",
+            "Transactions which failed, by operation",
+            by_operation(&|counters| counters.failures.to_string()),
+        );
+        metric(
+            "moss_transaction_duration_seconds_total",
+            "counter",
+            "Time spent in transactions, by operation",
+            by_operation(&|counters| format!("{:.3}", counters.duration_seconds)),
+        );
+        metric(
+            "moss_download_bytes_total",
+            "counter",
+            "Bytes downloaded by transactions",
+            single(Some(self.download_bytes.to_string())),
+        );
+        metric(
+            "moss_last_success_timestamp_seconds",
+            "gauge",
+            "Time of the last successful transaction",
+            single(self.last_success.map(|time| time.to_string())),
+        );
+        metric(
+            "moss_last_failure_timestamp_seconds",
+            "gauge",
+            "Time of the last failed transaction",
+            single(self.last_failure.map(|time| time.to_string())),
+        );
+        metric(
+            "moss_active_state",
+            "gauge",
+            "Id of the state activated by the last successful transaction",
+            single(self.active_state.map(|state| state.to_string())),
+        );
+
+        out
+    }
+}
+
+/// Collectors may read at any time, so files are replaced whole
+fn write_atomic(path: &Path, contents: String) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(".tmp");
+    fs::write(&staged, contents)?;
+    fs::rename(&staged, path)
+}
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut counters = Counters::default();
+        counters.add("Install", Outcome::Success(4), Duration::from_millis(1500), 1024);
+        counters.add("sync", Outcome::Failure, Duration::from_secs(2), 0);
+
+        let rendered = counters.render();
+        assert!(rendered.contains("# TYPE moss_transactions_total counter\n"));
+        assert!(rendered.contains("moss_transactions_total{operation=\"install\"} 1\n"));
+        assert!(rendered.contains("moss_transaction_failures_total{operation=\"install\"} 0\n"));
+        assert!(rendered.contains("moss_transaction_failures_total{operation=\"sync\"} 1\n"));
+        assert!(rendered.contains("moss_transaction_duration_seconds_total{operation=\"install\"} 1.500\n"));
+        assert!(rendered.contains("moss_download_bytes_total 1024\n"));
+        assert!(rendered.contains("moss_active_state 4\n"));
+    }
+}
@@ -11,19 +11,25 @@
 
 use std::{
     borrow::Borrow,
+    collections::{BTreeSet, btree_set},
     fmt, io,
-    os::{fd::RawFd, unix::fs::symlink},
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::fs::symlink,
+    },
     path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
     time::{Duration, Instant},
 };
 
 use fs_err as fs;
 use futures_util::{StreamExt, TryStreamExt, stream};
+use itertools::Itertools;
 use nix::{
     errno::Errno,
     fcntl::{self, OFlag},
-    libc::{AT_FDCWD, RENAME_EXCHANGE, SYS_renameat2, syscall},
-    sys::stat::{Mode, fchmodat, mkdirat},
+    sys::stat::{Mode, fchmod, fchmodat, mkdirat},
     unistd::{close, linkat, mkdir, symlinkat},
 };
 use postblit::TriggerScope;
@@ -34,23 +40,44 @@ use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 use vfs::tree::{BlitFile, Element, builder::TreeBuilder};
 
 use self::install::install;
+use self::journal::Journal;
 use self::prune::{prune_cache, prune_states};
 use self::verify::verify;
 use crate::{
-    Installation, Package, Provider, Registry, Signal, State, SystemModel, db, environment, installation, package,
+    Installation, Package, Provider, Registry, Repository, Signal, State, SystemModel, db, dependency, foreign,
+    installation::{self, BlitStrategy},
+    package, prompt,
     registry::plugin::{self, Plugin},
-    repository, runtime, signal,
+    repository, request, runtime,
+    settings::Settings,
+    signal,
     state::{self, Selection},
     system_model,
 };
-use tracing::{info, info_span};
+use tracing::{info, trace, warn};
+use tracing_common::{progress, watchdog};
 
 pub mod boot;
 pub mod cache;
+pub mod changelog;
+pub mod divergence;
+pub mod emulation;
+pub mod essential;
+pub mod etc;
+pub mod fixup;
+pub mod handoff;
+pub mod hook;
 pub mod install;
-mod postblit;
+pub mod journal;
+pub mod metrics;
+pub mod pending;
+pub mod postblit;
+pub mod profile;
 pub mod prune;
+pub mod snapshot;
+pub mod space;
 mod verify;
+pub mod wait;
 
 /// A Client is a connection to the underlying package management systems
 pub struct Client {
@@ -78,6 +105,24 @@ pub struct Client {
 
     /// Operational scope (real systems, ephemeral, etc)
     scope: Scope,
+
+    /// Don't run triggers when applying new states
+    skip_triggers: bool,
+
+    /// Continue transactions lacking disk space, with a warning
+    force_space: bool,
+
+    /// Stage new states to be activated on the next boot, see [`pending`]
+    stage: bool,
+
+    /// Files changed at runtime carried over to new states, see [`divergence`]
+    keep_local: Vec<divergence::Divergence>,
+
+    /// How binaries of the root can be run, as triggers need to
+    emulation: emulation::Support,
+
+    /// Timing of the last blit, see [`Client::blit_timing`]
+    blit_timing: Mutex<BlitTiming>,
 }
 
 impl Client {
@@ -115,7 +160,27 @@ impl Client {
             repository::Manager::system(config.clone(), installation.clone())?
         };
 
-        let registry = build_registry(&installation, &repositories, &install_db, &state_db)?;
+        // Reindex local directory repositories whose stones changed, using the
+        // existing index of any that fail
+        if repository::local::reindex() {
+            for id in repositories.changed_local() {
+                if let Err(error) = runtime::block_on(repositories.refresh(&id)) {
+                    warn!("Failed to reindex local repository {id}: {error}");
+                }
+            }
+        }
+
+        let state = active_state(&installation, &state_db)?;
+        let registry = build_registry(&installation, &repositories, &install_db, state)?;
+
+        let emulation = emulation::detect(installation.architecture(), &installation.root);
+        if let emulation::Support::Emulated { interpreter } = &emulation {
+            info!(
+                architecture = installation.architecture(),
+                interpreter = %interpreter.display(),
+                "Running binaries of the root through binfmt_misc"
+            );
+        }
 
         Ok(Client {
             name,
@@ -127,16 +192,27 @@ impl Client {
             state_db,
             layout_db,
             scope: Scope::Stateful,
+            skip_triggers: false,
+            force_space: false,
+            stage: false,
+            keep_local: vec![],
+            emulation,
+            blit_timing: Mutex::default(),
         })
     }
 
+    /// Where the time of the last blit by this client went
+    pub fn blit_timing(&self) -> BlitTiming {
+        *self.blit_timing.lock().unwrap()
+    }
+
     /// Returns `true` if this is an ephemeral client
     pub fn is_ephemeral(&self) -> bool {
         matches!(self.scope, Scope::Ephemeral { .. })
     }
 
     /// Perform an installation via [`install::install`]
-    pub fn install(&mut self, packages: &[&str], yes: bool) -> Result<install::Timing, install::Error> {
+    pub fn install(&mut self, packages: &[&str], yes: bool) -> Result<Option<install::Timing>, install::Error> {
         install(self, packages, yes)
     }
 
@@ -161,11 +237,141 @@ impl Client {
         })
     }
 
+    /// Answer registry queries against the state `id` rather than the active state
+    ///
+    /// The packages selected by `id` are reported as installed instead, so queries show
+    /// the system as it was at that state. Only meant for queries, as transactions are
+    /// always applied on top of the active state.
+    pub fn as_of_state(self, id: state::Id) -> Result<Self, Error> {
+        let state = self.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))?;
+        let registry = build_registry(&self.installation, &self.repositories, &self.install_db, Some(state))?;
+
+        Ok(Self { registry, ..self })
+    }
+
+    /// Don't run transaction or system triggers (or fixups) when applying new states
+    ///
+    /// Useful to recover from a broken trigger, which can then be re-run later.
+    pub fn skip_triggers(self, skip: bool) -> Self {
+        Self {
+            skip_triggers: skip,
+            ..self
+        }
+    }
+
+    /// Continue transactions even if a filesystem lacks the space they need, see [`space::preflight`]
+    pub fn force_space(self, force: bool) -> Self {
+        Self {
+            force_space: force,
+            ..self
+        }
+    }
+
+    /// Prepare new states in full, but defer their activation to the next boot
+    ///
+    /// The state is activated by [`pending::apply_staged`] early during boot, rather
+    /// than mutating the running system.
+    pub fn stage(self, stage: bool) -> Self {
+        Self { stage, ..self }
+    }
+
+    /// Carry the live copy of the `kept` files over to new states, rather than
+    /// resetting them to the files of the new state
+    pub fn keep_local(self, kept: Vec<divergence::Divergence>) -> Self {
+        Self {
+            keep_local: kept,
+            ..self
+        }
+    }
+
+    /// Files of the active state changed at runtime, see [`divergence`]
+    pub fn divergence(&self) -> Result<Vec<divergence::Divergence>, Error> {
+        if self.scope.is_ephemeral() {
+            return Ok(vec![]);
+        }
+
+        Ok(divergence::detect(&self.installation)?)
+    }
+
+    /// Compute the sizes of a transaction, print them & check there's space for it
+    ///
+    /// See [`space::preflight`]
+    pub fn check_space(&self, incoming: &[&Package], outgoing: &[&Package]) -> Result<space::Preflight, space::Error> {
+        let preflight = space::preflight(self, incoming, outgoing)?;
+        preflight.print();
+        preflight.check(self.force_space)?;
+        Ok(preflight)
+    }
+
+    /// Returns true if triggers (and fixups) run when applying new states
+    ///
+    /// They're skipped when requested, or when binaries of the root can't be run
+    fn runs_triggers(&self) -> bool {
+        !self.skip_triggers && self.emulation.can_execute()
+    }
+
+    /// Warn that triggers won't run, if that's only due to binaries of the root being unable to run
+    fn warn_unsupported_emulation(&self, skip_triggers: bool) {
+        if !skip_triggers && !self.emulation.can_execute() {
+            eprintln!(
+                "{}: binaries of this {} root can't be run on this host, skipping triggers. Register a \
+                 binfmt_misc handler (i.e. qemu-user-static) & use `moss trigger run` to run them later",
+                "Warning".yellow(),
+                self.installation.architecture()
+            );
+        }
+    }
+
+    /// Packages providing the command `name` as a binary in `/usr/bin` or `/usr/sbin`,
+    /// in priority order
+    pub fn command_providers(&self, name: &str, flags: package::Flags) -> Vec<Package> {
+        let providers = [dependency::Kind::Binary, dependency::Kind::SystemBinary].map(|kind| Provider {
+            kind,
+            name: name.to_owned(),
+        });
+
+        providers
+            .iter()
+            .flat_map(|provider| self.registry.by_provider(provider, flags))
+            .unique_by(|package| package.meta.name.to_string())
+            .collect()
+    }
+
+    /// Returns the package name `name` is an alias for, as published by or
+    /// configured for the active repositories
+    pub fn package_alias(&self, name: &str) -> Option<String> {
+        self.repositories.alias(name)
+    }
+
+    /// When the metadata of each active repository was fetched & until when it's valid,
+    /// `None` if it was never recorded
+    pub fn repository_freshness(&self) -> Vec<(repository::Id, Option<repository::expiry::Record>)> {
+        self.repositories
+            .freshness()
+            .map(|(id, record)| (id.clone(), record))
+            .collect()
+    }
+
+    /// All of the active repositories
+    pub fn active_repositories(&self) -> impl Iterator<Item = (&repository::Id, &Repository)> {
+        self.repositories.list().filter(|(_, repo)| repo.active)
+    }
+
+    /// The active repository `package` would be downloaded from, honouring repository priority
+    pub fn origin(&self, package: &Package) -> Option<repository::Id> {
+        self.repositories.origin(&package.id, &package.meta)
+    }
+
     /// Ensures all repositories have been initialized by ensuring their stone indexes
     /// are downloaded and added to the meta db
     pub async fn ensure_repos_initialized(&mut self) -> Result<usize, Error> {
         let num_initialized = self.repositories.ensure_all_initialized().await?;
-        self.registry = build_registry(&self.installation, &self.repositories, &self.install_db, &self.state_db)?;
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.install_db,
+            active_state(&self.installation, &self.state_db)?,
+        )?;
         Ok(num_initialized)
     }
 
@@ -180,7 +386,12 @@ impl Client {
         self.repositories.refresh_all().await?;
 
         // Rebuild registry
-        self.registry = build_registry(&self.installation, &self.repositories, &self.install_db, &self.state_db)?;
+        self.registry = build_registry(
+            &self.installation,
+            &self.repositories,
+            &self.install_db,
+            active_state(&self.installation, &self.state_db)?,
+        )?;
 
         Ok(())
     }
@@ -197,23 +408,97 @@ impl Client {
     ///
     /// This allows automatic removal of unused states (and their associated assets)
     /// from the disk, acting as a garbage collection facility.
-    pub fn prune_states(&self, strategy: prune::Strategy, yes: bool) -> Result<(), Error> {
+    ///
+    /// States referenced by a boot entry are kept unless `force` is set, while
+    /// entries booting a removed state are cleaned up along with it. Nothing is
+    /// pruned when the boot entries can't be enumerated, unless `force` is set.
+    pub fn prune_states(&self, strategy: prune::Strategy, force: bool, yes: bool) -> Result<(), Error> {
         if self.scope.is_ephemeral() {
             return Err(Error::EphemeralProhibitedOperation);
         }
 
+        self.prune_states_of(&self.installation, strategy, force, yes)
+    }
+
+    /// Prune states of `installation`, which may have been activated since this client was created
+    fn prune_states_of(
+        &self,
+        installation: &Installation,
+        strategy: prune::Strategy,
+        force: bool,
+        yes: bool,
+    ) -> Result<(), Error> {
+        // Without the boot entries, states which are still booted can't be protected
+        let boot_entries = match boot::installed_entries(self) {
+            Ok(entries) => entries,
+            Err(error) if force => {
+                warn!("Failed to enumerate boot entries, they won't be cleaned up: {error}");
+                vec![]
+            }
+            Err(error) => return Err(Error::UnknownBootEntries(error)),
+        };
+
         prune_states(
             strategy,
             &self.state_db,
             &self.install_db,
             &self.layout_db,
-            &self.installation,
+            installation,
+            &boot_entries,
+            force,
             yes,
         )?;
 
         Ok(())
     }
 
+    /// The configured [`prune::Policy`], where admin configuration overrides vendor defaults
+    pub fn prune_policy(&self) -> prune::Policy {
+        self.config.load::<prune::Policy>().pop().unwrap_or_default()
+    }
+
+    /// Persist `policy` as the admin configured [`prune::Policy`]
+    pub fn save_prune_policy(&self, policy: &prune::Policy) -> Result<(), Error> {
+        self.config.save("policy", policy)?;
+        Ok(())
+    }
+
+    /// Reconcile the configured repositories with those declared by a system model
+    ///
+    /// See [`repository::Manager::reconcile`]
+    pub fn reconcile_repositories(
+        &self,
+        repositories: &repository::Map,
+    ) -> Result<repository::manager::Reconciliation, Error> {
+        let mut manager = repository::Manager::system(self.config.clone(), self.installation.clone())?;
+        Ok(manager.reconcile(repositories)?)
+    }
+
+    /// Prune states by the configured [`prune::Policy`] if it's set to apply automatically,
+    /// or the `auto-prune` setting says so, now that `active` was activated
+    ///
+    /// The transaction already completed, so failing to prune only warns.
+    fn auto_prune(&self, active: state::Id) {
+        let policy = self.prune_policy();
+        let auto = Settings::load(&self.installation.root)
+            .auto_prune
+            .unwrap_or(policy.auto);
+        if !auto {
+            return;
+        }
+
+        let mut installation = self.installation.clone();
+        installation.active_state = Some(active);
+
+        match self.prune_states_of(&installation, prune::Strategy::Policy(policy), false, true) {
+            Ok(()) => {}
+            Err(Error::UnknownBootEntries(error)) => {
+                warn!("Skipped pruning states by the configured policy, boot entries can't be enumerated: {error}");
+            }
+            Err(error) => warn!("Failed to prune states by the configured policy: {error}"),
+        }
+    }
+
     /// Prune all cached data that isn't related to any states or active repositories.
     ///
     /// This will remove all downloaded stones & unpacked asset data for packages not
@@ -233,6 +518,83 @@ impl Client {
         .map_err(Error::Prune)
     }
 
+    /// Purge all cached downloads of the repository, returning `false` if
+    /// no such repository is configured
+    ///
+    /// Unpacked assets are shared between repositories and left in place.
+    pub fn purge_downloads(&self, repo: &repository::Id) -> Result<bool, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        Ok(self.repositories.purge_downloads(repo)?)
+    }
+
+    /// Remove all cached downloads of every repository, including quarantined ones
+    ///
+    /// Stones are downloaded again when next needed, unpacked assets are left in place.
+    pub fn clean_downloads(&self) -> Result<(), Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        for dir in ["downloads", "quarantine", "content"] {
+            let path = self.installation.cache_path(dir);
+            if path.exists() {
+                fs::remove_dir_all(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Identify the boot assets (kernels, initrds & bootloader) of the provided state
+    pub fn boot_assets(&self, state: &State) -> Result<boot::StateAssets, Error> {
+        Ok(boot::state_assets(self, state)?)
+    }
+
+    /// Generate and install a unified kernel image for each kernel of `state`
+    pub fn generate_uki(&self, state: &State, extra_cmdline: Option<&str>) -> Result<Vec<boot::InstalledUki>, Error> {
+        Ok(boot::generate_uki(self, state, extra_cmdline)?)
+    }
+
+    /// Replace the kernel command line fragment of `state`, regenerating the boot loader entries
+    pub fn set_boot_cmdline(&self, state: &State, cmdline: Option<&str>) -> Result<(), Error> {
+        Ok(boot::set_cmdline(self, state, cmdline)?)
+    }
+
+    /// Enumerate the boot loader entries installed on the boot partitions which are mounted
+    ///
+    /// Boot partitions are never mounted for this, see [`boot::mounted_entries`]
+    pub fn boot_entries(&self) -> Result<Vec<boot::BootEntry>, Error> {
+        Ok(boot::mounted_entries(&self.installation)?)
+    }
+
+    /// Identify the running kernel or bootloader shipped by the `removed` packages
+    pub fn boot_critical(&self, removed: &[Package]) -> Result<Vec<boot::Critical>, Error> {
+        Ok(boot::critical_removals(self, removed)?)
+    }
+
+    /// Recover the transaction interrupted by a crash or power loss, if any, see [`journal`]
+    pub fn recover(&self) -> Result<Option<journal::Recovery>, Error> {
+        journal::recover(self)
+    }
+
+    /// Activate the state staged by [`Client::stage`], see [`pending::apply_staged`]
+    pub fn apply_staged(&self) -> Result<Option<pending::Pending>, Error> {
+        pending::apply_staged(self)
+    }
+
+    /// Complete the activation of the state applied on reboot once booted into, see [`pending`]
+    pub fn complete_pending(&self) -> Result<Option<pending::Pending>, Error> {
+        pending::complete(self)
+    }
+
+    /// Identify the essential packages among the `removed` packages
+    pub fn essential(&self, removed: &[Package]) -> Vec<essential::Essential> {
+        essential::essential_removals(self, removed)
+    }
+
     /// Resolves the provided id's with the underlying registry, returning
     /// the first [`Package`] for each id.
     ///
@@ -250,11 +612,14 @@ impl Client {
         Ok(metadata)
     }
 
-    /// Activates the provided state and runs system triggers once applied.
+    /// Activates the provided state, running system triggers & synchronizing boot entries once applied.
     ///
     /// The current state gets archived.\
     /// Returns the old state that was archived.
     pub fn activate_state(&self, id: state::Id, skip_triggers: bool) -> Result<state::Id, Error> {
+        self.warn_unsupported_emulation(skip_triggers);
+        let skip_triggers = skip_triggers || !self.emulation.can_execute();
+
         // Fetch the new state
         let new = self.state_db.get(id).map_err(|_| Error::StateDoesntExist(id))?;
 
@@ -267,6 +632,22 @@ impl Client {
             return Err(Error::StateAlreadyActive(id));
         }
 
+        let phase = progress::Phase::start("activate-state", new.selections.len());
+        let started = Instant::now();
+
+        // The archived tree is booted into as is
+        if self.installation.apply_on_reboot {
+            pending::stage(self, &new, Some(old), pending::Apply::Boot)?;
+            self.record_transaction("Activate", Some(old), &new, started);
+            phase.complete(new.selections.len());
+            return Ok(old);
+        }
+
+        // A live activation supersedes any state pending for the next boot
+        pending::clear(&self.installation)?;
+
+        self.snapshot_active_state();
+
         let staging_dir = self.installation.staging_dir();
 
         // Ensure staging dir exists
@@ -274,29 +655,70 @@ impl Client {
             fs::create_dir(&staging_dir)?;
         }
 
+        let mut journal = Journal::begin(
+            &self.installation,
+            journal::Operation::Activate,
+            Some(old),
+            Some(new.id),
+        )?;
+
         // Move new (archived) state to staging
         fs::rename(self.installation.root_path(new.id.to_string()), &staging_dir)?;
 
+        let image = handoff::Image::capture(&self.installation.root);
+
         // Promote staging
         self.promote_staging()?;
+        journal.advance(journal::Phase::Promoted)?;
 
         // Archive old state
         self.archive_state(old)?;
 
+        let config_files = self.merge_config_files(&self.installation.root, &new.selections, Some(old))?;
+        journal.advance(journal::Phase::Activating)?;
+
+        // Boot entries are synchronized by the new moss too, rather than by the stale one
+        if image.is_stale() && self.hand_off(skip_triggers, true)? {
+            journal.finish()?;
+            self.record_transaction("Activate", Some(old), &new, started);
+            phase.complete(new.selections.len());
+            print_config_report(&config_files);
+            return Ok(old);
+        }
+
         // Build VFS from new state selections
         // to build triggers from
         let fstree = self.vfs(new.selections.iter().map(|selection| &selection.package))?;
 
-        if skip_triggers {
-            return Ok(old);
-        }
+        if !skip_triggers {
+            // Run system triggers
+            let sys_triggers = postblit::triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
+
+            let policy = self.wait_policy();
+
+            let triggers_phase = progress::Phase::start("system-scope-triggers", sys_triggers.len());
+            for (i, trigger) in sys_triggers.iter().enumerate() {
+                if let Err(error) = execute_isolated(trigger, &policy, &self.installation.root, &ProgressBar::hidden())
+                {
+                    warn!("{} failed: {error}", trigger_description(trigger));
+                }
+                progress::update(i + 1, sys_triggers.len(), trigger_description(trigger));
+            }
+            triggers_phase.complete(sys_triggers.len());
 
-        // Run system triggers
-        let sys_triggers = postblit::triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
-        for trigger in sys_triggers {
-            trigger.execute()?;
+            self.run_fixups()?;
         }
 
+        let boot_phase = progress::Phase::start("boot-sync", 1);
+        boot::synchronize(self, &new)?;
+        boot_phase.complete(1);
+
+        journal.finish()?;
+        self.record_transaction("Activate", Some(old), &new, started);
+
+        phase.complete(new.selections.len());
+        print_config_report(&config_files);
+
         Ok(old)
     }
 
@@ -322,55 +744,227 @@ impl Client {
             &explicit_packages,
         )?;
 
-        let timer = Instant::now();
-
-        let state_span = info_span!(
-            "progress",
-            phase = summary.to_string().to_lowercase(),
-            event_type = "progress"
-        );
-        let _state_guard = state_span.enter();
-        info!(
-            total_items = selections.len(),
-            progress = 0.0,
-            event_type = "progress_start",
-        );
+        let operation = summary.to_string();
+        let phase = progress::Phase::start(operation.to_lowercase(), selections.len());
+        let started = Instant::now();
 
         let old_state = self.installation.active_state;
 
+        // Hooks only apply to the system itself, not ephemeral roots
+        let mut changeset = match &self.scope {
+            Scope::Stateful => Some(self.changeset(selections)?),
+            Scope::Ephemeral { .. } => None,
+        };
+        if let Some(changeset) = &changeset {
+            hook::run(&self.installation, hook::Phase::Pre, changeset)?;
+        }
+
+        self.snapshot_active_state();
+
+        // Journaled from the blit on, so an interrupted blit is rolled back too
+        let journal = match &self.scope {
+            Scope::Stateful => Some(Journal::begin(
+                &self.installation,
+                journal::Operation::NewState,
+                old_state,
+                None,
+            )?),
+            Scope::Ephemeral { .. } => None,
+        };
+
         let fstree = self.blit_root(selections.iter().map(|s| &s.package))?;
+        self.warn_unsupported_emulation(self.skip_triggers);
 
         let result = match &self.scope {
             Scope::Stateful => {
+                let mut journal = journal.expect("journaled with a stateful scope");
+
+                // Record the repository profile the state was created from
+                let summary = match self.repositories.active_profile() {
+                    Some(profile) => format!("{} (profile {profile})", summary.to_string()),
+                    None => summary.to_string(),
+                };
+
                 // Add to db
-                let state = self.state_db.add(selections, Some(&summary.to_string()), None)?;
+                let mut state = self
+                    .state_db
+                    .add(selections, &self.repositories.snapshot(), Some(&summary), None)?;
+                journal.record_state(state.id)?;
+
+                // Kernel command line tweaks carry over to the new state
+                if let Some(old) = old_state
+                    && let Some(cmdline) = self.state_db.get(old)?.cmdline
+                {
+                    self.state_db.set_cmdline(state.id, Some(&cmdline))?;
+                    state.cmdline = Some(cmdline);
+                }
 
-                self.apply_stateful_blit(fstree, &state, old_state, system_model)?;
+                let staged = self.installation.apply_on_reboot || self.stage;
+                let config_files = if staged {
+                    self.stage_for_reboot(journal, fstree, &state, old_state, system_model)?;
+                    etc::Report::default()
+                } else {
+                    let config_files = self.apply_stateful_blit(journal, fstree, &state, old_state, system_model)?;
+                    // Superseding any state pending for the next boot
+                    pending::clear(&self.installation)?;
+                    config_files
+                };
+
+                if let Some(changeset) = &mut changeset {
+                    changeset.state = Some(state.id.into());
+                    // The state is already applied, a failing hook can't undo it
+                    if let Err(error) = hook::run(&self.installation, hook::Phase::Post, changeset) {
+                        warn!("Post-transaction hooks failed: {error}");
+                    }
+                }
+
+                self.record_transaction(&operation, old_state, &state, started);
+                // Until the reboot, the old state remains active
+                if !staged {
+                    self.auto_prune(state.id);
+                }
+                print_config_report(&config_files);
 
                 Ok(Some(state))
             }
             Scope::Ephemeral { blit_root } => {
-                self.apply_ephemeral_blit(fstree, blit_root, system_model)?;
+                let config_files = self.apply_ephemeral_blit(fstree, blit_root, selections, system_model)?;
+                print_config_report(&config_files);
 
                 Ok(None)
             }
         };
 
-        info!(
-            duration_ms = timer.elapsed().as_millis(),
-            items_processed = selections.len(),
-            progress = 1.0,
-            event_type = "progress_completed",
-        );
+        if result.is_ok() {
+            phase.complete(selections.len());
+        }
 
         result
     }
 
+    /// Record the transaction from the `old` state to `new` in the [`changelog`], noting it for the [`metrics`]
+    fn record_transaction(&self, operation: impl ToString, old: Option<state::Id>, new: &State, started: Instant) {
+        let packages = |selections: &[Selection]| {
+            selections
+                .iter()
+                .filter_map(|selection| self.registry.by_id(&selection.package).next())
+                .collect::<Vec<_>>()
+        };
+        let old_packages = old
+            .and_then(|id| self.state_db.get(id).ok())
+            .map(|state| packages(&state.selections))
+            .unwrap_or_default();
+
+        let entry = changelog::Entry::new(
+            operation,
+            old.map(i32::from),
+            new.id.into(),
+            &old_packages,
+            &packages(&new.selections),
+            started.elapsed(),
+        );
+        changelog::record(&self.installation, &entry);
+        metrics::applied(&entry.operation, entry.new_state);
+    }
+
+    /// Packages added & removed by moving from the active state to `selections`
+    fn changeset(&self, selections: &[Selection]) -> Result<hook::Changeset, Error> {
+        let old = match self.installation.active_state {
+            Some(id) => self.state_db.get(id)?.selections,
+            None => vec![],
+        };
+        let old = old.iter().map(|s| &s.package).collect::<BTreeSet<_>>();
+        let new = selections.iter().map(|s| &s.package).collect::<BTreeSet<_>>();
+
+        let changes = |ids: btree_set::Difference<'_, &package::Id>| {
+            ids.filter_map(|id| self.registry.by_id(id).next())
+                .map(|package| hook::Change {
+                    name: package.meta.name.to_string(),
+                    version: package.meta.version_identifier,
+                })
+                .collect()
+        };
+
+        Ok(hook::Changeset {
+            state: None,
+            added: changes(new.difference(&old)),
+            removed: changes(old.difference(&new)),
+        })
+    }
+
+    /// Snapshot the root against the active state before it's replaced, if enabled
+    /// by [`snapshot::Config`]
+    ///
+    /// Failing to snapshot doesn't prevent the transition, as the state itself remains archived.
+    fn snapshot_active_state(&self) {
+        let (Scope::Stateful, Some(id)) = (&self.scope, self.installation.active_state) else {
+            return;
+        };
+
+        let config = self.config.load::<snapshot::Config>().pop().unwrap_or_default();
+        if !config.enabled {
+            return;
+        }
+
+        let Some(backend) = snapshot::backend(self.installation.filesystem) else {
+            warn!(
+                "Snapshots are enabled, but {} doesn't support them",
+                self.installation.filesystem
+            );
+            return;
+        };
+
+        let root = &self.installation.root;
+        let phase = progress::Phase::start("snapshot", 1);
+
+        if let Err(error) = backend.create(root, id) {
+            warn!("Failed to snapshot state {id}: {error}");
+            return;
+        }
+
+        let recorded = backend.list(root).unwrap_or_default();
+        for id in recorded.iter().take(recorded.len().saturating_sub(config.keep)) {
+            if let Err(error) = backend.remove(root, *id) {
+                warn!("Failed to remove snapshot of state {id}: {error}");
+            }
+        }
+
+        phase.complete(1);
+    }
+
+    /// Make the filesystem snapshot recorded against state `id` the root booted into next
+    pub fn restore_snapshot(&self, id: state::Id) -> Result<(), Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        let backend = snapshot::backend(self.installation.filesystem)
+            .ok_or(snapshot::Error::Unsupported(self.installation.filesystem))?;
+        backend.restore(&self.installation.root, id)?;
+
+        Ok(())
+    }
+
+    /// The configured [`wait::Policy`], where admin configuration overrides vendor defaults
+    fn wait_policy(&self) -> wait::Policy {
+        self.config.load::<wait::Policy>().pop().unwrap_or_default()
+    }
+
     /// Apply all triggers with the given scope, wrapping with a progressbar.
-    fn apply_triggers(scope: TriggerScope<'_>, fstree: &vfs::Tree<PendingFile>) -> Result<(), postblit::Error> {
+    ///
+    /// Each trigger waits for resources held by other programs and is retried on failure,
+    /// as configured by [`wait::Policy`]. Failing transaction triggers abort before a broken
+    /// `/usr` can be promoted, while failing system triggers are only reported.
+    fn apply_triggers(&self, scope: TriggerScope<'_>, fstree: &vfs::Tree<PendingFile>) -> Result<(), postblit::Error> {
         let triggers = postblit::triggers(scope, fstree)?;
 
-        let progress = ProgressBar::new(triggers.len() as u64).with_style(
+        let policy = self.wait_policy();
+        let root = match &self.scope {
+            Scope::Stateful => &self.installation.root,
+            Scope::Ephemeral { blit_root } => blit_root,
+        };
+
+        let progress = ProgressBar::with_draw_target(Some(triggers.len() as u64), tui::progress_target()).with_style(
             ProgressStyle::with_template("\n|{bar:20.green/blue}| {pos}/{len} {msg}")
                 .unwrap()
                 .progress_chars("■≡=- "),
@@ -387,61 +981,53 @@ impl Client {
             }
         };
 
-        let timer = Instant::now();
-
-        info!(
-            phase = phase_name,
-            total_items = triggers.len(),
-            progress = 0.0,
-            event_type = "progress_start",
-        );
+        let phase = progress::Phase::start(phase_name, triggers.len());
 
         for (i, trigger) in progress.wrap_iter(triggers.iter()).enumerate() {
-            trigger.execute()?;
-
-            let trigger_command = match trigger.handler() {
-                triggers::format::Handler::Run { run, .. } => run.clone(),
-                triggers::format::Handler::Delete { .. } => "delete operation".to_owned(),
-            };
-            info!(
-                progress = (i + 1) as f32 / triggers.len() as f32,
-                current = i + 1,
-                total = triggers.len(),
-                event_type = "progress_update",
-                "Executing {}",
-                trigger_command
-            );
+            if let Err(error) = execute_isolated(trigger, &policy, root, &progress) {
+                if matches!(scope, TriggerScope::Transaction(..)) {
+                    progress.finish_and_clear();
+                    return Err(error);
+                }
+                warn!("{} failed: {error}", trigger_description(trigger));
+            }
+            progress::update(i + 1, triggers.len(), trigger_description(trigger));
         }
 
-        info!(
-            phase = phase_name,
-            duration_ms = timer.elapsed().as_millis(),
-            items_processed = triggers.len(),
-            progress = 1.0,
-            event_type = "progress_completed",
-        );
+        phase.complete(triggers.len());
 
         progress.finish_and_clear();
 
         Ok(())
     }
 
-    pub fn apply_stateful_blit(
+    /// Returns the configuration files changed in `/etc`
+    ///
+    /// The `journal` begun before the blit to staging is finished once `state` is activated
+    pub(crate) fn apply_stateful_blit(
         &self,
+        mut journal: Journal<'_>,
         fstree: vfs::Tree<PendingFile>,
         state: &State,
         old_state: Option<state::Id>,
         system_model: SystemModel,
-    ) -> Result<(), Error> {
+    ) -> Result<etc::Report, Error> {
         record_state_id(&self.installation.staging_dir(), state.id)?;
         record_os_release(&self.installation.staging_dir())?;
         record_system_model(&self.installation.staging_dir(), system_model)?;
 
         create_root_links(&self.installation.isolation_dir())?;
-        Self::apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), &fstree)?;
+        if self.runs_triggers() {
+            self.apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), &fstree)?;
+        }
+        self.prepare_usr(state, &fstree)?;
+
+        // Mappings follow the old `/usr` once archived, so capture them beforehand
+        let image = handoff::Image::capture(&self.installation.root);
 
         // Staging is only used with [`Scope::Stateful`]
         self.promote_staging()?;
+        journal.advance(journal::Phase::Promoted)?;
 
         // Now we got it staged, we need working rootfs
         create_root_links(&self.installation.root)?;
@@ -450,20 +1036,186 @@ impl Client {
             self.archive_state(id)?;
         }
 
+        let config_files = self.merge_config_files(&self.installation.root, &state.selections, old_state)?;
+        journal.advance(journal::Phase::Activating)?;
+
+        if !(image.is_stale() && self.hand_off(!self.runs_triggers(), true)?) {
+            self.finish_activation(state, &fstree, true)?;
+        }
+        journal.finish()?;
+
+        Ok(config_files)
+    }
+
+    /// Record the baseline of the `/usr` of `state` in staging, then carry the files
+    /// kept from the live `/usr` over to it
+    fn prepare_usr(&self, state: &State, fstree: &vfs::Tree<PendingFile>) -> Result<(), Error> {
+        let usr = self.installation.staging_path("usr");
+
+        // Kept files still diverge from the new state, so they're reported again next time
+        if let Err(error) = divergence::record(&self.installation, state.id, &usr, fstree) {
+            warn!("Failed to record the files of state {}: {error}", state.id);
+        }
+        divergence::carry_over(&self.installation, &usr, &self.keep_local)?;
+
+        Ok(())
+    }
+
+    /// Archive the new `state` blitted to staging & make it the one applied on the next
+    /// boot, leaving the live `/usr` untouched, see [`pending`]
+    ///
+    /// Booted into directly when `/usr` can't be changed, otherwise activated by the
+    /// boot-time service.
+    fn stage_for_reboot(
+        &self,
+        journal: Journal<'_>,
+        fstree: vfs::Tree<PendingFile>,
+        state: &State,
+        old_state: Option<state::Id>,
+        system_model: SystemModel,
+    ) -> Result<(), Error> {
+        record_state_id(&self.installation.staging_dir(), state.id)?;
+        record_os_release(&self.installation.staging_dir())?;
+        record_system_model(&self.installation.staging_dir(), system_model)?;
+
+        create_root_links(&self.installation.isolation_dir())?;
+        if self.runs_triggers() {
+            self.apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), &fstree)?;
+        }
+        self.prepare_usr(state, &fstree)?;
+
+        // Kept just like an archived state, until the initrd swaps it in
+        self.archive_state(state.id)?;
+        let apply = if self.installation.apply_on_reboot {
+            pending::Apply::Boot
+        } else {
+            pending::Apply::Service
+        };
+        pending::stage(self, state, old_state, apply)?;
+        journal.finish()?;
+
+        println!("State {} staged, reboot to apply it", state.id.to_string().bold());
+
+        Ok(())
+    }
+
+    /// Bring the configuration files of `selections` in `/etc` of `root` in step with
+    /// their vendor defaults, merging with those of the archived `old_state`
+    fn merge_config_files(
+        &self,
+        root: &Path,
+        selections: &[Selection],
+        old_state: Option<state::Id>,
+    ) -> Result<etc::Report, Error> {
+        let config_files = |selections: &[Selection]| -> Result<BTreeSet<String>, Error> {
+            Ok(self
+                .resolve_packages(selections.iter().map(|selection| &selection.package))?
+                .into_iter()
+                .flat_map(|package| package.meta.config_files)
+                .collect())
+        };
+
+        let new = config_files(selections)?;
+        let old = match old_state {
+            Some(id) => Some((
+                self.installation.root_path(id.to_string()).join("usr"),
+                config_files(&self.state_db.get(id)?.selections)?,
+            )),
+            None => None,
+        };
+        let previous = old
+            .as_ref()
+            .map(|(usr, config_files)| etc::Previous { usr, config_files });
+
+        Ok(etc::apply(root, &new, previous)?)
+    }
+
+    /// Files in `/etc` differing from the vendor defaults of the active state, as recorded in
+    /// its layouts
+    pub fn config_status(&self) -> Result<Vec<etc::Status>, Error> {
+        let id = self.installation.active_state.ok_or(Error::NoActiveState)?;
+        let state = self.state_db.get(id)?;
+        let packages = state.selections.iter().map(|selection| &selection.package);
+
+        let config_files = self
+            .resolve_packages(packages.clone())?
+            .into_iter()
+            .flat_map(|package| package.meta.config_files)
+            .collect();
+
+        let vendor_dir = etc::VENDOR_DIR.strip_prefix("usr/").expect("vendor dir within /usr");
+        let defaults = self
+            .layout_db
+            .query(packages)?
+            .into_iter()
+            .filter_map(|(package, layout)| {
+                let layout::Entry::Regular(hash, target) = layout.entry else {
+                    return None;
+                };
+                let path = target.strip_prefix(vendor_dir)?.strip_prefix('/')?.to_owned();
+                Some((path, package, hash))
+            });
+
+        Ok(etc::status(&self.installation.root, defaults, &config_files)?)
+    }
+
+    /// Run the system triggers & fixups of the newly promoted `state`, synchronizing
+    /// boot if `sync_boot` is set
+    fn finish_activation(&self, state: &State, fstree: &vfs::Tree<PendingFile>, sync_boot: bool) -> Result<(), Error> {
         // At this point we're allowed to run system triggers
-        Self::apply_triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
+        if self.runs_triggers() {
+            self.apply_triggers(TriggerScope::System(&self.installation, &self.scope), fstree)?;
+            self.run_fixups()?;
+        }
 
-        boot::synchronize(self, state)?;
+        if sync_boot {
+            let phase = progress::Phase::start("boot-sync", 1);
+            boot::synchronize(self, state)?;
+            phase.complete(1);
+        }
+
+        Ok(())
+    }
+
+    /// Complete the activation of the active state, as handed over by [`handoff::complete`]
+    pub fn complete_activation(&self, sync_boot: bool) -> Result<(), Error> {
+        let id = self.installation.active_state.ok_or(Error::NoActiveState)?;
+        let state = self.state_db.get(id)?;
+
+        let fstree = self.vfs(state.selections.iter().map(|selection| &selection.package))?;
+
+        self.finish_activation(&state, &fstree, sync_boot)?;
+        journal::clear(&self.installation)?;
 
         Ok(())
     }
 
+    /// Hand the remainder of an activation over to the moss of the newly promoted state,
+    /// as libraries of the running moss were replaced
+    ///
+    /// Returns `false` if the new moss can't be run, in which case the running
+    /// moss must complete the activation itself.
+    fn hand_off(&self, skip_triggers: bool, sync_boot: bool) -> Result<bool, Error> {
+        info!("Libraries used by moss were replaced, completing activation with the new moss");
+
+        match handoff::complete(&self.installation, skip_triggers, sync_boot) {
+            Ok(()) => Ok(true),
+            Err(error @ (handoff::Error::Spawn(_) | handoff::Error::Verify(_))) => {
+                warn!("Unable to hand activation over to the new moss, completing it with the running moss: {error}");
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Returns the configuration files installed to `/etc`
     pub fn apply_ephemeral_blit(
         &self,
         fstree: vfs::Tree<PendingFile>,
         blit_root: &Path,
+        selections: &[Selection],
         system_model: SystemModel,
-    ) -> Result<(), Error> {
+    ) -> Result<etc::Report, Error> {
         record_os_release(blit_root)?;
         record_system_model(blit_root, system_model)?;
 
@@ -473,12 +1225,136 @@ impl Client {
         let etc = blit_root.join("etc");
         fs::create_dir_all(etc)?;
 
-        // ephemeral tx triggers
-        Self::apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), &fstree)?;
-        // ephemeral system triggers
-        Self::apply_triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
+        // Nothing to merge with, as an ephemeral root has no previous state
+        let config_files = self.merge_config_files(blit_root, selections, None)?;
 
-        Ok(())
+        if self.runs_triggers() {
+            // ephemeral tx triggers
+            self.apply_triggers(TriggerScope::Transaction(&self.installation, &self.scope), &fstree)?;
+            // ephemeral system triggers
+            self.apply_triggers(TriggerScope::System(&self.installation, &self.scope), &fstree)?;
+            self.run_fixups()?;
+        }
+
+        Ok(config_files)
+    }
+
+    /// Apply the ownership & mode fixups declared by the active state, recording them to its `/usr`
+    ///
+    /// Returns the number of adjusted paths.
+    pub fn run_fixups(&self) -> Result<usize, Error> {
+        let root = match &self.scope {
+            Scope::Stateful => &self.installation.root,
+            Scope::Ephemeral { blit_root } => blit_root,
+        };
+
+        let fixups = fixup::load(root)?;
+        if fixups.is_empty() {
+            return Ok(0);
+        }
+
+        let phase = progress::Phase::start("fixups", fixups.len());
+        fixup::record(root, &fixups)?;
+        let adjusted = fixup::apply(root, &fixups);
+        phase.complete(fixups.len());
+
+        Ok(adjusted)
+    }
+
+    /// Trigger definitions installed to the root
+    pub fn registered_triggers(&self) -> Vec<(postblit::Kind, triggers::format::Trigger)> {
+        let root = match &self.scope {
+            Scope::Stateful => &self.installation.root,
+            Scope::Ephemeral { blit_root } => blit_root,
+        };
+
+        [postblit::Kind::Transaction, postblit::Kind::System]
+            .into_iter()
+            .flat_map(|kind| {
+                postblit::load(root, kind)
+                    .into_iter()
+                    .map(move |trigger| (kind, trigger))
+            })
+            .collect()
+    }
+
+    /// Determine the triggers which would fire for a state of `packages`, without running them
+    ///
+    /// Definitions are read from the packages themselves, so triggers shipped by packages
+    /// that aren't installed yet are accounted for. All packages must be cached.
+    pub fn plan_triggers<'a>(
+        &self,
+        packages: impl IntoIterator<Item = &'a package::Id>,
+    ) -> Result<Vec<postblit::Plan>, Error> {
+        let layouts = self.layout_db.query(packages)?;
+
+        let mut plans = vec![];
+
+        for kind in [postblit::Kind::Transaction, postblit::Kind::System] {
+            let dir = format!("share/moss/triggers/{}/", kind.dir());
+
+            let triggers = layouts
+                .iter()
+                .filter_map(|(_, layout)| match &layout.entry {
+                    layout::Entry::Regular(hash, target) if target.starts_with(&dir) && target.ends_with(".yaml") => {
+                        Some((hash, target))
+                    }
+                    _ => None,
+                })
+                .filter_map(|(hash, target)| {
+                    let path = cache::asset_path(&self.installation, &format!("{hash:02x}"));
+                    let trigger = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|contents| serde_yaml::from_str(&contents).ok());
+                    if trigger.is_none() {
+                        warn!("Skipping unreadable trigger /usr/{target}");
+                    }
+                    trigger
+                })
+                .collect::<Vec<_>>();
+
+            let fstree = Self::tree_from_layouts(layouts.clone())?;
+            plans.push(postblit::plan(kind, &triggers, &fstree)?);
+        }
+
+        Ok(plans)
+    }
+
+    /// Re-run the trigger `name` against the active state
+    ///
+    /// Returns the number of executed handlers, or `None` if no such trigger is defined.
+    pub fn run_trigger(&self, name: &str) -> Result<Option<usize>, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        let state = self
+            .state_db
+            .get(self.installation.active_state.ok_or(Error::NoActiveState)?)?;
+        let fstree = self.vfs(state.selections.iter().map(|selection| &selection.package))?;
+
+        // Run in place, transaction triggers would otherwise target the staging tree
+        let scope = Scope::Ephemeral {
+            blit_root: self.installation.root.clone(),
+        };
+
+        let timeout = self.wait_policy().trigger_timeout();
+        let mut executed = None;
+
+        for scope in [
+            TriggerScope::Transaction(&self.installation, &scope),
+            TriggerScope::System(&self.installation, &scope),
+        ] {
+            let Some(triggers) = postblit::named(scope, &fstree, name)? else {
+                continue;
+            };
+            for trigger in &triggers {
+                trigger.execute(timeout)?;
+            }
+            *executed.get_or_insert(0) += triggers.len();
+        }
+
+        Ok(executed)
     }
 
     /// "Activate" the staging tree
@@ -502,30 +1378,11 @@ impl Client {
         }
 
         // Now swap staging with live
-        Self::atomic_swap(&usr_source, &usr_target)?;
+        fs_common::exchange(&usr_source, &usr_target)?;
 
         Ok(())
     }
 
-    /// syscall based wrapper for renameat2 so we can support musl libc which
-    /// unfortunately does not expose the API.
-    /// largely modelled on existing renameat2 API in nix crae
-    fn atomic_swap<A: ?Sized + nix::NixPath, B: ?Sized + nix::NixPath>(old_path: &A, new_path: &B) -> nix::Result<()> {
-        let result = old_path.with_nix_path(|old| {
-            new_path.with_nix_path(|new| unsafe {
-                syscall(
-                    SYS_renameat2,
-                    AT_FDCWD,
-                    old.as_ptr(),
-                    AT_FDCWD,
-                    new.as_ptr(),
-                    RENAME_EXCHANGE,
-                )
-            })
-        })?? as i32;
-        Errno::result(result).map(drop)
-    }
-
     /// Archive old states (currently not "activated") into their respective tree
     fn archive_state(&self, id: state::Id) -> Result<(), Error> {
         if self.scope.is_ephemeral() {
@@ -551,7 +1408,7 @@ impl Client {
         T: Borrow<Package>,
     {
         // Setup progress bar
-        let multi_progress = MultiProgress::new();
+        let multi_progress = MultiProgress::with_draw_target(tui::progress_target());
 
         // Add bar to track total package counts
         let total_progress = multi_progress.add(
@@ -589,21 +1446,37 @@ impl Client {
                 );
                 progress_bar.enable_steady_tick(Duration::from_millis(150));
 
+                // Downloads are cached per repository they originate from
+                let partition = self
+                    .repositories
+                    .origin(&package.id, &package.meta)
+                    .and_then(|id| self.repositories.partition(&id));
+
                 // Download and update progress
-                let download = cache::fetch(&package.meta, &self.installation, |progress| {
+                let activity = watchdog::Activity::start("fetch", package.meta.uri.clone().unwrap_or_default());
+                let download = cache::fetch(&package.meta, partition.as_deref(), &self.installation, |progress| {
+                    activity.tick();
                     progress_bar.inc(progress.delta);
-                    info!(
-                        progress = progress.completed as f32 / progress.total as f32,
-                        current = progress.completed as usize,
-                        total = progress.total as usize,
-                        event_type = "progress_update",
-                        "Downloading {}",
-                        package.meta.name
+                    progress::update(
+                        progress.completed as usize,
+                        progress.total as usize,
+                        format_args!("Downloading {}", package.meta.name),
                     );
                 })
                 .await?;
                 let is_cached = download.was_cached;
 
+                if let Some(path) = &download.quarantined {
+                    multi_progress.suspend(|| {
+                        println!(
+                            "{}: cached download of {} was corrupt & fetched again, it's kept at {}",
+                            "Warning".yellow(),
+                            package.meta.name.to_string().bold(),
+                            path.display()
+                        );
+                    });
+                }
+
                 // Move rest of blocking code to threadpool
 
                 let multi_progress = multi_progress.clone();
@@ -625,16 +1498,15 @@ impl Client {
                     let unpacked = download.unpack(unpacking_in_progress.clone(), {
                         let progress_bar = progress_bar.clone();
                         let package_name = package_name.clone();
+                        let activity = watchdog::Activity::start("unpack", package_name.clone());
 
                         move |progress| {
+                            activity.tick();
                             progress_bar.set_position((progress.pct() * 1000.0) as u64);
-                            info!(
-                                progress = progress.completed as f32 / progress.total as f32,
-                                current = progress.completed as usize,
-                                total = progress.total as usize,
-                                event_type = "progress_update",
-                                "Unpacking {}",
-                                package_name
+                            progress::update(
+                                progress.completed as usize,
+                                progress.total as usize,
+                                format_args!("Unpacking {package_name}"),
                             );
                         }
                     })?;
@@ -648,19 +1520,19 @@ impl Client {
                         .unwrap_or_default();
 
                     // Write installed line
-                    multi_progress
-                        .suspend(|| println!("{} {}{cached_tag}", "Installed".green(), package_name.clone().bold()));
+                    if !tui::is_quiet() {
+                        multi_progress.suspend(|| {
+                            println!("{} {}{cached_tag}", "Installed".green(), package_name.clone().bold());
+                        });
+                    }
 
                     // Inc total progress by 1
                     total_progress.inc(1);
 
-                    info!(
-                        progress = total_progress.position() as f32 / total_progress.length().unwrap_or(1) as f32,
-                        current = total_progress.position() as usize,
-                        total = total_progress.length().unwrap_or(0) as usize,
-                        event_type = "progress_update",
-                        "Cached {}",
-                        package_name
+                    progress::update(
+                        total_progress.position() as usize,
+                        total_progress.length().unwrap_or(0) as usize,
+                        format_args!("Cached {package_name}"),
                     );
 
                     Ok((package, unpacked)) as Result<(Package, cache::UnpackedAsset), Error>
@@ -668,7 +1540,7 @@ impl Client {
                 .await
             })
             // Use max network concurrency since we download files here
-            .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
+            .buffer_unordered(request::concurrency())
             .try_collect::<Vec<_>>()
             .await?;
 
@@ -718,8 +1590,13 @@ impl Client {
         &self,
         packages: impl IntoIterator<Item = &'a package::Id>,
     ) -> Result<vfs::Tree<PendingFile>, Error> {
-        let mut tbuild = TreeBuilder::new();
         let layouts = self.layout_db.query(packages)?;
+        Self::tree_from_layouts(layouts)
+    }
+
+    /// Build a [`vfs::Tree`] from the given package layouts
+    fn tree_from_layouts(layouts: Vec<(package::Id, layout::Layout)>) -> Result<vfs::Tree<PendingFile>, Error> {
+        let mut tbuild = TreeBuilder::new();
         for (id, layout) in layouts {
             tbuild.push(PendingFile { id: id.clone(), layout });
         }
@@ -736,15 +1613,17 @@ impl Client {
     ///
     /// The new `/usr` filesystem is written in optimal order to a staging tree by making
     /// use of the "at" family of functions (`mkdirat`, `linkat`, etc) with relative directory
-    /// file descriptors, linking files from the assets store to provide deduplication.
+    /// file descriptors, reflinking or hardlinking files from the assets store to provide
+    /// deduplication. Files are only copied if the target is on another filesystem, see
+    /// [`BlitStrategy`].
     ///
-    /// This provides a very quick means to generate a hardlinked "snapshot" on-demand,
+    /// This provides a very quick means to generate a deduplicated "snapshot" on-demand,
     /// which can then be activated via [`Self::promote_staging`]
     fn blit_root<'a>(
         &self,
         packages: impl IntoIterator<Item = &'a package::Id>,
     ) -> Result<vfs::tree::Tree<PendingFile>, Error> {
-        let progress = ProgressBar::new(1).with_style(
+        let progress = ProgressBar::with_draw_target(Some(1), tui::progress_target()).with_style(
             ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {msg}")
                 .unwrap()
                 .progress_chars("■≡=- "),
@@ -753,10 +1632,26 @@ impl Client {
         progress.enable_steady_tick(Duration::from_millis(150));
         progress.tick();
 
+        let phase = progress::Phase::start("blit", 1);
         let now = Instant::now();
         let mut stats = BlitStats::default();
+        let mut timing = BlitTiming::default();
 
-        let tree = self.vfs(packages)?;
+        let packages = packages.into_iter().collect::<Vec<_>>();
+        let mut layouts = self.layout_db.query(packages.iter().copied())?;
+
+        // Keep the running kernel's modules loadable until the next boot
+        let retained = boot::retained_assets(self, &layouts)?;
+        if !retained.is_empty() {
+            info!(
+                retained = retained.len(),
+                "Retaining running kernel modules in new state"
+            );
+        }
+        layouts.extend(retained);
+
+        let tree = Self::tree_from_layouts(layouts)?;
+        timing.plan = now.elapsed();
 
         progress.set_length(tree.len());
         progress.set_position(0_u64);
@@ -778,24 +1673,50 @@ impl Client {
         let rayon_runtime = rayon::ThreadPoolBuilder::new().build().expect("rayon runtime");
 
         rayon_runtime.install(|| -> Result<(), Error> {
-            if let Some(root) = tree.structured() {
+            if let Some(Element::Directory(_, _, children)) = tree.structured() {
                 let _ = mkdir(&blit_target, Mode::from_bits_truncate(0o755));
                 let root_dir = fcntl::open(&blit_target, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
 
-                if let Element::Directory(_, _, children) = root {
-                    let current_span = tracing::Span::current();
-                    stats = stats.merge(
-                        children
-                            .into_par_iter()
-                            .map(|child| {
-                                let _guard = current_span.enter();
-                                self.blit_element(root_dir, cache_fd, child, &progress)
-                            })
-                            .try_reduce(BlitStats::default, |a, b| Ok(a.merge(b)))?,
-                    );
+                let strategy = match &self.scope {
+                    Scope::Stateful => self.installation.blit_strategy(),
+                    Scope::Ephemeral { .. } => BlitStrategy::probe(&cache_dir, &blit_target),
+                };
+                trace!(%strategy, "Blitting");
+
+                // Directories first, so files can be written in any order
+                let instant = Instant::now();
+                let mut batches = file_batches("", &children);
+                for child in children {
+                    self.blit_directories(
+                        root_dir,
+                        cache_fd,
+                        child,
+                        "",
+                        strategy,
+                        &progress,
+                        &mut batches,
+                        &mut stats,
+                    )?;
                 }
+                timing.directories = instant.elapsed();
+
+                let instant = Instant::now();
+                let current_span = tracing::Span::current();
+                let results = batches
+                    .into_par_iter()
+                    .map(|batch| {
+                        let _guard = current_span.enter();
+                        self.blit_batch(root_dir, cache_fd, batch, strategy, &progress)
+                    })
+                    .collect::<Vec<_>>();
+                timing.files = instant.elapsed();
 
                 close(root_dir)?;
+
+                // Report the first failure in tree order, regardless of which thread hit it first
+                for result in results {
+                    stats = stats.merge(result?);
+                }
             }
 
             Ok(())
@@ -803,76 +1724,108 @@ impl Client {
 
         progress.finish_and_clear();
 
+        profile::record(&blit_target, &self.resolve_packages(packages)?)?;
+
         let elapsed = now.elapsed();
         let num_entries = stats.num_entries();
+        phase.complete(num_entries as usize);
+
+        if !tui::is_quiet() {
+            println!(
+                "\n{} entries blitted in {} {}",
+                num_entries.to_string().bold(),
+                format!("{:.2}s", elapsed.as_secs_f32()).bold(),
+                format!("({:.1}k / s)", num_entries as f32 / elapsed.as_secs_f32() / 1_000.0).dim()
+            );
+        }
 
-        println!(
-            "\n{} entries blitted in {} {}",
-            num_entries.to_string().bold(),
-            format!("{:.2}s", elapsed.as_secs_f32()).bold(),
-            format!("({:.1}k / s)", num_entries as f32 / elapsed.as_secs_f32() / 1_000.0).dim()
-        );
+        *self.blit_timing.lock().unwrap() = timing;
 
         Ok(tree)
     }
 
-    /// Recursively write a directory, or a single flat inode, to the staging tree.
-    /// Care is taken to retain the directory file descriptor to avoid costly path
-    /// resolution at runtime.
-    fn blit_element(
+    /// Create `element` within `parent` if it's a directory, recursing into it, and batch up the
+    /// files within for [`Self::blit_batch`]
+    ///
+    /// Directories are created sequentially in tree order, parents before children. `dir` is the
+    /// path of `parent` relative to the blit target.
+    #[allow(clippy::too_many_arguments)]
+    fn blit_directories<'a>(
         &self,
-        parent: RawFd,
+        root: RawFd,
         cache: RawFd,
-        element: Element<'_, PendingFile>,
+        element: Element<'a, PendingFile>,
+        dir: &str,
+        strategy: BlitStrategy,
         progress: &ProgressBar,
-    ) -> Result<BlitStats, Error> {
-        let mut stats = BlitStats::default();
+        batches: &mut Vec<BlitBatch<'a>>,
+        stats: &mut BlitStats,
+    ) -> Result<(), Error> {
+        let Element::Directory(name, item, children) = element else {
+            return Ok(());
+        };
 
         progress.inc(1);
 
-        let (_, item) = match &element {
-            Element::Directory(_, item, _) => ("directory", item),
-            Element::Child(_, item) => ("file", item),
+        let path = if dir.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{dir}/{name}")
         };
+        self.blit_element_item(root, cache, &path, item, strategy, stats)?;
 
-        info!(
-            progress = progress.position() as f32 / progress.length().unwrap_or(1) as f32,
-            current = progress.position() as usize,
-            total = progress.length().unwrap_or(0) as usize,
-            event_type = "progress_update",
-            "Blitting {}",
-            item.path()
-        );
+        batches.extend(file_batches(&path, &children));
 
-        match element {
-            Element::Directory(name, item, children) => {
-                // Construct within the parent
-                self.blit_element_item(parent, cache, name, item, &mut stats)?;
+        for child in children {
+            self.blit_directories(root, cache, child, &path, strategy, progress, batches, stats)?;
+        }
 
-                // open the new dir
-                let newdir = fcntl::openat(parent, name, OFlag::O_RDONLY | OFlag::O_DIRECTORY, Mode::empty())?;
+        Ok(())
+    }
 
-                let current_span = tracing::Span::current();
-                stats = stats.merge(
-                    children
-                        .into_par_iter()
-                        .map(|child| {
-                            let _guard = current_span.enter();
-                            self.blit_element(newdir, cache, child, progress)
-                        })
-                        .try_reduce(BlitStats::default, |a, b| Ok(a.merge(b)))?,
-                );
+    /// Write the files of `batch` into their directory of the staging tree
+    ///
+    /// The directory is opened once for the whole batch to avoid costly path resolution
+    /// per file.
+    fn blit_batch(
+        &self,
+        root: RawFd,
+        cache: RawFd,
+        batch: BlitBatch<'_>,
+        strategy: BlitStrategy,
+        progress: &ProgressBar,
+    ) -> Result<BlitStats, Error> {
+        let mut stats = BlitStats::default();
+        let _activity = watchdog::Activity::start("blit", format!("/{}", batch.dir));
 
-                close(newdir)?;
+        let parent = if batch.dir.is_empty() {
+            root
+        } else {
+            fcntl::openat(
+                root,
+                batch.dir.as_str(),
+                OFlag::O_RDONLY | OFlag::O_DIRECTORY,
+                Mode::empty(),
+            )?
+        };
 
-                Ok(stats)
-            }
-            Element::Child(name, item) => {
-                self.blit_element_item(parent, cache, name, item, &mut stats)?;
+        let result = batch.files.into_iter().try_for_each(|(name, item)| {
+            progress.inc(1);
+            progress::update(
+                progress.position() as usize,
+                progress.length().unwrap_or(0) as usize,
+                format_args!("Blitting {}", item.path()),
+            );
 
-                Ok(stats)
-            }
+            self.blit_element_item(parent, cache, name, item, strategy, &mut stats)
+        });
+
+        if parent != root {
+            close(parent)?;
         }
+        result?;
+
+        Ok(stats)
     }
 
     /// Write a single inode into the staging tree.
@@ -883,12 +1836,14 @@ impl Client {
     /// * `cache`   - raw file descriptor for the system asset pool tree
     /// * `subpath` - the base name of the new inode
     /// * `item`    - New inode being recorded
+    /// * `strategy` - how regular files are placed from the asset pool
     fn blit_element_item(
         &self,
         parent: RawFd,
         cache: RawFd,
         subpath: &str,
         item: &PendingFile,
+        strategy: BlitStrategy,
         stats: &mut BlitStats,
     ) -> Result<(), Error> {
         match &item.layout.entry {
@@ -916,7 +1871,7 @@ impl Client {
                         close(fd)?;
                     }
                     // Regular file
-                    _ => {
+                    _ if strategy == BlitStrategy::Hardlink => {
                         linkat(
                             Some(cache),
                             fp.to_str().unwrap(),
@@ -933,6 +1888,26 @@ impl Client {
                             nix::sys::stat::FchmodatFlags::NoFollowSymlink,
                         )?;
                     }
+                    // Reflinked or copied into a new inode of its own
+                    _ => {
+                        let source = fcntl::openat(cache, fp.to_str().unwrap(), OFlag::O_RDONLY, Mode::empty())?;
+                        // Safety: freshly opened & solely owned from here on
+                        let mut source = unsafe { std::fs::File::from_raw_fd(source) };
+                        let target = fcntl::openat(
+                            parent,
+                            subpath,
+                            OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_WRONLY,
+                            Mode::from_bits_truncate(item.layout.mode),
+                        )?;
+                        let mut target = unsafe { std::fs::File::from_raw_fd(target) };
+
+                        if strategy == BlitStrategy::Copy || installation::reflink(&source, &target).is_err() {
+                            io::copy(&mut source, &mut target)?;
+                        }
+
+                        // Creation mode is subject to the umask
+                        fchmod(target.as_raw_fd(), Mode::from_bits_truncate(item.layout.mode))?;
+                    }
                 }
 
                 stats.num_files += 1;
@@ -957,14 +1932,40 @@ impl Client {
     }
 
     fn load_or_create_system_model(&self, path: PathBuf, state: &State) -> Result<SystemModel, Error> {
-        match system_model::load(&path).map_err(Error::LoadSystemModel)? {
+        match system_model::load(&path, &self.installation.variables).map_err(Error::LoadSystemModel)? {
             Some(system_model) => Ok(system_model),
             None => {
-                let active_repos = self
-                    .repositories
-                    .active()
-                    .map(|repo| (repo.id, repo.repository))
-                    .collect::<repository::Map>();
+                // Prefer the repositories recorded with the state over those active now
+                let active_repos = if state.repositories.is_empty() {
+                    self.repositories
+                        .active()
+                        .map(|repo| (repo.id, repo.repository))
+                        .collect::<repository::Map>()
+                } else {
+                    state
+                        .repositories
+                        .iter()
+                        .map(|repo| {
+                            let description = self
+                                .repositories
+                                .list()
+                                .find(|(id, _)| **id == repo.id)
+                                .map(|(_, current)| current.description.clone())
+                                .unwrap_or_default();
+
+                            (
+                                repo.id.clone(),
+                                Repository {
+                                    description,
+                                    uri: repo.uri.clone(),
+                                    priority: repo.priority,
+                                    active: true,
+                                    aliases: Default::default(),
+                                },
+                            )
+                        })
+                        .collect::<repository::Map>()
+                };
 
                 let packages = self
                     .resolve_packages(state.selections.iter().filter_map(|s| s.explicit.then_some(&s.package)))?
@@ -977,6 +1978,65 @@ impl Client {
         }
     }
 
+    /// Create a system-model from the active repositories & the explicit packages of the active state
+    pub fn create_system_model(&self) -> Result<SystemModel, Error> {
+        let explicit = match self.installation.active_state {
+            Some(id) => {
+                let state = self.state_db.get(id)?;
+                self.resolve_packages(state.selections.iter().filter_map(|s| s.explicit.then_some(&s.package)))?
+            }
+            None => vec![],
+        };
+
+        update_or_create_system_model(None, &self.repositories, &explicit)
+    }
+
+    /// Record the explicit packages of the active state in the installation's system-model
+    ///
+    /// Persists a direct install or removal, which the next sync would otherwise revert.
+    /// Does nothing without a system-model.
+    pub fn update_system_model(&self) -> Result<(), Error> {
+        let Some(system_model) = self.installation.system_model.clone() else {
+            return Ok(());
+        };
+
+        // The active state of the installation predates our own transition
+        let id = installation::read_state_id(&self.installation.root).ok_or(Error::NoActiveState)?;
+        let state = self.state_db.get(id)?;
+        let explicit =
+            self.resolve_packages(state.selections.iter().filter_map(|s| s.explicit.then_some(&s.package)))?;
+
+        let updated = system_model.update(&explicit)?;
+        fs::write(self.installation.system_model_path(), updated.source())?;
+
+        Ok(())
+    }
+
+    /// Download the stones of `packages` without unpacking them, returning their paths
+    ///
+    /// Stones cached by any repository are reused, so packages whose repository was
+    /// removed since can still be found.
+    pub async fn fetch_stones(&self, packages: &[Package]) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = vec![];
+
+        for package in packages {
+            let hash = package.meta.hash.as_deref().ok_or(cache::Error::MissingHash)?;
+            if let Some(path) = cache::find_download(&self.installation, hash) {
+                paths.push(path);
+                continue;
+            }
+
+            let partition = self
+                .repositories
+                .origin(&package.id, &package.meta)
+                .and_then(|id| self.repositories.partition(&id));
+            let download = cache::fetch(&package.meta, partition.as_deref(), &self.installation, |_| {}).await?;
+            paths.push(download.path().to_owned());
+        }
+
+        Ok(paths)
+    }
+
     pub fn export_state(&self, state: state::Id) -> Result<SystemModel, Error> {
         let state = self.state_db.get(state)?;
         let is_active = self.installation.active_state == Some(state.id);
@@ -989,7 +2049,54 @@ impl Client {
                 .join("usr/lib/system-model.kdl")
         };
 
-        self.load_or_create_system_model(path, &state)
+        let system_model = self.load_or_create_system_model(path, &state)?;
+
+        Ok(system_model.pin_repositories(&state.repositories)?)
+    }
+
+    /// Differences between the repositories recorded in `state` and those active now
+    pub fn repository_changes(&self, state: &State) -> Vec<state::RepositoryChange> {
+        state::repository_changes(&state.repositories, &self.repositories.snapshot())
+    }
+}
+
+/// Execute a trigger once busy resources in `root` are released, retrying on failure
+///
+/// Returns the error of the last attempt, for the caller to decide whether it fails
+/// the transaction.
+fn execute_isolated(
+    trigger: &postblit::TriggerRunner<'_>,
+    policy: &wait::Policy,
+    root: &Path,
+    progress: &ProgressBar,
+) -> Result<(), postblit::Error> {
+    let description = trigger_description(trigger);
+
+    let mut attempt = 0;
+
+    loop {
+        policy.wait(root, |busy| {
+            progress.suspend(|| println!("Waiting on {} ({description})", busy.join(", ")));
+        });
+
+        let _activity = watchdog::Activity::start("trigger", description.clone());
+
+        match trigger.execute(policy.trigger_timeout()) {
+            Err(error) if attempt < policy.retries => {
+                warn!("{description} failed, retrying in {}s: {error}", policy.delay);
+                thread::sleep(policy.delay());
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Describe a trigger for progress reporting
+fn trigger_description(trigger: &postblit::TriggerRunner<'_>) -> String {
+    match trigger.handler() {
+        triggers::format::Handler::Run { run, .. } => format!("Executing {run}"),
+        triggers::format::Handler::Delete { .. } => "Executing delete operation".to_owned(),
     }
 }
 
@@ -1021,6 +2128,28 @@ fn create_root_links(root: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Report the configuration files in `/etc` that were merged or need merging by hand
+fn print_config_report(report: &etc::Report) {
+    for (path, outcome) in &report.changes {
+        let path = format!("/etc/{path}");
+        match outcome {
+            etc::Outcome::Installed | etc::Outcome::Updated => info!("{outcome:?} {path} from its vendor default"),
+            etc::Outcome::Merged => println!("{} local changes of {}", "Merged".green(), path.as_str().bold()),
+            etc::Outcome::Conflict => println!(
+                "{} local changes of {} conflict with its new default, written to {}",
+                "Conflict".yellow(),
+                path.as_str().bold(),
+                format!("{path}{}", etc::NEW_SUFFIX).bold()
+            ),
+        }
+    }
+
+    let conflicts = report.conflicts().count();
+    if conflicts > 0 {
+        println!("\n{conflicts} configuration file(s) need merging by hand");
+    }
+}
+
 fn record_state_id(root: &Path, state: state::Id) -> Result<(), Error> {
     let usr = root.join("usr");
     fs::create_dir_all(&usr)?;
@@ -1195,6 +2324,14 @@ impl fmt::Display for PendingFile {
     }
 }
 
+/// The active [`State`] of `installation`, if any
+fn active_state(installation: &Installation, statedb: &db::state::Database) -> Result<Option<State>, Error> {
+    match installation.active_state {
+        Some(id) => Ok(Some(statedb.get(id)?)),
+        None => Ok(None),
+    }
+}
+
 /// Build a [`crate::registry::Registry`] during client initialisation
 ///
 /// # Arguments
@@ -1202,30 +2339,71 @@ impl fmt::Display for PendingFile {
 /// * `installation` - Describe our installation target tree
 /// * `repositories` - Configured repositories to laoad [`crate::registry::Plugin::Repository`]
 /// * `installdb`    - Installation database opened in the installation tree
-/// * `statedb`      - State database opened in the installation tree
+/// * `state`        - State whose packages are reported as installed
 fn build_registry(
     installation: &Installation,
     repositories: &repository::Manager,
     installdb: &db::meta::Database,
-    statedb: &db::state::Database,
+    state: Option<State>,
 ) -> Result<Registry, Error> {
-    let state = match installation.active_state {
-        Some(id) => Some(statedb.get(id)?),
-        None => None,
-    };
-
     let mut registry = Registry::default();
 
     registry.add_plugin(Plugin::Cobble(plugin::Cobble::default()));
     registry.add_plugin(Plugin::Active(plugin::Active::new(state, installdb.clone())));
 
     for repo in repositories.active() {
-        registry.add_plugin(Plugin::Repository(plugin::Repository::new(repo)));
+        registry.add_plugin(Plugin::Repository(plugin::Repository::new(
+            repo,
+            installation.architecture(),
+        )));
+    }
+
+    for (name, db) in foreign::open_all(installation)? {
+        registry.add_plugin(Plugin::Foreign(plugin::Foreign::new(name, db)));
     }
 
     Ok(registry)
 }
 
+/// Files to blit into a single directory, relative to the blit target
+struct BlitBatch<'a> {
+    dir: String,
+    files: Vec<(&'a str, &'a PendingFile)>,
+}
+
+/// Upper bound of files per [`BlitBatch`], so large directories are spread across threads
+const BLIT_BATCH_SIZE: usize = 256;
+
+/// Batch up the files, symlinks etc. among `children` of the directory `dir`
+fn file_batches<'a>(dir: &str, children: &[Element<'a, PendingFile>]) -> Vec<BlitBatch<'a>> {
+    let files = children
+        .iter()
+        .filter_map(|child| match child {
+            Element::Child(name, item) => Some((*name, *item)),
+            Element::Directory(..) => None,
+        })
+        .collect::<Vec<_>>();
+
+    files
+        .chunks(BLIT_BATCH_SIZE)
+        .map(|chunk| BlitBatch {
+            dir: dir.to_owned(),
+            files: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// Where the time of the last blit went
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlitTiming {
+    /// Querying layouts & building the filesystem tree
+    pub plan: Duration,
+    /// Creating the directory tree
+    pub directories: Duration,
+    /// Linking & writing files across the thread pool
+    pub files: Duration,
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 struct BlitStats {
     num_files: u64,
@@ -1250,6 +2428,12 @@ impl BlitStats {
 /// Client-relevant error mapping type
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("journal")]
+    Journal(#[from] journal::Error),
+    #[error("divergence")]
+    Divergence(#[from] divergence::Error),
+    #[error("pending state")]
+    Pending(#[from] pending::Error),
     #[error("root must have an active state")]
     NoActiveState,
     #[error("state {0} already active")]
@@ -1272,6 +2456,8 @@ pub enum Error {
     Meta(#[from] db::Error),
     #[error("prune")]
     Prune(#[from] prune::Error),
+    #[error("save config")]
+    SaveConfig(#[from] config::SaveError),
     #[error("io")]
     Io(#[from] io::Error),
     #[error("filesystem")]
@@ -1280,11 +2466,23 @@ pub enum Error {
     Blit(#[from] Errno),
     #[error("postblit")]
     PostBlit(#[from] postblit::Error),
+    #[error("fixup")]
+    Fixup(#[from] fixup::Error),
+    #[error("hook")]
+    Hook(#[from] hook::Error),
+    #[error("snapshot")]
+    Snapshot(#[from] snapshot::Error),
+    #[error("hand off activation")]
+    Handoff(#[from] handoff::Error),
     #[error("boot")]
     Boot(#[from] boot::Error),
-    /// Had issues processing user-provided string input
-    #[error("string processing")]
-    Dialog(#[from] tui::dialoguer::Error),
+    #[error("boot entries can't be enumerated to keep the states they boot, use --force to prune regardless")]
+    UnknownBootEntries(#[source] boot::Error),
+    #[error("foreign repository")]
+    Foreign(#[from] foreign::Error),
+    /// Failed to obtain confirmation from the user
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
     /// The operation was explicitly cancelled at the user's request
     #[error("cancelled")]
     Cancelled,
@@ -1295,3 +2493,100 @@ pub enum Error {
     #[error("update system model")]
     UpdateSystemModel(#[from] system_model::UpdateError),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pending(entry: layout::Entry) -> PendingFile {
+        PendingFile {
+            id: Default::default(),
+            layout: layout::Layout {
+                uid: 0,
+                gid: 0,
+                mode: 0o644,
+                tag: 0,
+                entry,
+            },
+        }
+    }
+
+    #[test]
+    fn test_file_batches() {
+        let dir = PendingFile::from("lib".to_owned());
+        let symlink = pending(layout::Entry::Symlink("lib".into(), "lib64".into()));
+        let file = pending(layout::Entry::Regular(0x1234, "os-release".into()));
+        let nested = pending(layout::Entry::Regular(0x5678, "lib/libc.so.6".into()));
+
+        let children = vec![
+            Element::Directory("lib", &dir, vec![Element::Child("libc.so.6", &nested)]),
+            Element::Child("lib64", &symlink),
+            Element::Child("os-release", &file),
+        ];
+
+        // Entries at the top-level are blitted straight into the target
+        let batches = file_batches("", &children);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].dir, "");
+        assert_eq!(
+            batches[0].files.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            ["lib64", "os-release"]
+        );
+
+        let files = (0..BLIT_BATCH_SIZE + 1)
+            .map(|_| Element::Child("file", &file))
+            .collect::<Vec<_>>();
+        let batches = file_batches("usr/share", &files);
+        assert_eq!(batches.len(), 2);
+        assert!(batches.iter().all(|batch| batch.dir == "usr/share"));
+    }
+
+    #[test]
+    fn test_documented_phases() {
+        fn sources(dir: &Path, out: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    sources(&path, out);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    out.push(fs::read_to_string(&path).unwrap());
+                }
+            }
+        }
+
+        /// The phase name literal `source` starts with, if any
+        fn literal(source: &str) -> Option<&str> {
+            let name = source.trim_start().strip_prefix('"')?.split('"').next()?;
+            name.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                .then_some(name)
+        }
+
+        let mut files = vec![];
+        sources(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut files);
+
+        let mut emitted = BTreeSet::new();
+        for source in &files {
+            emitted.extend(
+                source
+                    .split("Phase::start(")
+                    .skip(1)
+                    .filter_map(literal)
+                    .map(str::to_owned),
+            );
+            // States are applied as a phase named after their summary
+            emitted.extend(source.split(".new_state(").skip(1).filter_map(|call| {
+                let (_, summary) = call.split_once(')')?.0.rsplit_once(", ")?;
+                literal(summary).map(str::to_lowercase)
+            }));
+        }
+        // Named by the scope `apply_triggers` is called with
+        emitted.extend(["transaction-scope-triggers", "system-scope-triggers"].map(str::to_owned));
+
+        let documented = progress::PHASES
+            .iter()
+            .map(|&phase| phase.to_owned())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(emitted, documented);
+    }
+}
@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! States applied on reboot
+//!
+//! When `/usr` can't be swapped live, i.e. it's mounted read-only by an immutable
+//! OS, a new state is archived to `.moss/root/<id>` rather than promoted, and the
+//! boot entries are regenerated to boot into it next: the initrd swaps in the tree
+//! of the state selected by `moss.fstx`. The state is recorded as pending in
+//! `.moss/db/pending.json` until the first moss invocation after the reboot, which
+//! completes its activation by merging `/etc` & running the system triggers.
+//!
+//! Alternatively, a state staged by `moss sync --stage` is left for a boot-time
+//! service to [`apply_staged`], activating it before the system is up rather than
+//! mutating it while running.
+
+use std::io;
+
+use chrono::{SecondsFormat, Utc};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::{Client, Installation, State, client, state};
+
+/// File in [`Installation::db_path`] holding the [`Pending`] state
+const PENDING_FILE: &str = "pending.json";
+
+/// A state waiting for the next boot to be applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pending {
+    pub state: i32,
+    /// The active state when it was staged
+    pub old_state: Option<i32>,
+    pub staged: String,
+    #[serde(default)]
+    pub apply: Apply,
+}
+
+/// How a pending state is applied on the next boot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Apply {
+    /// Booted into by the initrd, as selected by the boot loader entries
+    #[default]
+    Boot,
+    /// Activated early during boot by [`apply_staged`]
+    Service,
+}
+
+/// The state pending for `installation`, if any
+pub fn read(installation: &Installation) -> Result<Option<Pending>, Error> {
+    match fs::read(installation.db_path(PENDING_FILE)) {
+        Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Whether a pending state of `installation` was booted into & awaits completion
+pub fn is_booted(installation: &Installation) -> bool {
+    read(installation).ok().flatten().is_some_and(|pending| {
+        pending.apply == Apply::Boot && installation.active_state == Some(state::Id::from(pending.state))
+    })
+}
+
+fn write(installation: &Installation, pending: &Pending) -> Result<(), Error> {
+    let path = installation.db_path(PENDING_FILE);
+    let staged = path.with_extension("json.tmp");
+
+    fs::write(&staged, serde_json::to_vec(pending)?)?;
+    fs::rename(&staged, &path)?;
+
+    Ok(())
+}
+
+pub(crate) fn clear(installation: &Installation) -> Result<(), Error> {
+    match fs::remove_file(installation.db_path(PENDING_FILE)) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Make the archived `state` the one applied on the next boot, replacing any state pending before
+pub(crate) fn stage(
+    client: &Client,
+    state: &State,
+    old_state: Option<state::Id>,
+    apply: Apply,
+) -> Result<Pending, client::Error> {
+    let pending = Pending {
+        state: state.id.into(),
+        old_state: old_state.map(i32::from),
+        staged: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        apply,
+    };
+    write(&client.installation, &pending)?;
+
+    // The boot loader entries are left to the activation by the service
+    if apply == Apply::Boot {
+        client::boot::synchronize_pending(client, state)?;
+    }
+
+    Ok(pending)
+}
+
+/// Activate the state staged to be applied by the boot-time service, if any
+///
+/// Returns the activated state. A staged state no longer based on the active
+/// state, i.e. as another state was activated since, is discarded.
+pub fn apply_staged(client: &Client) -> Result<Option<Pending>, client::Error> {
+    let installation = &client.installation;
+    let Some(pending) = read(installation)?.filter(|pending| pending.apply == Apply::Service) else {
+        return Ok(None);
+    };
+
+    if installation.active_state.map(i32::from) != pending.old_state {
+        info!(
+            state = pending.state,
+            "Discarding staged state, another state was activated"
+        );
+        clear(installation)?;
+        return Ok(None);
+    }
+
+    client.activate_state(state::Id::from(pending.state), false)?;
+    clear(installation)?;
+
+    Ok(Some(pending))
+}
+
+/// Complete the activation of the pending state once booted into
+///
+/// Returns the completed state, or `None` if there's none to boot into or it wasn't
+/// booted into yet. A record left behind by booting another state is discarded.
+pub fn complete(client: &Client) -> Result<Option<Pending>, client::Error> {
+    let installation = &client.installation;
+    let Some(pending) = read(installation)? else {
+        return Ok(None);
+    };
+    let active = installation.active_state.map(i32::from);
+
+    if pending.apply != Apply::Boot {
+        return Ok(None);
+    }
+    if active != Some(pending.state) {
+        if active != pending.old_state {
+            info!(
+                state = pending.state,
+                "Discarding pending state, another state was booted"
+            );
+            clear(installation)?;
+        }
+        return Ok(None);
+    }
+
+    info!(
+        state = pending.state,
+        "Completing activation of state applied on reboot"
+    );
+
+    let state = client.state_db.get(state::Id::from(pending.state))?;
+    // The initrd archived the old `/usr` when swapping in the new one
+    let old = pending
+        .old_state
+        .map(state::Id::from)
+        .filter(|old| installation.root_path(old.to_string()).join("usr").exists());
+    client.merge_config_files(&installation.root, &state.selections, old)?;
+    client.complete_activation(true)?;
+
+    clear(installation)?;
+
+    Ok(Some(pending))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid pending state")]
+    Json(#[from] serde_json::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pending() {
+        let pending = Pending {
+            state: 7,
+            old_state: Some(6),
+            staged: "2025-01-01T00:00:00Z".to_owned(),
+            apply: Apply::Service,
+        };
+
+        let encoded = serde_json::to_string(&pending).unwrap();
+        assert_eq!(serde_json::from_str::<Pending>(&encoded).unwrap(), pending);
+        assert!(encoded.contains(r#""apply":"service""#));
+        assert_eq!(
+            serde_json::from_str::<Pending>(r#"{"state":7,"old_state":6,"staged":""}"#)
+                .unwrap()
+                .apply,
+            Apply::Boot
+        );
+    }
+}
@@ -9,13 +9,24 @@
 //! Note that currently we only load from `/usr/share/moss/triggers/{tx,sys.d}/*.yaml`
 //! and do not yet support local triggers
 use std::{
+    io::Read,
+    os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process,
+    process::{self, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::Installation;
 use container::Container;
 use itertools::Itertools;
+use nix::{
+    sys::{
+        resource::{Resource, setrlimit},
+        signal::{Signal, killpg},
+    },
+    unistd::Pid,
+};
 use serde::Deserialize;
 use thiserror::Error;
 use tracing::{error, warn};
@@ -45,6 +56,65 @@ impl config::Config for SystemTrigger {
     }
 }
 
+/// Location of trigger definitions within a root
+const TRIGGER_DIR: &str = "usr/share/moss/triggers";
+
+/// The kind of a trigger definition, determining when it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Kind {
+    /// Runs against the staged `/usr` before it is activated, defined in `tx.d`
+    Transaction,
+    /// Runs against the activated system, defined in `sys.d`
+    System,
+}
+
+impl Kind {
+    /// Directory of the definitions, relative to [`TRIGGER_DIR`]
+    pub fn dir(&self) -> &'static str {
+        match self {
+            Kind::Transaction => "tx.d",
+            Kind::System => "sys.d",
+        }
+    }
+}
+
+/// Load the trigger definitions of `kind` installed to `root`
+pub fn load(root: &Path, kind: Kind) -> Vec<Trigger> {
+    let manager = config::Manager::custom(root.join(TRIGGER_DIR));
+
+    match kind {
+        Kind::Transaction => manager.load::<TransactionTrigger>().into_iter().map(|t| t.0).collect(),
+        Kind::System => manager.load::<SystemTrigger>().into_iter().map(|t| t.0).collect(),
+    }
+}
+
+/// The triggers of a [`Kind`] that fire for a tree, without running them
+#[derive(Debug)]
+pub struct Plan {
+    pub kind: Kind,
+    /// Names of the matched triggers
+    pub triggers: Vec<String>,
+    /// Handlers in execution order
+    pub handlers: Vec<Handler>,
+}
+
+/// Determine which of `triggers` fire for the paths of `fstree`
+pub fn plan(kind: Kind, triggers: &[Trigger], fstree: &vfs::tree::Tree<PendingFile>) -> Result<Plan, Error> {
+    let mut collection = triggers::Collection::new(triggers.iter())?;
+    collection.process_paths(fstree.iter().map(|m| m.to_string()));
+
+    Ok(Plan {
+        kind,
+        triggers: collection.matched().map(ToOwned::to_owned).collect(),
+        handlers: collection
+            .bake()?
+            .into_iter()
+            .map(|trigger| trigger.handler().clone())
+            .collect(),
+    })
+}
+
 /// The trigger scope determines the environment that the trigger runs in
 #[derive(Clone, Copy, Debug)]
 pub(super) enum TriggerScope<'a> {
@@ -56,6 +126,13 @@ pub(super) enum TriggerScope<'a> {
 }
 
 impl TriggerScope<'_> {
+    fn kind(&self) -> Kind {
+        match self {
+            TriggerScope::Transaction(..) => Kind::Transaction,
+            TriggerScope::System(..) => Kind::System,
+        }
+    }
+
     // Determine the correct root directory
     fn root_dir(&self) -> PathBuf {
         match self {
@@ -116,33 +193,35 @@ pub(super) fn triggers<'a>(
     scope: TriggerScope<'a>,
     fstree: &vfs::tree::Tree<PendingFile>,
 ) -> Result<Vec<TriggerRunner<'a>>, Error> {
-    // Pre-calculate trigger root path once
-    let trigger_root = {
-        let mut path = PathBuf::with_capacity(50);
-        path.push("usr");
-        path.push("share");
-        path.push("moss");
-        path.push("triggers");
-        path
-    };
-
-    let full_trigger_path = scope.root_dir().join(&trigger_root);
-
-    // Load appropriate triggers from their locations and convert back to a vec of Trigger
-    let triggers = match scope {
-        TriggerScope::Transaction(..) => config::Manager::custom(&full_trigger_path)
-            .load::<TransactionTrigger>()
-            .into_iter()
-            .map(|t| t.0)
-            .collect_vec(),
-        TriggerScope::System(..) => config::Manager::custom(&full_trigger_path)
-            .load::<SystemTrigger>()
-            .into_iter()
-            .map(|t| t.0)
-            .collect_vec(),
-    };
+    let triggers = load(&scope.root_dir(), scope.kind());
 
-    // Load trigger collection, process all the paths, convert to scoped TriggerRunner vec
+    compile(scope, &triggers, fstree)
+}
+
+/// Load the trigger called `name` if defined for the given scope, matched against the filesystem
+pub(super) fn named<'a>(
+    scope: TriggerScope<'a>,
+    fstree: &vfs::tree::Tree<PendingFile>,
+    name: &str,
+) -> Result<Option<Vec<TriggerRunner<'a>>>, Error> {
+    let triggers = load(&scope.root_dir(), scope.kind())
+        .into_iter()
+        .filter(|trigger| trigger.name == name)
+        .collect_vec();
+
+    if triggers.is_empty() {
+        return Ok(None);
+    }
+
+    compile(scope, &triggers, fstree).map(Some)
+}
+
+/// Process all the paths against `triggers`, converting the hits to a scoped [`TriggerRunner`] vec
+fn compile<'a>(
+    scope: TriggerScope<'a>,
+    triggers: &[Trigger],
+    fstree: &vfs::tree::Tree<PendingFile>,
+) -> Result<Vec<TriggerRunner<'a>>, Error> {
     let mut collection = triggers::Collection::new(triggers.iter())?;
     collection.process_paths(fstree.iter().map(|m| m.to_string()));
     let computed_commands = collection
@@ -160,12 +239,19 @@ impl TriggerRunner<'_> {
 
     /// Execute a trigger, taking care to account for the transaction scope and client scope
     ///
-    /// All transaction triggers are run via sandboxing ([`container::Container`]) to limit their
-    /// system view, and limit write access.
-    /// System triggers will execute without any sandboxing when moss is used directly against the
-    /// live root filesystem, and will force sandboxing when using a non-`/` root (such as using the
-    /// `-D argument with `moss install`)
-    pub fn execute(&self) -> Result<(), Error> {
+    /// All triggers are run via sandboxing ([`container::Container`]) in their own mount & pid
+    /// namespace without networking, limiting their system view and write access.
+    /// Transaction triggers may only write to `/usr`, while system triggers can also write to
+    /// `/etc`, `/var` & `/run` of the root.
+    ///
+    /// This includes system triggers of the live root `/`, which used to run directly on
+    /// the host: those are the ones a broken or compromised trigger could wedge or damage,
+    /// and everything they legitimately update (caches, units, tmpfiles & the service
+    /// manager) lives in the bound directories. Boot entries aren't updated by triggers
+    /// but by [`super::boot::synchronize`], outside of the sandbox.
+    ///
+    /// Each trigger is bounded by `timeout`, both in CPU and wall time.
+    pub fn execute(&self, timeout: Duration) -> Result<(), Error> {
         match self.scope {
             TriggerScope::Transaction(install, _) => {
                 // TODO: Add caching support via /var/
@@ -175,38 +261,67 @@ impl TriggerRunner<'_> {
                     .bind_rw(self.scope.guest_path("usr"), "/usr")
                     .work_dir("/");
 
-                Ok(isolation.run(|| execute_trigger_directly(&self.trigger))?)
+                Ok(isolation.run(|| execute_trigger_directly(&self.trigger, timeout))?)
             }
             TriggerScope::System(install, _) => {
-                // OK, if the root == `/` then we can run directly, otherwise we need to containerise with RW.
-                if install.root.to_string_lossy() == "/" {
-                    Ok(execute_trigger_directly(&self.trigger)?)
-                } else {
-                    let isolation = Container::new(install.isolation_dir())
-                        .networking(false)
-                        .bind_rw(self.scope.host_path("etc"), "/etc")
-                        .bind_rw(self.scope.guest_path("usr"), "/usr")
-                        .work_dir("/");
-
-                    Ok(isolation.run(|| execute_trigger_directly(&self.trigger))?)
+                let mut isolation = Container::new(install.isolation_dir())
+                    .networking(false)
+                    .bind_rw(self.scope.host_path("etc"), "/etc")
+                    .bind_rw(self.scope.guest_path("usr"), "/usr")
+                    .work_dir("/");
+
+                // i.e. for `systemd-tmpfiles` & talking to the service manager
+                for dir in ["var", "run"] {
+                    let host = self.scope.host_path(dir);
+                    if host.exists() {
+                        isolation = isolation.bind_rw(host, Path::new("/").join(dir));
+                    }
                 }
+
+                Ok(isolation.run(|| execute_trigger_directly(&self.trigger, timeout))?)
             }
         }
     }
 }
 
-/// Internal executor for triggers.
-fn execute_trigger_directly(trigger: &CompiledHandler) -> Result<(), Error> {
+/// Internal executor for triggers, run within the container
+fn execute_trigger_directly(trigger: &CompiledHandler, timeout: Duration) -> Result<(), Error> {
     match trigger.handler() {
         Handler::Run { run, args } => {
-            let cmd = process::Command::new(run).args(args).current_dir("/").output()?;
+            // Inherited by anything the trigger spawns, guarding against busy loops
+            setrlimit(Resource::RLIMIT_CPU, timeout.as_secs(), timeout.as_secs())?;
 
-            if let Some(code) = cmd.status.code() {
-                if code != 0 {
-                    // Convert outputs once and reuse
-                    let stdout = String::from_utf8_lossy(&cmd.stdout);
-                    let stderr = String::from_utf8_lossy(&cmd.stderr);
+            let mut child = process::Command::new(run)
+                .args(args)
+                .current_dir("/")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                // Its own process group, so anything it spawns is killed along with it
+                .process_group(0)
+                .spawn()?;
+
+            // Drain output while waiting so the trigger can't block on a full pipe
+            let stdout = child.stdout.take().map(read_to_end);
+            let stderr = child.stderr.take().map(read_to_end);
+
+            let deadline = Instant::now() + timeout;
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL)?;
+                    child.wait()?;
+                    return Err(Error::Timeout(run.clone()));
+                }
+                thread::sleep(Duration::from_millis(50));
+            };
 
+            let stdout = stdout.and_then(|handle| handle.join().ok()).unwrap_or_default();
+            let stderr = stderr.and_then(|handle| handle.join().ok()).unwrap_or_default();
+
+            if let Some(code) = status.code() {
+                if code != 0 {
                     warn!(
                         command = run,
                         args = ?args,
@@ -215,6 +330,7 @@ fn execute_trigger_directly(trigger: &CompiledHandler) -> Result<(), Error> {
                         stderr = %stderr,
                         "Trigger exited with non-zero status code"
                     );
+                    return Err(Error::Status(run.clone(), code));
                 }
             } else {
                 error!(
@@ -222,6 +338,7 @@ fn execute_trigger_directly(trigger: &CompiledHandler) -> Result<(), Error> {
                     args = ?args,
                     "Failed to execute trigger"
                 );
+                return Err(Error::Status(run.clone(), -1));
             }
         }
         Handler::Delete { .. } => todo!(),
@@ -230,6 +347,15 @@ fn execute_trigger_directly(trigger: &CompiledHandler) -> Result<(), Error> {
     Ok(())
 }
 
+/// Read `reader` to completion on a separate thread
+fn read_to_end(mut reader: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = vec![];
+        let _ = reader.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).into_owned()
+    })
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("container")]
@@ -240,4 +366,13 @@ pub enum Error {
 
     #[error("io")]
     IO(#[from] std::io::Error),
+
+    #[error("limit or kill trigger")]
+    Limit(#[from] nix::Error),
+
+    #[error("{0} timed out")]
+    Timeout(String),
+
+    #[error("{0} exited with status {1}")]
+    Status(String, i32),
 }
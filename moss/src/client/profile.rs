@@ -0,0 +1,120 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Session environment generated from package exports
+//!
+//! The [`Export`]s of every package in a state are consolidated into a shell profile
+//! snippet for login shells and an `environment.d` snippet for systemd user sessions.
+//! Both are written to the state's `/usr`, so they're swapped along with the state on
+//! activation & rollback rather than drifting as hand maintained files in `/etc`.
+
+use std::{io, path::Path};
+
+use fs_err as fs;
+
+use crate::{
+    Package,
+    package::{Export, export},
+};
+
+/// Shell profile snippet, relative to the root
+pub const PROFILE_PATH: &str = "usr/share/defaults/etc/profile.d/50-moss-exports.sh";
+/// systemd `environment.d` snippet, relative to the root
+pub const ENVIRONMENT_PATH: &str = "usr/lib/environment.d/50-moss-exports.conf";
+
+const HEADER: &str = "# Generated by moss from the exports of installed packages, do not edit\n";
+
+/// Write the snippets for the exports of `packages` into `root`
+///
+/// Exports are applied in package name order, so the last package setting a
+/// variable wins. Nothing is written if no package exports anything.
+pub fn record(root: &Path, packages: &[Package]) -> io::Result<()> {
+    let exports = packages
+        .iter()
+        .flat_map(|package| &package.meta.exports)
+        .collect::<Vec<_>>();
+
+    if exports.is_empty() {
+        return Ok(());
+    }
+
+    for (path, contents) in [
+        (PROFILE_PATH, profile(&exports)),
+        (ENVIRONMENT_PATH, environment(&exports)),
+    ] {
+        let path = root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+    }
+
+    Ok(())
+}
+
+/// POSIX shell snippet sourced by login shells
+fn profile(exports: &[&Export]) -> String {
+    let mut contents = HEADER.to_owned();
+
+    for export in exports {
+        let value = shell_quote(&export.value);
+        let line = match export.kind {
+            export::Kind::Set => format!("export {}={value}\n", export.name),
+            export::Kind::Append => format!("export {0}=\"${{{0}:+${0}:}}\"{value}\n", export.name),
+        };
+        contents.push_str(&line);
+    }
+
+    contents
+}
+
+/// `environment.d(5)` snippet read by the systemd user manager
+fn environment(exports: &[&Export]) -> String {
+    let mut contents = HEADER.to_owned();
+
+    for export in exports {
+        let value = export.value.replace('\\', "\\\\").replace('$', "\\$");
+        let line = match export.kind {
+            export::Kind::Set => format!("{}={value}\n", export.name),
+            export::Kind::Append => format!("{0}=${{{0}:+${0}:}}{value}\n", export.name),
+        };
+        contents.push_str(&line);
+    }
+
+    contents
+}
+
+/// Single quote `value` so the shell doesn't expand it
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snippets() {
+        let exports = ["JAVA_HOME=/usr/lib/jvm/it's", "PATH+=/usr/lib/jvm/bin", "PRICE=$5"]
+            .map(|export| export.parse::<Export>().unwrap());
+        let exports = exports.iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            profile(&exports),
+            format!(
+                "{HEADER}export JAVA_HOME='/usr/lib/jvm/it'\\''s'\n\
+                 export PATH=\"${{PATH:+$PATH:}}\"'/usr/lib/jvm/bin'\n\
+                 export PRICE='$5'\n"
+            )
+        );
+        assert_eq!(
+            environment(&exports),
+            format!(
+                "{HEADER}JAVA_HOME=/usr/lib/jvm/it's\n\
+                 PATH=${{PATH:+$PATH:}}/usr/lib/jvm/bin\n\
+                 PRICE=\\$5\n"
+            )
+        );
+    }
+}
@@ -7,6 +7,18 @@
 //! Quite simply this is a strategy based garbage collector for unused/unwanted
 //! system states (i.e. historical snapshots) that cleans up database entries
 //! and assets on disk by way of refcounting.
+//!
+//! A [`Policy`] is configured in `/etc/moss/prune.yaml` or `/etc/moss/prune.d/*.yaml`,
+//! as saved to `/etc/moss/prune.d/policy.yaml` by `moss state prune --policy --save`,
+//! and is applied after each successful transaction when `auto` is set:
+//!
+//! ```yaml
+//! keep: 5
+//! keep-daily: 7
+//! keep-weekly: 4
+//! keep-tagged: true
+//! auto: true
+//! ```
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::{
@@ -14,17 +26,20 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use chrono::{DateTime, Datelike, Local};
 use fs_err as fs;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use tui::{
-    dialoguer::{Confirm, theme::ColorfulTheme},
-    pretty::autoprint_columns,
-};
+use tui::pretty::autoprint_columns;
 
 use crate::repository;
-use crate::{Installation, State, client::cache, db, package, state};
+use crate::{
+    Installation, State,
+    client::{boot, divergence},
+    db, package, prompt, state,
+};
 
 /// The prune strategy for removing old states
 #[derive(Debug, Clone, Copy)]
@@ -33,6 +48,90 @@ pub enum Strategy {
     KeepRecent { keep: u64, include_newer: bool },
     /// Removes a specific state
     Remove(state::Id),
+    /// Keep the states retained by the [`Policy`], remove older ones
+    Policy(Policy),
+}
+
+/// Which archived states to keep when pruning by [`Strategy::Policy`]
+///
+/// A state is kept if any of the rules retain it. States newer than the
+/// active state & states referenced by boot entries are always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Keep the most recent N states, including the active state
+    pub keep: u64,
+    /// Keep the newest state of each of the last N days with states
+    pub keep_daily: u64,
+    /// Keep the newest state of each of the last N weeks with states
+    pub keep_weekly: u64,
+    /// Keep all tagged states
+    pub keep_tagged: bool,
+    /// Prune after each successful transaction
+    pub auto: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            keep: 10,
+            keep_daily: 0,
+            keep_weekly: 0,
+            keep_tagged: true,
+            auto: false,
+        }
+    }
+}
+
+impl config::Config for Policy {
+    fn domain() -> String {
+        "prune".into()
+    }
+}
+
+impl Policy {
+    /// States retained by this policy, from `states` as `(id, created, tagged)` & the
+    /// `booted` states referenced by boot entries
+    fn retained(
+        &self,
+        states: &[(state::Id, DateTime<Local>, bool)],
+        booted: &BTreeSet<state::Id>,
+    ) -> BTreeSet<state::Id> {
+        let newest_first = states
+            .iter()
+            .sorted_by_key(|(id, created, _)| std::cmp::Reverse((*created, *id)))
+            .collect::<Vec<_>>();
+
+        // Newest state of each of the last `count` periods identified by `period`
+        let newest_per = |count: u64, period: &dyn Fn(&DateTime<Local>) -> (i32, u32)| {
+            newest_first
+                .iter()
+                .unique_by(|(_, created, _)| period(created))
+                .take(count as usize)
+                .map(|(id, _, _)| *id)
+                .collect::<Vec<_>>()
+        };
+
+        newest_first
+            .iter()
+            .take(self.keep as usize)
+            .map(|(id, _, _)| *id)
+            .chain(newest_per(self.keep_daily, &|created| {
+                (created.year(), created.ordinal())
+            }))
+            .chain(newest_per(self.keep_weekly, &|created| {
+                let week = created.iso_week();
+                (week.year(), week.week())
+            }))
+            .chain(
+                newest_first
+                    .iter()
+                    .filter(|(_, _, tagged)| self.keep_tagged && *tagged)
+                    .map(|(id, _, _)| *id),
+            )
+            .chain(booted.iter().copied())
+            .collect()
+    }
 }
 
 /// Prune old states using [`Strategy`] and garbage collect
@@ -45,12 +144,17 @@ pub enum Strategy {
 /// * - `install_db`   - Installation's "installed" database
 /// * - `layout_db`    - Installation's layout database
 /// * - `installation` - Client specific target filesystem encapsulation
+/// * - `boot_entries` - Boot loader entries installed on the boot partitions
+/// * - `force`        - Prune states even when a boot entry still references them
+#[allow(clippy::too_many_arguments)]
 pub fn prune_states(
     strategy: Strategy,
     state_db: &db::state::Database,
     install_db: &db::meta::Database,
     layout_db: &db::layout::Database,
     installation: &Installation,
+    boot_entries: &[boot::BootEntry],
+    force: bool,
     yes: bool,
 ) -> Result<(), Error> {
     // Only prune if the moss root has an active state (otherwise
@@ -61,8 +165,18 @@ pub fn prune_states(
 
     let state_ids = state_db.list_ids()?;
 
+    // States which can still be booted are protected from removal, unless forced
+    let protected = if force {
+        BTreeSet::new()
+    } else {
+        boot_entries
+            .iter()
+            .filter_map(|entry| entry.state)
+            .collect::<BTreeSet<_>>()
+    };
+
     // Find each state we need to remove
-    let removal_ids = match strategy {
+    let mut removal_ids = match strategy {
         Strategy::KeepRecent { keep, include_newer } => {
             // Filter for all removal candidates
             let candidates = state_ids
@@ -89,16 +203,61 @@ pub fn prune_states(
                 .filter_map(|(idx, (id, _))| if idx < num_to_remove { Some(*id) } else { None })
                 .collect::<Vec<_>>()
         }
-        Strategy::Remove(remove) => state_ids
-            .iter()
-            // Remove if this id actually exists
-            .find_map(|(id, _)| (*id == remove).then_some(remove))
-            .into_iter()
-            .collect(),
+        Strategy::Policy(policy) => {
+            let states = state_db
+                .all()?
+                .into_iter()
+                .map(|state| (state.id, state.created.with_timezone(&Local), !state.tags.is_empty()))
+                .collect::<Vec<_>>();
+            // Unlike states pruned otherwise, bootable states are kept even if forced
+            let booted = boot_entries.iter().filter_map(|entry| entry.state).collect();
+            let retained = policy.retained(&states, &booted);
+
+            state_ids
+                .iter()
+                .filter(|(id, _)| *id < current_state && !retained.contains(id))
+                .map(|(id, _)| *id)
+                .collect()
+        }
+        Strategy::Remove(remove) => {
+            if protected.contains(&remove) {
+                return Err(Error::BootReferenced(remove));
+            }
+
+            state_ids
+                .iter()
+                // Remove if this id actually exists
+                .find_map(|(id, _)| (*id == remove).then_some(remove))
+                .into_iter()
+                .collect()
+        }
     };
 
-    // Bail if there's no states to remove
-    if removal_ids.is_empty() {
+    let (skipped, removable): (Vec<_>, Vec<_>) = removal_ids.into_iter().partition(|id| protected.contains(id));
+    removal_ids = removable;
+
+    if !skipped.is_empty() {
+        println!(
+            "Keeping {} state(s) referenced by boot entries, use --force to prune them: {}",
+            skipped.len(),
+            skipped.iter().map(|id| format!("#{id}")).join(", ")
+        );
+        println!();
+    }
+
+    // Entries booting a state which is removed or no longer exists
+    let stale_entries = boot_entries
+        .iter()
+        .filter(|entry| {
+            entry
+                .state
+                .is_some_and(|id| removal_ids.contains(&id) || !state_ids.iter().any(|(state, _)| *state == id))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Bail if there's nothing to remove
+    if removal_ids.is_empty() && stale_entries.is_empty() {
         // TODO: Print no states to be removed
         return Ok(());
     }
@@ -139,20 +298,23 @@ pub fn prune_states(
         .filter_map(|(pkg, count)| (count == 0).then_some(pkg))
         .collect::<Vec<_>>();
 
-    // Print out the states to be removed to the user
-    println!("The following state(s) will be removed:");
-    println!();
-    autoprint_columns(&removals.iter().map(state::ColumnDisplay).collect::<Vec<_>>());
-    println!();
+    // Print out the states & boot entries to be removed to the user
+    if !removals.is_empty() {
+        println!("The following state(s) will be removed:");
+        println!();
+        autoprint_columns(&removals.iter().map(state::ColumnDisplay).collect::<Vec<_>>());
+        println!();
+    }
+    if !stale_entries.is_empty() {
+        println!("The following boot entries will be removed:");
+        println!();
+        for entry in &stale_entries {
+            println!("  {}", entry.path.display());
+        }
+        println!();
+    }
 
-    let result = if yes {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm("remove states", " Do you wish to continue? ", yes)?;
     if !result {
         return Err(Error::Cancelled);
     }
@@ -160,14 +322,12 @@ pub fn prune_states(
     // Prune these states / packages from all dbs
     prune_databases(&removals, &package_removals, state_db, install_db, layout_db)?;
 
-    // Remove orphaned downloads
+    // Remove orphaned downloads of every repository partition
     remove_orphaned_files(
         // root
-        installation.cache_path("downloads").join("v1"),
+        installation.cache_path("downloads"),
         // final set of hashes to compare against
         install_db.file_hashes()?,
-        // path builder using hash
-        |hash| cache::download_path(installation, &hash).ok(),
     )?;
 
     // Remove orphaned assets
@@ -176,8 +336,6 @@ pub fn prune_states(
         installation.assets_path("v2"),
         // final set of hashes to compare against
         layout_db.file_hashes()?,
-        // path builder using hash
-        |hash| Some(cache::asset_path(installation, &hash)),
     )?;
 
     // Remove each state's archive folder
@@ -187,8 +345,12 @@ pub fn prune_states(
         if archive_path.exists() {
             fs::remove_dir_all(&archive_path)?;
         }
+        divergence::remove(installation, state.id)?;
     }
 
+    // Remove boot entries (and their kernels) which can no longer boot
+    boot::remove_entries(installation, &stale_entries)?;
+
     Ok(())
 }
 
@@ -252,14 +414,12 @@ pub fn prune_cache(
     // Now we can prune "orphaned package artefacts" / packages artefacts
     // on disk but not defined in our internal dbs
     {
-        // Remove orphaned downloads (package stones)
+        // Remove orphaned downloads (package stones) of every repository partition
         num_removed_files += remove_orphaned_files(
             // root
-            installation.cache_path("downloads").join("v1"),
+            installation.cache_path("downloads"),
             // final set of hashes to compare against
             install_db.file_hashes()?,
-            // path builder using hash
-            |hash| cache::download_path(installation, &hash).ok(),
         )?;
 
         // Remove orphaned assets (unpacked package assets in CAS)
@@ -268,8 +428,6 @@ pub fn prune_cache(
             installation.assets_path("v2"),
             // final set of hashes to compare against
             layout_db.file_hashes()?,
-            // path builder using hash
-            |hash| Some(cache::asset_path(installation, &hash)),
         )?;
     }
 
@@ -305,32 +463,23 @@ fn prune_databases(
 }
 
 /// Removes all files under `root` that no longer exist in the provided `final_hashes` set
-fn remove_orphaned_files(
-    root: PathBuf,
-    final_hashes: BTreeSet<String>,
-    compute_path: impl Fn(String) -> Option<PathBuf>,
-) -> Result<usize, Error> {
-    // Compute hashes to remove by (installed - final)
-    let installed_hashes = enumerate_file_hashes(&root)?;
-    let hashes_to_remove = installed_hashes.difference(&final_hashes);
+///
+/// Files are named by their hash, possibly with a `.part` extension while still being
+/// downloaded. The same hash may be stored more than once, i.e. in separate download
+/// partitions, so files are matched by name rather than by computed path.
+fn remove_orphaned_files(root: PathBuf, final_hashes: BTreeSet<String>) -> Result<usize, Error> {
+    let files = enumerate_files(&root)?;
 
     // Remove each and it's parent dir if empty
-    hashes_to_remove.into_iter().try_fold(0, |acc, hash| {
-        // Compute path to file using hash
-        let Some(file) = compute_path(hash.clone()) else {
-            return Ok(acc);
-        };
-        let partial = file.with_extension("part");
+    files.into_iter().try_fold(0, |acc, file| {
+        let name = file.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+        let hash = name.strip_suffix(".part").unwrap_or(name);
 
-        // Remove if it exists
-        if file.exists() {
-            fs::remove_file(&file)?;
+        if final_hashes.contains(hash) {
+            return Ok(acc);
         }
 
-        // Remove partial file if it exists
-        if partial.exists() {
-            fs::remove_file(&partial)?;
-        }
+        fs::remove_file(&file)?;
 
         // Try to remove leading parent dirs if they're
         // now empty
@@ -342,15 +491,6 @@ fn remove_orphaned_files(
     })
 }
 
-/// Returns all nested files under `root` and parses the file name as a hash
-fn enumerate_file_hashes(root: impl AsRef<Path>) -> io::Result<BTreeSet<String>> {
-    let files = enumerate_files(root)?;
-
-    let path_to_hash = |path: PathBuf| path.file_name().and_then(|s| s.to_str()).unwrap_or_default().to_owned();
-
-    Ok(files.into_iter().map(path_to_hash).collect())
-}
-
 /// Returns all nested files under `root`
 fn enumerate_files(root: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
     use rayon::prelude::*;
@@ -427,10 +567,79 @@ pub enum Error {
     NoActiveState,
     #[error("cannot prune the currently active state")]
     PruneCurrent,
+    #[error("state #{0} is referenced by a boot entry, use --force to remove it along with its entries")]
+    BootReferenced(state::Id),
+    #[error("boot")]
+    Boot(#[from] boot::Error),
     #[error("db")]
     DB(#[from] db::Error),
     #[error("io")]
     Io(#[from] io::Error),
-    #[error("string processing")]
-    Dialog(#[from] tui::dialoguer::Error),
+    #[error("prompt")]
+    Prompt(#[from] prompt::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_policy_retained() {
+        let at = |day, hour| Local.with_ymd_and_hms(2025, 3, day, hour, 0, 0).unwrap();
+        // 2025-03-03 is a monday
+        let states = [
+            (state::Id::from(1), at(3, 9), false),
+            (state::Id::from(2), at(3, 18), false),
+            (state::Id::from(3), at(5, 9), true),
+            (state::Id::from(4), at(10, 9), false),
+            (state::Id::from(5), at(11, 9), false),
+            (state::Id::from(6), at(11, 18), false),
+        ];
+        let retained = |policy: Policy| {
+            policy
+                .retained(&states, &BTreeSet::new())
+                .into_iter()
+                .map(i32::from)
+                .collect::<Vec<_>>()
+        };
+
+        let keep = Policy {
+            keep: 2,
+            keep_tagged: false,
+            ..Policy::default()
+        };
+        assert_eq!(retained(keep), [5, 6]);
+        assert_eq!(
+            retained(Policy {
+                keep_tagged: true,
+                ..keep
+            }),
+            [3, 5, 6]
+        );
+        assert_eq!(
+            retained(Policy {
+                keep: 1,
+                keep_daily: 3,
+                ..keep
+            }),
+            [3, 4, 6]
+        );
+        assert_eq!(
+            retained(Policy {
+                keep: 0,
+                keep_weekly: 2,
+                ..keep
+            }),
+            [3, 6]
+        );
+        assert_eq!(
+            keep.retained(&states, &BTreeSet::from([state::Id::from(1)]))
+                .into_iter()
+                .map(i32::from)
+                .collect::<Vec<_>>(),
+            [1, 5, 6]
+        );
+    }
 }
@@ -0,0 +1,344 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Filesystem snapshots around state transitions
+//!
+//! When enabled, the root is snapshotted before a new state is applied and the
+//! snapshot is recorded against the state that was active at that time. Unlike
+//! archived states, which only cover `/usr`, a snapshot also covers `/etc` & the
+//! moss databases. Configured via `/etc/moss/snapshot.yaml`:
+//!
+//! ```yaml
+//! enabled: true
+//! keep: 5
+//! ```
+//!
+//! Restoring a snapshot creates a writable copy of it which is booted into
+//! next, as the running root can't be swapped out from under itself. Removing
+//! a snapshot on ZFS also removes stale copies restored from it, while one that's
+//! booted into (next) is promoted to no longer depend on it.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use fs_err as fs;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{installation::Filesystem, state};
+
+/// Snapshot configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Snapshot the root before applying a new state
+    pub enabled: bool,
+    /// Number of snapshots to keep, oldest are removed first
+    pub keep: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            keep: 5,
+        }
+    }
+}
+
+impl config::Config for Config {
+    fn domain() -> String {
+        "snapshot".into()
+    }
+}
+
+/// Snapshot support of a filesystem
+pub trait Backend {
+    /// Snapshot `root`, recorded against state `id`
+    fn create(&self, root: &Path, id: state::Id) -> Result<(), Error>;
+
+    /// State ids with a recorded snapshot, in ascending order
+    fn list(&self, root: &Path) -> Result<Vec<state::Id>, Error>;
+
+    /// Remove the snapshot recorded against state `id`
+    fn remove(&self, root: &Path, id: state::Id) -> Result<(), Error>;
+
+    /// Make the snapshot of state `id` the root booted into next
+    fn restore(&self, root: &Path, id: state::Id) -> Result<(), Error>;
+}
+
+/// The snapshot backend for `filesystem`, if it supports snapshots
+pub fn backend(filesystem: Filesystem) -> Option<Box<dyn Backend>> {
+    match filesystem {
+        Filesystem::Btrfs => Some(Box::new(Btrfs)),
+        Filesystem::Zfs => Some(Box::new(Zfs)),
+        Filesystem::Other => None,
+    }
+}
+
+fn snapshot_name(id: state::Id) -> String {
+    format!("moss-state-{id}")
+}
+
+fn parse_snapshot_name(name: &str) -> Option<state::Id> {
+    name.strip_prefix("moss-state-")?
+        .parse::<i32>()
+        .ok()
+        .map(state::Id::from)
+}
+
+/// Read-only subvolume snapshots below `.moss/snapshots`
+///
+/// Requires the root to be a subvolume.
+struct Btrfs;
+
+impl Btrfs {
+    fn dir(root: &Path) -> PathBuf {
+        root.join(".moss").join("snapshots")
+    }
+}
+
+impl Backend for Btrfs {
+    fn create(&self, root: &Path, id: state::Id) -> Result<(), Error> {
+        let dir = Self::dir(root);
+        fs::create_dir_all(&dir)?;
+
+        let target = dir.join(snapshot_name(id));
+        if target.exists() {
+            self.remove(root, id)?;
+        }
+
+        run(Command::new("btrfs")
+            .args(["subvolume", "snapshot", "-r"])
+            .arg(root)
+            .arg(target))?;
+
+        Ok(())
+    }
+
+    fn list(&self, root: &Path) -> Result<Vec<state::Id>, Error> {
+        let Ok(entries) = fs::read_dir(Self::dir(root)) else {
+            return Ok(vec![]);
+        };
+
+        let mut ids = entries
+            .flatten()
+            .filter_map(|entry| parse_snapshot_name(entry.file_name().to_str()?))
+            .collect::<Vec<_>>();
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    fn remove(&self, root: &Path, id: state::Id) -> Result<(), Error> {
+        run(Command::new("btrfs")
+            .args(["subvolume", "delete"])
+            .arg(Self::dir(root).join(snapshot_name(id))))?;
+        Ok(())
+    }
+
+    fn restore(&self, root: &Path, id: state::Id) -> Result<(), Error> {
+        let snapshot = Self::dir(root).join(snapshot_name(id));
+        if !snapshot.exists() {
+            return Err(Error::NoSnapshot(id));
+        }
+
+        // Writable copy next to the snapshots, so it survives later snapshots of the root
+        let restored = Self::dir(root).join(format!("{}-restored", snapshot_name(id)));
+        if restored.exists() {
+            run(Command::new("btrfs").args(["subvolume", "delete"]).arg(&restored))?;
+        }
+        run(Command::new("btrfs")
+            .args(["subvolume", "snapshot"])
+            .arg(&snapshot)
+            .arg(&restored))?;
+
+        let show = run(Command::new("btrfs").args(["subvolume", "show"]).arg(&restored))?;
+        let subvolume_id = show
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Subvolume ID:"))
+            .map(|id| id.trim().to_owned())
+            .ok_or(Error::UnexpectedOutput("btrfs subvolume show"))?;
+
+        run(Command::new("btrfs")
+            .args(["subvolume", "set-default", &subvolume_id])
+            .arg(root))?;
+
+        Ok(())
+    }
+}
+
+/// Snapshots of the dataset mounted at the root
+struct Zfs;
+
+impl Zfs {
+    fn dataset(root: &Path) -> Result<String, Error> {
+        let output = run(Command::new("zfs").args(["list", "-H", "-o", "name"]).arg(root))?;
+        output
+            .lines()
+            .next()
+            .map(ToOwned::to_owned)
+            .ok_or(Error::UnexpectedOutput("zfs list"))
+    }
+
+    fn pool(dataset: &str) -> &str {
+        dataset.split('/').next().unwrap_or(dataset)
+    }
+
+    /// The dataset booted into next, if the pool sets one
+    fn bootfs(pool: &str) -> Result<Option<String>, Error> {
+        let output = run(Command::new("zpool").args(["get", "-H", "-o", "value", "bootfs", pool]))?;
+        Ok(Some(output.trim().to_owned()).filter(|bootfs| !bootfs.is_empty() && bootfs != "-"))
+    }
+
+    /// Clones of `snapshot`, such as restored ones
+    fn clones(snapshot: &str) -> Result<Vec<String>, Error> {
+        let output = run(Command::new("zfs").args(["get", "-H", "-o", "value", "clones", snapshot]))?;
+        Ok(parse_clones(&output))
+    }
+
+    fn is_mounted(dataset: &str) -> Result<bool, Error> {
+        let output = run(Command::new("zfs").args(["get", "-H", "-o", "value", "mounted", dataset]))?;
+        Ok(output.trim() == "yes")
+    }
+}
+
+/// Parse the `clones` property of a snapshot, a comma separated list or `-` without any
+fn parse_clones(value: &str) -> Vec<String> {
+    value
+        .trim()
+        .split(',')
+        .filter(|clone| !clone.is_empty() && *clone != "-")
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+impl Backend for Zfs {
+    fn create(&self, root: &Path, id: state::Id) -> Result<(), Error> {
+        let snapshot = format!("{}@{}", Self::dataset(root)?, snapshot_name(id));
+
+        if self.list(root)?.contains(&id) {
+            run(Command::new("zfs").args(["destroy", &snapshot]))?;
+        }
+        run(Command::new("zfs").args(["snapshot", &snapshot]))?;
+
+        Ok(())
+    }
+
+    fn list(&self, root: &Path) -> Result<Vec<state::Id>, Error> {
+        let dataset = Self::dataset(root)?;
+        let output =
+            run(Command::new("zfs").args(["list", "-H", "-t", "snapshot", "-o", "name", "-d", "1", &dataset]))?;
+
+        let mut ids = output
+            .lines()
+            .filter_map(|line| parse_snapshot_name(line.rsplit_once('@')?.1))
+            .collect::<Vec<_>>();
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    fn remove(&self, root: &Path, id: state::Id) -> Result<(), Error> {
+        let dataset = Self::dataset(root)?;
+        let snapshot = format!("{dataset}@{}", snapshot_name(id));
+        let bootfs = Self::bootfs(Self::pool(&dataset))?;
+
+        // A snapshot can't be destroyed while restored clones depend on it. A clone booted
+        // into (next) is promoted, which moves the snapshot over to it, stale ones are destroyed
+        let mut promoted = false;
+        for clone in Self::clones(&snapshot)? {
+            if Some(&clone) == bootfs.as_ref() || Self::is_mounted(&clone)? {
+                run(Command::new("zfs").args(["promote", &clone]))?;
+                promoted = true;
+            } else {
+                run(Command::new("zfs").args(["destroy", &clone]))?;
+            }
+        }
+
+        if !promoted {
+            run(Command::new("zfs").args(["destroy", &snapshot]))?;
+        }
+
+        Ok(())
+    }
+
+    fn restore(&self, root: &Path, id: state::Id) -> Result<(), Error> {
+        if !self.list(root)?.contains(&id) {
+            return Err(Error::NoSnapshot(id));
+        }
+
+        let dataset = Self::dataset(root)?;
+        let snapshot = format!("{dataset}@{}", snapshot_name(id));
+        let clone = format!("{dataset}-{}", snapshot_name(id));
+
+        // Replace the clone of an earlier restore, so the snapshot is restored as it was taken
+        if Self::clones(&snapshot)?.contains(&clone) {
+            run(Command::new("zfs").args(["destroy", &clone]))?;
+        }
+
+        // The clone inherits the mountpoint, so keep it from mounting over the root
+        run(Command::new("zfs").args(["clone", "-o", "canmount=noauto", &snapshot, &clone]))?;
+        run(Command::new("zpool").args(["set", &format!("bootfs={clone}"), Self::pool(&dataset)]))?;
+
+        Ok(())
+    }
+}
+
+/// Run `command`, returning its stdout
+fn run(command: &mut Command) -> Result<String, Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| Error::Spawn(program.clone(), error))?;
+
+    if !output.status.success() {
+        return Err(Error::Status(program, output.status.code()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("run {0}")]
+    Spawn(String, #[source] io::Error),
+    #[error("{0} exited with status {1:?}")]
+    Status(String, Option<i32>),
+    #[error("unexpected output from {0}")]
+    UnexpectedOutput(&'static str),
+    #[error("no snapshot recorded for state {0}")]
+    NoSnapshot(state::Id),
+    #[error("{0} doesn't support snapshots")]
+    Unsupported(Filesystem),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_name() {
+        let id = state::Id::from(42);
+        assert_eq!(parse_snapshot_name(&snapshot_name(id)), Some(id));
+        assert_eq!(parse_snapshot_name("moss-state-42-restored"), None);
+        assert_eq!(parse_snapshot_name("other"), None);
+    }
+
+    #[test]
+    fn test_parse_clones() {
+        assert!(parse_clones("-\n").is_empty());
+        assert_eq!(
+            parse_clones("rpool/root-moss-state-4,rpool/other\n"),
+            vec!["rpool/root-moss-state-4".to_owned(), "rpool/other".to_owned()]
+        );
+    }
+}
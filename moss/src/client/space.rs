@@ -0,0 +1,405 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Size of a transaction & the disk space it needs, checked before it's confirmed
+//!
+//! Stones are downloaded into the cache, unpacked into the asset store of the
+//! installation & blitted into a new root from there. Only the blit is free when
+//! the assets can be reflinked or hardlinked, otherwise every file of the new
+//! root is copied.
+//!
+//! Packages that were never unpacked have no recorded layout, so their installed
+//! size is taken from the repository index, or estimated from their (compressed)
+//! download size for indexes predating installed sizes.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use nix::sys::statvfs::statvfs;
+use stone::payload::layout;
+use thiserror::Error;
+use tracing::info;
+use tui::{HumanBytes, Styled};
+
+use crate::{
+    Installation, Package,
+    client::{Client, Scope, cache},
+    db,
+    installation::BlitStrategy,
+    package,
+};
+
+/// Sizes of a transaction & the space it needs on each filesystem it writes to
+#[derive(Debug, Clone, Default)]
+pub struct Preflight {
+    /// Bytes to download, excluding stones already in the cache
+    pub download_size: u64,
+    /// Change in size of the installed packages once applied
+    pub installed_delta: i64,
+    /// Sizes are partly estimated from download sizes, see the module docs
+    pub estimated: bool,
+    /// Filesystems written to by the transaction
+    pub filesystems: Vec<Filesystem>,
+}
+
+/// Space needed & available on a single filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filesystem {
+    /// What's stored on this filesystem, i.e. `cache` or `root`
+    pub names: Vec<&'static str>,
+    /// Path of the first of [`Self::names`]
+    pub path: PathBuf,
+    pub required: u64,
+    pub available: u64,
+}
+
+impl Filesystem {
+    pub fn is_sufficient(&self) -> bool {
+        self.required <= self.available
+    }
+}
+
+impl Preflight {
+    /// Print a summary of the sizes for confirmation of the transaction
+    pub fn print(&self) {
+        let estimated = if self.estimated {
+            format!(" {}", "(estimated)".dim())
+        } else {
+            String::new()
+        };
+        let sign = if self.installed_delta < 0 { "-" } else { "+" };
+
+        println!("{:<16} {}", "Download size:", HumanBytes(self.download_size));
+        println!(
+            "{:<16} {sign}{}{estimated}",
+            "Installed size:",
+            HumanBytes(self.installed_delta.unsigned_abs())
+        );
+        for filesystem in &self.filesystems {
+            let line = format!(
+                "{:<16} {} required, {} available",
+                format!("Space on {}:", filesystem.names.join(" & ")),
+                HumanBytes(filesystem.required),
+                HumanBytes(filesystem.available)
+            );
+            if filesystem.is_sufficient() {
+                println!("{line}");
+            } else {
+                println!("{}", line.red());
+            }
+        }
+        println!();
+    }
+
+    /// Refuse the transaction if any filesystem lacks the space it needs
+    ///
+    /// With `force`, only a warning is printed instead.
+    pub fn check(&self, force: bool) -> Result<(), Error> {
+        info!(
+            download_size = self.download_size,
+            installed_size_delta = self.installed_delta,
+            estimated = self.estimated,
+            "Transaction size"
+        );
+        for filesystem in &self.filesystems {
+            info!(
+                filesystem = filesystem.names.join(","),
+                path = %filesystem.path.display(),
+                required = filesystem.required,
+                available = filesystem.available,
+                sufficient = filesystem.is_sufficient(),
+                "Filesystem space"
+            );
+        }
+
+        let Some(filesystem) = self.filesystems.iter().find(|filesystem| !filesystem.is_sufficient()) else {
+            return Ok(());
+        };
+
+        if force {
+            eprintln!(
+                "{}: {} needed on {}, only {} available. Continuing due to --force",
+                "Warning".yellow(),
+                HumanBytes(filesystem.required),
+                filesystem.path.display(),
+                HumanBytes(filesystem.available)
+            );
+            Ok(())
+        } else {
+            Err(Error::InsufficientSpace {
+                path: filesystem.path.clone(),
+                required: filesystem.required,
+                available: filesystem.available,
+            })
+        }
+    }
+}
+
+/// Compute the sizes of a transaction caching `incoming` packages & dropping `outgoing`
+/// ones from the installed set
+pub fn preflight(client: &Client, incoming: &[&Package], outgoing: &[&Package]) -> Result<Preflight, Error> {
+    let installation = &client.installation;
+
+    let (blit_name, blit_target, strategy) = match &client.scope {
+        Scope::Stateful => ("root", installation.staging_dir(), installation.blit_strategy()),
+        Scope::Ephemeral { blit_root } => (
+            "blit root",
+            blit_root.clone(),
+            BlitStrategy::probe(&installation.assets_path("v2"), blit_root),
+        ),
+    };
+    let is_copy = strategy == BlitStrategy::Copy;
+
+    // A copied root needs room for all of it, not just the changes
+    let installed = if is_copy && !client.is_ephemeral() {
+        client.registry.list_installed().collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
+    let unpacked = client.layout_db.package_ids()?;
+    let sizes = installed_sizes(
+        client,
+        incoming
+            .iter()
+            .chain(outgoing)
+            .map(|p| &p.id)
+            .chain(installed.iter().map(|p| &p.id))
+            .filter(|id| unpacked.contains(*id)),
+    )?;
+
+    let mut estimated = false;
+    let mut size_of = |package: &Package| match sizes.get(&package.id).copied().or(package.meta.installed_size) {
+        Some(size) => size,
+        None => {
+            estimated = true;
+            package.meta.download_size.unwrap_or_default()
+        }
+    };
+
+    let download_size = incoming
+        .iter()
+        .filter(|p| {
+            p.meta
+                .hash
+                .as_deref()
+                .is_none_or(|hash| cache::find_download(installation, hash).is_none())
+        })
+        .map(|p| p.meta.download_size.unwrap_or_default())
+        .sum::<u64>();
+    let unpack_size = incoming
+        .iter()
+        .filter(|p| !unpacked.contains(&p.id))
+        .map(|p| size_of(p))
+        .sum::<u64>();
+    let installed_delta = incoming.iter().map(|p| size_of(p)).sum::<u64>() as i64
+        - outgoing.iter().map(|p| size_of(p)).sum::<u64>() as i64;
+    let blit_size = if is_copy {
+        let installed_size = installed.iter().map(&mut size_of).sum::<u64>() as i64;
+        (installed_size + installed_delta).max(0) as u64
+    } else {
+        0
+    };
+
+    let filesystems = filesystems(
+        installation,
+        [
+            ("cache", installation.cache_path(""), download_size),
+            ("root", installation.assets_path(""), unpack_size),
+            (blit_name, blit_target, blit_size),
+        ],
+    )?;
+
+    Ok(Preflight {
+        download_size,
+        installed_delta,
+        estimated,
+        filesystems,
+    })
+}
+
+/// Installed size of each package, summed from the on-disk size of its assets
+pub fn installed_sizes<'a>(
+    client: &Client,
+    packages: impl IntoIterator<Item = &'a package::Id>,
+) -> Result<BTreeMap<package::Id, u64>, db::Error> {
+    let mut sizes = BTreeMap::new();
+    let mut asset_sizes = BTreeMap::new();
+
+    for (id, layout) in client.layout_db.query(packages)? {
+        let size = sizes.entry(id).or_insert(0);
+
+        if let layout::Entry::Regular(hash, _) = layout.entry {
+            let asset_size = *asset_sizes.entry(hash).or_insert_with(|| {
+                fs::metadata(cache::asset_path(&client.installation, &format!("{hash:02x}")))
+                    .map(|meta| meta.len())
+                    .unwrap_or_default()
+            });
+            *size += asset_size;
+        }
+    }
+
+    Ok(sizes)
+}
+
+/// Disk space used by the files under `paths`
+///
+/// Hardlinked files are counted once, so blitted states sharing their files
+/// with the asset store add nothing on top of it.
+pub fn disk_usage<'a>(paths: impl IntoIterator<Item = &'a Path>) -> io::Result<u64> {
+    let mut seen = BTreeSet::new();
+    let mut usage = 0;
+    let mut pending = paths.into_iter().map(Path::to_owned).collect::<Vec<_>>();
+
+    while let Some(path) = pending.pop() {
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+
+        if !seen.insert((meta.dev(), meta.ino())) {
+            continue;
+        }
+
+        // `st_blocks` is always in 512 byte units
+        usage += meta.blocks() * 512;
+
+        if meta.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+/// Group the space required by each named path per filesystem
+fn filesystems(
+    installation: &Installation,
+    requirements: impl IntoIterator<Item = (&'static str, PathBuf, u64)>,
+) -> Result<Vec<Filesystem>, Error> {
+    let mut devices = vec![];
+
+    for (name, path, required) in requirements {
+        if required == 0 {
+            continue;
+        }
+
+        // Paths not created yet are on the filesystem of their closest ancestor
+        let existing = path
+            .ancestors()
+            .find(|path| path.exists())
+            .unwrap_or(&installation.root);
+        let device = fs::metadata(existing)?.dev();
+
+        devices.push((device, name, path.clone(), required, available_space(existing)?));
+    }
+
+    Ok(group(devices))
+}
+
+/// Merge requirements on the same device, in order of first appearance
+fn group(devices: Vec<(u64, &'static str, PathBuf, u64, u64)>) -> Vec<Filesystem> {
+    let mut order = vec![];
+    let mut grouped = BTreeMap::<u64, Filesystem>::new();
+
+    for (device, name, path, required, available) in devices {
+        let filesystem = grouped.entry(device).or_insert_with(|| {
+            order.push(device);
+            Filesystem {
+                names: vec![],
+                path,
+                required: 0,
+                available,
+            }
+        });
+        if !filesystem.names.contains(&name) {
+            filesystem.names.push(name);
+        }
+        filesystem.required += required;
+    }
+
+    order.into_iter().filter_map(|device| grouped.remove(&device)).collect()
+}
+
+/// Bytes available to unprivileged users on the filesystem of `path`
+fn available_space(path: &Path) -> Result<u64, Error> {
+    let stat = statvfs(path).map_err(|errno| Error::Statvfs(path.to_owned(), errno))?;
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(
+        "not enough space on {}: {} required, {} available. Free up space or pass --force to continue anyway",
+        .path.display(),
+        HumanBytes(*.required),
+        HumanBytes(*.available)
+    )]
+    InsufficientSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    #[error("query free space of {0:?}")]
+    Statvfs(PathBuf, #[source] nix::Error),
+    #[error("db")]
+    Db(#[from] db::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let grouped = group(vec![
+            (1, "cache", PathBuf::from("/var/cache/moss"), 10, 100),
+            (2, "root", PathBuf::from("/.moss/assets"), 20, 50),
+            (2, "root", PathBuf::from("/.moss/root/staging"), 30, 50),
+            (1, "blit root", PathBuf::from("/tmp/root"), 40, 100),
+        ]);
+
+        assert_eq!(
+            grouped,
+            vec![
+                Filesystem {
+                    names: vec!["cache", "blit root"],
+                    path: PathBuf::from("/var/cache/moss"),
+                    required: 50,
+                    available: 100,
+                },
+                Filesystem {
+                    names: vec!["root"],
+                    path: PathBuf::from("/.moss/assets"),
+                    required: 50,
+                    available: 50,
+                },
+            ]
+        );
+        assert!(grouped.iter().all(Filesystem::is_sufficient));
+
+        let preflight = Preflight {
+            filesystems: vec![Filesystem {
+                names: vec!["root"],
+                path: PathBuf::from("/"),
+                required: 51,
+                available: 50,
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(preflight.check(false), Err(Error::InsufficientSpace { .. })));
+        assert!(preflight.check(true).is_ok());
+    }
+}
@@ -11,16 +11,16 @@ use std::{
 use fs_err as fs;
 use rayon::iter::{IntoParallelIterator as _, IntoParallelRefIterator as _, ParallelIterator as _};
 use stone::{payload::layout, write::digest};
-use tui::{
-    ProgressBar, ProgressStyle, Styled,
-    dialoguer::{Confirm, theme::ColorfulTheme},
-};
+use tui::{ProgressBar, ProgressStyle, Styled};
 use vfs::tree::BlitFile;
 
 use crate::{
     Client, Package, Signal,
-    client::{self, cache},
-    package, runtime, signal, state,
+    client::{
+        self, cache,
+        journal::{self, Journal},
+    },
+    package, prompt, runtime, signal, state,
 };
 
 pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::Error> {
@@ -41,7 +41,7 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
             .push((package, file));
     }
 
-    let pb = ProgressBar::new(unique_assets.len() as u64)
+    let pb = ProgressBar::with_draw_target(Some(unique_assets.len() as u64), tui::progress_target())
         .with_message("Verifying")
         .with_style(
             ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {wide_msg}")
@@ -183,14 +183,11 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
         println!(" {} {issue}", "×".yellow());
     }
 
-    let result = if yes {
-        true
-    } else {
-        Confirm::with_theme(&ColorfulTheme::default())
-            .with_prompt(" Fixing issues, this will change your system state. Do you wish to continue? ")
-            .default(false)
-            .interact()?
-    };
+    let result = prompt::confirm(
+        "fix verification issues",
+        " Fixing issues, this will change your system state. Do you wish to continue? ",
+        yes,
+    )?;
     if !result {
         return Err(client::Error::Cancelled);
     }
@@ -258,15 +255,20 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
 
         let is_active = client.installation.active_state == Some(state.id);
 
+        // The active state is swapped in like a new one, so it's journaled from the blit on
+        let journal = is_active
+            .then(|| Journal::begin(&client.installation, journal::Operation::NewState, None, Some(state.id)))
+            .transpose()?;
+
         // Blits to staging dir
         let fstree = client.blit_root(state.selections.iter().map(|s| &s.package))?;
 
-        if is_active {
+        if let Some(journal) = journal {
             let system_model =
                 client.load_or_create_system_model(client.installation.root.join("usr/lib/system-model.kdl"), state)?;
 
             // Override install root with the newly blitted active state
-            client.apply_stateful_blit(fstree, state, None, system_model)?;
+            client.apply_stateful_blit(journal, fstree, state, None, system_model)?;
             // Remove corrupt (swapped) state from staging directory
             fs::remove_dir_all(client.installation.staging_dir())?;
         } else {
@@ -279,9 +281,10 @@ pub fn verify(client: &Client, yes: bool, verbose: bool) -> Result<(), client::E
             )?;
 
             // Use the staged blit as an ephereral target for the non-active state
-            // then archive it to it's archive directory
+            // then archive it to it's archive directory. Only its `/usr` is archived,
+            // so there are no configuration files to install.
             client::record_state_id(&client.installation.staging_dir(), state.id)?;
-            client.apply_ephemeral_blit(fstree, &client.installation.staging_dir(), system_model)?;
+            client.apply_ephemeral_blit(fstree, &client.installation.staging_dir(), &[], system_model)?;
 
             // Remove the old archive state so the new blit can be archived
             fs::remove_dir_all(client.installation.root_path(state.id.to_string()))?;
@@ -0,0 +1,292 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Waiting out other programs that hold resources needed by triggers
+//!
+//! Triggers such as font cache or unit regeneration can fail when another program
+//! (i.e. a flatpak update) is busy with the same resource. Rather than leaving the
+//! state half-activated, system triggers wait for the configured resources to be
+//! released and may be retried on failure. By default, the resources of the programs
+//! commonly running alongside moss are waited for. Configured via `/etc/moss/trigger-wait.yaml`,
+//! where `resources` replaces the defaults:
+//!
+//! ```yaml
+//! retries: 3
+//! delay: 5
+//! timeout: 120
+//! trigger-timeout: 300
+//! resources:
+//!   - name: fontconfig cache
+//!     present: /var/cache/fontconfig/*.LCK
+//!   - name: flatpak
+//!     locked: /var/lib/flatpak/repo/.lock
+//!   - name: systemd jobs
+//!     command: ["sh", "-c", "systemctl list-jobs --no-legend | grep -q ."]
+//! ```
+
+use std::{
+    num::NonZeroU64,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fnmatch::Pattern;
+use fs_err as fs;
+use nix::{
+    errno::Errno,
+    fcntl::{FcntlArg, FlockArg, fcntl, flock},
+    libc,
+};
+use serde::Deserialize;
+
+/// How long, and how often, to wait for busy resources
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Number of times a failed trigger is retried
+    pub retries: u32,
+    /// Seconds between retries
+    pub delay: u64,
+    /// Seconds to wait for busy resources before running a trigger regardless
+    pub timeout: u64,
+    /// Seconds a trigger may run for, in both CPU and wall time, before it's killed
+    ///
+    /// Generous by default, as slow disks can drag out i.e. initramfs generation.
+    pub trigger_timeout: NonZeroU64,
+    /// Resources to wait for, see [`Policy::default_resources`] for the defaults
+    pub resources: Vec<Resource>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            delay: 5,
+            timeout: 60,
+            trigger_timeout: NonZeroU64::new(300).unwrap(),
+            resources: Self::default_resources(),
+        }
+    }
+}
+
+impl config::Config for Policy {
+    fn domain() -> String {
+        "trigger-wait".into()
+    }
+}
+
+/// A resource which may be held by another program
+#[derive(Debug, Clone, Deserialize)]
+pub struct Resource {
+    /// Shown to the user while waiting
+    pub name: String,
+    #[serde(flatten)]
+    pub check: Check,
+}
+
+/// How to tell a [`Resource`] is busy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Check {
+    /// Busy while a file exists, the file name may contain a glob
+    Present(PathBuf),
+    /// Busy while another program holds an advisory lock on the file, be it a
+    /// `fcntl` record lock (as OSTree, and so flatpak, takes) or a `flock`
+    Locked(PathBuf),
+    /// Busy while the command exits successfully
+    Command(Vec<String>),
+}
+
+impl Policy {
+    /// Resources of programs which commonly run alongside moss & share what its triggers regenerate
+    pub fn default_resources() -> Vec<Resource> {
+        let resource = |name: &str, check| Resource {
+            name: name.to_owned(),
+            check,
+        };
+
+        vec![
+            resource("fontconfig cache", Check::Present("/var/cache/fontconfig/*.LCK".into())),
+            resource("dynamic linker cache", Check::Present("/etc/ld.so.cache~".into())),
+            resource("flatpak", Check::Locked("/var/lib/flatpak/repo/.lock".into())),
+        ]
+    }
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_secs(self.delay)
+    }
+
+    pub fn trigger_timeout(&self) -> Duration {
+        Duration::from_secs(self.trigger_timeout.get())
+    }
+
+    /// Names of the resources in `root` currently held by other programs
+    pub fn busy(&self, root: &Path) -> Vec<&str> {
+        self.resources
+            .iter()
+            .filter(|resource| resource.check.is_busy(root))
+            .map(|resource| resource.name.as_str())
+            .collect()
+    }
+
+    /// Wait until no resources are busy or the timeout passes, calling `on_wait`
+    /// with the busy resources when waiting starts
+    pub fn wait(&self, root: &Path, on_wait: impl FnOnce(&[&str])) {
+        let busy = self.busy(root);
+        if busy.is_empty() {
+            return;
+        }
+
+        on_wait(&busy);
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        while Instant::now() < deadline && !self.busy(root).is_empty() {
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
+
+impl Check {
+    fn is_busy(&self, root: &Path) -> bool {
+        match self {
+            Check::Present(path) => !matching(root, path).is_empty(),
+            Check::Locked(path) => matching(root, path).iter().any(|path| is_locked(path)),
+            Check::Command(command) => command.split_first().is_some_and(|(program, args)| {
+                Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .is_ok_and(|status| status.success())
+            }),
+        }
+    }
+}
+
+/// Existing paths in `root` matching `path`, which may contain a glob in its file name
+fn matching(root: &Path, path: &Path) -> Vec<PathBuf> {
+    let path = root.join(path.strip_prefix("/").unwrap_or(path));
+
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|name| name.to_str())) else {
+        return vec![];
+    };
+    let Ok(pattern) = name.parse::<Pattern>() else {
+        return vec![];
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| pattern.match_path(name).is_some())
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Whether another program holds an advisory lock on `path`
+fn is_locked(path: &Path) -> bool {
+    let Ok(file) = fs::File::open(path) else {
+        return false;
+    };
+
+    // Ask which lock would conflict with writing the whole file, without taking one
+    // that'd stall the holder. Open file description locks conflict with process
+    // associated ones too, and so report both
+    let mut lock = libc::flock {
+        l_type: libc::F_WRLCK as libc::c_short,
+        l_whence: libc::SEEK_SET as libc::c_short,
+        l_start: 0,
+        l_len: 0,
+        l_pid: 0,
+    };
+    if fcntl(file.as_raw_fd(), FcntlArg::F_OFD_GETLK(&mut lock)).is_ok()
+        && lock.l_type != libc::F_UNLCK as libc::c_short
+    {
+        return true;
+    }
+
+    // `flock` locks are separate from the above, probing takes the lock, which is
+    // released again once the file is closed
+    matches!(
+        flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock),
+        Err(Errno::EWOULDBLOCK)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        let policy: Policy = serde_yaml::from_str(
+            r#"
+retries: 1
+trigger-timeout: 600
+resources:
+  - name: fontconfig cache
+    present: /var/cache/fontconfig/*.LCK
+  - name: flatpak
+    locked: /var/lib/flatpak/repo/.lock
+  - name: always
+    command: ["true"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.retries, 1);
+        assert_eq!(policy.delay, 5);
+        assert_eq!(policy.trigger_timeout(), Duration::from_secs(600));
+        assert_eq!(Policy::default().trigger_timeout(), Duration::from_secs(300));
+        assert!(serde_yaml::from_str::<Policy>("trigger-timeout: 0").is_err());
+        assert!(matches!(&policy.resources[0].check, Check::Present(path) if path.ends_with("*.LCK")));
+        assert!(matches!(policy.resources[1].check, Check::Locked(_)));
+
+        assert_eq!(policy.busy(Path::new("/nonexistent")), vec!["always"]);
+
+        let defaults = serde_yaml::from_str::<Policy>("retries: 1").unwrap();
+        assert_eq!(defaults.resources.len(), Policy::default_resources().len());
+        assert!(defaults.busy(Path::new("/nonexistent")).is_empty());
+    }
+
+    #[test]
+    fn test_is_locked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".lock");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        assert!(!is_locked(&path));
+
+        // As OSTree locks its repository
+        let lock = libc::flock {
+            l_type: libc::F_RDLCK as libc::c_short,
+            l_whence: libc::SEEK_SET as libc::c_short,
+            l_start: 0,
+            l_len: 0,
+            l_pid: 0,
+        };
+        fcntl(file.as_raw_fd(), FcntlArg::F_OFD_SETLK(&lock)).unwrap();
+        assert!(is_locked(&path));
+        drop(file);
+        assert!(!is_locked(&path));
+
+        let file = fs::File::open(&path).unwrap();
+        flock(file.as_raw_fd(), FlockArg::LockShared).unwrap();
+        assert!(is_locked(&path));
+    }
+}
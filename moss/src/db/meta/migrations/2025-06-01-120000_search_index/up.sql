@@ -0,0 +1,8 @@
+-- Inverted index of lowercase terms found in each package's name,
+-- summary, description and providers, rebuilt on repository refresh
+CREATE TABLE IF NOT EXISTS meta_search (
+    term TEXT NOT NULL,
+    package TEXT NOT NULL,
+    PRIMARY KEY (term, package),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS meta_replaces (
+    package TEXT NOT NULL,
+    replaces TEXT NOT NULL,
+    PRIMARY KEY (package, replaces),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
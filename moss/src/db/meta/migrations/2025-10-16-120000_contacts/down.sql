@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE meta DROP COLUMN bug_tracker;
+ALTER TABLE meta DROP COLUMN maintainer;
@@ -0,0 +1,2 @@
+ALTER TABLE meta ADD COLUMN bug_tracker TEXT;
+ALTER TABLE meta ADD COLUMN maintainer TEXT;
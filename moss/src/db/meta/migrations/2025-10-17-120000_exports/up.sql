@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS meta_exports (
+    package TEXT NOT NULL,
+    export TEXT NOT NULL,
+    PRIMARY KEY (package, export),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
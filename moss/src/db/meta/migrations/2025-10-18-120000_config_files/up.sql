@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS meta_config_files (
+    package TEXT NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY (package, path),
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
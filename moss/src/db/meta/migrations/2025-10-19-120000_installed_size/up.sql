@@ -0,0 +1 @@
+ALTER TABLE meta ADD COLUMN installed_size BIGINT;
@@ -0,0 +1 @@
+ALTER TABLE meta ADD COLUMN security_release BIGINT;
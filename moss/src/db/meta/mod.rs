@@ -25,6 +25,7 @@ pub enum Filter<'a> {
     Dependency(Dependency),
     Name(package::Name),
     Keyword(&'a str),
+    Replaces(Provider),
 }
 
 #[derive(Debug, Clone)]
@@ -75,6 +76,21 @@ impl Database {
                 .load_iter(conn)?
                 .map(|p| Ok(p?.conflict))
                 .collect::<Result<_, Error>>()?;
+            let replaces = model::Replaces::belonging_to(&meta)
+                .select(model::Replaces::as_select())
+                .load_iter(conn)?
+                .map(|p| Ok(p?.replaces))
+                .collect::<Result<_, Error>>()?;
+            let exports = model::Export::belonging_to(&meta)
+                .select(model::Export::as_select())
+                .load_iter(conn)?
+                .map(|e| Ok(e?.export))
+                .collect::<Result<_, Error>>()?;
+            let config_files = model::ConfigFile::belonging_to(&meta)
+                .select(model::ConfigFile::as_select())
+                .load_iter(conn)?
+                .map(|c| Ok(c?.path))
+                .collect::<Result<_, Error>>()?;
 
             Ok(Meta {
                 name: meta.name,
@@ -86,13 +102,23 @@ impl Database {
                 description: meta.description,
                 source_id: meta.source_id,
                 homepage: meta.homepage,
+                bug_tracker: meta.bug_tracker,
+                maintainer: meta.maintainer,
                 licenses,
                 dependencies,
                 providers,
                 conflicts,
+                replaces,
+                exports,
+                config_files,
                 uri: meta.uri,
                 hash: meta.hash,
                 download_size: meta.download_size.map(|size| size as u64),
+                installed_size: meta.installed_size.map(|size| size as u64),
+                classification: meta
+                    .classification
+                    .and_then(|classification| classification.parse().ok()),
+                security_release: meta.security_release.map(|release| release as u64),
             })
         })
     }
@@ -112,12 +138,36 @@ impl Database {
         })
     }
 
-    pub fn query(&self, filter: Option<Filter<'_>>) -> Result<Vec<(package::Id, Meta)>, Error> {
+    /// Like [`Database::provider_packages`], but only packages supporting `architecture`
+    ///
+    /// See [`Meta::supports_architecture`]
+    pub fn provider_packages_for(&self, provider: &Provider, architecture: &str) -> Result<Vec<package::Id>, Error> {
+        let architectures = package::meta::ANY_ARCHITECTURE
+            .iter()
+            .copied()
+            .chain([architecture])
+            .collect::<Vec<_>>();
+
         self.conn.exec(|conn| {
-            let map_row = |result| {
-                let meta: model::Meta = result?;
+            model::meta_providers::table
+                .inner_join(model::meta::table)
+                .select(model::meta_providers::package)
+                .distinct()
+                .filter(model::meta_providers::provider.eq(provider.to_string()))
+                .filter(model::meta::architecture.eq_any(architectures))
+                .load_iter::<String, _>(conn)?
+                .map(|result| {
+                    let id = result?;
+                    Ok(id.into())
+                })
+                .collect()
+        })
+    }
 
-                Ok((
+    pub fn query(&self, filter: Option<Filter<'_>>) -> Result<Vec<(package::Id, Meta)>, Error> {
+        self.conn.exec(|conn| {
+            let map_row = |meta: model::Meta| {
+                (
                     meta.package.into(),
                     Meta {
                         name: meta.name,
@@ -129,15 +179,25 @@ impl Database {
                         description: meta.description,
                         source_id: meta.source_id,
                         homepage: meta.homepage,
+                        bug_tracker: meta.bug_tracker,
+                        maintainer: meta.maintainer,
                         licenses: Default::default(),
                         dependencies: Default::default(),
                         providers: Default::default(),
                         conflicts: Default::default(),
+                        replaces: Default::default(),
+                        exports: Default::default(),
+                        config_files: Default::default(),
                         uri: meta.uri,
                         hash: meta.hash,
                         download_size: meta.download_size.map(|size| size as u64),
+                        installed_size: meta.installed_size.map(|size| size as u64),
+                        classification: meta
+                            .classification
+                            .and_then(|classification| classification.parse().ok()),
+                        security_release: meta.security_release.map(|release| release as u64),
                     },
-                ))
+                )
             };
 
             let mut entries: BTreeMap<package::Id, Meta> = match &filter {
@@ -145,33 +205,29 @@ impl Database {
                     .select(model::Meta::as_select())
                     .inner_join(model::meta_providers::table)
                     .filter(model::meta_providers::provider.eq(provider.to_string()))
-                    .load_iter::<model::Meta, _>(conn)?,
+                    .load::<model::Meta>(conn)?,
                 Some(Filter::Dependency(dependency)) => model::meta::table
                     .select(model::Meta::as_select())
                     .inner_join(model::meta_dependencies::table)
                     .filter(model::meta_dependencies::dependency.eq(dependency.to_string()))
-                    .load_iter::<model::Meta, _>(conn)?,
+                    .load::<model::Meta>(conn)?,
                 Some(Filter::Name(name)) => model::meta::table
                     .select(model::Meta::as_select())
                     .filter(model::meta::name.eq(name.to_string()))
-                    .load_iter::<model::Meta, _>(conn)?,
-                Some(Filter::Keyword(keyword)) => {
-                    let pattern = format!("%{keyword}%");
-                    model::meta::table
-                        .select(model::Meta::as_select())
-                        .filter(
-                            model::meta::name
-                                .like(pattern.clone())
-                                .or(model::meta::summary.like(pattern)),
-                        )
-                        .load_iter::<model::Meta, _>(conn)?
-                }
+                    .load::<model::Meta>(conn)?,
+                Some(Filter::Keyword(keyword)) => keyword_matches(keyword, conn)?,
+                Some(Filter::Replaces(provider)) => model::meta::table
+                    .select(model::Meta::as_select())
+                    .inner_join(model::meta_replaces::table)
+                    .filter(model::meta_replaces::replaces.eq(provider.to_string()))
+                    .load::<model::Meta>(conn)?,
                 None => model::meta::table
                     .select(model::Meta::as_select())
-                    .load_iter::<model::Meta, _>(conn)?,
+                    .load::<model::Meta>(conn)?,
             }
+            .into_iter()
             .map(map_row)
-            .collect::<Result<_, Error>>()?;
+            .collect();
 
             let package_ids = entries
                 .keys()
@@ -224,6 +280,39 @@ impl Database {
                         }
                         Ok(())
                     })?;
+
+                // Add replaces
+                model::Replaces::belonging_to(chunk)
+                    .load_iter::<model::Replaces, _>(conn)?
+                    .try_for_each::<_, Result<_, Error>>(|result| {
+                        let row = result?;
+                        if let Some(meta) = entries.get_mut(&row.package.into()) {
+                            meta.replaces.insert(row.replaces);
+                        }
+                        Ok(())
+                    })?;
+
+                // Add exports
+                model::Export::belonging_to(chunk)
+                    .load_iter::<model::Export, _>(conn)?
+                    .try_for_each::<_, Result<_, Error>>(|result| {
+                        let row = result?;
+                        if let Some(meta) = entries.get_mut(&row.package.into()) {
+                            meta.exports.insert(row.export);
+                        }
+                        Ok(())
+                    })?;
+
+                // Add config files
+                model::ConfigFile::belonging_to(chunk)
+                    .load_iter::<model::ConfigFile, _>(conn)?
+                    .try_for_each::<_, Result<_, Error>>(|result| {
+                        let row = result?;
+                        if let Some(meta) = entries.get_mut(&row.package.into()) {
+                            meta.config_files.insert(row.path);
+                        }
+                        Ok(())
+                    })?;
             }
 
             Ok(entries.into_iter().collect())
@@ -272,9 +361,14 @@ impl Database {
                     description: &meta.description,
                     source_id: &meta.source_id,
                     homepage: &meta.homepage,
+                    bug_tracker: meta.bug_tracker.as_deref(),
+                    maintainer: meta.maintainer.as_deref(),
                     uri: meta.uri.as_deref(),
                     hash: meta.hash.as_deref(),
                     download_size: meta.download_size.map(|size| size as i64),
+                    installed_size: meta.installed_size.map(|size| size as i64),
+                    classification: meta.classification.map(|classification| classification.to_string()),
+                    security_release: meta.security_release.map(|release| release as i64),
                 })
                 .collect::<Vec<_>>();
             let licenses = packages
@@ -321,10 +415,55 @@ impl Database {
                     })
                 })
                 .collect::<Vec<_>>();
+            let replaces = packages
+                .iter()
+                .flat_map(|(package, meta)| {
+                    meta.replaces.iter().map(|replaced| {
+                        (
+                            model::meta_replaces::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                            model::meta_replaces::replaces.eq(replaced.to_string()),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let exports = packages
+                .iter()
+                .flat_map(|(package, meta)| {
+                    meta.exports.iter().map(|export| {
+                        (
+                            model::meta_exports::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                            model::meta_exports::export.eq(export.to_string()),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let config_files = packages
+                .iter()
+                .flat_map(|(package, meta)| {
+                    meta.config_files.iter().map(|path| {
+                        (
+                            model::meta_config_files::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                            model::meta_config_files::path.eq(path.as_str()),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            let search_terms = packages
+                .iter()
+                .flat_map(|(package, meta)| {
+                    index_terms(meta).into_iter().map(|term| {
+                        (
+                            model::meta_search::term.eq(term),
+                            model::meta_search::package.eq(<package::Id as AsRef<str>>::as_ref(package)),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
 
             batch_remove_impl(&ids, tx)?;
 
-            for chunk in entries.chunks(MAX_VARIABLE_NUMBER / 13) {
+            // One variable per column of `meta`
+            for chunk in entries.chunks(MAX_VARIABLE_NUMBER / 18) {
                 diesel::insert_into(model::meta::table).values(chunk).execute(tx)?;
             }
             for chunk in licenses.chunks(MAX_VARIABLE_NUMBER / 2) {
@@ -347,6 +486,26 @@ impl Database {
                     .values(chunk)
                     .execute(tx)?;
             }
+            for chunk in replaces.chunks(MAX_VARIABLE_NUMBER / 2) {
+                diesel::insert_or_ignore_into(model::meta_replaces::table)
+                    .values(chunk)
+                    .execute(tx)?;
+            }
+            for chunk in exports.chunks(MAX_VARIABLE_NUMBER / 2) {
+                diesel::insert_or_ignore_into(model::meta_exports::table)
+                    .values(chunk)
+                    .execute(tx)?;
+            }
+            for chunk in config_files.chunks(MAX_VARIABLE_NUMBER / 2) {
+                diesel::insert_or_ignore_into(model::meta_config_files::table)
+                    .values(chunk)
+                    .execute(tx)?;
+            }
+            for chunk in search_terms.chunks(MAX_VARIABLE_NUMBER / 2) {
+                diesel::insert_or_ignore_into(model::meta_search::table)
+                    .values(chunk)
+                    .execute(tx)?;
+            }
 
             Ok(())
         })
@@ -371,10 +530,90 @@ impl Database {
 fn batch_remove_impl(packages: &[&str], tx: &mut SqliteConnection) -> Result<(), Error> {
     for chunk in packages.chunks(MAX_VARIABLE_NUMBER) {
         diesel::delete(model::meta::table.filter(model::meta::package.eq_any(chunk))).execute(tx)?;
+        // Removed explicitly, so stale terms never outlive their package
+        diesel::delete(model::meta_search::table.filter(model::meta_search::package.eq_any(chunk))).execute(tx)?;
     }
     Ok(())
 }
 
+/// Split `text` into the lowercase alphanumeric terms stored in the search index
+///
+/// Single character terms are too common to be useful and are skipped.
+pub fn search_terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().nth(1).is_some())
+        .map(str::to_lowercase)
+}
+
+/// All unique search terms for the package
+fn index_terms(meta: &Meta) -> BTreeSet<String> {
+    search_terms(meta.name.as_ref())
+        .chain(search_terms(&meta.summary))
+        .chain(search_terms(&meta.description))
+        .collect()
+}
+
+/// Packages whose name, summary or description contains `keyword`, ignoring case
+///
+/// A keyword made up of a single term is looked up in the search index as the prefix
+/// of an indexed term, which is a range over its primary key. Only when that finds no
+/// package is the text scanned for the keyword anywhere within it, as are keywords of
+/// several terms and all keywords while the index hasn't been built yet (the repository
+/// predates it and wasn't refreshed since).
+fn keyword_matches(keyword: &str, conn: &mut SqliteConnection) -> Result<Vec<model::Meta>, Error> {
+    let pattern = format!("%{keyword}%");
+    let term = keyword.to_lowercase();
+
+    // Terms are lowercased in full while `LIKE` only ignores the case of ASCII
+    let single_term = term.is_ascii() && search_terms(&term).eq([term.clone()]);
+
+    let indexed = single_term
+        && diesel::select(diesel::dsl::exists(
+            model::meta_search::table.select(model::meta_search::term),
+        ))
+        .get_result::<bool>(conn)?;
+
+    let packages = if indexed {
+        // Every term starting with `term` sorts between it and it followed by the last code point
+        model::meta_search::table
+            .select(model::meta_search::package)
+            .filter(
+                model::meta_search::term
+                    .ge(&term)
+                    .and(model::meta_search::term.lt(format!("{term}\u{10FFFF}"))),
+            )
+            .distinct()
+            .load::<String>(conn)?
+    } else {
+        vec![]
+    };
+
+    if packages.is_empty() {
+        return Ok(model::meta::table
+            .select(model::Meta::as_select())
+            .filter(
+                model::meta::name
+                    .like(&pattern)
+                    .or(model::meta::summary.like(&pattern))
+                    .or(model::meta::description.like(&pattern)),
+            )
+            .load(conn)?);
+    }
+
+    let mut rows = vec![];
+
+    for chunk in packages.chunks(MAX_VARIABLE_NUMBER) {
+        rows.extend(
+            model::meta::table
+                .select(model::Meta::as_select())
+                .filter(model::meta::package.eq_any(chunk))
+                .load::<model::Meta>(conn)?,
+        );
+    }
+
+    Ok(rows)
+}
+
 mod model {
     use diesel::{
         Selectable,
@@ -383,7 +622,10 @@ mod model {
         prelude::Insertable,
     };
 
-    pub use crate::db::meta::schema::{meta, meta_conflicts, meta_dependencies, meta_licenses, meta_providers};
+    pub use crate::db::meta::schema::{
+        meta, meta_config_files, meta_conflicts, meta_dependencies, meta_exports, meta_licenses, meta_providers,
+        meta_replaces, meta_search,
+    };
     use crate::package;
 
     #[derive(Queryable, Selectable, Identifiable)]
@@ -401,9 +643,14 @@ mod model {
         pub description: String,
         pub source_id: String,
         pub homepage: String,
+        pub bug_tracker: Option<String>,
+        pub maintainer: Option<String>,
         pub uri: Option<String>,
         pub hash: Option<String>,
         pub download_size: Option<i64>,
+        pub installed_size: Option<i64>,
+        pub classification: Option<String>,
+        pub security_release: Option<i64>,
     }
 
     #[derive(Queryable, Selectable, Identifiable)]
@@ -457,6 +704,38 @@ mod model {
         pub conflict: crate::Provider,
     }
 
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_replaces)]
+    #[diesel(primary_key(package, replaces))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Replaces {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub replaces: crate::Provider,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_exports)]
+    #[diesel(primary_key(package, export))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct Export {
+        pub package: String,
+        #[diesel(deserialize_as = String)]
+        pub export: package::Export,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = meta_config_files)]
+    #[diesel(primary_key(package, path))]
+    #[diesel(belongs_to(Meta, foreign_key = package))]
+    #[diesel(belongs_to(PackageId, foreign_key = package))]
+    pub struct ConfigFile {
+        pub package: String,
+        pub path: String,
+    }
+
     #[derive(Insertable)]
     #[diesel(table_name = meta)]
     pub struct NewMeta<'a> {
@@ -470,9 +749,14 @@ mod model {
         pub description: &'a str,
         pub source_id: &'a str,
         pub homepage: &'a str,
+        pub bug_tracker: Option<&'a str>,
+        pub maintainer: Option<&'a str>,
         pub uri: Option<&'a str>,
         pub hash: Option<&'a str>,
         pub download_size: Option<i64>,
+        pub installed_size: Option<i64>,
+        pub classification: Option<String>,
+        pub security_release: Option<i64>,
     }
 }
 
@@ -523,6 +807,94 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn keyword_search_index() {
+        let db = Database::new(":memory:").unwrap();
+
+        let bash_completion = include_bytes!("../../../../test/bash-completion-2.11-1-1-x86_64.stone");
+
+        let mut stone = stone::read_bytes(bash_completion).unwrap();
+
+        let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let meta_payload = payloads.iter().find_map(PayloadKind::meta).unwrap();
+        let meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+
+        let id = package::Id::from("test".to_owned());
+        db.add(id.clone(), meta.clone()).unwrap();
+
+        // Prefixes of any term match in any case through the index
+        assert_eq!(db.query(Some(Filter::Keyword("Completion"))).unwrap().len(), 1);
+        assert_eq!(db.query(Some(Filter::Keyword("compl"))).unwrap().len(), 1);
+        // Other substrings, also spanning terms, are still matched by the fallback scan
+        assert_eq!(db.query(Some(Filter::Keyword("ogramm"))).unwrap().len(), 1);
+        assert_eq!(db.query(Some(Filter::Keyword("h-comp"))).unwrap().len(), 1);
+        assert_eq!(
+            db.query(Some(Filter::Keyword("completion functions"))).unwrap().len(),
+            1
+        );
+        assert!(db.query(Some(Filter::Keyword("completion bash"))).unwrap().is_empty());
+        assert!(db.query(Some(Filter::Keyword("pineapple"))).unwrap().is_empty());
+
+        db.remove(&id).unwrap();
+        assert!(db.query(Some(Filter::Keyword("completion"))).unwrap().is_empty());
+        let terms = db
+            .conn
+            .exec(|conn| model::meta_search::table.count().get_result::<i64>(conn))
+            .unwrap();
+        assert_eq!(terms, 0);
+    }
+
+    #[test]
+    fn provider_packages_for_architecture() {
+        let db = Database::new(":memory:").unwrap();
+
+        let bash_completion = include_bytes!("../../../../test/bash-completion-2.11-1-1-x86_64.stone");
+
+        let mut stone = stone::read_bytes(bash_completion).unwrap();
+
+        let payloads = stone.payloads().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let meta_payload = payloads.iter().find_map(PayloadKind::meta).unwrap();
+        let meta = Meta::from_stone_payload(&meta_payload.body).unwrap();
+
+        db.add(package::Id::from("x86_64".to_owned()), meta.clone()).unwrap();
+        db.add(
+            package::Id::from("aarch64".to_owned()),
+            Meta {
+                architecture: "aarch64".to_owned(),
+                ..meta.clone()
+            },
+        )
+        .unwrap();
+        db.add(
+            package::Id::from("noarch".to_owned()),
+            Meta {
+                architecture: "noarch".to_owned(),
+                ..meta
+            },
+        )
+        .unwrap();
+
+        let provider = Provider {
+            kind: Kind::PackageName,
+            name: "bash-completion".to_owned(),
+        };
+        let ids = |architecture| {
+            let mut ids = db
+                .provider_packages_for(&provider, architecture)
+                .unwrap()
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(db.provider_packages(&provider).unwrap().len(), 3);
+        assert_eq!(ids("x86_64"), ["noarch", "x86_64"]);
+        assert_eq!(ids("aarch64"), ["aarch64", "noarch"]);
+        assert_eq!(ids("riscv64"), ["noarch"]);
+    }
+
     #[test]
     fn test_conflict_is_recognized() {
         let db = Database::new(":memory:").unwrap();
@@ -12,9 +12,14 @@ diesel::table! {
         description -> Text,
         source_id -> Text,
         homepage -> Text,
+        bug_tracker -> Nullable<Text>,
+        maintainer -> Nullable<Text>,
         uri -> Nullable<Text>,
         hash -> Nullable<Text>,
         download_size -> Nullable<BigInt>,
+        installed_size -> Nullable<BigInt>,
+        classification -> Nullable<Text>,
+        security_release -> Nullable<BigInt>,
     }
 }
 
@@ -25,6 +30,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    meta_config_files (package, path) {
+        package -> Text,
+        path -> Text,
+    }
+}
+
 diesel::table! {
     meta_dependencies (package, dependency) {
         package -> Text,
@@ -32,6 +44,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    meta_exports (package, export) {
+        package -> Text,
+        export -> Text,
+    }
+}
+
 diesel::table! {
     meta_licenses (package, license) {
         package -> Text,
@@ -46,9 +65,37 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    meta_replaces (package, replaces) {
+        package -> Text,
+        replaces -> Text,
+    }
+}
+
+diesel::table! {
+    meta_search (term, package) {
+        term -> Text,
+        package -> Text,
+    }
+}
+
+diesel::joinable!(meta_config_files -> meta (package));
 diesel::joinable!(meta_conflicts -> meta (package));
 diesel::joinable!(meta_dependencies -> meta (package));
+diesel::joinable!(meta_exports -> meta (package));
 diesel::joinable!(meta_licenses -> meta (package));
 diesel::joinable!(meta_providers -> meta (package));
+diesel::joinable!(meta_replaces -> meta (package));
+diesel::joinable!(meta_search -> meta (package));
 
-diesel::allow_tables_to_appear_in_same_query!(meta, meta_conflicts, meta_dependencies, meta_licenses, meta_providers);
+diesel::allow_tables_to_appear_in_same_query!(
+    meta,
+    meta_config_files,
+    meta_conflicts,
+    meta_dependencies,
+    meta_exports,
+    meta_licenses,
+    meta_providers,
+    meta_replaces,
+    meta_search,
+);
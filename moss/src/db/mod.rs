@@ -68,6 +68,10 @@ pub enum Error {
     LayoutEntryDecode,
     #[error("invalid timestamp: {0}")]
     InvalidTimestamp(i64),
+    #[error("invalid uri: {0}")]
+    InvalidUri(String),
+    #[error("no state tagged {0}")]
+    UnknownTag(String),
     #[error("diesel")]
     Diesel(#[from] diesel::result::Error),
     #[error("diesel connection")]
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS state_repositories (
+    state_id INTEGER NOT NULL,
+    repository TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    priority BIGINT NOT NULL,
+    index_hash TEXT NULL,
+    PRIMARY KEY(state_id, repository),
+    FOREIGN KEY(state_id) REFERENCES state(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS state_tags (
+    tag TEXT NOT NULL PRIMARY KEY,
+    state_id INTEGER NOT NULL,
+    FOREIGN KEY(state_id) REFERENCES state(id) ON DELETE CASCADE
+);
@@ -2,6 +2,8 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use diesel::{Connection as _, SqliteConnection};
@@ -10,7 +12,8 @@ use itertools::Itertools;
 
 use super::{Connection, Error, MAX_VARIABLE_NUMBER};
 use crate::State;
-use crate::state::{self, Id, Selection};
+use crate::state::{self, Id, Reference, Repository, Selection};
+use crate::{package, repository};
 
 const MIGRATIONS: EmbeddedMigrations = embed_migrations!("src/db/state/migrations");
 
@@ -65,12 +68,36 @@ impl Database {
                     )
                 })
                 .into_group_map();
+            let mut repositories = model::state_repositories::table
+                .select(model::Repository::as_select())
+                .load::<model::Repository>(conn)?
+                .into_iter()
+                .map(|row| Ok((Id::from(row.state_id), decode_repository(row)?)))
+                .collect::<Result<Vec<_>, Error>>()?
+                .into_iter()
+                .into_group_map();
+            let mut tags = model::state_tags::table
+                .select(model::Tag::as_select())
+                .order(model::state_tags::tag)
+                .load::<model::Tag>(conn)?
+                .into_iter()
+                .map(|row| (Id::from(row.state_id), row.tag))
+                .into_group_map();
+            let mut cmdlines = model::state_cmdline::table
+                .select(model::Cmdline::as_select())
+                .load::<model::Cmdline>(conn)?
+                .into_iter()
+                .map(|row| (Id::from(row.state_id), row.cmdline))
+                .collect::<BTreeMap<_, _>>();
 
             Ok(states
                 .into_iter()
                 .map(|state| {
                     let id = state.id.into();
                     let selections = selections.remove(&id).unwrap_or_default();
+                    let repositories = repositories.remove(&id).unwrap_or_default();
+                    let tags = tags.remove(&id).unwrap_or_default();
+                    let cmdline = cmdlines.remove(&id);
                     State {
                         id,
                         summary: state.summary,
@@ -78,6 +105,9 @@ impl Database {
                         selections,
                         created: state.created.0,
                         kind: state.kind,
+                        repositories,
+                        tags,
+                        cmdline,
                     }
                 })
                 .collect())
@@ -102,6 +132,19 @@ impl Database {
                     })
                 })
                 .collect::<Result<_, Error>>()?;
+            let repositories = model::Repository::belonging_to(&state)
+                .select(model::Repository::as_select())
+                .load_iter(conn)?
+                .map(|result| decode_repository(result?))
+                .collect::<Result<_, Error>>()?;
+            let tags = model::Tag::belonging_to(&state)
+                .select(model::state_tags::tag)
+                .order(model::state_tags::tag)
+                .load::<String>(conn)?;
+            let cmdline = model::Cmdline::belonging_to(&state)
+                .select(model::state_cmdline::cmdline)
+                .first::<String>(conn)
+                .optional()?;
 
             Ok(State {
                 id: state.id.into(),
@@ -110,6 +153,9 @@ impl Database {
                 selections,
                 created: state.created.0,
                 kind: state.kind,
+                repositories,
+                tags,
+                cmdline,
             })
         })
     }
@@ -117,6 +163,7 @@ impl Database {
     pub fn add(
         &self,
         selections: &[Selection],
+        repositories: &[Repository],
         summary: Option<&str>,
         description: Option<&str>,
     ) -> Result<State, Error> {
@@ -149,11 +196,123 @@ impl Database {
                         .execute(tx)?;
                 }
 
+                let repositories = repositories
+                    .iter()
+                    .map(|repo| model::NewRepository {
+                        state_id: id,
+                        repository: repo.id.to_string(),
+                        uri: repo.uri.as_str(),
+                        priority: u64::from(repo.priority) as i64,
+                        index_hash: repo.index_hash.as_deref(),
+                    })
+                    .collect::<Vec<_>>();
+
+                for chunk in repositories.chunks(MAX_VARIABLE_NUMBER / 5) {
+                    diesel::insert_into(model::state_repositories::table)
+                        .values(chunk)
+                        .execute(tx)?;
+                }
+
                 Ok(id.into())
             })
             .and_then(|id| self.get(id))
     }
 
+    /// Replace the summary and / or description of state `id`
+    ///
+    /// Fields given as `None` are left as is, `Some(None)` clears them.
+    pub fn edit(
+        &self,
+        id: Id,
+        summary: Option<Option<&str>>,
+        description: Option<Option<&str>>,
+    ) -> Result<State, Error> {
+        if summary.is_some() || description.is_some() {
+            self.conn.exec(|conn| {
+                diesel::update(model::state::table.find(i32::from(id)))
+                    .set(model::Annotation { summary, description })
+                    .execute(conn)?;
+
+                Ok::<_, Error>(())
+            })?;
+        }
+
+        self.get(id)
+    }
+
+    /// Resolve a [`Reference`] to the [`Id`] of the state
+    pub fn resolve(&self, reference: &Reference) -> Result<Id, Error> {
+        match reference {
+            Reference::Id(id) => Ok(*id),
+            Reference::Tag(tag) => self.conn.exec(|conn| {
+                model::state_tags::table
+                    .select(model::state_tags::state_id)
+                    .find(tag)
+                    .first::<i32>(conn)
+                    .optional()?
+                    .map(Id::from)
+                    .ok_or_else(|| Error::UnknownTag(tag.clone()))
+            }),
+        }
+    }
+
+    /// Name state `id` with `tag`, moving the tag if another state has it
+    pub fn tag(&self, id: Id, tag: &str) -> Result<(), Error> {
+        self.conn.exec(|conn| {
+            diesel::replace_into(model::state_tags::table)
+                .values(model::NewTag {
+                    tag,
+                    state_id: id.into(),
+                })
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+
+    /// Remove `tag`, returning `false` if no state has it
+    pub fn untag(&self, tag: &str) -> Result<bool, Error> {
+        self.conn.exec(|conn| {
+            let removed = diesel::delete(model::state_tags::table.find(tag)).execute(conn)?;
+
+            Ok(removed > 0)
+        })
+    }
+
+    /// Replace the kernel command line fragment of state `id`, `None` clears it
+    pub fn set_cmdline(&self, id: Id, cmdline: Option<&str>) -> Result<(), Error> {
+        self.conn.exec(|conn| {
+            match cmdline {
+                Some(cmdline) => {
+                    diesel::replace_into(model::state_cmdline::table)
+                        .values(model::NewCmdline {
+                            state_id: id.into(),
+                            cmdline,
+                        })
+                        .execute(conn)?;
+                }
+                None => {
+                    diesel::delete(model::state_cmdline::table.find(i32::from(id))).execute(conn)?;
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Replace the reason `package` was selected in state `id`, `None` clears it
+    ///
+    /// Returns `false` if the package isn't selected in the state.
+    pub fn set_reason(&self, id: Id, package: &package::Id, reason: Option<&str>) -> Result<bool, Error> {
+        self.conn.exec(|conn| {
+            let updated = diesel::update(model::state_selections::table.find((i32::from(id), package.to_string())))
+                .set(model::state_selections::reason.eq(reason))
+                .execute(conn)?;
+
+            Ok(updated > 0)
+        })
+    }
+
     pub fn remove(&self, state: &Id) -> Result<(), Error> {
         self.batch_remove(Some(state))
     }
@@ -165,6 +324,15 @@ impl Database {
             for chunk in states.chunks(MAX_VARIABLE_NUMBER) {
                 // Cascading wipes other tables
                 diesel::delete(model::state::table.filter(model::state::id.eq_any(chunk))).execute(tx)?;
+                // Tags are removed explicitly, so they don't keep naming a removed state
+                diesel::delete(model::state_tags::table.filter(model::state_tags::state_id.eq_any(chunk)))
+                    .execute(tx)?;
+                diesel::delete(model::state_cmdline::table.filter(model::state_cmdline::state_id.eq_any(chunk)))
+                    .execute(tx)?;
+                diesel::delete(
+                    model::state_repositories::table.filter(model::state_repositories::state_id.eq_any(chunk)),
+                )
+                .execute(tx)?;
             }
 
             Ok(())
@@ -172,18 +340,27 @@ impl Database {
     }
 }
 
+fn decode_repository(row: model::Repository) -> Result<Repository, Error> {
+    Ok(Repository {
+        id: repository::Id::new(&row.repository),
+        uri: row.uri.parse().map_err(|_| Error::InvalidUri(row.uri.clone()))?,
+        priority: repository::Priority::new(row.priority as u64),
+        index_hash: row.index_hash,
+    })
+}
+
 mod model {
     use diesel::{
         Selectable,
         associations::{Associations, Identifiable},
         deserialize::Queryable,
-        prelude::Insertable,
+        prelude::{AsChangeset, Insertable},
         sqlite::Sqlite,
     };
 
     use crate::{db::Timestamp, package, state::Kind};
 
-    pub use super::schema::{state, state_selections};
+    pub use super::schema::{state, state_cmdline, state_repositories, state_selections, state_tags};
 
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = state)]
@@ -210,6 +387,43 @@ mod model {
         pub reason: Option<String>,
     }
 
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = state_repositories)]
+    #[diesel(primary_key(state_id, repository))]
+    #[diesel(belongs_to(State))]
+    pub struct Repository {
+        pub state_id: i32,
+        pub repository: String,
+        pub uri: String,
+        pub priority: i64,
+        pub index_hash: Option<String>,
+    }
+
+    #[derive(AsChangeset)]
+    #[diesel(table_name = state)]
+    pub struct Annotation<'a> {
+        pub summary: Option<Option<&'a str>>,
+        pub description: Option<Option<&'a str>>,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = state_tags)]
+    #[diesel(primary_key(tag))]
+    #[diesel(belongs_to(State))]
+    pub struct Tag {
+        pub tag: String,
+        pub state_id: i32,
+    }
+
+    #[derive(Queryable, Selectable, Identifiable, Associations)]
+    #[diesel(table_name = state_cmdline)]
+    #[diesel(primary_key(state_id))]
+    #[diesel(belongs_to(State))]
+    pub struct Cmdline {
+        pub state_id: i32,
+        pub cmdline: String,
+    }
+
     #[derive(Queryable, Selectable, Identifiable)]
     #[diesel(table_name = state)]
     #[diesel(check_for_backend(Sqlite))]
@@ -236,6 +450,30 @@ mod model {
         pub explicit: bool,
         pub reason: Option<&'a str>,
     }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = state_repositories)]
+    pub struct NewRepository<'a> {
+        pub state_id: i32,
+        pub repository: String,
+        pub uri: &'a str,
+        pub priority: i64,
+        pub index_hash: Option<&'a str>,
+    }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = state_tags)]
+    pub struct NewTag<'a> {
+        pub tag: &'a str,
+        pub state_id: i32,
+    }
+
+    #[derive(Insertable)]
+    #[diesel(table_name = state_cmdline)]
+    pub struct NewCmdline<'a> {
+        pub state_id: i32,
+        pub cmdline: &'a str,
+    }
 }
 
 #[cfg(test)]
@@ -255,7 +493,16 @@ mod test {
             Selection::explicit(package::Id::from("pkg c".to_owned())),
         ];
 
-        let state = database.add(&selections, Some("test"), Some("test")).unwrap();
+        let repositories = vec![Repository {
+            id: repository::Id::new("volatile"),
+            uri: "https://test.dev/stone.index".parse().unwrap(),
+            priority: repository::Priority::new(10),
+            index_hash: Some("abc".to_owned()),
+        }];
+
+        let state = database
+            .add(&selections, &repositories, Some("test"), Some("test"))
+            .unwrap();
 
         // First record
         assert_eq!(i32::from(state.id), 1);
@@ -269,5 +516,80 @@ mod test {
         assert_eq!(state.description.as_deref(), Some("test"));
 
         assert_eq!(state.selections, selections);
+        assert_eq!(state.repositories, repositories);
+        assert_eq!(database.all().unwrap()[0].repositories, repositories);
+
+        database.remove(&state.id).unwrap();
+        let remaining = database
+            .conn
+            .exec(|conn| model::state_repositories::table.count().get_result::<i64>(conn))
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn edit() {
+        let database = Database::new(":memory:").unwrap();
+
+        let state = database.add(&[], &[], Some("Sync"), None).unwrap();
+
+        let state = database
+            .edit(state.id, None, Some(Some("pre-kernel-upgrade checkpoint")))
+            .unwrap();
+        assert_eq!(state.summary.as_deref(), Some("Sync"));
+        assert_eq!(state.description.as_deref(), Some("pre-kernel-upgrade checkpoint"));
+
+        let state = database.edit(state.id, Some(None), None).unwrap();
+        assert_eq!(state.summary, None);
+        assert_eq!(state.description.as_deref(), Some("pre-kernel-upgrade checkpoint"));
+
+        assert!(database.edit(Id::from(42), Some(Some("missing")), None).is_err());
+    }
+
+    #[test]
+    fn tags() {
+        let database = Database::new(":memory:").unwrap();
+
+        let first = database.add(&[], &[], None, None).unwrap();
+        let second = database.add(&[], &[], None, None).unwrap();
+
+        database.tag(first.id, "known-good").unwrap();
+        database.tag(first.id, "base").unwrap();
+        assert_eq!(database.get(first.id).unwrap().tags, ["base", "known-good"]);
+        assert_eq!(
+            database.resolve(&Reference::Tag("known-good".to_owned())).unwrap(),
+            first.id
+        );
+
+        // Tagging another state moves the tag
+        database.tag(second.id, "known-good").unwrap();
+        assert_eq!(database.get(first.id).unwrap().tags, ["base"]);
+        assert_eq!(database.all().unwrap()[1].tags, ["known-good"]);
+
+        assert!(database.untag("base").unwrap());
+        assert!(!database.untag("base").unwrap());
+        assert!(matches!(
+            database.resolve(&Reference::Tag("base".to_owned())),
+            Err(Error::UnknownTag(_))
+        ));
+
+        database.remove(&second.id).unwrap();
+        assert!(database.resolve(&Reference::Tag("known-good".to_owned())).is_err());
+    }
+
+    #[test]
+    fn cmdline() {
+        let database = Database::new(":memory:").unwrap();
+
+        let state = database.add(&[], &[], None, None).unwrap();
+        assert_eq!(state.cmdline, None);
+
+        database.set_cmdline(state.id, Some("quiet splash")).unwrap();
+        assert_eq!(database.get(state.id).unwrap().cmdline.as_deref(), Some("quiet splash"));
+        database.set_cmdline(state.id, Some("quiet")).unwrap();
+        assert_eq!(database.all().unwrap()[0].cmdline.as_deref(), Some("quiet"));
+
+        database.set_cmdline(state.id, None).unwrap();
+        assert_eq!(database.get(state.id).unwrap().cmdline, None);
     }
 }
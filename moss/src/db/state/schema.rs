@@ -11,6 +11,13 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    state_cmdline (state_id) {
+        state_id -> Integer,
+        cmdline -> Text,
+    }
+}
+
 diesel::table! {
     state_selections (state_id, package_id) {
         state_id -> Integer,
@@ -20,6 +27,26 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    state_repositories (state_id, repository) {
+        state_id -> Integer,
+        repository -> Text,
+        uri -> Text,
+        priority -> BigInt,
+        index_hash -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    state_tags (tag) {
+        tag -> Text,
+        state_id -> Integer,
+    }
+}
+
+diesel::joinable!(state_cmdline -> state (state_id));
+diesel::joinable!(state_repositories -> state (state_id));
 diesel::joinable!(state_selections -> state (state_id));
+diesel::joinable!(state_tags -> state (state_id));
 
-diesel::allow_tables_to_appear_in_same_query!(state, state_selections);
+diesel::allow_tables_to_appear_in_same_query!(state, state_cmdline, state_repositories, state_selections, state_tags);
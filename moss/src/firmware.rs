@@ -0,0 +1,262 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Select firmware & driver packages by the hardware they support
+//!
+//! Hardware specific packages declare the devices they support as
+//! [`Kind::Modalias`](crate::dependency::Kind::Modalias) providers, i.e.
+//! `modalias(pci:v00008086d*sv*sd*bc02sc80i*)`. A [`Profile`] lists the
+//! modaliases of a machine, detected from sysfs or written by hand, and
+//! [`select`] trims a system-model down to the hardware specific packages
+//! matching it.
+
+use std::{collections::BTreeSet, io, path::Path};
+
+use fs_err as fs;
+
+use crate::{Package, Registry, SystemModel, dependency::Kind, package::Flags, system_model};
+
+/// The hardware of a machine, as a set of modaliases
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    ids: BTreeSet<String>,
+}
+
+impl Profile {
+    /// Detect the hardware of the running machine from `sysfs`
+    pub fn detect(sysfs: &Path) -> Self {
+        let mut ids = BTreeSet::new();
+
+        for bus in fs::read_dir(sysfs.join("bus")).into_iter().flatten().flatten() {
+            for device in fs::read_dir(bus.path().join("devices")).into_iter().flatten().flatten() {
+                if let Ok(modalias) = fs::read_to_string(device.path().join("modalias")) {
+                    ids.insert(modalias.trim().to_owned());
+                }
+            }
+        }
+
+        // Boards are only described by the device-tree root, which isn't a device
+        if let Ok(compatible) = fs::read_to_string(sysfs.join("firmware/devicetree/base/compatible")) {
+            ids.extend(compatible.split('\0').filter(|c| !c.is_empty()).map(of_modalias));
+        }
+
+        ids.retain(|id| !id.is_empty());
+
+        Self { ids }
+    }
+
+    /// Load a hand written profile from `path`, see [`Profile::parse`]
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parse a profile with one device per line, either:
+    ///
+    /// - a PCI id, i.e. `8086:a7a0`
+    /// - a modalias, i.e. `usb:v0BDAp8179d0000dc00dsc00dp00icFFiscFFipFFin00`
+    /// - a device-tree compatible string, i.e. `raspberrypi,4-model-b`
+    ///
+    /// Empty lines & lines starting with `#` are ignored
+    pub fn parse(content: &str) -> Self {
+        let ids = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                if let Some((vendor, device)) = pci_id(line) {
+                    format!("pci:v0000{vendor}d0000{device}sv*sd*bc*sc*i*")
+                } else if line.contains(':') {
+                    line.to_owned()
+                } else {
+                    of_modalias(line)
+                }
+            })
+            .collect();
+
+        Self { ids }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether `package` supports any of the hardware in this profile
+    ///
+    /// Returns `None` if `package` isn't hardware specific
+    pub fn supports(&self, package: &Package) -> Option<bool> {
+        let mut patterns = package
+            .meta
+            .providers
+            .iter()
+            .filter(|provider| provider.kind == Kind::Modalias)
+            .peekable();
+
+        patterns.peek()?;
+
+        Some(patterns.any(|pattern| self.ids.iter().any(|id| overlaps(&pattern.name, id))))
+    }
+
+    /// The first device of this profile supported by `package`, if any
+    pub fn supported_device(&self, package: &Package) -> Option<&str> {
+        package
+            .meta
+            .providers
+            .iter()
+            .filter(|provider| provider.kind == Kind::Modalias)
+            .find_map(|pattern| self.ids.iter().find(|id| overlaps(&pattern.name, id)))
+            .map(String::as_str)
+    }
+}
+
+/// A system-model trimmed down to a [`Profile`]
+#[derive(Debug)]
+pub struct Selection {
+    pub model: SystemModel,
+    /// Hardware specific packages added for the profile
+    pub added: Vec<String>,
+    /// Hardware specific packages removed as the profile doesn't need them
+    pub removed: Vec<String>,
+}
+
+/// Trim the hardware specific packages of `model` down to those supporting `profile`
+///
+/// Hardware specific packages listed by the model but not supporting the profile are
+/// removed, those available from `registry` & supporting it are added.
+pub fn select(
+    registry: &Registry,
+    profile: &Profile,
+    model: SystemModel,
+) -> Result<Selection, system_model::UpdateError> {
+    let unsupported = model
+        .packages
+        .iter()
+        .filter(|provider| {
+            registry
+                .by_provider(provider, Flags::new().with_available())
+                .next()
+                .is_some_and(|package| profile.supports(&package) == Some(false))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut added = registry
+        .list(Flags::new().with_available())
+        .filter(|package| profile.supports(package) == Some(true))
+        .filter(|package| !package.meta.providers.iter().any(|p| model.packages.contains(p)))
+        .map(|package| package.meta.name.to_string())
+        .collect::<Vec<_>>();
+    added.sort();
+    added.dedup();
+
+    let removed = unsupported.iter().map(|provider| provider.to_name()).collect();
+    let model = model
+        .remove_packages(&unsupported)?
+        .add_packages(added.iter().map(String::as_str))?;
+
+    Ok(Selection { model, added, removed })
+}
+
+/// The `vendor` & `device` of a `vvvv:dddd` PCI id, in modalias case
+fn pci_id(line: &str) -> Option<(String, String)> {
+    let (vendor, device) = line.split_once(':')?;
+    let is_hex = |s: &str| s.len() == 4 && s.chars().all(|c| c.is_ascii_hexdigit());
+
+    (is_hex(vendor) && is_hex(device)).then(|| (vendor.to_ascii_uppercase(), device.to_ascii_uppercase()))
+}
+
+/// Modalias of a device-tree `compatible` string
+fn of_modalias(compatible: &str) -> String {
+    format!("of:N*T*C{compatible}*")
+}
+
+/// Whether the globs `a` & `b` match any string in common
+///
+/// Modaliases of a [`Profile`] may be globs themselves, as written by hand
+fn overlaps(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+
+    // reachable[j] = whether a[..i] & b[..j] can match a common prefix
+    let mut reachable = vec![false; b.len() + 1];
+    reachable[0] = true;
+
+    for i in 0..=a.len() {
+        let mut next = vec![false; b.len() + 1];
+
+        for j in 0..=b.len() {
+            if !reachable[j] {
+                continue;
+            }
+
+            match (a.get(i), b.get(j)) {
+                (Some(b'*'), _) => {
+                    // Star of `a` matches nothing more, or swallows the next of `b`
+                    next[j] = true;
+                    if j < b.len() {
+                        reachable[j + 1] = true;
+                    }
+                }
+                (_, Some(b'*')) => {
+                    reachable[j + 1] = true;
+                    if i < a.len() {
+                        next[j] = true;
+                    }
+                }
+                (Some(x), Some(y)) if x == y || *x == b'?' || *y == b'?' => next[j + 1] = true,
+                _ => {}
+            }
+        }
+
+        if i == a.len() {
+            return reachable[b.len()];
+        }
+        reachable = next;
+    }
+
+    unreachable!()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overlaps() {
+        let pattern = "pci:v00008086d0000A7A0sv*sd*bc*sc*i*";
+
+        assert!(overlaps(
+            pattern,
+            "pci:v00008086d0000A7A0sv00001028sd00000C0Bbc03sc00i00"
+        ));
+        assert!(!overlaps(
+            pattern,
+            "pci:v00008086d0000A7A1sv00001028sd00000C0Bbc03sc00i00"
+        ));
+        assert!(overlaps(
+            "pci:v00008086d*sv*sd*bc02sc80i*",
+            "pci:v00008086d0000A7A0sv*sd*bc*sc*i*"
+        ));
+        assert!(overlaps("of:N*T*Cbrcm,bcm2711*", &of_modalias("brcm,bcm2711")));
+        assert!(!overlaps("usb:v0BDA*", "pci:v0BDA*"));
+        assert!(overlaps("*", ""));
+        assert!(!overlaps("a?", "a"));
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = Profile::parse("# laptop\n8086:a7a0\n\nusb:v0BDAp8179d0000dc00\nraspberrypi,4-model-b\n");
+
+        assert_eq!(
+            profile.ids.into_iter().collect::<Vec<_>>(),
+            [
+                "of:N*T*Craspberrypi,4-model-b*",
+                "pci:v00008086d0000A7A0sv*sd*bc*sc*i*",
+                "usb:v0BDAp8179d0000dc00"
+            ]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! apt repositories, described by Debian style `Packages` indices
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+
+use crate::{
+    Dependency, Provider,
+    package::{Meta, Name},
+};
+
+use super::{Error, read_compressed};
+
+/// Preference of the available `Packages` index encodings, best first
+const INDICES: &[&str] = &["Packages", "Packages.gz", "Packages.zst", "Packages.xz"];
+
+pub fn import(dir: &Path) -> Result<Vec<Meta>, Error> {
+    let indices = find_indices(dir)?;
+
+    if indices.is_empty() {
+        return Err(Error::MissingMetadata("Packages", dir.to_owned()));
+    }
+
+    let mut packages = vec![];
+    for index in indices {
+        packages.extend(parse_packages(&read_compressed(&index)?)?);
+    }
+
+    Ok(packages)
+}
+
+/// Find the best `Packages` index in `dir` and any of its subdirectories, such as `dists/*/main/binary-*/`
+fn find_indices(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut indices = vec![];
+
+    if let Some(index) = INDICES.iter().map(|name| dir.join(name)).find(|path| path.is_file()) {
+        indices.push(index);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            indices.extend(find_indices(&entry.path())?);
+        }
+    }
+
+    Ok(indices)
+}
+
+/// Parse all stanzas of a `Packages` index
+fn parse_packages(contents: &str) -> Result<Vec<Meta>, Error> {
+    contents
+        .split("\n\n")
+        .map(parse_stanza)
+        .filter(|fields| !fields.is_empty())
+        .map(|fields| to_meta(&fields))
+        .collect()
+}
+
+/// Parse the fields of a single control stanza, joining continuation lines
+fn parse_stanza(stanza: &str) -> BTreeMap<&str, String> {
+    let mut fields = BTreeMap::<&str, String>::new();
+    let mut current = None;
+
+    for line in stanza.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = current.and_then(|key| fields.get_mut(key)) {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            fields.insert(key, value.trim().to_owned());
+            current = Some(key);
+        }
+    }
+
+    fields
+}
+
+fn to_meta(fields: &BTreeMap<&str, String>) -> Result<Meta, Error> {
+    let field = |name: &str| fields.get(name).map(String::as_str).unwrap_or_default();
+
+    let name = fields.get("Package").ok_or(Error::MissingField("Package"))?;
+    let version = fields.get("Version").ok_or(Error::MissingField("Version"))?;
+
+    // `Description` holds the summary, followed by the long description where `.` separates paragraphs
+    let (summary, description) = field("Description")
+        .split_once('\n')
+        .unwrap_or((field("Description"), ""));
+    let description = description
+        .lines()
+        .map(|line| if line == "." { "" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut providers = relations(field("Provides"));
+    providers.insert(Provider::package_name(name));
+
+    let dependencies = relations(field("Pre-Depends"))
+        .into_iter()
+        .chain(relations(field("Depends")))
+        .map(|provider| Dependency {
+            kind: provider.kind,
+            name: provider.name,
+        })
+        .collect();
+
+    Ok(Meta {
+        name: Name::from(name.clone()),
+        version_identifier: version.clone(),
+        source_release: 1,
+        build_release: 1,
+        architecture: field("Architecture").to_owned(),
+        summary: summary.to_owned(),
+        description,
+        // `Source` may carry its own version, i.e. `glibc (2.36-9)`
+        source_id: field("Source").split_whitespace().next().unwrap_or(name).to_owned(),
+        homepage: field("Homepage").to_owned(),
+        bug_tracker: Some(field("Bugs"))
+            .filter(|bugs| !bugs.is_empty())
+            .map(ToOwned::to_owned),
+        maintainer: Some(field("Maintainer"))
+            .filter(|maintainer| !maintainer.is_empty())
+            .map(ToOwned::to_owned),
+        licenses: vec![],
+        dependencies,
+        providers,
+        conflicts: relations(field("Conflicts"))
+            .into_iter()
+            .chain(relations(field("Breaks")))
+            .collect(),
+        replaces: relations(field("Replaces")),
+        exports: Default::default(),
+        config_files: Default::default(),
+        uri: None,
+        hash: None,
+        download_size: None,
+        installed_size: None,
+        classification: None,
+        security_release: None,
+    })
+}
+
+/// Parse a relationship field, i.e. `libc6 (>= 2.34), default-mta | mail-transport-agent`
+///
+/// Only the first of any alternatives is kept as moss has no notion of them.
+fn relations(value: &str) -> BTreeSet<Provider> {
+    value
+        .split(',')
+        .filter_map(|relation| relation.split('|').next())
+        .filter_map(|alternative| {
+            let name = alternative.split(['(', '[', '<']).next()?.trim();
+            // Strip architecture qualifiers such as `python3:any`
+            let name = name.split(':').next()?;
+            (!name.is_empty()).then(|| Provider::package_name(name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_packages() {
+        let contents = "\
+Package: zlib1g
+Source: zlib (1:1.2.13.dfsg-1)
+Version: 1:1.2.13.dfsg-1
+Architecture: amd64
+Pre-Depends: libc6 (>= 2.14)
+Depends: debconf | debconf-2.0, python3:any
+Provides: libz1
+Breaks: libxml2 (<< 2.7.6.dfsg-2)
+Homepage: http://zlib.net/
+Description: compression library - runtime
+ zlib is a library implementing the deflate compression method.
+ .
+ This package includes the shared library.
+
+Package: zlib1g-dev
+Version: 1:1.2.13.dfsg-1
+Architecture: amd64
+Depends: zlib1g (= 1:1.2.13.dfsg-1)
+Description: compression library - development
+";
+
+        let packages = parse_packages(contents).unwrap();
+        assert_eq!(packages.len(), 2);
+
+        let meta = &packages[0];
+        assert_eq!(meta.name.as_ref(), "zlib1g");
+        assert_eq!(meta.version_identifier, "1:1.2.13.dfsg-1");
+        assert_eq!(meta.source_id, "zlib");
+        assert_eq!(meta.summary, "compression library - runtime");
+        assert_eq!(
+            meta.description,
+            "zlib is a library implementing the deflate compression method.\n\nThis package includes the shared library."
+        );
+        assert_eq!(
+            meta.dependencies
+                .iter()
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from([
+                "name(libc6)".to_owned(),
+                "name(debconf)".to_owned(),
+                "name(python3)".to_owned()
+            ])
+        );
+        assert_eq!(
+            meta.providers,
+            BTreeSet::from([Provider::package_name("libz1"), Provider::package_name("zlib1g")])
+        );
+        assert_eq!(meta.conflicts, BTreeSet::from([Provider::package_name("libxml2")]));
+
+        assert_eq!(packages[1].source_id, "zlib1g-dev");
+        assert!(packages[1].description.is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Import package metadata from repositories of other distributions
+//!
+//! Foreign metadata is converted into moss [`Meta`] and stored in a meta database
+//! per imported repository. These are exposed as read-only registry plugins so
+//! search, info and provider lookups work, while nothing can be installed from them
+//! as there are no stones behind the metadata.
+
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use fs_err::{self as fs, File};
+use strum::{Display, EnumString};
+use thiserror::Error;
+
+use crate::{Installation, db, package::Meta};
+
+mod apt;
+mod rpm;
+
+/// Metadata format of a foreign repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Format {
+    /// `repodata/repomd.xml` & primary metadata used by dnf, zypper, etc.
+    RpmMd,
+    /// Debian style `Packages` indices
+    Apt,
+}
+
+/// Convert the foreign repository metadata found in `dir`
+pub fn import(format: Format, dir: &Path) -> Result<Vec<Meta>, Error> {
+    match format {
+        Format::RpmMd => rpm::import(dir),
+        Format::Apt => apt::import(dir),
+    }
+}
+
+/// Ensure `name` can name a foreign repository
+///
+/// Names become file names within the moss databases, so they're restricted to ASCII
+/// letters, digits, `.`, `_` & `-` and can't be a relative path component.
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    let is_valid = !matches!(name, "" | "." | "..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName(name.to_owned()))
+    }
+}
+
+/// Store the imported packages as foreign repository `name`, replacing any previous import
+pub fn save(installation: &Installation, name: &str, packages: Vec<Meta>) -> Result<(), Error> {
+    let dir = installation.db_path("foreign");
+    fs::create_dir_all(&dir)?;
+
+    let db = db::meta::Database::new(database_path(&dir, name).to_str().unwrap_or_default())?;
+    db.wipe()?;
+    db.batch_add(packages.into_iter().map(|meta| (meta.id().into(), meta)).collect())?;
+
+    Ok(())
+}
+
+/// Open the databases of all imported foreign repositories
+pub fn open_all(installation: &Installation) -> Result<Vec<(String, db::meta::Database)>, Error> {
+    let dir = installation.db_path("foreign");
+
+    let Ok(read_dir) = fs::read_dir(&dir) else {
+        return Ok(vec![]);
+    };
+
+    let mut databases = vec![];
+
+    for path in read_dir.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        let Some(name) = path.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+            continue;
+        };
+
+        let db = db::meta::Database::new(path.to_str().unwrap_or_default())?;
+        databases.push((name, db));
+    }
+
+    databases.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(databases)
+}
+
+fn database_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.db"))
+}
+
+/// Open `path` for reading, transparently decompressing gzip & zstd files
+fn open_compressed(path: &Path) -> Result<Box<dyn Read>, Error> {
+    let file = File::open(path)?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Ok(Box::new(flate2::read::MultiGzDecoder::new(file))),
+        Some("zst") => Ok(Box::new(zstd::stream::read::Decoder::new(file)?)),
+        Some("xz" | "bz2" | "lz4") => Err(Error::UnsupportedCompression(path.to_owned())),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+fn read_compressed(path: &Path) -> Result<String, Error> {
+    let mut contents = String::new();
+    open_compressed(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+
+    #[error("db")]
+    DB(#[from] db::Error),
+
+    #[error("parse xml")]
+    Xml(#[from] roxmltree::Error),
+
+    #[error("no {0} metadata found in {1:?}")]
+    MissingMetadata(&'static str, PathBuf),
+
+    #[error("unsupported compression for {0:?}, decompress it first")]
+    UnsupportedCompression(PathBuf),
+
+    #[error("package entry is missing {0}")]
+    MissingField(&'static str),
+
+    #[error("invalid foreign repository name {0:?}, expected ASCII letters, digits, '.', '_' or '-'")]
+    InvalidName(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("fedora-42_updates.x86").is_ok());
+        for name in ["", ".", "..", "../etc", "a/b", "name with space", "ünicode"] {
+            assert!(matches!(validate_name(name), Err(Error::InvalidName(_))), "{name}");
+        }
+    }
+}
@@ -0,0 +1,243 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! rpm-md repositories, as described by `repodata/repomd.xml`
+
+use std::{collections::BTreeSet, path::Path};
+
+use roxmltree::{Document, Node};
+
+use crate::{
+    Dependency, Provider,
+    dependency::Kind,
+    package::{Meta, Name},
+};
+
+use super::{Error, read_compressed};
+
+pub fn import(dir: &Path) -> Result<Vec<Meta>, Error> {
+    let repomd_path = dir.join("repodata").join("repomd.xml");
+    let repomd = read_compressed(&repomd_path)?;
+    let repomd = Document::parse(&repomd)?;
+
+    let primary = repomd
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name("data") && node.attribute("type") == Some("primary"))
+        .and_then(|data| child(data, "location"))
+        .and_then(|location| location.attribute("href"))
+        .ok_or_else(|| Error::MissingMetadata("primary", repomd_path.clone()))?;
+
+    let primary = read_compressed(&dir.join(primary))?;
+
+    parse_primary(&primary)
+}
+
+/// Parse the packages of the primary metadata
+fn parse_primary(xml: &str) -> Result<Vec<Meta>, Error> {
+    let document = Document::parse(xml)?;
+
+    document
+        .root_element()
+        .children()
+        .filter(|node| node.has_tag_name("package") && node.attribute("type") == Some("rpm"))
+        .filter(|node| child_text(*node, "arch") != Some("src"))
+        .map(parse_package)
+        .collect()
+}
+
+fn parse_package(package: Node<'_, '_>) -> Result<Meta, Error> {
+    let name = child_text(package, "name").ok_or(Error::MissingField("name"))?;
+    let architecture = child_text(package, "arch").unwrap_or("noarch").to_owned();
+    let version = child(package, "version").ok_or(Error::MissingField("version"))?;
+    let format = child(package, "format");
+
+    // Releases such as `3.fc40` only have a meaningful leading number
+    let release = version.attribute("rel").unwrap_or_default();
+    let source_release = release
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(1);
+
+    let capabilities = |section: &str| -> BTreeSet<Provider> {
+        format
+            .and_then(|format| child(format, section))
+            .into_iter()
+            .flat_map(|section| section.children().filter(|node| node.has_tag_name("entry")))
+            .filter_map(|entry| entry.attribute("name"))
+            .filter_map(|name| capability(name, &architecture))
+            .collect()
+    };
+
+    let mut providers = capabilities("provides");
+    providers.insert(Provider::package_name(name));
+    // Executables shipped by the package
+    providers.extend(
+        format
+            .into_iter()
+            .flat_map(|format| format.children().filter(|node| node.has_tag_name("file")))
+            .filter_map(|file| file.text())
+            .filter_map(|path| capability(path, &architecture)),
+    );
+
+    let dependencies = capabilities("requires")
+        .into_iter()
+        .filter(|provider| !providers.contains(provider))
+        .map(|provider| Dependency {
+            kind: provider.kind,
+            name: provider.name,
+        })
+        .collect();
+
+    Ok(Meta {
+        name: Name::from(name.to_owned()),
+        version_identifier: version.attribute("ver").unwrap_or_default().to_owned(),
+        source_release,
+        build_release: 1,
+        summary: child_text(package, "summary").unwrap_or_default().trim().to_owned(),
+        description: child_text(package, "description").unwrap_or_default().trim().to_owned(),
+        source_id: format
+            .and_then(|format| child_text(format, "sourcerpm"))
+            .and_then(|srpm| srpm.strip_suffix(".src.rpm"))
+            .and_then(|srpm| srpm.rsplitn(3, '-').nth(2))
+            .unwrap_or(name)
+            .to_owned(),
+        homepage: child_text(package, "url").unwrap_or_default().to_owned(),
+        bug_tracker: None,
+        maintainer: child_text(package, "packager").map(ToOwned::to_owned),
+        licenses: format
+            .and_then(|format| child_text(format, "license"))
+            .map(|license| vec![license.to_owned()])
+            .unwrap_or_default(),
+        dependencies,
+        providers,
+        conflicts: capabilities("conflicts"),
+        replaces: capabilities("obsoletes"),
+        exports: Default::default(),
+        config_files: Default::default(),
+        architecture,
+        uri: None,
+        hash: None,
+        download_size: None,
+        installed_size: None,
+        classification: None,
+        security_release: None,
+    })
+}
+
+/// Map an rpm capability (or file path) to the closest moss provider
+fn capability(name: &str, architecture: &str) -> Option<Provider> {
+    let provider = |kind, name: &str| {
+        Some(Provider {
+            kind,
+            name: name.to_owned(),
+        })
+    };
+
+    if let Some(path) = name.strip_prefix('/') {
+        let (dir, file) = path.rsplit_once('/')?;
+        return match dir {
+            "bin" | "usr/bin" => provider(Kind::Binary, file),
+            "sbin" | "usr/sbin" => provider(Kind::SystemBinary, file),
+            _ => None,
+        };
+    }
+
+    // 64-bit sonames, i.e. `libz.so.1()(64bit)`
+    if let Some(soname) = name.strip_suffix("()(64bit)") {
+        return provider(Kind::SharedLibrary, &format!("{soname}({architecture})"));
+    }
+
+    let Some((kind, target)) = name.strip_suffix(')').and_then(|name| name.split_once('(')) else {
+        return provider(Kind::PackageName, name);
+    };
+
+    match kind {
+        "pkgconfig" => provider(Kind::PkgConfig, target),
+        "cmake" => provider(Kind::CMake, target),
+        "python3dist" => provider(Kind::Python, target),
+        // Symbol versions, rpmlib features, bundled libraries etc. have no equivalent
+        _ => None,
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.tag_name().name() == name)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name).and_then(|child| child.text())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_primary() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="2">
+<package type="rpm">
+  <name>zlib-ng-compat</name>
+  <arch>x86_64</arch>
+  <version epoch="0" ver="2.1.7" rel="3.fc41"/>
+  <summary>Zlib implementation provided by zlib-ng</summary>
+  <description>zlib-ng is a zlib replacement.</description>
+  <url>https://github.com/zlib-ng/zlib-ng</url>
+  <format>
+    <rpm:license>Zlib</rpm:license>
+    <rpm:sourcerpm>zlib-ng-2.1.7-3.fc41.src.rpm</rpm:sourcerpm>
+    <rpm:provides>
+      <rpm:entry name="libz.so.1()(64bit)"/>
+      <rpm:entry name="zlib" flags="EQ" epoch="0" ver="1.3.1"/>
+    </rpm:provides>
+    <rpm:requires>
+      <rpm:entry name="libc.so.6()(64bit)"/>
+      <rpm:entry name="libc.so.6(GLIBC_2.14)(64bit)"/>
+      <rpm:entry name="rtld(GNU_HASH)"/>
+      <rpm:entry name="/usr/bin/sh"/>
+    </rpm:requires>
+    <rpm:obsoletes>
+      <rpm:entry name="zlib"/>
+    </rpm:obsoletes>
+    <file>/usr/bin/minigzip</file>
+  </format>
+</package>
+<package type="rpm">
+  <name>zlib-ng</name>
+  <arch>src</arch>
+  <version epoch="0" ver="2.1.7" rel="3.fc41"/>
+</package>
+</metadata>"#;
+
+        let packages = parse_primary(xml).unwrap();
+        assert_eq!(packages.len(), 1);
+
+        let meta = &packages[0];
+        assert_eq!(meta.name.as_ref(), "zlib-ng-compat");
+        assert_eq!(meta.version_identifier, "2.1.7");
+        assert_eq!(meta.source_release, 3);
+        assert_eq!(meta.source_id, "zlib-ng");
+        assert_eq!(meta.licenses, vec!["Zlib".to_owned()]);
+        assert_eq!(
+            meta.providers.iter().map(ToString::to_string).collect::<BTreeSet<_>>(),
+            BTreeSet::from([
+                "binary(minigzip)".to_owned(),
+                "name(zlib)".to_owned(),
+                "name(zlib-ng-compat)".to_owned(),
+                "soname(libz.so.1(x86_64))".to_owned(),
+            ])
+        );
+        assert_eq!(
+            meta.dependencies
+                .iter()
+                .map(ToString::to_string)
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["soname(libc.so.6(x86_64))".to_owned(), "binary(sh)".to_owned()])
+        );
+        assert_eq!(meta.replaces, BTreeSet::from([Provider::package_name("zlib")]));
+        assert!(meta.uri.is_none());
+    }
+}
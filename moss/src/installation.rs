@@ -4,17 +4,33 @@
 
 //! Encapsulation of a target installation filesystem
 
-use std::path::{Path, PathBuf};
+use std::{
+    io,
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use fs_err as fs;
 use log::{trace, warn};
-use nix::unistd::{AccessFlags, Uid, access};
+use nix::{
+    errno::Errno,
+    libc::{FICLONE, ioctl},
+    sys::{
+        statfs::{self, BTRFS_SUPER_MAGIC, FsType},
+        statvfs::{FsFlags, statvfs},
+    },
+    unistd::{AccessFlags, Uid, access},
+};
 use thiserror::Error;
 use tui::Styled;
 
-use crate::{SystemModel, state, system_model};
+use crate::{
+    SystemModel, state,
+    system_model::{self, template},
+};
 
-mod lockfile;
+pub mod lockfile;
 
 /// System mutability - do we have readwrite?
 #[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
@@ -26,6 +42,156 @@ pub enum Mutability {
     ReadWrite,
 }
 
+/// Marker files, relative to the root, which put moss into read-only mode
+///
+/// Shipped by live media & kiosk images where changes wouldn't persist. The
+/// contents of the marker, if any, are shown as guidance when a change is refused.
+const READ_ONLY_MARKERS: &[&str] = &["etc/moss/read-only", "usr/lib/moss/read-only"];
+
+/// The installation was marked read-only by its vendor or administrator
+#[derive(Debug, Clone)]
+pub struct ReadOnlyMode {
+    /// Marker file which enabled read-only mode
+    pub marker: PathBuf,
+    /// Guidance for the user, i.e. how to get a persistent installation
+    pub guidance: Option<String>,
+}
+
+impl ReadOnlyMode {
+    /// Detect read-only mode from the markers within `root`
+    fn detect(root: &Path) -> Option<Self> {
+        let marker = READ_ONLY_MARKERS
+            .iter()
+            .map(|marker| root.join(marker))
+            .find(|marker| marker.exists())?;
+        let guidance = fs::read_to_string(&marker)
+            .ok()
+            .map(|contents| contents.trim().to_owned())
+            .filter(|contents| !contents.is_empty());
+
+        Some(Self { marker, guidance })
+    }
+}
+
+/// Filesystem of the root, as far as moss can make use of its features
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Filesystem {
+    /// Supports subvolume snapshots
+    Btrfs,
+    /// Supports dataset snapshots
+    Zfs,
+    /// Anything else
+    Other,
+}
+
+impl Filesystem {
+    /// Detect the filesystem `path` resides on
+    pub fn detect(path: &Path) -> Self {
+        // Not exposed by libc
+        const ZFS_SUPER_MAGIC: FsType = FsType(0x2fc1_2fc1);
+
+        match statfs::statfs(path).map(|stat| stat.filesystem_type()) {
+            Ok(BTRFS_SUPER_MAGIC) => Filesystem::Btrfs,
+            Ok(ZFS_SUPER_MAGIC) => Filesystem::Zfs,
+            _ => Filesystem::Other,
+        }
+    }
+}
+
+/// Marker file, relative to the root, shipped by immutable OS images whose `/usr`
+/// must never be changed live, even while mounted read-write
+const IMMUTABLE_MARKER: &str = "usr/lib/moss/immutable";
+
+/// How the mounts of the root allow changing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Mounts {
+    /// `/usr` can be swapped with a new state live
+    Writable,
+    /// `/usr` is read-only or immutable, but `.moss` is writable so new states
+    /// can be staged & applied on reboot
+    ImmutableUsr,
+    /// The root is mounted read-only, nothing can be changed
+    ReadOnly,
+}
+
+impl Mounts {
+    /// Detect how the mounts of `root` allow changing it
+    pub fn detect(root: &Path) -> Self {
+        let read_only = |path: &Path| statvfs(path).is_ok_and(|stat| stat.flags().contains(FsFlags::ST_RDONLY));
+
+        let moss = root.join(".moss");
+        if read_only(if moss.exists() { &moss } else { root }) {
+            Mounts::ReadOnly
+        } else if read_only(&root.join("usr")) || root.join(IMMUTABLE_MARKER).exists() {
+            Mounts::ImmutableUsr
+        } else {
+            Mounts::Writable
+        }
+    }
+}
+
+/// How files from the asset store are placed into a root, cheapest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum BlitStrategy {
+    /// Copy-on-write clone sharing the extents, but not the inode, of the asset
+    Reflink,
+    /// Hardlink to the asset
+    Hardlink,
+    /// Full copy, for when the assets & root are on different filesystems
+    Copy,
+}
+
+impl BlitStrategy {
+    /// Probe the cheapest strategy for placing files from `assets` into `target`
+    ///
+    /// Falls back to [`BlitStrategy::Hardlink`] if the probe can't be written.
+    pub fn probe(assets: &Path, target: &Path) -> Self {
+        let name = format!(".moss-blit-probe-{}", std::process::id());
+        let source = assets.join(&name);
+        let dest = target.join(&name);
+
+        let Ok(file) = fs::File::create(&source) else {
+            return BlitStrategy::Hardlink;
+        };
+
+        let strategy = if fs::File::create(&dest).is_ok_and(|clone| reflink(&file, &clone).is_ok()) {
+            BlitStrategy::Reflink
+        } else if remove_if_exists(&dest)
+            .and_then(|_| fs::hard_link(&source, &dest))
+            .is_ok()
+        {
+            BlitStrategy::Hardlink
+        } else {
+            BlitStrategy::Copy
+        };
+
+        let _ = fs::remove_file(&source);
+        let _ = fs::remove_file(&dest);
+
+        strategy
+    }
+}
+
+/// Remove the file at `path`, which may not have been created
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Clone the contents of `source` into `target` as a copy-on-write reflink
+///
+/// Fails unless both reside on the same filesystem & it supports reflinks.
+pub fn reflink(source: &impl AsRawFd, target: &impl AsRawFd) -> io::Result<()> {
+    // FICLONE isn't wrapped by nix
+    let result = unsafe { ioctl(target.as_raw_fd(), FICLONE, source.as_raw_fd()) };
+    Errno::result(result).map(drop).map_err(io::Error::from)
+}
+
 /// Encapsulate details for a target installation filesystem
 #[derive(Debug, Clone)]
 pub struct Installation {
@@ -42,9 +208,34 @@ pub struct Installation {
     /// otherwise derived from root
     pub cache_dir: Option<PathBuf>,
 
+    /// Read-only caches layered below the writable cache in order of precedence,
+    /// i.e. a network mount shared by a fleet of builders
+    pub shared_caches: Vec<PathBuf>,
+
     /// If defined, the system model of the installation
     pub system_model: Option<SystemModel>,
 
+    /// Variables a templated system model is rendered with
+    pub variables: template::Variables,
+
+    /// Filesystem the root resides on
+    pub filesystem: Filesystem,
+
+    /// If marked, queries are allowed but changes must be refused
+    pub read_only_mode: Option<ReadOnlyMode>,
+
+    /// How the mounts of the root allow changing it
+    pub mounts: Mounts,
+
+    /// New states are staged to be booted into, rather than swapped in live, see
+    /// [`crate::client::pending`]
+    ///
+    /// Set for roots with an immutable `/usr`, or requested explicitly.
+    pub apply_on_reboot: bool,
+
+    /// Probed on first use by [`Installation::blit_strategy`]
+    blit_strategy: OnceLock<BlitStrategy>,
+
     /// Acquired locks that guarantee exclusive access
     /// to the installation for mutable operations
     _locks: Vec<lockfile::Lock>,
@@ -55,28 +246,71 @@ impl Installation {
     /// This will query the potential active state if found,
     /// and determine the mutability per the current user identity
     /// and ACL permissions.
-    pub fn open(root: impl Into<PathBuf>, cache_dir: Option<PathBuf>) -> Result<Self, Error> {
-        let root: PathBuf = root.into();
+    ///
+    /// `cache_dirs` are consulted in order of precedence. Downloads are written
+    /// to the first writable one, or the cache of the root if none are, while
+    /// the others are only looked up as read-only shared caches.
+    pub fn open(root: impl Into<PathBuf>, cache_dirs: impl IntoIterator<Item = PathBuf>) -> Result<Self, Error> {
+        Self::open_with_variables(root, cache_dirs, &template::Variables::default())
+    }
 
+    /// Open a system root as an Installation type, rendering a templated system model
+    /// with `variables` in addition to those detected for the root
+    pub fn open_with_variables(
+        root: impl Into<PathBuf>,
+        cache_dirs: impl IntoIterator<Item = PathBuf>,
+        variables: &template::Variables,
+    ) -> Result<Self, Error> {
+        Self::open_impl(root.into(), cache_dirs, variables, false)
+    }
+
+    /// Open a system root for queries alone, as [`Installation::open_with_variables`]
+    ///
+    /// Nothing is created or recorded within the root and no locks are taken, so it
+    /// never waits on another moss. Used where a prompt answer matters more than a
+    /// consistent view, i.e. the command-not-found handler of a shell.
+    pub fn open_read_only(
+        root: impl Into<PathBuf>,
+        cache_dirs: impl IntoIterator<Item = PathBuf>,
+        variables: &template::Variables,
+    ) -> Result<Self, Error> {
+        Self::open_impl(root.into(), cache_dirs, variables, true)
+    }
+
+    fn open_impl(
+        root: PathBuf,
+        cache_dirs: impl IntoIterator<Item = PathBuf>,
+        variables: &template::Variables,
+        read_only: bool,
+    ) -> Result<Self, Error> {
         if !root.exists() || !root.is_dir() {
             return Err(Error::RootInvalid);
         }
 
-        if let Some(dir) = &cache_dir
-            && (!dir.exists() || !dir.is_dir())
-        {
+        let mut shared_caches = cache_dirs.into_iter().collect::<Vec<_>>();
+
+        if shared_caches.iter().any(|dir| !dir.exists() || !dir.is_dir()) {
             return Err(Error::CacheInvalid);
         }
 
+        let cache_dir = shared_caches
+            .iter()
+            .position(|dir| access(dir, AccessFlags::W_OK).is_ok())
+            .map(|index| shared_caches.remove(index));
+
         // Make sure directories exist (silently fail if read-only)
         //
         // It's important we try this first in-case `root` needs to be created
         // as well, otherwise mutability will always be read-only
         // TODO: Should we instead fail if root doesn't exist?
-        ensure_dirs_exist(&root);
+        if !read_only {
+            ensure_dirs_exist(&root);
+        }
 
         // Root? Always RW. Otherwise, check access for W
-        let mutability = if Uid::effective().is_root() || access(&root, AccessFlags::W_OK).is_ok() {
+        let mutability = if read_only {
+            Mutability::ReadOnly
+        } else if Uid::effective().is_root() || access(&root, AccessFlags::W_OK).is_ok() {
             Mutability::ReadWrite
         } else {
             Mutability::ReadOnly
@@ -84,6 +318,9 @@ impl Installation {
 
         trace!("Mutability: {mutability}");
         trace!("Root dir: {root:?}");
+        if !shared_caches.is_empty() {
+            trace!("Shared caches: {shared_caches:?}");
+        }
 
         // Get exclusive access to work within these directories
         let _locks = if matches!(mutability, Mutability::ReadWrite) {
@@ -100,24 +337,73 @@ impl Installation {
             warn!("Unable to discover Active State ID");
         }
 
+        let variables = {
+            let mut detected = template::Variables::detect(&root);
+            let architecture = detected.get("arch").map(ToOwned::to_owned);
+            detected.extend(variables.iter());
+
+            // Record an overridden architecture, so later use of the root keeps to it
+            if matches!(mutability, Mutability::ReadWrite)
+                && let Some(overridden) = detected.get("arch")
+                && architecture.as_deref() != Some(overridden)
+            {
+                record_architecture(&root, overridden)?;
+            }
+
+            detected
+        };
         let system_model =
-            system_model::load(&root.join("etc/moss/system-model.kdl")).map_err(Error::LoadSystemModel)?;
+            system_model::load(&root.join("etc/moss/system-model.kdl"), &variables).map_err(Error::LoadSystemModel)?;
+
+        let filesystem = Filesystem::detect(&root);
+        trace!("Filesystem: {filesystem}");
+
+        let read_only_mode = ReadOnlyMode::detect(&root);
+        if let Some(mode) = &read_only_mode {
+            trace!("Read-only mode: {:?}", mode.marker);
+        }
+
+        let mounts = Mounts::detect(&root);
+        trace!("Mounts: {mounts}");
 
         Ok(Self {
             root,
             mutability,
             active_state,
             cache_dir,
+            shared_caches,
             system_model,
+            variables,
+            filesystem,
+            read_only_mode,
+            mounts,
+            apply_on_reboot: mounts == Mounts::ImmutableUsr,
+            blit_strategy: OnceLock::new(),
             _locks,
         })
     }
 
+    /// Architecture of the packages installed to the root
+    ///
+    /// The one recorded for the root by a previous override, otherwise that of the running host
+    pub fn architecture(&self) -> &str {
+        self.variables.get("arch").unwrap_or(std::env::consts::ARCH)
+    }
+
     /// Return true if we lack write access
     pub fn read_only(&self) -> bool {
         matches!(self.mutability, Mutability::ReadOnly)
     }
 
+    /// Strategy for blitting from the asset store into the staging tree
+    pub fn blit_strategy(&self) -> BlitStrategy {
+        *self.blit_strategy.get_or_init(|| {
+            let strategy = BlitStrategy::probe(&self.assets_path("v2"), &self.staging_dir());
+            trace!("Blit strategy: {strategy}");
+            strategy
+        })
+    }
+
     // Helper to form paths
     fn moss_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.root.join(".moss").join(path)
@@ -138,6 +424,16 @@ impl Installation {
         }
     }
 
+    /// Cache directories in order of precedence, as passed to [`Installation::open`]
+    pub fn cache_dirs(&self) -> Vec<PathBuf> {
+        self.cache_dir.iter().chain(&self.shared_caches).cloned().collect()
+    }
+
+    /// Build a log path relative to the moss root
+    pub fn log_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.moss_path("log").join(path)
+    }
+
     /// Build an asset path relative to the moss root
     pub fn assets_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.moss_path("assets").join(path)
@@ -177,6 +473,17 @@ impl Installation {
     pub fn system_model_path(&self) -> PathBuf {
         self.root.join("etc/moss/system-model.kdl")
     }
+
+    /// Allow child processes to inherit the locks held on the installation,
+    /// returning their file descriptors, see [`lockfile::Lock::share`]
+    pub fn share_locks(&self) -> Result<Vec<RawFd>, lockfile::Error> {
+        self._locks.iter().map(lockfile::Lock::share).collect()
+    }
+
+    /// Path to the record of the active repository profile
+    pub fn repo_profile_path(&self) -> PathBuf {
+        self.root.join("etc/moss/repo-profile")
+    }
 }
 
 /// Blocks until lockfiles can be obtained for the
@@ -205,7 +512,7 @@ pub fn acquire_locks(moss_path: &Path, cache_dir: Option<&Path>) -> Result<Vec<l
 /// In older versions of moss, the `/usr` entry was a symlink
 /// to an active state. In newer versions, the state is recorded
 /// within the installation tree. (`/usr/.stateID`)
-fn read_state_id(root: &Path) -> Option<state::Id> {
+pub fn read_state_id(root: &Path) -> Option<state::Id> {
     let usr_path = root.join("usr");
     let state_path = root.join("usr").join(".stateID");
 
@@ -248,6 +555,23 @@ fn ensure_dirs_exist(root: &Path) {
     ensure_cachedir_tag(&moss.join("cache"));
 }
 
+/// Path of the architecture recorded for the root
+fn architecture_path(root: &Path) -> PathBuf {
+    root.join(".moss").join("architecture")
+}
+
+/// The architecture recorded for `root`, if it was overridden
+pub fn recorded_architecture(root: &Path) -> Option<String> {
+    fs::read_to_string(architecture_path(root))
+        .ok()
+        .map(|content| content.trim().to_owned())
+        .filter(|architecture| !architecture.is_empty())
+}
+
+fn record_architecture(root: &Path, architecture: &str) -> Result<(), Error> {
+    fs::write(architecture_path(root), format!("{architecture}\n")).map_err(Error::RecordArchitecture)
+}
+
 /// Ensure we install a cachedir tag to prevent backup tools
 /// from archiving the contents of this tree.
 fn ensure_cachedir_tag(path: &Path) {
@@ -273,4 +597,6 @@ pub enum Error {
     Lockfile(#[from] lockfile::Error),
     #[error("load system model")]
     LoadSystemModel(#[from] system_model::LoadError),
+    #[error("record architecture")]
+    RecordArchitecture(#[source] io::Error),
 }
@@ -3,17 +3,36 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
-    fmt,
-    io::{self},
-    os::fd::AsRawFd,
-    path::PathBuf,
-    sync::Arc,
+    env, fmt,
+    io::{self, Write},
+    os::fd::{AsRawFd, FromRawFd, RawFd},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
-use fs_err::File;
-use nix::fcntl::{FlockArg, flock};
+use fs_err::{self as fs, File};
+use nix::{
+    fcntl::{FcntlArg, FdFlag, FlockArg, fcntl, flock},
+    sys::stat::{fstat, stat},
+};
 use thiserror::Error;
 
+/// Environment variable listing the lock file descriptors shared
+/// with a child moss, see [`Lock::share`]
+pub const SHARED_ENV: &str = "MOSS_LOCK_FDS";
+
+/// Whether [`acquire`] fails rather than blocking on a held lock, see [`set_fail_if_held`]
+static FAIL_IF_HELD: AtomicBool = AtomicBool::new(false);
+
+/// Fail to [`acquire`] a lock held by another process instead of waiting for it
+pub fn set_fail_if_held(fail: bool) {
+    FAIL_IF_HELD.store(fail, Ordering::Relaxed);
+}
+
 /// An acquired file lock guaranteeing exclusive access
 /// to the underlying directory.
 ///
@@ -23,32 +42,153 @@ use thiserror::Error;
 #[allow(unused)]
 pub struct Lock(Arc<File>);
 
+impl Lock {
+    /// Allow child processes to inherit the lock, returning its file descriptor
+    ///
+    /// A child moss passed the descriptors via [`SHARED_ENV`] adopts them in [`acquire`]
+    /// rather than blocking on a lock its parent holds on its behalf.
+    pub fn share(&self) -> Result<RawFd, Error> {
+        let fd = self.0.as_raw_fd();
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+        Ok(fd)
+    }
+}
+
 /// Acquires a file lock at the provided path. If the file is currently
 /// locked, `block_msg` will be displayed and the function will block
-/// until the lock is released.
+/// until the lock is released, unless [`set_fail_if_held`] was set.
 ///
 /// Returns the acquired [`Lock`] that will be held until dropped.
 pub fn acquire(path: impl Into<PathBuf>, block_msg: impl fmt::Display) -> Result<Lock, Error> {
     let path = path.into();
 
-    let file = File::options().create(true).write(true).truncate(false).open(path)?;
+    if let Some(lock) = adopt_shared(&path)? {
+        return Ok(lock);
+    }
+
+    let mut file = File::options().create(true).write(true).truncate(false).open(&path)?;
 
     match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
         Ok(_) => {}
         Err(nix::errno::Errno::EWOULDBLOCK) => {
-            println!("{block_msg}");
+            let holder = Holder::read(&path);
+            if FAIL_IF_HELD.load(Ordering::Relaxed) {
+                return Err(Error::Held(holder));
+            }
+
+            match holder {
+                Some(holder) => println!("{block_msg} ({holder})"),
+                None => println!("{block_msg}"),
+            }
             flock(file.as_raw_fd(), FlockArg::LockExclusive)?;
         }
         Err(e) => Err(e)?,
     }
 
+    Holder::current().write(&mut file)?;
+
     Ok(Lock(Arc::new(file)))
 }
 
+/// The process holding a lock, as recorded in the lock file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holder {
+    pub pid: u32,
+    pub command: String,
+}
+
+impl Holder {
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            command: env::args().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    /// The holder recorded in the lock file at `path`, if any
+    fn read(path: &Path) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let (pid, command) = contents.trim_end().split_once('\n')?;
+
+        Some(Self {
+            pid: pid.parse().ok()?,
+            command: command.to_owned(),
+        })
+    }
+
+    fn write(&self, file: &mut File) -> io::Result<()> {
+        file.set_len(0)?;
+        file.write_all(format!("{}\n{}\n", self.pid, self.command).as_bytes())
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pid {}, `{}`", self.pid, self.command)
+    }
+}
+
+/// Adopt a lock on `path` shared by the parent process, if any
+fn adopt_shared(path: &Path) -> Result<Option<Lock>, Error> {
+    let Ok(shared) = env::var(SHARED_ENV) else {
+        return Ok(None);
+    };
+    let Ok(lockfile) = stat(path) else {
+        return Ok(None);
+    };
+
+    for fd in shared.split(',').filter_map(|fd| fd.parse::<RawFd>().ok()) {
+        // Only take ownership of descriptors which really are the lock file
+        let is_lockfile = fstat(fd).is_ok_and(|fd| fd.st_dev == lockfile.st_dev && fd.st_ino == lockfile.st_ino);
+        if !is_lockfile {
+            continue;
+        }
+
+        // SAFETY: The descriptor is open & refers to the lock file, it was inherited
+        // for us to own and nothing else in this process references it
+        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+        // The lock belongs to the open file description we inherited, so this succeeds
+        // immediately unless the descriptor was never locked & another process holds it
+        flock(fd, FlockArg::LockExclusiveNonblock)?;
+
+        return Ok(Some(Lock(Arc::new(File::from_parts(file, path)))));
+    }
+
+    Ok(None)
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error(
+        "another moss process {}is running, pass --wait to wait for it to finish",
+        .0.as_ref().map(|holder| format!("({holder}) ")).unwrap_or_default()
+    )]
+    Held(Option<Holder>),
     #[error("io")]
     Io(#[from] io::Error),
     #[error("obtaining exclusive file lock")]
     Flock(#[from] nix::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_holder() {
+        let holder = Holder {
+            pid: 42,
+            command: "moss install -y nano".to_owned(),
+        };
+
+        assert_eq!(Holder::parse("42\nmoss install -y nano\n"), Some(holder.clone()));
+        assert_eq!(Holder::parse(""), None);
+        assert_eq!(Holder::parse("moss\n"), None);
+        assert_eq!(holder.to_string(), "pid 42, `moss install -y nano`");
+    }
+}
@@ -16,12 +16,18 @@ pub mod client;
 pub mod db;
 pub mod dependency;
 pub mod environment;
+pub mod firmware;
+pub mod foreign;
 pub mod installation;
 pub mod package;
+pub mod prompt;
 pub mod registry;
+pub mod release;
 pub mod repository;
 pub mod request;
 pub mod runtime;
+pub mod settings;
 pub mod signal;
+pub mod signing;
 pub mod state;
 pub mod system_model;
@@ -4,16 +4,25 @@
 
 use std::error::Error;
 
-use tracing::error;
+use moss::request;
+use tracing::{error, warn};
 use tui::Styled;
 
 mod cli;
 
 /// Main entry point
 fn main() {
-    if let Err(error) = cli::process() {
-        report_error(error);
-        std::process::exit(1);
+    let result = cli::process();
+    report_failed_sources();
+    cli::report_timings();
+
+    match result {
+        Ok(outcome) => std::process::exit(cli::ExitCode::from(outcome) as i32),
+        Err(error) => {
+            let code = error.exit_code();
+            report_error(error);
+            std::process::exit(code as i32);
+        }
     }
 }
 
@@ -25,6 +34,35 @@ fn report_error(error: cli::Error) {
     println!("{}: {error}", "Error".red());
 }
 
+/// Report sources downloads failed from, once at the end rather than interleaved with progress
+fn report_failed_sources() {
+    for (source, failures) in request::failures() {
+        warn!(
+            source,
+            retried = failures.retried,
+            failed = failures.failed,
+            "Downloads failed"
+        );
+
+        if failures.failed > 0 {
+            eprintln!(
+                "{}: downloads from {} failed {} time(s), after {} retried attempt(s)",
+                "Warning".yellow(),
+                source.as_str().bold(),
+                failures.failed,
+                failures.retried
+            );
+        } else {
+            eprintln!(
+                "{}: {} was unreliable, {} attempt(s) were retried",
+                "Note".yellow(),
+                source.as_str().bold(),
+                failures.retried
+            );
+        }
+    }
+}
+
 /// Accumulate sources through error chains
 fn sources(error: &cli::Error) -> Vec<String> {
     let mut sources = vec![error.to_string()];
@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Environment variables exported by packages
+//!
+//! Packages declare exports such as `JAVA_HOME=/usr/lib/jvm/default` or
+//! `PATH+=/usr/lib/jvm/default/bin` in their metadata, which moss consolidates into
+//! the shell profile & `environment.d` snippets of each state.
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+/// How an [`Export`] affects its variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    /// Set the variable, `NAME=value`
+    Set,
+    /// Append to a `:` separated list, `NAME+=value`
+    Append,
+}
+
+/// An environment variable exported by a package
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Export {
+    pub name: String,
+    pub kind: Kind,
+    pub value: String,
+}
+
+impl FromStr for Export {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once('=').ok_or_else(|| ParseError(s.to_owned()))?;
+        let (name, kind) = match name.strip_suffix('+') {
+            Some(name) => (name, Kind::Append),
+            None => (name, Kind::Set),
+        };
+
+        let is_valid_name = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_valid_name || value.contains(['\n', '\0']) {
+            return Err(ParseError(s.to_owned()));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            kind,
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl TryFrom<String> for Export {
+    type Error = ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(value.as_str())
+    }
+}
+
+impl fmt::Display for Export {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Set => write!(f, "{}={}", self.name, self.value),
+            Kind::Append => write!(f, "{}+={}", self.name, self.value),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid export: {0}, expected NAME=value or NAME+=value")]
+pub struct ParseError(String);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let set = "JAVA_HOME=/usr/lib/jvm/default".parse::<Export>().unwrap();
+        assert_eq!(set.name, "JAVA_HOME");
+        assert_eq!(set.kind, Kind::Set);
+        assert_eq!(set.value, "/usr/lib/jvm/default");
+
+        let append = "PATH+=/usr/lib/jvm/default/bin".parse::<Export>().unwrap();
+        assert_eq!(append.name, "PATH");
+        assert_eq!(append.kind, Kind::Append);
+        assert_eq!(append.to_string(), "PATH+=/usr/lib/jvm/default/bin");
+
+        // Values may contain `=`
+        assert_eq!("OPTS=-Da=b".parse::<Export>().unwrap().value, "-Da=b");
+
+        assert!("PATH".parse::<Export>().is_err());
+        assert!("=value".parse::<Export>().is_err());
+        assert!("1PATH=value".parse::<Export>().is_err());
+        assert!("MY-VAR=value".parse::<Export>().is_err());
+        assert!("PATH=a\nb".parse::<Export>().is_err());
+    }
+}
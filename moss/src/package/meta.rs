@@ -2,7 +2,10 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::BTreeSet;
+use std::{
+    collections::BTreeSet,
+    path::{Component, Path},
+};
 
 use derive_more::{AsRef, Debug, Display, From, Into};
 use stone::payload;
@@ -10,6 +13,8 @@ use thiserror::Error;
 
 use crate::{Dependency, Provider, dependency};
 
+use super::Export;
+
 /// A package identifier constructed from metadata fields
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Display)]
 #[debug("{_0:?}")]
@@ -46,6 +51,10 @@ pub struct Meta {
     pub source_id: String,
     /// Where'd we find this guy..
     pub homepage: String,
+    /// Where to report bugs, if known
+    pub bug_tracker: Option<String>,
+    /// Who maintains the package, if known
+    pub maintainer: Option<String>,
     /// Licenses this is available under
     pub licenses: Vec<String>,
     /// All dependencies
@@ -54,12 +63,49 @@ pub struct Meta {
     pub providers: BTreeSet<Provider>,
     /// All providers that conflict with this package
     pub conflicts: BTreeSet<Provider>,
+    /// All providers superseded by this package, i.e. prior names
+    pub replaces: BTreeSet<Provider>,
+    /// Environment variables exported to user sessions
+    pub exports: BTreeSet<Export>,
+    /// Configuration files, relative to `/etc`, installed from their vendor
+    /// default under `/usr/share/defaults/etc`
+    pub config_files: BTreeSet<String>,
     /// If relevant: uri to fetch from
     pub uri: Option<String>,
     /// If relevant: hash for the download
     pub hash: Option<String>,
     /// How big is this package in the repo..?
     pub download_size: Option<u64>,
+    /// How big is this package once unpacked
+    pub installed_size: Option<u64>,
+    /// What the update to this release brings, if classified by its packager
+    pub classification: Option<Classification>,
+    /// Repository index specific: the latest release up to this one classified as
+    /// [`Classification::Security`], see [`Meta::is_security_update`]
+    pub security_release: Option<u64>,
+}
+
+/// What an update to a package brings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Classification {
+    /// Fixes a security issue
+    Security,
+    /// Fixes bugs only
+    Bugfix,
+    /// Brings new features
+    Feature,
+}
+
+/// Architectures of packages which can be installed to a root of any architecture
+pub const ANY_ARCHITECTURE: &[&str] = &["noarch", "any"];
+
+/// Returns true if `path` is a valid configuration file path, relative to `/etc`
+pub fn is_config_file(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
 }
 
 impl Meta {
@@ -73,9 +119,16 @@ impl Meta {
         let description = find_meta_string(payload, payload::meta::Tag::Description)?;
         let source_id = find_meta_string(payload, payload::meta::Tag::SourceID)?;
         let homepage = find_meta_string(payload, payload::meta::Tag::Homepage)?;
+        let bug_tracker = find_meta_string(payload, payload::meta::Tag::BugTracker).ok();
+        let maintainer = find_meta_string(payload, payload::meta::Tag::Maintainer).ok();
         let uri = find_meta_string(payload, payload::meta::Tag::PackageURI).ok();
         let hash = find_meta_string(payload, payload::meta::Tag::PackageHash).ok();
         let download_size = find_meta_u64(payload, payload::meta::Tag::PackageSize).ok();
+        let installed_size = find_meta_u64(payload, payload::meta::Tag::InstalledSize).ok();
+        let classification = find_meta_string(payload, payload::meta::Tag::Classification)
+            .ok()
+            .and_then(|classification| classification.parse().ok());
+        let security_release = find_meta_u64(payload, payload::meta::Tag::SecurityRelease).ok();
 
         let licenses = payload
             .iter()
@@ -92,6 +145,17 @@ impl Meta {
             }))
             .collect();
         let conflicts = payload.iter().filter_map(meta_conflict).collect();
+        let replaces = payload.iter().filter_map(meta_replaces).collect();
+        let exports = payload
+            .iter()
+            .filter_map(|meta| meta_string(meta, payload::meta::Tag::Export))
+            .filter_map(|export| export.parse().ok())
+            .collect();
+        let config_files = payload
+            .iter()
+            .filter_map(|meta| meta_string(meta, payload::meta::Tag::ConfigFile))
+            .filter(|path| is_config_file(path))
+            .collect();
 
         Ok(Meta {
             name: Name::from(name),
@@ -103,16 +167,35 @@ impl Meta {
             description,
             source_id,
             homepage,
+            bug_tracker,
+            maintainer,
             licenses,
             dependencies,
             providers,
             conflicts,
+            replaces,
+            exports,
+            config_files,
             uri,
             hash,
             download_size,
+            installed_size,
+            classification,
+            security_release,
         })
     }
 
+    /// Whether updating from `installed` to this release brings a security fix
+    ///
+    /// Indices record the latest release classified as a security fix, so fixes in any
+    /// release since `installed` count. Without it only this release's classification is known.
+    pub fn is_security_update(&self, installed: &Meta) -> bool {
+        match self.security_release {
+            Some(release) => release > installed.source_release,
+            None => self.classification == Some(Classification::Security),
+        }
+    }
+
     pub fn to_stone_payload(self) -> Vec<payload::Meta> {
         use payload::meta::{Kind, Tag};
 
@@ -128,9 +211,23 @@ impl Meta {
             (Tag::Homepage, Kind::String(self.homepage)),
         ]
         .into_iter()
+        .chain(self.bug_tracker.map(|url| (Tag::BugTracker, Kind::String(url))))
+        .chain(
+            self.maintainer
+                .map(|maintainer| (Tag::Maintainer, Kind::String(maintainer))),
+        )
         .chain(self.uri.map(|uri| (Tag::PackageURI, Kind::String(uri))))
         .chain(self.hash.map(|hash| (Tag::PackageHash, Kind::String(hash))))
         .chain(self.download_size.map(|size| (Tag::PackageSize, Kind::Uint64(size))))
+        .chain(self.installed_size.map(|size| (Tag::InstalledSize, Kind::Uint64(size))))
+        .chain(
+            self.classification
+                .map(|classification| (Tag::Classification, Kind::String(classification.to_string()))),
+        )
+        .chain(
+            self.security_release
+                .map(|release| (Tag::SecurityRelease, Kind::Uint64(release))),
+        )
         .chain(
             self.licenses
                 .into_iter()
@@ -154,10 +251,30 @@ impl Meta {
                 // We re-add this on ingestion / it's implied
                 .map(|conflict| (Tag::Conflicts, Kind::Provider(conflict.kind.into(), conflict.name))),
         )
+        .chain(
+            self.replaces
+                .into_iter()
+                .map(|replaced| (Tag::Replaces, Kind::Provider(replaced.kind.into(), replaced.name))),
+        )
+        .chain(
+            self.exports
+                .into_iter()
+                .map(|export| (Tag::Export, Kind::String(export.to_string()))),
+        )
+        .chain(
+            self.config_files
+                .into_iter()
+                .map(|path| (Tag::ConfigFile, Kind::String(path))),
+        )
         .map(|(tag, kind)| payload::Meta { tag, kind })
         .collect()
     }
 
+    /// Returns true if the package can be installed to a root of `architecture`
+    pub fn supports_architecture(&self, architecture: &str) -> bool {
+        self.architecture == architecture || ANY_ARCHITECTURE.contains(&self.architecture.as_str())
+    }
+
     /// Return a reusable ID
     pub fn id(&self) -> Id {
         Id(format!(
@@ -167,6 +284,41 @@ impl Meta {
     }
 }
 
+#[cfg(test)]
+impl Meta {
+    /// Metadata of `name` 1.0-1 for x86_64, providing nothing but its name
+    ///
+    /// A base for test fixtures, which round trips through [`Meta::to_stone_payload`].
+    pub(crate) fn test(name: &str) -> Self {
+        Self {
+            name: Name::from(name.to_owned()),
+            version_identifier: "1.0".to_owned(),
+            source_release: 1,
+            build_release: 1,
+            architecture: "x86_64".to_owned(),
+            summary: String::new(),
+            description: String::new(),
+            source_id: name.to_owned(),
+            homepage: String::new(),
+            bug_tracker: None,
+            maintainer: None,
+            licenses: vec![],
+            dependencies: BTreeSet::new(),
+            providers: BTreeSet::from([Provider::package_name(name)]),
+            conflicts: BTreeSet::new(),
+            replaces: BTreeSet::new(),
+            exports: BTreeSet::new(),
+            config_files: BTreeSet::new(),
+            uri: None,
+            hash: None,
+            download_size: None,
+            installed_size: None,
+            classification: None,
+            security_release: None,
+        }
+    }
+}
+
 fn find_meta_string(meta: &[payload::Meta], tag: payload::meta::Tag) -> Result<String, MissingMetaFieldError> {
     meta.iter()
         .find_map(|meta| meta_string(meta, tag))
@@ -235,6 +387,16 @@ fn meta_conflict(meta: &payload::Meta) -> Option<Provider> {
     }
 }
 
+fn meta_replaces(meta: &payload::Meta) -> Option<Provider> {
+    match (meta.tag, meta.kind.clone()) {
+        (payload::meta::Tag::Replaces, payload::meta::Kind::Provider(kind, name)) => Some(Provider {
+            kind: dependency::Kind::from(kind),
+            name: name.clone(),
+        }),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Error)]
 #[error("Missing metadata field: {0:?}")]
 pub struct MissingMetaFieldError(pub payload::meta::Tag);
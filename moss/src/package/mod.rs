@@ -5,8 +5,10 @@
 use derive_more::{AsRef, Debug, Display, From, Into};
 use itertools::Itertools;
 
-pub use self::meta::{Meta, MissingMetaFieldError, Name};
+pub use self::export::Export;
+pub use self::meta::{Classification, Meta, MissingMetaFieldError, Name};
 
+pub mod export;
 pub mod meta;
 pub mod render;
 
@@ -51,6 +53,18 @@ impl Ord for Package {
     }
 }
 
+#[cfg(test)]
+impl Package {
+    /// Package `name` with the metadata of [`Meta::test`], identified by its name
+    pub(crate) fn test(name: &str, flags: Flags) -> Self {
+        Self {
+            id: Id::from(name.to_owned()),
+            meta: Meta::test(name),
+            flags,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Flags {
     /// Package is available for installation.
@@ -61,6 +75,8 @@ pub struct Flags {
     pub source: bool,
     /// Package is explicitly installed (use with [`Flags::installed`]).
     pub explicit: bool,
+    /// Metadata imported from a foreign repository, can't be installed.
+    pub foreign: bool,
 }
 
 impl Flags {
@@ -97,6 +113,11 @@ impl Flags {
         }
     }
 
+    /// Returns a copy of [`Flags`] with foreign set to true.
+    pub fn with_foreign(&self) -> Self {
+        Self { foreign: true, ..*self }
+    }
+
     /// Returns whether this flag set contains another flag set.
     pub fn contains(&self, other: Self) -> bool {
         (self.bits() & other.bits()) == other.bits()
@@ -107,6 +128,7 @@ impl Flags {
             | ((self.installed as u32) << 1)
             | ((self.source as u32) << 2)
             | ((self.explicit as u32) << 3)
+            | ((self.foreign as u32) << 4)
     }
 }
 
@@ -134,6 +156,7 @@ where
 }
 
 /// A package being updated from `old` to `new`
+#[derive(Clone, Copy)]
 pub struct Update<'a> {
     pub old: &'a Package,
     pub new: &'a Package,
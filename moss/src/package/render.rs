@@ -4,9 +4,11 @@
 
 use std::io::Write;
 
+use itertools::{Either, Itertools};
+
 use tui::{
-    Styled,
-    pretty::{Column, ColumnDisplay},
+    HumanBytes, Styled,
+    pretty::{Column, ColumnDisplay, autoprint_columns},
 };
 
 use crate::{Package, package};
@@ -14,6 +16,12 @@ use crate::{Package, package};
 /// We always pad columns by 3 spaces to just not jank up the output
 const COLUMN_PADDING: usize = 3;
 
+/// Widest rendering of a single size, i.e. `1023.99 KiB`
+const SIZE_WIDTH: usize = 11;
+
+/// Marks updates classified as security fixes
+const SECURITY_TAG: &str = "[security]";
+
 /// Allow display packages in column form
 impl ColumnDisplay for Package {
     fn get_display_width(&self) -> usize {
@@ -58,6 +66,7 @@ impl<'a> ColumnDisplay for package::Update<'a> {
             + self.new.meta.source_release.to_string().len()
             + COLUMN_PADDING
             + 6
+            + if self.is_security() { SECURITY_TAG.len() + 1 } else { 0 }
     }
 
     fn display_column(&self, writer: &mut impl Write, col: Column, width: usize) {
@@ -76,12 +85,113 @@ impl<'a> ColumnDisplay for package::Update<'a> {
             " ",
         );
 
+        if self.is_security() {
+            _ = write!(writer, " {}", SECURITY_TAG.red().bold());
+        }
+
         if col != Column::Last {
             _ = write!(writer, "   ");
         }
     }
 }
 
+impl package::Update<'_> {
+    fn is_security(&self) -> bool {
+        self.new.meta.is_security_update(&self.old.meta)
+    }
+}
+
+/// An entry of a transaction summary, displayed in columns along with its sizes
+///
+/// Added & updated packages show their download & installed size, removed
+/// packages only the installed size that is freed.
+pub enum Change<'a> {
+    Add(&'a Package),
+    Update(package::Update<'a>),
+    Remove(&'a Package),
+}
+
+impl Change<'_> {
+    fn sizes(&self) -> String {
+        let size = |size: Option<u64>| size.map_or_else(|| "?".to_owned(), |size| HumanBytes(size).to_string());
+
+        match self {
+            Change::Add(package) | Change::Update(package::Update { new: package, .. }) => format!(
+                "{:>SIZE_WIDTH$} / {:>SIZE_WIDTH$}",
+                size(package.meta.download_size),
+                size(package.meta.installed_size)
+            ),
+            Change::Remove(package) => format!("{:>SIZE_WIDTH$}", size(package.meta.installed_size)),
+        }
+    }
+}
+
+impl ColumnDisplay for Change<'_> {
+    fn get_display_width(&self) -> usize {
+        let width = match self {
+            Change::Add(package) | Change::Remove(package) => package.get_display_width(),
+            Change::Update(update) => update.get_display_width(),
+        };
+        width + 1 + self.sizes().len()
+    }
+
+    fn display_column(&self, writer: &mut impl Write, col: Column, width: usize) {
+        match self {
+            Change::Add(package) | Change::Remove(package) => package.display_column(writer, Column::Last, width),
+            Change::Update(update) => update.display_column(writer, Column::Last, width),
+        }
+
+        _ = write!(writer, " {}", self.sizes().dim());
+
+        if col != Column::Last {
+            _ = write!(writer, "   ");
+        }
+    }
+}
+
+/// Print `changes` in columns, followed by their total sizes
+pub fn print_changes(changes: &[Change<'_>]) {
+    autoprint_columns(changes);
+
+    let (incoming, removed): (Vec<_>, Vec<_>) = changes.iter().partition_map(|change| match change {
+        Change::Add(package) | Change::Update(package::Update { new: package, .. }) => Either::Left(*package),
+        Change::Remove(package) => Either::Right(*package),
+    });
+
+    let mut totals = vec![];
+    if !incoming.is_empty() {
+        totals.push(format!(
+            "{} download / {} installed",
+            total(incoming.iter().map(|p| p.meta.download_size)),
+            total(incoming.iter().map(|p| p.meta.installed_size))
+        ));
+    }
+    if !removed.is_empty() {
+        totals.push(format!(
+            "{} freed",
+            total(removed.iter().map(|p| p.meta.installed_size))
+        ));
+    }
+    if !totals.is_empty() {
+        println!();
+        println!("{} {}", "Total:".bold(), totals.join(", "));
+    }
+}
+
+/// Sum of `sizes`, which is only a lower bound if some are unknown
+fn total(sizes: impl Iterator<Item = Option<u64>>) -> String {
+    let sizes = sizes.collect::<Vec<_>>();
+    let sum = HumanBytes(sizes.iter().flatten().sum());
+
+    if sizes.iter().all(Option::is_none) {
+        "?".to_owned()
+    } else if sizes.iter().any(Option::is_none) {
+        format!("at least {sum}")
+    } else {
+        sum.to_string()
+    }
+}
+
 fn color_diff(a: &str, b: &str, red: bool) -> String {
     let mut b_segments = to_segments(b).into_iter();
 
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! User prompts
+//!
+//! All interactive questions asked by moss are routed through this module so that
+//! scripted runs never block on input. When running non-interactively (explicitly
+//! requested, or stdin isn't a terminal) a prompt fails with [`Error::NonInteractive`]
+//! naming the decision that was required, and confirmations are governed solely by `--yes`.
+
+use std::{
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use thiserror::Error;
+use tui::dialoguer::{Confirm, MultiSelect, Select, theme::ColorfulTheme};
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Explicitly enable (or disable) non-interactive mode
+pub fn set_non_interactive(enabled: bool) {
+    NON_INTERACTIVE.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if prompts must not block on user input
+pub fn is_non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed) || !io::stdin().is_terminal()
+}
+
+/// Ask the user to confirm `decision`, displaying `prompt`
+///
+/// Returns `true` immediately if `yes` is set, otherwise prompts the user
+/// or fails if running non-interactively.
+pub fn confirm(decision: &str, prompt: &str, yes: bool) -> Result<bool, Error> {
+    if yes {
+        return Ok(true);
+    }
+
+    if is_non_interactive() {
+        return Err(Error::NonInteractive(decision.to_owned()));
+    }
+
+    Ok(Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()?)
+}
+
+/// Ask the user to choose one of `items` for `decision`, displaying `prompt`
+///
+/// Returns the index of the `default` item immediately if `yes` is set, otherwise
+/// prompts the user or fails if running non-interactively.
+pub fn select<T: ToString>(
+    decision: &str,
+    prompt: &str,
+    items: &[T],
+    default: usize,
+    yes: bool,
+) -> Result<usize, Error> {
+    if yes {
+        return Ok(default);
+    }
+
+    if is_non_interactive() {
+        return Err(Error::NonInteractive(decision.to_owned()));
+    }
+
+    Ok(Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(items.iter().map(ToString::to_string))
+        .default(default)
+        .interact()?)
+}
+
+/// Ask the user to choose any of `items` for `decision`, displaying `prompt`
+///
+/// Returns the indices of the items marked in `defaults` immediately if `yes` is set,
+/// otherwise prompts the user or fails if running non-interactively.
+pub fn multi_select<T: ToString>(
+    decision: &str,
+    prompt: &str,
+    items: &[T],
+    defaults: &[bool],
+    yes: bool,
+) -> Result<Vec<usize>, Error> {
+    if yes {
+        return Ok(defaults
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, selected)| selected.then_some(idx))
+            .collect());
+    }
+
+    if is_non_interactive() {
+        return Err(Error::NonInteractive(decision.to_owned()));
+    }
+
+    Ok(MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(items.iter().map(ToString::to_string))
+        .defaults(defaults)
+        .interact()?)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Any prompt, as selections need input just as confirmations do
+    #[error("input required to {0}, rerun with --yes-all to proceed non-interactively")]
+    NonInteractive(String),
+    #[error("dialog")]
+    Dialog(#[from] tui::dialoguer::Error),
+}
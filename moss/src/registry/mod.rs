@@ -67,6 +67,15 @@ impl Registry {
         self.query(move |plugin| plugin.query_name(package_name, flags))
     }
 
+    /// Return a sorted stream of [`Package`] superseding the given provider
+    pub fn by_replaces<'a>(
+        &'a self,
+        provider: &'a Provider,
+        flags: package::Flags,
+    ) -> impl Iterator<Item = Package> + 'a {
+        self.query(move |plugin| plugin.query_replaces(provider, flags))
+    }
+
     /// Return a sorted stream of [`Package`] by id
     pub fn by_id<'a>(&'a self, id: &'a package::Id) -> impl Iterator<Item = Package> + 'a {
         self.query(move |plugin| plugin.package(id))
@@ -116,13 +125,21 @@ mod test {
                 description: Default::default(),
                 source_id: Default::default(),
                 homepage: Default::default(),
+                bug_tracker: None,
+                maintainer: None,
                 licenses: Default::default(),
                 dependencies: Default::default(),
                 providers: Default::default(),
                 conflicts: Default::default(),
+                replaces: Default::default(),
+                exports: Default::default(),
+                config_files: Default::default(),
                 uri: Default::default(),
                 hash: Default::default(),
                 download_size: Default::default(),
+                installed_size: Default::default(),
+                classification: Default::default(),
+                security_release: Default::default(),
             },
             flags: package::Flags::default(),
         };
@@ -171,13 +188,21 @@ mod test {
                 description: Default::default(),
                 source_id: Default::default(),
                 homepage: Default::default(),
+                bug_tracker: None,
+                maintainer: None,
                 licenses: Default::default(),
                 dependencies: Default::default(),
                 providers: Default::default(),
                 conflicts: Default::default(),
+                replaces: Default::default(),
+                exports: Default::default(),
+                config_files: Default::default(),
                 uri: Default::default(),
                 hash: Default::default(),
                 download_size: Default::default(),
+                installed_size: Default::default(),
+                classification: Default::default(),
+                security_release: Default::default(),
             },
             flags,
         };
@@ -217,4 +242,106 @@ mod test {
         assert!(matches(installed_source, &["d"]));
         assert!(matches(available_source, &["e"]));
     }
+
+    #[test]
+    fn test_transaction_conflicts() {
+        let mut registry = Registry::default();
+
+        let name = |name: &str| Provider::package_name(name);
+        let package = |id: &str, dependencies: &[&str], conflicts: &[&str], replaces: &[&str]| {
+            let mut package = Package::test(id, package::Flags::new().with_available());
+            package.meta.dependencies = dependencies
+                .iter()
+                .map(|d| crate::Dependency::from_name(d).unwrap())
+                .collect();
+            package.meta.conflicts = conflicts.iter().map(|c| name(c)).collect();
+            package.meta.replaces = replaces.iter().map(|r| name(r)).collect();
+            package
+        };
+
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            1,
+            vec![
+                package("vim", &[], &[], &[]),
+                package("neovim", &[], &[], &["vim"]),
+                package("openssl", &[], &["libressl"], &[]),
+                package("libressl", &[], &[], &[]),
+                package("curl", &["openssl", "libressl"], &[], &[]),
+                package("editors", &["vim", "neovim"], &[], &[]),
+                package("wget", &["openssl"], &[], &[]),
+            ],
+        )));
+
+        let add = |id: &str| {
+            let mut tx = registry.transaction(transaction::Lookup::AvailableOnly).unwrap();
+            tx.add(vec![package::Id::from(id.to_owned())])
+        };
+
+        assert!(matches!(add("curl"), Err(transaction::Error::Conflict { .. })));
+        // Replacing a package implies a conflict
+        assert!(matches!(add("editors"), Err(transaction::Error::Conflict { .. })));
+        assert!(add("wget").is_ok());
+
+        let replacements = registry
+            .by_replaces(&name("vim"), package::Flags::new().with_available())
+            .map(|p| String::from(p.meta.name))
+            .collect::<Vec<_>>();
+        assert_eq!(replacements, vec!["neovim".to_owned()]);
+    }
+
+    #[test]
+    fn test_transaction_explanation() {
+        let mut registry = Registry::default();
+
+        let package = |id: &str, dependencies: &[&str], flags| {
+            let mut package = Package::test(id, flags);
+            package.meta.dependencies = dependencies
+                .iter()
+                .map(|d| crate::Dependency::from_name(d).unwrap())
+                .collect();
+            package
+        };
+        let available = package::Flags::new().with_available();
+        let installed = package::Flags::new().with_installed();
+
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            1,
+            vec![
+                package("firefox", &["libpng", "zlib"], available),
+                package("libpng", &["binary(bar)"], available),
+                package("zlib", &[], available),
+                package("bar", &[], available),
+                package("gtk", &["cairo"], available),
+                package("cairo", &[], installed),
+            ],
+        )));
+
+        let add = |id: &str| {
+            let mut tx = registry.transaction(transaction::Lookup::AvailableOnly).unwrap();
+            tx.add(vec![package::Id::from(id.to_owned())])
+        };
+
+        let Err(transaction::Error::Unsatisfiable { explanation, .. }) = add("firefox") else {
+            panic!("expected unsatisfiable transaction");
+        };
+        let chain = explanation
+            .chain
+            .iter()
+            .map(|r| (r.package.as_str(), r.requires.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chain,
+            vec![
+                ("firefox 1.0-1", "name(libpng)".to_owned()),
+                ("libpng 1.0-1", "binary(bar)".to_owned())
+            ]
+        );
+        assert_eq!(explanation.missing.to_string(), "binary(bar)");
+        assert!(explanation.hints[0].contains("a package named bar exists"));
+
+        let Err(transaction::Error::Unsatisfiable { explanation, .. }) = add("gtk") else {
+            panic!("expected unsatisfiable transaction");
+        };
+        assert!(explanation.hints[0].contains("installed package cairo 1.0-1"));
+    }
 }
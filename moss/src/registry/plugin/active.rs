@@ -86,6 +86,11 @@ impl Active {
         self.query(flags, Some(db::meta::Filter::Name(package_name.clone())))
     }
 
+    /// Query packages superseding the given provider identity
+    pub fn query_replaces(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Replaces(provider.clone())))
+    }
+
     pub fn query_provider_id_only(&self, provider: &Provider, flags: package::Flags) -> Vec<package::Id> {
         if flags.installed || flags == package::Flags::default() {
             // TODO: Error handling
@@ -86,6 +86,10 @@ impl Cobble {
         self.query(flags, |meta| meta.name == *package_name)
     }
 
+    pub fn query_replaces(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, |meta| meta.replaces.contains(provider))
+    }
+
     pub fn priority(&self) -> u64 {
         u64::MAX
     }
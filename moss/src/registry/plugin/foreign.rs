@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use log::warn;
+
+use crate::{
+    Provider, db,
+    package::{self, Package},
+};
+
+/// Read-only package metadata imported from another distribution's repository
+///
+/// Packages are only returned for [`package::Flags::foreign`] queries (or unfiltered
+/// ones), so they never participate in transactions as there's nothing to install.
+#[derive(Debug)]
+pub struct Foreign {
+    name: String,
+    db: db::meta::Database,
+}
+
+impl Foreign {
+    pub fn new(name: String, db: db::meta::Database) -> Self {
+        Self { name, db }
+    }
+
+    /// Foreign repositories are always consulted last
+    pub fn priority(&self) -> u64 {
+        0
+    }
+
+    fn accepts(flags: package::Flags) -> bool {
+        flags.foreign || flags == package::Flags::default()
+    }
+
+    pub fn package(&self, id: &package::Id) -> Option<Package> {
+        match self.db.get(id) {
+            Ok(meta) => Some(Package {
+                id: id.clone(),
+                meta,
+                flags: package::Flags::new().with_foreign(),
+            }),
+            Err(db::meta::Error::RowNotFound) => None,
+            Err(error) => {
+                warn!("failed to query foreign package from {}: {error}", self.name);
+                None
+            }
+        }
+    }
+
+    fn query(&self, flags: package::Flags, filter: Option<db::meta::Filter<'_>>) -> Vec<Package> {
+        if !Self::accepts(flags) {
+            return vec![];
+        }
+
+        match self.db.query(filter) {
+            Ok(packages) => packages
+                .into_iter()
+                .map(|(id, meta)| Package {
+                    id,
+                    meta,
+                    flags: package::Flags::new().with_foreign(),
+                })
+                .collect(),
+            Err(error) => {
+                warn!("failed to query foreign packages from {}: {error}", self.name);
+                vec![]
+            }
+        }
+    }
+
+    pub fn list(&self, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, None)
+    }
+
+    pub fn query_keyword(&self, keyword: &str, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Keyword(keyword)))
+    }
+
+    pub fn query_provider(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Provider(provider.clone())))
+    }
+
+    pub fn query_name(&self, package_name: &package::Name, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Name(package_name.clone())))
+    }
+
+    pub fn query_replaces(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Replaces(provider.clone())))
+    }
+
+    pub fn query_provider_id_only(&self, provider: &Provider, flags: package::Flags) -> Vec<package::Id> {
+        if !Self::accepts(flags) {
+            return vec![];
+        }
+
+        match self.db.provider_packages(provider) {
+            Ok(packages) => packages,
+            Err(error) => {
+                warn!("failed to query foreign packages from {}: {error}", self.name);
+                vec![]
+            }
+        }
+    }
+}
+
+impl PartialEq for Foreign {
+    fn eq(&self, other: &Self) -> bool {
+        self.name.eq(&other.name)
+    }
+}
+
+impl Eq for Foreign {}
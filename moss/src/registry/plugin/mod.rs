@@ -15,12 +15,14 @@ use crate::registry::package::{self, Package};
 
 pub use self::active::Active;
 pub use self::cobble::Cobble;
+pub use self::foreign::Foreign;
 pub use self::repository::Repository;
 #[cfg(test)]
 pub use self::test::Test;
 
 mod active;
 mod cobble;
+mod foreign;
 mod repository;
 
 /// A [`Registry`] plugin that enables querying [`Package`] information.
@@ -30,6 +32,7 @@ mod repository;
 pub enum Plugin {
     Active(Active),
     Cobble(Cobble),
+    Foreign(Foreign),
     Repository(Repository),
 
     #[cfg(test)]
@@ -43,6 +46,7 @@ impl Plugin {
         match self {
             Plugin::Active(plugin) => plugin.package(id),
             Plugin::Cobble(plugin) => plugin.package(id),
+            Plugin::Foreign(plugin) => plugin.package(id),
             Plugin::Repository(plugin) => plugin.package(id),
 
             #[cfg(test)]
@@ -55,6 +59,7 @@ impl Plugin {
         package::Sorted::new(match self {
             Plugin::Active(plugin) => plugin.list(flags),
             Plugin::Cobble(plugin) => plugin.list(flags),
+            Plugin::Foreign(plugin) => plugin.list(flags),
             Plugin::Repository(plugin) => plugin.list(flags),
 
             #[cfg(test)]
@@ -66,6 +71,7 @@ impl Plugin {
         package::Sorted::new(match self {
             Plugin::Active(plugin) => plugin.query_keyword(keyword, flags),
             Plugin::Cobble(plugin) => plugin.query_keyword(keyword, flags),
+            Plugin::Foreign(plugin) => plugin.query_keyword(keyword, flags),
             Plugin::Repository(plugin) => plugin.query_keyword(keyword, flags),
 
             #[cfg(test)]
@@ -78,6 +84,7 @@ impl Plugin {
         package::Sorted::new(match self {
             Plugin::Active(plugin) => plugin.query_provider(provider, flags),
             Plugin::Cobble(plugin) => plugin.query_provider(provider, flags),
+            Plugin::Foreign(plugin) => plugin.query_provider(provider, flags),
             Plugin::Repository(plugin) => plugin.query_provider(provider, flags),
 
             #[cfg(test)]
@@ -97,6 +104,7 @@ impl Plugin {
                 .into_iter()
                 .map(|p| p.id)
                 .collect(),
+            Plugin::Foreign(plugin) => plugin.query_provider_id_only(provider, flags),
             Plugin::Repository(plugin) => plugin.query_provider_id_only(provider, flags),
 
             #[cfg(test)]
@@ -109,6 +117,7 @@ impl Plugin {
         package::Sorted::new(match self {
             Plugin::Active(plugin) => plugin.query_name(package_name, flags),
             Plugin::Cobble(plugin) => plugin.query_name(package_name, flags),
+            Plugin::Foreign(plugin) => plugin.query_name(package_name, flags),
             Plugin::Repository(plugin) => plugin.query_name(package_name, flags),
 
             #[cfg(test)]
@@ -116,6 +125,19 @@ impl Plugin {
         })
     }
 
+    /// Returns a list of packages superseding `provider` with matching `flags`
+    pub fn query_replaces(&self, provider: &Provider, flags: package::Flags) -> package::Sorted<Vec<Package>> {
+        package::Sorted::new(match self {
+            Plugin::Active(plugin) => plugin.query_replaces(provider, flags),
+            Plugin::Cobble(plugin) => plugin.query_replaces(provider, flags),
+            Plugin::Foreign(plugin) => plugin.query_replaces(provider, flags),
+            Plugin::Repository(plugin) => plugin.query_replaces(provider, flags),
+
+            #[cfg(test)]
+            Plugin::Test(plugin) => plugin.query_replaces(provider, flags),
+        })
+    }
+
     /// Plugin priority
     ///
     /// Higher priority = better chance of selection
@@ -123,6 +145,7 @@ impl Plugin {
         match self {
             Plugin::Active(plugin) => plugin.priority(),
             Plugin::Cobble(plugin) => plugin.priority(),
+            Plugin::Foreign(plugin) => plugin.priority(),
             Plugin::Repository(plugin) => plugin.priority(),
 
             #[cfg(test)]
@@ -190,5 +213,13 @@ pub mod test {
                 .cloned()
                 .collect()
         }
+
+        pub fn query_replaces(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
+            self.packages
+                .iter()
+                .filter(|p| p.meta.replaces.contains(provider) && p.flags.contains(flags))
+                .cloned()
+                .collect()
+        }
     }
 }
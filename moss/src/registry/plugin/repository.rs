@@ -13,11 +13,16 @@ use crate::{
 #[derive(Debug)]
 pub struct Repository {
     active: repository::Cached,
+    /// Only packages supporting this architecture are candidates
+    architecture: String,
 }
 
 impl Repository {
-    pub fn new(active: repository::Cached) -> Self {
-        Self { active }
+    pub fn new(active: repository::Cached, architecture: impl ToString) -> Self {
+        Self {
+            active,
+            architecture: architecture.to_string(),
+        }
     }
 
     pub fn priority(&self) -> u64 {
@@ -62,6 +67,7 @@ impl Repository {
 
             packages
                 .into_iter()
+                .filter(|(_, meta)| meta.supports_architecture(&self.architecture))
                 .map(|(id, meta)| Package {
                     id,
                     meta,
@@ -90,10 +96,15 @@ impl Repository {
         self.query(flags, Some(db::meta::Filter::Name(package_name.clone())))
     }
 
+    /// Query packages superseding the given provider identity
+    pub fn query_replaces(&self, provider: &Provider, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Replaces(provider.clone())))
+    }
+
     pub fn query_provider_id_only(&self, provider: &Provider, flags: package::Flags) -> Vec<package::Id> {
         if flags.available || flags == package::Flags::default() {
             // TODO: Error handling
-            match self.active.db.provider_packages(provider) {
+            match self.active.db.provider_packages_for(provider, &self.architecture) {
                 Ok(packages) => packages,
                 Err(error) => {
                     warn!("failed to query repository packages: {error}");
@@ -2,12 +2,15 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
 
 use dag::Dag;
 use thiserror::Error;
 
-use crate::{Provider, Registry, package};
+use crate::{Package, Provider, Registry, dependency, package};
 
 enum ProviderFilter {
     /// Must be installed
@@ -48,8 +51,17 @@ pub struct Transaction<'a> {
 
     /// Used as a cache to quickly resolve providers for things we've
     /// already added to the transaction so we don't have to hit the
-    /// registry again
-    selection_providers: HashMap<Provider, package::Id>,
+    /// registry again. Every package providing each is kept, in the
+    /// order they were added, so none escapes the conflict checks
+    selection_providers: HashMap<Provider, Vec<package::Id>>,
+
+    /// Providers which can't be installed alongside each package
+    /// in the transaction, including those it replaces
+    conflicts: HashMap<package::Id, BTreeSet<Provider>>,
+
+    /// The package and dependency which first pulled each package
+    /// into the transaction, to explain resolution failures
+    required_by: HashMap<package::Id, (package::Id, Provider)>,
 }
 
 /// Construct a new Transaction wrapped around the underlying [`Registry`].
@@ -63,6 +75,8 @@ pub fn new(registry: &Registry, lookup: Lookup) -> Result<Transaction<'_>, Error
         packages: Dag::default(),
         lookup,
         selection_providers: HashMap::default(),
+        conflicts: HashMap::default(),
+        required_by: HashMap::default(),
     })
 }
 
@@ -98,6 +112,32 @@ impl Transaction<'_> {
             items = next;
         }
 
+        self.check_conflicts()
+    }
+
+    /// Ensure no package in the transaction conflicts with another
+    fn check_conflicts(&self) -> Result<(), Error> {
+        for (id, conflicts) in &self.conflicts {
+            if !self.packages.node_exists(id) {
+                continue;
+            }
+
+            for conflict in conflicts {
+                if let Some(other) = self
+                    .selection_providers
+                    .get(conflict)
+                    .into_iter()
+                    .flatten()
+                    .find(|other| *other != id && self.packages.node_exists(other))
+                {
+                    return Err(Error::Conflict {
+                        package: id.to_string(),
+                        conflicts_with: other.to_string(),
+                    });
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -116,9 +156,20 @@ impl Transaction<'_> {
             "added package to transaction"
         );
 
+        let conflicts = package
+            .meta
+            .conflicts
+            .iter()
+            .chain(&package.meta.replaces)
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        if !conflicts.is_empty() {
+            self.conflicts.insert(check_id.clone(), conflicts);
+        }
+
         // Cache each provider for the package being added to our transaction
         for provider in package.meta.providers {
-            self.selection_providers.insert(provider, check_id.clone());
+            self.add_selection_provider(provider, &check_id);
         }
 
         for dependency in package.meta.dependencies {
@@ -128,7 +179,10 @@ impl Transaction<'_> {
             };
 
             // Now get it resolved
-            let search_id = self.resolve_provider(provider.clone())?;
+            let search_id = self.resolve_provider(provider.clone()).map_err(|error| match error {
+                Error::NoCandidate(_) => self.explain(&check_id, provider.clone(), error),
+                error => error,
+            })?;
 
             // Add dependency node
             let need_search = !self.packages.node_exists(&search_id);
@@ -138,8 +192,9 @@ impl Transaction<'_> {
             if need_search {
                 tracing::debug!(?search_id, "adding package to next");
 
-                // Add this provider to the cache
-                self.selection_providers.insert(provider, search_id.clone());
+                // Add this provider to the cache & remember why it was pulled in
+                self.add_selection_provider(provider.clone(), &search_id);
+                self.required_by.insert(search_id.clone(), (check_id.clone(), provider));
 
                 next.push(search_id);
             }
@@ -151,6 +206,88 @@ impl Transaction<'_> {
         Ok(())
     }
 
+    /// Record `id` as providing `provider` within the transaction
+    fn add_selection_provider(&mut self, provider: Provider, id: &package::Id) {
+        let ids = self.selection_providers.entry(provider).or_default();
+        if !ids.contains(id) {
+            ids.push(id.clone());
+        }
+    }
+
+    /// Explain why `missing`, required by `package`, couldn't be resolved
+    ///
+    /// Walks back through the packages which pulled `package` into the transaction
+    /// and looks for near misses that the lookup strategy excluded. The failed lookup
+    /// is kept as the `source` of the error.
+    fn explain(&self, package: &package::Id, missing: Provider, source: Error) -> Error {
+        let describe = |id: &package::Id| {
+            self.registry.by_id(id).next().map_or_else(
+                || id.to_string(),
+                |p| {
+                    format!(
+                        "{} {}-{}",
+                        p.meta.name, p.meta.version_identifier, p.meta.source_release
+                    )
+                },
+            )
+        };
+
+        let mut chain = vec![Requirement {
+            package: describe(package),
+            requires: missing.clone(),
+        }];
+        let mut current = package;
+        // Packages are only recorded when first added, so this always ends at a requested package
+        while let Some((parent, dependency)) = self.required_by.get(current) {
+            chain.push(Requirement {
+                package: describe(parent),
+                requires: dependency.clone(),
+            });
+            current = parent;
+        }
+        chain.reverse();
+
+        let mut hints = vec![];
+
+        let (excluded, source) = match self.lookup {
+            Lookup::InstalledOnly => (Some(package::Flags::new().with_available()), "available"),
+            Lookup::AvailableOnly => (Some(package::Flags::new().with_installed()), "installed"),
+            Lookup::PreferInstalled | Lookup::PreferAvailable => (None, ""),
+        };
+        if let Some(flags) = excluded
+            && let Some(candidate) = self.registry.by_provider(&missing, flags).next()
+        {
+            hints.push(format!(
+                "{missing} is provided by {source} package {} {}-{}, which the {} lookup doesn't consider",
+                candidate.meta.name, candidate.meta.version_identifier, candidate.meta.source_release, self.lookup
+            ));
+        }
+
+        if !matches!(missing.kind, dependency::Kind::PackageName) {
+            let name = package::Name::from(missing.name.clone());
+            if let Some(candidate) = self
+                .registry
+                .by_name(&name, package::Flags::default())
+                .find(|candidate| !candidate.flags.foreign)
+            {
+                hints.push(format!(
+                    "a package named {} exists ({}-{}) but doesn't provide {missing}",
+                    candidate.meta.name, candidate.meta.version_identifier, candidate.meta.source_release
+                ));
+            }
+        }
+
+        Error::Unsatisfiable {
+            explanation: Box::new(Explanation {
+                chain,
+                missing,
+                lookup: self.lookup,
+                hints,
+            }),
+            source: Box::new(source),
+        }
+    }
+
     // Try all strategies to resolve a provider for installation
     fn resolve_provider(&self, provider: Provider) -> Result<package::Id, Error> {
         match self.lookup {
@@ -187,17 +324,162 @@ impl Transaction<'_> {
             ProviderFilter::Selections(provider) => self
                 .selection_providers
                 .get(&provider)
+                .and_then(|ids| ids.first())
                 .cloned()
                 .ok_or(Error::NoCandidate(provider.to_string())),
         }
     }
 }
 
+/// Returns the first pair of packages which can't be installed alongside each other
+///
+/// A package conflicts with every provider it declares as a conflict or replaces.
+pub fn find_conflict<'a>(packages: &[&'a Package]) -> Option<(&'a Package, &'a Package)> {
+    packages.iter().find_map(|package| {
+        package
+            .meta
+            .conflicts
+            .iter()
+            .chain(&package.meta.replaces)
+            .find_map(|conflict| {
+                packages
+                    .iter()
+                    .find(|other| other.id != package.id && other.meta.providers.contains(conflict))
+            })
+            .map(|other| (*package, *other))
+    })
+}
+
+/// Map each of `packages` to the first of them depending on it
+///
+/// Used to record why a dependency entered a state, packages nothing depends on are left out.
+pub fn required_by<'a>(packages: &[&'a Package]) -> BTreeMap<package::Id, &'a Package> {
+    let mut required_by = BTreeMap::new();
+
+    for (package, provided_by) in dependency_edges(packages) {
+        required_by.entry(provided_by.clone()).or_insert(package);
+    }
+
+    required_by
+}
+
+/// Map each of `packages` to those of them it depends on
+///
+/// Dependencies provided by none of `packages` are left out.
+pub fn depends_on(packages: &[&Package]) -> BTreeMap<package::Id, BTreeSet<package::Id>> {
+    let mut depends_on = BTreeMap::<_, BTreeSet<_>>::new();
+
+    for (package, provided_by) in dependency_edges(packages) {
+        depends_on
+            .entry(package.id.clone())
+            .or_default()
+            .insert(provided_by.clone());
+    }
+
+    depends_on
+}
+
+/// Each dependency between `packages`, as the package & the one providing its dependency
+fn dependency_edges<'a>(packages: &[&'a Package]) -> Vec<(&'a Package, &'a package::Id)> {
+    let mut providers = HashMap::<&Provider, Vec<&package::Id>>::new();
+    for package in packages {
+        for provider in &package.meta.providers {
+            providers.entry(provider).or_default().push(&package.id);
+        }
+    }
+
+    let mut edges = vec![];
+
+    for package in packages {
+        for dependency in &package.meta.dependencies {
+            let provider = Provider {
+                kind: dependency.kind,
+                name: dependency.name.clone(),
+            };
+
+            for provided_by in providers.get(&provider).into_iter().flatten() {
+                if **provided_by != package.id {
+                    edges.push((*package, *provided_by));
+                }
+            }
+        }
+    }
+
+    edges
+}
+
+/// A package and one of its dependencies
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    /// Name & version of the requiring package
+    pub package: String,
+    /// The dependency it requires
+    pub requires: Provider,
+}
+
+/// Explanation of an unsatisfiable dependency
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    /// Requirements leading from the requested package to the missing dependency
+    pub chain: Vec<Requirement>,
+    /// The dependency no candidate could be found for
+    pub missing: Provider,
+    /// Lookup strategy used during resolution
+    pub lookup: Lookup,
+    /// Near misses which may explain the failure
+    pub hints: Vec<String>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot satisfy dependency on {}", self.missing)?;
+
+        let indent = |depth: usize| match depth {
+            0 => "  ".to_owned(),
+            _ => format!("  {}└─ ", "   ".repeat(depth - 1)),
+        };
+
+        for (depth, requirement) in self.chain.iter().enumerate() {
+            write!(
+                f,
+                "\n{}{} requires {}",
+                indent(depth),
+                requirement.package,
+                requirement.requires
+            )?;
+        }
+
+        let depth = self.chain.len();
+        write!(
+            f,
+            "\n{}nothing provides {} (lookup: {})",
+            indent(depth),
+            self.missing,
+            self.lookup
+        )?;
+        for hint in &self.hints {
+            write!(f, "\n  {}   hint: {hint}", "   ".repeat(depth - 1))?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("No such name: {0}")]
     NoCandidate(String),
 
+    #[error("{explanation}")]
+    Unsatisfiable {
+        explanation: Box<Explanation>,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("{package} conflicts with {conflicts_with}")]
+    Conflict { package: String, conflicts_with: String },
+
     #[error("Not yet implemented")]
     NotImplemented,
 
@@ -0,0 +1,228 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Releases of moss itself
+//!
+//! moss installed from a repository is updated like any other package. Installs
+//! outside of a repository, i.e. a binary dropped into place, can instead follow
+//! a release endpoint configured in `/etc/moss/release.yaml`:
+//!
+//! ```yaml
+//! url: https://example.com/moss/latest.json
+//! key: <hex encoded ed25519 public key>
+//! ```
+//!
+//! The endpoint publishes the latest release as
+//! `{"version": "0.26.0", "url": "<binary>", "sha256": "<hex>"}`. Both the manifest
+//! & the binary must be signed by `key`, with the detached signatures published next
+//! to them with a `.sig` suffix as written by `moss pack --sign-key`, and everything
+//! is fetched over https.
+
+use std::{
+    cmp::Ordering,
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::{
+    request,
+    signing::{self, PublicKey},
+};
+
+/// Version of the running moss
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Release endpoint configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where the latest release is published, if anywhere
+    pub url: Option<Url>,
+    /// Hex encoded ed25519 public key the releases are signed with
+    pub key: Option<String>,
+}
+
+impl config::Config for Config {
+    fn domain() -> String {
+        "release".into()
+    }
+}
+
+/// A published release of moss
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub version: String,
+    /// The moss binary of the release
+    pub url: Url,
+    pub sha256: String,
+}
+
+impl Release {
+    /// Whether this release is newer than the running moss
+    pub fn is_newer(&self) -> bool {
+        compare(&self.version, VERSION) == Ordering::Greater
+    }
+}
+
+/// Fetch the latest release published at `endpoint`, signed by the hex encoded `key`
+///
+/// The manifest is verified before it's parsed, so neither the version nor the
+/// binary it points to can be forged.
+pub async fn latest(endpoint: Url, key: &str) -> Result<Release, Error> {
+    let key = PublicKey::from_hex(key).map_err(|_| Error::InvalidKey(key.to_owned()))?;
+    let contents = fetch(endpoint.clone()).await?;
+
+    if !verify(&contents, &endpoint, &key).await? {
+        return Err(Error::Signature("release manifest"));
+    }
+
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+/// Replace the binary at `path` with the one of `release`, signed by the hex encoded `key`
+///
+/// The binary is written next to `path` & verified before it's moved into place,
+/// so a failure leaves the current binary untouched.
+pub async fn install(release: &Release, key: &str, path: &Path) -> Result<(), Error> {
+    let key = PublicKey::from_hex(key).map_err(|_| Error::InvalidKey(key.to_owned()))?;
+    let contents = fetch(release.url.clone()).await?;
+
+    let actual = hex::encode(Sha256::digest(&contents));
+    if !actual.eq_ignore_ascii_case(release.sha256.trim()) {
+        return Err(Error::Checksum {
+            expected: release.sha256.clone(),
+            actual,
+        });
+    }
+
+    if !verify(&contents, &release.url, &key).await? {
+        return Err(Error::Signature("downloaded binary"));
+    }
+
+    let staged = staged_path(path);
+
+    fs::write(&staged, contents)?;
+    fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    if let Err(error) = fs::rename(&staged, path) {
+        let _ = fs::remove_file(&staged);
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+/// Verify `contents` fetched from `url` against the detached signature published next to it
+async fn verify(contents: &[u8], url: &Url, key: &PublicKey) -> Result<bool, Error> {
+    let mut signature_url = url.clone();
+    signature_url.set_path(&format!("{}{}", url.path(), signing::SUFFIX));
+    let signature = fetch(signature_url).await?;
+
+    Ok(key.verify(contents, &signature).is_ok())
+}
+
+/// Where the binary replacing the one at `path` is written first
+fn staged_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".new");
+    path.with_file_name(name)
+}
+
+/// Fetch `url`, which must be https as releases replace moss itself
+async fn fetch(url: Url) -> Result<Vec<u8>, Error> {
+    if url.scheme() != "https" {
+        return Err(Error::Insecure(url));
+    }
+
+    let mut stream = request::get(url).await?;
+    let mut contents = vec![];
+
+    while let Some(chunk) = stream.next().await {
+        contents.extend_from_slice(&chunk?);
+    }
+
+    Ok(contents)
+}
+
+/// Compare two version identifiers, numeric parts by their value
+///
+/// A pre-release, i.e. `1.0-rc1`, ranks below its release while build metadata
+/// following a `+` is ignored.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let parts = |version: &str| {
+        version
+            .split(['.', '-'])
+            .map(|part| part.parse::<u64>().map_err(|_| part.to_owned()))
+            .collect::<Vec<_>>()
+    };
+    let (a, a_pre) = split_pre_release(a);
+    let (b, b_pre) = split_pre_release(b);
+
+    parts(a).cmp(&parts(b)).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => parts(a).cmp(&parts(b)),
+    })
+}
+
+/// Split `version` into the release & its pre-release identifier, if any
+fn split_pre_release(version: &str) -> (&str, Option<&str>) {
+    let version = version.split_once('+').map_or(version, |(version, _)| version);
+
+    match version.split_once('-') {
+        Some((release, pre_release)) => (release, Some(pre_release)),
+        None => (version, None),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("fetch")]
+    Fetch(#[from] request::Error),
+    #[error("invalid release")]
+    Json(#[from] serde_json::Error),
+    #[error("downloaded binary has checksum {actual}, expected {expected}")]
+    Checksum { expected: String, actual: String },
+    #[error("refusing to fetch the release from {0}, it must be https")]
+    Insecure(Url),
+    #[error("invalid release key {0:?}, expected a hex encoded ed25519 public key")]
+    InvalidKey(String),
+    #[error("{0} doesn't match its signature & the release key")]
+    Signature(&'static str),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare("0.25.6", "0.26.0"), Ordering::Less);
+        assert_eq!(compare("0.25.10", "0.25.9"), Ordering::Greater);
+        assert_eq!(compare("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare("1.0.1", "1.0"), Ordering::Greater);
+        assert_eq!(compare("1.0-rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0-rc1", "1.0-rc2"), Ordering::Less);
+        assert_eq!(compare("1.0-rc1", "0.9"), Ordering::Greater);
+        assert_eq!(compare("1.0+build.5", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_staged_path() {
+        assert_eq!(
+            staged_path(Path::new("/usr/bin/moss.x86_64")),
+            PathBuf::from("/usr/bin/moss.x86_64.new")
+        );
+    }
+}
@@ -0,0 +1,350 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Vulnerability advisories
+//!
+//! Next to its `stone.index`, a repository may publish `advisories.json`: a list
+//! of advisories in the [OSV](https://ossf.github.io/osv-schema/) format, with
+//! the affected packages named as they're known in the repository. Feeds from
+//! elsewhere can be read just the same.
+//!
+//! Affected ranges are compared by source release, as moss orders packages, so
+//! range events are either a release (`"12"`) or a `version-release` (`"1.2.3-12"`).
+//! Explicitly listed `versions` match the version identifier of a package.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::debug;
+use url::Url;
+
+use crate::package;
+
+use super::{FetchError, fetch_bytes};
+
+/// File holding the advisories published by a repository
+pub const ADVISORIES_FILE: &str = "advisories.json";
+
+/// Ecosystem of the affected packages in advisories published for moss repositories
+pub const ECOSYSTEM: &str = "moss";
+
+/// A vulnerability advisory in the OSV format, holding only what's needed to audit packages
+#[derive(Debug, Clone, Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    /// Other identifiers of the vulnerability, i.e. its CVE
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub summary: String,
+    #[serde(default)]
+    pub affected: Vec<Affected>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Affected {
+    pub package: AffectedPackage,
+    #[serde(default)]
+    pub ranges: Vec<Range>,
+    #[serde(default)]
+    pub versions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AffectedPackage {
+    pub name: String,
+    #[serde(default)]
+    pub ecosystem: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Range {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Event {
+    pub introduced: Option<String>,
+    pub fixed: Option<String>,
+    pub last_affected: Option<String>,
+}
+
+/// An installed package affected by an [`Advisory`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub summary: String,
+    /// The first release fixing the vulnerability, if known
+    pub fixed: Option<String>,
+}
+
+impl Advisory {
+    /// Whether the package with `meta` is affected, along with the release fixing it
+    pub fn affects(&self, meta: &package::Meta) -> Option<Finding> {
+        let release = meta.source_release;
+
+        let matching = self
+            .affected
+            .iter()
+            .filter(|affected| affected.applies_to(meta))
+            .filter(|affected| {
+                affected.versions.contains(&meta.version_identifier)
+                    || affected.ranges.iter().any(|range| range.contains(release))
+            })
+            .collect::<Vec<_>>();
+
+        if matching.is_empty() {
+            return None;
+        }
+
+        let fixed = matching
+            .iter()
+            .flat_map(|affected| &affected.ranges)
+            .flat_map(|range| &range.events)
+            .filter_map(|event| event.fixed.as_ref())
+            .filter_map(|fixed| Some((parse_release(fixed)?, fixed)))
+            .filter(|(fixed, _)| *fixed > release)
+            .min_by_key(|(fixed, _)| *fixed)
+            .map(|(_, fixed)| fixed.clone());
+
+        Some(Finding {
+            id: self.id.clone(),
+            aliases: self.aliases.clone(),
+            summary: self.summary.clone(),
+            fixed,
+        })
+    }
+}
+
+impl Affected {
+    /// Whether this affects moss packages, rather than those of another ecosystem
+    fn is_moss(&self) -> bool {
+        let ecosystem = &self.package.ecosystem;
+
+        ecosystem.is_empty() || ecosystem.eq_ignore_ascii_case(ECOSYSTEM)
+    }
+
+    /// Whether this names the package with `meta`, or the source it's built from
+    fn applies_to(&self, meta: &package::Meta) -> bool {
+        self.is_moss() && (*meta.name.as_ref() == self.package.name || self.package.name == meta.source_id)
+    }
+
+    /// The first range event which isn't a release, if any
+    ///
+    /// Such an event would otherwise be skipped, silently narrowing or widening the range.
+    fn invalid_event(&self) -> Option<&str> {
+        self.ranges
+            .iter()
+            .filter(|range| range.kind.eq_ignore_ascii_case("ECOSYSTEM"))
+            .flat_map(|range| &range.events)
+            .flat_map(|event| [&event.introduced, &event.fixed, &event.last_affected])
+            .flatten()
+            .map(String::as_str)
+            .find(|value| parse_release(value).is_none())
+    }
+}
+
+impl Range {
+    /// Whether `release` is affected, evaluating the events in order of their release
+    fn contains(&self, release: u64) -> bool {
+        if !self.kind.eq_ignore_ascii_case("ECOSYSTEM") {
+            return false;
+        }
+
+        let mut events = self
+            .events
+            .iter()
+            .filter_map(|event| {
+                if let Some(introduced) = &event.introduced {
+                    Some((parse_release(introduced)?, Boundary::Introduced))
+                } else if let Some(fixed) = &event.fixed {
+                    Some((parse_release(fixed)?, Boundary::Fixed))
+                } else {
+                    Some((parse_release(event.last_affected.as_ref()?)?, Boundary::LastAffected))
+                }
+            })
+            .collect::<Vec<_>>();
+        events.sort();
+
+        events
+            .into_iter()
+            .fold(false, |affected, (at, boundary)| match boundary {
+                Boundary::Introduced if at <= release => true,
+                Boundary::Fixed if at <= release => false,
+                Boundary::LastAffected if at < release => false,
+                _ => affected,
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Boundary {
+    Introduced,
+    Fixed,
+    LastAffected,
+}
+
+/// Release of a range event, either a bare release or the release of a `version-release`
+///
+/// OSV uses `0` to denote every release.
+fn parse_release(value: &str) -> Option<u64> {
+    value.rsplit('-').next()?.parse().ok()
+}
+
+/// Parse a feed of advisories
+///
+/// Accepts a list of advisories, a single advisory, or the `{"vulns": [..]}` object
+/// returned by the OSV query API. Ranges of moss packages with an event that isn't
+/// a release are rejected, as they can't be evaluated.
+pub fn parse(contents: &[u8]) -> Result<Vec<Advisory>, Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Feed {
+        List(Vec<Advisory>),
+        Query { vulns: Vec<Advisory> },
+        Single(Box<Advisory>),
+    }
+
+    let advisories = match serde_json::from_slice(contents)? {
+        Feed::List(advisories) | Feed::Query { vulns: advisories } => advisories,
+        Feed::Single(advisory) => vec![*advisory],
+    };
+
+    for advisory in &advisories {
+        if let Some(event) = advisory
+            .affected
+            .iter()
+            .filter(|affected| affected.is_moss())
+            .find_map(Affected::invalid_event)
+        {
+            return Err(Error::InvalidEvent {
+                advisory: advisory.id.clone(),
+                event: event.to_owned(),
+            });
+        }
+    }
+
+    Ok(advisories)
+}
+
+/// Fetch the feed of advisories at `url`
+pub async fn fetch(url: Url) -> Result<Vec<Advisory>, Error> {
+    let contents = fetch_bytes(url).await?;
+
+    parse(&contents)
+}
+
+/// Fetch the advisories published by the repository at `uri`
+///
+/// Returns `None` if the repository doesn't publish any.
+pub async fn fetch_published(uri: &Url) -> Result<Option<Vec<Advisory>>, Error> {
+    let url = uri.join(ADVISORIES_FILE)?;
+
+    match fetch(url).await {
+        Ok(advisories) => Ok(Some(advisories)),
+        Err(error) if error.is_not_found() => {
+            debug!(%uri, "Repository doesn't publish advisories");
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("fetch")]
+    Fetch(#[from] FetchError),
+    #[error("invalid advisories")]
+    Parse(#[from] serde_json::Error),
+    #[error("invalid url")]
+    Url(#[from] url::ParseError),
+    #[error("advisory {advisory} has range event {event:?}, expected a release or version-release")]
+    InvalidEvent { advisory: String, event: String },
+}
+
+impl Error {
+    fn is_not_found(&self) -> bool {
+        matches!(self, Error::Fetch(error) if error.is_not_found())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn meta(name: &str, version: &str, release: u64) -> package::Meta {
+        package::Meta {
+            version_identifier: version.to_owned(),
+            source_release: release,
+            ..package::Meta::test(name)
+        }
+    }
+
+    #[test]
+    fn test_affects() {
+        let advisories = parse(
+            br#"[{
+                "id": "MOSS-2025-1",
+                "aliases": ["CVE-2025-1234"],
+                "summary": "Overflow in the crust",
+                "affected": [{
+                    "package": {"name": "italian-pizza", "ecosystem": "moss"},
+                    "ranges": [{"type": "ECOSYSTEM", "events": [
+                        {"introduced": "0"}, {"fixed": "1.2-4"},
+                        {"introduced": "6"}, {"last_affected": "7"}
+                    ]}],
+                    "versions": ["0.9"]
+                }]
+            }]"#,
+        )
+        .unwrap();
+        let advisory = &advisories[0];
+
+        let finding = advisory.affects(&meta("italian-pizza", "1.1", 3)).unwrap();
+        assert_eq!(finding.aliases, vec!["CVE-2025-1234"]);
+        assert_eq!(finding.fixed.as_deref(), Some("1.2-4"));
+
+        assert!(advisory.affects(&meta("italian-pizza", "1.2", 4)).is_none());
+        assert!(advisory.affects(&meta("italian-pizza", "1.3", 6)).is_some());
+        assert!(advisory.affects(&meta("italian-pizza", "1.3", 7)).is_some());
+        assert!(advisory.affects(&meta("italian-pizza", "1.4", 8)).is_none());
+        assert!(
+            advisory
+                .affects(&meta("italian-pizza", "0.9", 100))
+                .unwrap()
+                .fixed
+                .is_none()
+        );
+        assert!(advisory.affects(&meta("hawaiian-pizza", "1.1", 3)).is_none());
+    }
+
+    #[test]
+    fn test_parse_feeds() {
+        let single =
+            br#"{"id": "A", "affected": [{"package": {"name": "x", "ecosystem": "PyPI"}, "versions": ["1"]}]}"#;
+        let advisories = parse(single).unwrap();
+        assert_eq!(advisories.len(), 1);
+        // Other ecosystems don't apply to moss packages
+        assert!(advisories[0].affects(&meta("x", "1", 1)).is_none());
+
+        assert_eq!(parse(br#"{"vulns": [{"id": "A"}, {"id": "B"}]}"#).unwrap().len(), 2);
+        assert!(parse(b"not json").is_err());
+
+        // Unparseable events of moss packages are rejected, those of other ecosystems ignored
+        let range = |ecosystem: &str| {
+            format!(
+                r#"{{"id": "A", "affected": [{{"package": {{"name": "x", "ecosystem": "{ecosystem}"}},
+                    "ranges": [{{"type": "ECOSYSTEM", "events": [{{"introduced": "0"}}, {{"fixed": "1.2.3"}}]}}]}}]}}"#
+            )
+        };
+        assert!(matches!(
+            parse(range("moss").as_bytes()),
+            Err(Error::InvalidEvent { event, .. }) if event == "1.2.3"
+        ));
+        assert!(parse(range("PyPI").as_bytes()).is_ok());
+    }
+}
@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Package name aliases published by a repository
+//!
+//! A repository may publish `stone.index.aliases` next to its index (written by
+//! `moss index --aliases`): a JSON object mapping names used by other distributions
+//! to the package they're known as in the repository, i.e. `{"vim": "neovim"}`.
+//!
+//! The aliases are fetched along with the index and cached in the cache directory
+//! of the repository, so they're resolved without network access.
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use fs_err as fs;
+use thiserror::Error;
+
+/// File published next to the index holding its [`Aliases`]
+pub const ALIASES_FILE: &str = "stone.index.aliases";
+
+/// Alternative package names mapped to the name they're known as in the repository
+pub type Aliases = BTreeMap<String, String>;
+
+/// Parse published aliases, either as JSON or YAML
+pub fn parse(contents: &str) -> Result<Aliases, Error> {
+    Ok(serde_yaml::from_str(contents)?)
+}
+
+/// Load the aliases cached in the cache `dir` of a repository, empty if there are none
+pub fn load(dir: &Path) -> Aliases {
+    fs::read_to_string(dir.join(ALIASES_FILE))
+        .ok()
+        .and_then(|contents| parse(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Cache the `aliases` in the cache `dir` of a repository, removing stale ones if it doesn't publish any
+pub fn save(dir: &Path, aliases: Option<&Aliases>) -> Result<(), Error> {
+    let path = dir.join(ALIASES_FILE);
+
+    match aliases {
+        Some(aliases) => fs::write(path, serde_json::to_string(aliases)?)?,
+        None => match fs::remove_file(path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        },
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid aliases")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("encode aliases")]
+    Json(#[from] serde_json::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = parse(r#"{"vim": "neovim", "netcat": "openbsd-netcat"}"#).unwrap();
+        let yaml = parse("vim: neovim\nnetcat: openbsd-netcat\n").unwrap();
+
+        assert_eq!(json, yaml);
+        assert_eq!(json.get("vim").map(String::as_str), Some("neovim"));
+        assert!(parse("[vim, neovim]").is_err());
+    }
+}
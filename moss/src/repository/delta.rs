@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Incremental repository index updates
+//!
+//! Next to its `stone.index`, a repository generated by `moss index` publishes:
+//!
+//! - `stone.index.revision`: the revision of the current index, the sha256 of its contents
+//! - `stone.index.d/<revision>.delta`: the changes from an older revision to the current index
+//!
+//! A delta is a stone repository file with a meta payload for every added package and
+//! a tombstone, a meta payload holding nothing but the package hash, for every removed
+//! package. Packages are identified by their hash, so an updated package is a removal
+//! of the old & an addition of the new. A package whose metadata changed alone, i.e. a
+//! moved `uri`, is added again & replaces the package of the same hash. The validity
+//! of the current index, if it declares one, is carried by a payload holding nothing
+//! but the [`Tag::ValidUntil`].
+//!
+//! Clients fall back to fetching the full index whenever no delta is available.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Seek, Write},
+};
+
+use sha2::{Digest, Sha256};
+use stone::payload::meta::{Kind, Tag};
+use thiserror::Error;
+
+use crate::package::{self, Meta, MissingMetaFieldError};
+
+/// File holding the revision of the current index
+pub const REVISION_FILE: &str = "stone.index.revision";
+
+/// Directory holding the deltas & the older indices they're generated from
+pub const DELTA_DIR: &str = "stone.index.d";
+
+/// The revision of an index with `contents`
+pub fn revision(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+}
+
+/// Path of the delta from `revision` to the current index, relative to the index
+pub fn delta_path(revision: &str) -> String {
+    format!("{DELTA_DIR}/{revision}.delta")
+}
+
+/// Changes between two index revisions
+#[derive(Debug, Default)]
+pub struct Delta {
+    pub added: Vec<(package::Id, Meta)>,
+    pub removed: Vec<package::Id>,
+    /// Unix time the current index is valid until, see [`index::valid_until`](super::index::valid_until)
+    pub valid_until: Option<u64>,
+}
+
+impl Delta {
+    /// Compute the delta from the packages of the `old` index to those of the `new` index
+    pub fn between<'a>(old: impl IntoIterator<Item = &'a Meta>, new: impl IntoIterator<Item = &'a Meta>) -> Self {
+        let id = |meta: &Meta| meta.hash.clone().map(package::Id::from);
+        // Compared as encoded, so every change to the metadata is picked up
+        let encoded = |meta: &Meta| meta.clone().to_stone_payload();
+
+        let old = old
+            .into_iter()
+            .filter_map(|meta| Some((id(meta)?, encoded(meta))))
+            .collect::<BTreeMap<_, _>>();
+        let new = new
+            .into_iter()
+            .filter_map(|meta| Some((id(meta)?, meta.clone())))
+            .collect::<Vec<_>>();
+        let kept = new.iter().map(|(id, _)| id).collect::<BTreeSet<_>>();
+
+        Self {
+            removed: old.keys().filter(|id| !kept.contains(id)).cloned().collect(),
+            added: new
+                .into_iter()
+                .filter(|(id, meta)| old.get(id) != Some(&encoded(meta)))
+                .collect(),
+            valid_until: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Write the delta as a stone file
+    pub fn write<W: Write + Seek>(self, writer: W) -> Result<(), stone::write::Error> {
+        let mut writer = stone::Writer::new(writer, stone::header::v1::FileType::Repository)?;
+
+        for (_, meta) in self.added {
+            writer.add_payload(meta.to_stone_payload().as_slice())?;
+        }
+        for id in self.removed {
+            let tombstone = stone::payload::Meta {
+                tag: Tag::PackageHash,
+                kind: Kind::String(id.into()),
+            };
+            writer.add_payload([tombstone].as_slice())?;
+        }
+        if let Some(valid_until) = self.valid_until {
+            let validity = stone::payload::Meta {
+                tag: Tag::ValidUntil,
+                kind: Kind::Uint64(valid_until),
+            };
+            writer.add_payload([validity].as_slice())?;
+        }
+
+        writer.finalize()
+    }
+
+    /// Read a delta written by [`Delta::write`]
+    pub fn read<R: Read + Seek>(reader: R) -> Result<Self, Error> {
+        let mut reader = stone::read(reader)?;
+        let mut delta = Self::default();
+
+        for payload in reader.payloads()? {
+            let stone::read::PayloadKind::Meta(meta) = payload? else {
+                continue;
+            };
+            let meta = meta.body;
+
+            if let Some(valid_until) = meta.iter().find_map(|record| match (record.tag, &record.kind) {
+                (Tag::ValidUntil, Kind::Uint64(valid_until)) => Some(*valid_until),
+                _ => None,
+            }) {
+                delta.valid_until = Some(valid_until);
+            } else if meta.iter().any(|record| record.tag == Tag::Name) {
+                let meta = Meta::from_stone_payload(&meta)?;
+                let id = meta.hash.clone().ok_or(Error::MissingHash)?;
+                delta.added.push((package::Id::from(id), meta));
+            } else {
+                let id = meta
+                    .into_iter()
+                    .find_map(|record| match (record.tag, record.kind) {
+                        (Tag::PackageHash, Kind::String(hash)) => Some(hash),
+                        _ => None,
+                    })
+                    .ok_or(Error::MissingHash)?;
+                delta.removed.push(package::Id::from(id));
+            }
+        }
+
+        Ok(delta)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("read delta")]
+    Read(#[from] stone::read::Error),
+    #[error("package without hash in delta")]
+    MissingHash,
+    #[error(transparent)]
+    MissingMetaField(#[from] MissingMetaFieldError),
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn meta(name: &str, hash: &str) -> Meta {
+        Meta {
+            hash: Some(hash.to_owned()),
+            ..Meta::test(name)
+        }
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let old = [meta("a", "aaaa"), meta("b", "bbbb")];
+        let new = [meta("a", "aaaa"), meta("b", "cccc"), meta("d", "dddd")];
+
+        let mut delta = Delta::between(&old, &new);
+        assert_eq!(delta.removed, vec![package::Id::from("bbbb".to_owned())]);
+        assert_eq!(delta.added.len(), 2);
+        delta.valid_until = Some(1_700_000_000);
+
+        let mut buffer = Cursor::new(vec![]);
+        delta.write(&mut buffer).unwrap();
+        buffer.set_position(0);
+
+        let read = Delta::read(buffer).unwrap();
+        assert_eq!(read.removed, vec![package::Id::from("bbbb".to_owned())]);
+        assert_eq!(read.valid_until, Some(1_700_000_000));
+        assert_eq!(
+            read.added.into_iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec![
+                package::Id::from("cccc".to_owned()),
+                package::Id::from("dddd".to_owned())
+            ]
+        );
+        assert!(Delta::between(&new, &new).is_empty());
+
+        let mut moved = new.clone();
+        moved[0].uri = Some("moved/a.stone".to_owned());
+        let delta = Delta::between(&new, &moved);
+        assert!(delta.removed.is_empty());
+        assert_eq!(
+            delta.added.into_iter().map(|(_, meta)| meta).collect::<Vec<_>>(),
+            vec![moved[0].clone()]
+        );
+    }
+}
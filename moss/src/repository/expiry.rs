@@ -0,0 +1,168 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Repository metadata expiry
+//!
+//! A repository may declare within its index the unix time after which the index must
+//! no longer be trusted (written by `moss index --valid-for`), see
+//! [`valid_until`](super::index::valid_until).
+//! An index which is past its validity, despite having just been fetched, points at a mirror
+//! which is frozen or replaying old metadata.
+//!
+//! Whenever an index is loaded, the time of the fetch & the validity are recorded in the
+//! cache directory of the repository as [`Record`].
+
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// File in the cache directory of a repository holding its [`Record`]
+const RECORD_FILE: &str = "stone.index.fetched";
+
+/// Age after which metadata without a published validity is considered stale
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// When the metadata of a repository was fetched & until when it's valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// Unix time of the last successful fetch
+    pub fetched: u64,
+    /// Unix time the publisher declared the metadata valid until
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<u64>,
+}
+
+impl Record {
+    /// A record of metadata fetched just now
+    pub fn now(valid_until: Option<u64>) -> Self {
+        Self {
+            fetched: unix_now(),
+            valid_until,
+        }
+    }
+
+    /// Load the record from the cache `dir` of a repository
+    pub fn load(dir: &Path) -> Option<Self> {
+        serde_json::from_str(&fs::read_to_string(dir.join(RECORD_FILE)).ok()?).ok()
+    }
+
+    /// Save the record to the cache `dir` of a repository
+    pub fn save(&self, dir: &Path) -> Result<(), Error> {
+        fs::write(dir.join(RECORD_FILE), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Time since the metadata was fetched
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.fetched))
+    }
+
+    /// Whether the metadata is past the validity declared by its publisher
+    pub fn is_expired(&self) -> bool {
+        self.valid_until.is_some_and(|valid_until| unix_now() > valid_until)
+    }
+
+    /// Whether the metadata should be warned about, being either expired or older
+    /// than [`DEFAULT_MAX_AGE`] without a published validity
+    pub fn is_stale(&self) -> bool {
+        self.is_expired() || (self.valid_until.is_none() && self.age() > DEFAULT_MAX_AGE)
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Parse a duration such as `90s`, `30m`, `12h`, `7d` or `2w`, plain numbers are seconds
+pub fn parse_duration(s: &str) -> Result<Duration, ParseDurationError> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+
+    let number = number.parse::<u64>().map_err(|_| ParseDurationError(s.to_owned()))?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(ParseDurationError(s.to_owned())),
+    };
+
+    let secs = number
+        .checked_mul(multiplier)
+        .ok_or_else(|| ParseDurationError(s.to_owned()))?;
+
+    Ok(Duration::from_secs(secs))
+}
+
+/// Display a duration in its largest whole unit, i.e. `3d`
+pub struct Human(pub Duration);
+
+impl fmt::Display for Human {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+
+        match secs {
+            0..60 => write!(f, "{secs}s"),
+            60..3_600 => write!(f, "{}m", secs / 60),
+            3_600..86_400 => write!(f, "{}h", secs / 3_600),
+            _ => write!(f, "{}d", secs / 86_400),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid duration {0:?}, expected i.e. 90s, 30m, 12h, 7d or 2w")]
+pub struct ParseDurationError(String);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] std::io::Error),
+    #[error("encode record")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(1_800));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(604_800));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration(&format!("{}w", u64::MAX)).is_err());
+
+        assert_eq!(Human(Duration::from_secs(604_800)).to_string(), "7d");
+        assert_eq!(Human(Duration::from_secs(5_400)).to_string(), "1h");
+    }
+
+    #[test]
+    fn test_expiry() {
+        let now = unix_now();
+
+        assert!(!Record::now(Some(now + 60)).is_stale());
+        assert!(Record::now(Some(now - 60)).is_expired());
+        assert!(!Record::now(None).is_stale());
+        assert!(
+            Record {
+                fetched: now - DEFAULT_MAX_AGE.as_secs() - 1,
+                valid_until: None
+            }
+            .is_stale()
+        );
+    }
+}
@@ -0,0 +1,287 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Repository health diagnostics
+//!
+//! [`check`] runs a series of [`Check`]s against a configured repository, from whether
+//! its index can be fetched at all to whether the packages it lists can be downloaded,
+//! so a failing sync can be traced back to the repository at fault.
+
+use std::{
+    io::Cursor,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use futures_util::{StreamExt, stream};
+use url::Url;
+
+use crate::{package, request, signing::PublicKey};
+
+use super::{Repository, expiry, fetch_bytes, fetch_text, index, local};
+
+/// Clock difference to the server beyond which metadata validity & TLS can't be trusted
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Outcome of a [`Check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warning,
+    Failed,
+    /// The check doesn't apply to the repository
+    Skipped,
+}
+
+/// A single diagnostic of a repository
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Check the health of `repo`
+///
+/// The signature of the index is verified against the ed25519 public `key` if provided,
+/// and up to `sample` of its packages are checked for availability.
+pub async fn check(repo: &Repository, key: Option<&PublicKey>, sample: usize) -> Vec<Check> {
+    let mut checks = vec![];
+
+    let started = Instant::now();
+    let fetched = match local::directory(&repo.uri) {
+        Some(dir) => generate_index(&dir),
+        None => fetch_bytes(repo.uri.clone()).await.map_err(|error| describe(&error)),
+    };
+    let contents = match fetched {
+        Ok(contents) => {
+            checks.push(Check::new(
+                "reachable",
+                Status::Ok,
+                format!(
+                    "fetched {} bytes in {}ms",
+                    contents.len(),
+                    started.elapsed().as_millis()
+                ),
+            ));
+            contents
+        }
+        Err(error) => {
+            checks.push(Check::new("reachable", Status::Failed, error));
+            return checks;
+        }
+    };
+
+    let packages = match index::detect(Cursor::new(&contents))
+        .and_then(|format| Ok((format, index::read(Cursor::new(&contents))?)))
+    {
+        Ok((format, packages)) => {
+            checks.push(Check::new(
+                "index",
+                Status::Ok,
+                format!("{} packages, {format} format", packages.len()),
+            ));
+            packages
+        }
+        Err(error) => {
+            checks.push(Check::new("index", Status::Failed, describe(&error)));
+            return checks;
+        }
+    };
+
+    checks.push(check_signature(&repo.uri, &contents, key).await);
+    checks.push(check_validity(&contents));
+    checks.push(check_clock(&repo.uri).await);
+
+    if sample > 0 {
+        checks.push(check_packages(&repo.uri, &packages, sample).await);
+    }
+
+    checks
+}
+
+/// Generate the index of a [`local`] directory repository in memory
+fn generate_index(dir: &Path) -> Result<Vec<u8>, String> {
+    let packages = local::scan(dir).map_err(|error| describe(&error))?;
+
+    let mut contents = Cursor::new(vec![]);
+    index::write(&mut contents, index::Format::V1, packages, None).map_err(|error| describe(&error))?;
+
+    Ok(contents.into_inner())
+}
+
+/// Verify the detached signature published as `stone.index.sig`
+async fn check_signature(uri: &Url, contents: &[u8], key: Option<&PublicKey>) -> Check {
+    const NAME: &str = "signature";
+
+    let signature = match Url::parse(&format!("{uri}.sig")) {
+        Ok(url) => fetch_text(url).await.ok(),
+        Err(_) => None,
+    };
+
+    match (signature, key) {
+        (None, None) => Check::new(NAME, Status::Skipped, "index isn't signed"),
+        (None, Some(_)) => Check::new(NAME, Status::Failed, "index isn't signed, but a key was given"),
+        (Some(_), None) => Check::new(NAME, Status::Skipped, "index is signed, pass a key to verify it"),
+        (Some(signature), Some(key)) => {
+            if key.verify(contents, signature.as_bytes()).is_ok() {
+                Check::new(NAME, Status::Ok, "valid")
+            } else {
+                Check::new(NAME, Status::Failed, "doesn't match the index & key")
+            }
+        }
+    }
+}
+
+/// Check the validity declared by the index `contents`, see [`expiry`]
+fn check_validity(contents: &[u8]) -> Check {
+    const NAME: &str = "validity";
+
+    let valid_until = match index::valid_until(Cursor::new(contents)) {
+        Ok(Some(valid_until)) => valid_until,
+        Ok(None) => return Check::new(NAME, Status::Skipped, "no validity declared"),
+        Err(error) => return Check::new(NAME, Status::Failed, describe(&error)),
+    };
+
+    let now = expiry::unix_now();
+    if now > valid_until {
+        Check::new(
+            NAME,
+            Status::Failed,
+            format!(
+                "expired {} ago, the mirror may be frozen or replaying old metadata",
+                expiry::Human(Duration::from_secs(now - valid_until))
+            ),
+        )
+    } else {
+        Check::new(
+            NAME,
+            Status::Ok,
+            format!("valid for {}", expiry::Human(Duration::from_secs(valid_until - now))),
+        )
+    }
+}
+
+/// Compare the local clock with the time reported by the server
+async fn check_clock(uri: &Url) -> Check {
+    const NAME: &str = "clock";
+
+    if uri.scheme() == "file" {
+        return Check::new(NAME, Status::Skipped, "local repository");
+    }
+
+    match request::probe(uri.clone()).await {
+        Ok(Some(server)) => {
+            let skew = Utc::now().signed_duration_since(server);
+            let (direction, skew) = if skew.num_seconds() >= 0 {
+                ("ahead of", skew.to_std().unwrap_or_default())
+            } else {
+                ("behind", (-skew).to_std().unwrap_or_default())
+            };
+
+            if skew > MAX_CLOCK_SKEW {
+                Check::new(
+                    NAME,
+                    Status::Warning,
+                    format!("local clock is {} {direction} the server", expiry::Human(skew)),
+                )
+            } else {
+                Check::new(
+                    NAME,
+                    Status::Ok,
+                    format!("within {} of the server", expiry::Human(skew)),
+                )
+            }
+        }
+        Ok(None) => Check::new(NAME, Status::Skipped, "server doesn't report its time"),
+        Err(error) => Check::new(NAME, Status::Warning, describe(&error)),
+    }
+}
+
+/// Check a `sample` of the packages spread across the index can be downloaded
+async fn check_packages(uri: &Url, packages: &[package::Meta], sample: usize) -> Check {
+    const NAME: &str = "packages";
+
+    let sampled = spread(packages, sample);
+
+    let unavailable = stream::iter(&sampled)
+        .map(|meta| async move {
+            let available = match meta.uri.as_deref().map(|relative| uri.join(relative)) {
+                Some(Ok(url)) => request::probe(url).await.is_ok(),
+                _ => false,
+            };
+            (!available).then(|| meta.name.to_string())
+        })
+        .buffer_unordered(request::concurrency())
+        .filter_map(|name| async { name })
+        .collect::<Vec<_>>()
+        .await;
+
+    if unavailable.is_empty() {
+        Check::new(
+            NAME,
+            Status::Ok,
+            format!("{} of {} sampled available", sampled.len(), sampled.len()),
+        )
+    } else {
+        Check::new(
+            NAME,
+            Status::Failed,
+            format!(
+                "{} of {} sampled unavailable: {}",
+                unavailable.len(),
+                sampled.len(),
+                unavailable.join(", ")
+            ),
+        )
+    }
+}
+
+/// Up to `count` items spread evenly across `items`
+fn spread<T>(items: &[T], count: usize) -> Vec<&T> {
+    if items.is_empty() || count == 0 {
+        return vec![];
+    }
+
+    let step = items.len().div_ceil(count).max(1);
+    items.iter().step_by(step).take(count).collect()
+}
+
+/// Describe an error along with its sources
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        description.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+    description
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spread() {
+        let items = (0..10).collect::<Vec<_>>();
+
+        assert_eq!(spread(&items, 3), [&0, &4, &8]);
+        assert_eq!(spread(&items, 20).len(), 10);
+        assert!(spread(&items, 0).is_empty());
+        assert!(spread::<u8>(&[], 3).is_empty());
+    }
+}
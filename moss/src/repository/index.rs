@@ -0,0 +1,399 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Repository index formats
+//!
+//! A `stone.index` is either:
+//!
+//! - [`Format::V1`]: a stone repository file with one meta payload per package
+//! - [`Format::V2`]: a compressed index with the metadata split into sections, so the
+//!   repetitive relations of all packages compress together
+//!
+//! Readers detect the format from the leading magic, so repositories can switch
+//! without any client configuration.
+//!
+//! # V2 layout
+//!
+//! All integers are big endian.
+//!
+//! ```text
+//! magic        b"MOSSIDX\x02"
+//! packages     u32
+//! sections     u8
+//! section      kind u8, stored size u64, plain size u64, zstd compressed body
+//! ...
+//! ```
+//!
+//! Each section body holds, for every package in order, a `u32` record count followed
+//! by the stone meta records of that package belonging to the section. Sections with
+//! an unknown kind are skipped, so new ones can be added without breaking readers.
+//!
+//! The validity section ([`VALIDITY_SECTION`]) holds the `u64` unix time the index is
+//! valid until, covered by the signature of the index unlike a separately published file.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use stone::payload::{self, meta::Tag};
+use thiserror::Error;
+
+use crate::package::{Meta, MissingMetaFieldError};
+
+const MAGIC: &[u8; 8] = b"MOSSIDX\x02";
+
+/// Compression level of v2 sections, indices are written once & read often
+const COMPRESSION_LEVEL: i32 = 19;
+
+/// Format of a `stone.index`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Format {
+    #[default]
+    V1,
+    V2,
+}
+
+/// Kind of the v2 section holding the validity of the index, see [`valid_until`]
+const VALIDITY_SECTION: u8 = 4;
+
+/// Sections of a v2 index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Section {
+    /// Names, versions & everything else describing the package itself
+    Core = 1,
+    /// What the package provides, replaces & conflicts with
+    Providers = 2,
+    /// What the package depends on
+    Dependencies = 3,
+}
+
+impl Section {
+    const ALL: [Self; 3] = [Self::Core, Self::Providers, Self::Dependencies];
+
+    fn from_u8(kind: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|section| *section as u8 == kind)
+    }
+
+    fn of(tag: Tag) -> Self {
+        match tag {
+            Tag::Provides | Tag::Replaces | Tag::Conflicts => Self::Providers,
+            Tag::Depends => Self::Dependencies,
+            _ => Self::Core,
+        }
+    }
+}
+
+/// Detect the format of the index read by `reader`, leaving it at the start
+pub fn detect<R: Read + Seek>(mut reader: R) -> Result<Format, Error> {
+    let mut magic = [0u8; MAGIC.len()];
+    let format = match reader.read_exact(&mut magic) {
+        Ok(()) if &magic == MAGIC => Format::V2,
+        Ok(()) => Format::V1,
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Format::V1,
+        Err(error) => return Err(error.into()),
+    };
+    reader.seek(SeekFrom::Start(0))?;
+
+    Ok(format)
+}
+
+/// Read the packages of an index in any [`Format`]
+pub fn read<R: Read + Seek>(mut reader: R) -> Result<Vec<Meta>, Error> {
+    let records = match detect(&mut reader)? {
+        Format::V1 => stone::read(reader)?
+            .payloads()?
+            .filter_map(|payload| match payload {
+                Ok(stone::read::PayloadKind::Meta(meta)) => Some(Ok(meta.body)),
+                Ok(_) => None,
+                Err(error) => Some(Err(error)),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        Format::V2 => read_v2(reader)?,
+    };
+
+    Ok(records
+        .iter()
+        .map(|records| Meta::from_stone_payload(records))
+        .collect::<Result<_, _>>()?)
+}
+
+/// The unix time the index read by `reader` is valid until, if it declares one
+///
+/// Only [`Format::V2`] indices declare a validity.
+pub fn valid_until<R: Read + Seek>(mut reader: R) -> Result<Option<u64>, Error> {
+    if detect(&mut reader)? == Format::V1 {
+        return Ok(None);
+    }
+
+    reader.read_exact(&mut [0u8; MAGIC.len()])?;
+    let _num_packages = read_u32(&mut reader)?;
+    let num_sections = read_u8(&mut reader)?;
+
+    for _ in 0..num_sections {
+        let kind = read_u8(&mut reader)?;
+        let stored_size = read_u64(&mut reader)?;
+        let _plain_size = read_u64(&mut reader)?;
+
+        if kind != VALIDITY_SECTION {
+            let offset = i64::try_from(stored_size).map_err(|_| Error::Corrupt("section exceeds the index"))?;
+            reader.seek(SeekFrom::Current(offset))?;
+            continue;
+        }
+
+        let mut plain = vec![];
+        zstd::Decoder::new((&mut reader).take(stored_size))?
+            .take(8)
+            .read_to_end(&mut plain)?;
+        let valid_until = plain.try_into().map_err(|_| Error::Corrupt("validity size mismatch"))?;
+
+        return Ok(Some(u64::from_be_bytes(valid_until)));
+    }
+
+    Ok(None)
+}
+
+fn read_v2<R: Read + Seek>(mut reader: R) -> Result<Vec<Vec<payload::Meta>>, Error> {
+    reader.read_exact(&mut [0u8; MAGIC.len()])?;
+
+    let num_packages = read_u32(&mut reader)? as usize;
+    let num_sections = read_u8(&mut reader)?;
+
+    // Counts & sizes come from an unverified header, so nothing is allocated
+    // beyond what the input actually holds
+    let position = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+
+    let mut packages: Vec<Vec<payload::Meta>> = vec![];
+
+    for _ in 0..num_sections {
+        let kind = read_u8(&mut reader)?;
+        let stored_size = read_u64(&mut reader)?;
+        let plain_size = read_u64(&mut reader)?;
+
+        if stored_size > end.saturating_sub(reader.stream_position()?) {
+            return Err(Error::Corrupt("section exceeds the index"));
+        }
+
+        let mut stored = vec![0; stored_size as usize];
+        reader.read_exact(&mut stored)?;
+
+        if Section::from_u8(kind).is_none() {
+            continue;
+        }
+
+        let mut plain = vec![];
+        zstd::Decoder::with_buffer(stored.as_slice())?
+            .take(plain_size)
+            .read_to_end(&mut plain)?;
+        if plain.len() as u64 != plain_size {
+            return Err(Error::Corrupt("section size mismatch"));
+        }
+        let mut body = plain.as_slice();
+
+        for index in 0..num_packages {
+            let num_records = read_u32(&mut body)? as usize;
+            // Every record takes up at least a byte
+            if num_records > body.len() {
+                return Err(Error::Corrupt("record count exceeds the section"));
+            }
+
+            let records = payload::meta::decode_records(&mut body, num_records)?;
+            match packages.get_mut(index) {
+                Some(existing) => existing.extend(records),
+                None => packages.push(records),
+            }
+        }
+    }
+
+    Ok(packages)
+}
+
+/// Write `packages` as an index in the given [`Format`], declaring it valid until the
+/// unix time `valid_until` if set
+pub fn write<W: Write + Seek>(
+    writer: W,
+    format: Format,
+    packages: Vec<Meta>,
+    valid_until: Option<u64>,
+) -> Result<(), Error> {
+    match format {
+        Format::V1 if valid_until.is_some() => Err(Error::ValidityUnsupported),
+        Format::V1 => {
+            let mut writer = stone::Writer::new(writer, stone::header::v1::FileType::Repository)?;
+            for meta in packages {
+                writer.add_payload(meta.to_stone_payload().as_slice())?;
+            }
+            Ok(writer.finalize()?)
+        }
+        Format::V2 => write_v2(writer, packages, valid_until),
+    }
+}
+
+fn write_v2<W: Write>(mut writer: W, packages: Vec<Meta>, valid_until: Option<u64>) -> Result<(), Error> {
+    let num_packages = packages.len() as u32;
+    let mut bodies = Section::ALL.map(|_| vec![]);
+
+    for meta in packages {
+        let records = meta.to_stone_payload();
+
+        for (section, body) in Section::ALL.iter().zip(&mut bodies) {
+            let records = records
+                .iter()
+                .filter(|record| Section::of(record.tag) == *section)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            body.write_all(&(records.len() as u32).to_be_bytes())?;
+            payload::encode_records(body, &records)?;
+        }
+    }
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&num_packages.to_be_bytes())?;
+    writer.write_all(&[(Section::ALL.len() + usize::from(valid_until.is_some())) as u8])?;
+
+    let validity = valid_until.map(|valid_until| (VALIDITY_SECTION, valid_until.to_be_bytes().to_vec()));
+    let sections = Section::ALL.map(|section| section as u8).into_iter().zip(bodies);
+
+    for (kind, body) in sections.chain(validity) {
+        let stored = zstd::bulk::compress(&body, COMPRESSION_LEVEL)?;
+
+        writer.write_all(&[kind])?;
+        writer.write_all(&(stored.len() as u64).to_be_bytes())?;
+        writer.write_all(&(body.len() as u64).to_be_bytes())?;
+        writer.write_all(&stored)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("read stone index")]
+    ReadStone(#[from] stone::read::Error),
+    #[error("write stone index")]
+    WriteStone(#[from] stone::write::Error),
+    #[error("decode index records")]
+    Decode(#[from] payload::DecodeError),
+    #[error("encode index records")]
+    Encode(#[from] payload::EncodeError),
+    #[error(transparent)]
+    MissingMetaField(#[from] MissingMetaFieldError),
+    #[error("corrupt index: {0}")]
+    Corrupt(&'static str),
+    #[error("only v2 indices declare a validity")]
+    ValidityUnsupported,
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{Dependency, Provider, dependency};
+
+    fn meta(name: &str) -> Meta {
+        let mut meta = Meta {
+            hash: Some(format!("{name}-hash")),
+            ..Meta::test(name)
+        };
+        meta.dependencies.insert(Dependency {
+            kind: dependency::Kind::SharedLibrary,
+            name: "libc.so.6(x86_64)".to_owned(),
+        });
+        meta.providers.insert(Provider {
+            kind: dependency::Kind::Binary,
+            name: name.to_owned(),
+        });
+        meta
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let packages = vec![meta("a"), meta("b"), meta("c")];
+
+        for format in [Format::V1, Format::V2] {
+            let mut buffer = Cursor::new(vec![]);
+            write(&mut buffer, format, packages.clone(), None).unwrap();
+
+            buffer.set_position(0);
+            assert_eq!(detect(&mut buffer).unwrap(), format);
+            assert_eq!(read(buffer).unwrap(), packages);
+        }
+    }
+
+    #[test]
+    fn test_valid_until() {
+        let packages = vec![meta("a"), meta("b")];
+
+        let mut buffer = Cursor::new(vec![]);
+        write(&mut buffer, Format::V2, packages.clone(), Some(1_700_000_000)).unwrap();
+        buffer.set_position(0);
+        assert_eq!(valid_until(&mut buffer).unwrap(), Some(1_700_000_000));
+        buffer.set_position(0);
+        assert_eq!(read(buffer).unwrap(), packages);
+
+        let mut buffer = Cursor::new(vec![]);
+        write(&mut buffer, Format::V2, packages.clone(), None).unwrap();
+        buffer.set_position(0);
+        assert_eq!(valid_until(buffer).unwrap(), None);
+
+        assert!(matches!(
+            write(Cursor::new(vec![]), Format::V1, packages, Some(1_700_000_000)),
+            Err(Error::ValidityUnsupported)
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_header() {
+        let mut index = Cursor::new(vec![]);
+        write(&mut index, Format::V2, vec![meta("a"), meta("b")], None).unwrap();
+        let index = index.into_inner();
+
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut index = index.clone();
+            index[offset..offset + bytes.len()].copy_from_slice(bytes);
+            read(Cursor::new(index))
+        };
+        let section = MAGIC.len() + 4 + 1;
+
+        // Package count
+        assert!(corrupt(MAGIC.len(), &u32::MAX.to_be_bytes()).is_err());
+        // Stored size
+        assert!(matches!(
+            corrupt(section + 1, &u64::MAX.to_be_bytes()),
+            Err(Error::Corrupt(_))
+        ));
+        // Plain size
+        assert!(matches!(
+            corrupt(section + 9, &u64::MAX.to_be_bytes()),
+            Err(Error::Corrupt(_))
+        ));
+        // Truncated
+        assert!(read(Cursor::new(&index[..index.len() - 1])).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Local directory repositories
+//!
+//! A `file://` repository URI may point at a directory of stones rather than at a
+//! `stone.index`, in which case the index is generated from the stones whenever the
+//! repository is refreshed. The generated index carries the newest modification time
+//! of the directory tree, so any stone being added, rebuilt or removed is noticed by
+//! [`is_changed`] and the repository reindexed without running `moss index`.
+
+use std::{
+    collections::{BTreeMap, btree_map},
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+use fs_err::{self as fs, File};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+use crate::package::{Meta, MissingMetaFieldError};
+
+use super::index;
+
+/// Whether clients reindex changed local directory repositories, see [`set_reindex`]
+static REINDEX: AtomicBool = AtomicBool::new(true);
+
+/// Reindex changed local directory repositories when building a client, or leave
+/// them be, i.e. for queries which mustn't write to the repositories
+pub fn set_reindex(reindex: bool) {
+    REINDEX.store(reindex, Ordering::Relaxed);
+}
+
+/// Whether changed local directory repositories are reindexed, see [`set_reindex`]
+pub fn reindex() -> bool {
+    REINDEX.load(Ordering::Relaxed)
+}
+
+/// The directory of stones a repository `uri` points at, if it's a local directory
+pub fn directory(uri: &Url) -> Option<PathBuf> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+
+    uri.to_file_path().ok().filter(|path| path.is_dir())
+}
+
+/// Ensure a `uri` pointing at a local directory ends with a `/`, so package
+/// URIs relative to the directory resolve within it
+pub fn normalize(mut uri: Url) -> Url {
+    if directory(&uri).is_some() && !uri.path().ends_with('/') {
+        uri.set_path(&format!("{}/", uri.path()));
+    }
+    uri
+}
+
+/// Whether the stones in `dir` changed since the index at `index_path` was generated
+pub fn is_changed(dir: &Path, index_path: &Path) -> bool {
+    let Ok(indexed) = fs::metadata(index_path).and_then(|metadata| metadata.modified()) else {
+        return true;
+    };
+
+    newest_modification(dir).is_ok_and(|newest| newest != indexed)
+}
+
+/// Generate a [`index::Format::V1`] index of the stones in `dir` at `index_path`
+pub fn write_index(dir: &Path, index_path: &Path) -> Result<(), Error> {
+    // Read before scanning, so stones changed while scanning are picked up next time
+    let newest = newest_modification(dir)?;
+
+    let mut file = File::create(index_path)?;
+    index::write(&mut file, index::Format::V1, scan(dir)?, None)?;
+    file.file().set_modified(newest)?;
+
+    Ok(())
+}
+
+/// Read the metadata of the stones in `dir`, keeping the latest of each package
+pub fn scan(dir: &Path) -> Result<Vec<Meta>, Error> {
+    let mut packages = BTreeMap::new();
+
+    for path in stones(dir)? {
+        let modified = fs::metadata(&path)?.modified()?;
+        let meta = read_meta(dir, &path)?;
+
+        // Keep the latest release, or the latest build of a release
+        match packages.entry(meta.name.clone()) {
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert((meta, modified));
+            }
+            btree_map::Entry::Occupied(mut entry) => {
+                let (current, current_modified) = entry.get();
+                if (meta.source_release, modified) > (current.source_release, *current_modified) {
+                    entry.insert((meta, modified));
+                }
+            }
+        }
+    }
+
+    Ok(packages.into_values().map(|(meta, _)| meta).collect())
+}
+
+/// Read the metadata of the stone at `path`, relative to `dir` for its URI
+fn read_meta(dir: &Path, path: &Path) -> Result<Meta, Error> {
+    let relative_path = path
+        .strip_prefix(dir)
+        .ok()
+        .and_then(Path::to_str)
+        .ok_or_else(|| Error::NonUtf8Path(path.to_owned()))?;
+
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let payloads = stone::read(&mut file)
+        .and_then(|mut reader| reader.payloads()?.collect::<Result<Vec<_>, _>>())
+        .map_err(|source| Error::ReadStone {
+            source,
+            path: path.to_owned(),
+        })?;
+    let payload = payloads
+        .iter()
+        .find_map(|payload| payload.meta())
+        .ok_or_else(|| Error::MissingMetaPayload(path.to_owned()))?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    let mut meta = Meta::from_stone_payload(&payload.body)?;
+    meta.hash = Some(hex::encode(hasher.finalize()));
+    meta.download_size = Some(size);
+    meta.installed_size = payloads
+        .iter()
+        .find_map(|payload| payload.content())
+        .map(|content| content.header.plain_size);
+    meta.uri = Some(relative_path.to_owned());
+
+    Ok(meta)
+}
+
+/// All stones within `dir`
+fn stones(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            paths.extend(stones(&path)?);
+        } else if file_type.is_file() && path.extension().is_some_and(|extension| extension == "stone") {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Newest modification time of `dir`, its subdirectories & stones
+///
+/// Directories are included so removed stones are noticed.
+fn newest_modification(dir: &Path) -> io::Result<SystemTime> {
+    let mut newest = fs::metadata(dir)?.modified()?;
+
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        let modified = if file_type.is_dir() {
+            newest_modification(&path)?
+        } else if file_type.is_file() && path.extension().is_some_and(|extension| extension == "stone") {
+            entry.metadata()?.modified()?
+        } else {
+            continue;
+        };
+
+        newest = newest.max(modified);
+    }
+
+    Ok(newest)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("io")]
+    Io(#[from] io::Error),
+    #[error("reading {path}")]
+    ReadStone { source: stone::read::Error, path: PathBuf },
+    #[error("meta payload missing from {0}")]
+    MissingMetaPayload(PathBuf),
+    #[error(transparent)]
+    MissingMetaField(#[from] MissingMetaFieldError),
+    #[error("non-utf8 path: {0}")]
+    NonUtf8Path(PathBuf),
+    #[error("write index")]
+    WriteIndex(#[from] index::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let uri = Url::from_file_path(&dir).unwrap();
+
+        assert!(!uri.path().ends_with('/'));
+        assert_eq!(directory(&uri), Some(dir.clone()));
+
+        let normalized = normalize(uri);
+        assert!(normalized.path().ends_with('/'));
+        assert_eq!(
+            normalized.join("a.stone").unwrap().to_file_path().unwrap(),
+            dir.join("a.stone")
+        );
+
+        // Index URIs are left alone
+        let index = Url::parse("https://example.com/stone.index").unwrap();
+        assert_eq!(normalize(index.clone()), index);
+    }
+}
@@ -5,19 +5,25 @@
 use std::collections::BTreeMap;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 use fs_err::{self as fs, File};
 use futures_util::{StreamExt, TryStreamExt, stream};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tracing::{debug, info, warn};
+use tracing_common::progress;
 use xxhash_rust::xxh3::xxh3_64;
 
 use tui::{MultiProgress, ProgressBar, ProgressStyle, Styled};
 
+use crate::client::cache;
 use crate::db::meta;
-use crate::repository::{self, Repository};
-use crate::{Installation, package};
-use crate::{environment, runtime};
+use crate::repository::{self, Repository, alias, delta, expiry, index, local, profile};
+use crate::{Installation, package, state};
+use crate::{environment, request, runtime};
 
 enum Source {
     System(config::Manager),
@@ -84,7 +90,9 @@ impl Manager {
         // Open all repo meta dbs and collect into hash map
         let repositories = configs
             .into_iter()
-            .map(|(id, repository)| {
+            .map(|(id, mut repository)| {
+                repository.uri = local::normalize(repository.uri);
+                track_source(&id, &repository);
                 let db = open_meta_db(source.identifier(), &repository, &installation)?;
 
                 Ok((id.clone(), repository::Cached { id, repository, db }))
@@ -99,23 +107,49 @@ impl Manager {
     }
 
     /// Add a [`Repository`]
-    pub fn add_repository(&mut self, id: repository::Id, repository: Repository) -> Result<(), Error> {
+    ///
+    /// The repository index is fetched & loaded before the config is committed, so
+    /// an unusable repository is never saved. The previous configuration is kept as
+    /// a backup restorable via [`Manager::undo`].
+    pub async fn add_repository(&mut self, id: repository::Id, mut repository: Repository) -> Result<(), Error> {
         let Source::System(config) = &self.source else {
             return Err(Error::ExplicitUnsupported);
         };
 
+        repository.uri = local::normalize(repository.uri);
+        track_source(&id, &repository);
+
+        let cache_dir = cache_dir(self.source.identifier(), &repository, &self.installation);
+        let is_new_cache = !cache_dir.exists();
+
+        let db = open_meta_db(self.source.identifier(), &repository, &self.installation)?;
+        let cached = repository::Cached {
+            id: id.clone(),
+            repository,
+            db,
+        };
+
+        // Validate with a test index load before committing
+        if cached.repository.active
+            && let Err(error) = load_index(self.source.identifier(), cached.clone(), &self.installation).await
+        {
+            if is_new_cache {
+                let _ = fs::remove_dir_all(&cache_dir);
+            }
+            return Err(Error::Validate(id, Box::new(error)));
+        }
+
         // Save repo as new config file
         // We save it as a map for easy merging across
         // multiple configuration files
         {
-            let map = repository::Map::with([(id.clone(), repository.clone())]);
+            let map = repository::Map::with([(id.clone(), cached.repository.clone())]);
+            config.backup::<repository::Map>().map_err(Error::BackupConfig)?;
             config.save(&id, &map).map_err(Error::SaveConfig)?;
+            profile::record_active(&self.installation, None).map_err(Error::RecordProfile)?;
         }
 
-        let db = open_meta_db(self.source.identifier(), &repository, &self.installation)?;
-
-        self.repositories
-            .insert(id.clone(), repository::Cached { id, repository, db });
+        self.repositories.insert(id, cached);
 
         Ok(())
     }
@@ -127,8 +161,87 @@ impl Manager {
         };
 
         if repo.repository.active {
-            let file = fetch_index(self.source.identifier(), &repo, &self.installation).await?;
-            runtime::unblock(move || update_meta_db(&repo, &file)).await?;
+            load_index(self.source.identifier(), repo, &self.installation).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Refresh each of the [`Repository`]'s by Id, carrying on past failures
+    ///
+    /// Returns the outcome for each repository
+    pub async fn refresh_each(&self, ids: &[repository::Id]) -> Vec<(repository::Id, Result<(), Error>)> {
+        let mpb = &MultiProgress::with_draw_target(tui::progress_target());
+
+        stream::iter(ids)
+            .map(|id| async move {
+                let pb = mpb.add(refresh_spinner(id));
+
+                let result = self.refresh(id).await;
+
+                pb.suspend(|| match &result {
+                    Ok(()) if tui::is_quiet() => {}
+                    Ok(()) => println!("{} {id}", "Refreshed".green()),
+                    Err(_) => println!("{} {id}", "Failed".red()),
+                });
+
+                (id.clone(), result)
+            })
+            .buffered(request::concurrency())
+            .collect()
+            .await
+    }
+
+    /// Refresh the [`Repository`]'s by Id, all or nothing
+    ///
+    /// Every index is fetched & read before any meta db is touched, so a repository
+    /// which can't be fetched or has a corrupt index leaves all of them as they were.
+    /// Deltas which don't apply to their meta db are replaced by the full index upfront.
+    pub async fn refresh_atomic(&self, ids: &[repository::Id]) -> Result<(), Error> {
+        let repos = ids
+            .iter()
+            .map(|id| self.repositories.get(id).ok_or_else(|| Error::UnknownRepo(id.clone())))
+            .filter_ok(|repo| repo.repository.active)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mpb = MultiProgress::with_draw_target(tui::progress_target());
+
+        let fetched = stream::iter(&repos)
+            .map(|repo| async {
+                let pb = mpb.add(refresh_spinner(&repo.id));
+                let dir = cache_dir(self.source.identifier(), &repo.repository, &self.installation);
+
+                let result = fetch_validated(repo, &dir)
+                    .await
+                    .map_err(|error| Error::Refresh(repo.id.clone(), Box::new(error)));
+
+                pb.finish_and_clear();
+
+                result
+            })
+            .buffered(request::concurrency())
+            .collect::<Vec<_>>()
+            .await;
+
+        if let Some(position) = fetched.iter().position(Result::is_err) {
+            let mut fetched = fetched;
+            let error = fetched.remove(position).err().expect("position of an error");
+            fetched.into_iter().flatten().for_each(Update::discard);
+            return Err(error);
+        }
+
+        let mut updates = repos.into_iter().zip(fetched.into_iter().flatten());
+        while let Some((repo, update)) = updates.next() {
+            let dir = cache_dir(self.source.identifier(), &repo.repository, &self.installation);
+
+            if let Err(error) = apply_or_refetch(repo.clone(), &dir, update).await {
+                updates.for_each(|(_, update)| update.discard());
+                return Err(Error::Refresh(repo.id.clone(), Box::new(error)));
+            }
+
+            if !tui::is_quiet() {
+                println!("{} {}", "Refreshed".green(), repo.id);
+            }
         }
 
         Ok(())
@@ -137,32 +250,37 @@ impl Manager {
     /// Refresh all [`Repository`]'s by fetching it's latest index
     /// file and updating it's associated meta database
     pub async fn refresh_all(&mut self) -> Result<(), Error> {
-        let mpb = MultiProgress::new();
+        let mpb = MultiProgress::with_draw_target(tui::progress_target());
+
+        let active = self.repositories.values().filter(|r| r.repository.active).count();
+        let phase = progress::AsyncPhase::start("repo-update", active);
+        let refreshed = &AtomicUsize::new(0);
+        let (this, mpb) = (&*self, &mpb);
 
         // Fetch index files asynchronously and then
         // update to DB
-        stream::iter(self.repositories.iter().filter(|(_, r)| r.repository.active))
-            .map(|(id, _)| async {
-                let pb = mpb.add(
-                    ProgressBar::new_spinner()
-                        .with_style(
-                            ProgressStyle::with_template(" {spinner} {wide_msg}")
-                                .unwrap()
-                                .tick_chars("--=≡■≡=--"),
-                        )
-                        .with_message(format!("{} {}", "Refreshing".blue(), *id)),
-                );
-                pb.enable_steady_tick(Duration::from_millis(150));
+        let refresh = stream::iter(this.repositories.iter().filter(|(_, r)| r.repository.active))
+            .map(|(id, _)| async move {
+                let pb = mpb.add(refresh_spinner(id));
 
-                self.refresh(id).await?;
+                this.refresh(id).await?;
+
+                if !tui::is_quiet() {
+                    pb.suspend(|| println!("{} {}", "Refreshed".green(), *id));
+                }
 
-                pb.suspend(|| println!("{} {}", "Refreshed".green(), *id));
+                let current = refreshed.fetch_add(1, Ordering::Relaxed) + 1;
+                progress::update(current, active, format_args!("Refreshed {id}"));
 
-                Ok(())
+                Ok::<_, Error>(())
             })
-            .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
-            .try_collect()
-            .await
+            .buffer_unordered(request::concurrency())
+            .try_collect::<()>();
+        phase.instrument(refresh).await?;
+
+        phase.complete(active);
+
+        Ok(())
     }
 
     /// Ensures all repositories are initialized - index file downloaded and meta db
@@ -187,67 +305,244 @@ impl Manager {
             return Ok(0);
         }
 
-        let mpb = MultiProgress::new();
+        let mpb = MultiProgress::with_draw_target(tui::progress_target());
 
         // Fetch index files asynchronously and then
         // update to DB
         stream::iter(&uninitialized)
             .map(|id| async {
-                let pb = mpb.add(
-                    ProgressBar::new_spinner()
-                        .with_style(
-                            ProgressStyle::with_template(" {spinner} {wide_msg}")
-                                .unwrap()
-                                .tick_chars("--=≡■≡=--"),
-                        )
-                        .with_message(format!("{} {}", "Refreshing".blue(), *id)),
-                );
-                pb.enable_steady_tick(Duration::from_millis(150));
+                let pb = mpb.add(refresh_spinner(id));
 
                 self.refresh(id).await?;
 
-                pb.suspend(|| println!("{} {}", "Refreshed".green(), *id));
+                if !tui::is_quiet() {
+                    pb.suspend(|| println!("{} {}", "Refreshed".green(), *id));
+                }
 
                 Ok(()) as Result<_, Error>
             })
-            .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
+            .buffer_unordered(request::concurrency())
             .try_collect::<()>()
             .await?;
 
         Ok(uninitialized.len())
     }
 
+    /// Active local directory repositories whose stones changed since they were indexed
+    pub fn changed_local(&self) -> Vec<repository::Id> {
+        self.repositories
+            .values()
+            .filter(|state| state.repository.active)
+            .filter(|state| {
+                local::directory(&state.repository.uri).is_some_and(|dir| {
+                    let index_file =
+                        cache_dir(self.source.identifier(), &state.repository, &self.installation).join("stone.index");
+                    index_file.exists() && local::is_changed(&dir, &index_file)
+                })
+            })
+            .map(|state| state.id.clone())
+            .collect()
+    }
+
     /// Returns the active repositories held by this manager
     pub(crate) fn active(&self) -> impl Iterator<Item = repository::Cached> + '_ {
         self.repositories.values().filter(|c| c.repository.active).cloned()
     }
 
-    /// Remove a repository, deleting any related config & cached data
-    pub fn remove(&mut self, id: impl Into<repository::Id>) -> Result<Removal, Error> {
+    /// The active repositories along with a hash of their cached index, recorded
+    /// in each new state so it can later be reproduced or compared against
+    pub fn snapshot(&self) -> Vec<state::Repository> {
+        self.repositories
+            .values()
+            .filter(|c| c.repository.active)
+            .map(|c| state::Repository {
+                id: c.id.clone(),
+                uri: c.repository.uri.clone(),
+                priority: c.repository.priority,
+                index_hash: index_hash(&cache_dir(self.source.identifier(), &c.repository, &self.installation)),
+            })
+            .collect()
+    }
+
+    /// Resolve an alternative package name via the aliases of active repositories,
+    /// honouring repository priority
+    ///
+    /// Aliases published by a repository may be overridden by those in its config.
+    pub fn alias(&self, name: &str) -> Option<String> {
+        self.repositories
+            .values()
+            .filter(|c| c.repository.active)
+            .sorted_by_key(|c| c.repository.priority)
+            .find_map(|c| {
+                c.repository.aliases.get(name).cloned().or_else(|| {
+                    alias::load(&cache_dir(self.source.identifier(), &c.repository, &self.installation)).remove(name)
+                })
+            })
+    }
+
+    /// Download cache partition of the repository, unique to its identity
+    pub fn partition(&self, id: &repository::Id) -> Option<String> {
+        self.repositories
+            .get(id)
+            .map(|repo| cache_key(self.source.identifier(), &repo.repository))
+    }
+
+    /// Returns the active repository `package` would be downloaded from,
+    /// honouring repository priority
+    pub(crate) fn origin(&self, package: &package::Id, meta: &package::Meta) -> Option<repository::Id> {
+        self.repositories
+            .values()
+            .filter(|c| c.repository.active)
+            .sorted_by_key(|c| std::cmp::Reverse(c.repository.priority))
+            .find(|c| c.db.get(package).is_ok_and(|found| found.hash == meta.hash))
+            .map(|c| c.id.clone())
+    }
+
+    /// Purge all cached downloads of a repository, returning `false`
+    /// if the repository doesn't exist
+    pub fn purge_downloads(&self, id: &repository::Id) -> Result<bool, Error> {
+        let Some(partition) = self.partition(id) else {
+            return Ok(false);
+        };
+
+        let dir = cache::download_dir(&self.installation, Some(&partition));
+
+        if dir.exists() {
+            fs::remove_dir_all(&dir).map_err(Error::RemoveDir)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Remove repositories, deleting any related config & cached data
+    ///
+    /// The configuration is backed up once for the whole batch, so [`Manager::undo`]
+    /// restores all of them.
+    pub fn remove(
+        &mut self,
+        ids: impl IntoIterator<Item = repository::Id>,
+    ) -> Result<Vec<(repository::Id, Removal)>, Error> {
         // Only allow removal for system repo manager
         let Source::System(config) = &self.source else {
             return Err(Error::ExplicitUnsupported);
         };
 
-        // Remove from memory
-        let Some(repo) = self.repositories.remove(&id.into()) else {
-            return Ok(Removal::NotFound);
+        let ids = ids.into_iter().collect::<Vec<_>>();
+
+        if ids.iter().any(|id| self.repositories.contains_key(id)) {
+            config.backup::<repository::Map>().map_err(Error::BackupConfig)?;
+            profile::record_active(&self.installation, None).map_err(Error::RecordProfile)?;
+        }
+
+        let mut removals = vec![];
+
+        for id in ids {
+            // Remove from memory
+            let Some(repo) = self.repositories.remove(&id) else {
+                removals.push((id, Removal::NotFound));
+                continue;
+            };
+
+            let cache_dir = cache_dir(self.source.identifier(), &repo.repository, &self.installation);
+            let download_dir = cache::download_dir(
+                &self.installation,
+                Some(&cache_key(self.source.identifier(), &repo.repository)),
+            );
+
+            // Remove cache
+            for dir in [cache_dir, download_dir] {
+                if dir.exists() {
+                    fs::remove_dir_all(&dir).map_err(Error::RemoveDir)?;
+                }
+            }
+
+            // Delete config, only succeeds for configs that live in their
+            // own config file w/ matching repo name
+            let deleted = config.delete::<repository::Map>(&repo.id).is_ok();
+            removals.push((id, Removal::ConfigDeleted(deleted)));
+        }
+
+        Ok(removals)
+    }
+
+    /// Reconcile the configured repositories with `desired`, i.e. those declared by a system model
+    ///
+    /// Missing repositories are added & those which differ are updated to match, while
+    /// any others are disabled rather than removed. Indexes aren't fetched, as the desired
+    /// repositories are expected to be in use already. The previous configuration is kept
+    /// as a backup restorable via [`Manager::undo`].
+    pub fn reconcile(&mut self, desired: &repository::Map) -> Result<Reconciliation, Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
         };
 
-        let cache_dir = cache_dir(self.source.identifier(), &repo.repository, &self.installation);
+        let mut reconciliation = Reconciliation::default();
+        let mut changed = vec![];
+
+        for (id, repository) in desired {
+            let mut repository = repository.clone();
+            repository.uri = local::normalize(repository.uri);
+
+            match self.repositories.get(id) {
+                None => reconciliation.added.push(id.clone()),
+                Some(cached) => {
+                    let configured = &cached.repository;
+                    if configured.uri == repository.uri
+                        && configured.priority == repository.priority
+                        && configured.description == repository.description
+                        && configured.active == repository.active
+                    {
+                        continue;
+                    }
+                    // Aliases aren't declared by system models
+                    if repository.aliases.is_empty() {
+                        repository.aliases = configured.aliases.clone();
+                    }
+                    reconciliation.updated.push(id.clone());
+                }
+            }
+
+            changed.push((id.clone(), repository));
+        }
+
+        for (id, cached) in &self.repositories {
+            if cached.repository.active && desired.get(id).is_none() {
+                let mut repository = cached.repository.clone();
+                repository.active = false;
 
-        // Remove cache
-        if cache_dir.exists() {
-            fs::remove_dir_all(&cache_dir).map_err(Error::RemoveDir)?;
+                reconciliation.disabled.push(id.clone());
+                changed.push((id.clone(), repository));
+            }
         }
 
-        // Delete config, only succeeds for configs that live in their
-        // own config file w/ matching repo name
-        if config.delete::<repository::Map>(&repo.id).is_err() {
-            return Ok(Removal::ConfigDeleted(false));
+        if changed.is_empty() {
+            return Ok(reconciliation);
         }
 
-        Ok(Removal::ConfigDeleted(true))
+        config.backup::<repository::Map>().map_err(Error::BackupConfig)?;
+        profile::record_active(&self.installation, None).map_err(Error::RecordProfile)?;
+
+        for (id, repository) in changed {
+            let map = repository::Map::with([(id.clone(), repository.clone())]);
+            config.save(&id, &map).map_err(Error::SaveConfig)?;
+
+            let db = open_meta_db(self.source.identifier(), &repository, &self.installation)?;
+            self.repositories
+                .insert(id.clone(), repository::Cached { id, repository, db });
+        }
+
+        Ok(reconciliation)
+    }
+
+    /// The [`expiry::Record`] of every active repository, `None` if it was never recorded
+    pub fn freshness(&self) -> impl Iterator<Item = (&repository::Id, Option<expiry::Record>)> {
+        self.repositories
+            .iter()
+            .filter(|(_, state)| state.repository.active)
+            .map(|(id, state)| {
+                let dir = cache_dir(self.source.identifier(), &state.repository, &self.installation);
+                (id, expiry::Record::load(&dir))
+            })
     }
 
     /// List all of the known repositories
@@ -255,42 +550,268 @@ impl Manager {
         self.repositories.iter().map(|(id, state)| (id, &state.repository))
     }
 
-    /// Sets the repo as active or not
-    async fn set_active(&mut self, id: &repository::Id, active: bool) -> Result<(), Error> {
+    /// Sets the repos as active or not
+    ///
+    /// Repos being enabled are validated with a test index load before any config is
+    /// changed, so either all of them are updated or none are.
+    async fn set_active(&mut self, ids: &[repository::Id], active: bool) -> Result<(), Error> {
         // Only allow disable for system repo manager
         let Source::System(config) = &self.source else {
             return Err(Error::ExplicitUnsupported);
         };
 
-        let Some(cached) = self.repositories.get_mut(id) else {
-            return Err(Error::UnknownRepo(id.clone()));
+        let mut changed = vec![];
+
+        for id in ids {
+            let Some(cached) = self.repositories.get(id) else {
+                return Err(Error::UnknownRepo(id.clone()));
+            };
+
+            if active != cached.repository.active {
+                let mut updated = cached.clone();
+                updated.repository.active = active;
+                changed.push(updated);
+            }
+        }
+
+        // Validate with a test index load before committing
+        if active {
+            stream::iter(&changed)
+                .map(|updated| async {
+                    load_index(self.source.identifier(), updated.clone(), &self.installation)
+                        .await
+                        .map_err(|error| Error::Validate(updated.id.clone(), Box::new(error)))
+                })
+                .buffer_unordered(request::concurrency())
+                .try_collect::<()>()
+                .await?;
+        }
+
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        config.backup::<repository::Map>().map_err(Error::BackupConfig)?;
+        profile::record_active(&self.installation, None).map_err(Error::RecordProfile)?;
+
+        for updated in changed {
+            let map = repository::Map::with([(updated.id.clone(), updated.repository.clone())]);
+            config.save(&updated.id, &map).map_err(Error::SaveConfig)?;
+
+            self.repositories.insert(updated.id.clone(), updated);
+        }
+
+        Ok(())
+    }
+
+    /// Restore the repository configuration from before the last modification
+    ///
+    /// Returns `false` if there is nothing to undo
+    pub async fn undo(&mut self) -> Result<bool, Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
         };
 
-        if active != cached.repository.active {
-            cached.repository.active = active;
+        if !config.restore::<repository::Map>().map_err(Error::RestoreConfig)? {
+            return Ok(false);
+        }
+        profile::record_active(&self.installation, None).map_err(Error::RecordProfile)?;
 
-            let map = repository::Map::with([(id.clone(), cached.repository.clone())]);
-            config.save(id, &map).map_err(Error::SaveConfig)?;
+        // Reload from the restored configs & fetch any repos whose cache was removed
+        *self = Self::system(config.clone(), self.installation.clone())?;
+        self.ensure_all_initialized().await?;
+
+        Ok(true)
+    }
+
+    /// Enable the repos
+    pub async fn enable(&mut self, ids: &[repository::Id]) -> Result<(), Error> {
+        self.set_active(ids, true).await
+    }
+
+    /// Disable the repos
+    pub async fn disable(&mut self, ids: &[repository::Id]) -> Result<(), Error> {
+        self.set_active(ids, false).await
+    }
+
+    /// All configured [`profile::Profile`]s
+    pub fn profiles(&self) -> Result<profile::Map, Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
+        };
+
+        Ok(config
+            .load::<profile::Map>()
+            .into_iter()
+            .reduce(profile::Map::merge)
+            .unwrap_or_default())
+    }
+
+    /// Name of the profile the repositories were last switched to, unless they've since been changed
+    pub fn active_profile(&self) -> Option<String> {
+        match &self.source {
+            Source::System(_) => profile::active(&self.installation),
+            Source::Explicit { .. } => None,
         }
+    }
+
+    /// Save the current repositories as the profile `name`, which becomes the active profile
+    pub fn save_profile(&self, name: &str, description: String) -> Result<(), Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
+        };
+
+        // Names the config file, so it's held to the characters of a repository id
+        if name.is_empty() || repository::Id::new(name).to_string() != name {
+            return Err(Error::InvalidProfileName(name.to_owned()));
+        }
+
+        let profile = profile::Profile {
+            description,
+            repositories: self.list().map(|(id, repo)| (id.clone(), repo.clone())).collect(),
+        };
+
+        config
+            .save(name, &profile::Map::with([(name.to_owned(), profile)]))
+            .map_err(Error::SaveConfig)?;
+        profile::record_active(&self.installation, Some(name)).map_err(Error::RecordProfile)?;
 
         Ok(())
     }
 
-    /// Enable the repo
-    pub async fn enable(&mut self, id: &repository::Id) -> Result<(), Error> {
-        self.set_active(id, true).await
+    /// Delete the profile `name`, returning `false` if it doesn't live in its own config file
+    pub fn delete_profile(&self, name: &str) -> Result<bool, Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
+        };
+
+        if self.profiles()?.get(name).is_none() {
+            return Err(Error::UnknownProfile(name.to_owned()));
+        }
+
+        let deleted = config.delete::<profile::Map>(name).is_ok();
+        if deleted && self.active_profile().as_deref() == Some(name) {
+            profile::record_active(&self.installation, None).map_err(Error::RecordProfile)?;
+        }
+
+        Ok(deleted)
     }
 
-    /// Disable the repo
-    pub async fn disable(&mut self, id: &repository::Id) -> Result<(), Error> {
-        self.set_active(id, false).await
+    /// Swap the configured repositories for those of the profile `name`
+    ///
+    /// Repositories outside of the profile are kept, but disabled. Repositories the profile
+    /// activates are validated with a test index load before any config is changed, and the
+    /// configs are then replaced in one step, so either the whole profile is applied or
+    /// nothing is. The previous configuration is kept as a backup restorable via [`Manager::undo`].
+    pub async fn use_profile(&mut self, name: &str) -> Result<(), Error> {
+        let Source::System(config) = &self.source else {
+            return Err(Error::ExplicitUnsupported);
+        };
+
+        let profile = self
+            .profiles()?
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownProfile(name.to_owned()))?;
+
+        let disabled = self
+            .repositories
+            .iter()
+            .filter(|(id, _)| profile.repositories.get(id).is_none())
+            .map(|(id, cached)| {
+                let mut repository = cached.repository.clone();
+                repository.active = false;
+                (id.clone(), repository)
+            })
+            .collect::<repository::Map>();
+
+        let repositories = disabled
+            .merge(profile.repositories)
+            .into_iter()
+            .map(|(id, mut repository)| {
+                repository.uri = local::normalize(repository.uri);
+                let db = open_meta_db(self.source.identifier(), &repository, &self.installation)?;
+
+                Ok((id.clone(), repository::Cached { id, repository, db }))
+            })
+            .collect::<Result<BTreeMap<_, _>, Error>>()?;
+
+        // Validate repos which weren't already active before committing
+        let activated = repositories
+            .values()
+            .filter(|cached| {
+                cached.repository.active
+                    && self.repositories.get(&cached.id).is_none_or(|current| {
+                        !current.repository.active || current.repository.uri != cached.repository.uri
+                    })
+            })
+            .collect::<Vec<_>>();
+        stream::iter(activated)
+            .map(|cached| async {
+                load_index(self.source.identifier(), cached.clone(), &self.installation)
+                    .await
+                    .map_err(|error| Error::Validate(cached.id.clone(), Box::new(error)))
+            })
+            .buffer_unordered(request::concurrency())
+            .try_collect::<()>()
+            .await?;
+
+        config.backup::<repository::Map>().map_err(Error::BackupConfig)?;
+        config
+            .replace(repositories.values().map(|cached| {
+                (
+                    cached.id.clone(),
+                    repository::Map::with([(cached.id.clone(), cached.repository.clone())]),
+                )
+            }))
+            .map_err(Error::SaveConfig)?;
+        profile::record_active(&self.installation, Some(name)).map_err(Error::RecordProfile)?;
+
+        self.repositories = repositories;
+
+        Ok(())
     }
 }
 
-/// Directory for the repo cached data (db & stone index), hashed by identifier & repo URI
+/// Spinner shown while refreshing the repository `id`
+fn refresh_spinner(id: &repository::Id) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(None, tui::progress_target())
+        .with_style(
+            ProgressStyle::with_template(" {spinner} {wide_msg}")
+                .unwrap()
+                .tick_chars("--=≡■≡=--"),
+        )
+        .with_message(format!("{} {id}", "Refreshing".blue()));
+    pb.enable_steady_tick(Duration::from_millis(150));
+    pb
+}
+
+/// Identity of a repo, hashed by identifier & repo URI
+fn cache_key(identifier: &str, repo: &Repository) -> String {
+    format!("{:02x}", xxh3_64(format!("{identifier}-{}", repo.uri).as_bytes()))
+}
+
+/// Directory for the repo cached data (db & stone index)
 fn cache_dir(identifier: &str, repo: &Repository, installation: &Installation) -> PathBuf {
-    let hash = format!("{:02x}", xxh3_64(format!("{identifier}-{}", repo.uri).as_bytes()));
-    installation.repo_path(hash)
+    installation.repo_path(cache_key(identifier, repo))
+}
+
+/// Hash of the index the meta db cached in `dir` was loaded from
+///
+/// The index itself isn't rewritten when updated via a [`delta`], so the revision
+/// the meta db was brought to takes precedence.
+fn index_hash(dir: &Path) -> Option<String> {
+    match fs::read_to_string(dir.join(delta::REVISION_FILE)) {
+        Ok(revision) => Some(revision.trim().to_owned()),
+        Err(_) => hash_file(&dir.join("stone.index")).ok(),
+    }
+}
+
+/// sha256 of the file at `path`
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
 }
 
 /// Open the meta db file, ensuring it's
@@ -305,64 +826,272 @@ fn open_meta_db(identifier: &str, repo: &Repository, installation: &Installation
     Ok(db)
 }
 
-/// Fetches a stone index file from the repository URL
-/// and saves it to the repo installation path
-async fn fetch_index(
-    identifier: &str,
-    state: &repository::Cached,
-    installation: &Installation,
-) -> Result<PathBuf, Error> {
-    let out_dir = cache_dir(identifier, &state.repository, installation);
+/// An index update fetched by [`fetch_update`] & loaded into the meta db by [`apply_update`]
+enum Update {
+    /// The meta db is already at the current `revision`
+    Current(String),
+    /// The [`delta`] from the revision of the meta db to the current `revision`
+    Delta { delta: delta::Delta, revision: String },
+    /// The full index, downloaded to a staging path & read ahead of replacing the meta db
+    Full {
+        path: PathBuf,
+        packages: Vec<(package::Id, package::Meta)>,
+        revision: String,
+        valid_until: Option<u64>,
+    },
+}
+
+impl Update {
+    /// Whether the update applies to the meta db of `repo`, i.e. a [`delta`] only removes
+    /// packages it holds
+    fn applies_to(&self, repo: &repository::Cached) -> Result<bool, Error> {
+        let Update::Delta { delta, .. } = self else {
+            return Ok(true);
+        };
+        let ids = repo.db.package_ids()?;
+
+        Ok(delta.removed.iter().all(|id| ids.contains(id)))
+    }
+
+    /// Remove anything staged for an update which won't be applied
+    fn discard(self) {
+        if let Update::Full { path, .. } = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
 
-    tokio::fs::create_dir_all(&out_dir).await.map_err(Error::CreateDir)?;
+/// Fetch the index file of the repository and load it into its meta db
+///
+/// If the meta db was loaded from an earlier revision of the index, only the
+/// [`delta`] to the current revision is fetched when the repository provides it.
+async fn load_index(identifier: &str, repo: repository::Cached, installation: &Installation) -> Result<(), Error> {
+    let dir = cache_dir(identifier, &repo.repository, installation);
 
-    let out_path = out_dir.join("stone.index");
+    let update = fetch_update(&repo, &dir).await?;
+    apply_or_refetch(repo, &dir, update).await
+}
 
-    // Fetch index & write to `out_path`
-    repository::fetch_index(state.repository.uri.clone(), &out_path).await?;
+/// Apply a fetched [`Update`], falling back to the full index if applying a [`delta`] fails
+async fn apply_or_refetch(repo: repository::Cached, dir: &Path, update: Update) -> Result<(), Error> {
+    let incremental = matches!(update, Update::Delta { .. });
 
-    Ok(out_path)
+    match apply_update(repo.clone(), dir, update).await {
+        Err(error) if incremental => {
+            debug!(%error, repo = %repo.id, "Incremental index update failed, fetching full index");
+            let update = fetch_full(&repo, dir).await?;
+            apply_update(repo, dir, update).await
+        }
+        result => result,
+    }
 }
 
-/// Updates a stones metadata into the meta db
-fn update_meta_db(state: &repository::Cached, index_path: &Path) -> Result<(), Error> {
-    // Wipe db since we're refreshing from a new index file
-    state.db.wipe()?;
+/// Fetch what's needed to bring the meta db of `repo`, cached in `dir`, to the current
+/// revision of its index, without modifying the meta db
+async fn fetch_update(repo: &repository::Cached, dir: &Path) -> Result<Update, Error> {
+    let local = fs::read_to_string(dir.join(delta::REVISION_FILE))
+        .ok()
+        .filter(|_| dir.join("stone.index").exists())
+        .filter(|_| local::directory(&repo.repository.uri).is_none());
+
+    if let Some(local) = local {
+        match fetch_delta(repo, dir, local.trim()).await {
+            Ok(Some(update)) => return Ok(update),
+            Ok(None) => {}
+            Err(error) => debug!(%error, repo = %repo.id, "Incremental index update failed, fetching full index"),
+        }
+    }
 
-    // Get a stream of payloads
-    let mut file = File::open(index_path).map_err(Error::OpenIndex)?;
-    let mut reader = stone::read(&mut file)?;
+    fetch_full(repo, dir).await
+}
 
-    let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+/// [`fetch_update`], fetching the full index instead of a [`delta`] which doesn't apply
+async fn fetch_validated(repo: &repository::Cached, dir: &Path) -> Result<Update, Error> {
+    let update = fetch_update(repo, dir).await?;
 
-    // Construct Meta for each payload
-    let packages = payloads
-        .into_iter()
-        .filter_map(|payload| {
-            if let stone::read::PayloadKind::Meta(meta) = payload {
-                Some(meta)
-            } else {
-                None
+    if update.applies_to(repo)? {
+        Ok(update)
+    } else {
+        debug!(repo = %repo.id, "Index delta doesn't apply, fetching full index");
+        fetch_full(repo, dir).await
+    }
+}
+
+/// Fetch the full index of `repo` to a staging path in `dir` & read its packages
+///
+/// The index of a local directory repository is generated from its stones instead.
+async fn fetch_full(repo: &repository::Cached, dir: &Path) -> Result<Update, Error> {
+    tokio::fs::create_dir_all(dir).await.map_err(Error::CreateDir)?;
+
+    let path = dir.join("stone.index.part");
+
+    if let Some(stones) = local::directory(&repo.repository.uri) {
+        let path = path.clone();
+        runtime::unblock(move || local::write_index(&stones, &path)).await?;
+    } else {
+        repository::fetch_index(repo.repository.uri.clone(), &path).await?;
+    }
+
+    let staged = path.clone();
+    let read = runtime::unblock(move || {
+        let packages = read_packages(&staged)?;
+        let contents = fs::read(&staged).map_err(Error::OpenIndex)?;
+        let valid_until = index::valid_until(io::Cursor::new(&contents))?;
+        Ok::<_, Error>((packages, delta::revision(&contents), valid_until))
+    })
+    .await;
+
+    match read {
+        Ok((packages, revision, valid_until)) => Ok(Update::Full {
+            path,
+            packages,
+            revision,
+            valid_until,
+        }),
+        Err(error) => {
+            let _ = fs::remove_file(&path);
+            Err(error)
+        }
+    }
+}
+
+/// Fetch the delta from the `local` revision of the meta db to the current revision of the index
+///
+/// Returns `None` if the repository doesn't publish deltas.
+async fn fetch_delta(repo: &repository::Cached, dir: &Path, local: &str) -> Result<Option<Update>, Error> {
+    let Ok(remote) = repository::fetch_text(repo.repository.uri.join(delta::REVISION_FILE)?).await else {
+        return Ok(None);
+    };
+    let remote = remote.trim().to_owned();
+
+    if remote == local {
+        debug!(repo = %repo.id, revision = remote, "Index is up to date");
+        return Ok(Some(Update::Current(remote)));
+    }
+
+    let path = dir.join("stone.index.delta");
+    repository::fetch_index(repo.repository.uri.join(&delta::delta_path(local))?, &path).await?;
+
+    let delta = runtime::unblock(move || {
+        let delta = delta::Delta::read(File::open(&path).map_err(Error::OpenIndex)?);
+        let _ = fs::remove_file(&path);
+        delta.map_err(Error::from)
+    })
+    .await?;
+
+    Ok(Some(Update::Delta {
+        delta,
+        revision: remote,
+    }))
+}
+
+/// Load a fetched [`Update`] into the meta db of `repo`, cached in `dir`
+async fn apply_update(repo: repository::Cached, dir: &Path, update: Update) -> Result<(), Error> {
+    let revision_path = dir.join(delta::REVISION_FILE);
+    let uri = repo.repository.uri.clone();
+
+    let (revision, valid_until) = match update {
+        // Still the index the validity was recorded for
+        Update::Current(revision) => (
+            revision,
+            expiry::Record::load(dir).and_then(|record| record.valid_until),
+        ),
+        Update::Delta { delta, revision } => {
+            let revision_path = revision_path.clone();
+            runtime::unblock(move || {
+                info!(
+                    repo = %repo.id,
+                    added = delta.added.len(),
+                    removed = delta.removed.len(),
+                    "Applying index delta"
+                );
+
+                // Invalidate until the delta is fully applied
+                fs::remove_file(&revision_path).map_err(Error::WriteRevision)?;
+                repo.db.batch_remove(&delta.removed)?;
+                repo.db.batch_add(delta.added)?;
+
+                Ok::<_, Error>((revision, delta.valid_until))
+            })
+            .await?
+        }
+        Update::Full {
+            path,
+            packages,
+            revision,
+            valid_until,
+        } => {
+            // Invalidate until the meta db matches the new index
+            let _ = fs::remove_file(&revision_path);
+
+            fs::rename(&path, dir.join("stone.index")).map_err(Error::OpenIndex)?;
+
+            runtime::unblock(move || {
+                // Wipe db since we're refreshing from a new index file
+                repo.db.wipe()?;
+                repo.db.batch_add(packages)?;
+                Ok::<_, Error>((revision, valid_until))
+            })
+            .await?
+        }
+    };
+
+    fs::write(&revision_path, revision).map_err(Error::WriteRevision)?;
+
+    fetch_aliases(&uri, dir).await?;
+    expiry::Record::now(valid_until).save(dir)?;
+
+    Ok(())
+}
+
+/// Cache the [`alias`]es published next to the index at `uri`
+///
+/// Previously cached aliases are kept if they can't be fetched, unless the
+/// repository no longer publishes any.
+async fn fetch_aliases(uri: &url::Url, dir: &Path) -> Result<(), Error> {
+    let aliases = match repository::fetch_text(uri.join(alias::ALIASES_FILE)?).await {
+        Ok(contents) => match alias::parse(&contents) {
+            Ok(aliases) => Some(aliases),
+            Err(error) => {
+                warn!(%uri, %error, "Ignoring invalid aliases published by repository");
+                return Ok(());
             }
-        })
-        .map(|payload| {
-            let meta = package::Meta::from_stone_payload(&payload.body)?;
+        },
+        Err(error) if error.is_not_found() => None,
+        Err(error) => {
+            debug!(%uri, %error, "Failed to fetch aliases of repository");
+            return Ok(());
+        }
+    };
 
-            // Create id from hash of meta
+    alias::save(dir, aliases.as_ref())?;
+
+    Ok(())
+}
+
+/// Read the packages of the index at `index_path`, keyed by their hash
+fn read_packages(index_path: &Path) -> Result<Vec<(package::Id, package::Meta)>, Error> {
+    let file = File::open(index_path).map_err(Error::OpenIndex)?;
+
+    // Construct an id from the hash of each meta
+    index::read(file)?
+        .into_iter()
+        .map(|meta| {
             let hash = meta
                 .hash
                 .clone()
                 .ok_or(Error::MissingMetaField(stone::payload::meta::Tag::PackageHash))?;
-            let id = package::Id::from(hash);
 
-            Ok((id, meta))
+            Ok((package::Id::from(hash), meta))
         })
-        .collect::<Result<Vec<_>, Error>>()?;
-
-    // Batch add to db
-    state.db.batch_add(packages)?;
+        .collect()
+}
 
-    Ok(())
+/// Account failed downloads of `repository` to it, see [`request::failures`]
+fn track_source(id: &repository::Id, repository: &Repository) {
+    if let Ok(base) = repository.uri.join(".") {
+        request::track_source(id, base);
+    }
 }
 
 #[derive(Debug, Error)]
@@ -381,12 +1110,40 @@ pub enum Error {
     OpenIndex(#[source] io::Error),
     #[error("read index file")]
     ReadStone(#[from] stone::read::Error),
+    #[error("read index file")]
+    ReadIndex(#[from] index::Error),
+    #[error("record fetch time")]
+    RecordFetch(#[from] expiry::Error),
+    #[error("cache aliases")]
+    Alias(#[from] alias::Error),
     #[error("meta db")]
     Database(#[from] meta::Error),
     #[error("save config")]
     SaveConfig(#[source] config::SaveError),
+    #[error("backup config")]
+    BackupConfig(#[source] io::Error),
+    #[error("restore config")]
+    RestoreConfig(#[source] io::Error),
+    #[error("repository {0} failed validation")]
+    Validate(repository::Id, #[source] Box<Error>),
+    #[error("refresh repository {0}")]
+    Refresh(repository::Id, #[source] Box<Error>),
     #[error("unknown repo")]
     UnknownRepo(repository::Id),
+    #[error("unknown repository profile {0}")]
+    UnknownProfile(String),
+    #[error("invalid repository profile name {0:?}, expected letters, digits, '-' or '_'")]
+    InvalidProfileName(String),
+    #[error("record active profile")]
+    RecordProfile(#[source] io::Error),
+    #[error("write index revision")]
+    WriteRevision(#[source] io::Error),
+    #[error("index delta")]
+    Delta(#[from] delta::Error),
+    #[error("index local repository")]
+    Local(#[from] local::Error),
+    #[error("invalid index url")]
+    Url(#[from] url::ParseError),
 }
 
 impl From<package::MissingMetaFieldError> for Error {
@@ -395,6 +1152,20 @@ impl From<package::MissingMetaFieldError> for Error {
     }
 }
 
+/// Changes made by [`Manager::reconcile`]
+#[derive(Debug, Clone, Default)]
+pub struct Reconciliation {
+    pub added: Vec<repository::Id>,
+    pub updated: Vec<repository::Id>,
+    pub disabled: Vec<repository::Id>,
+}
+
+impl Reconciliation {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.disabled.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Removal {
     NotFound,
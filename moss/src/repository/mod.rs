@@ -19,7 +19,16 @@ use crate::{db::meta, request};
 
 pub use self::manager::Manager;
 
+pub mod advisory;
+pub mod alias;
+pub mod delta;
+pub mod expiry;
+pub mod health;
+pub mod index;
+pub mod local;
 pub mod manager;
+pub mod official;
+pub mod profile;
 
 /// A unique [`Repository`] identifier
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Ord, PartialOrd, From, Display)]
@@ -46,6 +55,11 @@ pub struct Repository {
     pub priority: Priority,
     #[serde(default = "default_as_true")]
     pub active: bool,
+    /// Alternative package names (i.e. those used by other distributions)
+    /// mapped to the package name they're known as in this repository,
+    /// overriding the [`alias`]es it publishes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
 }
 
 fn default_as_true() -> bool {
@@ -153,6 +167,24 @@ async fn fetch_index(url: Url, out_path: impl Into<PathBuf>) -> Result<(), Fetch
     Ok(())
 }
 
+/// Fetch a file into memory
+async fn fetch_bytes(url: Url) -> Result<Vec<u8>, FetchError> {
+    let mut stream = request::get(url).await?;
+    let mut contents = vec![];
+
+    while let Some(chunk) = stream.next().await {
+        contents.extend_from_slice(&chunk?);
+    }
+
+    Ok(contents)
+}
+
+/// Fetch a small text file, i.e. the [`delta::REVISION_FILE`] of an index
+async fn fetch_text(url: Url) -> Result<String, FetchError> {
+    String::from_utf8(fetch_bytes(url).await?)
+        .map_err(|error| FetchError::Io(io::Error::new(io::ErrorKind::InvalidData, error)))
+}
+
 #[derive(Debug, Error)]
 pub enum FetchError {
     #[error("request")]
@@ -160,3 +192,15 @@ pub enum FetchError {
     #[error("io")]
     Io(#[from] io::Error),
 }
+
+impl FetchError {
+    /// Whether the fetched file doesn't exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            FetchError::Request(request::Error::Fetch(error)) => error.status() == Some(reqwest::StatusCode::NOT_FOUND),
+            FetchError::Request(request::Error::Read(error)) | FetchError::Io(error) => {
+                error.kind() == std::io::ErrorKind::NotFound
+            }
+        }
+    }
+}
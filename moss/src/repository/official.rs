@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Curated manifest of official repositories
+//!
+//! The manifest is shipped with moss as `/usr/share/moss/official-repositories.yaml`
+//! (see `moss/data` & `just install-data`), along with a detached signature of it as
+//! written by `moss pack --sign-key`. It's only trusted if the signature is valid for
+//! the hex encoded ed25519 public key given as `MOSS_OFFICIAL_REPOSITORIES_KEY` when
+//! building moss. Without one, no official repositories are available.
+
+use std::{io, path::Path};
+
+use fs_err as fs;
+use thiserror::Error;
+
+use super::{Id, Map, Repository};
+use crate::signing::{self, PublicKey};
+
+/// Manifest of official repositories, relative to the root
+const MANIFEST: &str = "usr/share/moss/official-repositories.yaml";
+
+/// Key the manifest is signed with, if moss is built with one
+const KEY: Option<&str> = option_env!("MOSS_OFFICIAL_REPOSITORIES_KEY");
+
+/// All official repositories, as shipped in `root`
+pub fn list(root: &Path) -> Result<Map, Error> {
+    let key = PublicKey::from_hex(KEY.ok_or(Error::NoKey)?).map_err(|_| Error::InvalidKey)?;
+
+    let path = root.join(MANIFEST);
+    let manifest = match fs::read(&path) {
+        Ok(manifest) => manifest,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(Error::Missing),
+        Err(error) => return Err(error.into()),
+    };
+    let mut signature_path = path.into_os_string();
+    signature_path.push(signing::SUFFIX);
+    let signature = match fs::read(signature_path) {
+        Ok(signature) => signature,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(Error::Signature),
+        Err(error) => return Err(error.into()),
+    };
+
+    verify(&manifest, &signature, &key)
+}
+
+/// The official repository known as `name`
+pub fn get(root: &Path, name: &str) -> Result<Option<Repository>, Error> {
+    Ok(list(root)?.get(&Id::new(name)).cloned())
+}
+
+/// Parse the `manifest` once its `signature` is verified with `key`
+fn verify(manifest: &[u8], signature: &[u8], key: &PublicKey) -> Result<Map, Error> {
+    key.verify(manifest, signature).map_err(|_| Error::Signature)?;
+
+    Ok(serde_yaml::from_slice(manifest)?)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("moss is built without a key to verify official repositories with")]
+    NoKey,
+    #[error("invalid key to verify official repositories with")]
+    InvalidKey,
+    #[error("no manifest of official repositories at /{MANIFEST}")]
+    Missing,
+    #[error("manifest of official repositories isn't signed by the key moss is built with")]
+    Signature,
+    #[error("invalid manifest of official repositories")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let manifest = include_bytes!("../../data/official-repositories.yaml");
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = PublicKey::from_hex(&hex::encode(key_pair.public_key())).unwrap();
+        let signature = signing::sign(&key_pair, manifest);
+
+        let repos = verify(manifest, signature.as_bytes(), &key).unwrap();
+        assert!(repos.get(&Id::new("volatile")).is_some());
+
+        let mut tampered = manifest.to_vec();
+        tampered.extend_from_slice(b"\nmirror:\n  description: \"\"\n  uri: \"http://example.com\"\n  priority: 0\n");
+        assert!(matches!(
+            verify(&tampered, signature.as_bytes(), &key),
+            Err(Error::Signature)
+        ));
+    }
+}
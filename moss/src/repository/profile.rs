@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Repository profiles
+//!
+//! A profile bundles several repository definitions under a name such as `stable` or
+//! `local-dev`, so the whole set of active repositories can be swapped in one step
+//! with [`Manager::use_profile`](super::Manager::use_profile).
+//!
+//! The profile last switched to is recorded, and the record cleared as soon as the
+//! repositories are changed by other means, so it always describes the active set.
+
+use std::{collections::BTreeMap, io};
+
+use config::Config;
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+
+use crate::Installation;
+
+use super::Map as Repositories;
+
+/// A named set of repositories
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub description: String,
+    pub repositories: Repositories,
+}
+
+/// A map of profiles by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Map(BTreeMap<String, Profile>);
+
+impl Map {
+    pub fn with(items: impl IntoIterator<Item = (String, Profile)>) -> Self {
+        Self(items.into_iter().collect())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&String, &Profile)> {
+        self.0.iter()
+    }
+
+    pub fn merge(self, other: Self) -> Self {
+        Self(self.0.into_iter().chain(other.0).collect())
+    }
+}
+
+impl Config for Map {
+    fn domain() -> String {
+        "repo-profile".into()
+    }
+}
+
+/// Name of the profile the repositories of `installation` were last switched to
+pub fn active(installation: &Installation) -> Option<String> {
+    fs::read_to_string(installation.repo_profile_path())
+        .ok()
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// Record `name` as the active profile, or clear the record
+pub fn record_active(installation: &Installation, name: Option<&str>) -> io::Result<()> {
+    let path = installation.repo_profile_path();
+
+    match name {
+        Some(name) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, format!("{name}\n"))
+        }
+        None if path.exists() => fs::remove_file(path),
+        None => Ok(()),
+    }
+}
@@ -2,17 +2,31 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{io, path::PathBuf, sync::OnceLock};
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use fs_err::tokio::File;
 use futures_util::{
-    Stream, StreamExt,
+    StreamExt,
     stream::{self, BoxStream},
 };
+use reqwest::{StatusCode, header::RANGE};
+use serde::Deserialize;
 use thiserror::Error;
-use tokio::io::AsyncReadExt;
+use tokio::{io::AsyncReadExt, time::Instant};
 use tokio_util::io::ReaderStream;
+use tracing::warn;
 use url::Url;
 
 use crate::environment;
@@ -30,23 +44,415 @@ fn get_client() -> &'static reqwest::Client {
     })
 }
 
+/// Limit shared by all downloads, see [`configure`]
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+/// Retries of failed downloads, see [`configure`]
+static RETRY: OnceLock<Retry> = OnceLock::new();
+
+/// Downloads running at once, see [`set_concurrency`]
+static CONCURRENCY: OnceLock<usize> = OnceLock::new();
+
+/// Failures of each source, see [`track_source`]
+static SOURCES: Mutex<Vec<(String, Url)>> = Mutex::new(vec![]);
+static FAILURES: Mutex<BTreeMap<String, Failures>> = Mutex::new(BTreeMap::new());
+
+/// Bytes received by all downloads, see [`take_downloaded`]
+static DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+
+/// Download configuration, i.e. `/etc/moss/download.d/*.yaml`
+///
+/// ```yaml
+/// limit_rate: 2M
+/// retry:
+///   attempts: 5
+///   backoff: 2
+///   statuses: [429, 503]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Limit all downloads combined to this rate
+    pub limit_rate: Option<Rate>,
+    pub retry: Retry,
+}
+
+/// Retries of failed downloads, with an exponential backoff
+///
+/// Interrupted transfers resume from where they left off, so the attempts are
+/// counted from the last bytes received.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Retry {
+    /// Attempts after a failure before giving up, 0 to never retry
+    pub attempts: u32,
+    /// Seconds to wait before the first retry, doubled for each further one
+    pub backoff: f64,
+    /// Upper bound of the wait between retries, in seconds
+    pub max_backoff: f64,
+    /// HTTP statuses worth retrying, failures to connect or receive are always retried
+    pub statuses: Vec<u16>,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: 1.0,
+            max_backoff: 30.0,
+            statuses: vec![408, 425, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl Retry {
+    /// Wait before retrying after `failures` consecutive failures
+    fn delay(&self, failures: u32) -> Duration {
+        let seconds = self.backoff * 2_f64.powi(failures.min(32) as i32);
+        Duration::from_secs_f64(seconds.min(self.max_backoff).max(0.0))
+    }
+
+    fn is_retryable(&self, error: &reqwest::Error) -> bool {
+        match error.status() {
+            Some(status) => self.statuses.contains(&status.as_u16()),
+            // Interrupted bodies are reported as failures to decode them
+            None => {
+                error.is_connect() || error.is_timeout() || error.is_request() || error.is_body() || error.is_decode()
+            }
+        }
+    }
+}
+
+impl config::Config for Config {
+    fn domain() -> String {
+        "download".into()
+    }
+}
+
+/// A transfer rate in bytes per second
+///
+/// Parsed from a number of bytes, with an optional `K`, `M` or `G` (binary) suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RateRepr")]
+pub struct Rate(u64);
+
+impl Rate {
+    pub fn bytes_per_second(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Rate {
+    type Err = InvalidRate;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let (number, multiplier) = match trimmed.char_indices().last() {
+            Some((i, 'k' | 'K')) => (&trimmed[..i], 1 << 10),
+            Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+            Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+            _ => (trimmed, 1),
+        };
+
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(multiplier))
+            .map(Self)
+            .ok_or_else(|| InvalidRate(s.to_owned()))
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/s", tui::HumanBytes(self.0))
+    }
+}
+
+/// A [`Rate`] is either a number of bytes or a string with a suffix in config
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RateRepr {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<RateRepr> for Rate {
+    type Error = InvalidRate;
+
+    fn try_from(repr: RateRepr) -> Result<Self, Self::Error> {
+        match repr {
+            RateRepr::Bytes(bytes) => Ok(Self(bytes)),
+            RateRepr::Text(text) => text.parse(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid rate {0:?}, expected bytes per second with an optional K, M or G suffix")]
+pub struct InvalidRate(String);
+
+/// Apply `config` to all downloads of this process, a rate limit of 0 is unlimited
+///
+/// Only the first configuration applies.
+pub fn configure(config: Config) {
+    if let Some(rate) = config.limit_rate.filter(|rate| rate.0 > 0) {
+        let _ = THROTTLE.set(Throttle {
+            rate: rate.0,
+            due: Mutex::new(None),
+        });
+    }
+    let _ = RETRY.set(config.retry);
+}
+
+/// Run up to `downloads` downloads at once, instead of [`environment::MAX_NETWORK_CONCURRENCY`]
+///
+/// Only the first call applies.
+pub fn set_concurrency(downloads: usize) {
+    let _ = CONCURRENCY.set(downloads.max(1));
+}
+
+/// Number of downloads to run at once
+pub fn concurrency() -> usize {
+    *CONCURRENCY.get_or_init(|| environment::MAX_NETWORK_CONCURRENCY)
+}
+
+fn retry_policy() -> &'static Retry {
+    RETRY.get_or_init(Retry::default)
+}
+
+/// Account failures of downloads under `base` to `name`, i.e. a repository
+///
+/// Downloads from elsewhere are accounted to their host.
+pub fn track_source(name: impl ToString, base: Url) {
+    let mut sources = SOURCES.lock().unwrap();
+    if !sources.iter().any(|(_, url)| *url == base) {
+        sources.push((name.to_string(), base));
+    }
+}
+
+/// Failed downloads of a source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Failures {
+    /// Failed attempts that were retried
+    pub retried: u32,
+    /// Downloads given up on after retrying
+    pub failed: u32,
+}
+
+/// Failed downloads so far, by source
+pub fn failures() -> BTreeMap<String, Failures> {
+    FAILURES.lock().unwrap().clone()
+}
+
+/// Source of `url`, see [`track_source`]
+fn source(url: &Url) -> String {
+    SOURCES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, base)| url.as_str().starts_with(base.as_str()))
+        .max_by_key(|(_, base)| base.as_str().len())
+        .map(|(name, _)| name.clone())
+        .unwrap_or_else(|| url.host_str().unwrap_or(url.as_str()).to_owned())
+}
+
+/// Bytes downloaded since the last call
+pub fn take_downloaded() -> u64 {
+    DOWNLOADED.swap(0, Ordering::Relaxed)
+}
+
+fn record_failure(url: &Url, retried: bool) {
+    let mut failures = FAILURES.lock().unwrap();
+    let failures = failures.entry(source(url)).or_default();
+    if retried {
+        failures.retried += 1;
+    } else {
+        failures.failed += 1;
+    }
+}
+
+/// Paces the bytes received by all downloads to a rate
+struct Throttle {
+    rate: u64,
+    /// When the bytes received so far are due at `rate`
+    due: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// Wait for `bytes` more to be due
+    async fn wait(&self, bytes: usize) {
+        let deadline = {
+            let mut due = self.due.lock().unwrap();
+            let now = Instant::now();
+            // Don't make up for time spent idle
+            let start = due.filter(|due| *due > now).unwrap_or(now);
+            let deadline = start + Duration::from_secs_f64(bytes as f64 / self.rate as f64);
+            *due = Some(deadline);
+            deadline
+        };
+
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
 /// Fetch a resource at the provided [`Url`] and stream response body as bytes
 pub async fn get(url: Url) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
     match url_file(&url) {
         Some(path) => read(path).await,
-        _ => Ok(fetch(url).await?.boxed()),
+        _ => fetch(url).await,
     }
 }
 
+/// Check the resource at the provided [`Url`] is available without fetching it,
+/// returning the time reported by the server, if any
+pub async fn probe(url: Url) -> Result<Option<DateTime<Utc>>, Error> {
+    if let Some(path) = url_file(&url) {
+        tokio::fs::metadata(path).await?;
+        return Ok(None);
+    }
+
+    let response = get_client().head(url).send().await?.error_for_status()?;
+
+    Ok(response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        .map(|date| date.with_timezone(&Utc)))
+}
+
 /// Internal fetch helper (sanity control) for `get`
-async fn fetch(url: Url) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error> {
-    let response = get_client().get(url).send().await?;
+async fn fetch(url: Url) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+    let mut transfer = Transfer {
+        url,
+        offset: 0,
+        skip: 0,
+        failures: 0,
+        body: None,
+    };
+    transfer.connect().await?;
+
+    Ok(stream::unfold(Some(transfer), |transfer| async move {
+        let mut transfer = transfer?;
+        match transfer.next().await? {
+            Ok(bytes) => Some((Ok(bytes), Some(transfer))),
+            Err(error) => Some((Err(error), None)),
+        }
+    })
+    .boxed())
+}
+
+/// A download retrying failures as configured by [`Retry`]
+struct Transfer {
+    url: Url,
+    /// Bytes received so far
+    offset: u64,
+    /// Bytes received before, still to skip when the server ignored the range requested
+    skip: u64,
+    /// Consecutive failed attempts
+    failures: u32,
+    body: Option<BoxStream<'static, reqwest::Result<Bytes>>>,
+}
 
-    response
-        .error_for_status()
-        .map(reqwest::Response::bytes_stream)
-        .map(|stream| stream.map(|result| result.map_err(Error::Fetch)))
-        .map_err(Error::Fetch)
+impl Transfer {
+    /// Request the rest of the resource, retrying failures
+    async fn connect(&mut self) -> Result<(), Error> {
+        loop {
+            match self.request().await {
+                Ok(body) => {
+                    self.body = Some(body);
+                    return Ok(());
+                }
+                Err(error) => self.backoff(error).await?,
+            }
+        }
+    }
+
+    async fn request(&mut self) -> reqwest::Result<BoxStream<'static, reqwest::Result<Bytes>>> {
+        let mut request = get_client().get(self.url.clone());
+        if self.offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", self.offset));
+        }
+
+        let response = request.send().await?.error_for_status()?;
+        self.skip = if response.status() == StatusCode::PARTIAL_CONTENT {
+            0
+        } else {
+            self.offset
+        };
+
+        Ok(response.bytes_stream().boxed())
+    }
+
+    /// Wait before retrying after `error`, or give up on it
+    async fn backoff(&mut self, error: reqwest::Error) -> Result<(), Error> {
+        let policy = retry_policy();
+
+        // Others, i.e. missing files, are expected at times & reported as they are
+        if !policy.is_retryable(&error) {
+            return Err(Error::Fetch(error));
+        }
+        if self.failures >= policy.attempts {
+            record_failure(&self.url, false);
+            return Err(Error::Fetch(error));
+        }
+
+        let delay = policy.delay(self.failures);
+        self.failures += 1;
+        record_failure(&self.url, true);
+        warn!(
+            url = %self.url,
+            attempt = self.failures,
+            delay_ms = delay.as_millis(),
+            %error,
+            "Retrying download"
+        );
+        tokio::time::sleep(delay).await;
+
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Option<Result<Bytes, Error>> {
+        loop {
+            let Some(body) = &mut self.body else {
+                if let Err(error) = self.connect().await {
+                    return Some(Err(error));
+                }
+                continue;
+            };
+
+            match body.next().await {
+                Some(Ok(mut bytes)) => {
+                    if self.skip > 0 {
+                        let skipped = self.skip.min(bytes.len() as u64);
+                        bytes = bytes.slice(skipped as usize..);
+                        self.skip -= skipped;
+                        if bytes.is_empty() {
+                            continue;
+                        }
+                    }
+
+                    self.offset += bytes.len() as u64;
+                    self.failures = 0;
+                    DOWNLOADED.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+
+                    if let Some(throttle) = THROTTLE.get() {
+                        throttle.wait(bytes.len()).await;
+                    }
+
+                    return Some(Ok(bytes));
+                }
+                Some(Err(error)) => {
+                    self.body = None;
+                    if let Err(error) = self.backoff(error).await {
+                        return Some(Err(error));
+                    }
+                }
+                None => return None,
+            }
+        }
+    }
 }
 
 /// Asynchronously read a filesystem path akin to the fetch API
@@ -75,6 +481,52 @@ fn url_file(url: &Url) -> Option<PathBuf> {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        assert_eq!("512".parse::<Rate>().unwrap(), Rate(512));
+        assert_eq!("64k".parse::<Rate>().unwrap(), Rate(64 * 1024));
+        assert_eq!(" 2M ".parse::<Rate>().unwrap(), Rate(2 * 1024 * 1024));
+        assert_eq!("1G".parse::<Rate>().unwrap(), Rate(1 << 30));
+        assert!("".parse::<Rate>().is_err());
+        assert!("fast".parse::<Rate>().is_err());
+        assert!("1.5M".parse::<Rate>().is_err());
+        assert!("99999999999999999999G".parse::<Rate>().is_err());
+
+        let config: Config = serde_yaml::from_str("limit_rate: 1048576").unwrap();
+        assert_eq!(config.limit_rate, Some(Rate(1 << 20)));
+        let config: Config = serde_yaml::from_str("limit_rate: 1M").unwrap();
+        assert_eq!(config.limit_rate, Some(Rate(1 << 20)));
+    }
+
+    #[test]
+    fn test_retry() {
+        let config: Config = serde_yaml::from_str("retry:\n  attempts: 5\n  max_backoff: 3").unwrap();
+        let retry = config.retry;
+        assert_eq!(retry.attempts, 5);
+        assert_eq!(retry.statuses, Retry::default().statuses);
+
+        assert_eq!(retry.delay(0), Duration::from_secs(1));
+        assert_eq!(retry.delay(1), Duration::from_secs(2));
+        assert_eq!(retry.delay(2), Duration::from_secs(3));
+        assert_eq!(retry.delay(u32::MAX), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_source() {
+        track_source("main", "https://example.com/repo/".parse().unwrap());
+        track_source("main-extra", "https://example.com/repo/extra/".parse().unwrap());
+
+        let source = |url: &str| source(&url.parse().unwrap());
+        assert_eq!(source("https://example.com/repo/a.stone"), "main");
+        assert_eq!(source("https://example.com/repo/extra/b.stone"), "main-extra");
+        assert_eq!(source("https://example.com/other/c.stone"), "example.com");
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("fetch")]
@@ -0,0 +1,305 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Global settings
+//!
+//! Defaults for options which apply to every command are read from `config.yaml`
+//! in each [`Layer`], with later layers overriding earlier ones key by key:
+//!
+//! ```yaml
+//! parallel-downloads: 4
+//! color: never
+//! auto-prune: true
+//! log-level: info
+//! ```
+//!
+//! Each layer also merges any `config.d/*.yaml` drop-ins of its directory.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use thiserror::Error;
+use tracing_subscriber::filter::LevelFilter;
+
+const DOMAIN: &str = "config";
+
+/// Known keys with their description
+pub const KEYS: &[(&str, &str)] = &[
+    ("parallel-downloads", "Number of packages & indexes downloaded at once"),
+    ("color", "When to color output: auto, always or never"),
+    ("auto-prune", "Prune states by the prune policy after each transaction"),
+    (
+        "log-level",
+        "Log level when no -v or --log is passed: error, warn, info, debug or trace",
+    ),
+];
+
+/// Where settings are read from, in increasing precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Layer {
+    /// Defaults shipped by the OS, `usr/share/moss` of the root
+    Vendor,
+    /// Set by the admin, `etc/moss` of the root
+    System,
+    /// Set by the invoking user, `$XDG_CONFIG_HOME/moss`
+    User,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 3] = [Layer::Vendor, Layer::System, Layer::User];
+
+    /// Directory of the layer for `root`, if it can be located
+    pub fn dir(&self, root: &Path) -> Option<PathBuf> {
+        match self {
+            Layer::Vendor => Some(root.join("usr/share/moss")),
+            Layer::System => Some(root.join("etc/moss")),
+            Layer::User => dirs::config_dir().map(|dir| dir.join("moss")),
+        }
+    }
+
+    /// The `config.yaml` of the layer for `root`
+    pub fn path(&self, root: &Path) -> Option<PathBuf> {
+        self.dir(root).map(|dir| dir.join(format!("{DOMAIN}.yaml")))
+    }
+
+    /// Settings of this layer alone, merged with its drop-ins
+    pub fn load(&self, root: &Path) -> Settings {
+        self.dir(root)
+            .map(|dir| {
+                config::Manager::custom(dir)
+                    .load::<Settings>()
+                    .into_iter()
+                    .fold(Settings::default(), Settings::merge)
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Global settings, unset keys fall back to the built in defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_downloads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_prune: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<LogLevel>,
+}
+
+impl config::Config for Settings {
+    fn domain() -> String {
+        DOMAIN.into()
+    }
+}
+
+impl Settings {
+    /// Settings of all layers for `root`
+    pub fn load(root: &Path) -> Self {
+        Layer::ALL
+            .iter()
+            .map(|layer| layer.load(root))
+            .fold(Self::default(), Self::merge)
+    }
+
+    /// Override the keys set in `other`
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            parallel_downloads: other.parallel_downloads.or(self.parallel_downloads),
+            color: other.color.or(self.color),
+            auto_prune: other.auto_prune.or(self.auto_prune),
+            log_level: other.log_level.or(self.log_level),
+        }
+    }
+
+    /// The value of `key`, if set
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        check_key(key)?;
+
+        let value = match serde_yaml::to_value(self)? {
+            serde_yaml::Value::Mapping(mapping) => mapping.get(key).cloned(),
+            _ => None,
+        };
+
+        Ok(value.map(|value| match value {
+            serde_yaml::Value::String(value) => value,
+            value => serde_yaml::to_string(&value).unwrap_or_default().trim().to_owned(),
+        }))
+    }
+
+    /// Set `key` to `value`, parsed as it would be in the config file
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        check_key(key)?;
+
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert(key.into(), serde_yaml::from_str(value)?);
+        let other = serde_yaml::from_value::<Self>(mapping.into()).map_err(|error| Error::InvalidValue {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            source: error,
+        })?;
+
+        *self = self.clone().merge(other);
+
+        Ok(())
+    }
+
+    /// Unset `key`, falling back to a lower layer or the default
+    pub fn unset(&mut self, key: &str) -> Result<(), Error> {
+        check_key(key)?;
+
+        let mut mapping = match serde_yaml::to_value(&*self)? {
+            serde_yaml::Value::Mapping(mapping) => mapping,
+            _ => serde_yaml::Mapping::new(),
+        };
+        mapping.remove(key);
+        *self = serde_yaml::from_value(mapping.into())?;
+
+        Ok(())
+    }
+
+    /// The value of each key set in any layer, along with the layer setting it
+    pub fn resolve(root: &Path) -> Result<BTreeMap<&'static str, (String, Layer)>, Error> {
+        let mut resolved = BTreeMap::new();
+
+        for layer in Layer::ALL {
+            let settings = layer.load(root);
+
+            for (key, _) in KEYS {
+                if let Some(value) = settings.get(key)? {
+                    resolved.insert(*key, (value, layer));
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Read only the `config.yaml` of `layer`, i.e. to modify it
+pub fn read(layer: Layer, root: &Path) -> Result<Settings, Error> {
+    let path = layer.path(root).ok_or(Error::NoUserDir)?;
+
+    match fs::read_to_string(&path) {
+        Ok(contents) if contents.trim().is_empty() => Ok(Settings::default()),
+        Ok(contents) => Ok(serde_yaml::from_str(&contents)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Replace the `config.yaml` of `layer` with `settings`
+pub fn write(layer: Layer, root: &Path, settings: &Settings) -> Result<PathBuf, Error> {
+    let path = layer.path(root).ok_or(Error::NoUserDir)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let staged = path.with_extension("yaml.tmp");
+    fs::write(&staged, serde_yaml::to_string(settings)?)?;
+    fs::rename(&staged, &path)?;
+
+    Ok(path)
+}
+
+fn check_key(key: &str) -> Result<(), Error> {
+    if KEYS.iter().any(|(known, _)| *known == key) {
+        Ok(())
+    } else {
+        Err(Error::UnknownKey(key.to_owned()))
+    }
+}
+
+/// When to color output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Color {
+    /// Only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// Default level of the log written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unknown setting {0:?}")]
+    UnknownKey(String),
+    #[error("invalid value {value:?} for {key}")]
+    InvalidValue {
+        key: String,
+        value: String,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    #[error("$HOME or $XDG_CONFIG_HOME not set")]
+    NoUserDir,
+    #[error("yaml")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_get() {
+        let mut settings = Settings::default();
+        settings.set("parallel-downloads", "4").unwrap();
+        settings.set("color", "never").unwrap();
+
+        assert_eq!(settings.parallel_downloads, Some(4));
+        assert_eq!(settings.get("color").unwrap().as_deref(), Some("never"));
+        assert_eq!(settings.get("auto-prune").unwrap(), None);
+        assert!(matches!(
+            settings.set("color", "sometimes"),
+            Err(Error::InvalidValue { .. })
+        ));
+        assert!(matches!(settings.get("colour"), Err(Error::UnknownKey(_))));
+
+        settings.unset("color").unwrap();
+        assert_eq!(settings.color, None);
+
+        let merged = settings.merge(Settings {
+            parallel_downloads: Some(2),
+            auto_prune: Some(true),
+            ..Settings::default()
+        });
+        assert_eq!(merged.parallel_downloads, Some(2));
+        assert_eq!(merged.auto_prune, Some(true));
+    }
+}
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Detached ed25519 signatures
+//!
+//! A signature covers the SHA-256 digest of the signed file and is published hex
+//! encoded next to it with a `.sig` suffix, as written by `moss pack --sign-key`.
+//! Repository indices, imported system models, official repository manifests &
+//! releases of moss are all verified this way.
+
+use ring::signature::{ED25519, Ed25519KeyPair, UnparsedPublicKey};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Suffix of the file holding the detached signature of another
+pub const SUFFIX: &str = ".sig";
+
+/// An ed25519 public key to verify signatures with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey(Vec<u8>);
+
+impl PublicKey {
+    /// Parse a hex encoded public key
+    pub fn from_hex(key: &str) -> Result<Self, Error> {
+        hex::decode(key.trim())
+            .ok()
+            .filter(|key| key.len() == 32)
+            .map(Self)
+            .ok_or_else(|| Error::InvalidKey(key.to_owned()))
+    }
+
+    /// Verify the detached `signature` of `contents`, as read from its `.sig` file
+    pub fn verify(&self, contents: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let signature = std::str::from_utf8(signature)
+            .ok()
+            .and_then(|signature| hex::decode(signature.trim()).ok())
+            .ok_or(Error::Mismatch)?;
+
+        UnparsedPublicKey::new(&ED25519, &self.0)
+            .verify(&Sha256::digest(contents), &signature)
+            .map_err(|_| Error::Mismatch)
+    }
+}
+
+/// The detached signature of the SHA-256 `digest` of a file, as written to its `.sig` file
+pub fn sign_digest(key_pair: &Ed25519KeyPair, digest: &[u8]) -> String {
+    format!("{}\n", hex::encode(key_pair.sign(digest)))
+}
+
+/// The detached signature of `contents`, as written to its `.sig` file
+pub fn sign(key_pair: &Ed25519KeyPair, contents: &[u8]) -> String {
+    sign_digest(key_pair, &Sha256::digest(contents))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid key {0:?}, expected a hex encoded ed25519 public key")]
+    InvalidKey(String),
+    #[error("signature doesn't match the signed contents & key")]
+    Mismatch,
+}
+
+#[cfg(test)]
+mod test {
+    use ring::{rand::SystemRandom, signature::KeyPair};
+
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = PublicKey::from_hex(&hex::encode(key_pair.public_key())).unwrap();
+
+        let contents = b"stone.index";
+        let signature = sign(&key_pair, contents);
+
+        assert!(key.verify(contents, signature.as_bytes()).is_ok());
+        assert!(matches!(
+            key.verify(b"tampered", signature.as_bytes()),
+            Err(Error::Mismatch)
+        ));
+        assert!(matches!(key.verify(contents, b"not hex"), Err(Error::Mismatch)));
+        assert!(matches!(PublicKey::from_hex("abcd"), Err(Error::InvalidKey(_))));
+    }
+}
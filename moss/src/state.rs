@@ -2,13 +2,15 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::io::Write;
+use std::{fmt, io::Write, str::FromStr};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, Utc};
 use derive_more::{Debug, Display, From, Into};
+use thiserror::Error;
 use tui::{Styled, pretty};
+use url::Url;
 
-use crate::package;
+use crate::{package, repository};
 
 /// Unique identifier for [`State`]
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, From, Into, Display)]
@@ -53,8 +55,69 @@ pub struct State {
     pub created: DateTime<Utc>,
     /// Relevant type for this State
     pub kind: Kind,
+    /// Repositories in use when this state was created
+    pub repositories: Vec<Repository>,
+    /// Human friendly names given to this state
+    pub tags: Vec<String>,
+    /// Kernel command line fragment appended to the boot entries of this state
+    pub cmdline: Option<String>,
 }
 
+/// Reference to a [`State`] by [`Id`] or tag, as accepted on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference {
+    Id(Id),
+    Tag(String),
+}
+
+impl FromStr for Reference {
+    type Err = InvalidTag;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = s.parse::<i32>() {
+            return Ok(Self::Id(Id(id)));
+        }
+
+        validate_tag(s)?;
+
+        Ok(Self::Tag(s.to_owned()))
+    }
+}
+
+impl From<Id> for Reference {
+    fn from(id: Id) -> Self {
+        Self::Id(id)
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reference::Id(id) => write!(f, "{id}"),
+            Reference::Tag(tag) => write!(f, "{tag}"),
+        }
+    }
+}
+
+/// Ensure `tag` can name a state
+///
+/// Tags can't be numeric, so they're never mistaken for an [`Id`].
+pub fn validate_tag(tag: &str) -> Result<(), InvalidTag> {
+    let is_valid = !tag.is_empty()
+        && !tag.chars().all(|c| c.is_ascii_digit())
+        && tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(InvalidTag(tag.to_owned()))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("invalid state tag {0:?}, expected letters, digits, '-', '_' or '.' and not only digits")]
+pub struct InvalidTag(String);
+
 /// The Selection records the presence of a package ID in a [`State`]
 /// It also records whether it was selected as a transitive dependency,
 /// along with an optional human-readable reason
@@ -64,6 +127,7 @@ pub struct Selection {
     /// Marks whether the package was explicitly installed
     /// by the user, or if it's a "transitive" dependency
     pub explicit: bool,
+    /// Why the package was installed, i.e. `dependency of firefox`
     pub reason: Option<String>,
 }
 
@@ -81,11 +145,34 @@ impl Selection {
     pub fn transitive(package: package::Id) -> Self {
         Self {
             package,
-            explicit: true,
+            explicit: false,
             reason: None,
         }
     }
 
+    /// Construct a new explicit Selection for a package the user asked for today
+    pub fn requested(package: package::Id) -> Self {
+        Self::explicit(package).reason(format!("user request on {}", Local::now().format("%Y-%m-%d")))
+    }
+
+    /// Construct a new Selection for a package installed by the system-model,
+    /// either as one of its packages or as a dependency of `dependent`
+    pub fn system_model(package: package::Id, explicit: bool, dependent: Option<&package::Name>) -> Self {
+        if explicit {
+            Self::explicit(package).reason("system-model")
+        } else {
+            Self::dependency(package, dependent)
+        }
+    }
+
+    /// Construct a new transitive Selection for a dependency of `dependent`, if known
+    pub fn dependency(package: package::Id, dependent: Option<&package::Name>) -> Self {
+        match dependent {
+            Some(name) => Self::transitive(package).reason(format!("dependency of {name}")),
+            None => Self::transitive(package),
+        }
+    }
+
     /// Record a reason for the Selection entering the state
     pub fn reason(self, reason: impl ToString) -> Self {
         Self {
@@ -95,6 +182,55 @@ impl Selection {
     }
 }
 
+/// A repository in use when a [`State`] was created, along with
+/// the index it was resolved against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repository {
+    pub id: repository::Id,
+    pub uri: Url,
+    pub priority: repository::Priority,
+    /// sha256 of the `stone.index` in use, if one was cached
+    pub index_hash: Option<String>,
+}
+
+/// A difference between the repositories recorded in a [`State`] and those in use now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepositoryChange {
+    /// Recorded in the state but no longer active
+    Removed(Repository),
+    /// Active now but not recorded in the state
+    Added(Repository),
+    /// Active in both, but with a different URI, priority or index
+    Changed { recorded: Repository, current: Repository },
+}
+
+/// Compare the `recorded` repositories of a state against the `current` ones
+pub fn repository_changes(recorded: &[Repository], current: &[Repository]) -> Vec<RepositoryChange> {
+    let mut changes = recorded
+        .iter()
+        .filter_map(
+            |recorded| match current.iter().find(|current| current.id == recorded.id) {
+                None => Some(RepositoryChange::Removed(recorded.clone())),
+                Some(current) if current != recorded => Some(RepositoryChange::Changed {
+                    recorded: recorded.clone(),
+                    current: current.clone(),
+                }),
+                Some(_) => None,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    changes.extend(
+        current
+            .iter()
+            .filter(|current| !recorded.iter().any(|recorded| recorded.id == current.id))
+            .cloned()
+            .map(RepositoryChange::Added),
+    );
+
+    changes
+}
+
 /// Columnar display encapsulation for a [`State`]
 pub struct ColumnDisplay<'a>(pub &'a State);
 
@@ -107,3 +243,52 @@ impl pretty::ColumnDisplay for ColumnDisplay<'_> {
         let _ = write!(writer, "State {}{:width$}", self.0.id.to_string().bold(), " ");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn repo(id: &str, index_hash: &str) -> Repository {
+        Repository {
+            id: repository::Id::new(id),
+            uri: format!("https://{id}.dev/stone.index").parse().unwrap(),
+            priority: repository::Priority::new(0),
+            index_hash: Some(index_hash.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_reference() {
+        assert_eq!("42".parse::<Reference>().unwrap(), Reference::Id(Id(42)));
+        assert_eq!(
+            "known-good".parse::<Reference>().unwrap(),
+            Reference::Tag("known-good".to_owned())
+        );
+        assert_eq!(
+            "pre-6.12".parse::<Reference>().unwrap(),
+            Reference::Tag("pre-6.12".to_owned())
+        );
+        assert!("".parse::<Reference>().is_err());
+        assert!("known good".parse::<Reference>().is_err());
+        assert!(validate_tag("42").is_err());
+    }
+
+    #[test]
+    fn test_repository_changes() {
+        let recorded = [repo("a", "1"), repo("b", "1"), repo("c", "1")];
+        let current = [repo("a", "1"), repo("b", "2"), repo("d", "1")];
+
+        assert_eq!(
+            repository_changes(&recorded, &current),
+            vec![
+                RepositoryChange::Changed {
+                    recorded: repo("b", "1"),
+                    current: repo("b", "2"),
+                },
+                RepositoryChange::Removed(repo("c", "1")),
+                RepositoryChange::Added(repo("d", "1")),
+            ]
+        );
+        assert!(repository_changes(&recorded, &recorded).is_empty());
+    }
+}
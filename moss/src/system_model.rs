@@ -1,17 +1,19 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{collections::BTreeSet, io};
 
 use fs_err as fs;
 use thiserror::Error;
 
-use crate::{Package, dependency, repository};
+use crate::{Package, dependency, repository, state};
 
 use self::decode::decode;
 use self::encode::encode;
-use self::update::update;
+use self::update::{pin_repositories, update};
 
 mod decode;
+pub mod detect;
 mod encode;
+pub mod template;
 mod update;
 
 #[derive(Debug, Clone)]
@@ -19,23 +21,72 @@ pub struct SystemModel {
     pub repositories: repository::Map,
     pub packages: BTreeSet<dependency::Provider>,
     encoded: String,
+    template: Option<Template>,
+}
+
+/// The source of a templated [`SystemModel`] & the variables it was rendered with
+#[derive(Debug, Clone)]
+struct Template {
+    source: String,
+    variables: template::Variables,
 }
 
 impl SystemModel {
+    /// The KDL of the system model, rendered if it is templated
     pub fn encoded(&self) -> &str {
         &self.encoded
     }
+
+    /// The KDL the system model was loaded from, which differs from
+    /// [`SystemModel::encoded`] when it is templated
+    pub fn source(&self) -> &str {
+        self.template
+            .as_ref()
+            .map_or(&self.encoded, |template| &template.source)
+    }
+
+    /// Returns true if the system model was rendered from a template
+    pub fn is_templated(&self) -> bool {
+        self.template.is_some()
+    }
+
+    /// Apply `f` to the source of the system model, rendering the result again if it is templated
+    fn updated(&self, f: impl FnOnce(&str) -> Result<String, decode::Error>) -> Result<SystemModel, UpdateError> {
+        let updated_content = f(self.source())?;
+
+        match &self.template {
+            Some(template) => Ok(parse(&updated_content, &template.variables)?),
+            None => Ok(decode(&updated_content)?),
+        }
+    }
 }
 
-/// Loads a [`SystemModel`] from the provided path
-pub fn load(path: &Path) -> Result<Option<SystemModel>, LoadError> {
+/// Loads a [`SystemModel`] from the provided path, rendering it with `variables` if it is templated
+pub fn load(path: &Path, variables: &template::Variables) -> Result<Option<SystemModel>, LoadError> {
     if !path.exists() {
         return Ok(None);
     }
 
     let content = fs::read_to_string(path).map_err(LoadError::ReadFile)?;
 
-    Ok(Some(decode(&content)?))
+    Ok(Some(parse(&content, variables)?))
+}
+
+/// Parses a [`SystemModel`] from its KDL `content`, rendering it with `variables` if it is templated
+pub fn parse(content: &str, variables: &template::Variables) -> Result<SystemModel, LoadError> {
+    if !template::is_template(content) {
+        return Ok(decode(content)?);
+    }
+
+    let rendered = template::render(content, variables)?;
+
+    Ok(SystemModel {
+        template: Some(Template {
+            source: content.to_owned(),
+            variables: variables.clone(),
+        }),
+        ..decode(&rendered)?
+    })
 }
 
 /// Creates a new [`SystemModel`] with the given items
@@ -46,6 +97,7 @@ pub fn create(repositories: repository::Map, packages: BTreeSet<dependency::Prov
         repositories,
         packages,
         encoded,
+        template: None,
     }
 }
 
@@ -55,7 +107,8 @@ impl SystemModel {
     /// This function will retain formatting from the original system model
     /// and either delete existing packages where those do not exist in the
     /// incoming set, or append packages to the very end if those aren't
-    /// already present in the system model
+    /// already present in the system model. A templated system model is updated
+    /// at its source, so packages of branches not taken on this host are kept
     pub fn update(self, packages: &[Package]) -> Result<SystemModel, UpdateError> {
         // Packages not provided by the incoming set of packages
         let packages_to_remove = self
@@ -77,24 +130,71 @@ impl SystemModel {
             // We add these as their package name
             .map(|package| package.meta.name.as_ref().as_str());
 
-        // Apply diffs to encoded system model which allows us to retain existing formatting
-        let updated_content = update(&self.encoded, &packages_to_remove, packages_to_add)?;
+        // Apply diffs to encoded system model which allows us to retain existing formatting,
+        // then convert back into decoded system model
+        self.updated(|content| update(content, &packages_to_remove, packages_to_add))
+    }
+
+    /// Adds the named packages to the [`SystemModel`], skipping any it already provides
+    ///
+    /// Like [`SystemModel::update`], formatting of the original system model is retained
+    pub fn add_packages<'a>(self, names: impl IntoIterator<Item = &'a str>) -> Result<SystemModel, UpdateError> {
+        let packages_to_add = names
+            .into_iter()
+            .filter(|name| !self.packages.iter().any(|provider| provider.to_name() == *name));
+
+        self.updated(|content| update(content, &BTreeSet::new(), packages_to_add))
+    }
+
+    /// Removes the given packages from the [`SystemModel`]
+    ///
+    /// Like [`SystemModel::update`], formatting of the original system model is retained
+    pub fn remove_packages<'a>(
+        self,
+        providers: impl IntoIterator<Item = &'a dependency::Provider>,
+    ) -> Result<SystemModel, UpdateError> {
+        let providers = providers.into_iter().collect();
+
+        self.updated(|content| update(content, &providers, std::iter::empty()))
+    }
 
-        // Convert back into decoded system model
-        Ok(decode(&updated_content)?)
+    /// Records the `index-hash` of the recorded `repositories` of a state, pinning
+    /// each repository to the index the state was resolved against
+    ///
+    /// Like [`SystemModel::update`], formatting of the original system model is retained
+    pub fn pin_repositories(self, repositories: &[state::Repository]) -> Result<SystemModel, UpdateError> {
+        let index_hashes = repositories
+            .iter()
+            .filter_map(|repo| Some((&repo.id, repo.index_hash.as_deref()?)))
+            .collect();
+
+        self.updated(|content| pin_repositories(content, &index_hashes))
     }
 }
 
+/// A direct change while the system-model at the path is active, with neither
+/// recording it in the system-model nor accepting it as ephemeral requested
+#[derive(Debug, Error)]
+#[error(
+    "system-model {0:?} is active, pass --update-model to record this change in it or --ephemeral-change \
+     to accept it will be reverted by the next sync"
+)]
+pub struct UnrecordedChange(pub PathBuf);
+
 #[derive(Debug, Error)]
 pub enum LoadError {
     #[error("read file")]
     ReadFile(#[source] io::Error),
     #[error("decode")]
     Decode(#[from] decode::Error),
+    #[error("render template")]
+    Render(#[from] template::Error),
 }
 
 #[derive(Debug, Error)]
 pub enum UpdateError {
     #[error("decode")]
     Decode(#[from] decode::Error),
+    #[error("load")]
+    Load(#[from] LoadError),
 }
@@ -22,6 +22,7 @@ pub fn decode(content: &str) -> Result<SystemModel, Error> {
         repositories,
         packages,
         encoded: content.to_owned(),
+        template: None,
     })
 }
 
@@ -94,6 +95,7 @@ fn decode_repository(node: &KdlNode) -> Result<(repository::Id, Repository), Err
             uri,
             priority,
             active: enabled,
+            aliases: Default::default(),
         },
     ))
 }
@@ -0,0 +1,123 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Recommend driver & firmware packages for the hardware of the running host
+//!
+//! Packages are matched to the host's devices by the modaliases they provide,
+//! as for [`firmware::select`](crate::firmware::select), so a GPU driver, WiFi
+//! firmware or CPU microcode is recommended once its package declares the
+//! hardware it supports.
+
+use kdl::{KdlDocument, KdlNode, KdlNodeFormat};
+
+use crate::{Package, Registry, firmware::Profile, package::Flags};
+
+use super::encode::push_child;
+
+/// A package recommended for the detected hardware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recommendation {
+    pub package: String,
+    /// Modalias of the device it's recommended for
+    pub reason: String,
+}
+
+/// Recommend the packages available from `registry` supporting the hardware of `profile`
+pub fn detect(registry: &Registry, profile: &Profile) -> Vec<Recommendation> {
+    recommend(registry.list(Flags::new().with_available()), profile)
+}
+
+/// Each package is recommended once, for the first device it supports
+fn recommend(packages: impl IntoIterator<Item = Package>, profile: &Profile) -> Vec<Recommendation> {
+    let mut recommendations = packages
+        .into_iter()
+        .filter_map(|package| {
+            Some(Recommendation {
+                reason: profile.supported_device(&package)?.to_owned(),
+                package: package.meta.name.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    recommendations.sort_by(|a, b| a.package.cmp(&b.package));
+    recommendations.dedup_by(|a, b| a.package == b.package);
+
+    recommendations
+}
+
+/// Encode `recommendations` as a system-model fragment
+pub fn fragment(recommendations: &[Recommendation]) -> String {
+    let mut doc = KdlDocument::new();
+    let mut packages = KdlNode::new("packages");
+
+    for recommendation in recommendations {
+        push_child(&mut packages, &recommendation.package, |node| {
+            node.set_format(KdlNodeFormat {
+                leading: format!("\n    // {}\n    ", recommendation.reason),
+                terminator: "\n".to_owned(),
+                ..Default::default()
+            });
+        });
+    }
+
+    packages.set_format(KdlNodeFormat {
+        leading: "// Generated by `moss model detect`\n".to_owned(),
+        ..Default::default()
+    });
+    doc.nodes_mut().push(packages);
+
+    doc.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Provider, dependency::Kind};
+
+    use super::*;
+
+    fn package(name: &str, modaliases: &[&str]) -> Package {
+        let mut package = Package::test(name, Flags::new().with_available());
+        package
+            .meta
+            .providers
+            .extend(modaliases.iter().map(|modalias| Provider {
+                kind: Kind::Modalias,
+                name: (*modalias).to_owned(),
+            }));
+        package
+    }
+
+    #[test]
+    fn test_recommend() {
+        let profile = Profile::parse(
+            "pci:v00008086d000046A6sv00008086sd00000000bc03sc00i00\n\
+             pci:v00008086d000051F0sv00008086sd00000094bc02sc80i00\n\
+             cpu:type:x86,ven0000fam0006mod009A:feature:,0000,0001\n",
+        );
+
+        let packages = [
+            package(
+                "mesa",
+                &["pci:v00008086d*sv*sd*bc03sc*i*", "pci:v00001002d*sv*sd*bc03sc*i*"],
+            ),
+            package("linux-firmware-intel", &["pci:v00008086d*sv*sd*bc02sc80i*"]),
+            package("linux-firmware-amd-graphics", &["pci:v00001002d*sv*sd*bc03sc*i*"]),
+            package("intel-microcode", &["cpu:type:x86,ven0000*"]),
+            // Not hardware specific
+            package("nano", &[]),
+            // Listed again by another repository
+            package("mesa", &["pci:v00008086d*sv*sd*bc03sc*i*"]),
+        ];
+
+        let recommendations = recommend(packages, &profile);
+
+        assert_eq!(
+            recommendations.iter().map(|r| r.package.as_str()).collect::<Vec<_>>(),
+            ["intel-microcode", "linux-firmware-intel", "mesa"]
+        );
+        assert_eq!(
+            recommendations[1].reason,
+            "pci:v00008086d000051F0sv00008086sd00000094bc02sc80i00"
+        );
+    }
+}
@@ -115,6 +115,7 @@ packages {
                     uri: "https://test.dev/index.stone".parse().unwrap(),
                     priority: repository::Priority::new(1),
                     active: true,
+                    aliases: Default::default(),
                 },
             ),
             (
@@ -124,6 +125,7 @@ packages {
                     uri: "https://test2.dev/index.stone".parse().unwrap(),
                     priority: repository::Priority::new(2),
                     active: false,
+                    aliases: Default::default(),
                 },
             ),
         ]);
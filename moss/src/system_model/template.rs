@@ -0,0 +1,323 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Variable substitution & conditionals within a system-model
+//!
+//! A templated system-model remains valid KDL, so it can still be updated in place:
+//!
+//! - `${name}` is replaced by the value of a variable, `$$` by a literal `$`.
+//!   Substitutions must appear within strings, i.e. `"${gpu}-drivers"`
+//! - `// #if <condition>`, `// #elif <condition>`, `// #else` & `// #endif`
+//!   lines select which of the lines between them are kept
+//!
+//! A condition is either `name`, true when the variable is defined, or
+//! `name == value` / `name != value`, where `value` may be quoted.
+
+use std::{collections::BTreeMap, env::consts::ARCH, path::Path};
+
+use fs_err as fs;
+use nix::unistd::gethostname;
+use thiserror::Error;
+
+use crate::installation;
+
+/// Marker of a directive line, following the KDL line comment
+const DIRECTIVE: char = '#';
+
+/// Variables available to a templated system-model
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variables(BTreeMap<String, String>);
+
+impl Variables {
+    /// Detect the `hostname` & `arch` variables of the installation at `root`
+    ///
+    /// The hostname is read from `etc/hostname` of `root`, falling back to that
+    /// of the running host when `root` is `/`. The architecture is the one recorded
+    /// for `root`, if any, otherwise that of the running host.
+    pub fn detect(root: &Path) -> Self {
+        let mut variables = Self::default();
+
+        let hostname = fs::read_to_string(root.join("etc/hostname"))
+            .ok()
+            .map(|content| content.trim().to_owned())
+            .filter(|hostname| !hostname.is_empty())
+            .or_else(|| {
+                (root == Path::new("/"))
+                    .then(|| gethostname().ok()?.into_string().ok())
+                    .flatten()
+            });
+        if let Some(hostname) = hostname {
+            variables.define("hostname", hostname);
+        }
+        variables.define(
+            "arch",
+            installation::recorded_architecture(root).as_deref().unwrap_or(ARCH),
+        );
+
+        variables
+    }
+
+    /// Define the variable `name`, replacing any existing value
+    pub fn define(&mut self, name: impl ToString, value: impl ToString) {
+        self.0.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl<K: ToString, V: ToString> Extend<(K, V)> for Variables {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (name, value) in iter {
+            self.define(name, value);
+        }
+    }
+}
+
+/// Returns true if `content` uses substitutions or directives
+pub fn is_template(content: &str) -> bool {
+    content.contains("${") || content.lines().any(|line| directive(line).is_some())
+}
+
+/// Render the templated `content` with `variables`
+pub fn render(content: &str, variables: &Variables) -> Result<String, Error> {
+    let mut output = String::with_capacity(content.len());
+    let mut blocks = vec![];
+
+    for (idx, line) in content.split_inclusive('\n').enumerate() {
+        let line_number = idx + 1;
+        // Lines are only kept when every enclosing block is taken
+        let active = blocks.iter().all(|block: &Block| block.taken);
+
+        let Some((keyword, argument)) = directive(line) else {
+            if active {
+                substitute(line, variables, line_number, &mut output)?;
+            }
+            continue;
+        };
+
+        match keyword {
+            "if" => {
+                let taken = evaluate(argument, variables, line_number)?;
+                blocks.push(Block {
+                    taken,
+                    matched: taken,
+                    has_else: false,
+                });
+            }
+            "elif" => {
+                let block = blocks.last_mut().ok_or(Error::Unmatched("elif", line_number))?;
+                if block.has_else {
+                    return Err(Error::Unmatched("elif", line_number));
+                }
+                let condition = evaluate(argument, variables, line_number)?;
+                block.taken = !block.matched && condition;
+                block.matched |= block.taken;
+            }
+            "else" => {
+                let block = blocks.last_mut().ok_or(Error::Unmatched("else", line_number))?;
+                if block.has_else {
+                    return Err(Error::Unmatched("else", line_number));
+                }
+                block.has_else = true;
+                block.taken = !block.matched;
+                block.matched = true;
+            }
+            "endif" => {
+                blocks.pop().ok_or(Error::Unmatched("endif", line_number))?;
+            }
+            _ => unreachable!("only known directives are matched"),
+        }
+
+        // A block nested within a branch that isn't taken is never taken
+        if let Some((last, parents)) = blocks.split_last_mut()
+            && !parents.iter().all(|block| block.taken)
+        {
+            last.taken = false;
+            last.matched = true;
+        }
+    }
+
+    if !blocks.is_empty() {
+        return Err(Error::Unterminated);
+    }
+
+    Ok(output)
+}
+
+/// A conditional block of the template
+struct Block {
+    /// Lines of the current branch are kept
+    taken: bool,
+    /// A branch of the block has already been taken
+    matched: bool,
+    /// The `else` branch has been reached
+    has_else: bool,
+}
+
+/// The keyword & argument of a directive `line`, if it is one
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim().strip_prefix("//")?.trim_start().strip_prefix(DIRECTIVE)?;
+    let (keyword, argument) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+    matches!(keyword, "if" | "elif" | "else" | "endif").then_some((keyword, argument.trim()))
+}
+
+fn evaluate(condition: &str, variables: &Variables, line: usize) -> Result<bool, Error> {
+    let invalid = || Error::InvalidCondition(condition.to_owned(), line);
+
+    let (name, comparison) = if let Some((name, value)) = condition.split_once("==") {
+        (name, Some((true, value)))
+    } else if let Some((name, value)) = condition.split_once("!=") {
+        (name, Some((false, value)))
+    } else {
+        (condition, None)
+    };
+
+    let name = name.trim();
+    if !is_name(name) {
+        return Err(invalid());
+    }
+
+    let Some((equal, value)) = comparison else {
+        return Ok(variables.get(name).is_some());
+    };
+
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    if value.is_empty() || value.contains('"') {
+        return Err(invalid());
+    }
+
+    Ok((variables.get(name) == Some(value)) == equal)
+}
+
+fn substitute(line: &str, variables: &Variables, line_number: usize, output: &mut String) -> Result<(), Error> {
+    let mut rest = line;
+
+    while let Some(idx) = rest.find('$') {
+        output.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            output.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let (name, after) = after.split_once('}').ok_or(Error::UnterminatedVariable(line_number))?;
+            let value = variables
+                .get(name)
+                .ok_or_else(|| Error::UndefinedVariable(name.to_owned(), line_number))?;
+            output.push_str(value);
+            rest = after;
+        } else {
+            output.push('$');
+        }
+    }
+    output.push_str(rest);
+
+    Ok(())
+}
+
+/// Returns true if `name` is a valid variable name
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("line {1}: undefined variable {0:?}")]
+    UndefinedVariable(String, usize),
+    #[error("line {0}: unterminated variable")]
+    UnterminatedVariable(usize),
+    #[error("line {1}: invalid condition {0:?}")]
+    InvalidCondition(String, usize),
+    #[error("line {1}: #{0} without a matching #if")]
+    Unmatched(&'static str, usize),
+    #[error("#if without a matching #endif")]
+    Unterminated,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn variables() -> Variables {
+        let mut variables = Variables::default();
+        variables.extend([("hostname", "build-01"), ("arch", "x86_64"), ("class", "server")]);
+        variables
+    }
+
+    #[test]
+    fn test_render() {
+        let content = r#"packages {
+    "${class}-base"
+    // #if class == desktop
+    plasma
+    // #elif class == "server"
+    openssh
+    // #if hostname == build-01
+    boulder
+    // #else
+    nano
+    // #endif
+    // #else
+    nano
+    // #endif
+    // #if gpu
+    "${gpu}-drivers"
+    // #endif
+    "costs-$$5"
+}
+"#;
+
+        let rendered = render(content, &variables()).unwrap();
+
+        assert_eq!(
+            rendered,
+            r#"packages {
+    "server-base"
+    openssh
+    boulder
+    "costs-$5"
+}
+"#
+        );
+        assert!(is_template(content));
+        assert!(!is_template(&rendered));
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let variables = variables();
+
+        assert!(matches!(
+            render("\"${gpu}\"", &variables),
+            Err(Error::UndefinedVariable(name, 1)) if name == "gpu"
+        ));
+        assert!(matches!(
+            render("\"${arch\"", &variables),
+            Err(Error::UnterminatedVariable(1))
+        ));
+        assert!(matches!(
+            render("// #if arch\nfoo\n", &variables),
+            Err(Error::Unterminated)
+        ));
+        assert!(matches!(
+            render("foo\n// #endif\n", &variables),
+            Err(Error::Unmatched("endif", 2))
+        ));
+        assert!(matches!(
+            render("// #if arch ==\n// #endif\n", &variables),
+            Err(Error::InvalidCondition(_, 1))
+        ));
+    }
+}
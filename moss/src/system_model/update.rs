@@ -1,10 +1,10 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use kdl::{FormatConfig, KdlDocument, KdlNode, KdlNodeFormat};
 
 use super::decode::{self, decode_package};
-use super::encode::push_child;
-use crate::Provider;
+use super::encode::{push_child, push_value};
+use crate::{Provider, repository};
 
 pub fn update<'a>(
     content: &str,
@@ -52,6 +52,36 @@ pub fn update<'a>(
     Ok(document.to_string())
 }
 
+/// Set the `index-hash` of each repository in `index_hashes`, replacing any existing one
+pub fn pin_repositories(
+    content: &str,
+    index_hashes: &BTreeMap<&repository::Id, &str>,
+) -> Result<String, decode::Error> {
+    let mut document: KdlDocument = content.parse().map_err(decode::Error::ParseKdlDocument)?;
+
+    let Some(repositories) = document.get_mut("repositories").and_then(KdlNode::children_mut) else {
+        return Ok(content.to_owned());
+    };
+
+    for repo in repositories.nodes_mut() {
+        let Some(index_hash) = index_hashes.get(&repository::Id::new(repo.name().value())) else {
+            continue;
+        };
+
+        let children = repo.ensure_children();
+        children
+            .nodes_mut()
+            .retain(|child| child.name().value() != "index-hash");
+
+        push_child(repo, "index-hash", |node| {
+            push_value(node, index_hash.to_string());
+            node.autoformat_config(&FormatConfig::builder().indent_level(2).build());
+        });
+    }
+
+    Ok(document.to_string())
+}
+
 #[cfg(test)]
 mod test {
     use crate::{Package, package, system_model};
@@ -171,6 +201,46 @@ packages {
         assert_eq!(updated.encoded, EXPECTED);
     }
 
+    #[test]
+    fn test_pin_repositories() {
+        const CONTENT: &str = r#"repositories {
+    // Official
+    volatile {
+        uri "https://test.dev/stone.index"
+        priority 0
+        index-hash old
+    }
+    local {
+        uri "file:///local/"
+        priority 10
+    }
+}
+"#;
+        const EXPECTED: &str = r#"repositories {
+    // Official
+    volatile {
+        uri "https://test.dev/stone.index"
+        priority 0
+        index-hash abc
+    }
+    local {
+        uri "file:///local/"
+        priority 10
+        index-hash def
+    }
+}
+"#;
+
+        let volatile = repository::Id::new("volatile");
+        let local = repository::Id::new("local");
+        let unknown = repository::Id::new("unknown");
+        let hashes = [(&volatile, "abc"), (&local, "def"), (&unknown, "ghi")]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(pin_repositories(CONTENT, &hashes).unwrap(), EXPECTED);
+    }
+
     fn package(name: &str) -> Package {
         Package {
             id: package::Id::from(name.to_owned()),
@@ -184,13 +254,21 @@ packages {
                 description: "".to_owned(),
                 source_id: "".to_owned(),
                 homepage: "".to_owned(),
+                bug_tracker: None,
+                maintainer: None,
                 licenses: vec![],
                 dependencies: Default::default(),
                 providers: [Provider::from_name(name).unwrap()].into_iter().collect(),
                 conflicts: Default::default(),
+                replaces: Default::default(),
+                exports: Default::default(),
+                config_files: Default::default(),
                 uri: None,
                 hash: None,
                 download_size: None,
+                installed_size: None,
+                classification: None,
+                security_release: None,
             },
             flags: package::Flags::default(),
         }